@@ -0,0 +1,274 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Declarations of the Lua API of Eruption. The daemon declares each function
+//! that it provides to Lua scripts in a registry, that is used to generate the
+//! API reference, and by third party tools that embed the same API, e.g. to run
+//! scripts against stub functions. The interface of plugins is declared by the
+//! module [`plugin`], so that third parties may ship plugins as shared objects.
+
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+pub mod plugin;
+
+pub use plugin::Plugin;
+
+pub type Result<T> = std::result::Result<T, SdkError>;
+
+#[derive(Debug, Error)]
+pub enum SdkError {
+    #[error("The function '{name}' of '{namespace}' has already been declared by '{other}'")]
+    DuplicateFunction {
+        name: String,
+        namespace: String,
+        other: String,
+    },
+
+    #[error("Invalid function signature: {signature}")]
+    InvalidSignature { signature: String },
+}
+
+/// The namespace of the functions that are provided by the daemon itself,
+/// all other functions are provided by plugins
+pub const CORE_NAMESPACE: &str = "_core_";
+
+/// The library that a function belongs to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Lib {
+    Std,
+    Math,
+    Color,
+    Noise,
+    Hw,
+    Sys,
+    Dsp,
+    Macro,
+}
+
+impl Lib {
+    /// Name of the table that holds the functions of the library, as seen by Lua
+    /// scripts. Functions of the `Math` library are added to the `math` table of
+    /// the Lua standard library, without replacing any of its functions
+    pub fn table_name(self) -> &'static str {
+        match self {
+            Lib::Std => "eruption",
+            Lib::Math => "math",
+            Lib::Color => "color",
+            Lib::Noise => "noise",
+            Lib::Hw => "device",
+            Lib::Sys => "system",
+            Lib::Dsp => "audio",
+            Lib::Macro => "macro",
+        }
+    }
+}
+
+impl fmt::Display for Lib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Lib::Std => "Std",
+            Lib::Math => "Math",
+            Lib::Color => "Color",
+            Lib::Noise => "Noise",
+            Lib::Hw => "Hw",
+            Lib::Sys => "Sys",
+            Lib::Dsp => "dsp",
+            Lib::Macro => "Macro",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The declaration of a function of the Lua API
+#[derive(Debug, Copy, Clone)]
+pub struct ApiFunction {
+    /// The signature of the function, e.g. "get_key_color([device,] key_index) -> color"
+    pub signature: &'static str,
+
+    pub lib: Lib,
+
+    /// The version that introduced the function, e.g. "since 0.1.2", or "removed in 0.0.11"
+    pub since: &'static str,
+
+    pub description: &'static str,
+}
+
+impl ApiFunction {
+    /// Get the name of the function, as seen by Lua scripts
+    pub fn name(&self) -> &'static str {
+        self.signature
+            .split('(')
+            .next()
+            .unwrap_or(self.signature)
+            .trim()
+    }
+
+    /// Returns true if the function has been removed from the API, it is only
+    /// declared to keep the API reference complete
+    pub fn is_removed(&self) -> bool {
+        self.since.starts_with("removed")
+    }
+
+    fn validate(&self) -> Result<()> {
+        let name = self.name();
+
+        let valid = !name.is_empty()
+            && self.signature.contains('(')
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if valid {
+            Ok(())
+        } else {
+            Err(SdkError::InvalidSignature {
+                signature: self.signature.to_string(),
+            })
+        }
+    }
+}
+
+/// A deprecated function of the Lua API
+#[derive(Debug, Copy, Clone)]
+pub struct ApiDeprecation {
+    /// The name of the deprecated function
+    pub name: &'static str,
+
+    /// The name of the function that should be used instead, if any. Calls of a
+    /// removed function are mapped to it, for scripts that target an older API level
+    pub replacement: Option<&'static str>,
+
+    /// The API level that deprecated the function, e.g. "0.1.2"
+    pub since: &'static str,
+}
+
+/// Declare a slice of API functions, each declaration consists of the signature,
+/// the library, the version and the description, separated by commas:
+///
+/// ```
+/// use eruption_sdk::{api_functions, ApiFunction};
+///
+/// const API: &[ApiFunction] = api_functions! {
+///     "get_volume() -> i", Sys, "since 0.1.2", "Returns the volume of the audio sink in percent";
+/// };
+/// ```
+#[macro_export]
+macro_rules! api_functions {
+    ($($signature:expr, $lib:ident, $since:expr, $description:expr;)*) => {
+        &[$(
+            $crate::ApiFunction {
+                signature: $signature,
+                lib: $crate::Lib::$lib,
+                since: $since,
+                description: $description,
+            },
+        )*]
+    };
+}
+
+/// The registry of all functions of the Lua API, grouped by the namespace
+/// (the name of the plugin) that provides them
+#[derive(Debug, Default)]
+pub struct Registry {
+    functions: Vec<(String, ApiFunction)>,
+    namespaces: HashMap<&'static str, String>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the functions `functions` of the namespace `namespace`. Fails if a
+    /// function has already been declared, by the same or by another namespace
+    pub fn add(&mut self, namespace: &str, functions: &[ApiFunction]) -> Result<()> {
+        for function in functions.iter() {
+            function.validate()?;
+
+            if let Some(other) = self.namespaces.get(function.name()) {
+                return Err(SdkError::DuplicateFunction {
+                    name: function.name().to_string(),
+                    namespace: namespace.to_string(),
+                    other: other.clone(),
+                });
+            }
+
+            self.namespaces
+                .insert(function.name(), namespace.to_string());
+            self.functions.push((namespace.to_string(), *function));
+        }
+
+        Ok(())
+    }
+
+    /// Get all declared functions and their namespaces
+    pub fn functions(&self) -> &[(String, ApiFunction)] {
+        &self.functions
+    }
+
+    /// Get the namespace that declared the function `name`
+    pub fn find_namespace(&self, name: &str) -> Option<&str> {
+        self.namespaces.get(name).map(|n| n.as_str())
+    }
+
+    /// Generate the API reference as a markdown table
+    pub fn to_markdown(&self) -> String {
+        let mut result = String::new();
+
+        result.push_str(
+            "| Name      | Plugin         | Lib | Since | Description                   |\n",
+        );
+        result.push_str(
+            "| --------- | -------------- | ------ | ---- | ----------------------------- |\n",
+        );
+
+        for (namespace, function) in self.functions.iter() {
+            result.push_str(&format!(
+                "| `{}`    | {}  | {}  | {} | {} |\n",
+                function.signature, namespace, function.lib, function.since, function.description
+            ));
+        }
+
+        result
+    }
+
+    /// Generate a Lua chunk that defines a stub for each function that has not
+    /// been removed. Stubs raise an error when called, so that tools can run
+    /// scripts outside of the daemon and provide implementations as needed
+    pub fn to_lua_stubs(&self) -> String {
+        let mut result = String::new();
+
+        for (namespace, function) in self.functions.iter() {
+            if function.is_removed() {
+                continue;
+            }
+
+            result.push_str(&format!(
+                "-- {} ({}, {})\nfunction {}(...) error(\"{}() is not implemented\") end\n\n",
+                function.signature,
+                namespace,
+                function.since,
+                function.name(),
+                function.name()
+            ));
+        }
+
+        result
+    }
+}
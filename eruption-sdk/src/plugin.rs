@@ -0,0 +1,333 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! The interface of plugins. The plugins of the daemon implement [`Plugin`].
+//! Out-of-tree plugins are shared objects that implement [`ExternalPlugin`] and
+//! export a [`PluginVTable`], see [`export_plugin!`]. Only types of the C ABI cross
+//! the boundary of the shared object, so a plugin may be built with another
+//! compiler than the daemon, as long as the ABI version matches.
+
+use rlua::Context;
+use std::any::Any;
+use std::ffi::{c_void, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::ApiFunction;
+
+pub type Result<T> = std::result::Result<T, anyhow::Error>;
+
+/// Version of the vtable of out-of-tree plugins, incremented on incompatible changes
+pub const PLUGIN_ABI_VERSION: u32 = 3;
+
+/// Name of the symbol of the vtable, that is exported by a plugin
+pub const PLUGIN_VTABLE_SYMBOL: &str = "eruption_plugin_vtable";
+
+/// Represents a plugin
+pub trait Plugin: Any {
+    /// Get the user visible name of a plugin
+    fn get_name(&self) -> String;
+
+    /// Get the user visible short description of a plugin
+    fn get_description(&self) -> String;
+
+    /// Called upon initialization of the plugin
+    fn initialize(&mut self) -> Result<()>;
+
+    /// Called when the plugin is disabled at runtime, it may be initialized again later
+    fn teardown(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns true if `teardown()` releases all resources of the plugin, e.g. its
+    /// threads, so that it may be disabled at runtime
+    fn supports_teardown(&self) -> bool {
+        false
+    }
+
+    /// Register supplied lua functions and extensions
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()>;
+
+    /// Get the declarations of the lua functions supplied by the plugin
+    fn get_api(&self) -> &'static [ApiFunction];
+
+    /// Called on each iteration of the main loop, that is due according to
+    /// `get_hook_interval()`
+    fn main_loop_hook(&self, ticks: u64);
+
+    /// Number of iterations of the main loop between two calls of `main_loop_hook`,
+    /// or None if the plugin does not need to be called at all
+    fn get_hook_interval(&self) -> Option<u64> {
+        Some(1)
+    }
+
+    /// Event handling entrypoint
+    // fn process_event(&mut self, event: Event);
+
+    /// Downcast support
+    fn as_any(&self) -> &dyn Any;
+
+    /// Downcast support (mutable)
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Represents an out-of-tree plugin. Out-of-tree plugins can not supply Lua
+/// functions, since the Lua VM of the daemon can not be shared with them
+pub trait ExternalPlugin: Send + Sync {
+    /// Get the user visible name of a plugin
+    fn get_name(&self) -> String;
+
+    /// Get the user visible short description of a plugin
+    fn get_description(&self) -> String;
+
+    /// Called upon initialization of the plugin
+    fn initialize(&mut self) -> Result<()>;
+
+    /// Called when the plugin is disabled at runtime, it may be initialized again later
+    fn teardown(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called on each iteration of the main loop, that is due according to
+    /// `get_hook_interval()`
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    /// Number of iterations of the main loop between two calls of `main_loop_hook`,
+    /// or None if the plugin does not need to be called at all
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The vtable that is exported by an out-of-tree plugin. The ABI version is the
+/// first field, so that it may be checked before anything else is accessed. The
+/// instance is opaque to the daemon, strings are owned by the instance and valid
+/// until it is destroyed. Functions that return an `i32` return 0 on success
+#[repr(C)]
+pub struct PluginVTable {
+    pub abi_version: u32,
+
+    /// Create an instance of the plugin, returns null on failure
+    pub create: unsafe extern "C" fn() -> *mut c_void,
+
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+
+    pub get_name: unsafe extern "C" fn(instance: *const c_void) -> *const c_char,
+
+    pub get_description: unsafe extern "C" fn(instance: *const c_void) -> *const c_char,
+
+    pub initialize: unsafe extern "C" fn(instance: *mut c_void) -> i32,
+
+    pub teardown: unsafe extern "C" fn(instance: *mut c_void) -> i32,
+
+    pub main_loop_hook: unsafe extern "C" fn(instance: *const c_void, ticks: u64),
+
+    /// Returns 0 if the plugin does not need to be called at all
+    pub get_hook_interval: unsafe extern "C" fn(instance: *const c_void) -> u64,
+}
+
+/// The functions of the vtable, that are instantiated by [`export_plugin!`]. Panics
+/// of the plugin are caught, since they must not unwind into the daemon
+#[doc(hidden)]
+pub mod ffi {
+    use super::*;
+
+    /// An instance of the plugin `P`, with its strings
+    struct Instance<P> {
+        plugin: P,
+        name: CString,
+        description: CString,
+    }
+
+    fn to_c_string(s: String) -> CString {
+        CString::new(s.replace('\0', "")).unwrap_or_default()
+    }
+
+    pub fn create<P: ExternalPlugin>(create: fn() -> P) -> *mut c_void {
+        match panic::catch_unwind(create) {
+            Ok(plugin) => {
+                let instance = Instance {
+                    name: to_c_string(plugin.get_name()),
+                    description: to_c_string(plugin.get_description()),
+                    plugin,
+                };
+
+                Box::into_raw(Box::new(instance)) as *mut c_void
+            }
+
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+
+    pub unsafe extern "C" fn destroy<P: ExternalPlugin>(instance: *mut c_void) {
+        let instance = Box::from_raw(instance as *mut Instance<P>);
+        let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(instance)));
+    }
+
+    pub unsafe extern "C" fn get_name<P: ExternalPlugin>(instance: *const c_void) -> *const c_char {
+        (*(instance as *const Instance<P>)).name.as_ptr()
+    }
+
+    pub unsafe extern "C" fn get_description<P: ExternalPlugin>(
+        instance: *const c_void,
+    ) -> *const c_char {
+        (*(instance as *const Instance<P>)).description.as_ptr()
+    }
+
+    pub unsafe extern "C" fn initialize<P: ExternalPlugin>(instance: *mut c_void) -> i32 {
+        let plugin = &mut (*(instance as *mut Instance<P>)).plugin;
+
+        match panic::catch_unwind(AssertUnwindSafe(|| plugin.initialize())) {
+            Ok(Ok(())) => 0,
+            _ => -1,
+        }
+    }
+
+    pub unsafe extern "C" fn teardown<P: ExternalPlugin>(instance: *mut c_void) -> i32 {
+        let plugin = &mut (*(instance as *mut Instance<P>)).plugin;
+
+        match panic::catch_unwind(AssertUnwindSafe(|| plugin.teardown())) {
+            Ok(Ok(())) => 0,
+            _ => -1,
+        }
+    }
+
+    pub unsafe extern "C" fn main_loop_hook<P: ExternalPlugin>(
+        instance: *const c_void,
+        ticks: u64,
+    ) {
+        let plugin = &(*(instance as *const Instance<P>)).plugin;
+
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| plugin.main_loop_hook(ticks)));
+    }
+
+    pub unsafe extern "C" fn get_hook_interval<P: ExternalPlugin>(instance: *const c_void) -> u64 {
+        let plugin = &(*(instance as *const Instance<P>)).plugin;
+
+        panic::catch_unwind(AssertUnwindSafe(|| plugin.get_hook_interval()))
+            .ok()
+            .flatten()
+            .unwrap_or(0)
+    }
+}
+
+/// Export the plugin `$plugin`, that is created by the constructor `$create`, from
+/// a crate of the type `cdylib`:
+///
+/// ```ignore
+/// eruption_sdk::export_plugin!(StreamDeckPlugin, StreamDeckPlugin::new);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:ty, $create:path) => {
+        #[no_mangle]
+        pub static eruption_plugin_vtable: $crate::plugin::PluginVTable =
+            $crate::plugin::PluginVTable {
+                abi_version: $crate::plugin::PLUGIN_ABI_VERSION,
+                create: {
+                    unsafe extern "C" fn create() -> *mut ::std::ffi::c_void {
+                        $crate::plugin::ffi::create::<$plugin>($create)
+                    }
+
+                    create
+                },
+                destroy: $crate::plugin::ffi::destroy::<$plugin>,
+                get_name: $crate::plugin::ffi::get_name::<$plugin>,
+                get_description: $crate::plugin::ffi::get_description::<$plugin>,
+                initialize: $crate::plugin::ffi::initialize::<$plugin>,
+                teardown: $crate::plugin::ffi::teardown::<$plugin>,
+                main_loop_hook: $crate::plugin::ffi::main_loop_hook::<$plugin>,
+                get_hook_interval: $crate::plugin::ffi::get_hook_interval::<$plugin>,
+            };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    struct TestPlugin {
+        initialized: bool,
+    }
+
+    impl TestPlugin {
+        fn new() -> Self {
+            Self { initialized: false }
+        }
+    }
+
+    impl ExternalPlugin for TestPlugin {
+        fn get_name(&self) -> String {
+            "Test".into()
+        }
+
+        fn get_description(&self) -> String {
+            "A plugin for tests".into()
+        }
+
+        fn initialize(&mut self) -> Result<()> {
+            if self.initialized {
+                anyhow::bail!("Already initialized");
+            }
+
+            self.initialized = true;
+
+            Ok(())
+        }
+
+        fn main_loop_hook(&self, ticks: u64) {
+            if ticks == 42 {
+                panic!("The panic must not unwind into the daemon");
+            }
+        }
+
+        fn get_hook_interval(&self) -> Option<u64> {
+            Some(10)
+        }
+    }
+
+    crate::export_plugin!(TestPlugin, TestPlugin::new);
+
+    #[test]
+    fn test_vtable() {
+        let vtable = &eruption_plugin_vtable;
+        assert_eq!(vtable.abi_version, PLUGIN_ABI_VERSION);
+
+        unsafe {
+            let instance = (vtable.create)();
+            assert!(!instance.is_null());
+
+            let name = CStr::from_ptr((vtable.get_name)(instance));
+            assert_eq!(name.to_str().unwrap(), "Test");
+
+            let description = CStr::from_ptr((vtable.get_description)(instance));
+            assert_eq!(description.to_str().unwrap(), "A plugin for tests");
+
+            assert_eq!((vtable.initialize)(instance), 0);
+            assert_ne!((vtable.initialize)(instance), 0);
+            assert_eq!((vtable.teardown)(instance), 0);
+
+            assert_eq!((vtable.get_hook_interval)(instance), 10);
+
+            (vtable.main_loop_hook)(instance, 1);
+            (vtable.main_loop_hook)(instance, 42);
+
+            (vtable.destroy)(instance);
+        }
+    }
+}
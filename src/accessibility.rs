@@ -0,0 +1,256 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use palette::{ConvertFrom, Hsv, Srgb};
+use parking_lot::RwLock;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+use crate::profiles::Profile;
+use crate::rvdevice::RGBA;
+
+pub type Result<T> = std::result::Result<T, AccessibilityError>;
+
+#[derive(Debug, Error)]
+pub enum AccessibilityError {
+    #[error("Unknown color filter: {name}")]
+    UnknownFilter { name: String },
+}
+
+type Matrix = [[f64; 3]; 3];
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Simulation of protanopia (Machado et al. 2009, severity 1.0)
+const PROTANOPIA: Matrix = [
+    [0.152_286, 1.052_583, -0.204_868],
+    [0.114_503, 0.786_281, 0.099_216],
+    [-0.003_882, -0.048_116, 1.051_998],
+];
+
+/// Simulation of deuteranopia (Machado et al. 2009, severity 1.0)
+const DEUTERANOPIA: Matrix = [
+    [0.367_322, 0.860_646, -0.227_968],
+    [0.280_085, 0.672_501, 0.047_413],
+    [-0.011_820, 0.042_940, 0.968_881],
+];
+
+/// Simulation of tritanopia (Machado et al. 2009, severity 1.0)
+const TRITANOPIA: Matrix = [
+    [1.255_528, -0.076_749, -0.178_779],
+    [-0.078_411, 0.930_809, 0.147_602],
+    [0.004_733, 0.691_367, 0.303_900],
+];
+
+/// Shifts the information that is lost with protanopia and deuteranopia
+/// into the channels that can be perceived
+const SHIFT_RED_GREEN: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+/// Shifts the information that is lost with tritanopia into the channels
+/// that can be perceived
+const SHIFT_BLUE_YELLOW: Matrix = [[1.0, 0.0, 0.7], [0.0, 1.0, 0.7], [0.0, 0.0, 0.0]];
+
+/// Filters of the output stage, applied to the final frames of all devices
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorFilter {
+    None,
+
+    /// Show the colors as they are perceived with a color vision deficiency, e.g.
+    /// to verify that an effect works for everybody
+    SimulateProtanopia,
+    SimulateDeuteranopia,
+    SimulateTritanopia,
+
+    /// Compensate for a color vision deficiency (daltonization), colors that are
+    /// hard to distinguish are shifted apart
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl FromStr for ColorFilter {
+    type Err = AccessibilityError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.trim() {
+            "none" | "" => Ok(ColorFilter::None),
+
+            "simulate-protanopia" => Ok(ColorFilter::SimulateProtanopia),
+            "simulate-deuteranopia" => Ok(ColorFilter::SimulateDeuteranopia),
+            "simulate-tritanopia" => Ok(ColorFilter::SimulateTritanopia),
+
+            "protanopia" => Ok(ColorFilter::Protanopia),
+            "deuteranopia" => Ok(ColorFilter::Deuteranopia),
+            "tritanopia" => Ok(ColorFilter::Tritanopia),
+
+            _ => Err(AccessibilityError::UnknownFilter {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 3]; 3];
+
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+
+    result
+}
+
+/// Daltonization: `color + shift * (color - simulation * color)`
+fn compensate(simulation: &Matrix, shift: &Matrix) -> Matrix {
+    let mut error = IDENTITY;
+
+    for (i, row) in error.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value -= simulation[i][j];
+        }
+    }
+
+    let mut result = multiply(shift, &error);
+
+    for (i, row) in result.iter_mut().enumerate() {
+        row[i] += 1.0;
+    }
+
+    result
+}
+
+impl ColorFilter {
+    /// The matrix of the filter, or None if colors are left untouched
+    fn matrix(self) -> Option<Matrix> {
+        match self {
+            ColorFilter::None => None,
+
+            ColorFilter::SimulateProtanopia => Some(PROTANOPIA),
+            ColorFilter::SimulateDeuteranopia => Some(DEUTERANOPIA),
+            ColorFilter::SimulateTritanopia => Some(TRITANOPIA),
+
+            ColorFilter::Protanopia => Some(compensate(&PROTANOPIA, &SHIFT_RED_GREEN)),
+            ColorFilter::Deuteranopia => Some(compensate(&DEUTERANOPIA, &SHIFT_RED_GREEN)),
+            ColorFilter::Tritanopia => Some(compensate(&TRITANOPIA, &SHIFT_BLUE_YELLOW)),
+        }
+    }
+}
+
+lazy_static! {
+    /// Matrix of the active color filter, if any
+    static ref FILTER_MATRIX: RwLock<Option<Matrix>> = RwLock::new(None);
+
+    /// Remap the colors of status indicators, like the volume bar and alarms
+    static ref HIGH_CONTRAST: AtomicBool = AtomicBool::new(false);
+}
+
+/// Select the color filter and the high-contrast mode of the profile `profile`.
+/// Profiles that do not specify them use the `[accessibility]` section of the config
+pub fn apply_profile(profile: &Profile) {
+    let (default_filter, default_high_contrast) = match crate::CONFIG.lock().as_ref() {
+        Some(config) => (
+            config
+                .get_str("accessibility.color_filter")
+                .unwrap_or_else(|_| "none".to_string()),
+            config
+                .get::<bool>("accessibility.high_contrast")
+                .unwrap_or(false),
+        ),
+
+        None => ("none".to_string(), false),
+    };
+
+    let filter = profile
+        .color_filter
+        .clone()
+        .unwrap_or(default_filter)
+        .parse::<ColorFilter>()
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            ColorFilter::None
+        });
+
+    if filter != ColorFilter::None {
+        info!("Using color filter: {:?}", filter);
+    }
+
+    *FILTER_MATRIX.write() = filter.matrix();
+
+    HIGH_CONTRAST.store(
+        profile.high_contrast.unwrap_or(default_high_contrast),
+        Ordering::SeqCst,
+    );
+}
+
+/// Apply the active color filter to `led_map`, this is the last stage before the
+/// frame is sent to the device
+pub fn filter_led_map(led_map: &mut [RGBA]) {
+    let matrix = match *FILTER_MATRIX.read() {
+        Some(matrix) => matrix,
+        None => return,
+    };
+
+    let clamp = |v: f64| v.round().max(0.0).min(255.0) as u8;
+
+    for color in led_map.iter_mut() {
+        let (r, g, b) = (f64::from(color.r), f64::from(color.g), f64::from(color.b));
+
+        color.r = clamp(matrix[0][0] * r + matrix[0][1] * g + matrix[0][2] * b);
+        color.g = clamp(matrix[1][0] * r + matrix[1][1] * g + matrix[1][2] * b);
+        color.b = clamp(matrix[2][0] * r + matrix[2][1] * g + matrix[2][2] * b);
+    }
+}
+
+/// Remap the color `color` of a status indicator, if the high-contrast mode is
+/// active: Hues between red and green, that are hard to tell apart, are mapped to
+/// the range between orange and blue, at full saturation
+pub fn status_color(color: RGBA) -> RGBA {
+    if !HIGH_CONTRAST.load(Ordering::SeqCst) {
+        return color;
+    }
+
+    let rgb = Srgb::from_components((
+        f64::from(color.r) / 255.0,
+        f64::from(color.g) / 255.0,
+        f64::from(color.b) / 255.0,
+    ));
+
+    let (h, s, v) = Hsv::from(rgb).into_components();
+    let hue: f64 = h.to_positive_degrees();
+
+    if s < 0.1 || v < 0.05 || hue > 150.0 {
+        // grays, black and blue-ish colors are distinguishable already
+        return color;
+    }
+
+    // red (0°) becomes orange (30°), green (120°) becomes blue (225°)
+    let hue = 30.0 + hue.min(120.0) / 120.0 * 195.0;
+
+    let (r, g, b) = Srgb::convert_from(Hsv::new(hue, 1.0, v)).into_components();
+
+    RGBA {
+        r: (r * 255.0) as u8,
+        g: (g * 255.0) as u8,
+        b: (b * 255.0) as u8,
+        a: color.a,
+    }
+}
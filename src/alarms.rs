@@ -0,0 +1,334 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::accessibility;
+use crate::constants;
+use crate::plugins::SensorsPlugin;
+use crate::rvdevice::RGBA;
+
+pub type Result<T> = std::result::Result<T, AlarmError>;
+
+#[derive(Debug, Error)]
+pub enum AlarmError {
+    #[error("Invalid alarm condition: {condition}")]
+    InvalidCondition { condition: String },
+
+    #[error("Invalid alarm effect: {effect}")]
+    InvalidEffect { effect: String },
+
+    #[error("Invalid alarm configuration")]
+    InvalidConfig {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+lazy_static! {
+    /// The configured alarm rules
+    static ref ALARMS: Mutex<Vec<Alarm>> = Mutex::new(vec![]);
+
+    /// The effect of the alarm that is currently raised, and the time it has been raised
+    static ref ACTIVE_EFFECT: Mutex<Option<(Effect, Instant)>> = Mutex::new(None);
+}
+
+/// Sensor values that may be monitored by an alarm rule
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sensor {
+    CpuTemp,
+    GpuTemp,
+    MemUsedPercent,
+}
+
+impl FromStr for Sensor {
+    type Err = AlarmError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "cpu_temp" => Ok(Sensor::CpuTemp),
+            "gpu_temp" => Ok(Sensor::GpuTemp),
+            "mem_used_percent" => Ok(Sensor::MemUsedPercent),
+
+            _ => Err(AlarmError::InvalidCondition {
+                condition: name.to_string(),
+            }),
+        }
+    }
+}
+
+impl Sensor {
+    fn get_value(self) -> f64 {
+        match self {
+            Sensor::CpuTemp => f64::from(SensorsPlugin::get_package_temp()),
+            Sensor::GpuTemp => f64::from(SensorsPlugin::get_gpu_temp()),
+
+            Sensor::MemUsedPercent => {
+                let total = SensorsPlugin::get_mem_total_kb();
+                let used = SensorsPlugin::get_mem_used_kb();
+
+                if total > 0 {
+                    used as f64 * 100.0 / total as f64
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// The effects that may be displayed while an alarm is raised
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Effect {
+    /// All keys are lit in the color
+    Solid(RGBA),
+
+    /// All keys pulse in the color
+    Pulse(RGBA),
+
+    /// All keys blink in the color
+    Blink(RGBA),
+}
+
+impl FromStr for Effect {
+    type Err = AlarmError;
+
+    /// Parse an effect like "pulse:0xff0000"
+    fn from_str(effect: &str) -> Result<Self> {
+        let err = || AlarmError::InvalidEffect {
+            effect: effect.to_string(),
+        };
+
+        let mut parts = effect.trim().splitn(2, ':');
+        let name = parts.next().ok_or_else(err)?;
+        let color = parts.next().ok_or_else(err)?.trim();
+
+        let color = u32::from_str_radix(color.trim_start_matches("0x"), 16).map_err(|_e| err())?;
+        let color = RGBA {
+            r: ((color >> 16) & 0xff) as u8,
+            g: ((color >> 8) & 0xff) as u8,
+            b: (color & 0xff) as u8,
+            a: 0xff,
+        };
+
+        match name {
+            "solid" => Ok(Effect::Solid(color)),
+            "pulse" => Ok(Effect::Pulse(color)),
+            "blink" => Ok(Effect::Blink(color)),
+
+            _ => Err(err()),
+        }
+    }
+}
+
+/// An alarm rule, like "cpu_temp > 85" => "pulse:0xff0000"
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub condition: String,
+
+    pub sensor: Sensor,
+
+    /// True if the alarm is raised while the value is above the threshold
+    pub above: bool,
+
+    pub threshold: f64,
+
+    pub effect: Effect,
+
+    /// True while the alarm is raised
+    raised: bool,
+}
+
+impl Alarm {
+    /// Parse an alarm rule from a condition like "cpu_temp > 85" and an effect
+    pub fn new(condition: &str, effect: &str) -> Result<Self> {
+        let err = || AlarmError::InvalidCondition {
+            condition: condition.to_string(),
+        };
+
+        let parts: Vec<&str> = condition.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(err());
+        }
+
+        let sensor = parts[0].parse::<Sensor>()?;
+
+        let above = match parts[1] {
+            ">" => true,
+            "<" => false,
+
+            _ => return Err(err()),
+        };
+
+        let threshold = parts[2].parse::<f64>().map_err(|_e| err())?;
+
+        Ok(Alarm {
+            condition: condition.to_string(),
+            sensor,
+            above,
+            threshold,
+            effect: effect.parse::<Effect>()?,
+            raised: false,
+        })
+    }
+
+    /// Evaluate the rule for the current value `value`, a raised alarm is only
+    /// cleared once the value is ALARM_HYSTERESIS past the threshold
+    fn evaluate(&mut self, value: f64) -> bool {
+        let threshold = match (self.raised, self.above) {
+            (false, _) => self.threshold,
+            (true, true) => self.threshold - constants::ALARM_HYSTERESIS,
+            (true, false) => self.threshold + constants::ALARM_HYSTERESIS,
+        };
+
+        let raised = if self.above {
+            value > threshold
+        } else {
+            value < threshold
+        };
+
+        if raised != self.raised {
+            if raised {
+                warn!("Alarm raised: {} (value: {:.1})", self.condition, value);
+            } else {
+                info!("Alarm cleared: {} (value: {:.1})", self.condition, value);
+            }
+
+            self.raised = raised;
+        }
+
+        raised
+    }
+}
+
+/// Load the alarm rules from the `[alarms]` section of the configuration file,
+/// and start evaluating them
+pub fn initialize() -> Result<()> {
+    {
+        let config = crate::CONFIG.lock();
+        let table = match config.as_ref().unwrap().get_table("alarms") {
+            Ok(table) => table,
+
+            // no alarms configured
+            Err(_) => return Ok(()),
+        };
+
+        let mut alarms = ALARMS.lock();
+        alarms.clear();
+
+        for (condition, effect) in table {
+            let effect = effect
+                .into_str()
+                .map_err(|_e| AlarmError::InvalidConfig {})?;
+
+            match Alarm::new(&condition, &effect) {
+                Ok(alarm) => {
+                    info!("Alarm: {} => {}", condition, effect);
+                    alarms.push(alarm);
+                }
+
+                Err(e) => error!("Invalid alarm '{}': {}", condition, e),
+            }
+        }
+
+        if alarms.is_empty() {
+            return Ok(());
+        }
+
+        // the table is unordered, so order the rules by their conditions, the
+        // first raised alarm determines the effect
+        alarms.sort_by(|lhs, rhs| lhs.condition.cmp(&rhs.condition));
+    }
+
+    spawn_alarm_thread()
+}
+
+/// Spawn a thread that evaluates the alarm rules, querying the sensors
+/// may be slow, so this is not done on the main thread
+fn spawn_alarm_thread() -> Result<()> {
+    thread::Builder::new()
+        .name("alarms".into())
+        .spawn(move || loop {
+            let mut effect = None;
+
+            for alarm in ALARMS.lock().iter_mut() {
+                let value = alarm.sensor.get_value();
+
+                if alarm.evaluate(value) && effect.is_none() {
+                    effect = Some(alarm.effect);
+                }
+            }
+
+            let mut active_effect = ACTIVE_EFFECT.lock();
+
+            *active_effect = match (effect, active_effect.take()) {
+                // keep the phase of the effect, while the same alarm is raised
+                (Some(effect), Some((active, since))) if active == effect => Some((effect, since)),
+                (Some(effect), _) => Some((effect, Instant::now())),
+                (None, _) => None,
+            };
+
+            drop(active_effect);
+
+            thread::sleep(Duration::from_millis(constants::ALARM_CHECK_MILLIS));
+        })
+        .map_err(|_e| AlarmError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// Render the effect of the raised alarm into `led_map`, if any. Alarms
+/// have the highest priority, they replace the output of the scripts
+pub fn render(led_map: &mut [RGBA]) {
+    let (effect, since) = match *ACTIVE_EFFECT.lock() {
+        Some(active) => active,
+        None => return,
+    };
+
+    render_effect(effect, since, led_map);
+}
+
+/// Render the effect `effect`, that started at `since`, on all keys of `led_map`
+pub fn render_effect(effect: Effect, since: Instant, led_map: &mut [RGBA]) {
+    let phase = (since.elapsed().as_millis() % u128::from(constants::ALARM_PERIOD_MILLIS)) as f64
+        / constants::ALARM_PERIOD_MILLIS as f64;
+
+    let (color, p) = match effect {
+        Effect::Solid(color) => (color, 1.0),
+        Effect::Pulse(color) => (color, 0.5 - 0.5 * (2.0 * PI * phase).cos()),
+        Effect::Blink(color) => (color, if phase < 0.5 { 1.0 } else { 0.0 }),
+    };
+
+    let color = accessibility::status_color(color);
+    let color = RGBA {
+        r: (f64::from(color.r) * p) as u8,
+        g: (f64::from(color.g) * p) as u8,
+        b: (f64::from(color.b) * p) as u8,
+        a: 0xff,
+    };
+
+    for led in led_map.iter_mut() {
+        *led = color;
+    }
+}
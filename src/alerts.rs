@@ -0,0 +1,429 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Alerts that are scheduled for a point in time, e.g. by a calendar script via
+//! the `org.eruption.Timer` interface. Scheduled alerts are stored in the state
+//! directory, so that they survive a restart of the daemon. When an alert is due,
+//! its effect is displayed on all keys, until it expires or a key is pressed.
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveDateTime, NaiveTime, TimeZone};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::alarms::{self, Effect};
+use crate::constants;
+use crate::events;
+use crate::rvdevice::RGBA;
+
+pub type Result<T> = std::result::Result<T, AlertError>;
+
+#[derive(Debug, Error)]
+pub enum AlertError {
+    #[error("Invalid point in time: {time}")]
+    InvalidTime { time: String },
+
+    #[error("Invalid effect: {effect}")]
+    InvalidEffect { effect: String },
+
+    #[error("Invalid parameter: {name}")]
+    InvalidParam { name: String },
+
+    #[error("Too many scheduled alerts")]
+    TooManyAlerts {},
+
+    #[error("Could not load the scheduled alerts: {description}")]
+    LoadError { description: String },
+
+    #[error("Could not save the scheduled alerts: {description}")]
+    SaveError { description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// An alert that is scheduled for a point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub id: u32,
+
+    /// The point in time the alert is due, in seconds since the epoch
+    pub at: i64,
+
+    /// The effect of the alert, like "pulse:0xff0000"
+    pub effect: String,
+
+    /// How long the effect is displayed, in seconds
+    pub duration_secs: u64,
+}
+
+/// Contents of the file that stores the scheduled alerts
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AlertsFile {
+    #[serde(default)]
+    alerts: Vec<Alert>,
+}
+
+/// The alert that is currently displayed
+struct ActiveAlert {
+    id: u32,
+    effect: Effect,
+    since: Instant,
+    duration: Duration,
+}
+
+lazy_static! {
+    /// The scheduled alerts, that are not yet due
+    static ref ALERTS: Mutex<Vec<Alert>> = Mutex::new(vec![]);
+
+    /// The alert that is currently displayed, if any
+    static ref ACTIVE_ALERT: Mutex<Option<ActiveAlert>> = Mutex::new(None);
+}
+
+fn get_alerts_file() -> PathBuf {
+    PathBuf::from(constants::STATE_DIR).join("alerts.toml")
+}
+
+fn load_alerts() -> Result<Vec<Alert>> {
+    let file = get_alerts_file();
+
+    if !file.exists() {
+        return Ok(vec![]);
+    }
+
+    let toml = fs::read_to_string(&file).map_err(|e| AlertError::LoadError {
+        description: format!("{}", e),
+    })?;
+
+    let alerts = toml::de::from_str::<AlertsFile>(&toml).map_err(|e| AlertError::LoadError {
+        description: format!("{}", e),
+    })?;
+
+    Ok(alerts.alerts)
+}
+
+fn save_alerts(alerts: &[Alert]) -> Result<()> {
+    let toml = toml::ser::to_string_pretty(&AlertsFile {
+        alerts: alerts.to_vec(),
+    })
+    .map_err(|e| AlertError::SaveError {
+        description: format!("{}", e),
+    })?;
+
+    fs::write(get_alerts_file(), toml).map_err(|e| AlertError::SaveError {
+        description: format!("{}", e),
+    })
+}
+
+/// Parse a point in time, relative to `now`. Supported are countdowns like "+10m"
+/// (units are "s", "m" and "h"), a time of day like "14:30" (today, or tomorrow if
+/// it has passed), a local date and time like "2020-04-01 14:30", and RFC 3339
+pub fn parse_time(time: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let err = || AlertError::InvalidTime {
+        time: time.to_string(),
+    };
+
+    let time = time.trim();
+
+    if time.starts_with('+') {
+        let countdown = &time[1..];
+
+        let split = countdown
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(err)?;
+
+        let (value, unit) = countdown.split_at(split);
+        let value = value.parse::<u64>().map_err(|_e| err())?;
+
+        let multiplier = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+
+            _ => return Err(err()),
+        };
+
+        // countdowns that are out of range are rejected, instead of overflowing
+        let duration = value
+            .checked_mul(multiplier)
+            .and_then(|secs| ChronoDuration::from_std(Duration::from_secs(secs)).ok())
+            .ok_or_else(err)?;
+
+        return now.checked_add_signed(duration).ok_or_else(err);
+    }
+
+    if let Ok(at) = DateTime::parse_from_rfc3339(time) {
+        return Ok(at.with_timezone(&Local));
+    }
+
+    if let Ok(at) = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M") {
+        return Local.from_local_datetime(&at).single().ok_or_else(err);
+    }
+
+    if let Ok(at) = NaiveTime::parse_from_str(time, "%H:%M") {
+        let today = now.date().and_time(at).ok_or_else(err)?;
+
+        return if today > now {
+            Ok(today)
+        } else {
+            today
+                .checked_add_signed(ChronoDuration::days(1))
+                .ok_or_else(err)
+        };
+    }
+
+    Err(err())
+}
+
+/// Schedule an alert with the effect `effect` ("solid", "pulse" or "blink") for
+/// the point in time `at_time`. Supported parameters are `color` (e.g. "0xff0000")
+/// and `duration` in seconds. Returns the id of the alert
+pub fn schedule(at_time: &str, effect: &str, params: &HashMap<String, String>) -> Result<u32> {
+    for name in params.keys() {
+        if name != "color" && name != "duration" {
+            return Err(AlertError::InvalidParam { name: name.clone() });
+        }
+    }
+
+    let at = parse_time(at_time, Local::now())?;
+
+    let color = params
+        .get("color")
+        .map(String::as_str)
+        .unwrap_or("0xff0000");
+    let effect = format!("{}:{}", effect, color);
+
+    effect
+        .parse::<Effect>()
+        .map_err(|_e| AlertError::InvalidEffect {
+            effect: effect.clone(),
+        })?;
+
+    let duration_secs = match params.get("duration") {
+        Some(duration) => duration
+            .parse::<u64>()
+            .map_err(|_e| AlertError::InvalidParam {
+                name: "duration".into(),
+            })?
+            .min(constants::ALERT_MAX_DURATION_SECS),
+
+        None => constants::ALERT_DEFAULT_DURATION_SECS,
+    };
+
+    // the id of the displayed alert is not reused, so that it may be cancelled
+    let active_id = ACTIVE_ALERT.lock().as_ref().map_or(0, |active| active.id);
+
+    let mut alerts = ALERTS.lock();
+
+    if alerts.len() >= constants::MAX_ALERTS {
+        return Err(AlertError::TooManyAlerts {});
+    }
+
+    let id = alerts
+        .iter()
+        .map(|alert| alert.id)
+        .max()
+        .unwrap_or(0)
+        .max(active_id)
+        + 1;
+
+    info!("Scheduling alert {}: {} at {}", id, effect, at.to_rfc3339());
+
+    alerts.push(Alert {
+        id,
+        at: at.timestamp(),
+        effect,
+        duration_secs,
+    });
+
+    alerts.sort_by_key(|alert| alert.at);
+
+    save_alerts(&alerts)?;
+
+    Ok(id)
+}
+
+/// Cancel the alert `id`, it is hidden if it is currently displayed. Returns
+/// true if the alert has been found
+pub fn cancel(id: u32) -> Result<bool> {
+    {
+        let mut active_alert = ACTIVE_ALERT.lock();
+
+        if active_alert
+            .as_ref()
+            .map_or(false, |active| active.id == id)
+        {
+            info!("Dismissing alert {}", id);
+
+            *active_alert = None;
+            return Ok(true);
+        }
+    }
+
+    let mut alerts = ALERTS.lock();
+    let len = alerts.len();
+
+    alerts.retain(|alert| alert.id != id);
+
+    if alerts.len() == len {
+        return Ok(false);
+    }
+
+    info!("Cancelled alert {}", id);
+
+    save_alerts(&alerts)?;
+
+    Ok(true)
+}
+
+/// Get the scheduled alerts, that are not yet due
+pub fn get_alerts() -> Vec<Alert> {
+    ALERTS.lock().clone()
+}
+
+/// Display the alerts that are due, alerts that have been missed by more than
+/// ALERT_GRACE_SECS (e.g. while the system was powered off) are dropped
+fn dispatch_alerts() {
+    let now = Local::now().timestamp();
+
+    let mut alerts = ALERTS.lock();
+
+    let (due, pending): (Vec<Alert>, Vec<Alert>) =
+        alerts.drain(..).partition(|alert| alert.at <= now);
+
+    *alerts = pending;
+
+    if due.is_empty() {
+        return;
+    }
+
+    for alert in due {
+        if now - alert.at > constants::ALERT_GRACE_SECS {
+            warn!("Missed alert {}: {}", alert.id, alert.effect);
+            continue;
+        }
+
+        match alert.effect.parse::<Effect>() {
+            Ok(effect) => {
+                info!("Alert {} is due: {}", alert.id, alert.effect);
+
+                *ACTIVE_ALERT.lock() = Some(ActiveAlert {
+                    id: alert.id,
+                    effect,
+                    since: Instant::now(),
+                    duration: Duration::from_secs(alert.duration_secs),
+                });
+            }
+
+            Err(e) => error!("Invalid alert {}: {}", alert.id, e),
+        }
+    }
+
+    save_alerts(&alerts).unwrap_or_else(|e| error!("{}", e));
+}
+
+/// Load the scheduled alerts, and start the timer that dispatches them
+pub fn initialize() -> Result<()> {
+    let alerts = load_alerts()?;
+
+    if !alerts.is_empty() {
+        info!("Loaded {} scheduled alert(s)", alerts.len());
+    }
+
+    *ALERTS.lock() = alerts;
+
+    // a key press dismisses the displayed alert
+    events::register_observer(|event: &events::Event| {
+        if let events::Event::KeyDown(_index) = event {
+            let mut active_alert = ACTIVE_ALERT.lock();
+
+            if let Some(active) = active_alert.take() {
+                info!("Dismissed alert {}", active.id);
+            }
+        }
+
+        Ok(true) // event has been processed
+    });
+
+    thread::Builder::new()
+        .name("alerts".into())
+        .spawn(|| loop {
+            dispatch_alerts();
+
+            thread::sleep(Duration::from_millis(constants::ALERT_CHECK_MILLIS));
+        })
+        .map_err(|_e| AlertError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// Render the effect of the displayed alert into `led_map`, if any. The effect
+/// replaces the output of the scripts, only a raised alarm is drawn on top of it
+pub fn render(led_map: &mut [RGBA]) {
+    let mut active_alert = ACTIVE_ALERT.lock();
+
+    let (effect, since) = match active_alert.as_ref() {
+        Some(active) if active.since.elapsed() < active.duration => (active.effect, active.since),
+
+        Some(_) => {
+            *active_alert = None;
+            return;
+        }
+
+        None => return,
+    };
+
+    alarms::render_effect(effect, since, led_map);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_countdown() {
+        let now = Local.ymd(2020, 4, 1).and_hms(12, 0, 0);
+
+        assert_eq!(
+            parse_time("+10m", now).unwrap(),
+            Local.ymd(2020, 4, 1).and_hms(12, 10, 0)
+        );
+        assert_eq!(
+            parse_time("+2h", now).unwrap(),
+            Local.ymd(2020, 4, 1).and_hms(14, 0, 0)
+        );
+
+        assert!(parse_time("+10d", now).is_err());
+        assert!(parse_time("+m", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_time_overflow() {
+        let now = Local.ymd(2020, 4, 1).and_hms(12, 0, 0);
+
+        assert!(parse_time("+9223372036854775807h", now).is_err());
+        assert!(parse_time("+99999999999999999999s", now).is_err());
+        assert!(parse_time("+9223372036854775s", now).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::{KEYS_PER_COLUMN, NUM_COLUMNS, NUM_KEYS, RGBA};
+use crate::sinks;
+
+pub type Result<T> = std::result::Result<T, AnimationError>;
+
+#[derive(Debug, Error)]
+pub enum AnimationError {
+    #[error("Invalid animation: {name}")]
+    InvalidAnimation { name: String },
+}
+
+/// Width of the bar of the sweep animation, in columns
+const SWEEP_WIDTH: f64 = 4.0;
+
+/// Animations that are rendered by the daemon itself, they are used on startup
+/// and shutdown, so they don't depend on any Lua scripts
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Animation {
+    /// A bar that sweeps across the keyboard, from left to right
+    Sweep,
+
+    /// Fade in from black
+    FadeIn,
+
+    /// Fade out the current frame to black
+    FadeOut,
+}
+
+impl FromStr for Animation {
+    type Err = AnimationError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.trim() {
+            "sweep" => Ok(Animation::Sweep),
+            "fade-in" => Ok(Animation::FadeIn),
+            "fade-out" => Ok(Animation::FadeOut),
+
+            _ => Err(AnimationError::InvalidAnimation {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+fn scale(color: RGBA, p: f64) -> RGBA {
+    let p = p.max(0.0).min(1.0);
+
+    RGBA {
+        r: (f64::from(color.r) * p) as u8,
+        g: (f64::from(color.g) * p) as u8,
+        b: (f64::from(color.b) * p) as u8,
+        a: color.a,
+    }
+}
+
+/// Render the frame of `animation` at progress `p` (0.0 - 1.0) into `led_map`.
+/// `from` holds the frame that was displayed when the animation has been started
+fn render(animation: Animation, color: RGBA, from: &[RGBA], p: f64, led_map: &mut [RGBA]) {
+    match animation {
+        Animation::Sweep => {
+            let position = p * (NUM_COLUMNS as f64 + SWEEP_WIDTH) - SWEEP_WIDTH / 2.0;
+
+            for (i, led) in led_map.iter_mut().enumerate() {
+                let column = (i / KEYS_PER_COLUMN) as f64;
+                let distance = (column - position).abs();
+
+                *led = scale(color, 1.0 - distance / (SWEEP_WIDTH / 2.0));
+            }
+        }
+
+        Animation::FadeIn => {
+            for led in led_map.iter_mut() {
+                *led = scale(color, p);
+            }
+        }
+
+        Animation::FadeOut => {
+            for (led, from) in led_map.iter_mut().zip(from.iter()) {
+                *led = scale(*from, 1.0 - p);
+            }
+        }
+    }
+}
+
+/// Play `animation` on the `num_devices` devices, the frames are written to the
+/// output sinks. This blocks for ANIMATION_DURATION_MILLIS. `from` holds the
+/// current frame of each device, it is faded out by FadeOut
+pub fn play(num_devices: usize, animation: Animation, color: RGBA, from: &[Vec<RGBA>]) {
+    debug!("Playing animation: {:?}", animation);
+
+    let duration = Duration::from_millis(constants::ANIMATION_DURATION_MILLIS);
+    let black = vec![
+        RGBA {
+            r: 0x00,
+            g: 0x00,
+            b: 0x00,
+            a: 0xff,
+        };
+        NUM_KEYS
+    ];
+
+    let mut led_map = black.clone();
+    let start = Instant::now();
+
+    loop {
+        let elapsed = start.elapsed();
+        let p = (elapsed.as_millis() as f64 / duration.as_millis() as f64).min(1.0);
+
+        for index in 0..num_devices {
+            let from = from.get(index).unwrap_or(&black);
+            render(animation, color, from, p, &mut led_map);
+
+            sinks::write_frame(index, &led_map);
+        }
+
+        if elapsed >= duration {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(constants::MAIN_LOOP_DELAY_MILLIS));
+    }
+}
@@ -0,0 +1,671 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Bundles of profiles and scripts, that may be shared as a single file. A bundle
+//! is a tar archive with the extension `.eruption-bundle`, that contains:
+//!
+//! * `bundle.toml`: The name, version, author and description of the bundle
+//! * `profiles/*.profile`: Profiles
+//! * `scripts/*.lua`, `scripts/*.lua.manifest`: Scripts and their manifests
+//! * `scripts/**`: Assets, that are used by the scripts
+//!
+//! Bundles are installed into the configuration directory of the user, the files
+//! that were installed are recorded in `bundles/<name>.toml`, so that they can be
+//! removed again.
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+use crate::constants;
+use crate::paths;
+use crate::profiles::Profile;
+use crate::scripting::api;
+use crate::scripting::integrity::{self, Verification};
+use crate::scripting::manifest::Manifest;
+
+pub type Result<T> = std::result::Result<T, BundleError>;
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("Could not access the bundle: {description}")]
+    IoError { description: String },
+
+    #[error("Invalid bundle: {description}")]
+    InvalidBundle { description: String },
+
+    #[error(
+        "The script '{script}' requires API level {required}, but only {supported} is supported"
+    )]
+    IncompatibleScript {
+        script: String,
+        required: String,
+        supported: String,
+    },
+
+    #[error("The script '{script}' failed the integrity check: {description}")]
+    IntegrityError { script: String, description: String },
+
+    #[error("The bundle '{name}' is already installed")]
+    AlreadyInstalled { name: String },
+
+    #[error("The bundle '{name}' is not installed")]
+    NotInstalled { name: String },
+
+    #[error("The file {file} belongs to the bundle '{owner}', remove it first")]
+    OwnedByBundle { file: String, owner: String },
+
+    #[error("Could not determine the configuration directory of the user")]
+    NoUserDir {},
+}
+
+impl From<std::io::Error> for BundleError {
+    fn from(e: std::io::Error) -> Self {
+        BundleError::IoError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+fn invalid_bundle<S: Into<String>>(description: S) -> BundleError {
+    BundleError::InvalidBundle {
+        description: description.into(),
+    }
+}
+
+/// Name of the description of the bundle, in the archive
+const BUNDLE_INFO_FILE: &str = "bundle.toml";
+
+/// Metadata of a bundle, as specified by `bundle.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleInfo {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+}
+
+/// An installed bundle, and the files that were installed from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledBundle {
+    pub bundle: BundleInfo,
+    pub files: Vec<PathBuf>,
+}
+
+/// A bundle that has been read into memory
+#[derive(Debug)]
+pub struct Bundle {
+    pub info: BundleInfo,
+
+    /// The files of the bundle, with paths relative to the root of the archive
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// Returns true if `path` is relative and does not leave the directory it is
+/// extracted to
+fn is_safe_path(path: &Path) -> bool {
+    path.components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+/// Strip leading "./" components from `path`
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// Read the bundle `path` and validate it: Only profiles and scripts are allowed,
+/// every script needs a manifest, and must be compatible with the API level of the
+/// daemon, and all profiles and manifests must be parseable
+pub fn read_bundle<P: AsRef<Path>>(path: P) -> Result<Bundle> {
+    let mut archive = tar::Archive::new(File::open(path.as_ref())?);
+
+    let mut info = None;
+    let mut files = vec![];
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = normalize_path(&entry.path()?);
+
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+
+        if !entry.header().entry_type().is_file() {
+            return Err(invalid_bundle(format!(
+                "Only regular files are allowed: {}",
+                entry_path.display()
+            )));
+        }
+
+        if !is_safe_path(&entry_path) {
+            return Err(invalid_bundle(format!(
+                "Invalid path: {}",
+                entry_path.display()
+            )));
+        }
+
+        let mut buf = vec![];
+        entry.read_to_end(&mut buf)?;
+
+        if entry_path == Path::new(BUNDLE_INFO_FILE) {
+            let toml = String::from_utf8_lossy(&buf);
+
+            info = Some(
+                toml::de::from_str::<BundleInfo>(&toml)
+                    .map_err(|e| invalid_bundle(format!("{}: {}", BUNDLE_INFO_FILE, e)))?,
+            );
+        } else if entry_path.starts_with("profiles") || entry_path.starts_with("scripts") {
+            files.push((entry_path, buf));
+        } else {
+            return Err(invalid_bundle(format!(
+                "Unexpected file: {}",
+                entry_path.display()
+            )));
+        }
+    }
+
+    let info = info.ok_or_else(|| invalid_bundle(format!("Missing {}", BUNDLE_INFO_FILE)))?;
+
+    if !is_safe_path(Path::new(&info.name)) || Path::new(&info.name).components().count() != 1 {
+        return Err(invalid_bundle(format!("Invalid name: {}", info.name)));
+    }
+
+    let find_file = |name: &Path| {
+        files
+            .iter()
+            .find(|(path, _)| path == name)
+            .map(|(_, data)| data)
+    };
+
+    let trusted_keys = integrity::get_trusted_keys();
+    let require_signed = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get::<bool>("security.require_signed_bundles").ok())
+        .unwrap_or(false);
+
+    for (path, data) in files.iter() {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_string());
+
+        match extension.as_deref() {
+            Some("profile") if path.parent() == Some(Path::new("profiles")) => {
+                toml::de::from_str::<Profile>(&String::from_utf8_lossy(data))
+                    .map_err(|e| invalid_bundle(format!("{}: {}", path.display(), e)))?;
+            }
+
+            Some("lua") => {
+                let manifest_file = path.with_extension("lua.manifest");
+
+                if find_file(&manifest_file).is_none() {
+                    return Err(invalid_bundle(format!(
+                        "Missing manifest: {}",
+                        manifest_file.display()
+                    )));
+                }
+            }
+
+            Some("manifest") => {
+                let manifest = toml::de::from_str::<Manifest>(&String::from_utf8_lossy(data))
+                    .map_err(|e| invalid_bundle(format!("{}: {}", path.display(), e)))?;
+
+                if !api::is_api_level_supported(&manifest.min_supported_version) {
+                    return Err(BundleError::IncompatibleScript {
+                        script: manifest.name,
+                        required: manifest.min_supported_version,
+                        supported: constants::API_LEVEL.to_string(),
+                    });
+                }
+
+                // the manifest "x.lua.manifest" belongs to the script "x.lua"
+                let script_file = path.with_extension("");
+                let script_data = find_file(&script_file).ok_or_else(|| {
+                    invalid_bundle(format!("Missing script: {}", script_file.display()))
+                })?;
+
+                let integrity_error = |description: &str| BundleError::IntegrityError {
+                    script: manifest.name.clone(),
+                    description: description.to_string(),
+                };
+
+                match integrity::verify_data(script_data, &manifest, &trusted_keys) {
+                    Verification::Signed { key } => {
+                        info!("The script '{}' is signed by: {}", manifest.name, key)
+                    }
+
+                    Verification::Unsigned if require_signed => {
+                        return Err(integrity_error("The script is not signed"))
+                    }
+
+                    Verification::Unsigned => {
+                        warn!("The script '{}' is not signed", manifest.name)
+                    }
+
+                    Verification::ChecksumMismatch => {
+                        return Err(integrity_error("The script does not match its checksum"))
+                    }
+
+                    Verification::InvalidSignature => {
+                        return Err(integrity_error(
+                            "The signature is invalid, or its key is not trusted",
+                        ))
+                    }
+                }
+            }
+
+            _ if path.starts_with("profiles") => {
+                return Err(invalid_bundle(format!(
+                    "Unexpected file: {}",
+                    path.display()
+                )));
+            }
+
+            // assets of the scripts
+            _ => {}
+        }
+    }
+
+    Ok(Bundle { info, files })
+}
+
+/// Get the directory, that records the installed bundles
+fn get_registry_dir() -> Result<PathBuf> {
+    paths::get_local_user_dir("bundles").ok_or(BundleError::NoUserDir {})
+}
+
+fn get_registry_file(registry_dir: &Path, name: &str) -> PathBuf {
+    registry_dir.join(format!("{}.toml", name))
+}
+
+/// Validate the bundle `path`, and print a summary of its contents
+pub fn validate<P: AsRef<Path>>(path: P) -> Result<()> {
+    let bundle = read_bundle(path)?;
+
+    println!(
+        "{} {} by {}: {}",
+        bundle.info.name, bundle.info.version, bundle.info.author, bundle.info.description
+    );
+
+    for (file, _) in bundle.files.iter() {
+        println!("  {}", file.display());
+    }
+
+    println!("The bundle is valid");
+
+    Ok(())
+}
+
+/// Install the bundle `path` into the configuration directory of the user. Files
+/// of the user are only replaced if `force` is set, files of other bundles are
+/// never replaced
+pub fn install<P: AsRef<Path>>(path: P, force: bool) -> Result<()> {
+    let bundle = read_bundle(path)?;
+
+    let user_dir = paths::get_local_user_dir("").ok_or(BundleError::NoUserDir {})?;
+
+    install_into(&bundle, &user_dir, &get_registry_dir()?, force)?;
+
+    println!(
+        "Installed {} {} into {}",
+        bundle.info.name,
+        bundle.info.version,
+        user_dir.display()
+    );
+
+    Ok(())
+}
+
+/// Install the files of `bundle` into `user_dir`, and record them in `registry_dir`
+fn install_into(bundle: &Bundle, user_dir: &Path, registry_dir: &Path, force: bool) -> Result<()> {
+    let registry_file = get_registry_file(registry_dir, &bundle.info.name);
+
+    if registry_file.exists() && !force {
+        return Err(BundleError::AlreadyInstalled {
+            name: bundle.info.name.clone(),
+        });
+    }
+
+    // files of other bundles are never replaced, even if `force` is set, since
+    // removing either bundle would remove the files of the other one
+    let others = get_installed_bundles_in(registry_dir)?;
+
+    for (file, _) in bundle.files.iter() {
+        if let Some(owner) = others
+            .iter()
+            .filter(|other| other.bundle.name != bundle.info.name)
+            .find(|other| other.files.iter().any(|f| normalize_path(f) == *file))
+        {
+            return Err(BundleError::OwnedByBundle {
+                file: user_dir.join(file).display().to_string(),
+                owner: owner.bundle.name.clone(),
+            });
+        }
+    }
+
+    if !force {
+        if let Some((file, _)) = bundle
+            .files
+            .iter()
+            .find(|(file, _)| user_dir.join(file).exists())
+        {
+            return Err(invalid_bundle(format!(
+                "The file {} exists already, use --force to replace it",
+                user_dir.join(file).display()
+            )));
+        }
+    }
+
+    for (file, data) in bundle.files.iter() {
+        let target = user_dir.join(file);
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        debug!("Installing {}", target.display());
+
+        fs::write(&target, data)?;
+    }
+
+    let installed = InstalledBundle {
+        bundle: bundle.info.clone(),
+        files: bundle.files.iter().map(|(file, _)| file.clone()).collect(),
+    };
+
+    let toml = toml::ser::to_string_pretty(&installed).map_err(|e| BundleError::IoError {
+        description: format!("{}", e),
+    })?;
+
+    fs::create_dir_all(registry_dir)?;
+    fs::write(&registry_file, toml)?;
+
+    Ok(())
+}
+
+/// Get all installed bundles
+pub fn get_installed_bundles() -> Result<Vec<InstalledBundle>> {
+    get_installed_bundles_in(&get_registry_dir()?)
+}
+
+/// Get all bundles, that are recorded in `registry_dir`
+fn get_installed_bundles_in(registry_dir: &Path) -> Result<Vec<InstalledBundle>> {
+    if !registry_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut result = vec![];
+
+    for entry in fs::read_dir(registry_dir)? {
+        let path = entry?.path();
+
+        if path.extension().map_or(false, |e| e == "toml") {
+            match fs::read_to_string(&path).map(|toml| toml::de::from_str::<InstalledBundle>(&toml))
+            {
+                Ok(Ok(bundle)) => result.push(bundle),
+                Ok(Err(e)) => warn!("Invalid bundle registry file {}: {}", path.display(), e),
+                Err(e) => warn!("Could not read {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    result.sort_by(|lhs, rhs| lhs.bundle.name.cmp(&rhs.bundle.name));
+
+    Ok(result)
+}
+
+/// Print a listing of all installed bundles
+pub fn list() -> Result<()> {
+    for installed in get_installed_bundles()? {
+        println!(
+            "{} {} by {}: {} ({} files)",
+            installed.bundle.name,
+            installed.bundle.version,
+            installed.bundle.author,
+            installed.bundle.description,
+            installed.files.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Remove the installed bundle `name`, and all files that were installed from it
+pub fn remove(name: &str) -> Result<()> {
+    let user_dir = paths::get_local_user_dir("").ok_or(BundleError::NoUserDir {})?;
+
+    let installed = remove_from(name, &user_dir, &get_registry_dir()?)?;
+
+    println!(
+        "Removed {} {}",
+        installed.bundle.name, installed.bundle.version
+    );
+
+    Ok(())
+}
+
+/// Remove the files of the bundle `name`, as recorded in `registry_dir`, from
+/// `user_dir`
+fn remove_from(name: &str, user_dir: &Path, registry_dir: &Path) -> Result<InstalledBundle> {
+    let registry_file = get_registry_file(registry_dir, name);
+
+    if !registry_file.is_file() {
+        return Err(BundleError::NotInstalled {
+            name: name.to_string(),
+        });
+    }
+
+    let installed = toml::de::from_str::<InstalledBundle>(&fs::read_to_string(&registry_file)?)
+        .map_err(|e| invalid_bundle(format!("{}", e)))?;
+
+    for file in installed.files.iter().filter(|file| is_safe_path(file)) {
+        let target = user_dir.join(file);
+
+        debug!("Removing {}", target.display());
+
+        fs::remove_file(&target)
+            .unwrap_or_else(|e| warn!("Could not remove {}: {}", target.display(), e));
+    }
+
+    fs::remove_file(&registry_file)?;
+
+    Ok(installed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "eruption-test-bundles-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    /// Write a bundle named `name` to `dir`, that contains the entries `files`
+    fn write_bundle(dir: &Path, name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let path = dir.join(format!("{}.eruption-bundle", name));
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+
+        let info = format!(
+            "name = '{}'\nversion = '1.0'\nauthor = 'Test'\ndescription = 'A test bundle'\n",
+            name
+        );
+
+        let mut entries = vec![(BUNDLE_INFO_FILE, info.as_bytes())];
+        entries.extend_from_slice(files);
+
+        for (file, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            builder.append_data(&mut header, file, data).unwrap();
+        }
+
+        builder.finish().unwrap();
+
+        path
+    }
+
+    const PROFILE: &[u8] = b"id = '5dc62fa6-e965-45cb-a0da-e87d29713093'\nname = 'Test'\ndescription = 'A test profile'\nactive_scripts = ['clock.lua']\n";
+
+    #[test]
+    fn test_is_safe_path() {
+        assert!(is_safe_path(Path::new("scripts/clock.lua")));
+        assert!(is_safe_path(Path::new("./profiles/test.profile")));
+        assert!(!is_safe_path(Path::new("../profiles/test.profile")));
+        assert!(!is_safe_path(Path::new("/etc/eruption/eruption.conf")));
+        assert!(!is_safe_path(Path::new("scripts/../../x.lua")));
+    }
+
+    #[test]
+    fn test_read_bundle() {
+        let dir = test_dir("read");
+
+        let script =
+            fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("src/scripts/clock.lua")).unwrap();
+        let manifest =
+            fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("src/scripts/clock.lua.manifest"))
+                .unwrap();
+
+        let path = write_bundle(
+            &dir,
+            "valid",
+            &[
+                ("profiles/test.profile", PROFILE),
+                ("scripts/clock.lua", &script),
+                ("scripts/clock.lua.manifest", &manifest),
+            ],
+        );
+
+        let bundle = read_bundle(&path).unwrap();
+        assert_eq!(bundle.info.name, "valid");
+        assert_eq!(bundle.files.len(), 3);
+
+        // a script without its manifest
+        let path = write_bundle(&dir, "no-manifest", &[("scripts/clock.lua", &script)]);
+        assert!(matches!(
+            read_bundle(&path),
+            Err(BundleError::InvalidBundle { .. })
+        ));
+
+        // a file outside of the profiles and scripts
+        let path = write_bundle(&dir, "unexpected", &[("eruption.conf", b"")]);
+        assert!(matches!(
+            read_bundle(&path),
+            Err(BundleError::InvalidBundle { .. })
+        ));
+
+        // an unparseable profile
+        let path = write_bundle(&dir, "invalid-profile", &[("profiles/x.profile", b"[[")]);
+        assert!(matches!(
+            read_bundle(&path),
+            Err(BundleError::InvalidBundle { .. })
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_and_remove() {
+        let dir = test_dir("install");
+        let user_dir = dir.join("user");
+        let registry_dir = user_dir.join("bundles");
+
+        let first = read_bundle(write_bundle(
+            &dir,
+            "first",
+            &[("profiles/test.profile", PROFILE)],
+        ))
+        .unwrap();
+
+        install_into(&first, &user_dir, &registry_dir, false).unwrap();
+        assert!(user_dir.join("profiles/test.profile").is_file());
+
+        let installed = get_installed_bundles_in(&registry_dir).unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].bundle.name, "first");
+
+        // installed already
+        assert!(matches!(
+            install_into(&first, &user_dir, &registry_dir, false),
+            Err(BundleError::AlreadyInstalled { .. })
+        ));
+
+        install_into(&first, &user_dir, &registry_dir, true).unwrap();
+
+        // the file of another bundle is not replaced, even if forced
+        let second = read_bundle(write_bundle(
+            &dir,
+            "second",
+            &[("profiles/test.profile", PROFILE)],
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            install_into(&second, &user_dir, &registry_dir, true),
+            Err(BundleError::OwnedByBundle { .. })
+        ));
+
+        // the file of the user is only replaced if forced
+        fs::write(user_dir.join("profiles/user.profile"), PROFILE).unwrap();
+
+        let third = read_bundle(write_bundle(
+            &dir,
+            "third",
+            &[("profiles/user.profile", PROFILE)],
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            install_into(&third, &user_dir, &registry_dir, false),
+            Err(BundleError::InvalidBundle { .. })
+        ));
+
+        install_into(&third, &user_dir, &registry_dir, true).unwrap();
+
+        let removed = remove_from("first", &user_dir, &registry_dir).unwrap();
+        assert_eq!(removed.bundle.name, "first");
+        assert!(!user_dir.join("profiles/test.profile").exists());
+        assert!(user_dir.join("profiles/user.profile").exists());
+
+        assert!(matches!(
+            remove_from("first", &user_dir, &registry_dir),
+            Err(BundleError::NotInstalled { .. })
+        ));
+
+        let installed = get_installed_bundles_in(&registry_dir).unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].bundle.name, "third");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
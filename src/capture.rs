@@ -0,0 +1,344 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use thiserror::Error;
+
+use crate::rvdevice::{SpecialKeyEvent, NUM_KEYS, RGBA};
+
+pub type Result<T> = std::result::Result<T, CaptureError>;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("Could not access the capture file: {description}")]
+    IoError { description: String },
+
+    #[error("Invalid capture file")]
+    InvalidFormat {},
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(e: std::io::Error) -> Self {
+        CaptureError::IoError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+/// Magic bytes at the start of each capture file
+const MAGIC: &[u8; 4] = b"ERUP";
+
+/// Version of the file format
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of the header of a record: timestamp, interface, kind and length
+const RECORD_HEADER_SIZE: usize = 8 + 1 + 1 + 4;
+
+/// Size of the LED map, as it is transferred to the device
+const HWMAP_SIZE: usize = 444;
+
+/// Feature reports that are sent by the daemon to the supported devices
+const KNOWN_FEATURE_REPORTS: &[u8] = &[
+    0x04, 0x05, 0x06, 0x07, 0x09, 0x0a, 0x0b, 0x0d, 0x0f, 0x13, 0x15,
+];
+
+lazy_static! {
+    /// The capture file, while a capture is running, and the start time of the capture
+    static ref CAPTURE: Mutex<Option<(BufWriter<File>, Instant)>> = Mutex::new(None);
+}
+
+/// The USB interface of the device, that a report has been exchanged with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interface {
+    Control = 0,
+    Led = 1,
+}
+
+/// The kind of a captured report
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// A feature report, sent to the device
+    SetFeature = 0,
+
+    /// A feature report, received from the device
+    GetFeature = 1,
+
+    /// An output report, written to the device
+    Write = 2,
+
+    /// An input report, read from the device
+    Read = 3,
+}
+
+/// A captured report
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Time since the start of the capture, in microseconds
+    pub timestamp: u64,
+
+    pub interface: Interface,
+    pub kind: Kind,
+    pub data: Vec<u8>,
+}
+
+/// Start capturing all reports that are exchanged with the devices to `path`
+pub fn start<P: AsRef<Path>>(path: P) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path.as_ref())?);
+
+    file.write_all(MAGIC)?;
+    file.write_all(&[FORMAT_VERSION])?;
+
+    info!("Capturing HID reports to: {}", path.as_ref().display());
+
+    *CAPTURE.lock() = Some((file, Instant::now()));
+
+    Ok(())
+}
+
+/// Stop the running capture, if any
+pub fn stop() {
+    if let Some((mut file, _)) = CAPTURE.lock().take() {
+        file.flush()
+            .unwrap_or_else(|e| error!("Could not write the capture file: {}", e));
+    }
+}
+
+/// Record a report, if a capture is running
+pub fn record(interface: Interface, kind: Kind, data: &[u8]) {
+    let mut capture = CAPTURE.lock();
+
+    if let Some((file, start)) = capture.as_mut() {
+        let timestamp = start.elapsed().as_micros() as u64;
+
+        let mut buf = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+        buf.extend_from_slice(&timestamp.to_le_bytes());
+        buf.push(interface as u8);
+        buf.push(kind as u8);
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(data);
+
+        if let Err(e) = file.write_all(&buf) {
+            error!(
+                "Could not write the capture file, stopping the capture: {}",
+                e
+            );
+            *capture = None;
+        }
+    }
+}
+
+/// Read all records of the capture file `path`
+pub fn read_capture<P: AsRef<Path>>(path: P) -> Result<Vec<Record>> {
+    let buf = fs::read(path.as_ref())?;
+
+    if buf.len() < 5 || &buf[0..4] != MAGIC || buf[4] != FORMAT_VERSION {
+        return Err(CaptureError::InvalidFormat {});
+    }
+
+    let mut result = vec![];
+    let mut pos = 5;
+
+    while pos < buf.len() {
+        if buf.len() - pos < RECORD_HEADER_SIZE {
+            return Err(CaptureError::InvalidFormat {});
+        }
+
+        let header = &buf[pos..pos + RECORD_HEADER_SIZE];
+
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&header[0..8]);
+
+        let interface = match header[8] {
+            0 => Interface::Control,
+            1 => Interface::Led,
+
+            _ => return Err(CaptureError::InvalidFormat {}),
+        };
+
+        let kind = match header[9] {
+            0 => Kind::SetFeature,
+            1 => Kind::GetFeature,
+            2 => Kind::Write,
+            3 => Kind::Read,
+
+            _ => return Err(CaptureError::InvalidFormat {}),
+        };
+
+        let len = u32::from_le_bytes([header[10], header[11], header[12], header[13]]) as usize;
+
+        pos += RECORD_HEADER_SIZE;
+
+        if buf.len() - pos < len {
+            return Err(CaptureError::InvalidFormat {});
+        }
+
+        result.push(Record {
+            timestamp: u64::from_le_bytes(timestamp),
+            interface,
+            kind,
+            data: buf[pos..pos + len].to_vec(),
+        });
+
+        pos += len;
+    }
+
+    Ok(result)
+}
+
+/// A mock device, that decodes the reports of a capture like a ROCCAT Vulcan would
+#[derive(Debug, Default)]
+pub struct MockDevice {
+    /// Number of feature reports received, by report id
+    pub feature_reports: BTreeMap<u8, usize>,
+
+    /// Feature reports that are not sent by the daemon, by report id
+    pub unknown_reports: BTreeMap<u8, usize>,
+
+    /// The LED maps that have been received completely
+    pub frames: Vec<Vec<RGBA>>,
+
+    /// Number of LED maps that have been received only partially
+    pub incomplete_frames: usize,
+
+    /// Events of special keys, decoded from the input reports
+    pub special_key_events: Vec<SpecialKeyEvent>,
+
+    /// The LED map that is currently being received
+    hwmap: Vec<u8>,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process the captured report `record`
+    pub fn process(&mut self, record: &Record) {
+        match (record.interface, record.kind) {
+            (Interface::Control, Kind::SetFeature) | (Interface::Control, Kind::GetFeature) => {
+                if let Some(id) = record.data.first() {
+                    if KNOWN_FEATURE_REPORTS.contains(id) {
+                        *self.feature_reports.entry(*id).or_insert(0) += 1;
+                    } else {
+                        *self.unknown_reports.entry(*id).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            (Interface::Control, Kind::Read) => {
+                if let Some(event) = SpecialKeyEvent::from_report(&record.data) {
+                    self.special_key_events.push(event);
+                }
+            }
+
+            (Interface::Led, Kind::Write) => self.process_led_report(&record.data),
+
+            _ => warn!(
+                "Unexpected report: {:?} {:?}",
+                record.interface, record.kind
+            ),
+        }
+    }
+
+    /// The LED map is transferred in 7 output reports of 65 bytes, the first
+    /// one starts with a header. See `RvDeviceState::send_led_map`
+    fn process_led_report(&mut self, buf: &[u8]) {
+        if buf.len() < 65 {
+            return;
+        }
+
+        if buf[1..5] == [0xa1, 0x01, 0x01, 0xb4] {
+            if !self.hwmap.is_empty() {
+                self.incomplete_frames += 1;
+            }
+
+            self.hwmap = buf[5..65].to_vec();
+        } else if !self.hwmap.is_empty() {
+            self.hwmap.extend_from_slice(&buf[1..65]);
+        }
+
+        if self.hwmap.len() >= HWMAP_SIZE {
+            let led_map = (0..NUM_KEYS)
+                .map(|i| {
+                    let offset = ((i / 12) * 36) + (i % 12);
+
+                    RGBA {
+                        r: self.hwmap[offset],
+                        g: self.hwmap[offset + 12],
+                        b: self.hwmap[offset + 24],
+                        a: 0xff,
+                    }
+                })
+                .collect();
+
+            self.frames.push(led_map);
+            self.hwmap.clear();
+        }
+    }
+}
+
+/// Replay the capture file `path` against the mock device, and print a summary.
+/// If `verbose` is set, each report is printed as well
+pub fn replay<P: AsRef<Path>>(path: P, verbose: bool) -> Result<()> {
+    let records = read_capture(path)?;
+    let mut device = MockDevice::new();
+
+    for record in records.iter() {
+        if verbose {
+            println!(
+                "{:>12.6} {:?} {:?}, {} bytes",
+                record.timestamp as f64 / 1_000_000.0,
+                record.interface,
+                record.kind,
+                record.data.len()
+            );
+
+            hexdump::hexdump(&record.data);
+        }
+
+        device.process(record);
+    }
+
+    let duration = records.last().map(|r| r.timestamp).unwrap_or(0);
+
+    println!("Records:            {}", records.len());
+    println!("Duration:           {:.3} s", duration as f64 / 1_000_000.0);
+    println!("LED frames:         {}", device.frames.len());
+    println!("Incomplete frames:  {}", device.incomplete_frames);
+    println!("Special key events: {}", device.special_key_events.len());
+
+    println!("Feature reports:");
+    for (id, count) in device.feature_reports.iter() {
+        println!("  0x{:02x}: {}", id, count);
+    }
+
+    if !device.unknown_reports.is_empty() {
+        println!("Unknown feature reports:");
+        for (id, count) in device.unknown_reports.iter() {
+            println!("  0x{:02x}: {}", id, count);
+        }
+    }
+
+    Ok(())
+}
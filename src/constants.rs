@@ -24,24 +24,459 @@ pub const DEFAULT_PROFILE_DIR: &str = "/var/lib/eruption/profiles/";
 /// Default script directory
 pub const DEFAULT_SCRIPT_DIR: &str = "/usr/lib/eruption/scripts/";
 
+/// System-wide profile directory, profiles in here take precedence over the default profile directory
+pub const SYSTEM_PROFILE_DIR: &str = "/etc/eruption/profiles/";
+
+/// System-wide script directory, scripts in here take precedence over the default script directory
+pub const SYSTEM_SCRIPT_DIR: &str = "/etc/eruption/scripts/";
+
+/// Directory of the public keys, that signatures of scripts are verified against
+pub const DEFAULT_TRUST_STORE_DIR: &str = "/etc/eruption/trusted-keys/";
+
+/// Configuration directory of a user, relative to the home directory of the user
+pub const USER_CONFIG_DIR: &str = ".config/eruption/";
+
+/// The seat that the devices are attached to
+pub const DEFAULT_SEAT: &str = "seat0";
+
 /// State directory
 pub const STATE_DIR: &str = "/var/lib/eruption/";
 
+/// Directory of the data files of the scripts, each script has its own subdirectory
+pub const DEFAULT_DATA_DIR: &str = "/var/lib/eruption/data/";
+
+/// Maximum size of a data file, that may be written by a script
+pub const MAX_DATA_FILE_SIZE: usize = 1024 * 1024;
+
+/// Maximum size of the text on the clipboard, that may be read or written by a script
+pub const MAX_CLIPBOARD_SIZE: usize = 1024 * 1024;
+
+/// Maximum size of a file in a snapshot, that is imported
+pub const MAX_SNAPSHOT_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Timeout of the tools that access the clipboard
+pub const CLIPBOARD_TIMEOUT_MILLIS: u64 = 500;
+
+/// The X display that is used to access the clipboard, on X11
+pub const DEFAULT_CLIPBOARD_DISPLAY: &str = ":0";
+
+/// Cache directory, used if the XDG cache directory can't be determined
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/eruption/";
+
+/// API level of the Lua scripting interface
+pub const API_LEVEL: &str = "0.1.2";
+
 /// Default effect script
 pub const DEFAULT_EFFECT_SCRIPT: &str = "batique.lua";
 
+/// Target frames per second of the main loop
+pub const TARGET_FPS: u64 = 100;
+
 /// Target delay time of main loop iteration
-pub const MAIN_LOOP_DELAY_MILLIS: u64 = (1000.0 / /* target FPS: */ 100.0) as u64;
+pub const MAIN_LOOP_DELAY_MILLIS: u64 = 1000 / TARGET_FPS;
+
+/// Time allotted to the Lua VMs to render a frame, measured from the start of
+/// the render pipeline. Layers that miss the deadline contribute their previous frame
+pub const FRAME_DEADLINE_MILLIS: u64 = MAIN_LOOP_DELAY_MILLIS * 3 / 4;
+
+/// Size of the canvas that scripts may render to, it is sampled onto the keys of the device
+pub const CANVAS_WIDTH: usize = 96;
+pub const CANVAS_HEIGHT: usize = 24;
+
+/// Duration of the startup and shutdown animations
+pub const ANIMATION_DURATION_MILLIS: u64 = 1000;
+
+/// Length of the window, over which the load of the Lua VMs is measured
+pub const TICK_RATE_WINDOW_MILLIS: u64 = 2000;
+
+/// Reduce the tick rate, if this percentage of the ticks had to be coalesced
+pub const TICK_RATE_OVERLOAD_PERCENT: u32 = 10;
+
+/// Reduce the tick rate to no less than TARGET_FPS / MAX_TICK_RATE_DIVISOR
+pub const MAX_TICK_RATE_DIVISOR: u64 = 4;
 
 /// Amount of time that has to pass before we can send another command to the LED control device
 pub const DEVICE_SETTLE_MILLIS: u64 = 10;
 
+/// Settle times that are tried by the calibration, in ascending order
+pub const DEVICE_SETTLE_CANDIDATES_MILLIS: &[u64] = &[0, 1, 2, 3, 5, 7];
+
+/// Number of frames that are written per settle time, during the calibration
+pub const DEVICE_CALIBRATION_FRAMES: usize = 5;
+
 /// Update sensors every other second
 pub const SENSOR_UPDATE_TICKS: u64 = 60;
 
+/// Check the KDE settings for a changed accent color every other second
+pub const ACCENT_COLOR_UPDATE_TICKS: u64 = 200;
+
+/// Color temperature of the white point of the screens, the warmth filter is
+/// neutral at and above this temperature
+pub const NEUTRAL_COLOR_TEMPERATURE: u32 = 6500;
+
+/// Lowest color temperature that the warmth filter accepts
+pub const MIN_COLOR_TEMPERATURE: u32 = 1000;
+
+/// The KDE settings file, relative to the home directory of the seat user
+pub const KDEGLOBALS_FILE: &str = ".config/kdeglobals";
+
+/// Highest evdev key code that may be bound to a hotkey
+pub const MAX_EV_KEY_CODE: u32 = 0x2ff;
+
+/// Pseudo key code of the Easy Shift key, that may be used as a modifier of a hotkey
+pub const EASY_SHIFT_KEY_CODE: u32 = MAX_EV_KEY_CODE + 1;
+
+/// Default directory of out-of-tree plugins (shared objects)
+pub const DEFAULT_PLUGIN_DIR: &str = "/usr/lib/eruption/plugins/";
+
+/// Main loop hooks of plugins that take longer are logged, in microseconds
+pub const SLOW_HOOK_MICROS: u64 = 2000;
+
+/// Plugins that the daemon depends on, they may not be disabled
+pub const REQUIRED_PLUGINS: &[&str] = &["Keyboard", "Macros"];
+
+/// Amount of brightness in percent that a hotkey will add or remove
+pub const HOTKEY_BRIGHTNESS_STEP: isize = 10;
+
+/// How long an overlay effect, like the volume bar, is displayed
+pub const OSD_DURATION_MILLIS: u64 = 1000;
+
+/// Duration of the fade out of an overlay effect
+pub const OSD_FADE_MILLIS: u64 = 300;
+
+/// Upper limit of the number of blink patterns that are played simultaneously
+pub const MAX_BLINK_PATTERNS: usize = 8;
+
+/// Upper limit of the number of steps of a blink pattern
+pub const MAX_BLINK_PATTERN_STEPS: usize = 1024;
+
+/// Lower limit of the length of a step of a blink pattern
+pub const MIN_BLINK_PATTERN_UNIT_MILLIS: u64 = 10;
+
+/// Upper limit of the length of a step of a blink pattern
+pub const MAX_BLINK_PATTERN_UNIT_MILLIS: u64 = 10_000;
+
+/// Default time that each LED is lit by the LED test
+pub const LED_TEST_DELAY_MILLIS: u64 = 500;
+
+/// Interval of the evaluation of the alarm rules
+pub const ALARM_CHECK_MILLIS: u64 = 1000;
+
+/// Interval of the evaluation of the rules of the rule engine
+pub const RULES_CHECK_MILLIS: u64 = 1000;
+
+/// An alarm is cleared, once the value is below (or above) the threshold by this amount
+pub const ALARM_HYSTERESIS: f64 = 2.0;
+
+/// Period of the pulse and blink effects of an alarm
+pub const ALARM_PERIOD_MILLIS: u64 = 1000;
+
+/// Interval of the checks for scheduled alerts that are due
+pub const ALERT_CHECK_MILLIS: u64 = 1000;
+
+/// Scheduled alerts that have been missed by more than this, e.g. while the
+/// system was powered off, are dropped
+pub const ALERT_GRACE_SECS: i64 = 5 * 60;
+
+/// Default duration of the effect of a scheduled alert
+pub const ALERT_DEFAULT_DURATION_SECS: u64 = 30;
+
+/// Upper limit of the duration of the effect of a scheduled alert
+pub const ALERT_MAX_DURATION_SECS: u64 = 60 * 60;
+
+/// Upper limit of the number of scheduled alerts
+pub const MAX_ALERTS: usize = 64;
+
+/// Interval in which `eruption identify` polls the daemon for pressed keys
+pub const IDENTIFY_POLL_MILLIS: u64 = 100;
+
+/// The identify mode ends if the client stopped polling for this long
+pub const IDENTIFY_TIMEOUT_MILLIS: u64 = 2000;
+
+/// Brightness of the frozen frame in do-not-disturb mode, in percent
+pub const DND_BRIGHTNESS: isize = 25;
+
+/// Update the state of the power supplies every other second
+pub const POWER_UPDATE_TICKS: u64 = 200;
+
+/// Interval of the queries of the session lock state
+#[cfg(feature = "dbus")]
+pub const SESSION_POLL_MILLIS: u64 = 1000;
+
+/// Location of the power supply information in sysfs
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply/";
+
+/// Sample rate of the recording of the microphone, sufficient for speech
+pub const MIC_SAMPLE_RATE: u32 = 16000;
+
+/// The level of the microphone is computed over chunks of this length
+pub const MIC_CHUNK_MILLIS: u64 = 20;
+
+/// Lowest level of the microphone, in dBFS (the level of silence in 16 bits)
+pub const MIC_MIN_LEVEL_DB: f64 = -96.0;
+
+/// Noise floor of the microphone in dBFS, that is used until it has been measured
+pub const MIC_DEFAULT_NOISE_FLOOR_DB: f64 = -60.0;
+
+/// Default duration of the measurement of the noise floor, after the start of the daemon
+pub const MIC_CALIBRATION_SECS: u64 = 3;
+
+/// Default level above the noise floor in dB, that is considered voice activity
+pub const MIC_VAD_THRESHOLD_DB: f64 = 12.0;
+
+/// Voice activity ends after the level stayed below the threshold for this long
+pub const MIC_VAD_HANGOVER_MILLIS: u64 = 400;
+
+/// Sound that is played on key presses, unless configured otherwise
+pub const DEFAULT_KEY_SOUND: &str = "typewriter1.wav";
+
+/// Default volume of the key sounds, in percent
+pub const DEFAULT_KEY_SOUND_VOLUME: u32 = 80;
+
+/// Default number of key sounds that may be played simultaneously
+pub const DEFAULT_KEY_SOUND_POLYPHONY: usize = 4;
+
+/// Upper limit of the number of key sounds that may be played simultaneously
+pub const MAX_KEY_SOUND_POLYPHONY: usize = 16;
+
+/// Length of the playback buffer of a key sound, a short buffer keeps the sound
+/// in sync with the effects
+pub const KEY_SOUND_LATENCY_MILLIS: u32 = 20;
+
+/// Default interval between two updates of the weather data
+#[cfg(feature = "weather")]
+pub const WEATHER_UPDATE_INTERVAL_SECS: u64 = 15 * 60;
+
+/// Min. interval between two updates of the weather data
+#[cfg(feature = "weather")]
+pub const WEATHER_MIN_UPDATE_INTERVAL_SECS: u64 = 60;
+
+/// Timeout value to use for requests to the weather data provider
+#[cfg(feature = "weather")]
+pub const WEATHER_TIMEOUT_MILLIS: u64 = 5000;
+
+/// Default interval between two updates of the status of the CI pipelines
+#[cfg(feature = "ci")]
+pub const CI_UPDATE_INTERVAL_SECS: u64 = 2 * 60;
+
+/// Min. interval between two updates of the status of the CI pipelines
+#[cfg(feature = "ci")]
+pub const CI_MIN_UPDATE_INTERVAL_SECS: u64 = 30;
+
+/// Timeout value to use for requests to the CI providers
+#[cfg(feature = "ci")]
+pub const CI_TIMEOUT_MILLIS: u64 = 5000;
+
+/// Timeout value of HTTP requests of scripts
+#[cfg(feature = "http")]
+pub const HTTP_TIMEOUT_MILLIS: u64 = 5000;
+
+/// Number of threads that execute the HTTP requests of scripts
+#[cfg(feature = "http")]
+pub const HTTP_WORKER_THREADS: usize = 2;
+
+/// Max. number of HTTP requests per minute and script
+#[cfg(feature = "http")]
+pub const HTTP_MAX_REQUESTS_PER_MINUTE: usize = 10;
+
+/// Max. size of the body of a response to an HTTP request of a script, longer bodies are truncated
+#[cfg(feature = "http")]
+pub const HTTP_MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Default timeout of commands that are executed by scripts
+pub const EXEC_TIMEOUT_MILLIS: u64 = 10000;
+
+/// Number of threads that execute the commands of scripts
+pub const EXEC_WORKER_THREADS: usize = 2;
+
+/// Max. number of commands per minute and script
+pub const EXEC_MAX_COMMANDS_PER_MINUTE: usize = 30;
+
+/// Max. size of the captured output of a command, longer output is truncated
+pub const EXEC_MAX_OUTPUT_SIZE: usize = 64 * 1024;
+
+/// Max. nesting depth of the tables, that are encoded as JSON or TOML
+pub const ENCODE_MAX_DEPTH: usize = 64;
+
+/// Max. number of values, that are encoded as JSON or TOML at once
+pub const ENCODE_MAX_VALUES: usize = 100_000;
+
+/// Default number of colors of the palette of the wallpaper
+pub const WALLPAPER_NUM_COLORS: usize = 6;
+
+/// Default interval in which the wallpaper is checked for changes
+pub const WALLPAPER_POLL_INTERVAL_SECS: u64 = 30;
+
+/// Max. number of pixels of the wallpaper, larger images are not decoded at all
+pub const WALLPAPER_MAX_PIXELS: usize = 16 * 1024 * 1024;
+
+/// Max. number of pixels of the wallpaper that are analyzed, larger images are sampled
+pub const WALLPAPER_MAX_SAMPLES: usize = 64 * 1024;
+
+/// Colors of the wallpaper with all components below this value are not part of the palette
+pub const WALLPAPER_MIN_BRIGHTNESS: u8 = 24;
+
+/// Min. euclidean distance in RGB space between the colors of the palette of the wallpaper
+pub const WALLPAPER_MIN_DISTANCE: i32 = 48;
+
+/// Number of recent log lines to keep in memory
+pub const LOG_BUFFER_SIZE: usize = 512;
+
+/// Upper limit of the number of errors, that are kept until they are acknowledged
+pub const MAX_UNACKNOWLEDGED_ERRORS: usize = 32;
+
+/// Period of the blinking of the error indicator
+pub const ERROR_INDICATOR_PERIOD_MILLIS: u128 = 1000;
+
 /// Timeout value to use for D-Bus connections
 pub const DBUS_TIMEOUT_MILLIS: u32 = 250;
 
+/// Max. time to wait for the main loop to pause rendering, before the system may suspend
+pub const SLEEP_PAUSE_TIMEOUT_MILLIS: u64 = 1500;
+
+/// Default multicast group used by the network sync mode
+pub const DEFAULT_SYNC_ADDRESS: &str = "239.255.42.99";
+
+/// Default UDP port used by the network sync mode
+pub const DEFAULT_SYNC_PORT: u16 = 8093;
+
+/// Multicast TTL of the frames sent by the leader, keep them on the local network
+pub const SYNC_MULTICAST_TTL: u32 = 1;
+
+/// Followers fall back to their local effects, if no frame has been received for this long
+pub const SYNC_TIMEOUT_MILLIS: u64 = 1000;
+
+/// Max. number of frames that may be reordered by the network, older frames are dropped
+pub const SYNC_MAX_REORDER: u32 = 100;
+
+/// Upper limit of the latency compensation of the leader
+pub const SYNC_MAX_LATENCY_MILLIS: u64 = 1000;
+
+/// Max. number of frames that are delayed by the leader, to compensate for the latency
+pub const SYNC_MAX_DELAYED_FRAMES: usize = 256;
+
+/// Default UDP port of the realtime protocol of WLED
+pub const DEFAULT_LED_STRIP_PORT: u16 = 21324;
+
+/// Default number of LEDs of the virtual LED strip
+pub const DEFAULT_LED_STRIP_NUM_LEDS: usize = 60;
+
+/// Max. number of LEDs of the virtual LED strip
+pub const LED_STRIP_MAX_LEDS: usize = 1500;
+
+/// Interval in which the address of the LED strip is resolved again
+pub const LED_STRIP_RESOLVE_INTERVAL_SECS: u64 = 60;
+
+/// The LED strip returns to its own effects, if no frame has been received for this long
+pub const LED_STRIP_TIMEOUT_SECS: u8 = 2;
+
+/// Number of attempts to write a frame to the device, before the frame is dropped
+pub const DEVICE_WRITE_ATTEMPTS: usize = 3;
+
+/// Re-open a device after this many consecutive frames could not be written
+pub const DEVICE_REOPEN_THRESHOLD: u32 = 10;
+
+/// Min. interval between two attempts to re-open a failed device
+pub const DEVICE_REOPEN_INTERVAL_MILLIS: u64 = 1000;
+
+/// Timeout of reads from the control device, the reading thread checks for shutdown in between
+pub const DEVICE_READ_TIMEOUT_MILLIS: i32 = 100;
+
+/// Max. time to wait for the control device to acknowledge a command
+pub const DEVICE_RESPONSE_TIMEOUT_MILLIS: u64 = 2000;
+
+/// A device is considered unresponsive, if writing a frame takes longer than this
+pub const DEVICE_WRITE_TIMEOUT_MILLIS: u64 = 250;
+
+/// Unchanged frames are written to the device again after this interval
+pub const DEVICE_REFRESH_INTERVAL_MILLIS: u64 = 1000;
+
+/// The device that is used to inject keys, it is probed by the startup self-test
+pub const UINPUT_DEVICE: &str = "/dev/uinput";
+
+/// Time that an external effect is given to exit after the `quit` event, before it is killed
+pub const EXEC_TERMINATE_TIMEOUT_MILLIS: u64 = 500;
+
+/// Maximum number of events that are queued for an external effect, while it does
+/// not read its standard input. Further events are dropped
+pub const EXEC_EVENT_QUEUE_LEN: usize = 256;
+
+/// Default debounce window, a press within this time after the release of the
+/// same key is considered chatter of the switch
+pub const DEFAULT_DEBOUNCE_MILLIS: u64 = 30;
+
+/// Default time after which a key that is held down is considered stuck
+pub const DEFAULT_STUCK_KEY_SECS: u64 = 60;
+
+/// Number of recent samples per stage that the latency percentiles are computed from
+pub const LATENCY_SAMPLES: usize = 4096;
+
+/// Maximum number of traced events that are on their way through the input path
+pub const LATENCY_MAX_PENDING: usize = 256;
+
+/// Default interval of the latency reports in the log
+pub const DEFAULT_LATENCY_REPORT_SECS: u64 = 60;
+
+/// Default location of the named pipe, that chat clients write mentions to
+pub const DEFAULT_MENTION_PIPE: &str = "/run/eruption/mentions";
+
+/// Access mode of the named pipe of mentions, it is owned by the user of the active
+/// session on the seat
+pub const MENTION_PIPE_MODE: libc::mode_t = 0o600;
+
+/// Default path of the socket of the debug console
+pub const DEFAULT_REPL_SOCKET: &str = "/run/eruption/repl.sock";
+
+/// Upper limit of the number of clients of the debug console
+pub const REPL_MAX_CLIENTS: usize = 4;
+
+/// How long to wait for a script to evaluate a snippet of the debug console
+pub const REPL_EVAL_TIMEOUT_MILLIS: u64 = 2000;
+
+/// Calls into a WASM effect are interrupted, if they don't return within this time
+pub const WASM_CALL_TIMEOUT_MILLIS: u64 = 500;
+
+/// Upper limit of the number of entries of a table, displayed by the debug console
+pub const REPL_MAX_TABLE_ENTRIES: usize = 32;
+
+/// A script that is paused by the debugger is resumed after this timeout
+pub const DEBUGGER_PAUSE_TIMEOUT_SECS: u64 = 30;
+
+/// Upper limit of the number of variables, that are read from a paused script
+pub const DEBUGGER_MAX_VARIABLES: usize = 200;
+
+/// How long `eruption debug step` waits for the script to pause again
+pub const DEBUGGER_STEP_WAIT_MILLIS: u64 = 100;
+
+/// Default name of the shared memory segment, that the frames are exported to
+pub const DEFAULT_SHM_FRAME_NAME: &str = "/eruption-frame";
+
+/// Access mode of the shared memory segment, readable by all local users
+pub const SHM_FRAME_MODE: libc::mode_t = 0o644;
+
+/// Max. length of the text of a mention in characters, longer texts are truncated
+pub const MAX_MENTION_TEXT_LENGTH: usize = 1024;
+
+/// Max. length of a line, that is read from the named pipe of mentions in bytes,
+/// the rest of a longer line is discarded
+pub const MAX_MENTION_LINE_LENGTH: usize = 4096;
+
+/// Upper limit of the number of mentions per minute, that are passed to the scripts
+pub const MAX_MENTIONS_PER_MINUTE: usize = 30;
+
+/// Speed of the color quantization, when exporting recordings as GIF (1: best quality, 30: fastest)
+pub const GIF_QUANTIZATION_SPEED: i32 = 10;
+
+// REST API
+
+/// Default listen address of the REST API, use "0.0.0.0:8060" to make it available on the LAN
+#[cfg(feature = "rest")]
+pub const DEFAULT_REST_LISTEN_ADDRESS: &str = "127.0.0.1:8060";
+
+/// Default file of the access token of the REST API, readable by root and the seat user
+#[cfg(feature = "rest")]
+pub const DEFAULT_REST_TOKEN_FILE: &str = "/run/eruption/rest-token";
+
 // Browser-based GUI
 
 /// Default listen address of the web frontend
@@ -55,3 +490,60 @@ pub const WEB_FRONTEND_PORT: u16 = 8059;
 /// Default web frontend theme. Available themese are "eruption" and "metal"
 #[cfg(feature = "frontend")]
 pub const DEFAULT_FRONTEND_THEME: &str = "eruption";
+
+/// Default duration of the transition between profiles
+pub const TRANSITION_DURATION_MILLIS: u64 = 500;
+
+/// Upper limit of the duration of the transition between profiles
+pub const TRANSITION_MAX_DURATION_MILLIS: u64 = 5000;
+
+/// Upper limit of the number of injected keys, that are queued on the virtual keyboard
+pub const MACRO_MAX_QUEUE_DEPTH: usize = 1024;
+
+/// Delay between injected keys, that are queued on the virtual keyboard
+pub const MACRO_STEP_DELAY_MILLIS: u64 = 0;
+
+/// Upper limit of the delay after an injected key
+pub const MACRO_MAX_PACING_MILLIS: u64 = 1000;
+
+/// Default initial speed of the pointer keys, in pixels per second
+pub const POINTER_KEYS_SPEED: f64 = 200.0;
+
+/// Default maximum speed of the pointer keys, in pixels per second
+pub const POINTER_KEYS_MAX_SPEED: f64 = 1500.0;
+
+/// Default time until the pointer keys reach their maximum speed
+pub const POINTER_KEYS_ACCEL_MILLIS: u64 = 1000;
+
+/// Interval in which the pointer of the virtual mouse is moved
+pub const POINTER_KEYS_INTERVAL_MILLIS: u64 = 10;
+
+/// Interval in which held scroll keys turn the wheel of the virtual mouse
+pub const POINTER_KEYS_SCROLL_REPEAT_MILLIS: u64 = 100;
+
+/// Steps of the horizontal wheel of the virtual mouse, per step of the dial
+pub const DIAL_SCROLL_STEP: i32 = 1;
+
+/// Feature reports that scripts may access by default, if raw HID access is
+/// enabled: Only the status (0x0f), which is read-only. Reports that write the
+/// hardware profile (0x0d) or select it (0x13) have to be allowed explicitly
+pub const RAW_HID_ALLOWED_REPORTS: &[u8] = &[0x0f];
+
+/// Max. size of a raw feature report in bytes
+pub const RAW_HID_MAX_REPORT_SIZE: usize = 1024;
+
+/// Default path of the socket, that key events are received on
+pub const DEFAULT_INPUT_SOCKET: &str = "/run/eruption/input.sock";
+
+/// File mode of the input socket, only root may send key events
+pub const INPUT_SOCKET_MODE: u32 = 0o600;
+
+/// Max. size of a datagram of the input socket, in bytes
+pub const INPUT_SOCKET_MAX_DATAGRAM_SIZE: usize = 1500;
+
+/// Initial delay before an input device that went away is re-opened, it is doubled
+/// after each failed attempt
+pub const INPUT_REOPEN_MIN_MILLIS: u64 = 250;
+
+/// Upper limit of the delay between two attempts to re-open an input device
+pub const INPUT_REOPEN_MAX_MILLIS: u64 = 10000;
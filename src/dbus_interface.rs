@@ -17,35 +17,64 @@
 
 #[cfg(feature = "dbus")]
 use dbus::{
-    ffidisp::BusType, ffidisp::Connection, ffidisp::NameFlag, message::SignalArgs, tree::Access,
-    tree::EmitsChangedSignal, tree::Factory, tree::MethodErr, tree::Signal,
+    arg::Variant, ffidisp::BusType, ffidisp::Connection, ffidisp::NameFlag, message::SignalArgs,
+    tree::Access, tree::EmitsChangedSignal, tree::Factory, tree::MethodErr, tree::Signal,
+    Message as DbusMessage,
 };
-use failure::Fail;
 use log::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 
+use crate::alerts;
 use crate::constants;
+use crate::display;
+use crate::identify;
+use crate::latency;
+use crate::logging;
+use crate::overlay;
+use crate::paths;
+use crate::plugin_manager;
 use crate::plugins::audio;
-use crate::profiles;
-use crate::CONFIG;
+use crate::plugins::{accent, AccentPlugin, PomodoroPlugin};
+use crate::profiles::{self, GetAttr};
+use crate::rvdevice::{DeviceInfo, RGBA};
+use crate::scripting::capabilities;
+use crate::scripting::debugger;
+use crate::scripting::overrides;
+use crate::scripting::raw_hid;
+use crate::scripting::script;
+use crate::warmth;
+use crate::windows;
+use crate::ACTIVE_SCRIPTS;
 
 /// D-Bus messages and signals that are processed by the main thread
 #[derive(Debug, Clone)]
 pub enum Message {
     SwitchProfile(PathBuf),
+    PreviewProfile(PathBuf, u32),
+    CommitPreview,
+    CancelPreview,
+
+    /// Reload the active profile, e.g. after a plugin has been enabled or disabled
+    ReloadProfile,
     //LoadScript(PathBuf),
 }
 
 pub type Result<T> = std::result::Result<T, DbusApiError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum DbusApiError {
-    #[fail(display = "D-Bus not connected")]
+    #[error("D-Bus not connected")]
     BusNotConnected {},
-    // #[fail(display = "Unknown error: {}", description)]
+
+    #[error("D-Bus method call failed: {description}")]
+    MethodCallError { description: String },
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
@@ -56,18 +85,31 @@ pub struct DbusApi {
 
     active_profile_changed: Arc<Signal<()>>,
     profiles_changed: Arc<Signal<()>>,
+    device_error: Arc<Signal<()>>,
+    device_recovered: Arc<Signal<()>>,
 }
 
 #[cfg(feature = "dbus")]
 impl DbusApi {
     /// Initialize the D-Bus API
-    pub fn new(dbus_tx: Sender<Message>) -> Self {
-        let c = Connection::get_private(BusType::System).unwrap();
+    pub fn new(dbus_tx: Sender<Message>, device_info: Option<DeviceInfo>) -> Result<Self> {
+        let c = Connection::get_private(BusType::System)
+            .map_err(|_e| DbusApiError::BusNotConnected {})?;
         c.register_name("org.eruption", NameFlag::ReplaceExisting as u32)
-            .unwrap();
+            .map_err(|e| DbusApiError::MethodCallError {
+                description: format!("{}", e),
+            })?;
 
         let c_clone = Arc::new(c);
         let c_clone2 = c_clone.clone();
+        let c_windows = c_clone.clone();
+        let c_fullscreen = c_clone.clone();
+        let c_dnd = c_clone.clone();
+
+        let dbus_tx_preview = dbus_tx.clone();
+        let dbus_tx_commit = dbus_tx.clone();
+        let dbus_tx_cancel = dbus_tx.clone();
+        let dbus_tx_plugins = dbus_tx.clone();
 
         let f = Factory::new_fn::<()>();
 
@@ -80,6 +122,17 @@ impl DbusApi {
         let profiles_changed_signal = Arc::new(f.signal("ProfilesChanged", ()));
         let profiles_changed_signal_clone = profiles_changed_signal.clone();
 
+        let device_error_signal = Arc::new(
+            f.signal("DeviceError", ())
+                .sarg::<u64, _>("device")
+                .sarg::<String, _>("description"),
+        );
+        let device_error_signal_clone = device_error_signal.clone();
+
+        let device_recovered_signal =
+            Arc::new(f.signal("DeviceRecovered", ()).sarg::<u64, _>("device"));
+        let device_recovered_signal_clone = device_recovered_signal.clone();
+
         let active_profile_property = f
             .property::<String, _>("ActiveProfile", ())
             .emits_changed(EmitsChangedSignal::Const)
@@ -88,12 +141,9 @@ impl DbusApi {
 
                 let result = result
                     .as_ref()
-                    .unwrap()
-                    .profile_file
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .ok_or_else(|| MethodErr::failed("Method failed"))?;
+                    .and_then(|profile| profile.profile_file.file_name())
+                    .and_then(|file_name| file_name.to_str())
+                    .ok_or_else(|| MethodErr::failed("No active profile"))?;
 
                 i.append(result);
                 Ok(())
@@ -119,6 +169,26 @@ impl DbusApi {
 
         let enable_sfx_property_clone = Arc::new(enable_sfx_property);
 
+        let dnd_property = f
+            .property::<bool, _>("DoNotDisturb", ())
+            .emits_changed(EmitsChangedSignal::True)
+            .access(Access::ReadWrite)
+            .auto_emit_on_set(true)
+            .on_get(|i, _m| {
+                i.append(crate::DO_NOT_DISTURB.load(Ordering::SeqCst));
+
+                Ok(())
+            })
+            .on_set(move |i, m| {
+                check_seat_user(&c_dnd, m.msg)?;
+
+                crate::DO_NOT_DISTURB.store(i.read::<bool>()?, Ordering::SeqCst);
+
+                Ok(())
+            });
+
+        let dnd_property_clone = Arc::new(dnd_property);
+
         let brightness_property = f
             .property::<i64, _>("Brightness", ())
             .emits_changed(EmitsChangedSignal::True)
@@ -137,187 +207,1662 @@ impl DbusApi {
 
         let brightness_property_clone = Arc::new(brightness_property);
 
+        let device_info = device_info.unwrap_or_default();
+
         let tree = f
             .tree(())
             .add(
                 f.object_path("/org/eruption/status", ())
                     .introspectable()
                     .add(
-                        f.interface("org.eruption.Status", ()).add_p(
-                            f.property::<bool, _>("Running", ())
-                                .emits_changed(EmitsChangedSignal::True)
-                                .on_get(|i, _m| {
-                                    i.append(true);
-                                    Ok(())
-                                })
-                                .on_set(|i, _m| {
-                                    let _b: bool = i.read()?;
-                                    Ok(())
-                                }),
-                        ),
+                        f.interface("org.eruption.Status", ())
+                            .add_m(
+                                f.method("GetLatencyMetrics", (), move |m| {
+                                    let s = latency::metrics();
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<HashMap<String, u64>, _>("metrics"),
+                            )
+                            .add_p(
+                                f.property::<bool, _>("Running", ())
+                                    .emits_changed(EmitsChangedSignal::True)
+                                    .on_get(|i, _m| {
+                                        i.append(true);
+                                        Ok(())
+                                    })
+                                    .on_set(|i, _m| {
+                                        let _b: bool = i.read()?;
+                                        Ok(())
+                                    }),
+                            ),
                     ),
             )
             .add(
-                f.object_path("/org/eruption/config", ())
+                f.object_path("/org/eruption/plugins", ())
                     .introspectable()
                     .add(
-                        f.interface("org.eruption.Config", ())
-                            .add_p(enable_sfx_property_clone)
-                            .add_p(brightness_property_clone),
+                        f.interface("org.eruption.Plugins", ())
+                            .add_m(
+                                f.method("GetPlugins", (), move |m| {
+                                    let s: Vec<(String, String, bool)> =
+                                        plugin_manager::PLUGIN_MANAGER
+                                            .read()
+                                            .get_plugin_infos()
+                                            .into_iter()
+                                            .map(|p| (p.name, p.description, p.enabled))
+                                            .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(String, String, bool)>, _>("plugins"),
+                            )
+                            .add_m(
+                                f.method("SetPluginEnabled", (), move |m| {
+                                    let (name, enabled): (&str, bool) = m.msg.read2()?;
+
+                                    let changed = plugin_manager::set_plugin_enabled(name, enabled)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                    // the Lua functions of the plugins are registered
+                                    // when the scripts are loaded
+                                    if changed {
+                                        dbus_tx_plugins
+                                            .send(Message::ReloadProfile)
+                                            .unwrap_or_else(|e| {
+                                                error!(
+                                                    "Could not send a pending D-Bus event: {}",
+                                                    e
+                                                )
+                                            });
+                                    }
+
+                                    Ok(vec![m.msg.method_return().append1(changed)])
+                                })
+                                .inarg::<&str, _>("name")
+                                .inarg::<bool, _>("enabled")
+                                .outarg::<bool, _>("changed"),
+                            ),
                     ),
             )
             .add(
-                f.object_path("/org/eruption/profile", ())
+                f.object_path("/org/eruption/device", ())
                     .introspectable()
                     .add(
-                        f.interface("org.eruption.Profile", ())
-                            .add_s(profiles_changed_signal_clone)
-                            .add_s(active_profile_changed_signal_clone)
-                            .add_p(active_profile_property_clone.clone())
+                        f.interface("org.eruption.Device", ())
+                            .add_s(device_error_signal_clone)
+                            .add_s(device_recovered_signal_clone)
                             .add_m(
-                                f.method("SwitchProfile", (), move |m| {
-                                    let n: &str = m.msg.read1()?;
+                                f.method("GetDeviceInfo", (), move |m| {
+                                    let mut s: HashMap<String, String> = HashMap::new();
 
-                                    dbus_tx
-                                        .send(Message::SwitchProfile(PathBuf::from(n)))
-                                        .unwrap_or_else(|e| {
-                                            error!("Could not send a pending D-Bus event: {}", e)
-                                        });
+                                    s.insert("model_name".into(), device_info.model_name.clone());
+                                    s.insert(
+                                        "firmware_version".into(),
+                                        device_info.firmware_version.clone(),
+                                    );
+                                    s.insert(
+                                        "serial_number".into(),
+                                        device_info.serial_number.clone(),
+                                    );
+                                    s.insert("usb_path".into(), device_info.usb_path.clone());
 
-                                    //c_clone2
-                                    //.send(active_profile_changed_signal_clone.emit(
-                                    //&"/org/eruption/profile".into(),
-                                    //&"org.eruption.Profile".into(),
-                                    //&[n],
-                                    //))
-                                    //.unwrap();
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<HashMap<String, String>, _>("device_info"),
+                            ),
+                    )
+                    .add(
+                        f.interface("org.eruption.Identify", ())
+                            .add_m(
+                                f.method("StartIdentify", (), move |m| {
+                                    identify::start();
 
-                                    let mut changed_properties = Vec::new();
-                                    active_profile_property_clone.add_propertieschanged(
-                                        &mut changed_properties,
-                                        &"org.eruption".into(),
-                                        || Box::new(n.to_owned()),
-                                    );
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetIdentifiedKey", (), move |m| {
+                                    let reply = match identify::poll() {
+                                        Some(Some(key)) => m
+                                            .msg
+                                            .method_return()
+                                            .append3(true, key.seq, key.index as u32)
+                                            .append2(key.code, key.name),
 
-                                    if !changed_properties.is_empty() {
-                                        let msg = changed_properties
-                                            .first()
-                                            .unwrap()
-                                            .to_emit_message(&"/org/eruption/profile".into());
-                                        c_clone2.clone().send(msg).unwrap();
-                                    }
+                                        Some(None) => m
+                                            .msg
+                                            .method_return()
+                                            .append3(true, 0u32, 0u32)
+                                            .append2(0u32, String::new()),
+
+                                        None => m
+                                            .msg
+                                            .method_return()
+                                            .append3(false, 0u32, 0u32)
+                                            .append2(0u32, String::new()),
+                                    };
+
+                                    Ok(vec![reply])
+                                })
+                                .outarg::<bool, _>("active")
+                                .outarg::<u32, _>("seq")
+                                .outarg::<u32, _>("index")
+                                .outarg::<u32, _>("code")
+                                .outarg::<String, _>("name"),
+                            ),
+                    )
+                    .add(
+                        f.interface("org.eruption.RawHid", ())
+                            .add_m(
+                                f.method("SendFeatureReport", (), move |m| {
+                                    let (device, report): (u32, Vec<u8>) = m.msg.read2()?;
+
+                                    raw_hid::send_feature_to(device as usize, &report)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
 
                                     let s = true;
                                     Ok(vec![m.msg.method_return().append1(s)])
                                 })
-                                .inarg::<&str, _>("filename")
+                                .inarg::<u32, _>("device")
+                                .inarg::<Vec<u8>, _>("report")
                                 .outarg::<bool, _>("status"),
                             )
                             .add_m(
-                                f.method("EnumProfiles", (), move |m| {
-                                    let profile_dir = PathBuf::from(
-                                        CONFIG
-                                            .lock()
-                                            .as_ref()
-                                            .unwrap()
-                                            .get_str("global.profile_dir")
-                                            .unwrap_or_else(|_| {
-                                                constants::DEFAULT_PROFILE_DIR.to_string()
-                                            }),
-                                    );
+                                f.method("GetFeatureReport", (), move |m| {
+                                    let (device, id, len): (u32, u8, u32) = m.msg.read3()?;
 
-                                    let mut s: Vec<(String, String)> =
-                                        profiles::get_profiles(&profile_dir)
-                                            .unwrap()
-                                            .iter()
-                                            .map(|profile| {
-                                                (
-                                                    profile.name.clone(),
-                                                    profile
-                                                        .profile_file
-                                                        .file_name()
-                                                        .unwrap()
-                                                        .to_string_lossy()
-                                                        .to_string(),
-                                                )
-                                            })
-                                            .collect();
+                                    let s = raw_hid::get_feature_from(
+                                        device as usize,
+                                        id,
+                                        len as usize,
+                                    )
+                                    .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
 
-                                    s.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("device")
+                                .inarg::<u8, _>("id")
+                                .inarg::<u32, _>("len")
+                                .outarg::<Vec<u8>, _>("report"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/debugger", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Debugger", ())
+                            .add_m(
+                                f.method("SetBreakpoint", (), move |m| {
+                                    let (script, line): (&str, u32) = m.msg.read2()?;
+
+                                    if !debugger::is_enabled() {
+                                        return Err(MethodErr::failed(
+                                            "The debugger is not enabled",
+                                        ));
+                                    }
 
+                                    let s = debugger::set_breakpoint(script, line);
                                     Ok(vec![m.msg.method_return().append1(s)])
                                 })
-                                .outarg::<Vec<(String, String)>, _>("profiles"),
+                                .inarg::<&str, _>("script")
+                                .inarg::<u32, _>("line")
+                                .outarg::<bool, _>("inserted"),
+                            )
+                            .add_m(
+                                f.method("ClearBreakpoint", (), move |m| {
+                                    let (script, line): (&str, u32) = m.msg.read2()?;
+
+                                    let s = debugger::clear_breakpoint(script, line);
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("script")
+                                .inarg::<u32, _>("line")
+                                .outarg::<bool, _>("removed"),
+                            )
+                            .add_m(
+                                f.method("GetBreakpoints", (), move |m| {
+                                    let s = debugger::get_breakpoints();
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(String, u32)>, _>("breakpoints"),
+                            )
+                            .add_m(
+                                f.method("GetPaused", (), move |m| {
+                                    let reply = match debugger::get_paused() {
+                                        Some(paused) => m
+                                            .msg
+                                            .method_return()
+                                            .append3(true, paused.script, paused.line)
+                                            .append2(paused.variables, paused.locals),
+
+                                        None => m
+                                            .msg
+                                            .method_return()
+                                            .append3(false, String::new(), 0u32)
+                                            .append2(
+                                                Vec::<(String, String)>::new(),
+                                                Vec::<(String, String)>::new(),
+                                            ),
+                                    };
+
+                                    Ok(vec![reply])
+                                })
+                                .outarg::<bool, _>("paused")
+                                .outarg::<String, _>("script")
+                                .outarg::<u32, _>("line")
+                                .outarg::<Vec<(String, String)>, _>("variables")
+                                .outarg::<Vec<(String, String)>, _>("locals"),
+                            )
+                            .add_m(
+                                f.method("Resume", (), move |m| {
+                                    let command: &str = m.msg.read1()?;
+
+                                    let command = match command {
+                                        "continue" => debugger::Command::Continue,
+                                        "step" => debugger::Command::Step,
+
+                                        _ => return Err(MethodErr::invalid_arg(&command)),
+                                    };
+
+                                    let s = debugger::resume(command);
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("command")
+                                .outarg::<bool, _>("resumed"),
                             ),
                     ),
-            );
+            )
+            .add(
+                f.object_path("/org/eruption/logging", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Logging", ())
+                            .add_m(
+                                f.method("GetLogFilter", (), move |m| {
+                                    let s = logging::get_log_filter();
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<String, _>("filter"),
+                            )
+                            .add_m(
+                                f.method("SetLogFilter", (), move |m| {
+                                    let n: &str = m.msg.read1()?;
 
-        tree.set_registered(&*c_clone, true).unwrap();
-        c_clone.add_handler(tree);
+                                    logging::set_log_filter(n)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
 
-        DbusApi {
-            connection: Some(c_clone),
-            active_profile_changed: active_profile_changed_signal,
-            profiles_changed: profiles_changed_signal,
-        }
-    }
+                                    info!("Log filter changed to: {}", n);
 
-    pub fn notify_active_profile_changed(&self) {
-        let active_profile = crate::ACTIVE_PROFILE.lock();
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("filter")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetLogLines", (), move |m| {
+                                    let n: u32 = m.msg.read1()?;
 
-        let active_profile = active_profile
-            .as_ref()
-            .unwrap()
-            .profile_file
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap();
+                                    let s = logging::get_recent_log_lines(n as usize);
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("count")
+                                .outarg::<Vec<String>, _>("lines"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/errors", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Errors", ())
+                            .add_m(
+                                f.method("GetErrors", (), move |m| {
+                                    let s = logging::get_unacknowledged_errors();
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<String>, _>("errors"),
+                            )
+                            .add_m(
+                                f.method("AcknowledgeErrors", (), move |m| {
+                                    let s = logging::acknowledge_errors() as u32;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<u32, _>("count"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/osd", ()).introspectable().add(
+                    f.interface("org.eruption.Osd", ())
+                        .add_m(
+                            f.method("SetProgress", (), move |m| {
+                                let (percent, color, seconds): (f64, u32, u32) = m.msg.read3()?;
 
-        self.connection
-            .as_ref()
-            .unwrap()
-            .send(self.active_profile_changed.emit(
-                &"/org/eruption/profile".into(),
-                &"org.eruption.Profile".into(),
-                &[active_profile],
-            ))
-            .unwrap();
-    }
+                                let color = RGBA {
+                                    r: ((color >> 16) & 0xff) as u8,
+                                    g: ((color >> 8) & 0xff) as u8,
+                                    b: (color & 0xff) as u8,
+                                    a: 0xff,
+                                };
 
-    pub fn notify_profiles_changed(&self) {
-        self.connection
-            .as_ref()
-            .unwrap()
-            .send(self.profiles_changed.msg(
-                &"/org/eruption/profile".into(),
-                &"org.eruption.Profile".into(),
-            ))
-            .unwrap();
-    }
+                                overlay::show_progress(
+                                    percent,
+                                    color,
+                                    Duration::from_secs(seconds.into()),
+                                );
 
-    /// Get the next event from D-Bus
-    pub fn get_next_event(&self) -> Result<()> {
-        match self.connection {
-            Some(ref connection) => {
-                if let Some(item) = connection.incoming(constants::DBUS_TIMEOUT_MILLIS).next() {
-                    debug!("Message: {:?}", item);
-                } else {
-                    trace!("Received a timeout message");
-                }
+                                let s = true;
+                                Ok(vec![m.msg.method_return().append1(s)])
+                            })
+                            .inarg::<f64, _>("percent")
+                            .inarg::<u32, _>("color")
+                            .inarg::<u32, _>("seconds")
+                            .outarg::<bool, _>("status"),
+                        )
+                        .add_m(
+                            f.method("ClearProgress", (), move |m| {
+                                overlay::hide_progress();
 
-                Ok(())
-            }
+                                let s = true;
+                                Ok(vec![m.msg.method_return().append1(s)])
+                            })
+                            .outarg::<bool, _>("status"),
+                        ),
+                ),
+            )
+            .add(
+                f.object_path("/org/eruption/timer", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Timer", ())
+                            .add_m(
+                                f.method("StartPomodoro", (), move |m| {
+                                    let (work_mins, break_mins): (u32, u32) = m.msg.read2()?;
 
-            None => Err(DbusApiError::BusNotConnected {}),
-        }
-    }
-}
+                                    PomodoroPlugin::start(work_mins, break_mins);
 
-/// Initialize the Eruption D-Bus API support
-#[cfg(feature = "dbus")]
-pub fn initialize(dbus_tx: Sender<Message>) -> Result<DbusApi> {
-    Ok(DbusApi::new(dbus_tx))
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("work_mins")
+                                .inarg::<u32, _>("break_mins")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StopPomodoro", (), move |m| {
+                                    PomodoroPlugin::stop();
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetStatus", (), move |m| {
+                                    let (phase, remaining) = match PomodoroPlugin::get_status() {
+                                        Some((phase, remaining)) => (phase.name(), remaining),
+                                        None => ("stopped", 0),
+                                    };
+
+                                    Ok(vec![m.msg.method_return().append2(phase, remaining)])
+                                })
+                                .outarg::<&str, _>("phase")
+                                .outarg::<u32, _>("remaining"),
+                            )
+                            .add_m(
+                                f.method("ScheduleAlert", (), move |m| {
+                                    let (at_time, effect, params): (
+                                        &str,
+                                        &str,
+                                        HashMap<String, String>,
+                                    ) = m.msg.read3()?;
+
+                                    let id = alerts::schedule(at_time, effect, &params)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                    Ok(vec![m.msg.method_return().append1(id)])
+                                })
+                                .inarg::<&str, _>("at_time")
+                                .inarg::<&str, _>("effect")
+                                .inarg::<HashMap<String, String>, _>("params")
+                                .outarg::<u32, _>("id"),
+                            )
+                            .add_m(
+                                f.method("CancelAlert", (), move |m| {
+                                    let id: u32 = m.msg.read1()?;
+
+                                    let s = alerts::cancel(id)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("id")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetAlerts", (), move |m| {
+                                    let s: Vec<(u32, i64, String, u64)> = alerts::get_alerts()
+                                        .into_iter()
+                                        .map(|a| (a.id, a.at, a.effect, a.duration_secs))
+                                        .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(u32, i64, String, u64)>, _>("alerts"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/windows", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Windows", ())
+                            .add_m(
+                                f.method("SetActiveApp", (), move |m| {
+                                    check_seat_user(&c_windows, m.msg)?;
+
+                                    let class: &str = m.msg.read1()?;
+
+                                    windows::set_active_app(class);
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("class")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetActiveApp", (), move |m| {
+                                    let s = windows::get_active_app().unwrap_or_default();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<&str, _>("class"),
+                            )
+                            .add_m(
+                                f.method("SetFullscreen", (), move |m| {
+                                    check_seat_user(&c_fullscreen, m.msg)?;
+
+                                    let fullscreen: bool = m.msg.read1()?;
+
+                                    windows::set_fullscreen(fullscreen);
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<bool, _>("fullscreen")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("IsFullscreen", (), move |m| {
+                                    let s = windows::is_fullscreen();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<bool, _>("fullscreen"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/desktop", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Desktop", ())
+                            .add_m(
+                                f.method("SetAccentColor", (), move |m| {
+                                    let color: &str = m.msg.read1()?;
+
+                                    let color = accent::parse_color(color)
+                                        .ok_or_else(|| MethodErr::invalid_arg(&color))?;
+
+                                    AccentPlugin::set_accent_color(color);
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("color")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetAccentColor", (), move |m| {
+                                    let s = AccentPlugin::get_accent_color().unwrap_or(0);
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<u32, _>("color"),
+                            )
+                            .add_m(
+                                f.method("SetColorTemperature", (), move |m| {
+                                    let kelvin: u32 = m.msg.read1()?;
+
+                                    warmth::set_color_temperature(kelvin);
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("kelvin")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetColorTemperature", (), move |m| {
+                                    let s = warmth::get_color_temperature();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<u32, _>("kelvin"),
+                            )
+                            .add_m(
+                                f.method("SetDisplayBlanked", (), move |m| {
+                                    let blanked: bool = m.msg.read1()?;
+
+                                    display::set_blanked(blanked);
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<bool, _>("blanked")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("IsDisplayBlanked", (), move |m| {
+                                    let s = display::is_blanked();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<bool, _>("blanked"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/config", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Config", ())
+                            .add_p(enable_sfx_property_clone)
+                            .add_p(dnd_property_clone)
+                            .add_p(brightness_property_clone)
+                            .add_m(
+                                f.method("GetActiveScripts", (), move |m| {
+                                    let scripts: Vec<(String, String)> = ACTIVE_SCRIPTS
+                                        .lock()
+                                        .iter()
+                                        .map(|manifest| {
+                                            (
+                                                manifest.name.clone(),
+                                                capabilities::describe(manifest),
+                                            )
+                                        })
+                                        .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(scripts)])
+                                })
+                                .outarg::<Vec<(&str, &str)>, _>("scripts"),
+                            )
+                            .add_m(
+                                f.method("SetParam", (), move |m| {
+                                    let (script, name, value, persist): (&str, &str, &str, bool) =
+                                        m.msg.read4()?;
+
+                                    overrides::set(script, name, value, persist)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("script")
+                                .inarg::<&str, _>("name")
+                                .inarg::<&str, _>("value")
+                                .inarg::<bool, _>("persist")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetParams", (), move |m| {
+                                    let params: Vec<(String, String, String)> =
+                                        overrides::get_all()
+                                            .into_iter()
+                                            .flat_map(|(script, params)| {
+                                                params.into_iter().map(move |param| {
+                                                    (
+                                                        script.clone(),
+                                                        param.get_name().clone(),
+                                                        param.get_value(),
+                                                    )
+                                                })
+                                            })
+                                            .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(params)])
+                                })
+                                .outarg::<Vec<(String, String, String)>, _>("params"),
+                            )
+                            .add_m(
+                                f.method("ClearParams", (), move |m| {
+                                    let script: &str = m.msg.read1()?;
+                                    let script = if script.is_empty() {
+                                        None
+                                    } else {
+                                        Some(script)
+                                    };
+
+                                    overrides::clear(script)
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("script")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetLayers", (), move |m| {
+                                    let layers: Vec<(u32, String, u32, bool)> = get_layer_scripts()
+                                        .into_iter()
+                                        .enumerate()
+                                        .map(|(layer, script)| {
+                                            (
+                                                layer as u32,
+                                                script,
+                                                script::get_layer_opacity(layer),
+                                                script::is_layer_enabled(layer),
+                                            )
+                                        })
+                                        .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(layers)])
+                                })
+                                .outarg::<Vec<(u32, &str, u32, bool)>, _>("layers"),
+                            )
+                            .add_m(
+                                f.method("SetLayerOpacity", (), move |m| {
+                                    let (layer, percent, fade_millis): (u32, u32, u32) =
+                                        m.msg.read3()?;
+
+                                    check_layer(layer)?;
+
+                                    if percent > 100 {
+                                        return Err(MethodErr::invalid_arg(&percent));
+                                    }
+
+                                    script::set_layer_opacity(
+                                        layer as usize,
+                                        percent,
+                                        Duration::from_millis(fade_millis as u64),
+                                    );
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("layer")
+                                .inarg::<u32, _>("percent")
+                                .inarg::<u32, _>("fade_millis")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("SetLayerEnabled", (), move |m| {
+                                    let (layer, enabled): (u32, bool) = m.msg.read2()?;
+
+                                    check_layer(layer)?;
+
+                                    script::set_layer_enabled(layer as usize, enabled);
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<u32, _>("layer")
+                                .inarg::<bool, _>("enabled")
+                                .outarg::<bool, _>("status"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/profile", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Profile", ())
+                            .add_s(profiles_changed_signal_clone)
+                            .add_s(active_profile_changed_signal_clone)
+                            .add_p(active_profile_property_clone.clone())
+                            .add_m(
+                                f.method("SwitchProfile", (), move |m| {
+                                    let n: &str = m.msg.read1()?;
+
+                                    dbus_tx
+                                        .send(Message::SwitchProfile(PathBuf::from(n)))
+                                        .unwrap_or_else(|e| {
+                                            error!("Could not send a pending D-Bus event: {}", e)
+                                        });
+
+                                    //c_clone2
+                                    //.send(active_profile_changed_signal_clone.emit(
+                                    //&"/org/eruption/profile".into(),
+                                    //&"org.eruption.Profile".into(),
+                                    //&[n],
+                                    //))
+                                    //.unwrap();
+
+                                    let mut changed_properties = Vec::new();
+                                    active_profile_property_clone.add_propertieschanged(
+                                        &mut changed_properties,
+                                        &"org.eruption".into(),
+                                        || Box::new(n.to_owned()),
+                                    );
+
+                                    if !changed_properties.is_empty() {
+                                        let msg = changed_properties[0]
+                                            .to_emit_message(&"/org/eruption/profile".into());
+                                        c_clone2.clone().send(msg).map_err(|_e| {
+                                            MethodErr::failed("Could not send a signal")
+                                        })?;
+                                    }
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("filename")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("SwitchProfilePreview", (), move |m| {
+                                    let (n, seconds): (&str, u32) = m.msg.read2()?;
+
+                                    dbus_tx_preview
+                                        .send(Message::PreviewProfile(PathBuf::from(n), seconds))
+                                        .map_err(|_e| {
+                                            MethodErr::failed(
+                                                "Could not send a pending D-Bus event",
+                                            )
+                                        })?;
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("filename")
+                                .inarg::<u32, _>("seconds")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("CommitPreview", (), move |m| {
+                                    dbus_tx_commit.send(Message::CommitPreview).map_err(|_e| {
+                                        MethodErr::failed("Could not send a pending D-Bus event")
+                                    })?;
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("CancelPreview", (), move |m| {
+                                    dbus_tx_cancel.send(Message::CancelPreview).map_err(|_e| {
+                                        MethodErr::failed("Could not send a pending D-Bus event")
+                                    })?;
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("EnumProfiles", (), move |m| {
+                                    let profile_files = paths::get_profile_files();
+
+                                    let mut s: Vec<(String, String)> =
+                                        profiles::load_profiles(&profile_files)
+                                            .map_err(|e| MethodErr::failed(&format!("{}", e)))?
+                                            .iter()
+                                            .map(|profile| {
+                                                (
+                                                    profile.name.clone(),
+                                                    profile
+                                                        .profile_file
+                                                        .file_name()
+                                                        .unwrap_or_default()
+                                                        .to_string_lossy()
+                                                        .to_string(),
+                                                )
+                                            })
+                                            .collect();
+
+                                    s.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(String, String)>, _>("profiles"),
+                            ),
+                    ),
+            );
+
+        tree.set_registered(&*c_clone, true)
+            .map_err(|e| DbusApiError::MethodCallError {
+                description: format!("{}", e),
+            })?;
+        c_clone.add_handler(tree);
+
+        Ok(DbusApi {
+            connection: Some(c_clone),
+            active_profile_changed: active_profile_changed_signal,
+            profiles_changed: profiles_changed_signal,
+            device_error: device_error_signal,
+            device_recovered: device_recovered_signal,
+        })
+    }
+
+    pub fn notify_active_profile_changed(&self) -> Result<()> {
+        let active_profile = crate::ACTIVE_PROFILE.lock();
+
+        let active_profile = active_profile
+            .as_ref()
+            .and_then(|profile| profile.profile_file.file_name())
+            .and_then(|file_name| file_name.to_str())
+            .ok_or(DbusApiError::MethodCallError {
+                description: "No active profile".into(),
+            })?;
+
+        self.connection
+            .as_ref()
+            .ok_or(DbusApiError::BusNotConnected {})?
+            .send(self.active_profile_changed.emit(
+                &"/org/eruption/profile".into(),
+                &"org.eruption.Profile".into(),
+                &[active_profile],
+            ))
+            .map_err(|_e| DbusApiError::MethodCallError {
+                description: "Could not send a signal".into(),
+            })?;
+
+        Ok(())
+    }
+
+    pub fn notify_profiles_changed(&self) -> Result<()> {
+        self.connection
+            .as_ref()
+            .ok_or(DbusApiError::BusNotConnected {})?
+            .send(self.profiles_changed.msg(
+                &"/org/eruption/profile".into(),
+                &"org.eruption.Profile".into(),
+            ))
+            .map_err(|_e| DbusApiError::MethodCallError {
+                description: "Could not send a signal".into(),
+            })?;
+
+        Ok(())
+    }
+
+    /// Emit the DeviceError signal, the device with the index `device` has failed
+    pub fn notify_device_error(&self, device: usize, description: &str) -> Result<()> {
+        self.connection
+            .as_ref()
+            .ok_or(DbusApiError::BusNotConnected {})?
+            .send(
+                self.device_error
+                    .msg(
+                        &"/org/eruption/device".into(),
+                        &"org.eruption.Device".into(),
+                    )
+                    .append2(device as u64, description),
+            )
+            .map_err(|_e| DbusApiError::MethodCallError {
+                description: "Could not send a signal".into(),
+            })?;
+
+        Ok(())
+    }
+
+    /// Emit the DeviceRecovered signal, the device with the index `device` works again
+    pub fn notify_device_recovered(&self, device: usize) -> Result<()> {
+        self.connection
+            .as_ref()
+            .ok_or(DbusApiError::BusNotConnected {})?
+            .send(
+                self.device_recovered
+                    .msg(
+                        &"/org/eruption/device".into(),
+                        &"org.eruption.Device".into(),
+                    )
+                    .append1(device as u64),
+            )
+            .map_err(|_e| DbusApiError::MethodCallError {
+                description: "Could not send a signal".into(),
+            })?;
+
+        Ok(())
+    }
+
+    /// Get the next event from D-Bus
+    pub fn get_next_event(&self) -> Result<()> {
+        match self.connection {
+            Some(ref connection) => {
+                if let Some(item) = connection.incoming(constants::DBUS_TIMEOUT_MILLIS).next() {
+                    debug!("Message: {:?}", item);
+                } else {
+                    trace!("Received a timeout message");
+                }
+
+                Ok(())
+            }
+
+            None => Err(DbusApiError::BusNotConnected {}),
+        }
+    }
+}
+
+/// Get the script files of the active profile, in the order of their layers
+#[cfg(feature = "dbus")]
+fn get_layer_scripts() -> Vec<String> {
+    crate::ACTIVE_PROFILE
+        .lock()
+        .as_ref()
+        .map(|profile| {
+            profile
+                .active_scripts
+                .iter()
+                .map(|script| script.display().to_string())
+                .collect()
+        })
+        .unwrap_or_else(Vec::new)
+}
+
+/// Fail with an invalid argument, unless `layer` is a layer of the active profile
+#[cfg(feature = "dbus")]
+fn check_layer(layer: u32) -> std::result::Result<(), MethodErr> {
+    if (layer as usize) < get_layer_scripts().len() {
+        Ok(())
+    } else {
+        Err(MethodErr::invalid_arg(&layer))
+    }
+}
+
+/// Fail with an access denied error, unless the sender of the message `msg` is the
+/// user of the active session on our seat, or root. The user id of the sender is
+/// queried from the bus, it can not be spoofed by the sender
+#[cfg(feature = "dbus")]
+fn check_seat_user(
+    connection: &Connection,
+    msg: &DbusMessage,
+) -> std::result::Result<(), MethodErr> {
+    let uid = msg.sender().and_then(|sender| {
+        let call = DbusMessage::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "GetConnectionUnixUser",
+        )
+        .ok()?
+        .append1(&*sender);
+
+        connection
+            .send_with_reply_and_block(call, constants::DBUS_TIMEOUT_MILLIS as i32)
+            .ok()?
+            .read1::<u32>()
+            .ok()
+    });
+
+    let allowed = match uid {
+        Some(0) => true,
+        Some(uid) => paths::get_seat_user().map_or(false, |user| user.uid == uid),
+        None => false,
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err((
+            "org.freedesktop.DBus.Error.AccessDenied",
+            "Only the user of the active session on the seat may do this",
+        )
+            .into())
+    }
+}
+
+/// Initialize the Eruption D-Bus API support
+#[cfg(feature = "dbus")]
+pub fn initialize(dbus_tx: Sender<Message>, device_info: Option<DeviceInfo>) -> Result<DbusApi> {
+    DbusApi::new(dbus_tx, device_info)
+}
+
+/// Call the method `method` of the interface `interface` on the object `path`
+/// of a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn call_method<F>(path: &str, interface: &str, method: &str, append_args: F) -> Result<DbusMessage>
+where
+    F: FnOnce(DbusMessage) -> DbusMessage,
+{
+    let c =
+        Connection::get_private(BusType::System).map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })?;
+
+    let msg = DbusMessage::new_method_call("org.eruption", path, interface, method)
+        .map_err(|e| DbusApiError::MethodCallError { description: e })?;
+
+    c.send_with_reply_and_block(append_args(msg), constants::DBUS_TIMEOUT_MILLIS as i32 * 4)
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the active log filter of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_log_filter() -> Result<String> {
+    let reply = call_method(
+        "/org/eruption/logging",
+        "org.eruption.Logging",
+        "GetLogFilter",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<String>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Change the log filter of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn change_log_filter(filter: &str) -> Result<()> {
+    call_method(
+        "/org/eruption/logging",
+        "org.eruption.Logging",
+        "SetLogFilter",
+        |msg| msg.append1(filter),
+    )?;
+
+    Ok(())
+}
+
+/// Query the most recent log lines of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_log_lines(count: u32) -> Result<Vec<String>> {
+    let reply = call_method(
+        "/org/eruption/logging",
+        "org.eruption.Logging",
+        "GetLogLines",
+        |msg| msg.append1(count),
+    )?;
+
+    reply
+        .read1::<Vec<String>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Set a breakpoint in a script of a running instance of Eruption, returns false
+/// if the breakpoint has already been set
+#[cfg(feature = "dbus")]
+pub fn set_breakpoint(script: &str, line: u32) -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/debugger",
+        "org.eruption.Debugger",
+        "SetBreakpoint",
+        |msg| msg.append2(script, line),
+    )?;
+
+    reply
+        .read1::<bool>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Clear a breakpoint, or all breakpoints of a script if `line` is 0, of a running
+/// instance of Eruption. Returns false if no breakpoint has been found
+#[cfg(feature = "dbus")]
+pub fn clear_breakpoint(script: &str, line: u32) -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/debugger",
+        "org.eruption.Debugger",
+        "ClearBreakpoint",
+        |msg| msg.append2(script, line),
+    )?;
+
+    reply
+        .read1::<bool>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the breakpoints of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_breakpoints() -> Result<Vec<(String, u32)>> {
+    let reply = call_method(
+        "/org/eruption/debugger",
+        "org.eruption.Debugger",
+        "GetBreakpoints",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<(String, u32)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the paused script of a running instance of Eruption, as a tuple of
+/// whether a script is paused, the name of the script, the line, its global
+/// variables and the local variables of the paused function
+#[cfg(feature = "dbus")]
+#[allow(clippy::type_complexity)]
+pub fn query_paused() -> Result<(
+    bool,
+    String,
+    u32,
+    Vec<(String, String)>,
+    Vec<(String, String)>,
+)> {
+    let reply = call_method(
+        "/org/eruption/debugger",
+        "org.eruption.Debugger",
+        "GetPaused",
+        |msg| msg,
+    )?;
+
+    reply
+        .read5::<bool, String, u32, Vec<(String, String)>, Vec<(String, String)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Resume the paused script of a running instance of Eruption, `command` is either
+/// "continue" or "step". Returns false if no script is paused
+#[cfg(feature = "dbus")]
+pub fn resume_script(command: &str) -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/debugger",
+        "org.eruption.Debugger",
+        "Resume",
+        |msg| msg.append1(command),
+    )?;
+
+    reply
+        .read1::<bool>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the errors of a running instance of Eruption, that have not yet been
+/// acknowledged
+#[cfg(feature = "dbus")]
+pub fn query_errors() -> Result<Vec<String>> {
+    let reply = call_method(
+        "/org/eruption/errors",
+        "org.eruption.Errors",
+        "GetErrors",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<String>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Acknowledge the errors of a running instance of Eruption, this clears the
+/// error indicator. Returns the number of acknowledged errors
+#[cfg(feature = "dbus")]
+pub fn acknowledge_errors() -> Result<u32> {
+    let reply = call_method(
+        "/org/eruption/errors",
+        "org.eruption.Errors",
+        "AcknowledgeErrors",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<u32>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Start the pomodoro timer of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn start_pomodoro(work_mins: u32, break_mins: u32) -> Result<()> {
+    call_method(
+        "/org/eruption/timer",
+        "org.eruption.Timer",
+        "StartPomodoro",
+        |msg| msg.append2(work_mins, break_mins),
+    )?;
+
+    Ok(())
+}
+
+/// Stop the pomodoro timer of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn stop_pomodoro() -> Result<()> {
+    call_method(
+        "/org/eruption/timer",
+        "org.eruption.Timer",
+        "StopPomodoro",
+        |msg| msg,
+    )?;
+
+    Ok(())
+}
+
+/// Schedule an alert with a running instance of Eruption, returns the id of the alert
+#[cfg(feature = "dbus")]
+pub fn schedule_alert(
+    at_time: &str,
+    effect: &str,
+    params: &HashMap<String, String>,
+) -> Result<u32> {
+    let reply = call_method(
+        "/org/eruption/timer",
+        "org.eruption.Timer",
+        "ScheduleAlert",
+        |msg| msg.append3(at_time, effect, params),
+    )?;
+
+    reply
+        .read1::<u32>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Cancel a scheduled alert of a running instance of Eruption, returns true if
+/// the alert has been found
+#[cfg(feature = "dbus")]
+pub fn cancel_alert(id: u32) -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/timer",
+        "org.eruption.Timer",
+        "CancelAlert",
+        |msg| msg.append1(id),
+    )?;
+
+    reply
+        .read1::<bool>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the scheduled alerts of a running instance of Eruption, as tuples of
+/// id, point in time (seconds since the epoch), effect and duration in seconds
+#[cfg(feature = "dbus")]
+pub fn query_alerts() -> Result<Vec<(u32, i64, String, u64)>> {
+    let reply = call_method(
+        "/org/eruption/timer",
+        "org.eruption.Timer",
+        "GetAlerts",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<(u32, i64, String, u64)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Enter the identify mode of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn start_identify() -> Result<()> {
+    call_method(
+        "/org/eruption/device",
+        "org.eruption.Identify",
+        "StartIdentify",
+        |msg| msg,
+    )?;
+
+    Ok(())
+}
+
+/// Query the most recently pressed key in identify mode, as a tuple of the state
+/// of the identify mode, sequence number, LED index, key code and name of the key.
+/// The sequence number is zero while no key has been pressed
+#[cfg(feature = "dbus")]
+pub fn query_identified_key() -> Result<(bool, u32, u32, u32, String)> {
+    let reply = call_method(
+        "/org/eruption/device",
+        "org.eruption.Identify",
+        "GetIdentifiedKey",
+        |msg| msg,
+    )?;
+
+    reply
+        .read5::<bool, u32, u32, u32, String>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Report the window class of the focused application to a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_active_app(class: &str) -> Result<()> {
+    call_method(
+        "/org/eruption/windows",
+        "org.eruption.Windows",
+        "SetActiveApp",
+        |msg| msg.append1(class),
+    )?;
+
+    Ok(())
+}
+
+/// Report whether the focused application is in fullscreen mode to a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_fullscreen(fullscreen: bool) -> Result<()> {
+    call_method(
+        "/org/eruption/windows",
+        "org.eruption.Windows",
+        "SetFullscreen",
+        |msg| msg.append1(fullscreen),
+    )?;
+
+    Ok(())
+}
+
+/// Report the accent color of the desktop environment to a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_accent_color(color: &str) -> Result<()> {
+    call_method(
+        "/org/eruption/desktop",
+        "org.eruption.Desktop",
+        "SetAccentColor",
+        |msg| msg.append1(color),
+    )?;
+
+    Ok(())
+}
+
+/// Query the accent color of the desktop environment of a running instance of Eruption,
+/// 0 if unknown
+#[cfg(feature = "dbus")]
+pub fn query_accent_color() -> Result<u32> {
+    let reply = call_method(
+        "/org/eruption/desktop",
+        "org.eruption.Desktop",
+        "GetAccentColor",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<u32>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Report the color temperature of the screens to a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_color_temperature(kelvin: u32) -> Result<()> {
+    call_method(
+        "/org/eruption/desktop",
+        "org.eruption.Desktop",
+        "SetColorTemperature",
+        |msg| msg.append1(kelvin),
+    )?;
+
+    Ok(())
+}
+
+/// Query the color temperature of the screens of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_color_temperature() -> Result<u32> {
+    let reply = call_method(
+        "/org/eruption/desktop",
+        "org.eruption.Desktop",
+        "GetColorTemperature",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<u32>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the plugins of a running instance of Eruption: their names, descriptions,
+/// and whether they are enabled
+#[cfg(feature = "dbus")]
+pub fn query_plugins() -> Result<Vec<(String, String, bool)>> {
+    let reply = call_method(
+        "/org/eruption/plugins",
+        "org.eruption.Plugins",
+        "GetPlugins",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<(String, String, bool)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Enable or disable a plugin of a running instance of Eruption, returns true
+/// if the state changed
+#[cfg(feature = "dbus")]
+pub fn set_plugin_enabled(name: &str, enabled: bool) -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/plugins",
+        "org.eruption.Plugins",
+        "SetPluginEnabled",
+        |msg| msg.append2(name, enabled),
+    )?;
+
+    reply
+        .read1::<bool>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Report the power state of the displays to a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_display_blanked(blanked: bool) -> Result<()> {
+    call_method(
+        "/org/eruption/desktop",
+        "org.eruption.Desktop",
+        "SetDisplayBlanked",
+        |msg| msg.append1(blanked),
+    )?;
+
+    Ok(())
+}
+
+/// Query whether the displays are blanked, as known to a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_display_blanked() -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/desktop",
+        "org.eruption.Desktop",
+        "IsDisplayBlanked",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<bool>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the window class of the focused application of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_active_app() -> Result<String> {
+    let reply = call_method(
+        "/org/eruption/windows",
+        "org.eruption.Windows",
+        "GetActiveApp",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<String>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the latency percentiles of the input path of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_latency_metrics() -> Result<HashMap<String, u64>> {
+    let reply = call_method(
+        "/org/eruption/status",
+        "org.eruption.Status",
+        "GetLatencyMetrics",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<HashMap<String, u64>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Query the do-not-disturb state of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn query_dnd() -> Result<bool> {
+    let reply = call_method(
+        "/org/eruption/config",
+        "org.freedesktop.DBus.Properties",
+        "Get",
+        |msg| msg.append2("org.eruption.Config", "DoNotDisturb"),
+    )?;
+
+    reply
+        .read1::<Variant<bool>>()
+        .map(|v| v.0)
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Enter or leave do-not-disturb mode of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn change_dnd(enable: bool) -> Result<()> {
+    call_method(
+        "/org/eruption/config",
+        "org.freedesktop.DBus.Properties",
+        "Set",
+        |msg| msg.append3("org.eruption.Config", "DoNotDisturb", Variant(enable)),
+    )?;
+
+    Ok(())
+}
+
+/// Query the active scripts of a running instance of Eruption, with their capabilities
+#[cfg(feature = "dbus")]
+pub fn query_active_scripts() -> Result<Vec<(String, String)>> {
+    let reply = call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "GetActiveScripts",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<(String, String)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Override a parameter of a script of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_param(script: &str, name: &str, value: &str, persist: bool) -> Result<()> {
+    call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "SetParam",
+        |msg| msg.append2(script, name).append2(value, persist),
+    )?;
+
+    Ok(())
+}
+
+/// Query the overridden parameters of the scripts of a running instance of Eruption,
+/// as tuples of the name of the script, the name of the parameter and its value
+#[cfg(feature = "dbus")]
+pub fn query_params() -> Result<Vec<(String, String, String)>> {
+    let reply = call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "GetParams",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<(String, String, String)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Remove the overrides of the parameters of the script `script`, or of all scripts
+/// if `script` is empty, of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn clear_params(script: &str) -> Result<()> {
+    call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "ClearParams",
+        |msg| msg.append1(script),
+    )?;
+
+    Ok(())
+}
+
+/// Query the script layers of a running instance of Eruption, as tuples of the
+/// index of the layer, the script file, the opacity in percent and the enabled state
+#[cfg(feature = "dbus")]
+pub fn query_layers() -> Result<Vec<(u32, String, u32, bool)>> {
+    let reply = call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "GetLayers",
+        |msg| msg,
+    )?;
+
+    reply
+        .read1::<Vec<(u32, String, u32, bool)>>()
+        .map_err(|e| DbusApiError::MethodCallError {
+            description: format!("{}", e),
+        })
+}
+
+/// Set the opacity of a script layer of a running instance of Eruption, fading
+/// over `fade_millis` milliseconds
+#[cfg(feature = "dbus")]
+pub fn set_layer_opacity(layer: u32, percent: u32, fade_millis: u32) -> Result<()> {
+    call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "SetLayerOpacity",
+        |msg| msg.append3(layer, percent, fade_millis),
+    )?;
+
+    Ok(())
+}
+
+/// Enable or bypass a script layer of a running instance of Eruption
+#[cfg(feature = "dbus")]
+pub fn set_layer_enabled(layer: u32, enabled: bool) -> Result<()> {
+    call_method(
+        "/org/eruption/config",
+        "org.eruption.Config",
+        "SetLayerEnabled",
+        |msg| msg.append2(layer, enabled),
+    )?;
+
+    Ok(())
 }
 
 /// An empty dummy struct
@@ -0,0 +1,155 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Debouncing of worn switches. A switch that chatters reports a release that is
+//! followed by another press within a few milliseconds. Such a press, and the
+//! release that belongs to it, are dropped before they reach the hotkeys, the
+//! Lua VMs and the virtual keyboard.
+
+use evdev_rs::enums::EventCode;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::hotkeys;
+
+pub type Result<T> = std::result::Result<T, DebounceError>;
+
+#[derive(Debug, Error)]
+pub enum DebounceError {
+    #[error("Invalid debounce configuration for key '{key}'")]
+    InvalidConfig { key: String },
+}
+
+/// Debounce windows and the state of the keys
+struct DebounceState {
+    /// Default debounce window
+    window: Duration,
+
+    /// Debounce windows of individual keys, indexed by key code
+    overrides: HashMap<u32, Duration>,
+
+    /// Points in time of the last release of each key
+    last_release: HashMap<u32, Instant>,
+
+    /// Keys whose press has been dropped, their release is dropped as well
+    suppressed: HashSet<u32>,
+}
+
+lazy_static! {
+    /// Global "debounce filter enabled" flag
+    static ref ENABLE_DEBOUNCE: AtomicBool = AtomicBool::new(false);
+
+    static ref STATE: Mutex<DebounceState> = Mutex::new(DebounceState {
+        window: Duration::from_millis(constants::DEFAULT_DEBOUNCE_MILLIS),
+        overrides: HashMap::new(),
+        last_release: HashMap::new(),
+        suppressed: HashSet::new(),
+    });
+}
+
+/// Load the configuration of the `[debounce]` section
+pub fn initialize() -> Result<()> {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    let enabled = config.get::<bool>("debounce.enabled").unwrap_or(false);
+    ENABLE_DEBOUNCE.store(enabled, Ordering::SeqCst);
+
+    if !enabled {
+        return Ok(());
+    }
+
+    let mut state = STATE.lock();
+
+    state.window = Duration::from_millis(
+        config
+            .get_int("debounce.window_millis")
+            .unwrap_or(constants::DEFAULT_DEBOUNCE_MILLIS as i64)
+            .max(0) as u64,
+    );
+
+    state.overrides.clear();
+
+    if let Ok(table) = config.get_table("debounce.keys") {
+        for (name, value) in table {
+            let invalid_config = || DebounceError::InvalidConfig { key: name.clone() };
+
+            let code = hotkeys::parse_key_name(&name).map_err(|_e| invalid_config())?;
+            let millis = value.into_int().map_err(|_e| invalid_config())?;
+
+            state
+                .overrides
+                .insert(code, Duration::from_millis(millis.max(0) as u64));
+        }
+    }
+
+    info!(
+        "Debouncing keys: {} ms, {} override(s)",
+        state.window.as_millis(),
+        state.overrides.len()
+    );
+
+    Ok(())
+}
+
+/// Returns true if the event `event` is caused by a chattering switch, and has
+/// to be dropped. This has to be called for each event of the hardware keyboard
+pub fn is_chatter(event: &evdev_rs::InputEvent) -> bool {
+    if !ENABLE_DEBOUNCE.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    let code = match event.event_code {
+        EventCode::EV_KEY(ref key) => key.clone() as u32,
+        _ => return false,
+    };
+
+    let mut state = STATE.lock();
+    let window = *state.overrides.get(&code).unwrap_or(&state.window);
+
+    match event.value {
+        // key up
+        0 => {
+            state.last_release.insert(code, Instant::now());
+            state.suppressed.remove(&code)
+        }
+
+        // key down
+        1 => {
+            let is_chatter = state
+                .last_release
+                .get(&code)
+                .map_or(false, |t| t.elapsed() < window);
+
+            if is_chatter {
+                debug!("Dropped the chatter of key {}", code);
+                state.suppressed.insert(code);
+            }
+
+            is_chatter
+        }
+
+        // auto repeat
+        _ => state.suppressed.contains(&code),
+    }
+}
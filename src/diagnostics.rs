@@ -0,0 +1,140 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::rvdevice::{RvDeviceState, NUM_KEYS, RGBA};
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, DiagnosticsError>;
+
+#[derive(Debug, Error)]
+pub enum DiagnosticsError {
+    #[error("Could not open HIDAPI")]
+    HidApiError {},
+
+    #[error("No device with index {index}")]
+    NoSuchDevice { index: usize },
+
+    #[error("Device error: {description}")]
+    DeviceError { description: String },
+}
+
+/// Returns the names of the keys that are mapped to the LED `index`
+fn get_key_names(index: usize) -> Vec<String> {
+    util::key_index_to_ev_keys(index)
+        .iter()
+        .map(|key| format!("{:?}", key))
+        .collect()
+}
+
+/// Print the topology table: the keys that are mapped to each LED index,
+/// followed by the indices that have no key mapped to them
+pub fn print_topology() {
+    println!("Index  Keys");
+
+    let mut unmapped = vec![];
+
+    for index in 0..NUM_KEYS {
+        let names = get_key_names(index);
+
+        if names.is_empty() {
+            unmapped.push(index.to_string());
+        }
+
+        println!("{:>5}  {}", index, names.join(", "));
+    }
+
+    println!();
+    println!(
+        "{} of {} indices have no key mapped to them: {}",
+        unmapped.len(),
+        NUM_KEYS,
+        unmapped.join(", ")
+    );
+}
+
+/// Light each LED of the device `device` individually for `delay`, printing the
+/// index and the keys that are mapped to it. The daemon must not be running
+pub fn run_led_test(device: usize, delay: Duration) -> Result<()> {
+    let hidapi = hidapi::HidApi::new().map_err(|_e| DiagnosticsError::HidApiError {})?;
+
+    let mut rvdevice = RvDeviceState::enumerate_devices(&hidapi)
+        .map_err(|e| DiagnosticsError::DeviceError {
+            description: format!("{}", e),
+        })?
+        .into_iter()
+        .nth(device)
+        .ok_or(DiagnosticsError::NoSuchDevice { index: device })?;
+
+    let map_err = |e: crate::rvdevice::RvDeviceError| DiagnosticsError::DeviceError {
+        description: format!("{}", e),
+    };
+
+    rvdevice.open(&hidapi).map_err(map_err)?;
+    rvdevice.send_init_sequence().map_err(map_err)?;
+
+    let black = RGBA {
+        r: 0x00,
+        g: 0x00,
+        b: 0x00,
+        a: 0xff,
+    };
+
+    let white = RGBA {
+        r: 0xff,
+        g: 0xff,
+        b: 0xff,
+        a: 0xff,
+    };
+
+    println!(
+        "Lighting each LED for {} ms, watch the keyboard",
+        delay.as_millis()
+    );
+    println!();
+
+    for index in 0..NUM_KEYS {
+        let led_map: Vec<RGBA> = (0..NUM_KEYS)
+            .map(|i| if i == index { white } else { black })
+            .collect();
+
+        let names = get_key_names(index);
+        if names.is_empty() {
+            println!("{:>5}  (no key mapped)", index);
+        } else {
+            println!("{:>5}  {}", index, names.join(", "));
+        }
+
+        rvdevice.send_led_map(&led_map).map_err(map_err)?;
+        thread::sleep(delay);
+    }
+
+    rvdevice.send_led_map(&[black; NUM_KEYS]).map_err(map_err)?;
+
+    rvdevice
+        .close_all()
+        .unwrap_or_else(|e| warn!("Could not close the keyboard device: {}", e));
+
+    println!();
+    print_topology();
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! The volume dial of the keyboard. The firmware reports the dial as the volume
+//! keys, and a press of the dial as the mute key. The events of the dial are
+//! reported to the Lua scripts via `on_dial_rotate(delta)` and `on_dial_press()`,
+//! and the dial may be bound to another function than the volume of the desktop:
+//! The brightness, switching profiles, or horizontal scrolling.
+
+use evdev_rs::enums::{EventCode, EV_KEY};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::constants;
+use crate::hotkeys::{self, Disposition};
+use crate::pointer_keys;
+use crate::scripting::script;
+
+pub type Result<T> = std::result::Result<T, DialError>;
+
+#[derive(Debug, Error)]
+pub enum DialError {
+    #[error("Invalid dial mode: {mode}")]
+    InvalidMode { mode: String },
+
+    #[error("Could not create the virtual mouse: {description}")]
+    DeviceError { description: String },
+}
+
+/// The function the dial is bound to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Control the volume of the desktop, the events are mirrored
+    Volume,
+
+    /// Adjust the brightness of the LEDs
+    Brightness,
+
+    /// Switch to the next or the previous profile
+    Profiles,
+
+    /// Turn the horizontal wheel of the virtual mouse
+    Scroll,
+}
+
+impl FromStr for Mode {
+    type Err = DialError;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode.trim() {
+            "volume" => Ok(Mode::Volume),
+            "brightness" => Ok(Mode::Brightness),
+            "profiles" => Ok(Mode::Profiles),
+            "scroll" => Ok(Mode::Scroll),
+
+            _ => Err(DialError::InvalidMode {
+                mode: mode.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    mode: Mode,
+
+    /// Steps of the horizontal wheel per step of the dial
+    scroll_step: i32,
+}
+
+lazy_static! {
+    static ref SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+        mode: Mode::Volume,
+        scroll_step: constants::DIAL_SCROLL_STEP,
+    });
+}
+
+/// Load the configuration of the `[dial]` section
+pub fn initialize() -> Result<()> {
+    let (mode, scroll_step) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config
+                .get_str("dial.mode")
+                .unwrap_or_else(|_| "volume".into()),
+            config
+                .get::<i32>("dial.scroll_step")
+                .unwrap_or(constants::DIAL_SCROLL_STEP),
+        )
+    };
+
+    let mode = mode.parse::<Mode>()?;
+
+    if mode == Mode::Scroll {
+        pointer_keys::start_virtual_mouse().map_err(|e| DialError::DeviceError {
+            description: format!("{}", e),
+        })?;
+    }
+
+    if mode != Mode::Volume {
+        info!("The dial is bound to: {:?}", mode);
+    }
+
+    *SETTINGS.lock() = Settings { mode, scroll_step };
+
+    Ok(())
+}
+
+fn notify_scripts(message: impl Fn() -> script::Message) {
+    for lua_tx in crate::LUA_TXS.lock().iter() {
+        lua_tx
+            .send(message())
+            .unwrap_or_else(|e| error!("Send error: {}", e));
+    }
+}
+
+/// Process a key event of the hardware keyboard. Returns None if the event does
+/// not belong to the dial, or if the dial controls the volume, so that the event
+/// is processed as usual. Otherwise returns how to dispose of the event
+pub fn process_event(raw_event: &evdev_rs::InputEvent) -> Option<Disposition> {
+    let delta = match raw_event.event_code {
+        EventCode::EV_KEY(EV_KEY::KEY_VOLUMEUP) => 1,
+        EventCode::EV_KEY(EV_KEY::KEY_VOLUMEDOWN) => -1,
+        EventCode::EV_KEY(EV_KEY::KEY_MUTE) => 0,
+
+        _ => return None,
+    };
+
+    let settings = *SETTINGS.lock();
+
+    // each step of the dial is reported as a press and a release of a key
+    let is_pressed = raw_event.value == 1;
+
+    if is_pressed {
+        if delta == 0 {
+            notify_scripts(|| script::Message::DialPress);
+        } else {
+            notify_scripts(|| script::Message::DialRotate(delta));
+        }
+    }
+
+    match map_event(settings.mode, delta as i32, settings.scroll_step) {
+        DialAction::Pass => None,
+
+        DialAction::Trigger(action) if is_pressed => Some(Disposition::Trigger(action)),
+
+        DialAction::Scroll(steps) if is_pressed => {
+            pointer_keys::scroll_horizontal(steps);
+
+            Some(Disposition::Consume)
+        }
+
+        _ => Some(Disposition::Consume),
+    }
+}
+
+/// What a step (`delta` is 1 or -1) or a press (`delta` is 0) of the dial does
+#[derive(Debug, Clone, PartialEq)]
+enum DialAction {
+    /// The event is processed as usual
+    Pass,
+
+    /// The event is only reported to the scripts
+    Consume,
+
+    /// Execute the action
+    Trigger(hotkeys::Action),
+
+    /// Turn the horizontal wheel of the virtual mouse by this many steps
+    Scroll(i32),
+}
+
+/// Map a step or a press of the dial to its action in the mode `mode`
+fn map_event(mode: Mode, delta: i32, scroll_step: i32) -> DialAction {
+    match (mode, delta) {
+        (Mode::Volume, _) => DialAction::Pass,
+
+        // presses of the dial are only reported to the scripts
+        (_, 0) => DialAction::Consume,
+
+        (Mode::Brightness, 1) => DialAction::Trigger(hotkeys::Action::BrightnessUp),
+        (Mode::Brightness, _) => DialAction::Trigger(hotkeys::Action::BrightnessDown),
+
+        (Mode::Profiles, 1) => DialAction::Trigger(hotkeys::Action::NextProfile),
+        (Mode::Profiles, _) => DialAction::Trigger(hotkeys::Action::PreviousProfile),
+
+        (Mode::Scroll, delta) => DialAction::Scroll(delta * scroll_step),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_from_str() {
+        assert_eq!("volume".parse::<Mode>().unwrap(), Mode::Volume);
+        assert_eq!("brightness".parse::<Mode>().unwrap(), Mode::Brightness);
+        assert_eq!(" profiles ".parse::<Mode>().unwrap(), Mode::Profiles);
+        assert_eq!("scroll".parse::<Mode>().unwrap(), Mode::Scroll);
+
+        assert!("Volume".parse::<Mode>().is_err());
+        assert!("".parse::<Mode>().is_err());
+    }
+
+    #[test]
+    fn test_map_event() {
+        for delta in &[-1, 0, 1] {
+            assert_eq!(map_event(Mode::Volume, *delta, 1), DialAction::Pass);
+        }
+
+        for mode in &[Mode::Brightness, Mode::Profiles, Mode::Scroll] {
+            assert_eq!(map_event(*mode, 0, 1), DialAction::Consume);
+        }
+
+        assert_eq!(
+            map_event(Mode::Brightness, 1, 1),
+            DialAction::Trigger(hotkeys::Action::BrightnessUp)
+        );
+        assert_eq!(
+            map_event(Mode::Brightness, -1, 1),
+            DialAction::Trigger(hotkeys::Action::BrightnessDown)
+        );
+
+        assert_eq!(
+            map_event(Mode::Profiles, 1, 1),
+            DialAction::Trigger(hotkeys::Action::NextProfile)
+        );
+        assert_eq!(
+            map_event(Mode::Profiles, -1, 1),
+            DialAction::Trigger(hotkeys::Action::PreviousProfile)
+        );
+
+        assert_eq!(map_event(Mode::Scroll, 1, 3), DialAction::Scroll(3));
+        assert_eq!(map_event(Mode::Scroll, -1, 3), DialAction::Scroll(-3));
+    }
+}
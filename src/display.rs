@@ -0,0 +1,255 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Lighting policy while the displays are blanked. The power state of the
+//! displays (DPMS) is reported by a helper that runs in the session of the user
+//! (see `support/bin/eruption-display-watcher`), changes of the state of the lid
+//! of a laptop are signalled by logind. Depending on the `[display]` section, the keyboard is
+//! switched off, or a profile (e.g. a dim clock) is activated, until the displays
+//! wake up again.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, DisplayError>;
+
+#[derive(Debug, Error)]
+pub enum DisplayError {
+    #[error("Invalid action: {action}")]
+    InvalidAction { action: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// What to do while the displays are blanked
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    /// Keep the effects running
+    None,
+
+    /// Switch the keyboard off
+    Off,
+
+    /// Activate a profile, e.g. one with a dim clock
+    Profile(PathBuf),
+}
+
+/// A change that has to be performed by the main loop
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// The displays have been blanked, switch the keyboard off
+    Off,
+
+    /// The displays have been blanked, switch to the profile
+    Enter(PathBuf),
+
+    /// The displays woke up, restore the keyboard
+    On,
+
+    /// The displays woke up, restore the previous profile, if the profile of the
+    /// policy is still active
+    Leave,
+}
+
+lazy_static! {
+    static ref ACTION: Mutex<Action> = Mutex::new(Action::None);
+
+    /// Consider a closed lid like blanked displays
+    static ref HONOR_LID: AtomicBool = AtomicBool::new(true);
+
+    /// Set while the displays are blanked, as reported by the session
+    static ref DISPLAYS_BLANKED: AtomicBool = AtomicBool::new(false);
+
+    /// Set while the lid of the laptop is closed
+    static ref LID_CLOSED: AtomicBool = AtomicBool::new(false);
+
+    /// Set while the policy is in effect
+    static ref ACTIVE: AtomicBool = AtomicBool::new(false);
+
+    /// Set while the keyboard is switched off by the policy
+    static ref KEYBOARD_OFF: AtomicBool = AtomicBool::new(false);
+}
+
+/// Returns true while the displays are blanked, as reported by the session
+pub fn is_blanked() -> bool {
+    DISPLAYS_BLANKED.load(Ordering::SeqCst)
+}
+
+/// Record the power state of the displays, as reported by the session
+pub fn set_blanked(blanked: bool) {
+    if DISPLAYS_BLANKED.swap(blanked, Ordering::SeqCst) != blanked {
+        debug!(
+            "The displays have been {}",
+            if blanked { "blanked" } else { "woken up" }
+        );
+    }
+}
+
+/// Returns true while the keyboard is switched off, rendering is paused
+pub fn is_keyboard_off() -> bool {
+    KEYBOARD_OFF.load(Ordering::SeqCst)
+}
+
+/// Load the policy of the `[display]` section, and watch the lid via logind
+pub fn initialize() -> Result<()> {
+    let (action, profile, lid) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config
+                .get_str("display.action")
+                .unwrap_or_else(|_| "none".into()),
+            config.get_str("display.profile").ok(),
+            config.get_bool("display.lid").unwrap_or(true),
+        )
+    };
+
+    let action = match action.as_str() {
+        "none" => Action::None,
+        "off" => Action::Off,
+
+        "profile" => match profile.filter(|p| !p.is_empty()) {
+            Some(profile) => Action::Profile(PathBuf::from(profile)),
+            None => return Err(DisplayError::InvalidAction { action }),
+        },
+
+        _ => return Err(DisplayError::InvalidAction { action }),
+    };
+
+    #[cfg(feature = "dbus")]
+    {
+        if action != Action::None && lid {
+            std::thread::Builder::new()
+                .name("lid".into())
+                .spawn(|| {
+                    logind::run().unwrap_or_else(|e| error!("Could not watch the lid: {}", e));
+                })
+                .map_err(|_e| DisplayError::ThreadSpawnError {})?;
+        }
+    }
+
+    HONOR_LID.store(lid, Ordering::SeqCst);
+    *ACTION.lock() = action;
+
+    Ok(())
+}
+
+/// Put the policy into effect or revert it, if the displays have been blanked or
+/// woke up. Returns the change that has to be performed by the main loop, if any
+pub fn update() -> Option<Change> {
+    let blanked =
+        is_blanked() || (HONOR_LID.load(Ordering::SeqCst) && LID_CLOSED.load(Ordering::SeqCst));
+
+    if ACTIVE.load(Ordering::SeqCst) == blanked {
+        return None;
+    }
+
+    let action = ACTION.lock().clone();
+
+    if action == Action::None {
+        return None;
+    }
+
+    ACTIVE.store(blanked, Ordering::SeqCst);
+
+    if blanked {
+        info!("The displays have been blanked, applying the lighting policy");
+    } else {
+        info!("The displays woke up, restoring the effects");
+    }
+
+    match action {
+        Action::Off => {
+            KEYBOARD_OFF.store(blanked, Ordering::SeqCst);
+
+            Some(if blanked { Change::Off } else { Change::On })
+        }
+
+        Action::Profile(profile) => Some(if blanked {
+            Change::Enter(profile)
+        } else {
+            Change::Leave
+        }),
+
+        Action::None => None,
+    }
+}
+
+#[cfg(feature = "dbus")]
+mod logind {
+    use dbus::{arg::Variant, ffidisp::BusType, ffidisp::Connection, Message as DbusMessage};
+    use std::sync::atomic::Ordering;
+
+    use super::LID_CLOSED;
+    use crate::constants;
+
+    /// Query the `LidClosed` property of logind
+    fn is_lid_closed(connection: &Connection) -> Option<bool> {
+        let msg = DbusMessage::new_method_call(
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.DBus.Properties",
+            "Get",
+        )
+        .ok()?
+        .append2("org.freedesktop.login1.Manager", "LidClosed");
+
+        let reply = connection
+            .send_with_reply_and_block(msg, constants::DBUS_TIMEOUT_MILLIS as i32)
+            .ok()?;
+
+        reply.read1::<Variant<bool>>().ok().map(|v| v.0)
+    }
+
+    fn update_lid_state(connection: &Connection) {
+        if let Some(closed) = is_lid_closed(connection) {
+            LID_CLOSED.store(closed, Ordering::SeqCst);
+        }
+    }
+
+    pub fn run() -> Result<(), dbus::Error> {
+        let connection = Connection::get_private(BusType::System)?;
+
+        connection.add_match(
+            "type='signal',sender='org.freedesktop.login1',path='/org/freedesktop/login1',\
+             interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'",
+        )?;
+
+        // the initial state, changes are signalled afterwards
+        update_lid_state(&connection);
+
+        loop {
+            for msg in connection.incoming(constants::DBUS_TIMEOUT_MILLIS) {
+                if msg.member().as_deref() != Some("PropertiesChanged") {
+                    continue;
+                }
+
+                // the property may be invalidated instead of being sent along, so
+                // it is queried on every change of the properties of the manager
+                if msg.get1::<String>().as_deref() == Some("org.freedesktop.login1.Manager") {
+                    update_lid_state(&connection);
+                }
+            }
+        }
+    }
+}
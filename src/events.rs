@@ -15,12 +15,11 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use failure::Error;
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use std::sync::Arc;
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, anyhow::Error>;
 
 #[derive(Debug)]
 pub enum Event {
@@ -33,6 +32,15 @@ pub enum Event {
 
     KeyDown(u8),
     KeyUp(u8),
+
+    /// A device has failed, contains the index of the device
+    DeviceError(usize),
+
+    /// A device stopped responding, contains the index of the device
+    DeviceUnresponsive(usize),
+
+    /// A device works again after it has failed, contains the index of the device
+    DeviceRecovered(usize),
 }
 
 pub type Callback = dyn Fn(&Event) -> Result<bool> + Sync + Send + 'static;
@@ -31,7 +31,6 @@ use rocket_contrib::{
 };
 
 use crate::profiles::GetAttr;
-use failure::Fail;
 use lazy_static::*;
 use log::*;
 use parking_lot::Mutex;
@@ -41,6 +40,7 @@ use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use thiserror::Error;
 use uuid::Uuid;
 
 use crate::constants;
@@ -58,27 +58,27 @@ pub enum Message {
     SwitchProfile(PathBuf),
 }
 
-pub type Result<T> = std::result::Result<T, failure::Error>;
+pub type Result<T> = std::result::Result<T, anyhow::Error>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum WebFrontendError {
-    #[fail(display = "Web frontend inaccessible")]
+    #[error("Web frontend inaccessible")]
     FrontendInaccessible {},
 
-    //#[fail(display = "Could not enumerate script files")]
+    //#[error("Could not enumerate script files")]
     //ScriptEnumerationError {},
-    #[fail(display = "Could not enumerate profile files")]
+    #[error("Could not enumerate profile files")]
     ProfileEnumerationError {},
 
-    #[fail(display = "Could not load script files")]
+    #[error("Could not load script files")]
     ScriptLoadError {},
 
-    #[fail(display = "Could not switch to a different profile")]
+    #[error("Could not switch to a different profile")]
     ProfileSwitchError {},
 
-    #[fail(display = "Could not parse a parameter value")]
+    #[error("Could not parse a parameter value")]
     ParseParamError {},
-    // #[fail(display = "Unknown error: {}", description)]
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
@@ -642,7 +642,7 @@ where
     K: std::hash::Hash + Eq + std::convert::From<String>,
     V: std::convert::From<String>,
 {
-    type Error = failure::Error;
+    type Error = anyhow::Error;
 
     fn from_form(it: &mut FormItems<'f>, _strict: bool) -> Result<Self> {
         let mut params = Self::new();
@@ -0,0 +1,148 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Reduction of the overhead of the effects, while a fullscreen application like
+//! a game is focused. Depending on the policy of the `[fullscreen]` section, the
+//! tick rate is reduced, the scripts with expensive effects (e.g. the tag `Noise`)
+//! are paused, or a static profile is activated. Everything is restored as soon
+//! as the fullscreen application loses the focus.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::windows;
+
+/// A profile switch that is requested by the policy
+#[derive(Debug, Clone)]
+pub enum ProfileSwitch {
+    /// A fullscreen application has been focused, switch to the profile
+    Enter(PathBuf),
+
+    /// The fullscreen application lost the focus, restore the previous profile, if
+    /// the profile of the policy is still active
+    Leave,
+}
+
+/// The policy of the `[fullscreen]` section
+#[derive(Debug, Default, Clone)]
+struct Policy {
+    /// Ticks are sent on every `tick_divisor`-th iteration of the main loop at most
+    tick_divisor: u64,
+
+    /// Scripts with any of these tags receive no ticks, their frames are frozen
+    pause_tags: Vec<String>,
+
+    /// Profile to activate, e.g. one with static colors
+    profile: Option<PathBuf>,
+}
+
+lazy_static! {
+    static ref POLICY: Mutex<Policy> = Mutex::new(Policy {
+        tick_divisor: 1,
+        ..Policy::default()
+    });
+
+    /// Set while the policy is in effect
+    static ref ACTIVE: AtomicBool = AtomicBool::new(false);
+}
+
+/// Load the policy of the `[fullscreen]` section
+pub fn initialize() {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    let mut policy = POLICY.lock();
+
+    policy.tick_divisor = config
+        .get_int("fullscreen.tick_divisor")
+        .unwrap_or(1)
+        .max(1) as u64;
+
+    policy.pause_tags = config
+        .get::<Vec<String>>("fullscreen.pause_tags")
+        .unwrap_or_default()
+        .iter()
+        .map(|tag| tag.to_lowercase())
+        .collect();
+
+    policy.profile = config
+        .get_str("fullscreen.profile")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from);
+}
+
+/// Put the policy into effect or revert it, if the fullscreen state of the focused
+/// application changed. Returns the profile switch that has to be performed by the
+/// main loop, if any
+pub fn update() -> Option<ProfileSwitch> {
+    let fullscreen = windows::is_fullscreen();
+
+    if ACTIVE.swap(fullscreen, Ordering::SeqCst) == fullscreen {
+        return None;
+    }
+
+    let policy = POLICY.lock();
+
+    if fullscreen {
+        info!("A fullscreen application has been focused, reducing the overhead of the effects");
+
+        policy.profile.clone().map(ProfileSwitch::Enter)
+    } else {
+        info!("The fullscreen application lost the focus, restoring the effects");
+
+        policy.profile.as_ref().map(|_| ProfileSwitch::Leave)
+    }
+}
+
+/// The min. tick rate divisor, 1 unless the policy is in effect
+pub fn tick_divisor() -> u64 {
+    if ACTIVE.load(Ordering::SeqCst) {
+        POLICY.lock().tick_divisor
+    } else {
+        1
+    }
+}
+
+/// Returns true if the script layer `layer` shall not receive ticks, since the
+/// policy is in effect and its script has one of the paused tags
+pub fn is_layer_paused(layer: usize) -> bool {
+    if !ACTIVE.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    let policy = POLICY.lock();
+
+    if policy.pause_tags.is_empty() {
+        return false;
+    }
+
+    crate::ACTIVE_SCRIPTS
+        .lock()
+        .get(layer)
+        .and_then(|manifest| manifest.tags.as_ref())
+        .map_or(false, |tags| {
+            tags.iter().any(|tag| {
+                policy
+                    .pause_tags
+                    .contains(&format!("{:?}", tag).to_lowercase())
+            })
+        })
+}
@@ -0,0 +1,463 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use evdev_rs::enums::EventCode;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::constants;
+use crate::profiles::Profile;
+use crate::windows;
+
+pub type Result<T> = std::result::Result<T, HotkeyError>;
+
+#[derive(Debug, Error)]
+pub enum HotkeyError {
+    #[error("Invalid key name: {name}")]
+    InvalidKey { name: String },
+
+    #[error("Invalid hotkey action: {action}")]
+    InvalidAction { action: String },
+
+    #[error("Invalid hotkey configuration")]
+    InvalidConfig {},
+}
+
+lazy_static! {
+    /// The configured hotkeys
+    static ref HOTKEYS: Mutex<Vec<Hotkey>> = Mutex::new(vec![]);
+
+    /// The hotkeys of the active profile per application, indexed by window class
+    static ref APP_HOTKEYS: Mutex<HashMap<String, Vec<Hotkey>>> = Mutex::new(HashMap::new());
+
+    /// Key codes of all keys that are currently held down
+    static ref PRESSED_KEYS: Mutex<HashSet<u32>> = Mutex::new(HashSet::new());
+
+    /// Key codes of keys that triggered a hotkey, their release
+    /// events will be consumed as well
+    static ref CONSUMED_KEYS: Mutex<HashSet<u32>> = Mutex::new(HashSet::new());
+}
+
+/// Actions that may be bound to a hotkey
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    NextProfile,
+    PreviousProfile,
+    BrightnessUp,
+    BrightnessDown,
+    ToggleDnd,
+
+    /// Abort all running macros, and discard the queued keys
+    AbortMacros,
+
+    /// Replay a sequence of key combinations, e.g.: "macro:LEFTSHIFT+H I". The
+    /// pacing between the combinations may be overridden in milliseconds, e.g.:
+    /// "macro@20:LEFTSHIFT+H I"
+    Macro {
+        sequence: Vec<Vec<u32>>,
+        pacing_millis: Option<u64>,
+    },
+
+    /// Set the opacity of a script layer in percent, optionally fading over a
+    /// duration in milliseconds, e.g.: "layer-opacity:1:50:500"
+    LayerOpacity {
+        layer: usize,
+        percent: u32,
+        fade_millis: u64,
+    },
+
+    /// Enable or bypass a script layer, e.g.: "toggle-layer:1"
+    ToggleLayer(usize),
+
+    /// Fade a script layer out if it is visible, or in otherwise, over a
+    /// duration in milliseconds, e.g.: "fade-layer:1:500"
+    FadeLayer {
+        layer: usize,
+        fade_millis: u64,
+    },
+}
+
+/// Parse the colon separated numeric arguments of the action `action`, that
+/// starts with `prefix`. Between `min` and `max` arguments are accepted
+fn parse_args(action: &str, prefix: &str, min: usize, max: usize) -> Result<Vec<u64>> {
+    let err = || HotkeyError::InvalidAction {
+        action: action.to_string(),
+    };
+
+    let args = action[prefix.len()..]
+        .split(':')
+        .map(|arg| arg.trim().parse::<u64>().map_err(|_e| err()))
+        .collect::<Result<Vec<_>>>()?;
+
+    if args.len() < min || args.len() > max {
+        Err(err())
+    } else {
+        Ok(args)
+    }
+}
+
+impl FromStr for Action {
+    type Err = HotkeyError;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action.trim() {
+            "next-profile" => Ok(Action::NextProfile),
+            "previous-profile" => Ok(Action::PreviousProfile),
+            "brightness-up" => Ok(Action::BrightnessUp),
+            "brightness-down" => Ok(Action::BrightnessDown),
+            "toggle-dnd" => Ok(Action::ToggleDnd),
+            "abort-macros" => Ok(Action::AbortMacros),
+
+            action if action.starts_with("layer-opacity:") => {
+                let args = parse_args(action, "layer-opacity:", 2, 3)?;
+
+                if args[1] > 100 {
+                    return Err(HotkeyError::InvalidAction {
+                        action: action.to_string(),
+                    });
+                }
+
+                Ok(Action::LayerOpacity {
+                    layer: args[0] as usize,
+                    percent: args[1] as u32,
+                    fade_millis: args.get(2).copied().unwrap_or(0),
+                })
+            }
+
+            action if action.starts_with("toggle-layer:") => {
+                let args = parse_args(action, "toggle-layer:", 1, 1)?;
+
+                Ok(Action::ToggleLayer(args[0] as usize))
+            }
+
+            action if action.starts_with("fade-layer:") => {
+                let args = parse_args(action, "fade-layer:", 2, 2)?;
+
+                Ok(Action::FadeLayer {
+                    layer: args[0] as usize,
+                    fade_millis: args[1],
+                })
+            }
+
+            action if action.starts_with("macro:") || action.starts_with("macro@") => {
+                let err = || HotkeyError::InvalidAction {
+                    action: action.to_string(),
+                };
+
+                let (prefix, keys) = action.split_at(action.find(':').ok_or_else(err)?);
+
+                let pacing_millis = if prefix.starts_with("macro@") {
+                    Some(
+                        prefix["macro@".len()..]
+                            .trim()
+                            .parse::<u64>()
+                            .map_err(|_e| err())?,
+                    )
+                } else {
+                    None
+                };
+
+                let sequence = keys[1..]
+                    .split_whitespace()
+                    .map(parse_combination)
+                    .collect::<Result<Vec<_>>>()?;
+
+                if sequence.is_empty() {
+                    Err(err())
+                } else {
+                    Ok(Action::Macro {
+                        sequence,
+                        pacing_millis,
+                    })
+                }
+            }
+
+            _ => Err(HotkeyError::InvalidAction {
+                action: action.to_string(),
+            }),
+        }
+    }
+}
+
+/// A key combination, bound to an action
+#[derive(Debug, Clone)]
+pub struct Hotkey {
+    /// Keys that have to be held down, e.g. RIGHTCTRL
+    pub modifiers: Vec<u32>,
+
+    /// The key that triggers the action
+    pub key: u32,
+
+    pub action: Action,
+}
+
+impl Hotkey {
+    /// Parse a hotkey from a combination like "RIGHTCTRL+F5" and an action
+    pub fn new(combination: &str, action: &str) -> Result<Self> {
+        let mut keys = parse_combination(combination)?;
+        let key = keys.pop().ok_or(HotkeyError::InvalidConfig {})?;
+
+        Ok(Hotkey {
+            modifiers: keys,
+            key,
+            action: action.parse::<Action>()?,
+        })
+    }
+}
+
+/// What to do with a key event, after it has been processed by the
+/// hotkey subsystem
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disposition {
+    /// The event is not related to a hotkey, process it as usual
+    Pass,
+
+    /// The event belongs to a hotkey that already has been triggered
+    Consume,
+
+    /// The event triggered a hotkey, execute the action
+    Trigger(Action),
+}
+
+/// Find the key code of the key named `name`, e.g. "F5" or "KEY_F5".
+/// The Easy Shift key is named "EASYSHIFT"
+pub fn parse_key_name(name: &str) -> Result<u32> {
+    let name = name.trim().to_uppercase();
+    if name == "EASYSHIFT" {
+        return Ok(constants::EASY_SHIFT_KEY_CODE);
+    }
+
+    let name = if name.starts_with("KEY_") || name.starts_with("BTN_") {
+        name
+    } else {
+        format!("KEY_{}", name)
+    };
+
+    (0..=constants::MAX_EV_KEY_CODE)
+        .find(|code| {
+            evdev_rs::enums::int_to_ev_key(*code)
+                .map(|key| format!("{:?}", key) == name)
+                .unwrap_or(false)
+        })
+        .ok_or(HotkeyError::InvalidKey { name })
+}
+
+/// Parse a key combination like "RIGHTCTRL+F5" into a list of key codes
+fn parse_combination(combination: &str) -> Result<Vec<u32>> {
+    combination
+        .split('+')
+        .filter(|k| !k.trim().is_empty())
+        .map(parse_key_name)
+        .collect()
+}
+
+/// Load the hotkeys from the `[hotkeys]` section of the configuration file
+pub fn initialize() -> Result<()> {
+    let config = crate::CONFIG.lock();
+    let table = match config.as_ref().unwrap().get_table("hotkeys") {
+        Ok(table) => table,
+
+        // no hotkeys configured
+        Err(_) => return Ok(()),
+    };
+
+    let mut hotkeys = HOTKEYS.lock();
+    hotkeys.clear();
+
+    for (combination, action) in table {
+        let action = action
+            .into_str()
+            .map_err(|_e| HotkeyError::InvalidConfig {})?;
+
+        match Hotkey::new(&combination, &action) {
+            Ok(hotkey) => {
+                info!("Hotkey: {} => {}", combination, action);
+                hotkeys.push(hotkey);
+            }
+
+            Err(e) => error!("Invalid hotkey '{}': {}", combination, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the hotkeys per application of the profile `profile`, replacing the
+/// ones of the previously active profile
+pub fn apply_profile(profile: &Profile) {
+    let mut app_hotkeys = APP_HOTKEYS.lock();
+    app_hotkeys.clear();
+
+    if let Some(app_macros) = &profile.app_macros {
+        for (class, table) in app_macros.iter() {
+            let mut hotkeys = vec![];
+
+            for (combination, action) in table.iter() {
+                match Hotkey::new(combination, action) {
+                    Ok(hotkey) => {
+                        debug!("Hotkey of '{}': {} => {}", class, combination, action);
+                        hotkeys.push(hotkey);
+                    }
+
+                    Err(e) => error!("Invalid hotkey '{}' of '{}': {}", combination, class, e),
+                }
+            }
+
+            app_hotkeys.insert(windows::normalize_class(class), hotkeys);
+        }
+    }
+}
+
+/// Find the hotkey with the most modifiers, that is triggered by the key `code`
+fn find_hotkey(hotkeys: &[Hotkey], code: u32, pressed_keys: &HashSet<u32>) -> Option<Hotkey> {
+    hotkeys
+        .iter()
+        .filter(|h| h.key == code && h.modifiers.iter().all(|m| pressed_keys.contains(m)))
+        .max_by_key(|h| h.modifiers.len())
+        .cloned()
+}
+
+/// Record the state of a key that is not reported via evdev, like
+/// the Easy Shift or the FN key, so that it may act as a modifier
+pub fn set_key_state(code: u32, is_pressed: bool) {
+    if is_pressed {
+        PRESSED_KEYS.lock().insert(code);
+    } else {
+        PRESSED_KEYS.lock().remove(&code);
+    }
+}
+
+/// Returns true if the key with the key code `code` is currently held down
+pub fn is_key_pressed(code: u32) -> bool {
+    PRESSED_KEYS.lock().contains(&code)
+}
+
+/// Process a key event of the hardware keyboard, this has to be done
+/// before the event is reported to the Lua VMs and mirrored
+pub fn process_event(event: &evdev_rs::InputEvent) -> Disposition {
+    let code = match event.event_code {
+        EventCode::EV_KEY(ref key) => key.clone() as u32,
+        _ => return Disposition::Pass,
+    };
+
+    let mut pressed_keys = PRESSED_KEYS.lock();
+    let mut consumed_keys = CONSUMED_KEYS.lock();
+
+    match event.value {
+        // key up
+        0 => {
+            pressed_keys.remove(&code);
+
+            if consumed_keys.remove(&code) {
+                Disposition::Consume
+            } else {
+                Disposition::Pass
+            }
+        }
+
+        // key down
+        1 => {
+            pressed_keys.insert(code);
+
+            // the hotkeys of the focused application take precedence
+            let app_hotkey = windows::get_active_app().and_then(|class| {
+                APP_HOTKEYS
+                    .lock()
+                    .get(&class)
+                    .and_then(|hotkeys| find_hotkey(hotkeys, code, &pressed_keys))
+            });
+
+            let hotkey = app_hotkey.or_else(|| find_hotkey(&HOTKEYS.lock(), code, &pressed_keys));
+
+            match hotkey {
+                Some(hotkey) => {
+                    debug!("Hotkey triggered: {:?}", hotkey);
+
+                    consumed_keys.insert(code);
+                    Disposition::Trigger(hotkey.action)
+                }
+
+                None => Disposition::Pass,
+            }
+        }
+
+        // auto repeat
+        _ => {
+            if consumed_keys.contains(&code) {
+                Disposition::Consume
+            } else {
+                Disposition::Pass
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev_rs::enums::EV_KEY;
+
+    #[test]
+    fn test_parse_macro() {
+        let shift = EV_KEY::KEY_LEFTSHIFT as u32;
+        let h = EV_KEY::KEY_H as u32;
+        let i = EV_KEY::KEY_I as u32;
+
+        assert_eq!(
+            "macro:LEFTSHIFT+H I".parse::<Action>().unwrap(),
+            Action::Macro {
+                sequence: vec![vec![shift, h], vec![i]],
+                pacing_millis: None,
+            }
+        );
+
+        assert_eq!(
+            "macro@20:LEFTSHIFT+H I".parse::<Action>().unwrap(),
+            Action::Macro {
+                sequence: vec![vec![shift, h], vec![i]],
+                pacing_millis: Some(20),
+            }
+        );
+
+        assert_eq!(
+            "macro@ 0 :H".parse::<Action>().unwrap(),
+            Action::Macro {
+                sequence: vec![vec![h]],
+                pacing_millis: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_macro() {
+        for action in &[
+            "macro:",
+            "macro@20:",
+            "macro@:H",
+            "macro@x:H",
+            "macro@-5:H",
+            "macro@20",
+            "macro@20:NOSUCHKEY",
+        ] {
+            assert!(action.parse::<Action>().is_err(), "{}", action);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Identification of keys, for authors of scripts. While the identify mode is
+//! active, key presses are consumed: the pressed key is lit, and reported to the
+//! command line tool (`eruption identify`), that polls the daemon via D-Bus. The
+//! mode ends when ESC is pressed, or when the tool stopped polling.
+
+use evdev_rs::enums::{EventCode, EV_KEY};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::rvdevice::{KEYS_PER_COLUMN, RGBA};
+use crate::util;
+
+/// A key that has been pressed in identify mode
+#[derive(Debug, Clone)]
+pub struct IdentifiedKey {
+    /// Incremented on each key press, so that repeated presses of the same key
+    /// can be told apart
+    pub seq: u32,
+
+    /// The index of the LED of the key, zero based
+    pub index: usize,
+
+    /// The evdev key code
+    pub code: u32,
+
+    /// The evdev name of the key, like "KEY_A"
+    pub name: String,
+}
+
+struct IdentifyState {
+    last_poll: Instant,
+    key: Option<IdentifiedKey>,
+}
+
+lazy_static! {
+    /// State of the identify mode, None while inactive
+    static ref STATE: Mutex<Option<IdentifyState>> = Mutex::new(None);
+}
+
+/// Get the row and the column of the LED `index` in the layout of the device,
+/// zero based
+pub fn get_key_location(index: usize) -> (usize, usize) {
+    (index % KEYS_PER_COLUMN, index / KEYS_PER_COLUMN)
+}
+
+/// Enter the identify mode
+pub fn start() {
+    info!("Entering identify mode");
+
+    *STATE.lock() = Some(IdentifyState {
+        last_poll: Instant::now(),
+        key: None,
+    });
+}
+
+/// Get the most recently pressed key, and keep the identify mode active. Returns
+/// None if the identify mode is not active, e.g. because ESC has been pressed
+pub fn poll() -> Option<Option<IdentifiedKey>> {
+    let mut state = STATE.lock();
+
+    state.as_mut().map(|state| {
+        state.last_poll = Instant::now();
+        state.key.clone()
+    })
+}
+
+/// Returns true while the identify mode is active, it ends if the command line
+/// tool stopped polling
+fn is_active(state: &mut Option<IdentifyState>) -> bool {
+    let timeout = Duration::from_millis(constants::IDENTIFY_TIMEOUT_MILLIS);
+
+    if state
+        .as_ref()
+        .map_or(false, |state| state.last_poll.elapsed() > timeout)
+    {
+        info!("Leaving identify mode, the client stopped polling");
+        *state = None;
+    }
+
+    state.is_some()
+}
+
+/// Record the key press `raw_event`, if the identify mode is active. Returns true
+/// if the event has been consumed. Releases of keys are not consumed, since the
+/// key may have been pressed before the identify mode has been entered
+pub fn process_event(raw_event: &evdev_rs::InputEvent) -> bool {
+    let mut state = STATE.lock();
+
+    if !is_active(&mut state) {
+        return false;
+    }
+
+    let key = match raw_event.event_code {
+        EventCode::EV_KEY(ref key) => key.clone(),
+        _ => return false,
+    };
+
+    if raw_event.value == 0 {
+        return false;
+    }
+
+    if raw_event.value == 1 {
+        if key == EV_KEY::KEY_ESC {
+            info!("Leaving identify mode");
+
+            *state = None;
+            return true;
+        }
+
+        // keys without an LED can not be identified
+        let index = match (util::ev_key_to_key_index(key.clone()) as usize).checked_sub(1) {
+            Some(index) => index,
+            None => return true,
+        };
+
+        let state = state.as_mut().unwrap();
+        let seq = state.key.as_ref().map_or(0, |key| key.seq) + 1;
+
+        state.key = Some(IdentifiedKey {
+            seq,
+            index,
+            code: key.clone() as u32,
+            name: format!("{:?}", key),
+        });
+    }
+
+    true
+}
+
+/// Light the most recently pressed key, all other keys are dark while the identify
+/// mode is active
+pub fn render(led_map: &mut [RGBA]) {
+    let mut state = STATE.lock();
+
+    if !is_active(&mut state) {
+        return;
+    }
+
+    let index = state.as_ref().unwrap().key.as_ref().map(|key| key.index);
+
+    for (i, led) in led_map.iter_mut().enumerate() {
+        *led = if Some(i) == index {
+            RGBA {
+                r: 0xff,
+                g: 0xff,
+                b: 0xff,
+                a: 0xff,
+            }
+        } else {
+            RGBA {
+                r: 0x00,
+                g: 0x00,
+                b: 0x00,
+                a: 0xff,
+            }
+        };
+    }
+}
@@ -0,0 +1,896 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Input sources, that feed events into the main loop. Besides the keyboard
+//! itself, events are read from additional evdev devices, received via a local
+//! socket or the network, or replayed from a fixture file, so that profiles may be
+//! exercised without the hardware. Each source runs on its own thread, all sources feed
+//! the same kind of channel. New sources implement `InputSource` and are spawned
+//! via `spawn()`.
+//!
+//! Only the events of the keyboard are mirrored to the virtual keyboard. The other
+//! devices are not grabbed, so the desktop receives their events anyway, and the
+//! events of the sockets and of fixtures must never reach the session. For the same
+//! reason, hotkeys, the dial and the pointer keys only apply to the keyboard, no
+//! other source is able to consume its events.
+
+use evdev_rs::enums::{int_to_ev_key, EventCode, EventType, EV_SYN};
+use evdev_rs::{Device, InputEvent, ReadFlag, ReadStatus};
+use log::*;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::net::{IpAddr, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::hotkeys;
+use crate::latency;
+use crate::plugin_manager;
+use crate::plugins::{self, keyboard::KeyboardPluginError};
+use crate::pointer_keys;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, InputError>;
+
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("The input device went away")]
+    DeviceGone {},
+
+    #[error("Input device error: {description}")]
+    DeviceError { description: String },
+
+    #[error("Invalid fixture, line {line}: {description}")]
+    InvalidFixture { line: usize, description: String },
+
+    #[error("The input source has no more events")]
+    Exhausted {},
+}
+
+impl From<std::io::Error> for InputError {
+    fn from(e: std::io::Error) -> Self {
+        InputError::DeviceError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+/// The kind of source that an event originates from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    /// The (grabbed) keyboard itself
+    Keyboard,
+
+    /// An additional evdev device, that is not grabbed
+    Device,
+
+    /// The local socket
+    Socket,
+
+    /// The UDP socket
+    Network,
+
+    /// A fixture file
+    Fixture,
+}
+
+impl Origin {
+    /// Returns true if events of this origin are mirrored to the virtual keyboard.
+    /// Only the keyboard is grabbed, all other events either reach the desktop
+    /// anyway, or must not reach it at all
+    pub fn is_mirrored(self) -> bool {
+        self == Origin::Keyboard
+    }
+
+    /// Returns true if events of this origin may be consumed by hotkeys, the dial
+    /// and the pointer keys. Only the events of the grabbed keyboard can be withheld
+    /// from the desktop
+    pub fn is_consumable(self) -> bool {
+        self == Origin::Keyboard
+    }
+}
+
+/// An event, tagged with the kind of its source
+#[derive(Debug, Clone)]
+pub struct SourceEvent {
+    pub origin: Origin,
+    pub event: InputEvent,
+}
+
+/// A source of input events
+pub trait InputSource {
+    /// The name of the source, for logging
+    fn name(&self) -> &str;
+
+    /// The kind of the source
+    fn origin(&self) -> Origin;
+
+    /// Block until the next event is available. Returns None for spurious wakeups,
+    /// and an error of `DeviceGone` or `Exhausted` once the source has ended
+    fn next_event(&mut self) -> Result<Option<InputEvent>>;
+
+    /// Returns true if the source may be re-opened, after its device went away
+    fn can_reopen(&self) -> bool {
+        false
+    }
+
+    /// Re-open the source, after its device went away
+    fn reopen(&mut self) -> Result<()> {
+        Err(InputError::DeviceGone {})
+    }
+}
+
+/// Get the delay before the `attempt`-th attempt to re-open a source
+fn get_reopen_delay(attempt: u32) -> Duration {
+    // the shift is limited, so that no bits are shifted out
+    let millis = (constants::INPUT_REOPEN_MIN_MILLIS << attempt.min(16))
+        .min(constants::INPUT_REOPEN_MAX_MILLIS);
+
+    Duration::from_millis(millis)
+}
+
+/// Re-open the source `source` after its device went away, retry with an
+/// exponential backoff until it succeeds
+fn reopen_source(source: &mut dyn InputSource) {
+    warn!(
+        "The input device went away, trying to re-open it: {}",
+        source.name()
+    );
+
+    let mut attempt = 0;
+
+    loop {
+        thread::sleep(get_reopen_delay(attempt));
+
+        match source.reopen() {
+            Ok(()) => {
+                info!("Re-opened the input source: {}", source.name());
+                break;
+            }
+
+            Err(e) => {
+                debug!(
+                    "Could not re-open the input source {}: {}",
+                    source.name(),
+                    e
+                );
+
+                attempt = attempt.saturating_add(1);
+            }
+        }
+    }
+}
+
+/// Spawn a thread, that feeds the events of a source into `tx`. The source is
+/// created by `open` on that thread, since evdev handles are bound to the thread
+/// that opened them
+pub fn spawn<F>(name: &str, open: F, tx: Sender<Option<SourceEvent>>) -> Result<()>
+where
+    F: FnOnce() -> Result<Box<dyn InputSource>> + Send + 'static,
+{
+    let builder = thread::Builder::new().name(name.into());
+    builder.spawn(move || {
+        let mut source = match open() {
+            Ok(source) => source,
+
+            Err(e) => {
+                error!("Could not open an input source: {}", e);
+                return;
+            }
+        };
+
+        info!("Reading input events from: {}", source.name());
+
+        let origin = source.origin();
+
+        loop {
+            match source.next_event() {
+                Ok(event) => {
+                    if let Some(ref event) = event {
+                        latency::mark(event, latency::Stage::Read);
+                    }
+
+                    if tx
+                        .send(event.map(|event| SourceEvent { origin, event }))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+
+                // devices may be re-connected, e.g. after a USB reset
+                Err(InputError::DeviceGone {}) if source.can_reopen() => {
+                    reopen_source(source.as_mut())
+                }
+
+                Err(InputError::DeviceGone {}) | Err(InputError::Exhausted {}) => break,
+
+                Err(e) => {
+                    // ignore spurious errors
+                    trace!("{}: {}", source.name(), e);
+                }
+            }
+        }
+
+        info!("Input source ended: {}", source.name());
+    })?;
+
+    Ok(())
+}
+
+/// Returns true if `code` is a key code with an LED. Events of sources other
+/// than evdev devices are restricted to these keys, every other code would index
+/// beyond the key tables
+fn is_valid_key(code: u32) -> bool {
+    int_to_ev_key(code).is_some() && util::get_key_index(code).is_some()
+}
+
+/// A key event, followed by a SYN_REPORT
+fn key_events(code: u32, value: i32) -> Option<Vec<InputEvent>> {
+    if !is_valid_key(code) {
+        return None;
+    }
+
+    let key = int_to_ev_key(code)?;
+    let time = pointer_keys::now();
+
+    Some(vec![
+        InputEvent {
+            time: time.clone(),
+            event_type: EventType::EV_KEY,
+            event_code: EventCode::EV_KEY(key),
+            value,
+        },
+        InputEvent {
+            time,
+            event_type: EventType::EV_SYN,
+            event_code: EventCode::EV_SYN(EV_SYN::SYN_REPORT),
+            value: 0,
+        },
+    ])
+}
+
+/// The keyboard, read via the keyboard plugin
+pub struct KeyboardSource;
+
+impl KeyboardSource {
+    /// Open (and grab) the keyboard, on the calling thread
+    pub fn open() -> std::result::Result<Self, KeyboardPluginError> {
+        let mut plugin_manager = plugin_manager::PLUGIN_MANAGER.write();
+        let keyboard_plugin = plugin_manager
+            .find_plugin_by_name_mut("Keyboard".to_string())
+            .unwrap_or_else(|| {
+                error!("Could not find a required plugin");
+                panic!()
+            })
+            .as_any_mut()
+            .downcast_mut::<plugins::KeyboardPlugin>()
+            .unwrap();
+
+        keyboard_plugin.initialize_thread_locals()?;
+
+        Ok(KeyboardSource)
+    }
+}
+
+impl InputSource for KeyboardSource {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn origin(&self) -> Origin {
+        Origin::Keyboard
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>> {
+        let plugin_manager = plugin_manager::PLUGIN_MANAGER.read();
+        let keyboard_plugin = plugin_manager
+            .find_plugin_by_name("Keyboard".to_string())
+            .unwrap_or_else(|| {
+                error!("Could not find a required plugin");
+                panic!()
+            })
+            .as_any()
+            .downcast_ref::<plugins::KeyboardPlugin>()
+            .unwrap();
+
+        keyboard_plugin.get_next_event().map_err(|e| match e {
+            KeyboardPluginError::DeviceGone {} => InputError::DeviceGone {},
+
+            e => InputError::DeviceError {
+                description: format!("{}", e),
+            },
+        })
+    }
+
+    fn can_reopen(&self) -> bool {
+        true
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        *self = KeyboardSource::open().map_err(|e| InputError::DeviceError {
+            description: format!("{}", e),
+        })?;
+
+        Ok(())
+    }
+}
+
+/// An additional evdev device, e.g. a macro pad. The device is not grabbed, its
+/// events are seen by the rest of the system as well, so they are not mirrored
+pub struct EvdevSource {
+    path: String,
+    device: Device,
+}
+
+impl EvdevSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let device =
+            Device::new_from_fd(File::open(path)?).map_err(|e| InputError::DeviceError {
+                description: format!("{}", e),
+            })?;
+
+        info!(
+            "Additional input device: \"{}\" ({})",
+            device.name().unwrap_or("<n/a>"),
+            path
+        );
+
+        Ok(EvdevSource {
+            path: path.to_string(),
+            device,
+        })
+    }
+}
+
+impl InputSource for EvdevSource {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn origin(&self) -> Origin {
+        Origin::Device
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>> {
+        match self
+            .device
+            .next_event(ReadFlag::NORMAL | ReadFlag::BLOCKING)
+        {
+            Ok((ReadStatus::Success, event)) => Ok(Some(event)),
+            Ok(_) => Ok(None),
+
+            Err(e) if e as i32 == libc::ENODEV => Err(InputError::DeviceGone {}),
+            Err(e) => Err(InputError::DeviceError {
+                description: format!("{}", e),
+            }),
+        }
+    }
+
+    fn can_reopen(&self) -> bool {
+        true
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        *self = EvdevSource::open(&self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Decode the events of a datagram of the socket, 6 bytes each: The key code as
+/// u16 and the value as i32, big endian. Trailing bytes are ignored
+fn decode_datagram(buf: &[u8]) -> Vec<(u32, i32)> {
+    if buf.len() % 6 != 0 {
+        warn!("Ignoring {} trailing bytes of a datagram", buf.len() % 6);
+    }
+
+    buf.chunks_exact(6)
+        .map(|chunk| {
+            (
+                u16::from_be_bytes([chunk[0], chunk[1]]) as u32,
+                i32::from_be_bytes([chunk[2], chunk[3], chunk[4], chunk[5]]),
+            )
+        })
+        .collect()
+}
+
+/// Queue the key events of the datagram `buf`, that has been received by the
+/// source `name`
+fn queue_datagram(name: &str, buf: &[u8], pending: &mut VecDeque<InputEvent>) {
+    for (code, value) in decode_datagram(buf) {
+        match key_events(code, value) {
+            Some(events) => pending.extend(events),
+            None => warn!("Received an invalid key code via the {}: {}", name, code),
+        }
+    }
+}
+
+/// Key events received via a local datagram socket. Each datagram contains one
+/// or more events, see `decode_datagram()`. The socket is only accessible by root
+pub struct SocketSource {
+    path: PathBuf,
+    socket: UnixDatagram,
+    pending: VecDeque<InputEvent>,
+}
+
+impl SocketSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+
+        let socket = UnixDatagram::bind(path)?;
+        fs::set_permissions(
+            path,
+            fs::Permissions::from_mode(constants::INPUT_SOCKET_MODE),
+        )?;
+
+        info!("Receiving key events on {}", path.display());
+
+        Ok(SocketSource {
+            path: path.to_path_buf(),
+            socket,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl InputSource for SocketSource {
+    fn name(&self) -> &str {
+        "socket"
+    }
+
+    fn origin(&self) -> Origin {
+        Origin::Socket
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+
+        let mut buf = [0u8; constants::INPUT_SOCKET_MAX_DATAGRAM_SIZE];
+        let len = self.socket.recv(&mut buf)?;
+
+        queue_datagram(self.name(), &buf[..len], &mut self.pending);
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl Drop for SocketSource {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).unwrap_or_else(|e| {
+            warn!("Could not remove the socket {}: {}", self.path.display(), e)
+        });
+    }
+}
+
+/// Returns true if datagrams of `peer` are accepted. If no peers are configured,
+/// only datagrams of the local host are accepted
+fn is_allowed_peer(allowed_peers: &[IpAddr], peer: IpAddr) -> bool {
+    if allowed_peers.is_empty() {
+        peer.is_loopback()
+    } else {
+        allowed_peers.contains(&peer)
+    }
+}
+
+/// Key events received via UDP, in the format of the local socket, see
+/// `decode_datagram()`. The datagrams are not authenticated, so they are only
+/// accepted from the allowed peers, see `is_allowed_peer()`
+pub struct NetworkSource {
+    socket: UdpSocket,
+    allowed_peers: Vec<IpAddr>,
+    pending: VecDeque<InputEvent>,
+}
+
+impl NetworkSource {
+    pub fn open(address: &str, allowed_peers: Vec<IpAddr>) -> Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+
+        info!("Receiving key events on UDP {}", address);
+
+        Ok(NetworkSource {
+            socket,
+            allowed_peers,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl InputSource for NetworkSource {
+    fn name(&self) -> &str {
+        "network"
+    }
+
+    fn origin(&self) -> Origin {
+        Origin::Network
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+
+        let mut buf = [0u8; constants::INPUT_SOCKET_MAX_DATAGRAM_SIZE];
+        let (len, peer) = self.socket.recv_from(&mut buf)?;
+
+        if !is_allowed_peer(&self.allowed_peers, peer.ip()) {
+            warn!(
+                "Ignoring key events of a peer that is not allowed: {}",
+                peer
+            );
+            return Ok(None);
+        }
+
+        queue_datagram(self.name(), &buf[..len], &mut self.pending);
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Parse the events of a fixture, each line holds the delay in milliseconds, the
+/// name of the key and the value, e.g. `100 KEY_A 1`. Empty lines and lines
+/// starting with `#` are ignored
+fn parse_fixture(data: &str) -> Result<VecDeque<(Duration, u32, i32)>> {
+    data.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, data)| {
+            let invalid = |description: &str| InputError::InvalidFixture {
+                line,
+                description: description.to_string(),
+            };
+
+            let fields: Vec<&str> = data.split_whitespace().collect();
+
+            match fields.as_slice() {
+                [delay, key, value] => Ok((
+                    Duration::from_millis(
+                        delay.parse::<u64>().map_err(|_| invalid("Invalid delay"))?,
+                    ),
+                    hotkeys::parse_key_name(key)
+                        .ok()
+                        .filter(|code| is_valid_key(*code))
+                        .ok_or_else(|| invalid("Invalid key"))?,
+                    value.parse::<i32>().map_err(|_| invalid("Invalid value"))?,
+                )),
+
+                _ => Err(invalid("Expected: <delay> <key> <value>")),
+            }
+        })
+        .collect()
+}
+
+/// Key events replayed from a fixture file, see `parse_fixture()`
+pub struct FixtureSource {
+    path: String,
+    events: VecDeque<(Duration, u32, i32)>,
+    pending: VecDeque<InputEvent>,
+}
+
+impl FixtureSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let events = parse_fixture(&fs::read_to_string(path)?)?;
+
+        info!("Replaying {} key events from: {}", events.len(), path);
+
+        Ok(FixtureSource {
+            path: path.to_string(),
+            events,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl InputSource for FixtureSource {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn origin(&self) -> Origin {
+        Origin::Fixture
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+
+        let (delay, code, value) = self.events.pop_front().ok_or(InputError::Exhausted {})?;
+
+        thread::sleep(delay);
+
+        if let Some(events) = key_events(code, value) {
+            self.pending.extend(events);
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Spawn the additional sources of the `[inputs]` section: evdev devices, key
+/// events of the local socket and of the network, and a fixture file
+pub fn initialize(tx: &Sender<Option<SourceEvent>>) -> Result<()> {
+    let (devices, socket, socket_path, listen_address, allowed_peers, fixture) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config
+                .get::<Vec<String>>("inputs.devices")
+                .unwrap_or_else(|_| vec![]),
+            config.get_bool("inputs.socket").unwrap_or(false),
+            config
+                .get_str("inputs.socket_path")
+                .unwrap_or_else(|_| constants::DEFAULT_INPUT_SOCKET.to_string()),
+            config.get_str("inputs.listen_address").ok(),
+            config
+                .get::<Vec<String>>("inputs.allowed_peers")
+                .unwrap_or_else(|_| vec![]),
+            config.get_str("inputs.fixture").ok(),
+        )
+    };
+
+    for path in devices {
+        spawn(
+            "input-evdev",
+            move || Ok(Box::new(EvdevSource::open(&path)?) as Box<dyn InputSource>),
+            tx.clone(),
+        )?;
+    }
+
+    if socket {
+        spawn(
+            "input-socket",
+            move || {
+                Ok(Box::new(SocketSource::open(Path::new(&socket_path))?) as Box<dyn InputSource>)
+            },
+            tx.clone(),
+        )?;
+    }
+
+    if let Some(address) = listen_address {
+        let allowed_peers = allowed_peers
+            .iter()
+            .filter_map(|peer| match peer.parse::<IpAddr>() {
+                Ok(peer) => Some(peer),
+
+                Err(e) => {
+                    warn!("Invalid address of an allowed peer: {}: {}", peer, e);
+                    None
+                }
+            })
+            .collect();
+
+        spawn(
+            "input-network",
+            move || {
+                Ok(Box::new(NetworkSource::open(&address, allowed_peers)?) as Box<dyn InputSource>)
+            },
+            tx.clone(),
+        )?;
+    }
+
+    if let Some(path) = fixture {
+        spawn(
+            "input-fixture",
+            move || Ok(Box::new(FixtureSource::open(&path)?) as Box<dyn InputSource>),
+            tx.clone(),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev_rs::enums::EV_KEY;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_parse_fixture() {
+        let events = parse_fixture(
+            "# a comment\n\n100 KEY_A 1\n  # indented comment\n  50 b 0  \n0 KEY_ENTER 2\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                (Duration::from_millis(100), EV_KEY::KEY_A as u32, 1),
+                (Duration::from_millis(50), EV_KEY::KEY_B as u32, 0),
+                (Duration::from_millis(0), EV_KEY::KEY_ENTER as u32, 2),
+            ]
+        );
+
+        assert!(parse_fixture("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_fixture_errors() {
+        let error = |data: &str| match parse_fixture(data) {
+            Err(InputError::InvalidFixture { line, description }) => (line, description),
+            result => panic!("Expected an invalid fixture: {:?}", result),
+        };
+
+        assert_eq!(
+            error("100 KEY_A"),
+            (1, "Expected: <delay> <key> <value>".into())
+        );
+        assert_eq!(
+            error("100 KEY_A 1 2"),
+            (1, "Expected: <delay> <key> <value>".into())
+        );
+        assert_eq!(
+            error("# comment\nsoon KEY_A 1"),
+            (2, "Invalid delay".into())
+        );
+        assert_eq!(error("-1 KEY_A 1"), (1, "Invalid delay".into()));
+        assert_eq!(error("100 KEY_NOPE 1"), (1, "Invalid key".into()));
+        assert_eq!(error("100 KEY_A down"), (1, "Invalid value".into()));
+
+        // keys without an LED are rejected as well
+        assert_eq!(error("100 KEY_MICMUTE 1"), (1, "Invalid key".into()));
+    }
+
+    #[test]
+    fn test_decode_datagram() {
+        assert!(decode_datagram(&[]).is_empty());
+
+        assert_eq!(
+            decode_datagram(&[0x00, 0x1e, 0x00, 0x00, 0x00, 0x01]),
+            vec![(0x1e, 1)]
+        );
+
+        assert_eq!(
+            decode_datagram(&[
+                0x00, 0x1e, 0x00, 0x00, 0x00, 0x01, 0x01, 0x02, 0xff, 0xff, 0xff, 0xff,
+            ]),
+            vec![(0x1e, 1), (0x102, -1)]
+        );
+
+        // trailing bytes are ignored
+        assert_eq!(
+            decode_datagram(&[0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f, 0x00]),
+            vec![(0x1e, 0)]
+        );
+        assert!(decode_datagram(&[0x00, 0x1e, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn test_is_allowed_peer() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let peer: IpAddr = "192.168.1.20".parse().unwrap();
+
+        assert!(is_allowed_peer(&[], localhost));
+        assert!(is_allowed_peer(&[], "::1".parse().unwrap()));
+        assert!(!is_allowed_peer(&[], peer));
+
+        // the local host is not implicitly allowed, once peers are configured
+        assert!(is_allowed_peer(&[peer], peer));
+        assert!(!is_allowed_peer(&[peer], localhost));
+    }
+
+    #[test]
+    fn test_origin() {
+        assert!(Origin::Keyboard.is_mirrored());
+        assert!(Origin::Keyboard.is_consumable());
+
+        for origin in &[
+            Origin::Device,
+            Origin::Socket,
+            Origin::Network,
+            Origin::Fixture,
+        ] {
+            assert!(!origin.is_mirrored());
+            assert!(!origin.is_consumable());
+        }
+    }
+
+    #[test]
+    fn test_get_reopen_delay() {
+        assert_eq!(
+            get_reopen_delay(0),
+            Duration::from_millis(constants::INPUT_REOPEN_MIN_MILLIS)
+        );
+        assert_eq!(
+            get_reopen_delay(1),
+            Duration::from_millis(constants::INPUT_REOPEN_MIN_MILLIS * 2)
+        );
+
+        // the delay is capped, even for shifts beyond the width of the type
+        assert_eq!(
+            get_reopen_delay(63),
+            Duration::from_millis(constants::INPUT_REOPEN_MAX_MILLIS)
+        );
+        assert_eq!(
+            get_reopen_delay(20),
+            Duration::from_millis(constants::INPUT_REOPEN_MAX_MILLIS)
+        );
+        assert_eq!(
+            get_reopen_delay(200),
+            Duration::from_millis(constants::INPUT_REOPEN_MAX_MILLIS)
+        );
+    }
+
+    #[test]
+    fn test_key_events() {
+        assert!(key_events(EV_KEY::KEY_A as u32, 1).is_some());
+
+        // codes without an LED, or beyond the key tables
+        assert!(key_events(EV_KEY::KEY_MICMUTE as u32, 1).is_none());
+        assert!(key_events(0xffff, 1).is_none());
+    }
+
+    #[test]
+    fn test_spawn_fixture() {
+        let file =
+            std::env::temp_dir().join(format!("eruption-test-fixture-{}.txt", std::process::id()));
+
+        fs::write(&file, "0 KEY_A 1\n0 KEY_A 0\n").unwrap();
+
+        let (tx, rx) = channel();
+        let path = file.to_string_lossy().to_string();
+
+        spawn(
+            "test-fixture",
+            move || Ok(Box::new(FixtureSource::open(&path)?) as Box<dyn InputSource>),
+            tx,
+        )
+        .unwrap();
+
+        // the channel disconnects once the fixture is exhausted
+        let events: Vec<SourceEvent> = rx.iter().flatten().collect();
+
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|e| e.origin == Origin::Fixture));
+        assert!(!events[0].origin.is_mirrored());
+
+        let keys: Vec<(EventCode, i32)> = events
+            .iter()
+            .map(|e| (e.event.event_code.clone(), e.event.value))
+            .collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                (EventCode::EV_KEY(EV_KEY::KEY_A), 1),
+                (EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0),
+                (EventCode::EV_KEY(EV_KEY::KEY_A), 0),
+                (EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0),
+            ]
+        );
+    }
+}
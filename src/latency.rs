@@ -0,0 +1,272 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Tracing of the latency of the input path. If enabled, each key event is
+//! timestamped when it is read from the evdev device, when all Lua VMs completed
+//! their event handlers, and when it has been written to the virtual keyboard.
+//! Percentiles of the recent samples are logged periodically, and are available
+//! via the REST and D-Bus APIs.
+
+use evdev_rs::enums::EventCode;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::constants;
+
+/// The points of the input path an event is timestamped at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// The event has been read from the evdev device
+    Read,
+
+    /// All Lua VMs completed their event handlers
+    Dispatch,
+
+    /// The event has been written to the virtual keyboard
+    Write,
+}
+
+/// Identifies an event along the input path, by its kernel timestamp
+type EventId = (i64, i64, u32, i32);
+
+/// Timestamps of an event that is on its way through the input path
+#[derive(Debug, Clone)]
+struct Trace {
+    read: Instant,
+    dispatch: Option<Instant>,
+}
+
+/// Summary of the samples of a stage, in microseconds
+#[derive(Debug, Clone, Default)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+struct Tracer {
+    /// Events that have been read, but not yet written
+    pending: HashMap<EventId, Trace>,
+
+    /// Durations from read until the Lua VMs completed their event handlers
+    dispatch_samples: VecDeque<Duration>,
+
+    /// Durations from read until the event has been written to the virtual keyboard
+    write_samples: VecDeque<Duration>,
+
+    report_interval: Duration,
+    last_report: Instant,
+}
+
+lazy_static! {
+    /// Global "latency tracing enabled" flag
+    static ref ENABLE_TRACING: AtomicBool = AtomicBool::new(false);
+
+    static ref TRACER: Mutex<Tracer> = Mutex::new(Tracer {
+        pending: HashMap::new(),
+        dispatch_samples: VecDeque::with_capacity(constants::LATENCY_SAMPLES),
+        write_samples: VecDeque::with_capacity(constants::LATENCY_SAMPLES),
+        report_interval: Duration::from_secs(constants::DEFAULT_LATENCY_REPORT_SECS),
+        last_report: Instant::now(),
+    });
+}
+
+/// Load the configuration of the `[tracing]` section
+pub fn initialize() {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    let enabled = config.get::<bool>("tracing.latency").unwrap_or(false);
+
+    if enabled {
+        let mut tracer = TRACER.lock();
+
+        tracer.report_interval = Duration::from_secs(
+            config
+                .get_int("tracing.report_interval_secs")
+                .unwrap_or(constants::DEFAULT_LATENCY_REPORT_SECS as i64)
+                .max(1) as u64,
+        );
+
+        tracer.last_report = Instant::now();
+
+        info!("Tracing the latency of the input path");
+    }
+
+    ENABLE_TRACING.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns true if latency tracing is enabled
+pub fn is_enabled() -> bool {
+    ENABLE_TRACING.load(Ordering::SeqCst)
+}
+
+fn event_id(event: &evdev_rs::InputEvent) -> Option<EventId> {
+    match event.event_code {
+        EventCode::EV_KEY(ref key) => Some((
+            event.time.tv_sec,
+            event.time.tv_usec,
+            key.clone() as u32,
+            event.value,
+        )),
+
+        _ => None,
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+    if samples.len() >= constants::LATENCY_SAMPLES {
+        samples.pop_front();
+    }
+
+    samples.push_back(sample);
+}
+
+/// Record that the key event `event` reached the stage `stage` of the input path
+pub fn mark(event: &evdev_rs::InputEvent, stage: Stage) {
+    if !is_enabled() {
+        return;
+    }
+
+    let id = match event_id(event) {
+        Some(id) => id,
+        None => return,
+    };
+
+    let now = Instant::now();
+    let mut tracer = TRACER.lock();
+
+    match stage {
+        Stage::Read => {
+            // events that are consumed by hotkeys or the debounce filter are never
+            // written, so their traces are discarded after a while
+            if tracer.pending.len() >= constants::LATENCY_MAX_PENDING {
+                tracer
+                    .pending
+                    .retain(|_, trace| now.duration_since(trace.read) < Duration::from_secs(1));
+            }
+
+            tracer.pending.insert(
+                id,
+                Trace {
+                    read: now,
+                    dispatch: None,
+                },
+            );
+        }
+
+        Stage::Dispatch => {
+            if let Some(trace) = tracer.pending.get_mut(&id) {
+                trace.dispatch = Some(now);
+            }
+        }
+
+        Stage::Write => {
+            if let Some(trace) = tracer.pending.remove(&id) {
+                if let Some(dispatch) = trace.dispatch {
+                    push_sample(
+                        &mut tracer.dispatch_samples,
+                        dispatch.duration_since(trace.read),
+                    );
+                }
+
+                push_sample(&mut tracer.write_samples, now.duration_since(trace.read));
+            }
+        }
+    }
+}
+
+fn percentiles(samples: &VecDeque<Duration>) -> Percentiles {
+    if samples.is_empty() {
+        return Percentiles::default();
+    }
+
+    let mut sorted: Vec<u64> = samples.iter().map(|d| d.as_micros() as u64).collect();
+    sorted.sort_unstable();
+
+    // nearest-rank method
+    let rank = |p: usize| sorted[((sorted.len() * p + 99) / 100).max(1) - 1];
+
+    Percentiles {
+        samples: sorted.len(),
+        p50: rank(50),
+        p95: rank(95),
+        p99: rank(99),
+        max: *sorted.last().unwrap(),
+    }
+}
+
+/// Get the percentiles of the recent samples of the stages `Dispatch` and `Write`,
+/// relative to the point in time the event has been read
+pub fn summary() -> Vec<(Stage, Percentiles)> {
+    let tracer = TRACER.lock();
+
+    vec![
+        (Stage::Dispatch, percentiles(&tracer.dispatch_samples)),
+        (Stage::Write, percentiles(&tracer.write_samples)),
+    ]
+}
+
+/// Get the percentiles as a flat map, e.g. `write_p99_us`, for the D-Bus and REST APIs
+pub fn metrics() -> HashMap<String, u64> {
+    let mut result = HashMap::new();
+
+    for (stage, p) in summary() {
+        let prefix = format!("{:?}", stage).to_lowercase();
+
+        result.insert(format!("{}_samples", prefix), p.samples as u64);
+        result.insert(format!("{}_p50_us", prefix), p.p50);
+        result.insert(format!("{}_p95_us", prefix), p.p95);
+        result.insert(format!("{}_p99_us", prefix), p.p99);
+        result.insert(format!("{}_max_us", prefix), p.max);
+    }
+
+    result
+}
+
+/// Log the percentiles, if the report interval elapsed. This is called periodically
+/// by the main loop
+pub fn report() {
+    if !is_enabled() {
+        return;
+    }
+
+    {
+        let mut tracer = TRACER.lock();
+
+        if tracer.last_report.elapsed() < tracer.report_interval {
+            return;
+        }
+
+        tracer.last_report = Instant::now();
+    }
+
+    for (stage, p) in summary() {
+        if p.samples > 0 {
+            info!(
+                "Input latency (read -> {:?}, {} samples): p50 {} µs, p95 {} µs, p99 {} µs, max {} µs",
+                stage, p.samples, p.p50, p.p95, p.p99, p.max
+            );
+        }
+    }
+}
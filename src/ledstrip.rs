@@ -0,0 +1,405 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A virtual LED strip, that mirrors a region of the canvas to an LED strip on the
+//! network, e.g. an ambient strip on the desk that is driven by WLED. Frames are
+//! sent via the UDP realtime protocol of WLED, DRGB for up to 490 LEDs, and DNRGB
+//! in multiple packets for longer strips.
+//!
+//! The LEDs of the strip are laid out from left to right across the region, each
+//! LED shows the average color of the keys that lie in its slice of the region.
+//! The strip is an output sink of the render loop, it mirrors the default device.
+//! The address of the strip is resolved periodically on a thread of its own, so
+//! that a strip that joins the network later, or changes its address, is found.
+
+use log::*;
+use parking_lot::Mutex;
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::{NUM_KEYS, RGBA};
+use crate::scripting::canvas;
+use crate::sinks::{self, OutputSink};
+
+pub type Result<T> = std::result::Result<T, LedStripError>;
+
+#[derive(Debug, Error)]
+pub enum LedStripError {
+    #[error("Network error: {description}")]
+    SocketError { description: String },
+
+    #[error("Invalid address of the LED strip: {address}")]
+    InvalidAddress { address: String },
+
+    #[error("Invalid port of the LED strip: {port}")]
+    InvalidPort { port: i64 },
+
+    #[error("Invalid region of the LED strip")]
+    InvalidRegion {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+impl From<std::io::Error> for LedStripError {
+    fn from(e: std::io::Error) -> Self {
+        LedStripError::SocketError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+/// Protocol bytes of the UDP realtime protocol of WLED
+const PROTOCOL_DRGB: u8 = 2;
+const PROTOCOL_DNRGB: u8 = 4;
+
+/// Max. number of LEDs per packet of the DRGB and the DNRGB protocols
+const MAX_LEDS_DRGB: usize = 490;
+const MAX_LEDS_DNRGB: usize = 489;
+
+/// The resolved address of the strip, and a socket of the same address family
+type Target = Arc<Mutex<Option<(UdpSocket, SocketAddr)>>>;
+
+struct LedStrip {
+    target: Target,
+
+    /// The keys that each LED of the strip is averaged from
+    keys: Vec<Vec<usize>>,
+
+    /// Set while frames can not be sent, to log the error only once
+    is_failing: bool,
+}
+
+/// Find the keys that each of the `num_leds` LEDs of the strip is averaged from.
+/// The region is specified as `[x0, y0, x1, y1]` in pixels of the canvas. LEDs
+/// whose slice of the region contains no key use the nearest key
+fn map_keys(region: [f64; 4], num_leds: usize, reverse: bool) -> Vec<Vec<usize>> {
+    let [x0, y0, x1, y1] = region;
+    let width = (x1 - x0) / num_leds as f64;
+
+    let positions: Vec<(f64, f64)> = (0..NUM_KEYS)
+        .filter_map(|index| canvas::get_key_position(index, canvas::DEFAULT_GRID))
+        .collect();
+
+    let mut result: Vec<Vec<usize>> = (0..num_leds)
+        .map(|led| {
+            let (left, right) = (x0 + led as f64 * width, x0 + (led + 1) as f64 * width);
+
+            let keys: Vec<usize> = positions
+                .iter()
+                .enumerate()
+                .filter(|(_, (x, y))| *x >= left && *x < right && *y >= y0 && *y < y1)
+                .map(|(index, _)| index)
+                .collect();
+
+            if !keys.is_empty() {
+                return keys;
+            }
+
+            let center = ((left + right) / 2.0, (y0 + y1) / 2.0);
+            let distance = |p: &(f64, f64)| (p.0 - center.0).powi(2) + (p.1 - center.1).powi(2);
+
+            let nearest = positions
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| distance(a).partial_cmp(&distance(b)).unwrap())
+                .map(|(index, _)| index)
+                .unwrap_or(0);
+
+            vec![nearest]
+        })
+        .collect();
+
+    if reverse {
+        result.reverse();
+    }
+
+    result
+}
+
+/// Resolve the address `host`:`port`, and bind a socket of the same address family
+fn resolve(host: &str, port: u16) -> Result<(UdpSocket, SocketAddr)> {
+    let address = (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .ok_or_else(|| LedStripError::InvalidAddress {
+            address: host.to_string(),
+        })?;
+
+    let socket = if address.is_ipv4() {
+        UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+    } else {
+        UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+    };
+
+    socket.set_nonblocking(true)?;
+
+    Ok((socket, address))
+}
+
+/// Resolve the address of the strip periodically, and update `target` if it changed
+fn run_resolver(host: String, port: u16, target: Target) {
+    // set while the address can not be resolved, to log the error only once
+    let mut is_failing = false;
+
+    loop {
+        match resolve(&host, port) {
+            Ok((socket, address)) => {
+                let mut target = target.lock();
+
+                if target.as_ref().map(|(_, a)| *a) != Some(address) {
+                    info!("LED strip: Sending frames to {}", address);
+
+                    *target = Some((socket, address));
+                }
+
+                is_failing = false;
+            }
+
+            // the previous address is kept, the strip may still be reachable
+            Err(e) => {
+                if !is_failing {
+                    warn!("LED strip: {}", e);
+                }
+
+                is_failing = true;
+            }
+        }
+
+        thread::sleep(Duration::from_secs(
+            constants::LED_STRIP_RESOLVE_INTERVAL_SECS,
+        ));
+    }
+}
+
+/// Load the configuration of the `[led_strip]` section, spawn the thread that
+/// resolves the address of the strip and register the strip as an output sink
+pub fn initialize() -> Result<()> {
+    let (enabled, address, port, num_leds, region, reverse) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        let (width, height) = canvas::get_size();
+
+        (
+            config.get_bool("led_strip.enabled").unwrap_or(false),
+            config.get_str("led_strip.address").unwrap_or_default(),
+            config
+                .get_int("led_strip.port")
+                .unwrap_or(constants::DEFAULT_LED_STRIP_PORT as i64),
+            config
+                .get::<usize>("led_strip.num_leds")
+                .unwrap_or(constants::DEFAULT_LED_STRIP_NUM_LEDS),
+            config
+                .get::<Vec<f64>>("led_strip.region")
+                .unwrap_or_else(|_| vec![0.0, 0.0, width as f64, height as f64]),
+            config.get_bool("led_strip.reverse").unwrap_or(false),
+        )
+    };
+
+    if !enabled {
+        return Ok(());
+    }
+
+    let region = match region.as_slice() {
+        [x0, y0, x1, y1] if x1 > x0 && y1 > y0 => [*x0, *y0, *x1, *y1],
+        _ => return Err(LedStripError::InvalidRegion {}),
+    };
+
+    let port = u16::try_from(port).map_err(|_| LedStripError::InvalidPort { port })?;
+
+    if address.is_empty() {
+        return Err(LedStripError::InvalidAddress { address });
+    }
+
+    let num_leds = num_leds.max(1).min(constants::LED_STRIP_MAX_LEDS);
+
+    info!(
+        "LED strip: Sending {} LEDs to {}:{}, region: {:?}",
+        num_leds, address, port, region
+    );
+
+    let target: Target = Arc::new(Mutex::new(None));
+
+    {
+        let target = target.clone();
+
+        thread::Builder::new()
+            .name("led-strip".into())
+            .spawn(move || run_resolver(address, port, target))
+            .map_err(|_e| LedStripError::ThreadSpawnError {})?;
+    }
+
+    sinks::register(Box::new(LedStrip {
+        target,
+        keys: map_keys(region, num_leds, reverse),
+        is_failing: false,
+    }));
+
+    Ok(())
+}
+
+/// Encode the colors `colors` of the strip into packets of the realtime protocol
+fn encode_packets(colors: &[[u8; 3]]) -> Vec<Vec<u8>> {
+    let timeout = constants::LED_STRIP_TIMEOUT_SECS;
+
+    if colors.len() <= MAX_LEDS_DRGB {
+        let mut packet = vec![PROTOCOL_DRGB, timeout];
+        packet.extend(colors.iter().flatten());
+
+        vec![packet]
+    } else {
+        colors
+            .chunks(MAX_LEDS_DNRGB)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let start = (index * MAX_LEDS_DNRGB) as u16;
+
+                let mut packet = vec![PROTOCOL_DNRGB, timeout];
+                packet.extend_from_slice(&start.to_be_bytes());
+                packet.extend(chunk.iter().flatten());
+
+                packet
+            })
+            .collect()
+    }
+}
+
+impl OutputSink for LedStrip {
+    fn name(&self) -> &str {
+        "led-strip"
+    }
+
+    /// Mirror the frame `led_map` of the default device to the LED strip
+    fn write_frame(&mut self, _device: usize, led_map: &[RGBA]) {
+        let colors: Vec<[u8; 3]> = self
+            .keys
+            .iter()
+            .map(|keys| {
+                let mut sum = [0u32; 3];
+
+                for color in keys.iter().filter_map(|index| led_map.get(*index)) {
+                    sum[0] += color.r as u32;
+                    sum[1] += color.g as u32;
+                    sum[2] += color.b as u32;
+                }
+
+                let count = keys.len().max(1) as u32;
+
+                [
+                    (sum[0] / count) as u8,
+                    (sum[1] / count) as u8,
+                    (sum[2] / count) as u8,
+                ]
+            })
+            .collect();
+
+        // frames are dropped, until the address of the strip has been resolved
+        let target = self.target.lock();
+
+        if let Some((socket, address)) = target.as_ref() {
+            let result = encode_packets(&colors)
+                .iter()
+                .try_for_each(|packet| socket.send_to(packet, address).map(|_| ()));
+
+            match result {
+                Ok(()) => self.is_failing = false,
+
+                Err(e) => {
+                    if !self.is_failing {
+                        warn!("LED strip: Could not send a frame to {}: {}", address, e);
+                    }
+
+                    self.is_failing = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keys() {
+        let (width, height) = canvas::get_size();
+        let region = [0.0, 0.0, width as f64, height as f64];
+
+        let keys = map_keys(region, 8, false);
+
+        assert_eq!(keys.len(), 8);
+        assert!(keys
+            .iter()
+            .all(|keys| !keys.is_empty() && keys.iter().all(|index| *index < NUM_KEYS)));
+
+        // the LEDs are laid out from left to right
+        let first = canvas::get_key_position(keys[0][0], canvas::DEFAULT_GRID)
+            .unwrap()
+            .0;
+        let last = canvas::get_key_position(keys[7][0], canvas::DEFAULT_GRID)
+            .unwrap()
+            .0;
+
+        assert!(first < last);
+
+        let mut reversed = map_keys(region, 8, true);
+        reversed.reverse();
+
+        assert_eq!(keys, reversed);
+
+        // LEDs of a region without keys use the nearest key
+        let keys = map_keys([-100.0, -100.0, -50.0, -50.0], 4, false);
+
+        assert!(keys.iter().all(|keys| keys.len() == 1));
+    }
+
+    #[test]
+    fn test_encode_packets() {
+        let timeout = constants::LED_STRIP_TIMEOUT_SECS;
+
+        // up to 490 LEDs fit into a single DRGB packet
+        let colors = vec![[1, 2, 3]; MAX_LEDS_DRGB];
+        let packets = encode_packets(&colors);
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].len(), 2 + MAX_LEDS_DRGB * 3);
+        assert_eq!(packets[0][..5], [PROTOCOL_DRGB, timeout, 1, 2, 3]);
+
+        // longer strips are split into DNRGB packets, with the index of their first LED
+        let colors = vec![[1, 2, 3]; MAX_LEDS_DRGB + 1];
+        let packets = encode_packets(&colors);
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].len(), 4 + MAX_LEDS_DNRGB * 3);
+        assert_eq!(packets[0][..7], [PROTOCOL_DNRGB, timeout, 0, 0, 1, 2, 3]);
+
+        let start = (MAX_LEDS_DNRGB as u16).to_be_bytes();
+
+        assert_eq!(packets[1].len(), 4 + 2 * 3);
+        assert_eq!(
+            packets[1][..4],
+            [PROTOCOL_DNRGB, timeout, start[0], start[1]]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use parking_lot::{Mutex, RwLock};
+use std::collections::VecDeque;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::constants;
+
+pub type Result<T> = std::result::Result<T, LoggingError>;
+
+#[derive(Debug, Error)]
+pub enum LoggingError {
+    #[error("Could not install the logger")]
+    InitError {},
+
+    #[error("Invalid log filter specification: {spec}")]
+    InvalidFilterSpec { spec: String },
+}
+
+/// The log target of script errors, only errors logged to this target are shown
+/// by the error indicator, e.g.: `error!(target: logging::SCRIPT_ERRORS, ...)`
+pub const SCRIPT_ERRORS: &str = "eruption::scripting::errors";
+
+lazy_static! {
+    /// The currently active log filter
+    static ref LOG_FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::default());
+
+    /// Ring buffer holding the most recent log lines
+    static ref LOG_BUFFER: Mutex<VecDeque<String>> =
+        Mutex::new(VecDeque::with_capacity(constants::LOG_BUFFER_SIZE));
+
+    /// Script errors that have been logged, but not yet acknowledged by the user
+    static ref UNACKNOWLEDGED_ERRORS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
+/// A log filter, consisting of a global log level and a set of per-module
+/// overrides, e.g.: "info,scripting=debug"
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    pub level: LevelFilter,
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter {
+            level: LevelFilter::Info,
+            modules: vec![],
+        }
+    }
+}
+
+impl LogFilter {
+    /// Returns the log level that applies to `target`. The most specific
+    /// module filter wins
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let target = target.trim_start_matches("eruption::");
+
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target.starts_with(module.as_str())
+                    && (target.len() == module.len() || target[module.len()..].starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    /// Returns the most verbose level of all filters
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, std::cmp::max)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = LoggingError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut result = LogFilter::default();
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+
+            let first = parts.next().unwrap();
+            match parts.next() {
+                Some(level) => {
+                    let level = level.trim().parse::<LevelFilter>().map_err(|_| {
+                        LoggingError::InvalidFilterSpec {
+                            spec: spec.to_string(),
+                        }
+                    })?;
+
+                    let module = first.trim().trim_start_matches("eruption::").to_string();
+
+                    result.modules.push((module, level));
+                }
+
+                None => {
+                    // either a plain log level, or a module name without a level
+                    match first.parse::<LevelFilter>() {
+                        Ok(level) => result.level = level,
+                        Err(_) => result.modules.push((
+                            first.trim_start_matches("eruption::").to_string(),
+                            LevelFilter::Trace,
+                        )),
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.level.to_string().to_lowercase())?;
+
+        for (module, level) in self.modules.iter() {
+            write!(f, ",{}={}", module, level.to_string().to_lowercase())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Logger that applies the runtime adjustable log filter and records
+/// all log lines that pass the filter in the ring buffer
+struct Logger {
+    /// The logger that does the actual output formatting
+    inner: Box<dyn Log>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LOG_FILTER.read().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let line = format!(
+            "{}.{:03} {:<5} {} > {}",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        if record.level() == Level::Error && record.target() == SCRIPT_ERRORS {
+            let mut errors = UNACKNOWLEDGED_ERRORS.lock();
+
+            if errors.len() >= constants::MAX_UNACKNOWLEDGED_ERRORS {
+                errors.pop_front();
+            }
+
+            errors.push_back(line.clone());
+        }
+
+        {
+            let mut buffer = LOG_BUFFER.lock();
+
+            if buffer.len() >= constants::LOG_BUFFER_SIZE {
+                buffer.pop_front();
+            }
+
+            buffer.push_back(line);
+        }
+
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the logger. The initial filter is taken from the RUST_LOG
+/// environment variable, if set
+pub fn initialize() -> Result<()> {
+    let filter = match env::var("RUST_LOG") {
+        Ok(spec) => spec.parse::<LogFilter>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            LogFilter::default()
+        }),
+
+        Err(_) => LogFilter::default(),
+    };
+
+    // filtering is done by us, so let the inner logger pass everything
+    let inner = pretty_env_logger::formatted_builder()
+        .filter_level(LevelFilter::Trace)
+        .build();
+
+    log::set_max_level(filter.max_level());
+    *LOG_FILTER.write() = filter;
+
+    log::set_boxed_logger(Box::new(Logger {
+        inner: Box::new(inner),
+    }))
+    .map_err(|_e| LoggingError::InitError {})?;
+
+    Ok(())
+}
+
+/// Returns the currently active log filter, e.g.: "info,scripting=debug"
+pub fn get_log_filter() -> String {
+    LOG_FILTER.read().to_string()
+}
+
+/// Replace the active log filter with the filter specified by `spec`
+pub fn set_log_filter(spec: &str) -> Result<()> {
+    let filter = spec.parse::<LogFilter>()?;
+
+    log::set_max_level(filter.max_level());
+    *LOG_FILTER.write() = filter;
+
+    Ok(())
+}
+
+/// Returns up to `count` of the most recent log lines, oldest first
+pub fn get_recent_log_lines(count: usize) -> Vec<String> {
+    let buffer = LOG_BUFFER.lock();
+    let skip = buffer.len().saturating_sub(count);
+
+    buffer.iter().skip(skip).cloned().collect()
+}
+
+/// Returns the script errors that have been logged since they were last
+/// acknowledged, oldest first
+pub fn get_unacknowledged_errors() -> Vec<String> {
+    UNACKNOWLEDGED_ERRORS.lock().iter().cloned().collect()
+}
+
+/// Returns true if script errors have been logged since they were last acknowledged
+pub fn has_unacknowledged_errors() -> bool {
+    !UNACKNOWLEDGED_ERRORS.lock().is_empty()
+}
+
+/// Acknowledge all script errors that have been logged, returns the number of errors
+pub fn acknowledge_errors() -> usize {
+    let mut errors = UNACKNOWLEDGED_ERRORS.lock();
+    let count = errors.len();
+
+    errors.clear();
+
+    count
+}
@@ -22,7 +22,6 @@
 //#![feature(vec_into_raw_parts)]
 
 use clap::{App, Arg};
-use failure::Fail;
 use hotwatch::{
     blocking::{Flow, Hotwatch},
     Event,
@@ -31,7 +30,7 @@ use lazy_static::lazy_static;
 use log::*;
 use parking_lot::{Condvar, Mutex};
 use std::convert::TryInto;
-use std::env;
+use std::panic;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
@@ -40,25 +39,68 @@ use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 use std::u64;
+use thiserror::Error;
 
 mod util;
 
+mod accessibility;
+mod alarms;
+mod alerts;
+mod animations;
+mod bundles;
+mod capture;
 mod rvdevice;
-use rvdevice::RvDeviceState;
+use rvdevice::{HealthChange, RvDeviceState};
 
 mod constants;
 mod dbus_interface;
+mod debounce;
+mod diagnostics;
+mod dial;
+mod display;
 mod events;
+mod fullscreen;
+mod hotkeys;
+mod identify;
+mod inputs;
+mod latency;
+mod ledstrip;
+mod logging;
+mod mentions;
+mod migration;
+mod netsync;
+mod overlay;
+mod palettes;
+mod paths;
 mod plugin_manager;
 mod plugins;
+mod pointer_keys;
+mod postprocessing;
 mod profiles;
+mod recorder;
+mod repl;
+#[cfg(feature = "rest")]
+mod rest;
+mod rules;
+mod scheduling;
 mod scripting;
+mod selftest;
+mod shm;
+mod sinks;
+mod sleep;
+mod snapshot;
 mod state;
+mod stuck_keys;
+mod transitions;
+mod wallpaper;
+mod warmth;
+mod windows;
 
 use plugins::macros;
 use profiles::Profile;
 use scripting::manifest::Manifest;
 use scripting::script;
+use sinks::OutputSink;
 
 #[cfg(feature = "frontend")]
 mod frontend;
@@ -74,6 +116,10 @@ lazy_static! {
     /// The currently active profile
     pub static ref ACTIVE_PROFILE: Arc<Mutex<Option<Profile>>> = Arc::new(Mutex::new(None));
 
+    /// The profile that was active before a profile preview has been started,
+    /// and the time at which the preview ends
+    pub static ref PROFILE_PREVIEW: Arc<Mutex<Option<(PathBuf, Instant)>>> = Arc::new(Mutex::new(None));
+
     /// The current "pipeline" of scripts
     pub static ref ACTIVE_SCRIPTS: Arc<Mutex<Vec<Manifest>>> = Arc::new(Mutex::new(vec![]));
 
@@ -85,9 +131,11 @@ lazy_static! {
     /// Global "quit" status flag
     pub static ref QUIT: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 
-    // Color maps of Lua VMs ready?
-    pub static ref COLOR_MAPS_READY_CONDITION: Arc<(Mutex<usize>, Condvar)> =
-        Arc::new((Mutex::new(0), Condvar::new()));
+    /// Global "do not disturb" flag, freezes all animations while set
+    pub static ref DO_NOT_DISTURB: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+
+    /// Global "shared mode" flag, the keyboard is not grabbed exclusively while set
+    pub static ref SHARED_MODE: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 
     // All upcalls (event handlers) in Lua VM completed?
     pub static ref UPCALL_COMPLETED_ON_KEY_DOWN: Arc<(Mutex<usize>, Condvar)> =
@@ -95,102 +143,1307 @@ lazy_static! {
     pub static ref UPCALL_COMPLETED_ON_KEY_UP: Arc<(Mutex<usize>, Condvar)> =
         Arc::new((Mutex::new(0), Condvar::new()));
 
-    // Other state
+    // Other state
+
+    /// Global "keyboard brightness" modifier
+    pub static ref BRIGHTNESS: AtomicIsize = AtomicIsize::new(100);
+
+    static ref LUA_TXS: Arc<Mutex<Vec<Sender<script::Message>>>> = Arc::new(Mutex::new(vec![]));
+}
+
+pub type Result<T> = std::result::Result<T, MainError>;
+
+#[derive(Debug, Error)]
+pub enum MainError {
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+
+    #[error("Could not switch profiles")]
+    SwitchProfileError {},
+
+    #[error("Could not execute Lua script")]
+    ScriptExecError {},
+    // #[error("Unknown error: {description}")]
+    // UnknownError { description: String },
+}
+
+#[derive(Debug)]
+pub enum FileSystemEvent {
+    ProfilesChanged,
+    ScriptsChanged,
+}
+
+fn print_header() {
+    println!(
+        r#"
+ Eruption is free software: you can redistribute it and/or modify
+ it under the terms of the GNU General Public License as published by
+ the Free Software Foundation, either version 3 of the License, or
+ (at your option) any later version.
+
+ Eruption is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ GNU General Public License for more details.
+
+ You should have received a copy of the GNU General Public License
+ along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+"#
+    );
+}
+
+/// Process commandline options
+fn parse_commandline<'a>() -> clap::ArgMatches<'a> {
+    App::new("Eruption")
+        .version("0.1.1")
+        .author("X3n0m0rph59 <x3n0m0rph59@gmail.com>")
+        .about("Linux user-mode driver for the ROCCAT Vulcan 100/12x series keyboards")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Sets the configuration file to use")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .short("p")
+                .long("profile")
+                .value_name("profile")
+                .help("Sets the profile to activate")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("capture")
+                .long("capture")
+                .value_name("FILE")
+                .help("Record all HID reports exchanged with the devices to FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Record the frames shown on the keyboard to FILE, see export-recording")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scripts")
+                .help("The Lua scripts to execute")
+                .multiple(true)
+                // .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("v")
+                .short("v")
+                .multiple(true)
+                .help("Sets the level of verbosity"),
+        )
+        .subcommand(App::new("list-scripts").about("Display a listing of all available scripts"))
+        .subcommand(App::new("active-scripts").about(
+            "Display the active scripts of the running daemon, and the capabilities they have been granted",
+        ))
+        .subcommand(App::new("device-info").about(
+            "Display information about the connected devices, like e.g. their firmware versions",
+        ))
+        .subcommand(
+            App::new("test-leds")
+                .about("Light each LED individually and display the key topology table, the daemon must not be running")
+                .arg(
+                    Arg::with_name("device")
+                        .short("d")
+                        .long("device")
+                        .value_name("INDEX")
+                        .help("The index of the device to test")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("delay")
+                        .long("delay")
+                        .value_name("MILLIS")
+                        .help("The time each LED is lit, in milliseconds")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("topology")
+                        .long("topology")
+                        .help("Only display the key topology table, without accessing the device"),
+                ),
+        )
+        .subcommand(
+            App::new("replay-capture")
+                .about("Replay a capture of HID reports against a mock device, and display a summary")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The capture file, as recorded with --capture")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("verbose")
+                        .long("verbose")
+                        .help("Display each report"),
+                ),
+        )
+        .subcommand(
+            App::new("export-recording")
+                .about("Export a recording of frames as an animated GIF, or as a sequence of PNG images")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The recording, as recorded with --record")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .help("The GIF file, or the directory that receives the PNG images")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("fps")
+                        .long("fps")
+                        .value_name("FPS")
+                        .help("The frame rate of the exported animation, defaults to 25")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("bundle")
+                .about("Validate, install, list or remove bundles of profiles and scripts")
+                .subcommand(
+                    App::new("validate")
+                        .about("Validate a bundle, and display its contents")
+                        .arg(
+                            Arg::with_name("file")
+                                .help("The .eruption-bundle file")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("install")
+                        .about("Install a bundle into the configuration directory of the user")
+                        .arg(
+                            Arg::with_name("file")
+                                .help("The .eruption-bundle file")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("Replace existing files, except for files of other bundles"),
+                        ),
+                )
+                .subcommand(App::new("list").about("Display a listing of all installed bundles"))
+                .subcommand(
+                    App::new("remove")
+                        .about("Remove an installed bundle")
+                        .arg(
+                            Arg::with_name("name")
+                                .help("The name of the bundle")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("repl")
+                .about("Evaluate Lua snippets in the context of a running script, requires the debug console to be enabled")
+                .arg(
+                    Arg::with_name("script")
+                        .help("The name of the script, as specified by its manifest")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("The socket of the debug console")
+                        .default_value(constants::DEFAULT_REPL_SOCKET)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("snapshot")
+                .about("Export or import the effective configuration, including profiles and runtime state")
+                .subcommand(
+                    App::new("export")
+                        .about("Export the effective configuration into a snapshot")
+                        .arg(
+                            Arg::with_name("file")
+                                .help("The .eruption-snapshot file")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("import")
+                        .about("Import a snapshot, existing files are kept as a backup")
+                        .arg(
+                            Arg::with_name("file")
+                                .help("The .eruption-snapshot file")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("plugins")
+                .about("List, enable or disable the plugins of the running daemon")
+                .subcommand(App::new("list").about("Display a listing of all plugins"))
+                .subcommand(
+                    App::new("enable").about("Enable a plugin").arg(
+                        Arg::with_name("name")
+                            .help("The name of the plugin")
+                            .required(true)
+                            .index(1),
+                    ),
+                )
+                .subcommand(
+                    App::new("disable").about("Disable a plugin").arg(
+                        Arg::with_name("name")
+                            .help("The name of the plugin")
+                            .required(true)
+                            .index(1),
+                    ),
+                ),
+        )
+        .subcommand(
+            App::new("sign-script")
+                .about("Compute the checksum of a script, and optionally sign it, for its manifest")
+                .arg(
+                    Arg::with_name("script")
+                        .help("The script file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .value_name("FILE")
+                        .help("The file containing the hex encoded Ed25519 secret key to sign with")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("api-docs")
+                .about("Display the reference of the Lua API, as a markdown table")
+                .arg(
+                    Arg::with_name("stubs")
+                        .long("stubs")
+                        .help("Generate Lua stubs of all functions instead, e.g. to test scripts"),
+                ),
+        )
+        .subcommand(
+            App::new("log-filter")
+                .about("Display or change the log filter of the running daemon, e.g.: info,scripting=debug")
+                .arg(
+                    Arg::with_name("filter")
+                        .help("The new log filter")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("logs")
+                .about("Display the most recent log lines of the running daemon")
+                .arg(
+                    Arg::with_name("count")
+                        .short("n")
+                        .long("count")
+                        .value_name("COUNT")
+                        .help("The number of log lines to display")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("errors")
+                .about("Display or acknowledge the errors of the running daemon, acknowledging clears the error indicator")
+                .subcommand(App::new("list").about("Display the errors that have not been acknowledged"))
+                .subcommand(App::new("ack").about("Acknowledge all errors")),
+        )
+        .subcommand(
+            App::new("start-pomodoro")
+                .about("Start the pomodoro timer of the running daemon")
+                .arg(
+                    Arg::with_name("work")
+                        .help("Duration of the work phase in minutes")
+                        .default_value("25")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("break")
+                        .help("Duration of the break phase in minutes")
+                        .default_value("5")
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            App::new("stop-pomodoro").about("Stop the pomodoro timer of the running daemon"),
+        )
+        .subcommand(
+            App::new("alerts")
+                .about("List, schedule or cancel alerts of the running daemon")
+                .subcommand(App::new("list").about("Display a listing of the scheduled alerts"))
+                .subcommand(
+                    App::new("schedule")
+                        .about("Schedule an alert")
+                        .arg(
+                            Arg::with_name("time")
+                                .help("Point in time, like \"+10m\", \"14:30\" or \"2020-04-01 14:30\"")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("effect")
+                                .help("The effect: solid, pulse or blink")
+                                .default_value("pulse")
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::with_name("color")
+                                .long("color")
+                                .value_name("COLOR")
+                                .help("The color of the effect, like 0xff0000")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("duration")
+                                .long("duration")
+                                .value_name("SECONDS")
+                                .help("How long the effect is displayed")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    App::new("cancel").about("Cancel or dismiss an alert").arg(
+                        Arg::with_name("id")
+                            .help("The id of the alert")
+                            .required(true)
+                            .index(1),
+                    ),
+                ),
+        )
+        .subcommand(
+            App::new("identify")
+                .about("Light each key that is pressed, and display its index, key code and location, until ESC is pressed"),
+        )
+        .subcommand(
+            App::new("debug")
+                .about("Set breakpoints in the scripts of the running daemon, inspect and resume a paused script, requires the debugger to be enabled")
+                .subcommand(
+                    App::new("break")
+                        .about("Set a breakpoint")
+                        .arg(
+                            Arg::with_name("script")
+                                .help("The name of the script, as specified by its manifest")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("line")
+                                .help("The line number")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    App::new("clear")
+                        .about("Clear a breakpoint, or all breakpoints of a script")
+                        .arg(
+                            Arg::with_name("script")
+                                .help("The name of the script, as specified by its manifest")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("line")
+                                .help("The line number")
+                                .index(2),
+                        ),
+                )
+                .subcommand(App::new("list").about("Display a listing of the breakpoints"))
+                .subcommand(App::new("status").about("Display the location and the variables of the paused script"))
+                .subcommand(App::new("continue").about("Resume the paused script"))
+                .subcommand(App::new("step").about("Execute the next line of the paused script, and pause again")),
+        )
+        .subcommand(
+            App::new("dnd")
+                .about("Display or change the do-not-disturb mode of the running daemon")
+                .arg(
+                    Arg::with_name("state")
+                        .help("The new state")
+                        .possible_values(&["on", "off", "toggle"])
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("active-app")
+                .about("Display or report the window class of the focused application to the running daemon")
+                .arg(
+                    Arg::with_name("class")
+                        .help("The window class of the focused application, an empty class means that no window is focused")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("fullscreen")
+                        .long("fullscreen")
+                        .help("The focused application is in fullscreen mode")
+                        .requires("class"),
+                ),
+        )
+        .subcommand(
+            App::new("accent-color")
+                .about("Display or report the accent color of the desktop environment to the running daemon")
+                .arg(
+                    Arg::with_name("color")
+                        .help("The accent color, either #rrggbb or r,g,b")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("color-temperature")
+                .about("Display or report the color temperature of the screens to the running daemon")
+                .arg(
+                    Arg::with_name("kelvin")
+                        .help("The color temperature in Kelvin, e.g. 4500")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("display")
+                .about("Display or report the power state of the displays to the running daemon")
+                .arg(
+                    Arg::with_name("state")
+                        .help("The power state of the displays: on or off")
+                        .possible_values(&["on", "off"])
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("latency")
+                .about("Display the latency percentiles of the input path of the running daemon"),
+        )
+        .subcommand(
+            App::new("param")
+                .about("Override parameters of the active scripts of the running daemon")
+                .subcommand(
+                    App::new("set")
+                        .about("Override a parameter, e.g.: shockwave.color_step 12")
+                        .arg(
+                            Arg::with_name("param")
+                                .help("The script and the name of the parameter: <script>.<parameter>")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("value")
+                                .help("The new value")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::with_name("persist")
+                                .long("persist")
+                                .help("Save the value to the active profile as well"),
+                        ),
+                )
+                .subcommand(
+                    App::new("clear")
+                        .about("Remove the overrides, of all scripts or of the script SCRIPT")
+                        .arg(
+                            Arg::with_name("script")
+                                .help("The script")
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("layers")
+                .about("Display or control the script layers of the running daemon, the scripts of bypassed layers keep running")
+                .subcommand(App::new("list").about("Display a listing of the layers, with their opacity"))
+                .subcommand(
+                    App::new("opacity")
+                        .about("Set the opacity of a layer")
+                        .arg(
+                            Arg::with_name("layer")
+                                .help("The index of the layer, zero based")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("percent")
+                                .help("The opacity in percent (0-100)")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::with_name("fade")
+                                .long("fade")
+                                .value_name("MILLIS")
+                                .help("Fade to the new opacity over MILLIS milliseconds")
+                                .default_value("0"),
+                        ),
+                )
+                .subcommand(
+                    App::new("enable").about("Enable a bypassed layer").arg(
+                        Arg::with_name("layer")
+                            .help("The index of the layer, zero based")
+                            .required(true)
+                            .index(1),
+                    ),
+                )
+                .subcommand(
+                    App::new("disable").about("Bypass a layer").arg(
+                        Arg::with_name("layer")
+                            .help("The index of the layer, zero based")
+                            .required(true)
+                            .index(1),
+                    ),
+                ),
+        )
+        .subcommand(
+            App::new("check-syntax")
+                .about("Validate a Lua script for syntactical correctness")
+                .arg(
+                    Arg::with_name("script")
+                        .help("The Lua script to check")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .get_matches()
+}
+
+/// Query and print information about the connected devices
+fn print_device_info() {
+    match hidapi::HidApi::new() {
+        Ok(hidapi) => match RvDeviceState::enumerate_devices(&hidapi) {
+            Ok(rvdevices) => {
+                for (index, mut rvdevice) in rvdevices.into_iter().enumerate() {
+                    rvdevice.open(&hidapi).unwrap_or_else(|e| {
+                        error!("Error opening the keyboard device: {}", e);
+                        process::exit(3);
+                    });
+
+                    match rvdevice.query_device_info() {
+                        Ok(device_info) => {
+                            println!("Device index:     {}", index);
+                            println!("Model name:       {}", device_info.model_name);
+                            println!("Firmware version: {}", device_info.firmware_version);
+                            println!("Serial number:    {}", device_info.serial_number);
+                            println!("USB path:         {}", device_info.usb_path);
+                            println!();
+                        }
+
+                        Err(e) => error!("Could not query device information: {}", e),
+                    }
+
+                    rvdevice.close_all().unwrap_or_else(|e| {
+                        warn!("Could not close the keyboard device: {}", e);
+                    });
+                }
+            }
+
+            Err(_) => {
+                error!("Could not enumerate system HID devices");
+                process::exit(2);
+            }
+        },
+
+        Err(_) => {
+            error!("Could not open HIDAPI");
+            process::exit(1);
+        }
+    }
+}
+
+/// Parse the configuration file `config_file` and make it the global
+/// configuration, for the commands that run without the daemon
+fn load_config(config_file: &Path) {
+    let mut config = config::Config::default();
+    config
+        .merge(config::File::new(
+            &config_file.to_string_lossy(),
+            config::FileFormat::Toml,
+        ))
+        .unwrap_or_else(|e| {
+            error!("Could not parse configuration file: {}", e);
+            process::exit(4);
+        });
+
+    *CONFIG.lock() = Some(config);
+}
+
+/// Validate, install, remove or list script bundles
+fn run_bundle_command(matches: &clap::ArgMatches, config_file: &Path) {
+    // the configuration holds the security settings and the trust store
+    load_config(config_file);
+
+    let result = if let Some(sub_matches) = matches.subcommand_matches("validate") {
+        bundles::validate(sub_matches.value_of("file").unwrap())
+    } else if let Some(sub_matches) = matches.subcommand_matches("install") {
+        bundles::install(
+            sub_matches.value_of("file").unwrap(),
+            sub_matches.is_present("force"),
+        )
+    } else if let Some(sub_matches) = matches.subcommand_matches("remove") {
+        bundles::remove(sub_matches.value_of("name").unwrap())
+    } else {
+        bundles::list()
+    };
+
+    result.unwrap_or_else(|e| {
+        error!("{}", e);
+        process::exit(1);
+    });
+}
+
+fn run_snapshot_command(matches: &clap::ArgMatches, config_file: &Path) {
+    // the configuration is required to find the profiles
+    load_config(config_file);
+
+    if let Some(sub_matches) = matches.subcommand_matches("export") {
+        #[allow(unused_mut)]
+        let mut overrides = std::collections::BTreeMap::new();
+
+        // the overrides are only known to a running daemon
+        #[cfg(feature = "dbus")]
+        match dbus_interface::query_params() {
+            Ok(params) => {
+                for (script, name, value) in params {
+                    overrides.insert(format!("{}.{}", script, name), value);
+                }
+            }
+
+            Err(e) => warn!("Could not query the overridden parameters: {}", e),
+        }
+
+        let file = sub_matches.value_of("file").unwrap();
+
+        snapshot::export(file, config_file, overrides).unwrap_or_else(|e| {
+            error!("Could not export the snapshot: {}", e);
+            process::exit(1);
+        });
+
+        println!("Exported the configuration to: {}", file);
+    } else if let Some(sub_matches) = matches.subcommand_matches("import") {
+        let imported = snapshot::import(sub_matches.value_of("file").unwrap(), config_file)
+            .unwrap_or_else(|e| {
+                error!("Could not import the snapshot: {}", e);
+                process::exit(1);
+            });
+
+        println!(
+            "Imported a snapshot of Eruption {}, created {}",
+            imported.info.version, imported.info.created
+        );
+
+        for file in imported.files {
+            println!("  {}", file.display());
+        }
+
+        println!("Please restart the daemon, to apply the imported configuration");
+
+        // overrides are not persistent, they are only displayed to be reapplied
+        if !imported.info.overrides.is_empty() {
+            println!("Parameters that were overridden, apply them with 'eruption param set':");
+
+            for (param, value) in imported.info.overrides {
+                println!("  {} {}", param, value);
+            }
+        }
+    } else {
+        error!("Please specify a command, either 'export' or 'import'");
+        process::exit(1);
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_plugins_command(matches: &clap::ArgMatches) {
+    let (name, enabled) = if let Some(sub_matches) = matches.subcommand_matches("enable") {
+        (sub_matches.value_of("name").unwrap(), true)
+    } else if let Some(sub_matches) = matches.subcommand_matches("disable") {
+        (sub_matches.value_of("name").unwrap(), false)
+    } else {
+        match dbus_interface::query_plugins() {
+            Ok(plugins) => {
+                for (name, description, enabled) in plugins {
+                    let state = if enabled { "enabled" } else { "disabled" };
+                    println!("{:<16} {:<9} {}", name, state, description);
+                }
+            }
+
+            Err(e) => {
+                error!("Could not query the plugins: {}", e);
+                process::exit(1);
+            }
+        }
+
+        return;
+    };
+
+    match dbus_interface::set_plugin_enabled(name, enabled) {
+        Ok(true) => (),
+        Ok(false) => println!("Nothing to do"),
+
+        Err(e) => {
+            error!("Could not change the state of the plugin: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_alerts_command(matches: &clap::ArgMatches) {
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+
+    if let Some(sub_matches) = matches.subcommand_matches("schedule") {
+        let mut params = HashMap::new();
+
+        for name in &["color", "duration"] {
+            if let Some(value) = sub_matches.value_of(name) {
+                params.insert(name.to_string(), value.to_string());
+            }
+        }
+
+        match dbus_interface::schedule_alert(
+            sub_matches.value_of("time").unwrap(),
+            sub_matches.value_of("effect").unwrap(),
+            &params,
+        ) {
+            Ok(id) => println!("Scheduled alert {}", id),
+
+            Err(e) => {
+                error!("Could not schedule the alert: {}", e);
+                process::exit(1);
+            }
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("cancel") {
+        let id = match sub_matches.value_of("id").unwrap().parse::<u32>() {
+            Ok(id) => id,
+
+            Err(_) => {
+                error!("Invalid id");
+                process::exit(1);
+            }
+        };
+
+        match dbus_interface::cancel_alert(id) {
+            Ok(true) => (),
+            Ok(false) => println!("No such alert"),
+
+            Err(e) => {
+                error!("Could not cancel the alert: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        match dbus_interface::query_alerts() {
+            Ok(alerts) => {
+                for (id, at, effect, duration_secs) in alerts {
+                    let at = chrono::Local.timestamp(at, 0);
+
+                    println!(
+                        "{:>4} {} {} ({} seconds)",
+                        id,
+                        at.format("%Y-%m-%d %H:%M:%S"),
+                        effect,
+                        duration_secs
+                    );
+                }
+            }
+
+            Err(e) => {
+                error!("Could not query the alerts: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_identify_command() {
+    dbus_interface::start_identify().unwrap_or_else(|e| {
+        error!("Could not enter the identify mode: {}", e);
+        process::exit(1);
+    });
+
+    println!("Press a key to identify it, press ESC to quit");
+
+    let mut seq = 0;
+
+    loop {
+        match dbus_interface::query_identified_key() {
+            Ok((false, ..)) => break,
+
+            Ok((true, key_seq, index, code, name)) if key_seq != seq => {
+                seq = key_seq;
+
+                let (row, column) = identify::get_key_location(index as usize);
+
+                println!(
+                    "Key index {} (LED {}): {} (code {}), row {}, column {}",
+                    index + 1,
+                    index,
+                    name,
+                    code,
+                    row,
+                    column
+                );
+            }
+
+            Ok(_) => (),
+
+            Err(e) => {
+                error!("Could not query the identified key: {}", e);
+                process::exit(1);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(constants::IDENTIFY_POLL_MILLIS));
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_active_scripts_command() {
+    match dbus_interface::query_active_scripts() {
+        Ok(scripts) => {
+            for (name, capabilities) in scripts {
+                println!("{}: {}", name, capabilities);
+            }
+        }
+
+        Err(e) => {
+            error!("Could not query the active scripts: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_log_filter_command(filter: Option<&str>) {
+    match filter {
+        Some(filter) => {
+            dbus_interface::change_log_filter(filter).unwrap_or_else(|e| {
+                error!("Could not change the log filter: {}", e);
+                process::exit(1);
+            });
+        }
+
+        None => match dbus_interface::query_log_filter() {
+            Ok(filter) => println!("{}", filter),
+
+            Err(e) => {
+                error!("Could not query the log filter: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// Override or clear parameters of scripts of a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn run_param_command(matches: &clap::ArgMatches) {
+    if let Some(sub_matches) = matches.subcommand_matches("set") {
+        let param = sub_matches.value_of("param").unwrap();
+        let value = sub_matches.value_of("value").unwrap();
+
+        let (script, name) = scripting::overrides::parse_name(param).unwrap_or_else(|e| {
+            error!("{}", e);
+            process::exit(1);
+        });
+
+        dbus_interface::set_param(script, name, value, sub_matches.is_present("persist"))
+            .unwrap_or_else(|e| {
+                error!("Could not set the parameter: {}", e);
+                process::exit(1);
+            });
+    } else if let Some(sub_matches) = matches.subcommand_matches("clear") {
+        let script = sub_matches.value_of("script").unwrap_or("");
+
+        dbus_interface::clear_params(script).unwrap_or_else(|e| {
+            error!("Could not clear the parameters: {}", e);
+            process::exit(1);
+        });
+    } else {
+        error!("Please specify a command, either 'set' or 'clear'");
+        process::exit(1);
+    }
+}
+
+/// Display or control the script layers of a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn run_layers_command(matches: &clap::ArgMatches) {
+    let parse_number = |value: Option<&str>, name: &str| {
+        value
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or_else(|| {
+                error!("Invalid {}", name);
+                process::exit(1);
+            })
+    };
+
+    if let Some(sub_matches) = matches.subcommand_matches("opacity") {
+        let layer = parse_number(sub_matches.value_of("layer"), "layer");
+        let percent = parse_number(sub_matches.value_of("percent"), "opacity");
+        let fade = parse_number(sub_matches.value_of("fade"), "duration of the fade");
+
+        dbus_interface::set_layer_opacity(layer, percent, fade).unwrap_or_else(|e| {
+            error!("Could not set the opacity of the layer: {}", e);
+            process::exit(1);
+        });
+    } else if let Some((name, sub_matches)) = ["enable", "disable"]
+        .iter()
+        .find_map(|name| matches.subcommand_matches(name).map(|m| (*name, m)))
+    {
+        let layer = parse_number(sub_matches.value_of("layer"), "layer");
+
+        dbus_interface::set_layer_enabled(layer, name == "enable").unwrap_or_else(|e| {
+            error!("Could not {} the layer: {}", name, e);
+            process::exit(1);
+        });
+    } else {
+        match dbus_interface::query_layers() {
+            Ok(layers) => {
+                for (layer, script, opacity, enabled) in layers {
+                    println!(
+                        "{:>2}: {} ({}%{})",
+                        layer,
+                        script,
+                        opacity,
+                        if enabled { "" } else { ", bypassed" }
+                    );
+                }
+            }
+
+            Err(e) => {
+                error!("Could not query the layers: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Display or change the do-not-disturb mode of a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn run_dnd_command(state: Option<&str>) {
+    let enable = match state {
+        Some("on") => true,
+        Some("off") => false,
+
+        Some(_) => !dbus_interface::query_dnd().unwrap_or_else(|e| {
+            error!("Could not query the do-not-disturb mode: {}", e);
+            process::exit(1);
+        }),
+
+        None => {
+            match dbus_interface::query_dnd() {
+                Ok(true) => println!("on"),
+                Ok(false) => println!("off"),
+
+                Err(e) => {
+                    error!("Could not query the do-not-disturb mode: {}", e);
+                    process::exit(1);
+                }
+            }
+
+            return;
+        }
+    };
+
+    dbus_interface::change_dnd(enable).unwrap_or_else(|e| {
+        error!("Could not change the do-not-disturb mode: {}", e);
+        process::exit(1);
+    });
+}
+
+#[cfg(feature = "dbus")]
+fn run_active_app_command(class: Option<&str>, fullscreen: bool) {
+    match class {
+        Some(class) => {
+            dbus_interface::set_active_app(class)
+                .and_then(|_| dbus_interface::set_fullscreen(fullscreen))
+                .unwrap_or_else(|e| {
+                    error!("Could not report the active application: {}", e);
+                    process::exit(1);
+                });
+        }
+
+        None => match dbus_interface::query_active_app() {
+            Ok(class) => println!("{}", class),
+
+            Err(e) => {
+                error!("Could not query the active application: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_accent_color_command(color: Option<&str>) {
+    match color {
+        Some(color) => {
+            dbus_interface::set_accent_color(color).unwrap_or_else(|e| {
+                error!("Could not report the accent color: {}", e);
+                process::exit(1);
+            });
+        }
+
+        None => match dbus_interface::query_accent_color() {
+            Ok(0) => println!("unknown"),
+            Ok(color) => println!("#{:06x}", color & 0x00ff_ffff),
+
+            Err(e) => {
+                error!("Could not query the accent color: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_color_temperature_command(kelvin: Option<&str>) {
+    match kelvin {
+        Some(kelvin) => {
+            let kelvin = kelvin
+                .trim_end_matches('K')
+                .parse::<u32>()
+                .unwrap_or_else(|e| {
+                    error!("Invalid color temperature: {}", e);
+                    process::exit(1);
+                });
+
+            dbus_interface::set_color_temperature(kelvin).unwrap_or_else(|e| {
+                error!("Could not report the color temperature: {}", e);
+                process::exit(1);
+            });
+        }
+
+        None => match dbus_interface::query_color_temperature() {
+            Ok(kelvin) => println!("{} K", kelvin),
+
+            Err(e) => {
+                error!("Could not query the color temperature: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// Display or report the power state of the displays to a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn run_display_command(state: Option<&str>) {
+    match state {
+        Some(state) => {
+            dbus_interface::set_display_blanked(state == "off").unwrap_or_else(|e| {
+                error!("Could not report the power state of the displays: {}", e);
+                process::exit(1);
+            });
+        }
+
+        None => match dbus_interface::query_display_blanked() {
+            Ok(blanked) => println!("{}", if blanked { "off" } else { "on" }),
+
+            Err(e) => {
+                error!("Could not query the power state of the displays: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// Print the latency percentiles of the input path of a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn print_latency_metrics() {
+    match dbus_interface::query_latency_metrics() {
+        Ok(metrics) if metrics.values().all(|v| *v == 0) => {
+            println!("No samples, please enable latency tracing in the [tracing] section of eruption.conf")
+        }
+
+        Ok(metrics) => {
+            println!(
+                "{:<10} {:>8} {:>10} {:>10} {:>10} {:>10}",
+                "Stage", "Samples", "p50 µs", "p95 µs", "p99 µs", "max µs"
+            );
+
+            for stage in &["dispatch", "write"] {
+                let get = |name: &str| {
+                    metrics
+                        .get(&format!("{}_{}", stage, name))
+                        .copied()
+                        .unwrap_or(0)
+                };
+
+                println!(
+                    "{:<10} {:>8} {:>10} {:>10} {:>10} {:>10}",
+                    stage,
+                    get("samples"),
+                    get("p50_us"),
+                    get("p95_us"),
+                    get("p99_us"),
+                    get("max_us")
+                );
+            }
+        }
+
+        Err(e) => {
+            error!("Could not query the latency of the input path: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Print the most recent log lines of a running instance of Eruption
+#[cfg(feature = "dbus")]
+fn print_log_lines(count: u32) {
+    match dbus_interface::query_log_lines(count) {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+
+        Err(e) => {
+            error!("Could not query log lines: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn print_paused_script() {
+    match dbus_interface::query_paused() {
+        Ok((true, script, line, variables, locals)) => {
+            println!("Paused: {} at line {}", script, line);
+
+            for (name, value) in locals {
+                println!("  local {} = {}", name, value);
+            }
+
+            for (name, value) in variables {
+                println!("  {} = {}", name, value);
+            }
+        }
+
+        Ok((false, ..)) => println!("No script is paused"),
+
+        Err(e) => {
+            error!("Could not query the paused script: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn run_debug_command(matches: &clap::ArgMatches) {
+    let parse_line = |line: Option<&str>| {
+        line.map(|line| line.parse::<u32>())
+            .unwrap_or(Ok(0))
+            .unwrap_or_else(|_| {
+                error!("Invalid line number");
+                process::exit(1);
+            })
+    };
+
+    if let Some(sub_matches) = matches.subcommand_matches("break") {
+        let script = sub_matches.value_of("script").unwrap();
+        let line = parse_line(sub_matches.value_of("line"));
 
-    /// Global "keyboard brightness" modifier
-    pub static ref BRIGHTNESS: AtomicIsize = AtomicIsize::new(100);
+        match dbus_interface::set_breakpoint(script, line) {
+            Ok(true) => (),
+            Ok(false) => println!("Nothing to do"),
 
-    static ref LUA_TXS: Arc<Mutex<Vec<Sender<script::Message>>>> = Arc::new(Mutex::new(vec![]));
-}
+            Err(e) => {
+                error!("Could not set the breakpoint: {}", e);
+                process::exit(1);
+            }
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("clear") {
+        let script = sub_matches.value_of("script").unwrap();
+        let line = parse_line(sub_matches.value_of("line"));
 
-pub type Result<T> = std::result::Result<T, MainError>;
+        match dbus_interface::clear_breakpoint(script, line) {
+            Ok(true) => (),
+            Ok(false) => println!("No such breakpoint"),
 
-#[derive(Debug, Fail)]
-pub enum MainError {
-    #[fail(display = "Could not spawn a thread")]
-    ThreadSpawnError {},
+            Err(e) => {
+                error!("Could not clear the breakpoint: {}", e);
+                process::exit(1);
+            }
+        }
+    } else if matches.subcommand_matches("status").is_some() {
+        print_paused_script();
+    } else if let Some(command) = matches
+        .subcommand_name()
+        .filter(|name| *name == "continue" || *name == "step")
+    {
+        match dbus_interface::resume_script(command) {
+            Ok(true) => {
+                if command == "step" {
+                    // give the script the chance to reach the next line
+                    thread::sleep(Duration::from_millis(constants::DEBUGGER_STEP_WAIT_MILLIS));
+                    print_paused_script();
+                }
+            }
 
-    #[fail(display = "Could not switch profiles")]
-    SwitchProfileError {},
+            Ok(false) => println!("No script is paused"),
 
-    #[fail(display = "Could not execute Lua script")]
-    ScriptExecError {},
-    // #[fail(display = "Unknown error: {}", description)]
-    // UnknownError { description: String },
-}
+            Err(e) => {
+                error!("Could not resume the script: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        match dbus_interface::query_breakpoints() {
+            Ok(breakpoints) => {
+                for (script, line) in breakpoints {
+                    println!("{}:{}", script, line);
+                }
+            }
 
-#[derive(Debug)]
-pub enum FileSystemEvent {
-    ProfilesChanged,
-    ScriptsChanged,
+            Err(e) => {
+                error!("Could not query the breakpoints: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 }
 
-fn print_header() {
-    println!(
-        r#"
- Eruption is free software: you can redistribute it and/or modify
- it under the terms of the GNU General Public License as published by
- the Free Software Foundation, either version 3 of the License, or
- (at your option) any later version.
-
- Eruption is distributed in the hope that it will be useful,
- but WITHOUT ANY WARRANTY; without even the implied warranty of
- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
- GNU General Public License for more details.
+#[cfg(feature = "dbus")]
+fn run_errors_command(matches: &clap::ArgMatches) {
+    if matches.subcommand_matches("ack").is_some() {
+        match dbus_interface::acknowledge_errors() {
+            Ok(count) => println!("Acknowledged {} error(s)", count),
 
- You should have received a copy of the GNU General Public License
- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
-"#
-    );
-}
+            Err(e) => {
+                error!("Could not acknowledge the errors: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        match dbus_interface::query_errors() {
+            Ok(errors) => {
+                for line in errors {
+                    println!("{}", line);
+                }
+            }
 
-/// Process commandline options
-fn parse_commandline<'a>() -> clap::ArgMatches<'a> {
-    App::new("Eruption")
-        .version("0.1.1")
-        .author("X3n0m0rph59 <x3n0m0rph59@gmail.com>")
-        .about("Linux user-mode driver for the ROCCAT Vulcan 100/12x series keyboards")
-        .arg(
-            Arg::with_name("config")
-                .short("c")
-                .long("config")
-                .value_name("FILE")
-                .help("Sets the configuration file to use")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("profile")
-                .short("p")
-                .long("profile")
-                .value_name("profile")
-                .help("Sets the profile to activate")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("scripts")
-                .help("The Lua scripts to execute")
-                .multiple(true)
-                // .required(true)
-                .index(1),
-        )
-        .arg(
-            Arg::with_name("v")
-                .short("v")
-                .multiple(true)
-                .help("Sets the level of verbosity"),
-        )
-        .subcommand(App::new("list-scripts").about("Display a listing of all available scripts"))
-        .subcommand(
-            App::new("check-syntax")
-                .about("Validate a Lua script for syntactical correctness")
-                .arg(
-                    Arg::with_name("script")
-                        .help("The Lua script to check")
-                        .required(true)
-                        .index(1),
-                ),
-        )
-        .get_matches()
+            Err(e) => {
+                error!("Could not query the errors: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 }
 
 /// Spawns the web-frontend thread
@@ -217,29 +1470,48 @@ fn spawn_frontend_thread(
 pub enum DbusApiEvent {
     ProfilesChanged,
     ActiveProfileChanged,
+    DeviceError(usize, String),
+    DeviceRecovered(usize),
 }
 
 /// Spawns the dbus thread and executes it's main loop
 #[cfg(feature = "dbus")]
 fn spawn_dbus_thread(
     dbus_tx: Sender<dbus_interface::Message>,
+    device_info: Option<rvdevice::DeviceInfo>,
 ) -> plugins::Result<Sender<DbusApiEvent>> {
     let (dbus_api_tx, dbus_api_rx) = channel();
 
     let builder = thread::Builder::new().name("dbus".into());
     builder
         .spawn(move || -> Result<()> {
-            let dbus =
-                dbus_interface::initialize(dbus_tx).map_err(|_e| MainError::ThreadSpawnError {})?;
+            let dbus = dbus_interface::initialize(dbus_tx, device_info).map_err(|e| {
+                error!("Could not initialize the D-Bus API: {}", e);
+                MainError::ThreadSpawnError {}
+            })?;
 
             loop {
                 // process events, destined for the dbus api
                 match dbus_api_rx.recv_timeout(Duration::from_millis(0)) {
-                    Ok(result) => match result {
-                        DbusApiEvent::ProfilesChanged => dbus.notify_profiles_changed(),
+                    Ok(result) => {
+                        let result = match result {
+                            DbusApiEvent::ProfilesChanged => dbus.notify_profiles_changed(),
 
-                        DbusApiEvent::ActiveProfileChanged => dbus.notify_active_profile_changed(),
-                    },
+                            DbusApiEvent::ActiveProfileChanged => {
+                                dbus.notify_active_profile_changed()
+                            }
+
+                            DbusApiEvent::DeviceError(device, description) => {
+                                dbus.notify_device_error(device, &description)
+                            }
+
+                            DbusApiEvent::DeviceRecovered(device) => {
+                                dbus.notify_device_recovered(device)
+                            }
+                        };
+
+                        result.unwrap_or_else(|e| error!("Could not emit a D-Bus signal: {}", e));
+                    }
 
                     // ignore timeout errors
                     Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
@@ -259,71 +1531,110 @@ fn spawn_dbus_thread(
     Ok(dbus_api_tx)
 }
 
-/// Spawns the keyboard events thread and executes it's main loop
-fn spawn_input_thread(kbd_tx: Sender<Option<evdev_rs::InputEvent>>) -> plugins::Result<()> {
-    let builder = thread::Builder::new().name("events".into());
+/// Spawns the keyboard events thread, the keyboard is the primary input source
+fn spawn_input_thread(kbd_tx: Sender<Option<inputs::SourceEvent>>) -> inputs::Result<()> {
+    inputs::spawn(
+        "events",
+        || {
+            scheduling::apply("events");
+
+            let source = inputs::KeyboardSource::open().unwrap_or_else(|e| {
+                error!("Could not initialize the keyboard plugin: {}", e);
+
+                if let plugins::keyboard::KeyboardPluginError::GrabError { .. } = e {
+                    error!("Another program may have grabbed the keyboard, please terminate it or enable shared mode in eruption.conf");
+                }
+
+                panic!()
+            });
+
+            Ok(Box::new(source) as Box<dyn inputs::InputSource>)
+        },
+        kbd_tx,
+    )
+}
+
+/// Spawns the thread that reads the HID reports of the special keys of the
+/// device, like FN and Easy Shift, and forwards them to the Lua VMs. Reads time
+/// out periodically, so that the thread never blocks on an unresponsive device
+fn spawn_special_keys_thread(ctrl_dev: hidapi::HidDevice) -> plugins::Result<()> {
+    let builder = thread::Builder::new().name("special-keys".into());
     builder
-        .spawn(move || {
-            {
-                // initialize thread local state of the keyboard plugin
-                let mut plugin_manager = plugin_manager::PLUGIN_MANAGER.write();
-                let keyboard_plugin = plugin_manager
-                    .find_plugin_by_name_mut("Keyboard".to_string())
-                    .unwrap_or_else(|| {
-                        error!("Could not find a required plugin");
-                        panic!()
-                    })
-                    .as_any_mut()
-                    .downcast_mut::<plugins::KeyboardPlugin>()
-                    .unwrap();
-
-                keyboard_plugin
-                    .initialize_thread_locals()
-                    .unwrap_or_else(|e| {
-                        error!("Could not initialize the keyboard plugin: {}", e);
-                        panic!()
-                    })
-            }
-
-            let plugin_manager = plugin_manager::PLUGIN_MANAGER.read();
-            let keyboard_plugin = plugin_manager
-                .find_plugin_by_name("Keyboard".to_string())
-                .unwrap_or_else(|| {
-                    error!("Could not find a required plugin");
-                    panic!()
-                })
-                .as_any()
-                .downcast_ref::<plugins::KeyboardPlugin>()
-                .unwrap();
+        .spawn(move || loop {
+            if QUIT.load(Ordering::SeqCst) {
+                break;
+            }
 
-            loop {
-                if let Ok(event) = keyboard_plugin.get_next_event() {
-                    kbd_tx.send(event).unwrap_or_else(|e| {
-                        error!("Could not send a keyboard event to the main thread: {}", e)
-                    });
-                } else {
-                    // ignore spurious events
-                    // error!("Could not get next keyboard event");
+            let mut buf: [u8; 8] = [0; 8];
+
+            match ctrl_dev.read_timeout(&mut buf, constants::DEVICE_READ_TIMEOUT_MILLIS) {
+                // the read timed out, no special key has been pressed
+                Ok(0) => continue,
+
+                Ok(len) => {
+                    hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
+
+                    capture::record(
+                        capture::Interface::Control,
+                        capture::Kind::Read,
+                        &buf[..len.min(buf.len())],
+                    );
+
+                    let event = match rvdevice::SpecialKeyEvent::from_report(&buf) {
+                        Some(event) => event,
+                        None => continue,
+                    };
+
+                    debug!("Special key event: {:?}", event);
+
+                    match event {
+                        rvdevice::SpecialKeyEvent::EasyShift(down) => {
+                            hotkeys::set_key_state(constants::EASY_SHIFT_KEY_CODE, down)
+                        }
+
+                        rvdevice::SpecialKeyEvent::FnKey(down) => {
+                            hotkeys::set_key_state(evdev_rs::enums::EV_KEY::KEY_FN as u32, down)
+                        }
+                    }
+
+                    for lua_tx in LUA_TXS.lock().iter() {
+                        let message = match event {
+                            rvdevice::SpecialKeyEvent::EasyShift(down) => {
+                                script::Message::EasyShift(down)
+                            }
+
+                            rvdevice::SpecialKeyEvent::FnKey(down) => script::Message::FnKey(down),
+                        };
+
+                        lua_tx
+                            .send(message)
+                            .unwrap_or_else(|e| error!("Send error: {}", e));
+                    }
+                }
+
+                Err(e) => {
+                    error!("Could not read from the control device: {}", e);
+                    break;
                 }
             }
         })
-        .unwrap_or_else(|e| {
-            error!("Could not spawn a thread: {}", e);
-            panic!()
-        });
+        .map_err(|_e| MainError::ThreadSpawnError {})?;
 
     Ok(())
 }
 
 fn spawn_lua_thread(
     thread_idx: usize,
+    lua_tx: Sender<script::Message>,
     lua_rx: Receiver<script::Message>,
     script_path: PathBuf,
-    rvdevice: &RvDeviceState,
+    rvdevices: &[RvDeviceState],
+    target_device: usize,
 ) -> Result<()> {
     let result = util::is_file_accessible(&script_path);
     if let Err(result) = result {
         error!(
+            target: logging::SCRIPT_ERRORS,
             "Script file '{}' is not accessible: {}",
             script_path.display(),
             result
@@ -335,6 +1646,7 @@ fn spawn_lua_thread(
     let result = util::is_file_accessible(util::get_manifest_for(&script_path));
     if let Err(result) = result {
         error!(
+            target: logging::SCRIPT_ERRORS,
             "Manifest file for script '{}' is not accessible: {}",
             script_path.display(),
             result
@@ -343,7 +1655,7 @@ fn spawn_lua_thread(
         return Err(MainError::ScriptExecError {});
     }
 
-    let rvdevice = rvdevice.clone();
+    let rvdevices = rvdevices.to_vec();
 
     let builder = thread::Builder::new().name(format!(
         "{}:{}",
@@ -353,10 +1665,37 @@ fn spawn_lua_thread(
     builder
         .spawn(move || -> Result<()> {
             loop {
-                let rvdevice = rvdevice.clone();
+                let rvdevices = rvdevices.clone();
+
+                let result = script::run_script(
+                    script_path.clone(),
+                    rvdevices,
+                    target_device,
+                    thread_idx,
+                    &lua_tx,
+                    &lua_rx,
+                )
+                .map_err(|e| {
+                    match e {
+                        // these errors have already been counted by `run_script`
+                        script::ScriptingError::LuaError { .. }
+                        | script::ScriptingError::InaccessibleManifest {}
+                        | script::ScriptingError::UnsupportedApiLevel { .. } => error!(
+                            "Could not execute the script '{}': {}",
+                            script_path.display(),
+                            e
+                        ),
+
+                        _ => error!(
+                            target: logging::SCRIPT_ERRORS,
+                            "Could not execute the script '{}': {}",
+                            script_path.display(),
+                            e
+                        ),
+                    }
 
-                let result = script::run_script(script_path.clone(), rvdevice, &lua_rx)
-                    .map_err(|_e| MainError::ScriptExecError {})?;
+                    MainError::ScriptExecError {}
+                })?;
 
                 match result {
                     //script::RunScriptResult::ReExecuteOtherScript(script_file) => {
@@ -374,38 +1713,59 @@ fn spawn_lua_thread(
     Ok(())
 }
 
+/// Find the index of the device that the script `script_file` renders to,
+/// as specified in the `devices` table of the profile `profile`
+fn get_target_device(
+    profile: Option<&profiles::Profile>,
+    script_file: &Path,
+    rvdevices: &[RvDeviceState],
+) -> usize {
+    let selector = profile
+        .and_then(|p| p.devices.as_ref())
+        .and_then(|devices| devices.get(&*script_file.to_string_lossy()));
+
+    match selector {
+        Some(selector) => rvdevice::find_device(rvdevices, selector).unwrap_or_else(|| {
+            warn!(
+                "Device '{}' not found, script '{}' will render to the default device",
+                selector,
+                script_file.display()
+            );
+
+            0
+        }),
+
+        None => 0,
+    }
+}
+
 /// Switches the currently active profile to the profile file `profile_path`
 fn switch_profile<P: AsRef<Path>>(
     profile_file: P,
-    rvdevice: &RvDeviceState,
+    rvdevices: &[RvDeviceState],
     #[cfg(feature = "dbus")] dbus_api_tx: &Sender<DbusApiEvent>,
 ) -> Result<()> {
-    let script_dir = PathBuf::from(
-        CONFIG
-            .lock()
-            .as_ref()
-            .unwrap()
-            .get_str("global.script_dir")
-            .unwrap_or_else(|_| constants::DEFAULT_SCRIPT_DIR.to_string()),
-    );
-
-    let profile_dir = PathBuf::from(
-        CONFIG
-            .lock()
-            .as_ref()
-            .unwrap()
-            .get_str("global.profile_dir")
-            .unwrap_or_else(|_| constants::DEFAULT_PROFILE_DIR.to_string()),
-    );
-
-    let profile_path = profile_dir.join(&profile_file);
+    let profile_path = paths::find_profile(&profile_file).map_err(|e| {
+        error!("{}", e);
+        MainError::SwitchProfileError {}
+    })?;
     let profile =
         profiles::Profile::from(&profile_path).map_err(|_e| MainError::SwitchProfileError {})?;
 
-    // verify script files first; better fail early if we can
-    let script_files = profile.active_scripts.clone();
+    // verify script files first; better fail early if we can. Scripts that
+    // have been disabled by a rule are skipped
+    let script_files: Vec<PathBuf> = profile
+        .active_scripts
+        .iter()
+        .filter(|f| !rules::is_script_disabled(f))
+        .cloned()
+        .collect();
+    let mut script_paths = Vec::new();
     for script_file in script_files.iter() {
-        let script_path = script_dir.join(&script_file);
+        let script_path = paths::find_script(&script_file).map_err(|e| {
+            error!("{}", e);
+            MainError::SwitchProfileError {}
+        })?;
 
         if !util::is_script_file_accessible(&script_path)
             || !util::is_manifest_file_accessible(&script_path)
@@ -416,8 +1776,13 @@ fn switch_profile<P: AsRef<Path>>(
             );
             return Err(MainError::SwitchProfileError {});
         }
+
+        script_paths.push(script_path);
     }
 
+    // the last frame of the old profile is faded out
+    transitions::start();
+
     // now request termination of all Lua VMs
     let mut lua_txs = LUA_TXS.lock();
 
@@ -430,36 +1795,253 @@ fn switch_profile<P: AsRef<Path>>(
     // be safe and clear any leftover channels
     lua_txs.clear();
 
-    // now spawn a new set of Lua VMs, with scripts from the new profile
-    for (thread_idx, script_file) in script_files.iter().enumerate() {
-        let script_path = script_dir.join(&script_file);
+    // frames of the unloaded scripts must not be composed any more
+    script::clear_layers();
+    scripting::ticks::clear_tick_states();
+
+    // now spawn a new set of Lua VMs, with scripts from the new profile
+    for (thread_idx, (script_file, script_path)) in
+        script_files.iter().zip(script_paths.iter()).enumerate()
+    {
+        let target_device = get_target_device(Some(&profile), script_file, rvdevices);
+
+        let (lua_tx, lua_rx) = channel();
+        spawn_lua_thread(
+            thread_idx,
+            lua_tx.clone(),
+            lua_rx,
+            script_path.clone(),
+            rvdevices,
+            target_device,
+        )
+        .unwrap_or_else(|e| {
+            error!("Could not spawn a thread: {}", e);
+        });
+
+        lua_txs.push(lua_tx);
+    }
+
+    // finally assign the globally active profile
+    accessibility::apply_profile(&profile);
+    postprocessing::apply_profile(&profile);
+    overlay::apply_profile(&profile);
+    hotkeys::apply_profile(&profile);
+    *ACTIVE_PROFILE.lock() = Some(profile);
+
+    #[cfg(feature = "dbus")]
+    dbus_api_tx
+        .send(DbusApiEvent::ActiveProfileChanged)
+        .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+
+    Ok(())
+}
+
+/// Switches to the next or previous profile in the profile directory,
+/// in the order of their file names
+fn cycle_profile(
+    forward: bool,
+    rvdevices: &[RvDeviceState],
+    #[cfg(feature = "dbus")] dbus_api_tx: &Sender<DbusApiEvent>,
+) -> Result<()> {
+    let mut profile_files = paths::get_profile_files();
+    profile_files.sort_by(|lhs, rhs| lhs.file_name().cmp(&rhs.file_name()));
+
+    if profile_files.is_empty() {
+        return Err(MainError::SwitchProfileError {});
+    }
+
+    let current = ACTIVE_PROFILE.lock().as_ref().and_then(|p| {
+        profile_files
+            .iter()
+            .position(|f| f.file_name() == p.profile_file.file_name())
+    });
+
+    let index = match current {
+        Some(index) if forward => (index + 1) % profile_files.len(),
+        Some(index) => (index + profile_files.len() - 1) % profile_files.len(),
+        None => 0,
+    };
+
+    info!("Loading Profile: {}", profile_files[index].display());
+
+    switch_profile(
+        &profile_files[index],
+        rvdevices,
+        #[cfg(feature = "dbus")]
+        &dbus_api_tx,
+    )
+}
+
+/// Executes the action that is bound to a hotkey
+fn run_hotkey_action(
+    action: &hotkeys::Action,
+    rvdevices: &[RvDeviceState],
+    #[cfg(feature = "dbus")] dbus_api_tx: &Sender<DbusApiEvent>,
+) {
+    debug!("Executing hotkey action: {:?}", action);
+
+    match action {
+        hotkeys::Action::NextProfile | hotkeys::Action::PreviousProfile => cycle_profile(
+            *action == hotkeys::Action::NextProfile,
+            rvdevices,
+            #[cfg(feature = "dbus")]
+            &dbus_api_tx,
+        )
+        .unwrap_or_else(|e| error!("Could not switch profiles: {}", e)),
+
+        hotkeys::Action::BrightnessUp | hotkeys::Action::BrightnessDown => {
+            let step = if *action == hotkeys::Action::BrightnessUp {
+                constants::HOTKEY_BRIGHTNESS_STEP
+            } else {
+                -constants::HOTKEY_BRIGHTNESS_STEP
+            };
+
+            let brightness = (BRIGHTNESS.load(Ordering::SeqCst) + step).max(0).min(100);
+            BRIGHTNESS.store(brightness, Ordering::SeqCst);
+
+            info!("Brightness: {}%", brightness);
+        }
+
+        hotkeys::Action::ToggleDnd => {
+            DO_NOT_DISTURB.fetch_xor(true, Ordering::SeqCst);
+        }
+
+        hotkeys::Action::LayerOpacity {
+            layer,
+            percent,
+            fade_millis,
+        } => {
+            script::set_layer_opacity(*layer, *percent, Duration::from_millis(*fade_millis));
+
+            info!("Opacity of layer {}: {}%", layer, percent);
+        }
+
+        hotkeys::Action::ToggleLayer(layer) => {
+            let enabled = !script::is_layer_enabled(*layer);
+            script::set_layer_enabled(*layer, enabled);
+
+            info!(
+                "Layer {} is {}",
+                layer,
+                if enabled { "enabled" } else { "bypassed" }
+            );
+        }
+
+        hotkeys::Action::FadeLayer { layer, fade_millis } => {
+            let percent = if script::get_layer_opacity(*layer) > 0 {
+                0
+            } else {
+                100
+            };
+
+            script::set_layer_opacity(*layer, percent, Duration::from_millis(*fade_millis));
+
+            info!("Fading layer {} to {}%", layer, percent);
+        }
+
+        hotkeys::Action::Macro {
+            sequence,
+            pacing_millis,
+        } => {
+            for combination in sequence.iter() {
+                // the modifiers of the hotkey itself are still held down, they
+                // must not alter the keys of the macro
+                if let Some((key, modifiers)) = combination.split_last() {
+                    macros::queue_injection(macros::Message::InjectCombination {
+                        key: *key,
+                        modifiers: modifiers.to_vec(),
+                        neutralize: true,
+                        pacing_millis: *pacing_millis,
+                    });
+                }
+            }
+        }
+
+        hotkeys::Action::AbortMacros => macros::abort_macros(),
+    }
+}
+
+/// Performs an action of a rule of the rule engine
+fn run_rule_action(
+    action: &rules::Action,
+    rvdevices: &[RvDeviceState],
+    #[cfg(feature = "dbus")] dbus_api_tx: &Sender<DbusApiEvent>,
+) {
+    debug!("Executing rule action: {:?}", action);
+
+    // reload the active profile, so that the script is loaded or unloaded
+    let reload_profile = || {
+        let profile_file = ACTIVE_PROFILE
+            .lock()
+            .as_ref()
+            .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+        if let Some(profile_file) = profile_file {
+            switch_profile(
+                &profile_file,
+                rvdevices,
+                #[cfg(feature = "dbus")]
+                &dbus_api_tx,
+            )
+            .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+        }
+    };
+
+    match action {
+        rules::Action::SwitchProfile(profile_file) => switch_profile(
+            profile_file,
+            rvdevices,
+            #[cfg(feature = "dbus")]
+            &dbus_api_tx,
+        )
+        .unwrap_or_else(|e| error!("Could not switch profiles: {}", e)),
+
+        rules::Action::SetBrightness(brightness) => {
+            BRIGHTNESS.store(*brightness, Ordering::SeqCst);
+
+            info!("Brightness: {}%", brightness);
+        }
+
+        rules::Action::EnableScript(script_file) => {
+            if rules::set_script_disabled(script_file, false) {
+                reload_profile();
+            }
+        }
 
-        let (lua_tx, lua_rx) = channel();
-        spawn_lua_thread(thread_idx, lua_rx, script_path.clone(), &rvdevice).unwrap_or_else(|e| {
-            error!("Could not spawn a thread: {}", e);
-        });
+        rules::Action::DisableScript(script_file) => {
+            if rules::set_script_disabled(script_file, true) {
+                reload_profile();
+            }
+        }
 
-        lua_txs.push(lua_tx);
+        rules::Action::SetDnd(dnd) => {
+            DO_NOT_DISTURB.store(*dnd, Ordering::SeqCst);
+        }
     }
+}
 
-    // finally assign the globally active profile
-    *ACTIVE_PROFILE.lock() = Some(profile);
-
-    #[cfg(feature = "dbus")]
-    dbus_api_tx
-        .send(DbusApiEvent::ActiveProfileChanged)
-        .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+/// Get the state the LEDs are left in when we exit, or when the system suspends
+fn get_exit_state(config: &config::Config) -> rvdevice::ExitState {
+    let exit_state_name = config
+        .get_str("global.exit_state")
+        .unwrap_or_else(|_| "off".into());
+    let exit_color = config.get_int("global.exit_color").unwrap_or(0x000000) as u32;
 
-    Ok(())
+    rvdevice::ExitState::from_config(&exit_state_name, exit_color).unwrap_or_else(|| {
+        warn!("Invalid exit state specified: {}", exit_state_name);
+        rvdevice::ExitState::AllOff
+    })
 }
 
 #[allow(clippy::cognitive_complexity)]
 fn run_main_loop(
-    rvdevice: &mut RvDeviceState,
+    hidapi: &hidapi::HidApi,
+    rvdevices: &mut [RvDeviceState],
     #[cfg(feature = "dbus")] dbus_api_tx: &Sender<DbusApiEvent>,
     #[cfg(feature = "frontend")] frontend_rx: &Receiver<frontend::Message>,
     dbus_rx: &Receiver<dbus_interface::Message>,
-    kbd_rx: &Receiver<Option<evdev_rs::InputEvent>>,
+    kbd_rx: &Receiver<Option<inputs::SourceEvent>>,
+    inputs_rx: &Receiver<Option<inputs::SourceEvent>>,
     fsevents_rx: &Receiver<FileSystemEvent>,
 ) {
     trace!("Entering main loop...");
@@ -475,103 +2057,185 @@ fn run_main_loop(
 
     let mut start_time = Instant::now();
 
+    // do-not-disturb mode state
+    let mut dnd_active = false;
+
+    // reduces the tick rate, while the scripts can't keep up
+    let mut tick_rate = scripting::ticks::AdaptiveTickRate::new();
+
+    // the profile that was active before the session has been locked
+    #[cfg(feature = "dbus")]
+    let mut unlocked_profile: Option<PathBuf> = None;
+
+    // the profile that was active before a fullscreen application has been focused,
+    // and the profile that has been activated for the fullscreen application
+    #[cfg(feature = "dbus")]
+    let mut windowed_profile: Option<(PathBuf, PathBuf)> = None;
+
+    // the profile that was active before the displays have been blanked, and the
+    // profile that has been activated for the blanked displays
+    let mut unblanked_profile: Option<(PathBuf, PathBuf)> = None;
+
     // enter the main loop on the main thread
     'MAIN_LOOP: loop {
-        // prepare to call main loop hook
-        let plugin_manager = plugin_manager::PLUGIN_MANAGER.read();
-        let plugins = plugin_manager.get_plugins();
+        // call main loop hook of each registered plugin, that is due
+        plugin_manager::PLUGIN_MANAGER
+            .read()
+            .run_main_loop_hooks(ticks);
+
+        // send pending keyboard events to the Lua VMs and to the event dispatcher,
+        // events of the additional input sources are processed like keyboard events
+        let received = match kbd_rx.recv_timeout(Duration::from_millis(0)) {
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => inputs_rx
+                .try_recv()
+                .map_err(|_| std::sync::mpsc::RecvTimeoutError::Timeout),
+
+            result => result,
+        };
+
+        match received.map(|result| result.map(|event| (event.origin, event.event))) {
+            Ok(result) => match result {
+                // the chatter of worn switches is dropped before anything else
+                Some((_, raw_event)) if debounce::is_chatter(&raw_event) => {
+                    trace!("Key chatter dropped")
+                }
 
-        // call main loop hook of each registered plugin
-        for plugin in plugins.iter() {
-            plugin.main_loop_hook(ticks);
-        }
+                // in identify mode, key presses are only reported to the client
+                Some((_, raw_event)) if identify::process_event(&raw_event) => {
+                    trace!("Key event consumed by the identify mode")
+                }
 
-        // send pending keyboard events to the Lua VMs and to the event dispatcher
-        match kbd_rx.recv_timeout(Duration::from_millis(0)) {
-            Ok(result) => match result {
-                Some(raw_event) => {
-                    // notify all observers of raw events
-                    events::notify_observers(events::Event::RawKeyboardEvent(raw_event.clone()))
-                        .unwrap();
+                // while the modifier of the pointer keys is held down, they move
+                // the pointer of the virtual mouse
+                Some((origin, raw_event))
+                    if origin.is_consumable() && pointer_keys::process_event(&raw_event) =>
+                {
+                    trace!("Key event consumed by the pointer keys")
+                }
 
-                    if let evdev_rs::enums::EventCode::EV_KEY(ref code) = raw_event.event_code {
-                        let is_pressed = raw_event.value > 0;
-                        let index = util::ev_key_to_key_index(code.clone());
+                // key combinations that are bound to a hotkey are consumed here,
+                // they are neither reported to the Lua VMs nor mirrored. The same
+                // applies to the dial, unless it controls the volume
+                Some((origin, raw_event)) => match get_disposition(origin, &raw_event) {
+                    hotkeys::Disposition::Trigger(action) => {
+                        run_hotkey_action(
+                            &action,
+                            rvdevices,
+                            #[cfg(feature = "dbus")]
+                            &dbus_api_tx,
+                        );
+                    }
 
-                        trace!("Key index: {:#x}", index);
+                    hotkeys::Disposition::Consume => trace!("Hotkey event consumed"),
 
-                        if is_pressed {
-                            *UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() = LUA_TXS.lock().len();
+                    hotkeys::Disposition::Pass => {
+                        // notify all observers of raw events
+                        events::notify_observers(events::Event::RawKeyboardEvent(
+                            raw_event.clone(),
+                        ))
+                        .unwrap();
 
-                            for lua_tx in LUA_TXS.lock().iter() {
-                                lua_tx
-                                    .send(script::Message::KeyDown(index))
-                                    .unwrap_or_else(|e| {
-                                        error!("Could not send a pending keyboard event: {}", e)
-                                    });
+                        // keys without an LED are not reported to the Lua VMs, their
+                        // index would be 0
+                        let key_index = match raw_event.event_code {
+                            evdev_rs::enums::EventCode::EV_KEY(ref code) => {
+                                util::get_key_index(code.clone() as u32)
                             }
 
-                            // yield to thread
-                            //thread::sleep(Duration::from_millis(0));
+                            _ => None,
+                        };
+
+                        // only the keys of the keyboard are mirrored, and may get stuck
+                        if origin.is_mirrored() {
+                            stuck_keys::record_event(&raw_event);
+                        }
+
+                        if let Some(key_index) = key_index {
+                            let is_pressed = raw_event.value > 0;
+                            let index = key_index + 1;
 
-                            // wait until all Lua VMs completed the event handler
-                            loop {
-                                let mut pending = UPCALL_COMPLETED_ON_KEY_DOWN.0.lock();
+                            trace!("Key index: {:#x}", index);
 
-                                UPCALL_COMPLETED_ON_KEY_DOWN
-                                    .1
-                                    .wait_for(&mut pending, Duration::from_millis(50));
+                            if is_pressed {
+                                *UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() = LUA_TXS.lock().len();
 
-                                if *pending == 0 {
-                                    break;
+                                for lua_tx in LUA_TXS.lock().iter() {
+                                    lua_tx.send(script::Message::KeyDown(index)).unwrap_or_else(
+                                        |e| {
+                                            error!("Could not send a pending keyboard event: {}", e)
+                                        },
+                                    );
                                 }
-                            }
 
-                            events::notify_observers(events::Event::KeyDown(index))
-                                .unwrap_or_else(|e| error!("{}", e));
-                        } else {
-                            *UPCALL_COMPLETED_ON_KEY_UP.0.lock() = LUA_TXS.lock().len();
+                                // yield to thread
+                                //thread::sleep(Duration::from_millis(0));
 
-                            for lua_tx in LUA_TXS.lock().iter() {
-                                lua_tx
-                                    .send(script::Message::KeyUp(index))
-                                    .unwrap_or_else(|e| {
-                                        error!("Could not send a pending keyboard event: {}", e)
-                                    });
-                            }
+                                // wait until all Lua VMs completed the event handler
+                                loop {
+                                    let mut pending = UPCALL_COMPLETED_ON_KEY_DOWN.0.lock();
+
+                                    UPCALL_COMPLETED_ON_KEY_DOWN
+                                        .1
+                                        .wait_for(&mut pending, Duration::from_millis(50));
 
-                            // yield to thread
-                            //thread::sleep(Duration::from_millis(0));
+                                    if *pending == 0 {
+                                        break;
+                                    }
+                                }
+
+                                events::notify_observers(events::Event::KeyDown(index))
+                                    .unwrap_or_else(|e| error!("{}", e));
+                            } else {
+                                *UPCALL_COMPLETED_ON_KEY_UP.0.lock() = LUA_TXS.lock().len();
+
+                                for lua_tx in LUA_TXS.lock().iter() {
+                                    lua_tx.send(script::Message::KeyUp(index)).unwrap_or_else(
+                                        |e| {
+                                            error!("Could not send a pending keyboard event: {}", e)
+                                        },
+                                    );
+                                }
+
+                                // yield to thread
+                                //thread::sleep(Duration::from_millis(0));
 
-                            // wait until all Lua VMs completed the event handler
-                            loop {
-                                let mut pending = UPCALL_COMPLETED_ON_KEY_UP.0.lock();
+                                // wait until all Lua VMs completed the event handler
+                                loop {
+                                    let mut pending = UPCALL_COMPLETED_ON_KEY_UP.0.lock();
 
-                                UPCALL_COMPLETED_ON_KEY_UP
-                                    .1
-                                    .wait_for(&mut pending, Duration::from_millis(50));
+                                    UPCALL_COMPLETED_ON_KEY_UP
+                                        .1
+                                        .wait_for(&mut pending, Duration::from_millis(50));
 
-                                if *pending == 0 {
-                                    break;
+                                    if *pending == 0 {
+                                        break;
+                                    }
                                 }
+
+                                events::notify_observers(events::Event::KeyUp(index))
+                                    .unwrap_or_else(|e| error!("{}", e));
                             }
 
-                            events::notify_observers(events::Event::KeyUp(index))
-                                .unwrap_or_else(|e| error!("{}", e));
+                            latency::mark(&raw_event, latency::Stage::Dispatch);
                         }
-                    }
 
-                    // handler for Message::MirrorKey will drop the key if a Lua VM
-                    // called inject_key(..), so that the key won't be reported twice
-                    macros::UINPUT_TX
-                        .lock()
-                        .as_ref()
-                        .unwrap()
-                        .send(macros::Message::MirrorKey(raw_event.clone()))
-                        .unwrap_or_else(|e| {
-                            error!("Could not send a pending keyboard event: {}", e)
-                        });
-                }
+                        // in shared mode the desktop receives the events of the keyboard
+                        // itself, so they must not be mirrored to the virtual keyboard.
+                        // Events of other sources are never mirrored
+                        if origin.is_mirrored() && !SHARED_MODE.load(Ordering::SeqCst) {
+                            // handler for Message::MirrorKey will drop the key if a Lua VM
+                            // called inject_key(..), so that the key won't be reported twice
+                            macros::UINPUT_TX
+                                .lock()
+                                .as_ref()
+                                .unwrap()
+                                .send(macros::Message::MirrorKey(raw_event.clone()))
+                                .unwrap_or_else(|e| {
+                                    error!("Could not send a pending keyboard event: {}", e)
+                                });
+                        }
+                    }
+                },
 
                 // ignore spurious events
                 None => trace!("Spurious keyboard event ignored"),
@@ -586,6 +2250,12 @@ fn run_main_loop(
             }
         }
 
+        // release keys that have been held down for an implausibly long time
+        stuck_keys::check();
+
+        // log the latency of the input path, if tracing is enabled
+        latency::report();
+
         // process file system related events
         match fsevents_rx.recv_timeout(Duration::from_millis(0)) {
             Ok(result) => match result {
@@ -621,7 +2291,7 @@ fn run_main_loop(
                 frontend::Message::SwitchProfile(profile_path) => {
                     info!("Loading Profile: {}", profile_path.display());
 
-                    switch_profile(&profile_path, &rvdevice, &dbus_api_tx)
+                    switch_profile(&profile_path, rvdevices, &dbus_api_tx)
                         .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
                 }
             },
@@ -642,9 +2312,73 @@ fn run_main_loop(
                 dbus_interface::Message::SwitchProfile(profile_path) => {
                     info!("Loading Profile: {}", profile_path.display());
 
-                    switch_profile(&profile_path, &rvdevice, &dbus_api_tx)
+                    // an explicit switch ends a running preview
+                    *PROFILE_PREVIEW.lock() = None;
+
+                    switch_profile(&profile_path, rvdevices, &dbus_api_tx)
                         .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
                 }
+
+                dbus_interface::Message::PreviewProfile(profile_path, seconds) => {
+                    info!(
+                        "Previewing Profile: {} for {} seconds",
+                        profile_path.display(),
+                        seconds
+                    );
+
+                    // keep the original profile, if a preview is already running
+                    let previous_profile = PROFILE_PREVIEW
+                        .lock()
+                        .as_ref()
+                        .map(|(previous_profile, _)| previous_profile.clone())
+                        .or_else(|| {
+                            ACTIVE_PROFILE
+                                .lock()
+                                .as_ref()
+                                .and_then(|p| p.profile_file.file_name().map(PathBuf::from))
+                        });
+
+                    match switch_profile(&profile_path, rvdevices, &dbus_api_tx) {
+                        Ok(()) => {
+                            let deadline = Instant::now() + Duration::from_secs(seconds.into());
+                            *PROFILE_PREVIEW.lock() = previous_profile.map(|p| (p, deadline));
+                        }
+
+                        Err(e) => error!("Could not switch profiles: {}", e),
+                    }
+                }
+
+                dbus_interface::Message::CommitPreview => {
+                    if PROFILE_PREVIEW.lock().take().is_some() {
+                        info!("Keeping the previewed profile");
+                    }
+                }
+
+                dbus_interface::Message::ReloadProfile => {
+                    let profile_file = ACTIVE_PROFILE
+                        .lock()
+                        .as_ref()
+                        .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                    if let Some(profile_file) = profile_file {
+                        switch_profile(&profile_file, rvdevices, &dbus_api_tx)
+                            .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                    }
+                }
+
+                dbus_interface::Message::CancelPreview => {
+                    let preview = PROFILE_PREVIEW.lock().take();
+
+                    if let Some((previous_profile, _)) = preview {
+                        info!(
+                            "Preview cancelled, restoring: {}",
+                            previous_profile.display()
+                        );
+
+                        switch_profile(&previous_profile, rvdevices, &dbus_api_tx)
+                            .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                    }
+                }
             },
 
             // ignore timeout errors
@@ -656,68 +2390,387 @@ fn run_main_loop(
             }
         }
 
-        // send timer tick events to the Lua VMs
-        for lua_tx in LUA_TXS.lock().iter() {
-            lua_tx
-                .send(script::Message::Tick(
-                    start_time.elapsed().as_millis().try_into().unwrap(),
-                ))
-                .unwrap_or_else(|e| error!("Send error: {}", e));
+        // revert to the original profile, when a profile preview ends
+        #[cfg(feature = "dbus")]
+        {
+            let expired = PROFILE_PREVIEW
+                .lock()
+                .as_ref()
+                .map(|(_, deadline)| Instant::now() >= *deadline)
+                .unwrap_or(false);
+
+            if expired {
+                let preview = PROFILE_PREVIEW.lock().take();
+
+                if let Some((previous_profile, _)) = preview {
+                    info!("Preview ended, restoring: {}", previous_profile.display());
+
+                    switch_profile(&previous_profile, rvdevices, &dbus_api_tx)
+                        .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                }
+            }
         }
 
-        // execute render "pipeline" now
+        // switch to the "locked" profile while the session is locked, and back on unlock
+        #[cfg(feature = "dbus")]
+        match plugins::SessionPlugin::take_profile_switch() {
+            Some(plugins::session::ProfileSwitch::Lock(profile_path)) => {
+                unlocked_profile = ACTIVE_PROFILE
+                    .lock()
+                    .as_ref()
+                    .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                switch_profile(&profile_path, rvdevices, &dbus_api_tx)
+                    .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+            }
 
-        // first, clear the canvas
-        script::LED_MAP.lock().copy_from_slice(
-            &[rvdevice::RGBA {
-                r: 0,
-                g: 0,
-                b: 0,
-                a: 0,
-            }; rvdevice::NUM_KEYS],
-        );
+            Some(plugins::session::ProfileSwitch::Unlock) => {
+                if let Some(profile_path) = unlocked_profile.take() {
+                    switch_profile(&profile_path, rvdevices, &dbus_api_tx)
+                        .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                }
+            }
+
+            None => (),
+        }
 
-        // instruct Lua VMs to realize their color maps, e.g. to blend their
-        // local color maps with the canvas
-        *COLOR_MAPS_READY_CONDITION.0.lock() = LUA_TXS.lock().len();
+        // the profile search path changed, since another user is active on our seat
+        #[cfg(feature = "dbus")]
+        {
+            if plugins::SessionPlugin::take_seat_user_changed() {
+                let profile_file = ACTIVE_PROFILE
+                    .lock()
+                    .as_ref()
+                    .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                // reload the active profile, the user may have a profile with the same name
+                if let Some(profile_file) = profile_file {
+                    switch_profile(&profile_file, rvdevices, &dbus_api_tx)
+                        .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                }
 
-        let mut drop_frame = false;
+                dbus_api_tx
+                    .send(DbusApiEvent::ProfilesChanged)
+                    .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+            }
+        }
 
-        for lua_tx in LUA_TXS.lock().iter() {
-            // guarantee the right order of execution for the alpha blend
-            // operations, so we have to wait for the current Lua VM to
-            // complete its blending code, before continuing
-            let mut pending = COLOR_MAPS_READY_CONDITION.0.lock();
+        // perform the actions of rules, whose conditions started or ceased to hold
+        for action in rules::take_actions() {
+            run_rule_action(
+                &action,
+                rvdevices,
+                #[cfg(feature = "dbus")]
+                &dbus_api_tx,
+            );
+        }
 
-            lua_tx
-                .send(script::Message::RealizeColorMap)
-                .unwrap_or_else(|e| error!("Send error: {}", e));
+        // reduce the overhead of the effects while a fullscreen application is focused
+        #[cfg(feature = "dbus")]
+        match fullscreen::update() {
+            Some(fullscreen::ProfileSwitch::Enter(profile_path)) => {
+                let previous_profile = ACTIVE_PROFILE
+                    .lock()
+                    .as_ref()
+                    .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                match switch_profile(&profile_path, rvdevices, &dbus_api_tx) {
+                    Ok(()) => {
+                        let fullscreen_profile = ACTIVE_PROFILE
+                            .lock()
+                            .as_ref()
+                            .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                        windowed_profile = match (previous_profile, fullscreen_profile) {
+                            (Some(previous), Some(fullscreen)) => Some((previous, fullscreen)),
+                            _ => None,
+                        };
+                    }
 
-            // yield to thread
-            //thread::sleep(Duration::from_millis(0));
+                    Err(e) => error!("Could not switch profiles: {}", e),
+                }
+            }
 
-            let result = COLOR_MAPS_READY_CONDITION
-                .1
-                .wait_for(&mut pending, Duration::from_millis(50));
+            Some(fullscreen::ProfileSwitch::Leave) => {
+                if let Some((profile_path, fullscreen_profile)) = windowed_profile.take() {
+                    let active_profile = ACTIVE_PROFILE
+                        .lock()
+                        .as_ref()
+                        .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
 
-            if result.timed_out() {
-                drop_frame = true;
-                warn!("Frame dropped: Timeout while waiting for a lock!");
-                break;
+                    // keep the profile, if the user switched profiles while the
+                    // fullscreen application was focused
+                    if active_profile.as_ref() == Some(&fullscreen_profile) {
+                        switch_profile(&profile_path, rvdevices, &dbus_api_tx)
+                            .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                    } else {
+                        info!("The profile has been switched in fullscreen mode, keeping it");
+                    }
+                }
             }
+
+            None => (),
         }
 
-        // yield main thread
-        //thread::sleep(Duration::from_millis(0));
+        tick_rate.set_min_divisor(fullscreen::tick_divisor());
 
-        // number of pending blend ops should have reached zero by now
-        //assert!(*COLOR_MAPS_READY_CONDITION.0.lock() == 0);
+        // apply the lighting policy while the displays are blanked
+        match display::update() {
+            Some(display::Change::Off) => {
+                let led_map = [rvdevice::RGBA {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 0,
+                }; rvdevice::NUM_KEYS];
 
-        // send the final (combined) color map to the keyboard
-        if !drop_frame {
-            rvdevice
-                .send_led_map(&script::LED_MAP.lock())
-                .unwrap_or_else(|e| error!("Could not send led map to the device: {}", e));
+                for index in 0..rvdevices.len() {
+                    sinks::write_frame(index, &led_map);
+                }
+            }
+
+            Some(display::Change::Enter(profile_path)) => {
+                let previous_profile = ACTIVE_PROFILE
+                    .lock()
+                    .as_ref()
+                    .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                match switch_profile(
+                    &profile_path,
+                    rvdevices,
+                    #[cfg(feature = "dbus")]
+                    &dbus_api_tx,
+                ) {
+                    Ok(()) => {
+                        let blanked_profile = ACTIVE_PROFILE
+                            .lock()
+                            .as_ref()
+                            .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                        unblanked_profile = match (previous_profile, blanked_profile) {
+                            (Some(previous), Some(blanked)) => Some((previous, blanked)),
+                            _ => None,
+                        };
+                    }
+
+                    Err(e) => error!("Could not switch profiles: {}", e),
+                }
+            }
+
+            Some(display::Change::Leave) => {
+                if let Some((profile_path, blanked_profile)) = unblanked_profile.take() {
+                    let active_profile = ACTIVE_PROFILE
+                        .lock()
+                        .as_ref()
+                        .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                    // keep the profile, if the user switched profiles while the
+                    // displays were blanked
+                    if active_profile.as_ref() == Some(&blanked_profile) {
+                        switch_profile(
+                            &profile_path,
+                            rvdevices,
+                            #[cfg(feature = "dbus")]
+                            &dbus_api_tx,
+                        )
+                        .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                    } else {
+                        info!("The profile has been switched while the displays were blanked, keeping it");
+                    }
+                }
+            }
+
+            Some(display::Change::On) | None => (),
+        }
+
+        // pause rendering before the system suspends, the devices may lose their
+        // state while suspended, so reinitialize them and replay the active profile
+        match sleep::take_event() {
+            Some(sleep::SleepEvent::Suspend) => {
+                info!("Pausing rendering before suspend");
+
+                let led_map = [rvdevice::RGBA {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 0,
+                }; rvdevice::NUM_KEYS];
+
+                for index in 0..rvdevices.len() {
+                    sinks::write_frame(index, &led_map);
+                }
+
+                // the devices keep showing the stored color while the system is
+                // suspended, they are switched back to direct mode on resume
+                let exit_state = get_exit_state(CONFIG.lock().as_ref().unwrap());
+
+                if let rvdevice::ExitState::Stored(color) = exit_state {
+                    for rvdevice in rvdevices.iter_mut() {
+                        rvdevice
+                            .set_stored_color(hidapi, color)
+                            .unwrap_or_else(|e| error!("Could not store the color: {}", e));
+                    }
+                }
+
+                sleep::acknowledge_suspend();
+            }
+
+            Some(sleep::SleepEvent::Resume) => {
+                info!("Reinitializing the devices after resume");
+
+                for rvdevice in rvdevices.iter_mut() {
+                    rvdevice
+                        .reopen(hidapi)
+                        .and_then(|_| rvdevice.set_led_init_pattern())
+                        .unwrap_or_else(|e| error!("Could not reinitialize the device: {}", e));
+                }
+
+                let profile_file = ACTIVE_PROFILE
+                    .lock()
+                    .as_ref()
+                    .and_then(|p| p.profile_file.file_name().map(PathBuf::from));
+
+                if let Some(profile_file) = profile_file {
+                    switch_profile(
+                        &profile_file,
+                        rvdevices,
+                        #[cfg(feature = "dbus")]
+                        &dbus_api_tx,
+                    )
+                    .unwrap_or_else(|e| error!("Could not switch profiles: {}", e));
+                }
+            }
+
+            None => (),
+        }
+
+        // enter or leave do-not-disturb mode
+        let dnd = DO_NOT_DISTURB.load(Ordering::SeqCst);
+        if dnd != dnd_active {
+            dnd_active = dnd;
+
+            if dnd_active {
+                info!("Entering do-not-disturb mode");
+
+                // freeze the current frames, and dim them
+                let mut led_maps = script::LED_MAPS.lock();
+
+                for led_map in led_maps.iter_mut() {
+                    dim_led_map(led_map, constants::DND_BRIGHTNESS);
+                }
+
+                write_frames(&led_maps);
+            } else {
+                info!("Leaving do-not-disturb mode");
+            }
+
+            for lua_tx in LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::DndChanged(dnd_active))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+        }
+
+        // send timer tick events to the Lua VMs, animations are
+        // frozen while in do-not-disturb mode. Ticks of VMs that lag
+        // behind are coalesced, instead of piling up in their queues
+        if !dnd_active && !sleep::is_suspended() && !display::is_keyboard_off() {
+            let delta = start_time.elapsed().as_millis().try_into().unwrap();
+
+            if let Some(delta) = tick_rate.next_tick(ticks, delta) {
+                for (layer, lua_tx) in LUA_TXS.lock().iter().enumerate() {
+                    // paused and suspended layers keep their current frame
+                    if fullscreen::is_layer_paused(layer)
+                        || !scripting::ticks::is_tick_requested(layer, ticks, delta)
+                    {
+                        continue;
+                    }
+
+                    match scripting::ticks::queue_tick(layer, delta) {
+                        Some(delta) => {
+                            tick_rate.record(false);
+
+                            lua_tx
+                                .send(script::Message::Tick(delta))
+                                .unwrap_or_else(|e| error!("Send error: {}", e));
+                        }
+
+                        None => tick_rate.record(true),
+                    }
+                }
+            }
+        }
+
+        // execute render "pipeline" now, frames may be skipped e.g. to
+        // save power while running on battery, while a fullscreen game is
+        // running, in do-not-disturb mode, while the system is suspended, or
+        // while the keyboard is switched off since the displays are blanked
+        if !dnd_active
+            && !sleep::is_suspended()
+            && !display::is_keyboard_off()
+            && plugins::power::is_render_frame(ticks)
+            && scheduling::is_render_frame(ticks)
+        {
+            // first, clear the canvases
+            for led_map in script::LED_MAPS.lock().iter_mut() {
+                led_map.copy_from_slice(
+                    &[rvdevice::RGBA {
+                        r: 0,
+                        g: 0,
+                        b: 0,
+                        a: 0,
+                    }; rvdevice::NUM_KEYS],
+                );
+            }
+
+            // instruct the Lua VMs to publish their frames, they are rendered in
+            // parallel, each on the thread of its VM
+            let num_layers = LUA_TXS.lock().len();
+
+            for lua_tx in LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::RenderFrame(ticks))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+
+            // wait for all layers, stragglers that miss the deadline contribute
+            // their previous frame, so they don't block the device update
+            let deadline = Instant::now() + Duration::from_millis(constants::FRAME_DEADLINE_MILLIS);
+            let stragglers = script::wait_for_layers(ticks, num_layers, deadline);
+
+            if stragglers > 0 {
+                debug!("{} layer(s) missed the frame deadline", stragglers);
+            }
+
+            // compose the frames of all layers, and send the final (combined)
+            // color maps to the keyboards
+            let mut led_maps = script::LED_MAPS.lock();
+            script::compose_layers(&mut led_maps, num_layers);
+
+            // blend in the last frame of the previous profile, after a switch
+            transitions::process_frame(&mut led_maps);
+
+            // post-processing filters of the profile, like blur or scanlines
+            postprocessing::process_frame(&mut led_maps, rvdevices);
+
+            // broadcast the frame to, or receive it from other machines
+            netsync::process_frame(&mut led_maps[0]);
+
+            // draw overlay effects of the daemon itself, like the volume bar,
+            // they are shown on the default device only
+            overlay::render(&mut led_maps[0]);
+
+            // the key that has been pressed in identify mode is the only lit key
+            identify::render(&mut led_maps[0]);
+
+            // a due alert is shown on all devices, a raised alarm preempts
+            // everything else
+            for led_map in led_maps.iter_mut() {
+                alerts::render(led_map);
+                alarms::render(led_map);
+            }
+
+            write_frames(&led_maps);
         }
 
         // sync to MAIN_LOOP_DELAY_MILLIS iteration time
@@ -746,26 +2799,212 @@ fn run_main_loop(
             }
         }
 
-        // calculate and log fps each second
-        if fps_timer.elapsed().as_millis() >= 1000 {
-            debug!("FPS: {}", fps_cntr);
+        // calculate and log fps each second
+        if fps_timer.elapsed().as_millis() >= 1000 {
+            debug!("FPS: {}", fps_cntr);
+
+            fps_timer = Instant::now();
+            fps_cntr = 0;
+        }
+
+        // shall we quit the main loop?
+        if QUIT.load(Ordering::SeqCst) {
+            break 'MAIN_LOOP;
+        }
+
+        fps_cntr += 1;
+        ticks += 1;
+
+        start_time = Instant::now();
+    }
+
+    events::notify_observers(events::Event::DaemonShutdown).unwrap();
+}
+
+/// The final output stage: Write the frames `led_maps` to all registered sinks, like
+/// the devices, the frame recorder or LED strips on the network. The
+/// frames are matched to the color temperature of the screens first, color filters
+/// for accessibility are the last stage. The filters are applied to copies, so that
+/// frames that are reused, like the frozen frames of do-not-disturb mode or the last
+/// frame of a profile that is blended in by a transition, are never filtered twice
+fn write_frames(led_maps: &[Vec<rvdevice::RGBA>]) {
+    for (index, led_map) in led_maps.iter().enumerate() {
+        let mut led_map = led_map.clone();
+
+        warmth::filter_led_map(&mut led_map);
+        accessibility::filter_led_map(&mut led_map);
+
+        sinks::write_frame(index, &led_map);
+    }
+}
+
+/// Send the LED map `led_map` to the device with the index `index`, using the write
+/// retry policy. Plugins, scripts and the D-Bus API are notified if the device
+/// fails, or recovers after a failure
+fn send_led_map(
+    hidapi: &hidapi::HidApi,
+    index: usize,
+    rvdevice: &mut RvDeviceState,
+    led_map: &[rvdevice::RGBA],
+    #[cfg(feature = "dbus")] dbus_api_tx: &Sender<DbusApiEvent>,
+) {
+    match rvdevice.send_led_map_with_retry(hidapi, led_map) {
+        Some(HealthChange::Failed(description)) => {
+            events::notify_observers(events::Event::DeviceError(index))
+                .unwrap_or_else(|e| error!("{}", e));
+
+            for lua_tx in LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::DeviceError(index, description.clone()))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+
+            #[cfg(feature = "dbus")]
+            dbus_api_tx
+                .send(DbusApiEvent::DeviceError(index, description))
+                .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+        }
+
+        Some(HealthChange::Unresponsive) => {
+            events::notify_observers(events::Event::DeviceUnresponsive(index))
+                .unwrap_or_else(|e| error!("{}", e));
+
+            let description = "Device unresponsive".to_string();
+
+            for lua_tx in LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::DeviceError(index, description.clone()))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+
+            #[cfg(feature = "dbus")]
+            dbus_api_tx
+                .send(DbusApiEvent::DeviceError(index, description))
+                .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+        }
+
+        Some(HealthChange::Recovered) => {
+            events::notify_observers(events::Event::DeviceRecovered(index))
+                .unwrap_or_else(|e| error!("{}", e));
+
+            for lua_tx in LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::DeviceRecovered(index))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+
+            #[cfg(feature = "dbus")]
+            dbus_api_tx
+                .send(DbusApiEvent::DeviceRecovered(index))
+                .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+        }
+
+        None => (),
+    }
+}
+
+/// The devices, as an output sink of the render loop. Like the writer threads of
+/// the devices, the sink writes to clones of the devices, that share their handles
+struct HidSink {
+    hidapi: Arc<hidapi::HidApi>,
+    rvdevices: Vec<RvDeviceState>,
+    exit_state: rvdevice::ExitState,
+    #[cfg(feature = "dbus")]
+    dbus_api_tx: Sender<DbusApiEvent>,
+}
+
+impl OutputSink for HidSink {
+    fn name(&self) -> &str {
+        "hid"
+    }
 
-            fps_timer = Instant::now();
-            fps_cntr = 0;
+    fn accepts(&self, device: usize) -> bool {
+        device < self.rvdevices.len()
+    }
+
+    fn write_frame(&mut self, device: usize, led_map: &[rvdevice::RGBA]) {
+        send_led_map(
+            &self.hidapi,
+            device,
+            &mut self.rvdevices[device],
+            led_map,
+            #[cfg(feature = "dbus")]
+            &self.dbus_api_tx,
+        );
+    }
+
+    fn close(&mut self) {
+        info!("Restoring LED exit state...");
+
+        for rvdevice in self.rvdevices.iter_mut() {
+            rvdevice
+                .restore_exit_state(Some(&self.hidapi), self.exit_state)
+                .unwrap_or_else(|e| error!("Could not restore the exit state: {}", e));
         }
+    }
+}
 
-        // shall we quit the main loop?
-        if QUIT.load(Ordering::SeqCst) {
-            break 'MAIN_LOOP;
+/// Get the disposition of the key event `raw_event` of the source `origin`. Hotkeys
+/// that are bound to the keys of the dial take precedence over the dial. Only the
+/// events of the hardware keyboard may be consumed, all others are passed
+fn get_disposition(
+    origin: inputs::Origin,
+    raw_event: &evdev_rs::InputEvent,
+) -> hotkeys::Disposition {
+    if !origin.is_consumable() {
+        return hotkeys::Disposition::Pass;
+    }
+
+    match hotkeys::process_event(raw_event) {
+        hotkeys::Disposition::Pass => {
+            dial::process_event(raw_event).unwrap_or(hotkeys::Disposition::Pass)
         }
 
-        fps_cntr += 1;
-        ticks += 1;
+        disposition => disposition,
+    }
+}
 
-        start_time = Instant::now();
+/// Get the startup or the shutdown animation of the active profile and its
+/// color, or None if the profile does not specify one
+fn get_profile_animation(shutdown: bool) -> Option<(animations::Animation, rvdevice::RGBA)> {
+    let active_profile = ACTIVE_PROFILE.lock();
+    let profile = active_profile.as_ref()?;
+
+    let name = if shutdown {
+        profile.shutdown_animation.as_ref()?
+    } else {
+        profile.startup_animation.as_ref()?
+    };
+
+    match name.parse::<animations::Animation>() {
+        Ok(animation) => {
+            let color = profile.animation_color.unwrap_or(0xffffff);
+
+            Some((
+                animation,
+                rvdevice::RGBA {
+                    r: ((color >> 16) & 0xff) as u8,
+                    g: ((color >> 8) & 0xff) as u8,
+                    b: (color & 0xff) as u8,
+                    a: 0xff,
+                },
+            ))
+        }
+
+        Err(e) => {
+            error!("{}", e);
+            None
+        }
     }
+}
 
-    events::notify_observers(events::Event::DaemonShutdown).unwrap();
+/// Dim all colors of `led_map` to `percent` percent of their brightness
+fn dim_led_map(led_map: &mut [rvdevice::RGBA], percent: isize) {
+    for color in led_map.iter_mut() {
+        color.r = (color.r as isize * percent / 100) as u8;
+        color.g = (color.g as isize * percent / 100) as u8;
+        color.b = (color.b as isize * percent / 100) as u8;
+    }
 }
 
 /// Watch profiles and script directory, as well as our
@@ -882,15 +3121,256 @@ fn main() {
     let matches = parse_commandline();
 
     // initialize logging
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG_OVERRIDE", "info");
-        pretty_env_logger::init_custom_env("RUST_LOG_OVERRIDE");
-    } else {
-        pretty_env_logger::init();
+    logging::initialize().unwrap_or_else(|e| eprintln!("Could not initialize logging: {}", e));
+
+    if matches.subcommand_matches("device-info").is_some() {
+        print_device_info();
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("replay-capture") {
+        let file = sub_matches.value_of("file").unwrap();
+
+        capture::replay(file, sub_matches.is_present("verbose")).unwrap_or_else(|e| {
+            error!("Could not replay the capture: {}", e);
+            process::exit(1);
+        });
+
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("export-recording") {
+        let file = sub_matches.value_of("file").unwrap();
+        let output = sub_matches.value_of("output").unwrap();
+
+        let fps = sub_matches
+            .value_of("fps")
+            .map(|fps| fps.parse::<u32>())
+            .unwrap_or(Ok(25))
+            .unwrap_or_else(|e| {
+                error!("Invalid frame rate: {}", e);
+                process::exit(1);
+            });
+
+        recorder::export(file, output, fps).unwrap_or_else(|e| {
+            error!("Could not export the recording: {}", e);
+            process::exit(1);
+        });
+
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("bundle") {
+        let config_file = matches
+            .value_of("config")
+            .unwrap_or(constants::DEFAULT_CONFIG_FILE);
+
+        run_bundle_command(sub_matches, Path::new(config_file));
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("repl") {
+        repl::run_client(
+            sub_matches.value_of("script").unwrap(),
+            Path::new(sub_matches.value_of("socket").unwrap()),
+        )
+        .unwrap_or_else(|e| {
+            error!("Debug console: {}", e);
+            process::exit(1);
+        });
+
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("snapshot") {
+        let config_file = matches
+            .value_of("config")
+            .unwrap_or(constants::DEFAULT_CONFIG_FILE);
+
+        run_snapshot_command(sub_matches, Path::new(config_file));
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("sign-script") {
+        scripting::integrity::sign_script(
+            sub_matches.value_of("script").unwrap(),
+            sub_matches.value_of("key"),
+        )
+        .unwrap_or_else(|e| {
+            error!("Could not sign the script: {}", e);
+            process::exit(1);
+        });
+
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("api-docs") {
+        match scripting::api::build_registry() {
+            Ok(registry) => {
+                if sub_matches.is_present("stubs") {
+                    print!("{}", registry.to_lua_stubs());
+                } else {
+                    print!("{}", registry.to_markdown());
+                }
+            }
+
+            Err(e) => {
+                error!("Invalid Lua API declarations: {}", e);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("test-leds") {
+        if sub_matches.is_present("topology") {
+            diagnostics::print_topology();
+            return;
+        }
+
+        let device = sub_matches
+            .value_of("device")
+            .and_then(|d| d.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        let delay = sub_matches
+            .value_of("delay")
+            .and_then(|d| d.parse::<u64>().ok())
+            .unwrap_or(constants::LED_TEST_DELAY_MILLIS);
+
+        diagnostics::run_led_test(device, Duration::from_millis(delay)).unwrap_or_else(|e| {
+            error!("LED test failed: {}", e);
+            process::exit(1);
+        });
+
+        return;
+    }
+
+    #[cfg(feature = "dbus")]
+    {
+        if matches.subcommand_matches("active-scripts").is_some() {
+            run_active_scripts_command();
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("plugins") {
+            run_plugins_command(sub_matches);
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("alerts") {
+            run_alerts_command(sub_matches);
+            return;
+        }
+
+        if matches.subcommand_matches("identify").is_some() {
+            run_identify_command();
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("log-filter") {
+            run_log_filter_command(sub_matches.value_of("filter"));
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("debug") {
+            run_debug_command(sub_matches);
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("errors") {
+            run_errors_command(sub_matches);
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("logs") {
+            let count = sub_matches
+                .value_of("count")
+                .and_then(|c| c.parse::<u32>().ok())
+                .unwrap_or(constants::LOG_BUFFER_SIZE as u32);
+
+            print_log_lines(count);
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("start-pomodoro") {
+            let work_mins = sub_matches
+                .value_of("work")
+                .and_then(|m| m.parse::<u32>().ok())
+                .unwrap_or(25);
+
+            let break_mins = sub_matches
+                .value_of("break")
+                .and_then(|m| m.parse::<u32>().ok())
+                .unwrap_or(5);
+
+            dbus_interface::start_pomodoro(work_mins, break_mins).unwrap_or_else(|e| {
+                error!("Could not start the pomodoro timer: {}", e);
+                process::exit(1);
+            });
+
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("dnd") {
+            run_dnd_command(sub_matches.value_of("state"));
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("active-app") {
+            run_active_app_command(
+                sub_matches.value_of("class"),
+                sub_matches.is_present("fullscreen"),
+            );
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("accent-color") {
+            run_accent_color_command(sub_matches.value_of("color"));
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("color-temperature") {
+            run_color_temperature_command(sub_matches.value_of("kelvin"));
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("display") {
+            run_display_command(sub_matches.value_of("state"));
+            return;
+        }
+
+        if matches.subcommand_matches("latency").is_some() {
+            print_latency_metrics();
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("param") {
+            run_param_command(sub_matches);
+            return;
+        }
+
+        if let Some(sub_matches) = matches.subcommand_matches("layers") {
+            run_layers_command(sub_matches);
+            return;
+        }
+
+        if matches.subcommand_matches("stop-pomodoro").is_some() {
+            dbus_interface::stop_pomodoro().unwrap_or_else(|e| {
+                error!("Could not stop the pomodoro timer: {}", e);
+                process::exit(1);
+            });
+
+            return;
+        }
     }
 
     info!("Starting user-mode driver for ROCCAT Vulcan 100/12x series devices");
 
+    // start the monotonic clock of the Lua scripts
+    lazy_static::initialize(&script::START_TIME);
+
     // register ctrl-c handler
     let q = QUIT.clone();
     ctrlc::set_handler(move || {
@@ -913,6 +3393,79 @@ fn main() {
 
     *CONFIG.lock() = Some(config.clone());
 
+    // configure the overlay effects, like the volume bar
+    overlay::initialize();
+
+    // set up network synchronization of the lighting, if enabled
+    netsync::initialize().unwrap_or_else(|e| error!("Could not initialize network sync: {}", e));
+
+    // register the output sinks of the render loop, and the virtual LED strip
+    sinks::initialize();
+
+    ledstrip::initialize().unwrap_or_else(|e| error!("Could not initialize the LED strip: {}", e));
+
+    // configure the debouncing of the keys
+    debounce::initialize().unwrap_or_else(|e| error!("Could not configure debouncing: {}", e));
+
+    // configure the watchdog for stuck keys
+    stuck_keys::initialize();
+
+    // load the policy for fullscreen applications
+    fullscreen::initialize();
+
+    // configure the warmth filter, that matches the color temperature of the screens
+    warmth::initialize();
+
+    // configure the transitions between profiles
+    transitions::initialize();
+
+    // publish the palette of the desktop wallpaper, if enabled
+    wallpaper::initialize().unwrap_or_else(|e| error!("Could not watch the wallpaper: {}", e));
+
+    // restrict the threads of the daemon to the configured CPUs
+    scheduling::initialize()
+        .unwrap_or_else(|e| error!("Could not configure the CPU affinity: {}", e));
+
+    // configure the tracing of the latency of the input path
+    latency::initialize();
+
+    // load the hotkey bindings
+    hotkeys::initialize().unwrap_or_else(|e| error!("Could not load hotkeys: {}", e));
+
+    // create the virtual mouse of the pointer keys, if enabled
+    pointer_keys::initialize()
+        .unwrap_or_else(|e| error!("Could not initialize the pointer keys: {}", e));
+
+    // bind the dial to its function
+    dial::initialize().unwrap_or_else(|e| error!("Could not initialize the dial: {}", e));
+
+    // load the alarm rules, and start evaluating them
+    alarms::initialize().unwrap_or_else(|e| error!("Could not load alarms: {}", e));
+    alerts::initialize().unwrap_or_else(|e| error!("Could not load scheduled alerts: {}", e));
+
+    // load the rules of the rule engine, and start evaluating them
+    rules::initialize().unwrap_or_else(|e| error!("Could not load rules: {}", e));
+
+    // export the frames to external visualizers, if enabled
+    shm::initialize()
+        .unwrap_or_else(|e| error!("Could not export the frames to shared memory: {}", e));
+
+    // listen for mentions of chat clients, if enabled
+    mentions::initialize().unwrap_or_else(|e| error!("Could not listen for mentions: {}", e));
+
+    // listen for clients of the debug console, if enabled
+    repl::initialize().unwrap_or_else(|e| error!("Could not start the debug console: {}", e));
+
+    // install the line hook into the Lua VMs, if the debugger is enabled
+    scripting::debugger::initialize();
+
+    // load the lighting policy for blanked displays
+    display::initialize()
+        .unwrap_or_else(|e| error!("Could not load the lighting policy of the displays: {}", e));
+
+    // pause rendering before suspend, and reinitialize the devices on resume
+    sleep::initialize().unwrap_or_else(|e| error!("Could not watch for suspend: {}", e));
+
     // load and initialize global runtime state
     debug!("Loading saved state...");
     state::init_global_runtime_state()
@@ -936,8 +3489,6 @@ fn main() {
     let mut profile_file = PathBuf::from(&profile_name);
     profile_file.set_extension("profile");
 
-    let profile_file = PathBuf::from(&profile_dir).join(&profile_file);
-
     // try to load saved profile state
     let state = state::STATE.read();
     let saved_profile = state
@@ -946,19 +3497,28 @@ fn main() {
         .get("profile")
         .unwrap_or_else(|_| profile_file);
 
-    let profile_file = PathBuf::from(&profile_dir).join(saved_profile);
-
     // finally, load the profile
-    trace!("Loading profile data from '{}'", profile_file.display());
-    let profile = Profile::from(&profile_file).unwrap_or_else(|e| {
-        warn!(
-            "Error opening the profile file '{}': {}",
-            profile_file.display(),
-            e
-        );
+    let profile = match paths::find_profile(saved_profile) {
+        Ok(profile_file) => {
+            trace!("Loading profile data from '{}'", profile_file.display());
 
-        Profile::default()
-    });
+            Profile::from(&profile_file).unwrap_or_else(|e| {
+                warn!(
+                    "Error opening the profile file '{}': {}",
+                    profile_file.display(),
+                    e
+                );
+
+                Profile::default()
+            })
+        }
+
+        Err(e) => {
+            warn!("Error opening the profile file: {}", e);
+
+            Profile::default()
+        }
+    };
 
     info!("Loaded profile: {}", &profile.name);
 
@@ -980,11 +3540,22 @@ fn main() {
         profile.active_scripts.clone()
     };
 
-    let script_paths: Vec<PathBuf> = script_files
-        .iter()
-        .map(|p| PathBuf::from(&script_dir).join(p))
-        .collect();
+    let (script_files, script_paths): (Vec<PathBuf>, Vec<PathBuf>) = script_files
+        .into_iter()
+        .filter_map(|p| match paths::find_script(&p) {
+            Ok(path) => Some((p, path)),
+
+            Err(e) => {
+                error!("Skipping script: {}", e);
+                None
+            }
+        })
+        .unzip();
 
+    accessibility::apply_profile(&profile);
+    postprocessing::apply_profile(&profile);
+    overlay::apply_profile(&profile);
+    hotkeys::apply_profile(&profile);
     *ACTIVE_PROFILE.lock() = Some(profile);
 
     // frontend enable
@@ -992,6 +3563,19 @@ fn main() {
         .get::<bool>("frontend.enabled")
         .unwrap_or_else(|_| true);
 
+    // shared mode: don't grab the keyboard, and tolerate other programs using the device
+    let shared_mode = config
+        .get::<bool>("global.shared_mode")
+        .unwrap_or_else(|_| false);
+    SHARED_MODE.store(shared_mode, Ordering::SeqCst);
+
+    if shared_mode {
+        warn!("Running in shared mode, the keyboard will not be grabbed exclusively");
+    }
+
+    // the state the LEDs are left in when we exit
+    let exit_state = get_exit_state(&config);
+
     // others
     let _verbosity = matches.occurrences_of("v");
 
@@ -1001,67 +3585,276 @@ fn main() {
     // request realtime priority
     // crate::util::set_process_priority();
 
+    // record the HID reports exchanged with the devices, for protocol debugging
+    if let Some(capture_file) = matches.value_of("capture") {
+        capture::start(capture_file)
+            .unwrap_or_else(|e| error!("Could not start the capture: {}", e));
+    }
+
+    // record the frames shown on the keyboard, e.g. to share an effect
+    if let Some(record_file) = matches.value_of("record") {
+        recorder::start(record_file)
+            .unwrap_or_else(|e| error!("Could not start the recording: {}", e));
+    }
+
     // create the one and only hidapi instance
     match hidapi::HidApi::new() {
         Ok(hidapi) => {
+            // shared with the output sink of the devices
+            let hidapi = Arc::new(hidapi);
+
             match RvDeviceState::enumerate_devices(&hidapi) {
-                Ok(mut rvdevice) => {
-                    // open the control and led devices
-                    info!("Opening devices...");
-                    rvdevice
-                    .open(&hidapi)
-                    .unwrap_or_else(|e| {
-                        error!("Error opening the keyboard device: {}", e);
-                        error!("This could be a permission problem, or maybe the device is locked by another process?");
-                        process::exit(3);
-                    });
+                Ok(mut rvdevices) => {
+                    for (index, rvdevice) in rvdevices.iter_mut().enumerate() {
+                        info!("Device {}: {}", index, rvdevice.get_dev_id());
+
+                        // detect other programs that are using the device, like
+                        // another instance of eruption, they would fight over the LEDs
+                        let claims = rvdevice.find_device_claims();
+                        if !claims.is_empty() {
+                            let claims = claims
+                                .iter()
+                                .map(|c| c.to_string())
+                                .collect::<Vec<String>>()
+                                .join(", ");
+
+                            // a claim does not necessarily prevent opening the device,
+                            // e.g. a process may only hold a file descriptor of it
+                            warn!("The device is also in use by: {}", claims);
+
+                            if !shared_mode {
+                                warn!("Please terminate the conflicting process, or enable shared mode in eruption.conf");
+                            }
+                        }
 
-                    // send initialization handshake
-                    info!("Initializing devices...");
-                    rvdevice
-                        .send_init_sequence()
-                        .unwrap_or_else(|e| error!("Could not initialize the device: {}", e));
+                        // open the control and led devices
+                        info!("Opening devices...");
+                        rvdevice
+                        .open(&hidapi)
+                        .unwrap_or_else(|e| {
+                            error!("Error opening the keyboard device: {}", e);
 
-                    // set leds to a known initial state
-                    info!("Configuring LEDs...");
-                    rvdevice
-                        .set_led_init_pattern()
-                        .unwrap_or_else(|e| error!("Could not initialize LEDs: {}", e));
+                            if claims.is_empty() {
+                                error!("This could be a permission problem, or maybe the device is locked by another process?");
+                            } else {
+                                error!("The device is locked by another process, please terminate it");
+                            }
+
+                            process::exit(3);
+                        });
+
+                        // send initialization handshake
+                        info!("Initializing devices...");
+                        rvdevice
+                            .send_init_sequence()
+                            .unwrap_or_else(|e| error!("Could not initialize the device: {}", e));
+
+                        // measure the min. interval between two frames, unless configured
+                        let settle_millis = CONFIG.lock().as_ref().and_then(|config| {
+                            config.get::<u64>("global.device_settle_millis").ok()
+                        });
+
+                        match settle_millis {
+                            Some(millis) => rvdevice.set_settle_time(Duration::from_millis(millis)),
+
+                            None => match rvdevice.calibrate_settle_time() {
+                                Ok(settle_time) => info!(
+                                    "Calibrated the settle time of the device: {}ms",
+                                    settle_time.as_millis()
+                                ),
+
+                                Err(e) => warn!(
+                                    "Could not calibrate the settle time of the device: {}",
+                                    e
+                                ),
+                            },
+                        }
+
+                        // set leds to a known initial state
+                        info!("Configuring LEDs...");
+                        rvdevice
+                            .set_led_init_pattern()
+                            .unwrap_or_else(|e| error!("Could not initialize LEDs: {}", e));
+                    }
 
-                    // initialize the D-Bus API
+                    // probe the devices, so that the plugins can adapt to the available features
+                    info!("Running device self-test...");
+                    selftest::set_capabilities(
+                        rvdevices
+                            .iter_mut()
+                            .map(|rvdevice| selftest::probe(rvdevice, &hidapi))
+                            .collect(),
+                    );
+
+                    // allocate one LED map per device
+                    script::LED_MAPS.lock().resize(
+                        rvdevices.len(),
+                        vec![
+                            rvdevice::RGBA {
+                                r: 0,
+                                g: 0,
+                                b: 0,
+                                a: 0,
+                            };
+                            rvdevice::NUM_KEYS
+                        ],
+                    );
+
+                    // raw feature reports of the devices, for scripts and the D-Bus API
+                    scripting::raw_hid::initialize(hidapi.clone(), rvdevices.clone());
+
+                    // restore the configured exit state of the LEDs if the main thread or the
+                    // input thread panics, then abort. The daemon can not continue without them,
+                    // and terminating the process releases the exclusive grab of the keyboard.
+                    // Panics of other threads only end the respective thread
+                    let default_panic_hook = panic::take_hook();
+                    let panic_rvdevices = Mutex::new(rvdevices.clone());
+
+                    panic::set_hook(Box::new(move |info| {
+                        default_panic_hook(info);
+
+                        let thread = thread::current();
+                        if !matches!(thread.name(), Some("main") | Some("events")) {
+                            return;
+                        }
+
+                        // a second panic while restoring the LEDs must not deadlock the hook
+                        if let Some(mut rvdevices) = panic_rvdevices.try_lock() {
+                            for rvdevice in rvdevices.iter_mut() {
+                                // the LED device may be locked by the panicking thread itself,
+                                // the lock is held while writing
+                                rvdevice
+                                    .try_restore_exit_state(exit_state)
+                                    .unwrap_or_else(|e| {
+                                        eprintln!(
+                                            "Could not restore the exit state of the LEDs: {}",
+                                            e
+                                        )
+                                    });
+                            }
+                        }
+
+                        process::abort();
+                    }));
+
+                    // initialize the D-Bus API, it reports the default device
                     #[cfg(feature = "dbus")]
                     info!("Initializing D-Bus API...");
 
                     let (dbus_tx, dbus_rx) = channel();
+
+                    // the REST API requests profile switches via the channel of the D-Bus API
+                    #[cfg(feature = "rest")]
+                    rest::initialize(dbus_tx.clone())
+                        .unwrap_or_else(|e| error!("Could not initialize the REST API: {}", e));
+
                     #[cfg(feature = "dbus")]
-                    let dbus_api_tx = spawn_dbus_thread(dbus_tx).unwrap_or_else(|e| {
-                        error!("Could not spawn a thread: {}", e);
-                        panic!()
-                    });
+                    let dbus_api_tx = spawn_dbus_thread(dbus_tx, rvdevices[0].device_info.clone())
+                        .unwrap_or_else(|e| {
+                            error!("Could not spawn a thread: {}", e);
+                            panic!()
+                        });
+
+                    // the devices are an output sink like any other, the mock sink
+                    // replaces them if enabled. All frames are written via the sinks,
+                    // including the animations and the exit state
+                    if !sinks::is_mock_enabled() {
+                        sinks::register(Box::new(HidSink {
+                            hidapi: hidapi.clone(),
+                            rvdevices: rvdevices.clone(),
+                            exit_state,
+                            #[cfg(feature = "dbus")]
+                            dbus_api_tx: dbus_api_tx.clone(),
+                        }));
+                    }
+
+                    // play the startup animation, while the remaining subsystems are initialized
+                    let num_devices = rvdevices.len();
+                    let startup_animation =
+                        get_profile_animation(false).and_then(|(animation, color)| {
+                            thread::Builder::new()
+                                .name("animation".into())
+                                .spawn(move || animations::play(num_devices, animation, color, &[]))
+                                .map_err(|e| error!("Could not spawn a thread: {}", e))
+                                .ok()
+                        });
 
                     // initialize plugins
                     info!("Registering plugins...");
                     plugins::register_plugins()
                         .unwrap_or_else(|_e| error!("Could not register one or more plugins"));
 
+                    scripting::api::initialize()
+                        .unwrap_or_else(|e| error!("Invalid Lua API declarations: {}", e));
+
                     // spawn a thread to handle keyboard input
                     info!("Spawning input thread...");
 
                     let (kbd_tx, kbd_rx) = channel();
-                    spawn_input_thread(kbd_tx).unwrap_or_else(|e| {
-                        error!("Could not spawn a thread: {}", e);
-                        panic!()
-                    });
+
+                    // keep the sender alive if there is no input thread, otherwise
+                    // the main loop would exit on the disconnected channel
+                    let _kbd_tx = if selftest::has_capability(selftest::Capability::KeyEvents) {
+                        spawn_input_thread(kbd_tx).unwrap_or_else(|e| {
+                            error!("Could not spawn a thread: {}", e);
+                            panic!()
+                        });
+
+                        None
+                    } else {
+                        warn!("Key events are not available, scripts will not be notified of key presses");
+                        Some(kbd_tx)
+                    };
+
+                    // additional input sources, like evdev devices or the network. They
+                    // feed a channel of their own, so the main loop still exits when
+                    // the keyboard goes away
+                    let (inputs_tx, inputs_rx) = channel();
+
+                    inputs::initialize(&inputs_tx)
+                        .unwrap_or_else(|e| error!("Could not start an input source: {}", e));
+
+                    // spawn a thread per device to handle the special keys, like FN and Easy Shift
+                    for (index, rvdevice) in rvdevices.iter().enumerate() {
+                        if !selftest::get_capabilities(index).special_keys {
+                            warn!("Special keys are not available on device {}", index);
+                            continue;
+                        }
+
+                        match rvdevice.open_special_keys_device(&hidapi) {
+                            Ok(ctrl_dev) => {
+                                spawn_special_keys_thread(ctrl_dev).unwrap_or_else(|e| {
+                                    error!("Could not spawn a thread: {}", e);
+                                    panic!()
+                                })
+                            }
+
+                            Err(e) => warn!("Special keys will not be available: {}", e),
+                        }
+                    }
 
                     // spawn Lua VM threads
                     info!("Loading Lua scripts...");
 
-                    for (thread_idx, script_path) in script_paths.iter().enumerate() {
+                    for (thread_idx, (script_file, script_path)) in
+                        script_files.iter().zip(script_paths.iter()).enumerate()
+                    {
                         let script_path = script_path.clone();
+                        let target_device = get_target_device(
+                            ACTIVE_PROFILE.lock().as_ref(),
+                            script_file,
+                            &rvdevices,
+                        );
 
                         let (lua_tx, lua_rx) = channel();
-                        let result =
-                            spawn_lua_thread(thread_idx, lua_rx, script_path.clone(), &rvdevice);
+                        let result = spawn_lua_thread(
+                            thread_idx,
+                            lua_tx.clone(),
+                            lua_rx,
+                            script_path.clone(),
+                            &rvdevices,
+                            target_device,
+                        );
 
                         if result.is_err() {
                             error!("Could not spawn a Lua VM thread");
@@ -1097,15 +3890,27 @@ fn main() {
                     )
                     .unwrap_or_else(|e| error!("Could not register file changes watcher: {}", e));
 
+                    // the startup animation has to be completed before the scripts take over
+                    if let Some(handle) = startup_animation {
+                        handle
+                            .join()
+                            .unwrap_or_else(|_e| error!("The startup animation failed"));
+                    }
+
+                    // the main loop writes to the devices
+                    scheduling::apply("main");
+
                     // enter the main loop
                     run_main_loop(
-                        &mut rvdevice,
+                        &hidapi,
+                        &mut rvdevices,
                         #[cfg(feature = "dbus")]
                         &dbus_api_tx,
                         #[cfg(feature = "frontend")]
                         &frontend_rx,
                         &dbus_rx,
                         &kbd_rx,
+                        &inputs_rx,
                         &fsevents_rx,
                     );
 
@@ -1119,11 +3924,22 @@ fn main() {
                     // TODO: Ugly hack, find a better way to wait for exit of the Lua VMs
                     thread::sleep(Duration::from_millis(250));
 
+                    // play the shutdown animation, starting from the last frame
+                    if let Some((animation, color)) = get_profile_animation(true) {
+                        let led_maps = script::LED_MAPS.lock().clone();
+                        animations::play(rvdevices.len(), animation, color, &led_maps);
+                    }
+
+                    // leave the LEDs in the configured exit state
+                    sinks::close();
+
                     // close the control and LED devices
                     info!("Closing devices...");
-                    rvdevice.close_all().unwrap_or_else(|e| {
-                        warn!("Could not close the keyboard device: {}", e);
-                    });
+                    for rvdevice in rvdevices.iter_mut() {
+                        rvdevice.close_all().unwrap_or_else(|e| {
+                            warn!("Could not close the keyboard device: {}", e);
+                        });
+                    }
                 }
 
                 Err(_) => {
@@ -1143,5 +3959,8 @@ fn main() {
     debug!("Saving state...");
     state::save_runtime_state().unwrap_or_else(|e| error!("Could not save runtime state: {}", e));
 
+    capture::stop();
+    recorder::stop();
+
     info!("Exiting now");
 }
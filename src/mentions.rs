@@ -0,0 +1,319 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Mention notifications of chat clients. External programs, like a plugin of
+//! an IRC, Matrix or Discord client, write a line of the form `<source> <text>`
+//! to a named pipe (or POST it to the REST API), and the scripts are notified
+//! via the event handler `on_mention(source, text)`.
+//!
+//! The pipe is owned by the user of the active session on the seat, the number
+//! of mentions per minute is limited.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::paths;
+use crate::scripting::script;
+
+pub type Result<T> = std::result::Result<T, MentionError>;
+
+#[derive(Debug, Error)]
+pub enum MentionError {
+    #[error("Could not create the named pipe {path}: {description}")]
+    PipeError { path: String, description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+lazy_static! {
+    /// The path of the named pipe, if we listen for mentions
+    static ref PIPE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+    /// The times of the recent mentions, for the rate limit
+    static ref RECENT_MENTIONS: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+}
+
+/// Returns true if another mention may be passed to the scripts at `now`, and
+/// records the mention
+fn check_rate_limit(recent_mentions: &mut VecDeque<Instant>, now: Instant) -> bool {
+    let window = Duration::from_secs(60);
+
+    while recent_mentions
+        .front()
+        .map_or(false, |t| now.duration_since(*t) > window)
+    {
+        recent_mentions.pop_front();
+    }
+
+    if recent_mentions.len() >= constants::MAX_MENTIONS_PER_MINUTE {
+        return false;
+    }
+
+    recent_mentions.push_back(now);
+
+    true
+}
+
+/// Notify all scripts of a mention, `source` is e.g. the name of the chat network
+pub fn notify(source: &str, text: &str) {
+    if !check_rate_limit(&mut RECENT_MENTIONS.lock(), Instant::now()) {
+        warn!("Too many mentions, dropped the mention from {}", source);
+        return;
+    }
+
+    let text: String = text
+        .chars()
+        .take(constants::MAX_MENTION_TEXT_LENGTH)
+        .collect();
+
+    debug!("Mention from {}: {}", source, text);
+
+    for lua_tx in crate::LUA_TXS.lock().iter() {
+        lua_tx
+            .send(script::Message::Mention(source.to_string(), text.clone()))
+            .unwrap_or_else(|e| error!("Send error: {}", e));
+    }
+}
+
+/// Parse a line of the form `<source> <text>`, the text may be empty
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+
+    if line.is_empty() {
+        return None;
+    }
+
+    match line.find(char::is_whitespace) {
+        Some(pos) => Some((&line[..pos], line[pos..].trim_start())),
+        None => Some((line, "")),
+    }
+}
+
+/// Create the named pipe `path`, unless it already exists
+fn create_pipe(path: &Path) -> Result<()> {
+    let pipe_error = |description: String| MentionError::PipeError {
+        path: path.display().to_string(),
+        description,
+    };
+
+    let c_path =
+        CString::new(path.as_os_str().as_bytes()).map_err(|e| pipe_error(format!("{}", e)))?;
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_fifo() {
+            // the pipe may have been created with a less restrictive mode
+            unsafe { libc::chmod(c_path.as_ptr(), constants::MENTION_PIPE_MODE) };
+
+            return Ok(());
+        } else {
+            return Err(pipe_error("File exists and is not a named pipe".into()));
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| pipe_error(format!("{}", e)))?;
+    }
+
+    if unsafe { libc::mkfifo(c_path.as_ptr(), constants::MENTION_PIPE_MODE) } != 0 {
+        return Err(pipe_error(format!("{}", std::io::Error::last_os_error())));
+    }
+
+    // mkfifo() is subject to the umask of the daemon
+    unsafe { libc::chmod(c_path.as_ptr(), constants::MENTION_PIPE_MODE) };
+
+    Ok(())
+}
+
+/// Grant the user `uid` of the active session on the seat access to the named
+/// pipe, the pipe is handed back to root if nobody is logged in
+pub fn grant_pipe_access(uid: Option<u32>) {
+    if let Some(path) = PIPE.lock().as_ref() {
+        nix::unistd::chown(
+            path,
+            Some(nix::unistd::Uid::from_raw(uid.unwrap_or(0))),
+            Some(nix::unistd::Gid::from_raw(0)),
+        )
+        .unwrap_or_else(|e| warn!("Could not change the owner of {}: {}", path.display(), e));
+    }
+}
+
+/// Read a line of at most `max_len` bytes from `reader`, the rest of a longer line
+/// is discarded. Returns None at the end of the input
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<Option<String>> {
+    let mut line = vec![];
+    let mut found = false;
+
+    loop {
+        let (consumed, done) = {
+            let buf = reader.fill_buf()?;
+
+            if buf.is_empty() {
+                break;
+            }
+
+            found = true;
+
+            let (chunk, done) = match buf.iter().position(|b| *b == b'\n') {
+                Some(pos) => (&buf[..pos + 1], true),
+                None => (buf, false),
+            };
+
+            let remaining = max_len.saturating_sub(line.len());
+            line.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+
+            (chunk.len(), done)
+        };
+
+        reader.consume(consumed);
+
+        if done {
+            break;
+        }
+    }
+
+    if !found {
+        return Ok(None);
+    }
+
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+fn run_listener(path: PathBuf) {
+    loop {
+        // blocks until a writer opens the pipe, returns EOF when the last writer closes it
+        match File::open(&path) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+
+                loop {
+                    match read_line(&mut reader, constants::MAX_MENTION_LINE_LENGTH) {
+                        Ok(Some(line)) => {
+                            if let Some((source, text)) = parse_line(&line) {
+                                notify(source, text);
+                            }
+                        }
+
+                        Ok(None) => break,
+
+                        Err(e) => {
+                            warn!("Could not read from {}: {}", path.display(), e);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            Err(e) => {
+                error!("Could not open {}: {}", path.display(), e);
+                break;
+            }
+        }
+    }
+}
+
+/// Create the named pipe and listen for mentions, if enabled
+pub fn initialize() -> Result<()> {
+    let (enabled, path) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config.get_bool("mentions.enabled").unwrap_or(false),
+            config
+                .get_str("mentions.pipe")
+                .unwrap_or_else(|_| constants::DEFAULT_MENTION_PIPE.into()),
+        )
+    };
+
+    if !enabled {
+        return Ok(());
+    }
+
+    let path = PathBuf::from(path);
+    create_pipe(&path)?;
+
+    *PIPE.lock() = Some(path.clone());
+    grant_pipe_access(paths::get_seat_user().map(|user| user.uid));
+
+    info!("Listening for mentions on {}", path.display());
+
+    thread::Builder::new()
+        .name("mentions".into())
+        .spawn(move || run_listener(path))
+        .map_err(|_e| MentionError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("irc hello world"), Some(("irc", "hello world")));
+        assert_eq!(parse_line("matrix"), Some(("matrix", "")));
+        assert_eq!(parse_line("   "), None);
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader =
+            BufReader::with_capacity(4, "irc hi\nmatrix a very long line\n\nx".as_bytes());
+
+        assert_eq!(
+            read_line(&mut reader, 16).unwrap().as_deref(),
+            Some("irc hi\n")
+        );
+        assert_eq!(
+            read_line(&mut reader, 16).unwrap().as_deref(),
+            Some("matrix a very lo")
+        );
+        assert_eq!(read_line(&mut reader, 16).unwrap().as_deref(), Some("\n"));
+        assert_eq!(read_line(&mut reader, 16).unwrap().as_deref(), Some("x"));
+        assert_eq!(read_line(&mut reader, 16).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_rate_limit() {
+        let mut recent_mentions = VecDeque::new();
+        let start = Instant::now();
+
+        for _ in 0..constants::MAX_MENTIONS_PER_MINUTE {
+            assert!(check_rate_limit(&mut recent_mentions, start));
+        }
+
+        assert!(!check_rate_limit(&mut recent_mentions, start));
+        assert!(check_rate_limit(
+            &mut recent_mentions,
+            start + Duration::from_secs(61)
+        ));
+    }
+}
@@ -0,0 +1,105 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use thiserror::Error;
+use toml::value::{Table, Value};
+
+pub type Result<T> = std::result::Result<T, MigrationError>;
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("Not a valid profile")]
+    InvalidProfile {},
+
+    #[error("Invalid profile version: {version}")]
+    InvalidVersion { version: String },
+
+    #[error(
+        "The profile has version {version}, but only versions up to {supported} are supported"
+    )]
+    UnsupportedVersion { version: u32, supported: u32 },
+
+    #[error("Could not migrate the profile to version {version}: {description}")]
+    MigrationFailed { version: u32, description: String },
+}
+
+/// Version of the profile format, that is written by this version of the daemon.
+/// Bump this, and add a migration step below, whenever the format changes
+pub const PROFILE_VERSION: u32 = 1;
+
+/// A migration step, upgrades a profile from version `n` to version `n + 1`
+type Migration = fn(&mut Table) -> Result<()>;
+
+/// All migration steps, the step at index `n` upgrades version `n` to `n + 1`
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+/// Version 0 is the legacy format without a `version` field. Version 1 requires
+/// `active_scripts` to be a list, older profiles allowed a single file name
+fn migrate_v0_to_v1(profile: &mut Table) -> Result<()> {
+    if let Some(Value::String(script)) = profile.get("active_scripts") {
+        let scripts = vec![Value::String(script.clone())];
+        profile.insert("active_scripts".into(), Value::Array(scripts));
+    }
+
+    Ok(())
+}
+
+/// Returns the version of the profile `profile`, profiles without a `version` field have version 0
+pub fn get_version(profile: &Value) -> Result<u32> {
+    match profile.get("version") {
+        None => Ok(0),
+
+        Some(Value::Integer(version)) if *version >= 0 => Ok(*version as u32),
+
+        Some(version) => Err(MigrationError::InvalidVersion {
+            version: version.to_string(),
+        }),
+    }
+}
+
+/// Upgrade the parsed profile `profile` to the current version of the profile format.
+/// Returns true if the profile has been changed
+pub fn migrate(profile: &mut Value) -> Result<bool> {
+    let version = get_version(profile)?;
+
+    if version > PROFILE_VERSION {
+        return Err(MigrationError::UnsupportedVersion {
+            version,
+            supported: PROFILE_VERSION,
+        });
+    }
+
+    let table = profile
+        .as_table_mut()
+        .ok_or(MigrationError::InvalidProfile {})?;
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to = from as u32 + 1;
+
+        debug!("Migrating profile from version {} to version {}", from, to);
+
+        migration(table).map_err(|e| MigrationError::MigrationFailed {
+            version: to,
+            description: format!("{}", e),
+        })?;
+
+        table.insert("version".into(), Value::Integer(i64::from(to)));
+    }
+
+    Ok(version < PROFILE_VERSION)
+}
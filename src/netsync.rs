@@ -0,0 +1,455 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::{NUM_KEYS, RGBA};
+
+pub type Result<T> = std::result::Result<T, NetSyncError>;
+
+#[derive(Debug, Error)]
+pub enum NetSyncError {
+    #[error("Network error: {description}")]
+    SocketError { description: String },
+
+    #[error("Invalid network sync mode: {mode}")]
+    InvalidMode { mode: String },
+
+    #[error("Invalid network sync address: {address}")]
+    InvalidAddress { address: String },
+
+    #[error("Invalid network sync port: {port}")]
+    InvalidPort { port: i64 },
+
+    #[error("The network sync requires a shared key")]
+    MissingKey {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+impl From<std::io::Error> for NetSyncError {
+    fn from(e: std::io::Error) -> Self {
+        NetSyncError::SocketError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+/// Magic bytes at the start of each frame packet
+const MAGIC: &[u8; 4] = b"ERUF";
+
+/// Version of the wire format
+const PROTOCOL_VERSION: u8 = 2;
+
+/// Size of the header of a frame packet: magic, version and sequence number
+const HEADER_SIZE: usize = 4 + 1 + 4;
+
+/// Size of the HMAC-SHA256 of a packet, that authenticates the leader
+const TAG_SIZE: usize = 32;
+
+/// Size of a frame packet, colors are transmitted as RGB triplets, followed by the tag
+const PACKET_SIZE: usize = HEADER_SIZE + NUM_KEYS * 3 + TAG_SIZE;
+
+/// Role of this instance of the daemon
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyncMode {
+    /// Network sync is disabled
+    Off,
+
+    /// Broadcast the composited frames to the followers
+    Leader,
+
+    /// Display the frames received from the leader
+    Follower,
+}
+
+lazy_static! {
+    /// Role of this instance of the daemon
+    static ref SYNC_MODE: Mutex<SyncMode> = Mutex::new(SyncMode::Off);
+
+    /// Socket and destination address of the leader
+    static ref LEADER: Mutex<Option<(UdpSocket, SocketAddr)>> = Mutex::new(None);
+
+    /// Sequence number of the next frame sent by the leader
+    static ref SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+    /// Frames of the leader, delayed to compensate for the network latency
+    static ref DELAYED_FRAMES: Mutex<VecDeque<(Instant, Vec<RGBA>)>> = Mutex::new(VecDeque::new());
+
+    /// The frame that is currently displayed by the leader
+    static ref DISPLAYED_FRAME: Mutex<Option<Vec<RGBA>>> = Mutex::new(None);
+
+    /// The most recent frame received by a follower, and the time of its reception
+    static ref RECEIVED_FRAME: Mutex<Option<(Instant, Vec<RGBA>)>> = Mutex::new(None);
+
+    /// Latency compensation of the leader
+    static ref DELAY: Mutex<Duration> = Mutex::new(Duration::from_millis(0));
+
+    /// The key shared by the leader and the followers, that authenticates the frames
+    static ref KEY: Mutex<Vec<u8>> = Mutex::new(vec![]);
+}
+
+/// Load the network sync configuration from the `[sync]` section of the
+/// configuration file, and set up the network socket
+pub fn initialize() -> Result<()> {
+    let (mode, address, port, delay, key) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config.get_str("sync.mode").unwrap_or_else(|_| "off".into()),
+            config
+                .get_str("sync.address")
+                .unwrap_or_else(|_| constants::DEFAULT_SYNC_ADDRESS.into()),
+            config
+                .get_int("sync.port")
+                .unwrap_or(constants::DEFAULT_SYNC_PORT as i64),
+            config.get_int("sync.latency_millis").unwrap_or(0),
+            config.get_str("sync.key").unwrap_or_default(),
+        )
+    };
+
+    let mode = match mode.as_str() {
+        "off" => return Ok(()),
+        "leader" => SyncMode::Leader,
+        "follower" => SyncMode::Follower,
+
+        _ => return Err(NetSyncError::InvalidMode { mode }),
+    };
+
+    let port = u16::try_from(port).map_err(|_e| NetSyncError::InvalidPort { port })?;
+
+    if key.is_empty() {
+        return Err(NetSyncError::MissingKey {});
+    }
+
+    *KEY.lock() = key.into_bytes();
+
+    let delay = clamp_latency(delay);
+
+    let ip = address
+        .parse::<IpAddr>()
+        .map_err(|_e| NetSyncError::InvalidAddress {
+            address: address.clone(),
+        })?;
+
+    match mode {
+        SyncMode::Leader => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            socket.set_nonblocking(true)?;
+            socket.set_multicast_ttl_v4(constants::SYNC_MULTICAST_TTL)?;
+
+            *LEADER.lock() = Some((socket, SocketAddr::new(ip, port)));
+            *DELAY.lock() = Duration::from_millis(delay);
+
+            info!(
+                "Network sync: Leader, sending frames to {}:{}, latency compensation: {} ms",
+                ip, port, delay
+            );
+        }
+
+        SyncMode::Follower => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+
+            if let IpAddr::V4(group) = ip {
+                if group.is_multicast() {
+                    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+                }
+            }
+
+            spawn_receiver_thread(socket)?;
+
+            info!("Network sync: Follower, receiving frames on port {}", port);
+        }
+
+        SyncMode::Off => (),
+    }
+
+    *SYNC_MODE.lock() = mode;
+
+    Ok(())
+}
+
+/// Clamp the latency compensation `millis` of the configuration file to the
+/// supported range
+fn clamp_latency(millis: i64) -> u64 {
+    let result = millis.max(0).min(constants::SYNC_MAX_LATENCY_MILLIS as i64) as u64;
+
+    if result as i64 != millis {
+        warn!(
+            "Network sync: The latency compensation has been clamped to {} ms",
+            result
+        );
+    }
+
+    result
+}
+
+/// Compute the HMAC-SHA256 of `message` with the key `key`, see RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; TAG_SIZE] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+
+    if key.len() > BLOCK_SIZE {
+        block[..TAG_SIZE].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.input(&block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.input(message);
+
+    let mut outer = Sha256::new();
+    outer.input(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(&inner.result());
+
+    let mut result = [0u8; TAG_SIZE];
+    result.copy_from_slice(&outer.result());
+
+    result
+}
+
+/// Compare the tags `lhs` and `rhs` in constant time
+fn tags_equal(lhs: &[u8], rhs: &[u8]) -> bool {
+    lhs.len() == rhs.len()
+        && lhs
+            .iter()
+            .zip(rhs.iter())
+            .fold(0, |acc, (l, r)| acc | (l ^ r))
+            == 0
+}
+
+/// Returns the role of this instance of the daemon
+pub fn get_sync_mode() -> SyncMode {
+    *SYNC_MODE.lock()
+}
+
+/// Encode the frame `led_map` into a packet with the sequence number `sequence`,
+/// that is authenticated with the shared key `key`
+fn encode_frame(sequence: u32, led_map: &[RGBA], key: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(PACKET_SIZE);
+
+    result.extend_from_slice(MAGIC);
+    result.push(PROTOCOL_VERSION);
+    result.extend_from_slice(&sequence.to_be_bytes());
+
+    for color in led_map.iter().take(NUM_KEYS) {
+        result.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+
+    let tag = hmac_sha256(key, &result);
+    result.extend_from_slice(&tag);
+
+    result
+}
+
+/// Decode a frame packet, returns the sequence number and the frame, or None if
+/// the packet is invalid or has not been authenticated with the shared key `key`
+fn decode_frame(buf: &[u8], key: &[u8]) -> Option<(u32, Vec<RGBA>)> {
+    if buf.len() != PACKET_SIZE || &buf[0..4] != MAGIC || buf[4] != PROTOCOL_VERSION {
+        return None;
+    }
+
+    let (data, tag) = buf.split_at(PACKET_SIZE - TAG_SIZE);
+
+    if !tags_equal(&hmac_sha256(key, data), tag) {
+        return None;
+    }
+
+    let sequence = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]);
+
+    let led_map = data[HEADER_SIZE..]
+        .chunks(3)
+        .map(|c| RGBA {
+            r: c[0],
+            g: c[1],
+            b: c[2],
+            a: 0xff,
+        })
+        .collect();
+
+    Some((sequence, led_map))
+}
+
+/// Spawn a thread that receives the frames of the leader
+fn spawn_receiver_thread(socket: UdpSocket) -> Result<()> {
+    let builder = thread::Builder::new().name("netsync".into());
+    builder
+        .spawn(move || {
+            let mut buf = [0u8; PACKET_SIZE];
+            let mut last_sequence: Option<u32> = None;
+
+            let key = KEY.lock().clone();
+
+            loop {
+                match socket.recv_from(&mut buf) {
+                    Ok((len, _src)) => match decode_frame(&buf[..len], &key) {
+                        Some((sequence, led_map)) => {
+                            // drop frames that arrived out of order, the leader may
+                            // have been restarted though, so treat large gaps as a reset
+                            if let Some(last) = last_sequence {
+                                let diff = sequence.wrapping_sub(last) as i32;
+
+                                if diff <= 0 && diff > -(constants::SYNC_MAX_REORDER as i32) {
+                                    trace!("Network sync: Dropped a stale frame: {}", sequence);
+                                    continue;
+                                }
+                            }
+
+                            last_sequence = Some(sequence);
+                            *RECEIVED_FRAME.lock() = Some((Instant::now(), led_map));
+                        }
+
+                        None => {
+                            trace!("Network sync: Ignored an invalid or unauthenticated packet")
+                        }
+                    },
+
+                    Err(e) => {
+                        error!("Network sync: Could not receive a frame: {}", e);
+                        thread::sleep(Duration::from_millis(constants::SYNC_TIMEOUT_MILLIS));
+                    }
+                }
+            }
+        })
+        .map_err(|_e| NetSyncError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// Process the composited frame `led_map` of the default device, right before
+/// it is sent to the device. The leader broadcasts the frame and delays its own
+/// output to compensate for the network latency. Followers replace the frame
+/// with the one received from the leader, as long as the leader is present
+pub fn process_frame(led_map: &mut [RGBA]) {
+    match get_sync_mode() {
+        SyncMode::Off => (),
+
+        SyncMode::Leader => {
+            if let Some((socket, address)) = LEADER.lock().as_ref() {
+                let sequence = SEQUENCE.fetch_add(1, Ordering::SeqCst);
+                let packet = encode_frame(sequence, led_map, &KEY.lock());
+
+                socket.send_to(&packet, address).unwrap_or_else(|e| {
+                    trace!("Network sync: Could not send a frame: {}", e);
+                    0
+                });
+            }
+
+            let delay = *DELAY.lock();
+            if delay > Duration::from_millis(0) {
+                let mut delayed_frames = DELAYED_FRAMES.lock();
+                let mut displayed_frame = DISPLAYED_FRAME.lock();
+
+                delayed_frames.push_back((Instant::now(), led_map.to_vec()));
+
+                // an excess of frames is displayed early, rather than queued unbounded
+                while let Some((timestamp, _)) = delayed_frames.front() {
+                    if timestamp.elapsed() < delay
+                        && delayed_frames.len() <= constants::SYNC_MAX_DELAYED_FRAMES
+                    {
+                        break;
+                    }
+
+                    *displayed_frame = delayed_frames.pop_front().map(|(_, frame)| frame);
+                }
+
+                if let Some(frame) = displayed_frame.as_ref() {
+                    led_map.copy_from_slice(frame);
+                }
+            }
+        }
+
+        SyncMode::Follower => {
+            if let Some((timestamp, frame)) = RECEIVED_FRAME.lock().as_ref() {
+                // fall back to the local effects, if the leader is gone
+                if timestamp.elapsed() < Duration::from_millis(constants::SYNC_TIMEOUT_MILLIS) {
+                    led_map.copy_from_slice(frame);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256() {
+        // test case 2 of RFC 4231
+        let tag = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+
+        let hex: String = tag.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_frame() {
+        let led_map = vec![
+            RGBA {
+                r: 0x12,
+                g: 0x34,
+                b: 0x56,
+                a: 0xff,
+            };
+            NUM_KEYS
+        ];
+
+        let packet = encode_frame(42, &led_map, b"secret");
+        assert_eq!(packet.len(), PACKET_SIZE);
+
+        assert_eq!(decode_frame(&packet, b"secret"), Some((42, led_map)));
+
+        // frames of peers without the shared key are rejected
+        assert_eq!(decode_frame(&packet, b"other"), None);
+
+        let mut tampered = packet.clone();
+        tampered[HEADER_SIZE] ^= 0xff;
+        assert_eq!(decode_frame(&tampered, b"secret"), None);
+
+        assert_eq!(decode_frame(&packet[..PACKET_SIZE - 1], b"secret"), None);
+    }
+
+    #[test]
+    fn test_clamp_latency() {
+        assert_eq!(clamp_latency(0), 0);
+        assert_eq!(clamp_latency(50), 50);
+        assert_eq!(clamp_latency(-50), 0);
+        assert_eq!(
+            clamp_latency(i64::max_value()),
+            constants::SYNC_MAX_LATENCY_MILLIS
+        );
+    }
+}
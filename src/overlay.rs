@@ -0,0 +1,586 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use evdev_rs::enums::EV_KEY;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::accessibility;
+use crate::constants;
+use crate::plugins::pomodoro::Phase;
+use crate::plugins::PomodoroPlugin;
+use crate::profiles::Profile;
+use crate::rvdevice::RGBA;
+use crate::util;
+
+lazy_static! {
+    /// The overlay that is currently displayed, if any
+    static ref ACTIVE_OVERLAY: Mutex<Option<ActiveOverlay>> = Mutex::new(None);
+
+    /// The most recently reported volume, in percent, and the mute state
+    static ref LAST_VOLUME: Mutex<Option<(isize, bool)>> = Mutex::new(None);
+
+    /// Global "on screen display enabled" flag
+    pub static ref ENABLE_OSD: AtomicBool = AtomicBool::new(true);
+
+    /// Blink a key while there are unacknowledged errors, e.g. of scripts
+    static ref ENABLE_ERROR_INDICATOR: AtomicBool = AtomicBool::new(false);
+
+    /// Color of the error indicator
+    static ref ERROR_INDICATOR_COLOR: Mutex<RGBA> = Mutex::new(RGBA {
+        r: 0xff,
+        g: 0x80,
+        b: 0x00,
+        a: 0xff,
+    });
+
+    /// Show the remaining time of the pomodoro timer, enabled by the active profile
+    static ref ENABLE_FOCUS_TIMER: AtomicBool = AtomicBool::new(false);
+
+    /// Keys of the number row, the volume bar is drawn across them
+    static ref NUMBER_ROW: Vec<usize> = [
+        EV_KEY::KEY_1,
+        EV_KEY::KEY_2,
+        EV_KEY::KEY_3,
+        EV_KEY::KEY_4,
+        EV_KEY::KEY_5,
+        EV_KEY::KEY_6,
+        EV_KEY::KEY_7,
+        EV_KEY::KEY_8,
+        EV_KEY::KEY_9,
+        EV_KEY::KEY_0,
+        EV_KEY::KEY_MINUS,
+        EV_KEY::KEY_EQUAL,
+    ]
+    .iter()
+    .filter_map(|key| util::get_key_index(key.clone() as u32))
+    .map(|index| index as usize)
+    .collect();
+
+    /// Keys of the function key row
+    static ref FUNCTION_ROW: Vec<usize> = [
+        EV_KEY::KEY_F1,
+        EV_KEY::KEY_F2,
+        EV_KEY::KEY_F3,
+        EV_KEY::KEY_F4,
+        EV_KEY::KEY_F5,
+        EV_KEY::KEY_F6,
+        EV_KEY::KEY_F7,
+        EV_KEY::KEY_F8,
+        EV_KEY::KEY_F9,
+        EV_KEY::KEY_F10,
+        EV_KEY::KEY_F11,
+        EV_KEY::KEY_F12,
+    ]
+    .iter()
+    .filter_map(|key| util::get_key_index(key.clone() as u32))
+    .map(|index| index as usize)
+    .collect();
+
+    /// Keys of the top letter row
+    static ref TOP_ROW: Vec<usize> = [
+        EV_KEY::KEY_Q,
+        EV_KEY::KEY_W,
+        EV_KEY::KEY_E,
+        EV_KEY::KEY_R,
+        EV_KEY::KEY_T,
+        EV_KEY::KEY_Y,
+        EV_KEY::KEY_U,
+        EV_KEY::KEY_I,
+        EV_KEY::KEY_O,
+        EV_KEY::KEY_P,
+        EV_KEY::KEY_LEFTBRACE,
+        EV_KEY::KEY_RIGHTBRACE,
+    ]
+    .iter()
+    .filter_map(|key| util::get_key_index(key.clone() as u32))
+    .map(|index| index as usize)
+    .collect();
+
+    /// The row of keys that the progress bar is drawn across, from the `[osd]` section
+    static ref PROGRESS_ROW: Mutex<ProgressRow> = Mutex::new(ProgressRow::Function);
+
+    /// The blink patterns that are currently played, they are owned by the daemon,
+    /// so that they complete even if the requesting script has been unloaded
+    static ref BLINK_PATTERNS: Mutex<Vec<BlinkPattern>> = Mutex::new(vec![]);
+}
+
+/// Rows of keys that the progress bar may be drawn across
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProgressRow {
+    Function,
+    Number,
+    Top,
+}
+
+impl ProgressRow {
+    fn keys(self) -> &'static [usize] {
+        match self {
+            ProgressRow::Function => &FUNCTION_ROW,
+            ProgressRow::Number => &NUMBER_ROW,
+            ProgressRow::Top => &TOP_ROW,
+        }
+    }
+}
+
+/// Overlay effects that are rendered by the daemon itself, on top of
+/// the effects of the Lua scripts
+#[derive(Debug, Copy, Clone)]
+pub enum Overlay {
+    /// A volume bar, volume in percent
+    Volume { percent: isize, muted: bool },
+
+    /// A progress bar, progress in percent
+    Progress { percent: f64, color: RGBA },
+}
+
+/// A pattern of on and off steps of equal length, played once on a set of keys
+struct BlinkPattern {
+    keys: Vec<usize>,
+    steps: Vec<bool>,
+    unit: Duration,
+    color: RGBA,
+    since: Instant,
+}
+
+impl BlinkPattern {
+    fn duration(&self) -> Duration {
+        self.unit
+            .checked_mul(self.steps.len() as u32)
+            .unwrap_or_else(|| {
+                Duration::from_millis(
+                    constants::MAX_BLINK_PATTERN_UNIT_MILLIS
+                        * constants::MAX_BLINK_PATTERN_STEPS as u64,
+                )
+            })
+    }
+}
+
+struct ActiveOverlay {
+    overlay: Overlay,
+    since: Instant,
+    duration: Duration,
+}
+
+/// Load the configuration of the overlay effects
+pub fn initialize() {
+    let enabled = crate::CONFIG
+        .lock()
+        .as_ref()
+        .unwrap()
+        .get::<bool>("osd.enabled")
+        .unwrap_or(true);
+
+    ENABLE_OSD.store(enabled, Ordering::SeqCst);
+
+    let progress_row = crate::CONFIG
+        .lock()
+        .as_ref()
+        .unwrap()
+        .get_str("osd.progress_row")
+        .unwrap_or_else(|_| "function".into());
+
+    let (error_indicator, error_indicator_color) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config.get::<bool>("osd.error_indicator").unwrap_or(false),
+            config.get_int("osd.error_indicator_color").ok(),
+        )
+    };
+
+    ENABLE_ERROR_INDICATOR.store(error_indicator, Ordering::SeqCst);
+
+    if let Some(color) = error_indicator_color {
+        *ERROR_INDICATOR_COLOR.lock() = RGBA {
+            r: ((color >> 16) & 0xff) as u8,
+            g: ((color >> 8) & 0xff) as u8,
+            b: (color & 0xff) as u8,
+            a: 0xff,
+        };
+    }
+
+    *PROGRESS_ROW.lock() = match progress_row.as_str() {
+        "function" => ProgressRow::Function,
+        "number" => ProgressRow::Number,
+        "top" => ProgressRow::Top,
+
+        _ => {
+            warn!("Invalid progress bar row: {}", progress_row);
+            ProgressRow::Function
+        }
+    };
+}
+
+/// Enable or disable the focus timer, as requested by the profile `profile`
+pub fn apply_profile(profile: &Profile) {
+    ENABLE_FOCUS_TIMER.store(profile.focus_timer.unwrap_or(false), Ordering::SeqCst);
+}
+
+/// Display the overlay `overlay`, replacing the current one
+pub fn show(overlay: Overlay) {
+    if !ENABLE_OSD.load(Ordering::SeqCst) {
+        return;
+    }
+
+    show_for(
+        overlay,
+        Duration::from_millis(constants::OSD_DURATION_MILLIS),
+    );
+}
+
+/// Display the overlay `overlay` for `duration`, replacing the current one. This
+/// ignores `osd.enabled`, it is used for overlays that have been explicitly requested
+pub fn show_for(overlay: Overlay, duration: Duration) {
+    trace!("Showing overlay: {:?} for {:?}", overlay, duration);
+
+    *ACTIVE_OVERLAY.lock() = Some(ActiveOverlay {
+        overlay,
+        since: Instant::now(),
+        duration,
+    });
+}
+
+/// Display a progress bar of `percent` percent in the color `color` for `duration`
+pub fn show_progress(percent: f64, color: RGBA, duration: Duration) {
+    show_for(Overlay::Progress { percent, color }, duration);
+}
+
+/// Hide the progress bar, if it is currently displayed
+pub fn hide_progress() {
+    let mut active_overlay = ACTIVE_OVERLAY.lock();
+
+    if let Some(ActiveOverlay {
+        overlay: Overlay::Progress { .. },
+        ..
+    }) = *active_overlay
+    {
+        *active_overlay = None;
+    }
+}
+
+/// Parse the blink pattern `pattern` into on and off steps. A pattern is either a
+/// bitstring like "1100110", one step per digit, or morse code like "... --- ...",
+/// where a dot lasts one step, a dash three steps and elements are separated by one
+/// step. A space separates letters (three steps), a slash separates words (seven
+/// steps)
+pub fn parse_blink_pattern(pattern: &str) -> Option<Vec<bool>> {
+    let pattern = pattern.trim();
+
+    if pattern.is_empty() {
+        return None;
+    }
+
+    if pattern.chars().all(|c| c == '0' || c == '1') {
+        return Some(pattern.chars().map(|c| c == '1').collect());
+    }
+
+    // pad the gap at the end of `steps` to `len` steps
+    fn gap(steps: &mut Vec<bool>, len: usize) {
+        let current = steps.iter().rev().take_while(|on| !**on).count();
+
+        for _ in current..len {
+            steps.push(false);
+        }
+    }
+
+    let mut steps = vec![];
+
+    for c in pattern.chars() {
+        match c {
+            '.' | '-' => {
+                if !steps.is_empty() {
+                    gap(&mut steps, 1);
+                }
+
+                let len = if c == '.' { 1 } else { 3 };
+                steps.extend(std::iter::repeat(true).take(len));
+            }
+
+            ' ' => gap(&mut steps, 3),
+            '/' => gap(&mut steps, 7),
+
+            _ => return None,
+        }
+    }
+
+    // the final gap makes consecutive patterns distinguishable
+    gap(&mut steps, 1);
+
+    Some(steps)
+}
+
+/// Play the blink pattern `pattern` once on the keys `keys`, each step lasts
+/// `unit`, clamped to the limits of the length of a step. Returns false if the
+/// pattern is invalid, or too many patterns are being played already
+pub fn show_blink_pattern(keys: &[usize], pattern: &str, unit: Duration, color: RGBA) -> bool {
+    let unit = unit
+        .max(Duration::from_millis(
+            constants::MIN_BLINK_PATTERN_UNIT_MILLIS,
+        ))
+        .min(Duration::from_millis(
+            constants::MAX_BLINK_PATTERN_UNIT_MILLIS,
+        ));
+
+    let steps = match parse_blink_pattern(pattern) {
+        Some(steps) if steps.len() <= constants::MAX_BLINK_PATTERN_STEPS => steps,
+        _ => return false,
+    };
+
+    let mut patterns = BLINK_PATTERNS.lock();
+
+    if patterns.len() >= constants::MAX_BLINK_PATTERNS {
+        return false;
+    }
+
+    trace!("Playing blink pattern: {} on {:?}", pattern, keys);
+
+    patterns.push(BlinkPattern {
+        keys: keys.to_vec(),
+        steps,
+        unit,
+        color,
+        since: Instant::now(),
+    });
+
+    true
+}
+
+/// Draw the steps of the blink patterns that are currently played, keys are dark
+/// during off steps. Patterns that completed are removed
+fn render_blink_patterns(led_map: &mut [RGBA]) {
+    let mut patterns = BLINK_PATTERNS.lock();
+
+    patterns.retain(|pattern| pattern.since.elapsed() < pattern.duration());
+
+    for pattern in patterns.iter() {
+        let step = (pattern.since.elapsed().as_micros() / pattern.unit.as_micros().max(1)) as usize;
+
+        let color = if pattern.steps.get(step).copied().unwrap_or(false) {
+            accessibility::status_color(pattern.color)
+        } else {
+            RGBA {
+                r: 0x00,
+                g: 0x00,
+                b: 0x00,
+                a: 0xff,
+            }
+        };
+
+        for index in pattern.keys.iter() {
+            if let Some(led) = led_map.get_mut(*index) {
+                *led = color;
+            }
+        }
+    }
+}
+
+/// Record the current volume, and display the volume bar if it changed
+pub fn update_volume(percent: isize, muted: bool) {
+    let mut last_volume = LAST_VOLUME.lock();
+
+    // the first report only establishes the initial state
+    if last_volume.is_some() && *last_volume != Some((percent, muted)) {
+        show(Overlay::Volume { percent, muted });
+    }
+
+    *last_volume = Some((percent, muted));
+}
+
+/// Display the volume bar for the most recently reported volume, if known
+pub fn show_last_volume() {
+    if let Some((percent, muted)) = *LAST_VOLUME.lock() {
+        show(Overlay::Volume { percent, muted });
+    }
+}
+
+/// Draw the remaining time of the pomodoro timer across the number row, in steps
+/// of full minutes. The lit segment shrinks from right to left as the phase elapses
+fn render_focus_timer(led_map: &mut [RGBA]) {
+    let (phase, remaining, duration) = match PomodoroPlugin::get_progress() {
+        Some(progress) => progress,
+        None => return,
+    };
+
+    let remaining_mins = (remaining + 59) / 60;
+    let duration_mins = ((duration + 59) / 60).max(1);
+
+    let num_keys = NUMBER_ROW.len() as u32;
+    let num_lit = (num_keys * remaining_mins + duration_mins - 1) / duration_mins;
+
+    let color = match phase {
+        Phase::Work => RGBA {
+            r: 0xff,
+            g: 0x40,
+            b: 0x00,
+            a: 0xff,
+        },
+
+        Phase::Break => RGBA {
+            r: 0x00,
+            g: 0xff,
+            b: 0x00,
+            a: 0xff,
+        },
+    };
+
+    let color = accessibility::status_color(color);
+
+    for (i, index) in NUMBER_ROW.iter().enumerate() {
+        if (i as u32) < num_lit {
+            led_map[*index] = color;
+        }
+    }
+}
+
+/// Blink the ESC key while there are errors, that have not been acknowledged
+/// via `eruption errors ack`
+fn render_error_indicator(led_map: &mut [RGBA]) {
+    if !crate::logging::has_unacknowledged_errors() {
+        return;
+    }
+
+    let phase = crate::scripting::script::START_TIME.elapsed().as_millis()
+        % constants::ERROR_INDICATOR_PERIOD_MILLIS;
+
+    let color = if phase < constants::ERROR_INDICATOR_PERIOD_MILLIS / 2 {
+        accessibility::status_color(*ERROR_INDICATOR_COLOR.lock())
+    } else {
+        RGBA {
+            r: 0x00,
+            g: 0x00,
+            b: 0x00,
+            a: 0xff,
+        }
+    };
+
+    if let Some(led) = util::get_key_index(EV_KEY::KEY_ESC as u32)
+        .and_then(|index| led_map.get_mut(index as usize))
+    {
+        *led = color;
+    }
+}
+
+/// Blend the active overlay into `led_map`. The overlay is shown for its
+/// duration, and then faded out over OSD_FADE_MILLIS. The focus timer, the
+/// blink patterns and the error indicator are drawn below the other overlays
+pub fn render(led_map: &mut [RGBA]) {
+    if ENABLE_FOCUS_TIMER.load(Ordering::SeqCst) {
+        render_focus_timer(led_map);
+    }
+
+    render_blink_patterns(led_map);
+
+    if ENABLE_ERROR_INDICATOR.load(Ordering::SeqCst) {
+        render_error_indicator(led_map);
+    }
+
+    let mut active_overlay = ACTIVE_OVERLAY.lock();
+
+    let opacity = match active_overlay.as_ref() {
+        Some(active) => {
+            let elapsed = active.since.elapsed();
+            let duration = active.duration;
+
+            if elapsed < duration {
+                1.0
+            } else {
+                let fade = (elapsed - duration).as_millis() as f64;
+                1.0 - fade / constants::OSD_FADE_MILLIS as f64
+            }
+        }
+
+        None => return,
+    };
+
+    if opacity <= 0.0 {
+        *active_overlay = None;
+        return;
+    }
+
+    match active_overlay.as_ref().unwrap().overlay {
+        Overlay::Volume { percent, muted } => {
+            let num_lit = (NUMBER_ROW.len() as isize * percent.max(0).min(100) + 50) / 100;
+
+            for (i, index) in NUMBER_ROW.iter().enumerate() {
+                let color = if muted {
+                    RGBA {
+                        r: 0x80,
+                        g: 0x00,
+                        b: 0x00,
+                        a: 0xff,
+                    }
+                } else if (i as isize) < num_lit {
+                    // from green to red, as the volume increases
+                    let p = i as f64 / (NUMBER_ROW.len() - 1).max(1) as f64;
+
+                    RGBA {
+                        r: (p * 255.0) as u8,
+                        g: ((1.0 - p) * 255.0) as u8,
+                        b: 0x00,
+                        a: 0xff,
+                    }
+                } else {
+                    RGBA {
+                        r: 0x00,
+                        g: 0x00,
+                        b: 0x00,
+                        a: 0xff,
+                    }
+                };
+
+                let color = accessibility::status_color(color);
+                led_map[*index] = blend(led_map[*index], color, opacity);
+            }
+        }
+
+        Overlay::Progress { percent, color } => {
+            let keys = PROGRESS_ROW.lock().keys();
+            let progress = keys.len() as f64 * percent.max(0.0).min(100.0) / 100.0;
+            let color = accessibility::status_color(color);
+
+            for (i, index) in keys.iter().enumerate() {
+                // the last lit key is partially lit, to show fractional progress
+                let p = (progress - i as f64).max(0.0).min(1.0);
+
+                let color = RGBA {
+                    r: (f64::from(color.r) * p) as u8,
+                    g: (f64::from(color.g) * p) as u8,
+                    b: (f64::from(color.b) * p) as u8,
+                    a: 0xff,
+                };
+
+                led_map[*index] = blend(led_map[*index], color, opacity);
+            }
+        }
+    }
+}
+
+fn blend(from: RGBA, to: RGBA, p: f64) -> RGBA {
+    let mix = |a: u8, b: u8| (f64::from(a) * (1.0 - p) + f64::from(b) * p) as u8;
+
+    RGBA {
+        r: mix(from.r, to.r),
+        g: mix(from.g, to.g),
+        b: mix(from.b, to.b),
+        a: mix(from.a, to.a),
+    }
+}
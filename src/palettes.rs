@@ -0,0 +1,58 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! The palette library, named lists of colors that are shared by all scripts, e.g.
+//! the palette of the desktop wallpaper. Scripts get a palette via
+//! `get_palette(name)`, and are notified via `on_palette_changed(name)` when a
+//! palette is published or replaced.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+
+use crate::scripting::script;
+
+lazy_static! {
+    /// The palettes, colors are in the format of the Lua API (0xAARRGGBB)
+    static ref PALETTES: RwLock<BTreeMap<String, Vec<u32>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Publish the palette `name`, all scripts are notified if it changed
+pub fn set_palette(name: &str, colors: Vec<u32>) {
+    let previous = PALETTES.write().insert(name.to_string(), colors.clone());
+
+    if previous.as_ref() != Some(&colors) {
+        debug!("Palette '{}' changed: {:08x?}", name, colors);
+
+        for lua_tx in crate::LUA_TXS.lock().iter() {
+            lua_tx
+                .send(script::Message::PaletteChanged(name.to_string()))
+                .unwrap_or_else(|e| error!("Send error: {}", e));
+        }
+    }
+}
+
+/// Get the colors of the palette `name`
+pub fn get_palette(name: &str) -> Option<Vec<u32>> {
+    PALETTES.read().get(name).cloned()
+}
+
+/// Get the names of all palettes, sorted
+pub fn get_palette_names() -> Vec<String> {
+    PALETTES.read().keys().cloned().collect()
+}
@@ -0,0 +1,328 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+use crate::constants;
+use crate::profiles;
+
+pub type Result<T> = std::result::Result<T, PathsError>;
+
+#[derive(Debug, Error)]
+pub enum PathsError {
+    #[error("Path is outside of the search path: {path}")]
+    InvalidPath { path: String },
+}
+
+lazy_static! {
+    /// The user of the active session on our seat, if any
+    static ref SEAT_USER: RwLock<Option<SeatUser>> = RwLock::new(None);
+}
+
+/// The user of the active session on the seat that the devices are attached to
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeatUser {
+    pub uid: u32,
+    pub name: String,
+    pub home: PathBuf,
+}
+
+impl SeatUser {
+    /// The primary group of the user, as specified by the password database
+    pub fn gid(&self) -> Option<u32> {
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+        let rc = unsafe {
+            libc::getpwuid_r(
+                self.uid,
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        if rc != 0 || result.is_null() {
+            None
+        } else {
+            Some(passwd.pw_gid)
+        }
+    }
+}
+
+/// Returns the user of the active session on our seat, if any
+pub fn get_seat_user() -> Option<SeatUser> {
+    SEAT_USER.read().clone()
+}
+
+/// Set the user of the active session on our seat, returns true if the user changed
+pub fn set_seat_user(user: Option<SeatUser>) -> bool {
+    let mut seat_user = SEAT_USER.write();
+
+    if *seat_user == user {
+        return false;
+    }
+
+    match &user {
+        Some(user) => info!("User '{}' is now active on the seat", user.name),
+        None => info!("No user is active on the seat"),
+    }
+
+    *seat_user = user;
+
+    true
+}
+
+fn get_config_dir(key: &str, default: &str) -> PathBuf {
+    PathBuf::from(
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|config| config.get_str(key).ok())
+            .unwrap_or_else(|| default.to_string()),
+    )
+}
+
+/// Get the directory `name` in the configuration directory of the active seat user
+fn get_user_dir(name: &str) -> Option<PathBuf> {
+    get_seat_user().map(|user| user.home.join(constants::USER_CONFIG_DIR).join(name))
+}
+
+/// Get the directory `name` in the configuration directory of the user running
+/// this process, e.g. for the command line tools
+pub fn get_local_user_dir(name: &str) -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(constants::USER_CONFIG_DIR)
+            .join(name)
+    })
+}
+
+/// Returns the profile search path, in ascending order of precedence: the default
+/// profile directory, the system-wide directory in /etc and the profile directory
+/// of the user of the active session on our seat
+pub fn get_profile_dirs() -> Vec<PathBuf> {
+    let mut result = vec![
+        get_config_dir("global.profile_dir", constants::DEFAULT_PROFILE_DIR),
+        PathBuf::from(constants::SYSTEM_PROFILE_DIR),
+    ];
+
+    result.extend(get_user_dir("profiles"));
+
+    result
+}
+
+/// Returns the script search path, in ascending order of precedence. Scripts of
+/// users are only searched if `global.allow_user_scripts` is enabled, since they
+/// would be executed with the privileges of the daemon
+pub fn get_script_dirs() -> Vec<PathBuf> {
+    let mut result = vec![
+        get_config_dir("global.script_dir", constants::DEFAULT_SCRIPT_DIR),
+        PathBuf::from(constants::SYSTEM_SCRIPT_DIR),
+    ];
+
+    let allow_user_scripts = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get::<bool>("global.allow_user_scripts").ok())
+        .unwrap_or(false);
+
+    if allow_user_scripts {
+        result.extend(get_user_dir("scripts"));
+    }
+
+    result
+}
+
+/// Returns the canonical form of `path`, if it lies below one of the directories
+/// `dirs`, after resolving all symbolic links
+fn canonicalize_below(path: &Path, dirs: &[PathBuf]) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+
+    if dirs
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| path.starts_with(dir))
+    {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Find `file` in the search path `dirs`, the directory with the highest precedence
+/// wins. Returns the path below the default directory, if the file does not exist.
+/// Files are executed with the privileges of the daemon, so paths that lead out of
+/// the search path, absolute or via "..", are rejected
+fn find_file(file: &Path, dirs: &[PathBuf]) -> Result<PathBuf> {
+    let invalid = || PathsError::InvalidPath {
+        path: file.display().to_string(),
+    };
+
+    if file.is_absolute() {
+        return canonicalize_below(file, dirs).ok_or_else(invalid);
+    }
+
+    if file
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(invalid());
+    }
+
+    match dirs.iter().rev().find(|dir| dir.join(file).is_file()) {
+        Some(dir) => canonicalize_below(&dir.join(file), &[dir.clone()]).ok_or_else(invalid),
+
+        None => Ok(dirs[0].join(file)),
+    }
+}
+
+/// Find the profile file `profile_file` in the profile search path
+pub fn find_profile<P: AsRef<Path>>(profile_file: P) -> Result<PathBuf> {
+    find_file(profile_file.as_ref(), &get_profile_dirs())
+}
+
+/// Find the script file `script_file` in the script search path
+pub fn find_script<P: AsRef<Path>>(script_file: P) -> Result<PathBuf> {
+    find_file(script_file.as_ref(), &get_script_dirs())
+}
+
+/// Returns the profile files of all profile directories. Profiles of a directory
+/// with a higher precedence hide the profiles with the same file name
+pub fn get_profile_files() -> Vec<PathBuf> {
+    let mut result = BTreeMap::new();
+
+    for dir in get_profile_dirs().iter().filter(|dir| dir.is_dir()) {
+        match profiles::get_profile_files(dir) {
+            Ok(files) => {
+                for file in files {
+                    if let Some(name) = file.file_name() {
+                        result.insert(name.to_owned(), file.clone());
+                    }
+                }
+            }
+
+            Err(e) => warn!("Could not enumerate profiles in {}: {}", dir.display(), e),
+        }
+    }
+
+    result.into_iter().map(|(_, file)| file).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Create a search path of two directories below the temp directory, with
+    /// a file in each of them, and a file outside of the search path
+    fn setup(name: &str) -> (PathBuf, Vec<PathBuf>) {
+        let base = std::env::temp_dir().join(format!(
+            "eruption-test-paths-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        let dirs = vec![base.join("default"), base.join("system")];
+
+        for dir in dirs.iter() {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        fs::write(dirs[0].join("a.lua"), "").unwrap();
+        fs::write(dirs[0].join("b.lua"), "").unwrap();
+        fs::write(dirs[1].join("b.lua"), "").unwrap();
+        fs::write(base.join("outside.lua"), "").unwrap();
+
+        (base, dirs)
+    }
+
+    #[test]
+    fn test_find_file() {
+        let (base, dirs) = setup("find");
+
+        let canonical = |path: PathBuf| path.canonicalize().unwrap();
+
+        assert_eq!(
+            find_file(Path::new("a.lua"), &dirs).unwrap(),
+            canonical(dirs[0].join("a.lua"))
+        );
+
+        // the directory with the highest precedence wins
+        assert_eq!(
+            find_file(Path::new("b.lua"), &dirs).unwrap(),
+            canonical(dirs[1].join("b.lua"))
+        );
+
+        // missing files resolve below the default directory
+        assert_eq!(
+            find_file(Path::new("missing.lua"), &dirs).unwrap(),
+            dirs[0].join("missing.lua")
+        );
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_find_file_parent_dir() {
+        let (base, dirs) = setup("parent");
+
+        assert!(find_file(Path::new("../outside.lua"), &dirs).is_err());
+        assert!(find_file(Path::new("../system/b.lua"), &dirs).is_err());
+        assert!(find_file(Path::new("./../../etc/passwd"), &dirs).is_err());
+        assert!(find_file(Path::new("sub/../a.lua"), &dirs).is_err());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_find_file_absolute() {
+        let (base, dirs) = setup("absolute");
+
+        // absolute paths within the search path are accepted
+        assert_eq!(
+            find_file(&dirs[1].join("b.lua"), &dirs).unwrap(),
+            dirs[1].join("b.lua").canonicalize().unwrap()
+        );
+
+        assert!(find_file(&base.join("outside.lua"), &dirs).is_err());
+        assert!(find_file(&dirs[0].join("../outside.lua"), &dirs).is_err());
+        assert!(find_file(Path::new("/etc/passwd"), &dirs).is_err());
+        assert!(find_file(&dirs[0].join("missing.lua"), &dirs).is_err());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_file_symlink() {
+        let (base, dirs) = setup("symlink");
+
+        std::os::unix::fs::symlink(base.join("outside.lua"), dirs[1].join("link.lua")).unwrap();
+
+        assert!(find_file(Path::new("link.lua"), &dirs).is_err());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -17,10 +17,12 @@
 
 use lazy_static::lazy_static;
 use log::*;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::constants;
 use crate::plugins::{Plugin, Result};
 
 lazy_static! {
@@ -30,10 +32,38 @@ lazy_static! {
 
 type PluginType = dyn Plugin + Sync + Send;
 
+/// The name and description of a registered plugin, and whether it is enabled
+#[derive(Debug, Clone)]
+pub struct PluginInfo {
+    pub name: String,
+    pub description: String,
+    pub enabled: bool,
+}
+
+/// Runtimes of the main loop hook of a plugin
+#[derive(Debug, Default, Clone)]
+pub struct HookStats {
+    pub calls: u64,
+    pub total_us: u64,
+    pub max_us: u64,
+}
+
 /// Plugin manager
 /// Keeps track of registered plugins
 pub struct PluginManager {
     registered_plugins: HashMap<String, Box<PluginType>>,
+
+    /// Names of the registered plugins that are disabled
+    disabled_plugins: HashSet<String>,
+
+    /// Names of the plugins that are being initialized or torn down
+    busy_plugins: HashSet<String>,
+
+    /// Names of the registered plugins that have been initialized, and not torn down
+    initialized_plugins: HashSet<String>,
+
+    /// Runtimes of the main loop hooks, indexed by the names of the plugins
+    hook_stats: Mutex<HashMap<String, HookStats>>,
 }
 
 impl PluginManager {
@@ -41,31 +71,145 @@ impl PluginManager {
     pub fn new() -> Self {
         PluginManager {
             registered_plugins: HashMap::new(),
+            disabled_plugins: HashSet::new(),
+            busy_plugins: HashSet::new(),
+            initialized_plugins: HashSet::new(),
+            hook_stats: Mutex::new(HashMap::new()),
         }
     }
 
-    /// Register a plugin with the system
-    pub fn register_plugin(&mut self, mut plugin: Box<PluginType>) -> Result<()> {
-        info!(
-            "Registering plugin: {} - {}",
-            plugin.get_name(),
-            plugin.get_description()
-        );
+    /// Register a plugin with the system, enabled plugins must already be initialized
+    pub fn register_plugin(&mut self, plugin: Box<PluginType>, enabled: bool) {
+        let name = plugin.get_name();
+
+        if enabled {
+            info!(
+                "Registering plugin: {} - {}",
+                name,
+                plugin.get_description()
+            );
 
-        plugin.initialize()?;
+            self.initialized_plugins.insert(name.clone());
+        } else {
+            info!("Registering disabled plugin: {}", name);
 
-        self.registered_plugins.insert(plugin.get_name(), plugin);
+            self.disabled_plugins.insert(name.clone());
+        }
 
-        Ok(())
+        self.registered_plugins.insert(name, plugin);
     }
 
+    /// Get all enabled plugins
     pub fn get_plugins(&self) -> Vec<&PluginType> {
         self.registered_plugins
-            .values()
-            .map(AsRef::as_ref)
+            .iter()
+            .filter(|(name, _)| !self.disabled_plugins.contains(*name))
+            .map(|(_, plugin)| plugin.as_ref())
             .collect()
     }
 
+    /// Call the main loop hooks of all enabled plugins that are due in the
+    /// iteration `ticks`, and record their runtimes
+    pub fn run_main_loop_hooks(&self, ticks: u64) {
+        for (name, plugin) in self.registered_plugins.iter() {
+            if self.disabled_plugins.contains(name) {
+                continue;
+            }
+
+            match plugin.get_hook_interval() {
+                Some(interval) if ticks % interval.max(1) == 0 => {
+                    let start = Instant::now();
+
+                    plugin.main_loop_hook(ticks);
+
+                    let elapsed = start.elapsed().as_micros() as u64;
+
+                    let mut hook_stats = self.hook_stats.lock();
+                    let stats = hook_stats.entry(name.clone()).or_default();
+
+                    stats.calls += 1;
+                    stats.total_us += elapsed;
+                    stats.max_us = stats.max_us.max(elapsed);
+
+                    if elapsed > constants::SLOW_HOOK_MICROS {
+                        debug!("Slow main loop hook of plugin {}: {} µs", name, elapsed);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+    }
+
+    /// Get the runtimes of the main loop hooks, e.g. "sensors_avg_us"
+    pub fn get_hook_metrics(&self) -> HashMap<String, u64> {
+        let mut result = HashMap::new();
+
+        for (name, stats) in self.hook_stats.lock().iter() {
+            let prefix = name.to_lowercase();
+
+            result.insert(format!("{}_calls", prefix), stats.calls);
+            result.insert(
+                format!("{}_avg_us", prefix),
+                stats.total_us / stats.calls.max(1),
+            );
+            result.insert(format!("{}_max_us", prefix), stats.max_us);
+        }
+
+        result
+    }
+
+    /// Get the names and descriptions of all registered plugins, ordered by name
+    pub fn get_plugin_infos(&self) -> Vec<PluginInfo> {
+        let mut result: Vec<PluginInfo> = self
+            .registered_plugins
+            .iter()
+            .map(|(name, plugin)| PluginInfo {
+                name: name.clone(),
+                description: plugin.get_description(),
+                enabled: !self.disabled_plugins.contains(name),
+            })
+            .collect();
+
+        result.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+
+        result
+    }
+
+    /// Take the plugin `name` out of the manager, it is marked as busy until it is
+    /// returned by `return_plugin`
+    fn take_plugin(&mut self, name: &str) -> Option<Box<PluginType>> {
+        let plugin = self.registered_plugins.remove(name)?;
+
+        self.busy_plugins.insert(name.to_string());
+
+        Some(plugin)
+    }
+
+    /// Return the plugin `name`, that was taken by `take_plugin`
+    fn return_plugin(
+        &mut self,
+        name: &str,
+        plugin: Box<PluginType>,
+        enabled: bool,
+        initialized: bool,
+    ) {
+        if enabled {
+            self.disabled_plugins.remove(name);
+        } else {
+            self.disabled_plugins.insert(name.to_string());
+        }
+
+        if initialized {
+            self.initialized_plugins.insert(name.to_string());
+        } else {
+            self.initialized_plugins.remove(name);
+        }
+
+        self.busy_plugins.remove(name);
+        self.registered_plugins.insert(name.to_string(), plugin);
+    }
+
     // pub fn get_plugins_mut(&mut self) -> Vec<&mut Box<PluginType>> {
     //     self.registered_plugins.values_mut().collect()
     // }
@@ -78,3 +222,163 @@ impl PluginManager {
         self.registered_plugins.get_mut(&name).map(AsMut::as_mut)
     }
 }
+
+/// Enable or disable the plugin `name` at runtime, the plugin is initialized or
+/// torn down respectively. A plugin is initialized at most once, until it is torn
+/// down, so only plugins that support `teardown()` may be disabled. This may take a
+/// while, so the plugin is taken out of the plugin manager meanwhile, instead of
+/// holding its lock. Returns true if the state changed
+pub fn set_plugin_enabled(name: &str, enabled: bool) -> Result<bool> {
+    let (mut plugin, initialized) = {
+        let mut plugin_manager = PLUGIN_MANAGER.write();
+
+        if plugin_manager.busy_plugins.contains(name) {
+            return Err(anyhow::anyhow!("The plugin {} is busy", name));
+        }
+
+        if !plugin_manager.registered_plugins.contains_key(name) {
+            return Err(anyhow::anyhow!("Unknown plugin: {}", name));
+        }
+
+        if enabled != plugin_manager.disabled_plugins.contains(name) {
+            return Ok(false);
+        }
+
+        if !enabled && constants::REQUIRED_PLUGINS.contains(&name) {
+            return Err(anyhow::anyhow!("The plugin {} is required", name));
+        }
+
+        if !enabled && !plugin_manager.registered_plugins[name].supports_teardown() {
+            return Err(anyhow::anyhow!(
+                "The plugin {} can not be disabled at runtime",
+                name
+            ));
+        }
+
+        let initialized = plugin_manager.initialized_plugins.contains(name);
+
+        // the main loop hook of the plugin must not run while it is busy
+        plugin_manager.disabled_plugins.insert(name.to_string());
+
+        (plugin_manager.take_plugin(name).unwrap(), initialized)
+    };
+
+    let result = if enabled {
+        info!("Enabling plugin: {}", name);
+
+        if initialized {
+            Ok(())
+        } else {
+            plugin.initialize()
+        }
+    } else {
+        info!("Disabling plugin: {}", name);
+
+        plugin.teardown()
+    };
+
+    // the state is only changed if the plugin has been initialized or torn down
+    let enabled = enabled == result.is_ok();
+
+    PLUGIN_MANAGER
+        .write()
+        .return_plugin(name, plugin, enabled, enabled);
+
+    result.map(|_| true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static INITIALIZE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct TestPlugin {
+        name: &'static str,
+        supports_teardown: bool,
+    }
+
+    impl Plugin for TestPlugin {
+        fn get_name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn get_description(&self) -> String {
+            "A plugin for tests".into()
+        }
+
+        fn initialize(&mut self) -> Result<()> {
+            INITIALIZE_CALLS.fetch_add(1, Ordering::SeqCst);
+
+            Ok(())
+        }
+
+        fn supports_teardown(&self) -> bool {
+            self.supports_teardown
+        }
+
+        fn register_lua_funcs(&self, _lua_ctx: rlua::Context) -> rlua::Result<()> {
+            Ok(())
+        }
+
+        fn get_api(&self) -> &'static [eruption_sdk::ApiFunction] {
+            &[]
+        }
+
+        fn main_loop_hook(&self, _ticks: u64) {}
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_set_plugin_enabled() {
+        {
+            let mut plugin_manager = PLUGIN_MANAGER.write();
+
+            plugin_manager.register_plugin(
+                Box::new(TestPlugin {
+                    name: "TestFixed",
+                    supports_teardown: false,
+                }),
+                false,
+            );
+
+            plugin_manager.register_plugin(
+                Box::new(TestPlugin {
+                    name: "TestTeardown",
+                    supports_teardown: true,
+                }),
+                false,
+            );
+        }
+
+        // a disabled plugin is initialized once, when it is enabled
+        assert!(set_plugin_enabled("TestFixed", true).unwrap());
+        assert!(!set_plugin_enabled("TestFixed", true).unwrap());
+        assert!(set_plugin_enabled("TestFixed", false).is_err());
+        assert_eq!(INITIALIZE_CALLS.load(Ordering::SeqCst), 1);
+
+        // a plugin that has been torn down is initialized again
+        assert!(set_plugin_enabled("TestTeardown", true).unwrap());
+        assert!(set_plugin_enabled("TestTeardown", false).unwrap());
+        assert!(set_plugin_enabled("TestTeardown", true).unwrap());
+        assert_eq!(INITIALIZE_CALLS.load(Ordering::SeqCst), 3);
+
+        let mut plugin_manager = PLUGIN_MANAGER.write();
+
+        for name in &["TestFixed", "TestTeardown"] {
+            plugin_manager.take_plugin(name);
+            plugin_manager.busy_plugins.remove(*name);
+            plugin_manager.initialized_plugins.remove(*name);
+            plugin_manager.disabled_plugins.remove(*name);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::Context;
+use std::any::Any;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::constants;
+use crate::paths;
+use crate::plugins;
+use crate::plugins::Plugin;
+use crate::scripting::script;
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_accent_color() -> c",
+    Color,
+    "since 0.1.2",
+    "Returns the accent color of the desktop environment, or `nil` if unknown. Read from the KDE settings of the seat user, or reported by `eruption-accent-watcher` (XDG desktop portal)";
+};
+
+lazy_static! {
+    /// The accent color of the desktop environment, if known
+    static ref ACCENT_COLOR: Mutex<Option<u32>> = Mutex::new(None);
+
+    /// Modification time of the KDE settings file, when it has been read last
+    static ref KDEGLOBALS_MTIME: Mutex<Option<(PathBuf, SystemTime)>> = Mutex::new(None);
+}
+
+/// Parse a color of the form `#rrggbb`, `r,g,b` (0..255) or `r,g,b` (0.0..1.0, as
+/// reported by the XDG desktop portal)
+pub fn parse_color(color: &str) -> Option<u32> {
+    let color = color.trim();
+
+    if color.starts_with('#') {
+        let hex = &color[1..];
+
+        if hex.len() != 6 {
+            return None;
+        }
+
+        return u32::from_str_radix(hex, 16)
+            .ok()
+            .map(|rgb| 0xff00_0000 | rgb);
+    }
+
+    let components: Vec<&str> = color.split(',').map(|c| c.trim()).collect();
+
+    if components.len() != 3 {
+        return None;
+    }
+
+    let is_fraction = components.iter().any(|c| c.contains('.'));
+
+    let mut rgb = [0u8; 3];
+
+    for (value, component) in rgb.iter_mut().zip(components.iter()) {
+        *value = if is_fraction {
+            let f = component.parse::<f64>().ok()?;
+
+            if !(0.0..=1.0).contains(&f) {
+                return None;
+            }
+
+            (f * 255.0).round() as u8
+        } else {
+            component.parse::<u8>().ok()?
+        };
+    }
+
+    Some(script::callbacks::rgb_to_color(rgb[0], rgb[1], rgb[2]))
+}
+
+/// Read the accent color from the KDE settings file `path`. If no accent color is
+/// set, the color of selections (that is derived from it) is used instead
+fn read_kdeglobals(path: &Path) -> Option<u32> {
+    let contents = fs::read_to_string(path).ok()?;
+
+    let mut section = "";
+    let mut selection_color = None;
+
+    for line in contents.lines().map(|l| l.trim()) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line;
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=');
+
+        match (section, kv.next(), kv.next()) {
+            ("[General]", Some("AccentColor"), Some(value)) => return parse_color(value),
+
+            ("[Colors:Selection]", Some("BackgroundNormal"), Some(value)) => {
+                selection_color = parse_color(value)
+            }
+
+            _ => {}
+        }
+    }
+
+    selection_color
+}
+
+/// A plugin that exposes the accent color of the desktop environment
+pub struct AccentPlugin {}
+
+impl AccentPlugin {
+    pub fn new() -> Self {
+        AccentPlugin {}
+    }
+
+    /// Get the accent color of the desktop environment, if known
+    pub fn get_accent_color() -> Option<u32> {
+        *ACCENT_COLOR.lock()
+    }
+
+    /// Record the accent color of the desktop environment, the Lua VMs are
+    /// notified if the color changed
+    pub fn set_accent_color(color: u32) {
+        let old_color = ACCENT_COLOR.lock().replace(color);
+
+        if old_color != Some(color) {
+            debug!("Accent color of the desktop: {:#010x}", color);
+
+            for lua_tx in crate::LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::AccentColorChanged(color))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+        }
+    }
+
+    /// Read the KDE settings of the seat user, if they have been modified
+    fn poll_kdeglobals() {
+        let path = match paths::get_seat_user() {
+            Some(user) => user.home.join(constants::KDEGLOBALS_FILE),
+            None => return,
+        };
+
+        let mtime = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(mtime) => mtime,
+            Err(_) => return,
+        };
+
+        {
+            let mut last = KDEGLOBALS_MTIME.lock();
+
+            if last.as_ref() == Some(&(path.clone(), mtime)) {
+                return;
+            }
+
+            *last = Some((path.clone(), mtime));
+        }
+
+        if let Some(color) = read_kdeglobals(&path) {
+            Self::set_accent_color(color);
+        }
+    }
+}
+
+impl Plugin for AccentPlugin {
+    fn get_name(&self) -> String {
+        "Accent".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Synchronize with the accent color of the desktop environment".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        Self::poll_kdeglobals();
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_accent_color =
+            lua_ctx.create_function(|_, ()| Ok(AccentPlugin::get_accent_color()))?;
+        globals.set("get_accent_color", get_accent_color)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {
+        Self::poll_kdeglobals();
+    }
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        Some(constants::ACCENT_COLOR_UPDATE_TICKS)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -15,7 +15,7 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use failure::Fail;
+use eruption_sdk::{api_functions, ApiFunction};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use log::*;
@@ -24,6 +24,7 @@ use rlua::Context;
 use std::any::Any;
 use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
+use thiserror::Error;
 
 use rustfft::algorithm::Radix4;
 use rustfft::num_complex::Complex;
@@ -35,20 +36,20 @@ use crate::plugins::{self, Plugin};
 
 pub type Result<T> = std::result::Result<T, AudioPluginError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum AudioPluginError {
-    //#[fail(display = "Unknown error: {}", description)]
+    //#[error("Unknown error: {description}")]
     //UnknownError { description: String },
-    #[fail(display = "Pulse Audio error: {}", description)]
+    #[error("Pulse Audio error: {description}")]
     PulseError { description: String },
 
-    #[fail(display = "File I/O error: {}", description)]
+    #[error("File I/O error: {description}")]
     IoError { description: String },
 
-    #[fail(display = "Playback error: {}", description)]
+    #[error("Playback error: {description}")]
     PlaybackError { description: String },
 
-    #[fail(display = "Audio grabber error: {}", description)]
+    #[error("Audio grabber error: {description}")]
     GrabberError { description: String },
 }
 
@@ -120,6 +121,24 @@ fn try_start_audio_grabber() -> Result<()> {
     }
 }
 
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_audio_loudness() -> i",
+    Dsp,
+    "since 0.0.11",
+    "Returns the current RMS loudness of the configured audio input";
+
+    "get_audio_spectrum() -> [f]",
+    Dsp,
+    "since 0.0.11",
+    "Returns a vector of 1024 floats, containing results of a fourier transform (FFT) of the configured audio input";
+
+    "get_audio_raw_data() -> [i]",
+    Dsp,
+    "since 0.0.11",
+    "Returns a buffer of 16-bit wide signed integer values, containing samples from the configured audio input";
+};
+
 /// A plugin that performs audio-related tasks like playing or capturing sounds
 pub struct AudioPlugin {}
 
@@ -271,8 +290,16 @@ impl Plugin for AudioPlugin {
         Ok(())
     }
 
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
     fn main_loop_hook(&self, _ticks: u64) {}
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -282,19 +309,26 @@ impl Plugin for AudioPlugin {
     }
 }
 
-mod util {
+pub mod util {
     use super::AudioPluginError;
     use super::Result;
     use byteorder::{LittleEndian, WriteBytesExt};
     use std::path::{Path, PathBuf};
 
-    pub fn load_sfx<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    /// Get the directory of the sound effects
+    pub fn get_sfx_dir() -> PathBuf {
         #[cfg(debug_assertions)]
         let prefix = PathBuf::from("support/sfx");
 
         #[cfg(not(debug_assertions))]
         let prefix = PathBuf::from("/usr/share/eruption/sfx");
 
+        prefix
+    }
+
+    pub fn load_sfx<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+        let prefix = get_sfx_dir();
+
         let mut reader = hound::WavReader::open(prefix.join(path)).map_err(|e| {
             AudioPluginError::PlaybackError {
                 description: format!("Could not load waveform audio file: {}", e),
@@ -726,15 +760,11 @@ mod backends {
                     ACTIVE_SFX.fetch_add(1, Ordering::SeqCst);
 
                     pa.write(&data)
-                        .map_err(|e| AudioPluginError::PlaybackError {
-                            description: format!("Error during writing of playback buffer: {}", e),
-                        })
-                        .unwrap();
-                    pa.drain()
+                        .and_then(|_| pa.drain())
                         .map_err(|e| AudioPluginError::PlaybackError {
                             description: format!("Error during playback: {}", e),
                         })
-                        .unwrap();
+                        .unwrap_or_else(|e| error!("{}", e));
 
                     ACTIVE_SFX.fetch_sub(1, Ordering::SeqCst);
                 })
@@ -761,7 +791,12 @@ mod backends {
             let builder = thread::Builder::new().name("audio/grabber".into());
             builder
                 .spawn(move || -> Result<()> {
-                    let grabber = Self::init_grabber().unwrap();
+                    let grabber = Self::init_grabber().map_err(|e| {
+                        error!("Could not initialize the audio grabber: {}", e);
+                        AUDIO_GRABBER_THREAD_RUNNING.store(false, Ordering::SeqCst);
+
+                        e
+                    })?;
 
                     'RECORDER_LOOP: loop {
                         //let mut tmp: Vec<u8> = Vec::with_capacity(AUDIO_GRABBER_BUFFER_SIZE);
@@ -0,0 +1,370 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::Context;
+use serde::Deserialize;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::plugins;
+use crate::plugins::Plugin;
+use crate::scripting::script;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, CiPluginError>;
+
+#[derive(Debug, Error)]
+pub enum CiPluginError {
+    #[error("Could not fetch the status of '{pipeline}': {description}")]
+    FetchError {
+        pipeline: String,
+        description: String,
+    },
+
+    #[error("Could not parse the status of '{pipeline}'")]
+    ParseError { pipeline: String },
+
+    #[error("Unknown CI provider: {provider}")]
+    UnknownProvider { provider: String },
+}
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_build_status(name) -> s",
+    Sys,
+    "since 0.1.2",
+    "Returns the status of the latest pipeline of `name`, as configured in the `[ci]` section: \"success\", \"failure\", \"running\", \"pending\" or \"unknown\". Returns `nil` for unknown names";
+};
+
+lazy_static! {
+    /// The most recently fetched status of each pipeline, indexed by name
+    static ref BUILD_STATUS: Arc<Mutex<HashMap<String, BuildStatus>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Status of the latest run of a pipeline
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BuildStatus {
+    Success,
+    Failure,
+    Running,
+    Pending,
+    Unknown,
+}
+
+impl BuildStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildStatus::Success => "success",
+            BuildStatus::Failure => "failure",
+            BuildStatus::Running => "running",
+            BuildStatus::Pending => "pending",
+            BuildStatus::Unknown => "unknown",
+        }
+    }
+}
+
+/// A pipeline, as configured by a `[[ci.pipelines]]` table of the config
+#[derive(Debug, Clone, Deserialize)]
+struct PipelineConfig {
+    /// Name of the pipeline, that is used by the scripts
+    name: String,
+
+    /// Either "github" or "gitlab"
+    provider: String,
+
+    /// "owner/repository" on GitHub, the path of the project on GitLab
+    repo: String,
+
+    branch: Option<String>,
+
+    /// Personal access token, required for private repositories
+    token: Option<String>,
+
+    /// Base URL of the GitLab instance, defaults to https://gitlab.com
+    url: Option<String>,
+}
+
+/// A plugin that periodically polls the status of CI pipelines
+pub struct CiPlugin {}
+
+impl CiPlugin {
+    pub fn new() -> Self {
+        CiPlugin {}
+    }
+
+    /// Get the most recently fetched status of the pipeline `name`
+    pub fn get_build_status(name: &str) -> Option<BuildStatus> {
+        BUILD_STATUS.lock().get(name).copied()
+    }
+
+    /// Fetch the status of the latest run of the pipeline `pipeline`
+    fn fetch(pipeline: &PipelineConfig) -> Result<BuildStatus> {
+        let fetch_error = |description: String| CiPluginError::FetchError {
+            pipeline: pipeline.name.clone(),
+            description,
+        };
+
+        let parse_error = || CiPluginError::ParseError {
+            pipeline: pipeline.name.clone(),
+        };
+
+        let mut request = match pipeline.provider.as_str() {
+            "github" => {
+                // the slash between the owner and the repository is kept
+                let repo = pipeline
+                    .repo
+                    .split('/')
+                    .map(util::url_encode)
+                    .collect::<Vec<String>>()
+                    .join("/");
+
+                let mut url = format!(
+                    "https://api.github.com/repos/{}/actions/runs?per_page=1",
+                    repo
+                );
+
+                if let Some(branch) = &pipeline.branch {
+                    url.push_str(&format!("&branch={}", util::url_encode(branch)));
+                }
+
+                let mut request = ureq::get(&url);
+                request.set("User-Agent", "eruption");
+
+                if let Some(token) = &pipeline.token {
+                    request.set("Authorization", &format!("token {}", token));
+                }
+
+                request
+            }
+
+            "gitlab" => {
+                let mut url = format!(
+                    "{}/api/v4/projects/{}/pipelines?per_page=1",
+                    pipeline
+                        .url
+                        .as_deref()
+                        .unwrap_or("https://gitlab.com")
+                        .trim_end_matches('/'),
+                    util::url_encode(&pipeline.repo)
+                );
+
+                if let Some(branch) = &pipeline.branch {
+                    url.push_str(&format!("&ref={}", util::url_encode(branch)));
+                }
+
+                let mut request = ureq::get(&url);
+
+                if let Some(token) = &pipeline.token {
+                    request.set("PRIVATE-TOKEN", token);
+                }
+
+                request
+            }
+
+            _ => {
+                return Err(CiPluginError::UnknownProvider {
+                    provider: pipeline.provider.clone(),
+                })
+            }
+        };
+
+        let response = request
+            .timeout_connect(constants::CI_TIMEOUT_MILLIS)
+            .timeout_read(constants::CI_TIMEOUT_MILLIS)
+            .call();
+
+        if !response.ok() {
+            return Err(fetch_error(response.status_line().to_string()));
+        }
+
+        let json = response
+            .into_json()
+            .map_err(|e| fetch_error(format!("{}", e)))?;
+
+        if pipeline.provider == "github" {
+            let run = &json["workflow_runs"][0];
+
+            if run.is_null() {
+                return Ok(BuildStatus::Unknown);
+            }
+
+            let status = run["status"].as_str().ok_or_else(parse_error)?;
+
+            Ok(match status {
+                "completed" => match run["conclusion"].as_str() {
+                    Some("success") | Some("neutral") | Some("skipped") => BuildStatus::Success,
+                    Some(_) => BuildStatus::Failure,
+                    None => BuildStatus::Unknown,
+                },
+
+                "in_progress" => BuildStatus::Running,
+                "queued" | "waiting" | "requested" => BuildStatus::Pending,
+
+                _ => BuildStatus::Unknown,
+            })
+        } else {
+            let run = &json[0];
+
+            if run.is_null() {
+                return Ok(BuildStatus::Unknown);
+            }
+
+            Ok(match run["status"].as_str().ok_or_else(parse_error)? {
+                "success" | "skipped" => BuildStatus::Success,
+                "failed" | "canceled" => BuildStatus::Failure,
+                "running" => BuildStatus::Running,
+                "created"
+                | "pending"
+                | "preparing"
+                | "waiting_for_resource"
+                | "scheduled"
+                | "manual" => BuildStatus::Pending,
+
+                _ => BuildStatus::Unknown,
+            })
+        }
+    }
+
+    /// Record the status of a pipeline, and notify the Lua VMs if it changed
+    fn report_status(name: &str, status: BuildStatus) {
+        let old_status = BUILD_STATUS.lock().insert(name.to_string(), status);
+
+        if old_status != Some(status) {
+            info!("Build status of '{}': {}", name, status.name());
+
+            for lua_tx in crate::LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::BuildStatusChanged(
+                        name.to_string(),
+                        status.name(),
+                    ))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+        }
+    }
+
+    /// Spawn a thread that periodically polls the status of all pipelines
+    fn spawn_update_thread(
+        pipelines: Vec<PipelineConfig>,
+        interval: Duration,
+    ) -> plugins::Result<()> {
+        let builder = thread::Builder::new().name("ci".into());
+        builder.spawn(move || loop {
+            for pipeline in pipelines.iter() {
+                match Self::fetch(pipeline) {
+                    Ok(status) => Self::report_status(&pipeline.name, status),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+
+            thread::sleep(interval);
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Plugin for CiPlugin {
+    fn get_name(&self) -> String {
+        "CI".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Periodically poll the status of CI pipelines".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        if !config.get::<bool>("ci.enabled").unwrap_or(false) {
+            info!("CI status DISABLED by configuration");
+            return Ok(());
+        }
+
+        // pipelines of unknown providers are skipped, the others are still polled
+        let pipelines = config
+            .get::<Vec<PipelineConfig>>("ci.pipelines")
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|p| {
+                if p.provider == "github" || p.provider == "gitlab" {
+                    true
+                } else {
+                    error!(
+                        "Skipping the pipeline '{}': {}",
+                        p.name,
+                        CiPluginError::UnknownProvider {
+                            provider: p.provider.clone(),
+                        }
+                    );
+
+                    false
+                }
+            })
+            .collect::<Vec<PipelineConfig>>();
+
+        let interval = Duration::from_secs(
+            config
+                .get_int("ci.update_interval_secs")
+                .unwrap_or(constants::CI_UPDATE_INTERVAL_SECS as i64)
+                .max(constants::CI_MIN_UPDATE_INTERVAL_SECS as i64) as u64,
+        );
+
+        Self::spawn_update_thread(pipelines, interval)?;
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_build_status = lua_ctx.create_function(|_, name: String| {
+            Ok(CiPlugin::get_build_status(&name).map(|status| status.name()))
+        })?;
+        globals.set("get_build_status", get_build_status)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
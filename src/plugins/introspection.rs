@@ -15,20 +15,30 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-// use failure::Fail;
+// use thiserror::Error;
+use eruption_sdk::{api_functions, ApiFunction};
 use rlua::Context;
 use std::any::Any;
 
 use crate::plugins::{self, Plugin};
+use crate::selftest::{self, Capability};
 
 // pub type Result<T> = std::result::Result<T, IntrospectionPluginError>;
 
-// #[derive(Debug, Fail)]
+// #[derive(Debug, Error)]
 // pub enum IntrospectionPluginError {
-//     #[fail(display = "Unknown error: {}", description)]
+//     #[error("Unknown error: {description}")]
 //     UnknownError { description: String },
 // }
 
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "has_capability(name) -> b",
+    Hw,
+    "since 0.1.2",
+    "Returns true if the default device passed the startup self-test for the capability `name`: \"lighting\", \"key-events\", \"special-keys\" or \"media-keys\"";
+};
+
 pub struct IntrospectionPlugin {}
 
 impl IntrospectionPlugin {
@@ -50,14 +60,30 @@ impl Plugin for IntrospectionPlugin {
         Ok(())
     }
 
-    fn register_lua_funcs(&self, _lua_ctx: Context) -> rlua::Result<()> {
-        // let globals = lua_ctx.globals();
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let has_capability = lua_ctx.create_function(|_, name: String| {
+            Ok(name
+                .parse::<Capability>()
+                .map(selftest::has_capability)
+                .unwrap_or(false))
+        })?;
+        globals.set("has_capability", has_capability)?;
 
         Ok(())
     }
 
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
     fn main_loop_hook(&self, _ticks: u64) {}
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -15,14 +15,15 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use eruption_sdk::ApiFunction;
 use evdev_rs::{Device, GrabMode};
-use failure::Fail;
 use log::*;
 use rlua::Context;
 use std::any::Any;
 use std::cell::RefCell;
 use std::fs::File;
 use std::sync::atomic::Ordering;
+use thiserror::Error;
 
 use crate::plugins::macros;
 
@@ -30,20 +31,26 @@ use crate::plugins::{self, Plugin};
 
 pub type Result<T> = std::result::Result<T, KeyboardPluginError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum KeyboardPluginError {
-    #[fail(display = "Could not peek evdev event")]
+    #[error("Could not peek evdev event")]
     EvdevEventError {},
 
-    #[fail(display = "Could not get the name of the evdev device from udev")]
+    #[error("Could not get the name of the evdev device from udev")]
     UdevError {},
 
-    #[fail(display = "Could not open the evdev device")]
+    #[error("Could not open the evdev device")]
     EvdevError {},
 
-    #[fail(display = "Could not create a libevdev device handle")]
+    #[error("Could not create a libevdev device handle")]
     EvdevHandleError {},
-    // #[fail(display = "Unknown error: {}", description)]
+
+    #[error("The keyboard device went away")]
+    DeviceGone {},
+
+    #[error("Could not grab the keyboard device: {description}")]
+    GrabError { description: String },
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
@@ -81,10 +88,26 @@ impl KeyboardPlugin {
                         info!("Physical location: {}", device.phys().unwrap_or("<n/a>"));
                         // info!("Unique identifier: {}", device.uniq().unwrap_or("<n/a>"));
 
-                        info!("Grabbing the device exclusively");
-                        device
-                            .grab(GrabMode::Grab)
-                            .expect("Could not grab the device, terminating now.");
+                        if crate::SHARED_MODE.load(Ordering::SeqCst) {
+                            info!("Shared mode: Not grabbing the device");
+                        } else {
+                            info!("Grabbing the device exclusively");
+
+                            if let Err(e) = device.grab(GrabMode::Grab) {
+                                let claims = crate::util::find_device_claims(&filename)
+                                    .iter()
+                                    .map(|c| c.to_string())
+                                    .collect::<Vec<String>>();
+
+                                let description = if claims.is_empty() {
+                                    format!("{}", e)
+                                } else {
+                                    format!("{}, the device is opened by: {}", e, claims.join(", "))
+                                };
+
+                                return Err(KeyboardPluginError::GrabError { description });
+                            }
+                        }
 
                         DEVICE.with(|dev| *dev.borrow_mut() = Some(device));
 
@@ -106,7 +129,7 @@ impl KeyboardPlugin {
             let result = dev
                 .borrow()
                 .as_ref()
-                .unwrap()
+                .ok_or(KeyboardPluginError::EvdevHandleError {})?
                 .next_event(evdev_rs::ReadFlag::NORMAL | evdev_rs::ReadFlag::BLOCKING);
 
             match result {
@@ -122,7 +145,7 @@ impl KeyboardPlugin {
                 Err(e) => {
                     if e as i32 == libc::ENODEV {
                         error!("Keyboard device went away: {}", e);
-                        panic!();
+                        Err(KeyboardPluginError::DeviceGone {})
                     } else {
                         error!("Could not peek evdev event: {}", e);
                         Err(KeyboardPluginError::EvdevEventError {})
@@ -156,8 +179,16 @@ impl Plugin for KeyboardPlugin {
         Ok(())
     }
 
+    fn get_api(&self) -> &'static [ApiFunction] {
+        &[]
+    }
+
     fn main_loop_hook(&self, _ticks: u64) {}
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -0,0 +1,418 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::ApiFunction;
+use evdev_rs::enums::EventCode;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Mutex, RwLock};
+use rlua::Context;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use thiserror::Error;
+
+use crate::constants;
+use crate::events;
+use crate::hotkeys;
+use crate::plugins::{self, audio, Plugin};
+
+pub type Result<T> = std::result::Result<T, KeySoundsPluginError>;
+
+#[derive(Debug, Error)]
+pub enum KeySoundsPluginError {
+    #[error("Pulse Audio error: {description}")]
+    PulseError { description: String },
+
+    #[error("Could not load the sound {file}: {description}")]
+    LoadError { file: String, description: String },
+
+    #[error("Invalid configuration of the key sounds: {key}")]
+    InvalidConfig { key: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// Samples of a sound, 16 bits stereo at 44.1 kHz, in native byte order
+type Sound = Arc<Vec<u8>>;
+
+/// Groups of keys, that may share a sound
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Group {
+    Alphanumeric,
+    Modifiers,
+    Function,
+    Navigation,
+    Keypad,
+    Space,
+    Enter,
+    Backspace,
+}
+
+impl Group {
+    /// Find the group named `name`, as used in the `[keysounds.groups]` section
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "alphanumeric" => Some(Group::Alphanumeric),
+            "modifiers" => Some(Group::Modifiers),
+            "function" => Some(Group::Function),
+            "navigation" => Some(Group::Navigation),
+            "keypad" => Some(Group::Keypad),
+            "space" => Some(Group::Space),
+            "enter" => Some(Group::Enter),
+            "backspace" => Some(Group::Backspace),
+            _ => None,
+        }
+    }
+
+    /// Get the group of the key with the key code `code`
+    fn of_key(code: u32) -> Self {
+        let name = evdev_rs::enums::int_to_ev_key(code)
+            .map(|key| format!("{:?}", key))
+            .unwrap_or_default();
+
+        let name = name.trim_start_matches("KEY_");
+
+        match name {
+            "LEFTSHIFT" | "RIGHTSHIFT" | "LEFTCTRL" | "RIGHTCTRL" | "LEFTALT" | "RIGHTALT"
+            | "LEFTMETA" | "RIGHTMETA" | "CAPSLOCK" | "COMPOSE" => Group::Modifiers,
+
+            "UP" | "DOWN" | "LEFT" | "RIGHT" | "HOME" | "END" | "PAGEUP" | "PAGEDOWN"
+            | "INSERT" => Group::Navigation,
+
+            "SPACE" => Group::Space,
+            "ENTER" | "KPENTER" => Group::Enter,
+            "BACKSPACE" | "DELETE" => Group::Backspace,
+
+            "ESC" | "SYSRQ" | "SCROLLLOCK" | "PAUSE" => Group::Function,
+            _ if name.starts_with('F') && name[1..].parse::<u32>().is_ok() => Group::Function,
+
+            _ if name.starts_with("KP") || name == "NUMLOCK" => Group::Keypad,
+
+            _ => Group::Alphanumeric,
+        }
+    }
+}
+
+/// The sounds of the keys, per key overrides take precedence over the groups
+struct Sounds {
+    default: Option<Sound>,
+    groups: HashMap<Group, Sound>,
+    keys: HashMap<u32, Sound>,
+}
+
+impl Sounds {
+    fn find(&self, code: u32) -> Option<&Sound> {
+        self.keys
+            .get(&code)
+            .or_else(|| self.groups.get(&Group::of_key(code)))
+            .or_else(|| self.default.as_ref())
+    }
+}
+
+lazy_static! {
+    /// The sounds of the keys, None unless the plugin has been enabled
+    static ref SOUNDS: RwLock<Option<Sounds>> = RwLock::new(None);
+
+    /// Hands sounds to an idle voice. The channel has no capacity, so sounds are
+    /// dropped while all voices are busy, instead of being played late
+    static ref VOICES: Mutex<Option<SyncSender<Sound>>> = Mutex::new(None);
+
+    /// The observer of key presses is registered only once, even if the plugin is
+    /// initialized again after it has been disabled
+    static ref OBSERVER_REGISTERED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Load the sound `file`, relative to the directory of the sound effects, and
+/// scale it to `volume` percent
+fn load_sound(file: &str, volume: u32) -> Result<Sound> {
+    let load_error = |description: String| KeySoundsPluginError::LoadError {
+        file: file.to_string(),
+        description,
+    };
+
+    let mut reader = hound::WavReader::open(audio::util::get_sfx_dir().join(file))
+        .map_err(|e| load_error(format!("{}", e)))?;
+
+    let spec = reader.spec();
+
+    if spec.channels != 2 || spec.sample_rate != 44100 || spec.bits_per_sample != 16 {
+        return Err(load_error(
+            "Only 16 bit stereo files with a sample rate of 44.1 kHz are supported".into(),
+        ));
+    }
+
+    let scale = f64::from(volume) / 100.0;
+
+    let mut result = Vec::with_capacity(reader.len() as usize * 2);
+
+    for sample in reader.samples::<i16>() {
+        let sample = sample.map_err(|e| load_error(format!("{}", e)))?;
+        let sample = (f64::from(sample) * scale).round() as i16;
+
+        result.extend_from_slice(&sample.to_ne_bytes());
+    }
+
+    Ok(Arc::new(result))
+}
+
+/// Load the sounds of the `[keysounds]` section of the config
+fn load_sounds(config: &config::Config, volume: u32) -> Result<Sounds> {
+    let default = match config.get_str("keysounds.sound") {
+        Ok(file) if file.is_empty() => None,
+        Ok(file) => Some(load_sound(&file, volume)?),
+        Err(_) => Some(load_sound(constants::DEFAULT_KEY_SOUND, volume)?),
+    };
+
+    let mut groups = HashMap::new();
+
+    if let Ok(table) = config.get_table("keysounds.groups") {
+        for (name, value) in table {
+            let invalid_config = || KeySoundsPluginError::InvalidConfig { key: name.clone() };
+
+            let group = Group::from_name(&name).ok_or_else(invalid_config)?;
+            let file = value.into_str().map_err(|_e| invalid_config())?;
+
+            groups.insert(group, load_sound(&file, volume)?);
+        }
+    }
+
+    let mut keys = HashMap::new();
+
+    if let Ok(table) = config.get_table("keysounds.keys") {
+        for (name, value) in table {
+            let invalid_config = || KeySoundsPluginError::InvalidConfig { key: name.clone() };
+
+            let code = hotkeys::parse_key_name(&name).map_err(|_e| invalid_config())?;
+            let file = value.into_str().map_err(|_e| invalid_config())?;
+
+            keys.insert(code, load_sound(&file, volume)?);
+        }
+    }
+
+    Ok(Sounds {
+        default,
+        groups,
+        keys,
+    })
+}
+
+/// A voice plays one sound at a time, on a stream of its own, so that the sounds
+/// of voices mix. Does not return unless an error occurs
+fn run_voice(receiver: Arc<Mutex<Receiver<Sound>>>, device: Option<String>) -> Result<()> {
+    use libpulse_binding as pulse;
+    use libpulse_simple_binding as psimple;
+    use psimple::Simple;
+    use pulse::def::BufferAttr;
+    use pulse::sample;
+    use pulse::stream::Direction;
+
+    let spec = sample::Spec {
+        format: sample::SAMPLE_S16NE,
+        channels: 2,
+        rate: 44100,
+    };
+
+    assert!(spec.is_valid());
+
+    // a short buffer keeps the sound in sync with the effects of the key press
+    let attr = BufferAttr {
+        maxlength: std::u32::MAX,
+        tlength: (spec.rate * 4 / 1000) * constants::KEY_SOUND_LATENCY_MILLIS,
+        prebuf: std::u32::MAX,
+        minreq: std::u32::MAX,
+        fragsize: std::u32::MAX,
+    };
+
+    let player = Simple::new(
+        None,
+        "eruption",
+        Direction::Playback,
+        device.as_deref(),
+        "Key Sounds",
+        &spec,
+        None,
+        Some(&attr),
+    )
+    .map_err(|e| KeySoundsPluginError::PulseError {
+        description: format!("Could not open Pulse Audio: {}", e),
+    })?;
+
+    loop {
+        let sound = match receiver.lock().recv() {
+            Ok(sound) => sound,
+
+            // the plugin has been shut down
+            Err(_) => return Ok(()),
+        };
+
+        player
+            .write(&sound)
+            .and_then(|_| player.drain())
+            .map_err(|e| KeySoundsPluginError::PulseError {
+                description: format!("Error during playback: {}", e),
+            })?;
+    }
+}
+
+/// Play the sound of the key with the key code `code`, unless all voices are busy
+fn play_key_sound(code: u32) {
+    let sound = match SOUNDS.read().as_ref().and_then(|sounds| sounds.find(code)) {
+        Some(sound) => sound.clone(),
+        None => return,
+    };
+
+    if let Some(voices) = VOICES.lock().as_ref() {
+        if voices.try_send(sound).is_err() {
+            trace!("All voices are busy, dropping the sound of a key");
+        }
+    }
+}
+
+/// A plugin that plays a sound on each key press, like the clicks of a typewriter.
+/// The sounds may be configured per group of keys and per key, in the `[keysounds]`
+/// section of the config. Sounds are played via PulseAudio (or PipeWire)
+pub struct KeySoundsPlugin {}
+
+impl KeySoundsPlugin {
+    pub fn new() -> Self {
+        KeySoundsPlugin {}
+    }
+}
+
+impl Plugin for KeySoundsPlugin {
+    fn get_name(&self) -> String {
+        "KeySounds".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Play a sound on each key press".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        let (sounds, polyphony, device) = {
+            let config = crate::CONFIG.lock();
+            let config = config.as_ref().unwrap();
+
+            if !config.get::<bool>("keysounds.enabled").unwrap_or(false) {
+                info!("Key sounds DISABLED by configuration");
+                return Ok(());
+            }
+
+            let volume = config
+                .get_int("keysounds.volume")
+                .unwrap_or(constants::DEFAULT_KEY_SOUND_VOLUME as i64)
+                .max(0)
+                .min(100) as u32;
+
+            let polyphony = config
+                .get_int("keysounds.polyphony")
+                .unwrap_or(constants::DEFAULT_KEY_SOUND_POLYPHONY as i64)
+                .max(1)
+                .min(constants::MAX_KEY_SOUND_POLYPHONY as i64)
+                as usize;
+
+            (
+                load_sounds(config, volume)?,
+                polyphony,
+                config.get_str("keysounds.device").ok(),
+            )
+        };
+
+        info!(
+            "Playing key sounds with {} voice(s) on: {}",
+            polyphony,
+            device.as_deref().unwrap_or("default sink")
+        );
+
+        let (tx, rx) = mpsc::sync_channel(0);
+        let rx = Arc::new(Mutex::new(rx));
+
+        for _ in 0..polyphony {
+            let rx = rx.clone();
+            let device = device.clone();
+
+            thread::Builder::new()
+                .name("keysounds/voice".into())
+                .spawn(move || {
+                    run_voice(rx, device).unwrap_or_else(|e| error!("Key sounds: {}", e));
+                })
+                .map_err(|_e| KeySoundsPluginError::ThreadSpawnError {})?;
+        }
+
+        *SOUNDS.write() = Some(sounds);
+        *VOICES.lock() = Some(tx);
+
+        if OBSERVER_REGISTERED.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // the raw event of a key press is observed before the scripts are notified
+        events::register_observer(|event: &events::Event| {
+            if let events::Event::RawKeyboardEvent(raw_event) = event {
+                // key repeats are silent
+                if raw_event.value == 1 {
+                    if let EventCode::EV_KEY(ref code) = raw_event.event_code {
+                        play_key_sound(code.clone() as u32);
+                    }
+                }
+            }
+
+            Ok(true) // event has been processed
+        });
+
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> plugins::Result<()> {
+        // the voices terminate, when the sender is dropped
+        *VOICES.lock() = None;
+        *SOUNDS.write() = None;
+
+        Ok(())
+    }
+
+    fn supports_teardown(&self) -> bool {
+        true
+    }
+
+    fn register_lua_funcs(&self, _lua_ctx: Context) -> rlua::Result<()> {
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        &[]
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,291 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Loading of out-of-tree plugins from shared objects, e.g. integrations with a
+//! Stream Deck or OBS. Plugins run with the privileges of the daemon, so only
+//! files that are owned by root and not writable by others are loaded.
+//!
+//! Plugins export a C ABI vtable, that is wrapped by a [`DynamicPlugin`]. Trait
+//! objects and types of the Lua VM never cross the boundary of the shared object,
+//! so out-of-tree plugins can not supply Lua functions.
+
+use eruption_sdk::plugin::{self, PluginVTable};
+use eruption_sdk::ApiFunction;
+use log::*;
+use rlua::Context;
+use std::any::Any;
+use std::ffi::{c_void, CStr, CString};
+use std::fs;
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::constants;
+use crate::plugins::{self, Plugin};
+
+pub type Result<T> = std::result::Result<T, LoaderError>;
+
+#[derive(Debug, Error)]
+pub enum LoaderError {
+    #[error("Insecure permissions, the file must be owned by root and not writable by others")]
+    InsecurePermissions {},
+
+    #[error("Could not load the shared object: {description}")]
+    LoadError { description: String },
+
+    #[error("The shared object does not export a plugin")]
+    MissingDeclaration {},
+
+    #[error("Incompatible ABI version {version}, expected {expected}")]
+    AbiVersionMismatch { version: u32, expected: u32 },
+
+    #[error("The plugin could not be created")]
+    CreateError {},
+
+    #[error("Failed to {operation} the plugin {name}")]
+    PluginError { operation: String, name: String },
+}
+
+/// Get the error message of the dynamic linker
+fn dlerror() -> String {
+    let error = unsafe { libc::dlerror() };
+
+    if error.is_null() {
+        "unknown error".into()
+    } else {
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+/// Copy the string `s`, that is owned by a plugin
+fn from_c_string(s: *const c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(s) }.to_string_lossy().to_string()
+    }
+}
+
+/// An instance of an out-of-tree plugin, that is accessed via its vtable
+pub struct DynamicPlugin {
+    vtable: &'static PluginVTable,
+    instance: *mut c_void,
+
+    name: String,
+    description: String,
+}
+
+// the vtable requires plugins to be `Send` and `Sync`
+unsafe impl Send for DynamicPlugin {}
+unsafe impl Sync for DynamicPlugin {}
+
+impl DynamicPlugin {
+    /// Create an instance of the plugin of the vtable `vtable`, the ABI version
+    /// must have been checked
+    fn new(vtable: &'static PluginVTable) -> Result<Self> {
+        let instance = unsafe { (vtable.create)() };
+
+        if instance.is_null() {
+            return Err(LoaderError::CreateError {});
+        }
+
+        let name = from_c_string(unsafe { (vtable.get_name)(instance) });
+        let description = from_c_string(unsafe { (vtable.get_description)(instance) });
+
+        Ok(Self {
+            vtable,
+            instance,
+            name,
+            description,
+        })
+    }
+
+    fn check(&self, operation: &str, status: i32) -> plugins::Result<()> {
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(LoaderError::PluginError {
+                operation: operation.into(),
+                name: self.name.clone(),
+            }
+            .into())
+        }
+    }
+}
+
+impl Drop for DynamicPlugin {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.destroy)(self.instance) };
+    }
+}
+
+impl Plugin for DynamicPlugin {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_description(&self) -> String {
+        self.description.clone()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        let status = unsafe { (self.vtable.initialize)(self.instance) };
+
+        self.check("initialize", status)
+    }
+
+    fn teardown(&mut self) -> plugins::Result<()> {
+        let status = unsafe { (self.vtable.teardown)(self.instance) };
+
+        self.check("tear down", status)
+    }
+
+    fn supports_teardown(&self) -> bool {
+        true
+    }
+
+    fn register_lua_funcs(&self, _lua_ctx: Context) -> rlua::Result<()> {
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        &[]
+    }
+
+    fn main_loop_hook(&self, ticks: u64) {
+        unsafe { (self.vtable.main_loop_hook)(self.instance, ticks) };
+    }
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        match unsafe { (self.vtable.get_hook_interval)(self.instance) } {
+            0 => None,
+            interval => Some(interval),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Load the plugin from the shared object `path`
+fn load_plugin(path: &Path) -> Result<Box<dyn Plugin + Sync + Send>> {
+    let metadata = fs::metadata(path).map_err(|e| LoaderError::LoadError {
+        description: format!("{}", e),
+    })?;
+
+    if metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+        return Err(LoaderError::InsecurePermissions {});
+    }
+
+    let c_path =
+        CString::new(path.as_os_str().as_bytes()).map_err(|_e| LoaderError::LoadError {
+            description: "Invalid file name".into(),
+        })?;
+
+    // the shared object is never unloaded, since the vtable of the plugin
+    // resides in it
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+
+    if handle.is_null() {
+        return Err(LoaderError::LoadError {
+            description: dlerror(),
+        });
+    }
+
+    let symbol = CString::new(plugin::PLUGIN_VTABLE_SYMBOL).unwrap();
+    let vtable = unsafe { libc::dlsym(handle, symbol.as_ptr()) } as *const PluginVTable;
+
+    if vtable.is_null() {
+        unsafe { libc::dlclose(handle) };
+        return Err(LoaderError::MissingDeclaration {});
+    }
+
+    // the ABI version is checked first, the layout of the other fields may differ
+    let abi_version = unsafe { std::ptr::read(vtable as *const u32) };
+
+    if abi_version != plugin::PLUGIN_ABI_VERSION {
+        unsafe { libc::dlclose(handle) };
+
+        return Err(LoaderError::AbiVersionMismatch {
+            version: abi_version,
+            expected: plugin::PLUGIN_ABI_VERSION,
+        });
+    }
+
+    let vtable: &'static PluginVTable = unsafe { &*vtable };
+
+    Ok(Box::new(DynamicPlugin::new(vtable)?))
+}
+
+/// Get the directory of the out-of-tree plugins
+fn get_plugin_dir() -> PathBuf {
+    crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get_str("global.plugin_dir").ok())
+        .unwrap_or_else(|| constants::DEFAULT_PLUGIN_DIR.into())
+        .into()
+}
+
+/// Load all out-of-tree plugins from the plugin directory, in the order of their
+/// file names. Plugins that can't be loaded are skipped
+pub fn load_plugins() -> Vec<Box<dyn Plugin + Sync + Send>> {
+    let plugin_dir = get_plugin_dir();
+
+    let mut files: Vec<PathBuf> = match fs::read_dir(&plugin_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "so"))
+            .collect(),
+
+        // no out-of-tree plugins installed
+        Err(_) => return vec![],
+    };
+
+    files.sort();
+
+    let mut result = vec![];
+
+    for file in files {
+        debug!("Loading plugin: {}", file.display());
+
+        match load_plugin(&file) {
+            Ok(plugin) => {
+                info!(
+                    "Loaded out-of-tree plugin: {} ({})",
+                    plugin.get_name(),
+                    file.display()
+                );
+
+                result.push(plugin);
+            }
+
+            Err(e) => error!("Could not load the plugin {}: {}", file.display(), e),
+        }
+    }
+
+    result
+}
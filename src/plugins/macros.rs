@@ -15,49 +15,288 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use eruption_sdk::ApiFunction;
 use evdev_rs::enums::*;
 use evdev_rs::{Device, InputEvent, TimeVal, UInputDevice};
-use failure::Fail;
 use lazy_static::lazy_static;
 use log::*;
 use parking_lot::Mutex;
 use rlua::Context;
 use std::any::Any;
 use std::cell::RefCell;
-use std::sync::atomic::AtomicBool;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::Ordering;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
+use crate::constants;
+use crate::hotkeys;
+use crate::latency;
+use crate::overlay;
 use crate::plugins::{self, Plugin};
+use crate::scheduling;
+use crate::selftest::{self, Capability};
 
 pub type Result<T> = std::result::Result<T, MacrosPluginError>;
 
 pub enum Message {
     MirrorKey(evdev_rs::InputEvent),
-    InjectKey { key: u32, down: bool },
+    InjectKey {
+        key: u32,
+        down: bool,
+
+        /// Delay after the injection in milliseconds, overrides the default pacing
+        pacing_millis: Option<u64>,
+    },
+
+    /// Release the key `key`, that is stuck. This is not subject to `INJECTION_BLOCKED`
+    ReleaseKey(u32),
+
+    /// Press and release `key`, while exactly the modifiers `modifiers` are held
+    /// down. If `neutralize` is set, other modifiers that are currently held down
+    /// are released first, and pressed again afterwards
+    InjectCombination {
+        key: u32,
+        modifiers: Vec<u32>,
+        neutralize: bool,
+
+        /// Delay after the injection in milliseconds, overrides the default pacing
+        pacing_millis: Option<u64>,
+    },
+
+    /// Discard the queued injections, and release the keys that have been pressed
+    /// by them
+    AbortMacros,
 }
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum MacrosPluginError {
-    #[fail(display = "Could not open the evdev device")]
+    #[error("Could not open the evdev device")]
     EvdevError {},
 
-    #[fail(display = "Could not spawn a thread")]
+    #[error("Could not write to the virtual keyboard device: {description}")]
+    UInputError { description: String },
+
+    #[error("Could not spawn a thread")]
     ThreadSpawnError {},
-    // #[fail(display = "Unknown error: {}", description)]
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
+impl From<std::io::Error> for MacrosPluginError {
+    fn from(e: std::io::Error) -> Self {
+        MacrosPluginError::UInputError {
+            description: format!("{}", e),
+        }
+    }
+}
+
 lazy_static! {
     pub static ref UINPUT_TX: Arc<Mutex<Option<Sender<Message>>>> = Arc::new(Mutex::new(None));
     pub static ref DROP_CURRENT_KEY: AtomicBool = AtomicBool::new(false);
+
+    /// Set while the injection of keys is disabled, e.g. while the session is locked.
+    /// Mirroring of the hardware keyboard is not affected
+    pub static ref INJECTION_BLOCKED: AtomicBool = AtomicBool::new(false);
+
+    /// Number of injections that have been queued, but not yet executed
+    static ref PENDING_INJECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Set if the queue of injections overflowed, so that the warning is logged once
+    static ref QUEUE_OVERFLOWED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Increment `pending`, unless it reached `max_depth`. Returns false if the limit
+/// has been reached
+fn try_reserve(pending: &AtomicUsize, max_depth: usize) -> bool {
+    let mut current = pending.load(Ordering::SeqCst);
+
+    loop {
+        if current >= max_depth {
+            return false;
+        }
+
+        match pending.compare_exchange_weak(
+            current,
+            current + 1,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => return true,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+/// Get the key events, that press and release `key` while exactly the modifiers
+/// `modifiers` are held down, see `Message::InjectCombination`. `held` are the
+/// modifiers that are currently held down, they are restored in the reverse order
+fn combination_steps(
+    key: EV_KEY,
+    modifiers: &[EV_KEY],
+    neutralize: bool,
+    held: &HashSet<u32>,
+) -> Vec<(EV_KEY, i32)> {
+    let mut held: Vec<u32> = held.iter().cloned().collect();
+    held.sort();
+
+    let to_release: Vec<EV_KEY> = if neutralize {
+        held.iter()
+            .filter(|code| !modifiers.iter().any(|m| m.clone() as u32 == **code))
+            .filter_map(|code| evdev_rs::enums::int_to_ev_key(*code))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let to_press: Vec<EV_KEY> = modifiers
+        .iter()
+        .filter(|m| !held.contains(&((*m).clone() as u32)))
+        .cloned()
+        .collect();
+
+    let mut result = vec![];
+
+    result.extend(to_release.iter().map(|m| (m.clone(), 0)));
+    result.extend(to_press.iter().map(|m| (m.clone(), 1)));
+
+    result.push((key.clone(), 1));
+    result.push((key, 0));
+
+    result.extend(to_press.iter().rev().map(|m| (m.clone(), 0)));
+    result.extend(to_release.iter().rev().map(|m| (m.clone(), 1)));
+
+    result
+}
+
+/// Remove all entries from the queue `queue`, returns the mirrored keys in order,
+/// and the number of injections that have been discarded
+fn drain_queue(queue: &mut VecDeque<Message>) -> (Vec<InputEvent>, usize) {
+    let mut mirrored = vec![];
+    let mut discarded = 0;
+
+    for message in queue.drain(..) {
+        match message {
+            Message::MirrorKey(raw_event) => mirrored.push(raw_event),
+            _ => discarded += 1,
+        }
+    }
+
+    (mirrored, discarded)
+}
+
+/// Queue the injection `message` (`InjectKey` or `InjectCombination`) for the
+/// virtual keyboard. Returns false if the queue is full, or the virtual keyboard
+/// is not available
+pub fn queue_injection(message: Message) -> bool {
+    let max_depth = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get::<usize>("macros.max_queue_depth").ok())
+        .unwrap_or(constants::MACRO_MAX_QUEUE_DEPTH);
+
+    let uinput_tx = UINPUT_TX.lock();
+
+    let uinput_tx = match uinput_tx.as_ref() {
+        Some(uinput_tx) => uinput_tx,
+        None => return false,
+    };
+
+    if !try_reserve(&PENDING_INJECTIONS, max_depth) {
+        if !QUEUE_OVERFLOWED.swap(true, Ordering::SeqCst) {
+            warn!(
+                "The queue of the virtual keyboard is full, dropping injected keys (max. {})",
+                max_depth
+            );
+        }
+
+        return false;
+    }
+
+    uinput_tx.send(message).map_or_else(
+        |e| {
+            PENDING_INJECTIONS.fetch_sub(1, Ordering::SeqCst);
+            error!("Could not send a pending keyboard event: {}", e);
+
+            false
+        },
+        |_| true,
+    )
+}
+
+/// Abort all running macros: The queued injections are discarded, keys that have
+/// been pressed by an injection are released
+pub fn abort_macros() {
+    if let Some(uinput_tx) = UINPUT_TX.lock().as_ref() {
+        uinput_tx
+            .send(Message::AbortMacros)
+            .unwrap_or_else(|e| error!("Could not send a pending keyboard event: {}", e));
+    }
+}
+
+/// Returns true while injections are queued, e.g. the keys of a macro
+pub fn is_macro_running() -> bool {
+    PENDING_INJECTIONS.load(Ordering::SeqCst) > 0
+}
+
+lazy_static! {
+    /// Keys that act as a modifier for the media keys, any of them will do
+    static ref MEDIA_KEY_MODIFIERS: Mutex<Vec<u32>> = Mutex::new(vec![]);
+
+    /// Maps key codes to the media keys that will be injected instead, while
+    /// one of the media key modifiers is held down
+    static ref MEDIA_KEY_BINDINGS: Mutex<HashMap<u32, EV_KEY>> = Mutex::new(HashMap::new());
+}
+
+/// Modifier keys, their state on the virtual keyboard is tracked so that
+/// injected keys are not altered by modifiers that happen to be held down
+const MODIFIER_KEYS: &[EV_KEY] = &[
+    EV_KEY::KEY_LEFTCTRL,
+    EV_KEY::KEY_RIGHTCTRL,
+    EV_KEY::KEY_LEFTSHIFT,
+    EV_KEY::KEY_RIGHTSHIFT,
+    EV_KEY::KEY_LEFTALT,
+    EV_KEY::KEY_RIGHTALT,
+    EV_KEY::KEY_LEFTMETA,
+    EV_KEY::KEY_RIGHTMETA,
+];
+
+/// Returns true if the key with the key code `code` is a modifier key
+pub fn is_modifier(code: u32) -> bool {
+    MODIFIER_KEYS.iter().any(|key| key.clone() as u32 == code)
 }
 
 thread_local! {
     static DEVICE: RefCell<Option<UInputDevice>> = RefCell::new(None);
-    static MODIFIER_PRESSED: RefCell<bool> = RefCell::new(false);
+
+    /// Modifier keys that are currently held down on the virtual keyboard
+    static HELD_MODIFIERS: RefCell<HashSet<u32>> = RefCell::new(HashSet::new());
+
+    /// Keys that have been mapped to a media key when they were pressed, so that
+    /// their release is mapped too, even if the modifier has been released already
+    static MAPPED_KEYS: RefCell<HashMap<u32, EV_KEY>> = RefCell::new(HashMap::new());
+
+    /// Keys that have been pressed by `Message::InjectKey`, and not yet released
+    static INJECTED_KEYS: RefCell<HashSet<u32>> = RefCell::new(HashSet::new());
+}
+
+/// Get the media key that is injected for the action `action`
+fn media_key_from_action(action: &str) -> Option<EV_KEY> {
+    match action {
+        "previous" => Some(EV_KEY::KEY_PREVIOUSSONG),
+        "stop" => Some(EV_KEY::KEY_STOPCD),
+        "play" => Some(EV_KEY::KEY_PLAYPAUSE),
+        "next" => Some(EV_KEY::KEY_NEXTSONG),
+        "mute" => Some(EV_KEY::KEY_MUTE),
+        "volume-down" => Some(EV_KEY::KEY_VOLUMEDOWN),
+        "volume-up" => Some(EV_KEY::KEY_VOLUMEUP),
+
+        _ => None,
+    }
 }
 
 /// Implements support for macros by registering a virtual keyboard with the
@@ -70,7 +309,7 @@ impl MacrosPlugin {
     }
 
     fn initialize_thread_locals() -> Result<()> {
-        let dev = Device::new().unwrap();
+        let dev = Device::new().ok_or(MacrosPluginError::EvdevError {})?;
 
         // setup virtual keyboard device
         dev.set_name("Eruption Virtual Keyboard");
@@ -80,18 +319,15 @@ impl MacrosPlugin {
         dev.set_version(0x01);
 
         // configure allowed events
-        dev.enable(&EventType::EV_KEY).unwrap();
-        dev.enable(&EventType::EV_MSC).unwrap();
-        dev.enable(&EventCode::EV_SYN(EV_SYN::SYN_REPORT)).unwrap();
+        dev.enable(&EventType::EV_KEY)?;
+        dev.enable(&EventType::EV_MSC)?;
+        dev.enable(&EventCode::EV_SYN(EV_SYN::SYN_REPORT))?;
 
         // enable media keys
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PREVIOUSSONG))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_STOPCD)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PLAYPAUSE))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_NEXTSONG))
-            .unwrap();
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PREVIOUSSONG))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_STOPCD))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PLAYPAUSE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_NEXTSONG))?;
 
         // Enable all supported keys; this is used to mirror the hardware device
         // to the virtual keyboard, so that the hardware device can be disabled.
@@ -99,201 +335,169 @@ impl MacrosPlugin {
         // Generated via `sudo evtest`
         // Input device name: "ROCCAT ROCCAT Vulcan AIMO"
         // Supported events:
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_ESC)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_1)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_2)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_3)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_4)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_5)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_6)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_7)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_8)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_9)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_0)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_MINUS)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_EQUAL)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_BACKSPACE))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_TAB)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_Q)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_W)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_E)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_R)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_T)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_Y)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_U)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_I)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_O)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_P)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTBRACE))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTBRACE))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_ENTER)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTCTRL))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_A)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_S)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_D)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_G)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_H)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_J)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_K)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_L)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SEMICOLON))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_APOSTROPHE))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_GRAVE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTSHIFT))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_BACKSLASH))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_Z)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_X)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_C)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_V)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_B)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_N)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_M)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_COMMA)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_DOT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SLASH)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTSHIFT))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPASTERISK))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTALT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SPACE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_CAPSLOCK))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F1)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F2)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F3)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F4)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F5)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F6)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F7)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F8)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F9)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F10)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_NUMLOCK)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCROLLLOCK))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP7)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP8)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP9)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPMINUS)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP4)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP5)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP6)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPPLUS)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP1)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP2)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP3)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP0)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPDOT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_ZENKAKUHANKAKU))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_102ND)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F11)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F12)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RO)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KATAKANA))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HIRAGANA))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HENKAN)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KATAKANAHIRAGANA))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_MUHENKAN))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPJPCOMMA))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPENTER)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTCTRL))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPSLASH)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SYSRQ)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTALT))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HOME)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_UP)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PAGEUP)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_END)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_DOWN)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PAGEDOWN))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_INSERT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_DELETE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_MUTE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_VOLUMEDOWN))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_VOLUMEUP))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_POWER)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPEQUAL)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PAUSE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPCOMMA)).unwrap();
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_ESC))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_1))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_2))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_3))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_4))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_5))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_6))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_7))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_8))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_9))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_0))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_MINUS))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_EQUAL))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_BACKSPACE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_TAB))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_Q))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_W))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_E))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_R))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_T))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_Y))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_U))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_I))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_O))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_P))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTBRACE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTBRACE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_ENTER))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTCTRL))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_A))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_S))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_D))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_G))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_H))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_J))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_K))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_L))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SEMICOLON))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_APOSTROPHE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_GRAVE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTSHIFT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_BACKSLASH))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_Z))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_X))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_C))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_V))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_B))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_N))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_M))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_COMMA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_DOT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SLASH))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTSHIFT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPASTERISK))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTALT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SPACE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_CAPSLOCK))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F1))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F2))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F3))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F4))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F5))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F6))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F7))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F8))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F9))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F10))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_NUMLOCK))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCROLLLOCK))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP7))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP8))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP9))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPMINUS))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP4))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP5))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP6))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPPLUS))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP1))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP2))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP3))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KP0))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPDOT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_ZENKAKUHANKAKU))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_102ND))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F11))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F12))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RO))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KATAKANA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HIRAGANA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HENKAN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KATAKANAHIRAGANA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_MUHENKAN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPJPCOMMA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPENTER))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTCTRL))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPSLASH))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SYSRQ))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTALT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HOME))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_UP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PAGEUP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_END))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_DOWN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PAGEDOWN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_INSERT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_DELETE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_MUTE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_VOLUMEDOWN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_VOLUMEUP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_POWER))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPEQUAL))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PAUSE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPCOMMA))?;
         //dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HANGUEL)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HANJA)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_YEN)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTMETA))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTMETA))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_COMPOSE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_STOP)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_AGAIN)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PROPS)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_UNDO)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_FRONT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_COPY)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_OPEN)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PASTE)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_FIND)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_CUT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HELP)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_CALC)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SLEEP)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_WWW)).unwrap();
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HANJA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_YEN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_LEFTMETA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_RIGHTMETA))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_COMPOSE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_STOP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_AGAIN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PROPS))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_UNDO))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_FRONT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_COPY))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_OPEN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PASTE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_FIND))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_CUT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_HELP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_CALC))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SLEEP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_WWW))?;
         //dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCREENLOCK)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_BACK)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_FORWARD)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_EJECTCD)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_NEXTSONG))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PLAYPAUSE))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PREVIOUSSONG))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_STOPCD)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_REFRESH)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_EDIT)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCROLLUP))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCROLLDOWN))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPLEFTPAREN))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPRIGHTPAREN))
-            .unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F13)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F14)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F15)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F16)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F17)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F18)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F19)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F20)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F21)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F22)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F23)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F24)).unwrap();
-        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_UNKNOWN)).unwrap();
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_BACK))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_FORWARD))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_EJECTCD))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_NEXTSONG))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PLAYPAUSE))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_PREVIOUSSONG))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_STOPCD))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_REFRESH))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_EDIT))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCROLLUP))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_SCROLLDOWN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPLEFTPAREN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_KPRIGHTPAREN))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F13))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F14))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F15))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F16))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F17))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F18))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F19))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F20))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F21))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F22))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F23))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_F24))?;
+        dev.enable(&EventCode::EV_KEY(EV_KEY::KEY_UNKNOWN))?;
 
         match UInputDevice::create_from_device(&dev) {
             Ok(device) => {
@@ -310,7 +514,7 @@ impl MacrosPlugin {
     fn inject_single_key(key: EV_KEY, value: i32, time: &TimeVal) -> Result<()> {
         //let mut do_initialize = false;
 
-        DEVICE.with(|dev| {
+        DEVICE.with(|dev| -> Result<()> {
             let device = dev.borrow();
 
             if let Some(device) = device.as_ref() {
@@ -321,7 +525,8 @@ impl MacrosPlugin {
                     value,
                 };
 
-                device.write_event(&event).unwrap();
+                device.write_event(&event)?;
+                Self::track_modifier(key.clone() as u32, value);
 
                 let event = InputEvent {
                     time: time.clone(),
@@ -330,158 +535,443 @@ impl MacrosPlugin {
                     value,
                 };
 
-                device.write_event(&event).unwrap();
+                device.write_event(&event)?;
             } else {
                 error!("Inconsistent thread local storage state detected");
                 //do_initialize = true;
             }
-        });
+
+            Ok(())
+        })?;
 
         //if do_initialize {
-            //Self::initialize_thread_locals().unwrap();
+        //Self::initialize_thread_locals().unwrap();
         //}
 
         Ok(())
     }
 
+    /// Record the state of the key `code` on the virtual keyboard, if it is a modifier
+    fn track_modifier(code: u32, value: i32) {
+        if is_modifier(code) {
+            HELD_MODIFIERS.with(|held| {
+                if value == 0 {
+                    held.borrow_mut().remove(&code);
+                } else {
+                    held.borrow_mut().insert(code);
+                }
+            });
+        }
+    }
+
     /// Inject a pre-existing InputEvent into to output of the virtual keyboard
     fn inject_key_event(event: evdev_rs::InputEvent) -> Result<()> {
         let mut do_initialize = false;
 
-        DEVICE.with(|dev| {
+        DEVICE.with(|dev| -> Result<()> {
             debug!("Injecting: {:?}", event);
 
             if let Some(device) = dev.borrow().as_ref() {
-                device.write_event(&event).unwrap();
+                device.write_event(&event)?;
+
+                if let EventCode::EV_KEY(ref key) = event.event_code {
+                    Self::track_modifier(key.clone() as u32, event.value);
+                }
             } else {
                 do_initialize = true;
             }
-        });
+
+            Ok(())
+        })?;
 
         if do_initialize {
-            Self::initialize_thread_locals().unwrap();
+            Self::initialize_thread_locals()?;
         }
 
         Ok(())
     }
 
+    /// Load the media key bindings from the `[media_keys]` section of the
+    /// configuration file, the defaults map RIGHTCTRL/EASYSHIFT + F9..F12
+    fn load_media_key_bindings() {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        let mut modifiers = MEDIA_KEY_MODIFIERS.lock();
+        let mut bindings = MEDIA_KEY_BINDINGS.lock();
+
+        modifiers.clear();
+        bindings.clear();
+
+        if !config.get::<bool>("media_keys.enabled").unwrap_or(true) {
+            info!("Media keys DISABLED by configuration");
+            return;
+        }
+
+        if !selftest::has_capability(Capability::MediaKeys) {
+            warn!("Media keys DISABLED, the device self-test did not succeed");
+            return;
+        }
+
+        let modifier_names = config
+            .get::<Vec<String>>("media_keys.modifiers")
+            .unwrap_or_else(|_| vec!["RIGHTCTRL".into(), "EASYSHIFT".into()]);
+
+        for name in modifier_names.iter() {
+            match hotkeys::parse_key_name(name) {
+                Ok(code) => modifiers.push(code),
+                Err(e) => error!("Invalid media key modifier: {}", e),
+            }
+        }
+
+        let binding_names = config
+            .get::<HashMap<String, String>>("media_keys.bindings")
+            .unwrap_or_else(|_| {
+                [
+                    ("F9", "previous"),
+                    ("F10", "stop"),
+                    ("F11", "play"),
+                    ("F12", "next"),
+                ]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+            });
+
+        for (name, action) in binding_names.iter() {
+            match (hotkeys::parse_key_name(name), media_key_from_action(action)) {
+                (Ok(code), Some(media_key)) => {
+                    bindings.insert(code, media_key);
+                }
+
+                (Err(e), _) => error!("Invalid media key binding: {}", e),
+                (_, None) => error!("Invalid media key action: {}", action),
+            }
+        }
+    }
+
+    /// Inject the media key that is bound to the key of `event`, if one of the
+    /// media key modifiers is held down. Returns true if a media key has been
+    /// injected, in which case the original event must not be mirrored
+    fn map_media_key(event: &evdev_rs::InputEvent) -> Result<bool> {
+        let code = match event.event_code {
+            EventCode::EV_KEY(ref key) => key.clone() as u32,
+            _ => return Ok(false),
+        };
+
+        let media_key = MAPPED_KEYS.with(|mapped_keys| {
+            let mut mapped_keys = mapped_keys.borrow_mut();
+
+            match event.value {
+                // key up
+                0 => mapped_keys.remove(&code),
+
+                // key down
+                1 => {
+                    let is_modifier_pressed = MEDIA_KEY_MODIFIERS
+                        .lock()
+                        .iter()
+                        .any(|m| hotkeys::is_key_pressed(*m));
+
+                    if is_modifier_pressed {
+                        if let Some(media_key) = MEDIA_KEY_BINDINGS.lock().get(&code) {
+                            mapped_keys.insert(code, media_key.clone());
+                        }
+                    }
+
+                    mapped_keys.get(&code).cloned()
+                }
+
+                // auto repeat
+                _ => mapped_keys.get(&code).cloned(),
+            }
+        });
+
+        match media_key {
+            Some(media_key) => {
+                trace!("Mapped to media key: {:?}", media_key);
+
+                if event.value == 1 {
+                    match media_key {
+                        EV_KEY::KEY_MUTE | EV_KEY::KEY_VOLUMEDOWN | EV_KEY::KEY_VOLUMEUP => {
+                            overlay::show_last_volume()
+                        }
+
+                        _ => (),
+                    }
+                }
+
+                Self::inject_single_key(media_key, event.value, &event.time)?;
+
+                Ok(true)
+            }
+
+            None => Ok(false),
+        }
+    }
+
+    /// The current time, as a timestamp of injected events
+    fn now() -> TimeVal {
+        let mut time: libc::timeval = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+
+        unsafe {
+            libc::gettimeofday(&mut time, std::ptr::null_mut());
+        }
+
+        evdev_rs::TimeVal::from_raw(&time)
+    }
+
+    /// Release the keys that have been pressed by `Message::InjectKey`, e.g. when
+    /// a macro has been aborted
+    fn release_injected_keys() {
+        let keys: Vec<u32> = INJECTED_KEYS.with(|keys| keys.borrow_mut().drain().collect());
+
+        for key in keys.into_iter().filter_map(evdev_rs::enums::int_to_ev_key) {
+            Self::inject_single_key(key, 0, &Self::now())
+                .unwrap_or_else(|e| error!("Could not release a key: {}", e));
+        }
+    }
+
+    /// Execute the queued injection `injection`. Returns the key events of a
+    /// combination, they are executed one by one by the caller
+    fn execute_injection(injection: Message) -> Vec<(EV_KEY, i32)> {
+        match injection {
+            Message::InjectKey { .. } | Message::InjectCombination { .. }
+                if INJECTION_BLOCKED.load(Ordering::SeqCst) =>
+            {
+                debug!("Key injection is currently disabled, dropping the key");
+
+                vec![]
+            }
+
+            Message::InjectKey {
+                key: ev_key, down, ..
+            } => {
+                let key = match evdev_rs::enums::int_to_ev_key(ev_key) {
+                    Some(key) => key,
+
+                    None => {
+                        error!("Invalid key code: {}", ev_key);
+                        return vec![];
+                    }
+                };
+
+                INJECTED_KEYS.with(|keys| {
+                    if down {
+                        keys.borrow_mut().insert(ev_key);
+                    } else {
+                        keys.borrow_mut().remove(&ev_key);
+                    }
+                });
+
+                let value = if down { 1 } else { 0 };
+
+                Self::inject_single_key(key, value, &Self::now())
+                    .unwrap_or_else(|e| error!("Could not inject a key: {}", e));
+
+                vec![]
+            }
+
+            Message::InjectCombination {
+                key,
+                modifiers,
+                neutralize,
+                ..
+            } => {
+                let keys: Option<Vec<EV_KEY>> = std::iter::once(key)
+                    .chain(modifiers.iter().cloned())
+                    .map(evdev_rs::enums::int_to_ev_key)
+                    .collect();
+
+                let mut keys = match keys {
+                    Some(keys) => keys,
+
+                    None => {
+                        error!("Invalid key code in combination: {:?}", modifiers);
+                        return vec![];
+                    }
+                };
+
+                let key = keys.remove(0);
+
+                HELD_MODIFIERS
+                    .with(|held| combination_steps(key, &keys, neutralize, &held.borrow()))
+            }
+
+            _ => vec![],
+        }
+    }
+
+    /// Mirror the key event `raw_event` of the hardware keyboard on the virtual
+    /// keyboard, media keys are injected instead of the original key
+    fn mirror_key(raw_event: &evdev_rs::InputEvent) {
+        let result = Self::map_media_key(raw_event).and_then(|mapped| {
+            if !mapped {
+                Self::inject_key_event(raw_event.clone())
+            } else {
+                Ok(())
+            }
+        });
+
+        result.unwrap_or_else(|e| error!("Could not mirror a key: {}", e));
+
+        latency::mark(raw_event, latency::Stage::Write);
+    }
+
     fn spawn_uinput_thread() -> Result<()> {
         let (uinput_tx, uinput_rx) = channel();
 
         thread::Builder::new()
             .name("uinput".into())
             .spawn(move || {
-                Self::initialize_thread_locals().unwrap();
-
-                // media keys will be handled by the Lua script 'macros.lua'
-
-                // register an event observer for processing of all raw
-                // keyboard events from the hardware keyboard
-                //events::register_observer(|event: &events::Event| {
-                    //match event {
-                        //events::Event::RawKeyboardEvent(raw_event) => {
-                            ////debug!("Key event: {:?}", raw_event);
-
-                            //let mut already_processed = false;
-
-                            //if let EventCode::EV_KEY(ref code) = raw_event.event_code {
-                                //if code == &EV_KEY::KEY_RIGHTCTRL {
-                                    //MODIFIER_PRESSED.with(|modifier| {
-                                        //*modifier.borrow_mut() = raw_event.value > 0
-                                    //});
-                                //}
-
-                                //// support media keys
-                                //MODIFIER_PRESSED.with(|modifier| {
-                                    //if raw_event.value < 2 {
-                                        //trace!("Modifier pressed: {}", *modifier.borrow());
-
-                                        //if code == &EV_KEY::KEY_F9 && *modifier.borrow() {
-                                            //Self::inject_single_key(
-                                                //EV_KEY::KEY_PREVIOUSSONG,
-                                                //raw_event.value,
-                                                //&raw_event.time,
-                                            //)
-                                            //.unwrap();
-                                            //already_processed = true;
-                                        //}
-
-                                        //if code == &EV_KEY::KEY_F10 && *modifier.borrow() {
-                                            //Self::inject_single_key(
-                                                //EV_KEY::KEY_STOPCD,
-                                                //raw_event.value,
-                                                //&raw_event.time,
-                                            //)
-                                            //.unwrap();
-                                            //already_processed = true;
-                                        //}
-
-                                        //if code == &EV_KEY::KEY_F11 && *modifier.borrow() {
-                                            //Self::inject_single_key(
-                                                //EV_KEY::KEY_PLAYPAUSE,
-                                                //raw_event.value,
-                                                //&raw_event.time,
-                                            //)
-                                            //.unwrap();
-                                            //already_processed = true;
-                                        //}
-
-                                        //if code == &EV_KEY::KEY_F12 && *modifier.borrow() {
-                                            //Self::inject_single_key(
-                                                //EV_KEY::KEY_NEXTSONG,
-                                                //raw_event.value,
-                                                //&raw_event.time,
-                                            //)
-                                            //.unwrap();
-                                            //already_processed = true;
-                                        //}
-                                    //}
-                                //});
-                            //}
-
-                            //if !already_processed [>&& !DROP_CURRENT_KEY.load(Ordering::SeqCst)<] {
-                                //// mirror hardware keyboard to virtual keyboard
-                                //Self::inject_key_event(raw_event.clone()).unwrap();
-                            //}
-
-                            //Ok(true)
-                        //}
-
-                        //_ => Ok(false),
-                    //}
-                //});
+                if let Err(e) = Self::initialize_thread_locals() {
+                    error!("Could not create the virtual keyboard device: {}", e);
+                    return;
+                }
+
+                scheduling::apply("uinput");
+
+                let step_delay = crate::CONFIG
+                    .lock()
+                    .as_ref()
+                    .and_then(|config| config.get::<u64>("macros.step_delay_millis").ok())
+                    .unwrap_or(constants::MACRO_STEP_DELAY_MILLIS);
 
+                // injections are executed one by one, paced so that applications
+                // can keep up, and so that they may be aborted. Keys that are
+                // mirrored while injections are queued, are queued as well, so that
+                // they reach the applications in order, but they are not paced
+                let mut queue = VecDeque::new();
+                let mut next_step = Instant::now();
+
+                // the remaining key events of the current combination, and their pacing
+                let mut steps: VecDeque<(EV_KEY, i32)> = VecDeque::new();
+                let mut step_pacing = step_delay;
+
+                // the loop ends when the sender has been dropped
                 loop {
-                    let message = uinput_rx.recv().unwrap();
+                    let busy = !queue.is_empty() || !steps.is_empty();
+                    let due = busy && Instant::now() >= next_step;
+
+                    // while injections are due, pending messages are still handled
+                    // between the steps, so that a macro can be aborted even without
+                    // a step delay
+                    let message = if due {
+                        match uinput_rx.try_recv() {
+                            Ok(message) => Some(message),
+                            Err(TryRecvError::Empty) => None,
+                            Err(TryRecvError::Disconnected) => break,
+                        }
+                    } else if !busy {
+                        match uinput_rx.recv() {
+                            Ok(message) => Some(message),
+                            Err(_) => break,
+                        }
+                    } else {
+                        match uinput_rx
+                            .recv_timeout(next_step.saturating_duration_since(Instant::now()))
+                        {
+                            Ok(message) => Some(message),
+                            Err(RecvTimeoutError::Timeout) => None,
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                    };
+
                     match message {
-                        Message::MirrorKey(raw_event) => {
-                            if !DROP_CURRENT_KEY.load(Ordering::SeqCst) {
-                                Self::inject_key_event(raw_event).unwrap();
-                            } else {
+                        Some(Message::MirrorKey(raw_event)) => {
+                            if DROP_CURRENT_KEY.load(Ordering::SeqCst) {
                                 debug!("Original input has been dropped, as requested");
+                            } else if queue.is_empty() && steps.is_empty() {
+                                Self::mirror_key(&raw_event);
+                            } else {
+                                queue.push_back(Message::MirrorKey(raw_event));
                             }
                         }
 
-                        Message::InjectKey { key: ev_key, down } => {
-                            let key = evdev_rs::enums::int_to_ev_key(ev_key).unwrap_or_else(|| {
-                                error!("Invalid key index");
-                                panic!()
-                            });
+                        Some(Message::ReleaseKey(code)) => {
+                            match evdev_rs::enums::int_to_ev_key(code) {
+                                Some(key) => Self::inject_single_key(key, 0, &Self::now())
+                                    .unwrap_or_else(|e| error!("Could not release a key: {}", e)),
+
+                                None => error!("Invalid key code: {}", code),
+                            }
+                        }
 
-                            let value = if down { 1 } else { 0 };
+                        Some(Message::AbortMacros) => {
+                            let (mirrored, discarded) = drain_queue(&mut queue);
 
-                            let mut time: libc::timeval = libc::timeval {
-                                tv_sec: 0,
-                                tv_usec: 0,
-                            };
+                            // modifiers of an aborted combination must not get stuck
+                            for (key, _) in steps.drain(..).filter(|(_, value)| *value == 0) {
+                                Self::inject_single_key(key, 0, &Self::now())
+                                    .unwrap_or_else(|e| error!("Could not release a key: {}", e));
+                            }
 
-                            unsafe {
-                                libc::gettimeofday(&mut time, std::ptr::null_mut());
+                            // mirrored keys are not discarded, the user typed them
+                            for raw_event in mirrored.iter() {
+                                Self::mirror_key(raw_event);
                             }
 
-                            let time = evdev_rs::TimeVal::from_raw(&time);
+                            PENDING_INJECTIONS.fetch_sub(discarded, Ordering::SeqCst);
+
+                            Self::release_injected_keys();
+
+                            info!("Aborted macros, discarded {} queued key(s)", discarded);
+                        }
+
+                        Some(injection) => queue.push_back(injection),
+
+                        // the next queued injection is due
+                        None => (),
+                    }
+
+                    // execute the next key event of a combination, or the next
+                    // queued entry, unless the queue has been discarded in the meantime
+                    if due {
+                        if let Some((key, value)) = steps.pop_front() {
+                            Self::inject_single_key(key, value, &Self::now())
+                                .unwrap_or_else(|e| error!("Could not inject a key: {}", e));
+
+                            next_step = Instant::now() + Duration::from_millis(step_pacing);
+
+                            continue;
+                        }
+
+                        match queue.pop_front() {
+                            Some(Message::MirrorKey(raw_event)) => Self::mirror_key(&raw_event),
+
+                            Some(injection) => {
+                                PENDING_INJECTIONS.fetch_sub(1, Ordering::SeqCst);
+                                QUEUE_OVERFLOWED.store(false, Ordering::SeqCst);
+
+                                let pacing = match injection {
+                                    Message::InjectKey { pacing_millis, .. }
+                                    | Message::InjectCombination { pacing_millis, .. } => {
+                                        pacing_millis.unwrap_or(step_delay)
+                                    }
+
+                                    _ => step_delay,
+                                }
+                                .min(constants::MACRO_MAX_PACING_MILLIS);
+
+                                // each key event of a combination is paced, e.g.
+                                // modifier down, key down, key up, modifier up
+                                steps.extend(Self::execute_injection(injection));
+                                step_pacing = pacing;
 
-                            Self::inject_single_key(key, value, &time).unwrap();
+                                if let Some((key, value)) = steps.pop_front() {
+                                    Self::inject_single_key(key, value, &Self::now())
+                                        .unwrap_or_else(|e| {
+                                            error!("Could not inject a key: {}", e)
+                                        });
+                                }
+
+                                next_step = Instant::now() + Duration::from_millis(pacing);
+                            }
+
+                            None => (),
                         }
                     }
                 }
@@ -504,6 +994,7 @@ impl Plugin for MacrosPlugin {
     }
 
     fn initialize(&mut self) -> plugins::Result<()> {
+        Self::load_media_key_bindings();
         Self::spawn_uinput_thread()?;
 
         Ok(())
@@ -513,8 +1004,16 @@ impl Plugin for MacrosPlugin {
         Ok(())
     }
 
+    fn get_api(&self) -> &'static [ApiFunction] {
+        &[]
+    }
+
     fn main_loop_hook(&self, _ticks: u64) {}
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -523,3 +1022,113 @@ impl Plugin for MacrosPlugin {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_event(key: EV_KEY, value: i32) -> InputEvent {
+        InputEvent {
+            time: TimeVal::from_raw(&libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            }),
+            event_type: EventType::EV_KEY,
+            event_code: EventCode::EV_KEY(key),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_combination_steps() {
+        let held = HashSet::new();
+
+        assert_eq!(
+            combination_steps(EV_KEY::KEY_H, &[EV_KEY::KEY_LEFTSHIFT], true, &held),
+            vec![
+                (EV_KEY::KEY_LEFTSHIFT, 1),
+                (EV_KEY::KEY_H, 1),
+                (EV_KEY::KEY_H, 0),
+                (EV_KEY::KEY_LEFTSHIFT, 0),
+            ]
+        );
+
+        // a held modifier is released first, and pressed again afterwards
+        let held: HashSet<u32> = vec![EV_KEY::KEY_LEFTCTRL as u32].into_iter().collect();
+
+        assert_eq!(
+            combination_steps(EV_KEY::KEY_H, &[EV_KEY::KEY_LEFTSHIFT], true, &held),
+            vec![
+                (EV_KEY::KEY_LEFTCTRL, 0),
+                (EV_KEY::KEY_LEFTSHIFT, 1),
+                (EV_KEY::KEY_H, 1),
+                (EV_KEY::KEY_H, 0),
+                (EV_KEY::KEY_LEFTSHIFT, 0),
+                (EV_KEY::KEY_LEFTCTRL, 1),
+            ]
+        );
+
+        // not neutralized, and the requested modifier is held already
+        assert_eq!(
+            combination_steps(EV_KEY::KEY_C, &[EV_KEY::KEY_LEFTCTRL], false, &held),
+            vec![(EV_KEY::KEY_C, 1), (EV_KEY::KEY_C, 0)]
+        );
+    }
+
+    #[test]
+    fn test_try_reserve() {
+        let pending = AtomicUsize::new(0);
+
+        assert!(try_reserve(&pending, 2));
+        assert!(try_reserve(&pending, 2));
+        assert!(!try_reserve(&pending, 2));
+        assert_eq!(pending.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_try_reserve_concurrently() {
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let pending = pending.clone();
+
+                thread::spawn(move || (0..100).filter(|_| try_reserve(&pending, 50)).count())
+            })
+            .collect();
+
+        let reserved: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+        // the limit is never exceeded
+        assert_eq!(reserved, 50);
+        assert_eq!(pending.load(Ordering::SeqCst), 50);
+    }
+
+    #[test]
+    fn test_drain_queue() {
+        let mut queue = VecDeque::new();
+
+        queue.push_back(Message::InjectKey {
+            key: EV_KEY::KEY_A as u32,
+            down: true,
+            pacing_millis: None,
+        });
+        queue.push_back(Message::MirrorKey(key_event(EV_KEY::KEY_B, 1)));
+        queue.push_back(Message::InjectCombination {
+            key: EV_KEY::KEY_C as u32,
+            modifiers: vec![EV_KEY::KEY_LEFTCTRL as u32],
+            neutralize: true,
+            pacing_millis: Some(5),
+        });
+        queue.push_back(Message::MirrorKey(key_event(EV_KEY::KEY_B, 0)));
+
+        let (mirrored, discarded) = drain_queue(&mut queue);
+
+        assert!(queue.is_empty());
+        assert_eq!(discarded, 2);
+        assert_eq!(
+            mirrored.iter().map(|e| e.value).collect::<Vec<_>>(),
+            vec![1, 0]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::Context;
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::plugins;
+use crate::plugins::Plugin;
+use crate::scripting::script;
+
+pub type Result<T> = std::result::Result<T, MicrophonePluginError>;
+
+#[derive(Debug, Error)]
+pub enum MicrophonePluginError {
+    #[error("Pulse Audio error: {description}")]
+    PulseError { description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_mic_level() -> f",
+    Dsp,
+    "since 0.1.2",
+    "Returns the level of the microphone in percent, 0 at the noise floor and 100 at full scale. Always 0 if the microphone is disabled";
+
+    "is_voice_active() -> b",
+    Dsp,
+    "since 0.1.2",
+    "Returns true while someone is speaking into the microphone";
+};
+
+/// Level of the microphone and the state of the voice activity detection
+struct MicState {
+    /// Most recent level, in dBFS
+    level_db: f64,
+
+    /// Noise floor, in dBFS
+    noise_floor_db: f64,
+
+    /// Point in time of the last chunk of samples that was above the threshold
+    last_voice: Option<Instant>,
+}
+
+lazy_static! {
+    static ref MIC_STATE: Mutex<MicState> = Mutex::new(MicState {
+        level_db: constants::MIC_MIN_LEVEL_DB,
+        noise_floor_db: constants::MIC_DEFAULT_NOISE_FLOOR_DB,
+        last_voice: None,
+    });
+
+    /// Current state of the voice activity detection
+    static ref VOICE_ACTIVE: AtomicBool = AtomicBool::new(false);
+}
+
+/// Settings of the `[microphone]` section of the config
+#[derive(Debug, Clone)]
+struct MicConfig {
+    device: Option<String>,
+    noise_floor_db: Option<f64>,
+    calibration_secs: u64,
+    vad_threshold_db: f64,
+}
+
+/// Level of the samples `samples` in dBFS
+fn level_db(samples: &[i16]) -> f64 {
+    if samples.is_empty() {
+        return constants::MIC_MIN_LEVEL_DB;
+    }
+
+    let sqr_sum = samples
+        .iter()
+        .map(|s| f64::from(*s))
+        .fold(0.0, |sqr_sum, s| sqr_sum + s * s);
+
+    let rms = (sqr_sum / samples.len() as f64).sqrt() / f64::from(i16::MAX);
+
+    (20.0 * rms.log10()).max(constants::MIC_MIN_LEVEL_DB)
+}
+
+/// Notify the Lua VMs of the start or the end of voice activity
+fn report_voice_activity(active: bool) {
+    if VOICE_ACTIVE.swap(active, Ordering::SeqCst) != active {
+        trace!("Voice activity: {}", active);
+
+        for lua_tx in crate::LUA_TXS.lock().iter() {
+            lua_tx
+                .send(script::Message::VoiceActivity(active))
+                .unwrap_or_else(|e| error!("Send error: {}", e));
+        }
+    }
+}
+
+/// Record the level of a chunk of samples, and update the voice activity detection.
+/// Voice activity ends after the level stayed below the threshold for a while, so
+/// that short pauses between words do not end it
+fn process_chunk(samples: &[i16], config: &MicConfig) {
+    let level = level_db(samples);
+
+    let active = {
+        let mut state = MIC_STATE.lock();
+        state.level_db = level;
+
+        if level > state.noise_floor_db + config.vad_threshold_db {
+            state.last_voice = Some(Instant::now());
+        }
+
+        state.last_voice.map_or(false, |t| {
+            t.elapsed() < Duration::from_millis(constants::MIC_VAD_HANGOVER_MILLIS)
+        })
+    };
+
+    report_voice_activity(active);
+}
+
+/// Record from the microphone, does not return unless an error occurs
+fn run_recorder(config: MicConfig) -> Result<()> {
+    use libpulse_binding as pulse;
+    use libpulse_simple_binding as psimple;
+    use psimple::Simple;
+    use pulse::sample;
+    use pulse::stream::Direction;
+
+    let spec = sample::Spec {
+        format: sample::SAMPLE_S16NE,
+        channels: 1,
+        rate: constants::MIC_SAMPLE_RATE,
+    };
+
+    assert!(spec.is_valid());
+
+    let recorder = Simple::new(
+        None,
+        "eruption",
+        Direction::Record,
+        config.device.as_deref(),
+        "Microphone Level",
+        &spec,
+        None,
+        None,
+    )
+    .map_err(|e| MicrophonePluginError::PulseError {
+        description: format!("Could not open Pulse Audio: {}", e),
+    })?;
+
+    let chunk_size =
+        (constants::MIC_SAMPLE_RATE as usize * constants::MIC_CHUNK_MILLIS as usize / 1000) * 2;
+
+    let mut buffer = vec![0; chunk_size];
+    let mut samples: Vec<i16> = Vec::with_capacity(chunk_size / 2);
+
+    // measure the noise floor, unless it has been configured
+    let calibration_end = match config.noise_floor_db {
+        Some(noise_floor_db) => {
+            MIC_STATE.lock().noise_floor_db = noise_floor_db;
+            None
+        }
+
+        None => Some(Instant::now() + Duration::from_secs(config.calibration_secs)),
+    };
+
+    let mut calibration_levels: Vec<f64> = vec![];
+
+    loop {
+        recorder
+            .read(&mut buffer)
+            .map_err(|e| MicrophonePluginError::PulseError {
+                description: format!("Error during recording: {}", e),
+            })?;
+
+        samples.clear();
+        samples.extend(
+            buffer
+                .chunks_exact(2)
+                .map(|c| i16::from_ne_bytes([c[0], c[1]])),
+        );
+
+        match calibration_end {
+            Some(end) if Instant::now() < end => {
+                calibration_levels.push(level_db(&samples));
+                MIC_STATE.lock().level_db = constants::MIC_MIN_LEVEL_DB;
+
+                continue;
+            }
+
+            Some(_) if !calibration_levels.is_empty() => {
+                let noise_floor_db =
+                    calibration_levels.iter().sum::<f64>() / calibration_levels.len() as f64;
+
+                info!("Noise floor of the microphone: {:.1} dBFS", noise_floor_db);
+
+                MIC_STATE.lock().noise_floor_db = noise_floor_db;
+                calibration_levels.clear();
+            }
+
+            _ => {}
+        }
+
+        process_chunk(&samples, &config);
+    }
+}
+
+/// A plugin that monitors the level of the microphone, and detects voice activity.
+/// Nothing is recorded, unless the plugin has been enabled in the `[microphone]`
+/// section of the config. The samples are never stored or sent anywhere
+pub struct MicrophonePlugin {}
+
+impl MicrophonePlugin {
+    pub fn new() -> Self {
+        MicrophonePlugin {}
+    }
+
+    /// Get the level of the microphone in percent, relative to the noise floor
+    pub fn get_mic_level() -> f64 {
+        let state = MIC_STATE.lock();
+
+        let range = -state.noise_floor_db;
+
+        if range <= 0.0 {
+            0.0
+        } else {
+            ((state.level_db - state.noise_floor_db) / range * 100.0)
+                .max(0.0)
+                .min(100.0)
+        }
+    }
+
+    /// Returns true while someone is speaking
+    pub fn is_voice_active() -> bool {
+        VOICE_ACTIVE.load(Ordering::SeqCst)
+    }
+}
+
+impl Plugin for MicrophonePlugin {
+    fn get_name(&self) -> String {
+        "Microphone".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Monitor the level of the microphone, and detect voice activity".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        let config = {
+            let config = crate::CONFIG.lock();
+            let config = config.as_ref().unwrap();
+
+            if !config.get::<bool>("microphone.enabled").unwrap_or(false) {
+                info!("Microphone DISABLED by configuration");
+                return Ok(());
+            }
+
+            MicConfig {
+                device: config.get_str("microphone.device").ok(),
+                noise_floor_db: config.get_float("microphone.noise_floor_db").ok(),
+                calibration_secs: config
+                    .get_int("microphone.calibration_secs")
+                    .unwrap_or(constants::MIC_CALIBRATION_SECS as i64)
+                    as u64,
+                vad_threshold_db: config
+                    .get_float("microphone.vad_threshold_db")
+                    .unwrap_or(constants::MIC_VAD_THRESHOLD_DB),
+            }
+        };
+
+        info!(
+            "Monitoring the microphone: {}",
+            config.device.as_deref().unwrap_or("default source")
+        );
+
+        thread::Builder::new()
+            .name("microphone".into())
+            .spawn(move || {
+                run_recorder(config).unwrap_or_else(|e| error!("Microphone: {}", e));
+            })
+            .map_err(|_e| MicrophonePluginError::ThreadSpawnError {})?;
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_mic_level =
+            lua_ctx.create_function(|_, ()| Ok(MicrophonePlugin::get_mic_level()))?;
+        globals.set("get_mic_level", get_mic_level)?;
+
+        let is_voice_active =
+            lua_ctx.create_function(|_, ()| Ok(MicrophonePlugin::is_voice_active()))?;
+        globals.set("is_voice_active", is_voice_active)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -15,54 +15,162 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-// use failure::Fail;
+// use thiserror::Error;
 
+pub mod accent;
 pub mod audio;
+#[cfg(feature = "ci")]
+pub mod ci;
 pub mod introspection;
 pub mod keyboard;
+pub mod keysounds;
+mod loader;
 pub mod macros;
+pub mod microphone;
 pub mod plugin;
+pub mod pomodoro;
+pub mod power;
 pub mod profiles;
 pub mod sensors;
+#[cfg(feature = "dbus")]
+pub mod session;
 pub mod system;
+pub mod volume;
+#[cfg(feature = "weather")]
+pub mod weather;
 
+pub use accent::AccentPlugin;
 pub use audio::AudioPlugin;
+#[cfg(feature = "ci")]
+pub use ci::CiPlugin;
 pub use introspection::IntrospectionPlugin;
 pub use keyboard::KeyboardPlugin;
+pub use keysounds::KeySoundsPlugin;
 pub use macros::MacrosPlugin;
+pub use microphone::MicrophonePlugin;
 pub use plugin::Plugin;
+pub use pomodoro::PomodoroPlugin;
+pub use power::PowerPlugin;
 pub use profiles::ProfilesPlugin;
 pub use sensors::SensorsPlugin;
+#[cfg(feature = "dbus")]
+pub use session::SessionPlugin;
 pub use system::SystemPlugin;
+pub use volume::VolumePlugin;
+#[cfg(feature = "weather")]
+pub use weather::WeatherPlugin;
 
+use eruption_sdk::ApiFunction;
 use log::*;
 
 use super::plugin_manager;
+use crate::constants;
 
-pub type Result<T> = std::result::Result<T, failure::Error>;
+pub type Result<T> = std::result::Result<T, anyhow::Error>;
 
-// #[derive(Debug, Fail)]
+// #[derive(Debug, Error)]
 // pub enum PluginError {
-//     // #[fail(display = "Could not register Lua extensions")]
+//     // #[error("Could not register Lua extensions")]
 //     // LuaExtensionError {},
 
-//     #[fail(display = "Unknown error: {}", description)]
+//     #[error("Unknown error: {description}")]
 //     UnknownError { description: String },
 // }
 
-/// Register all available plugins
+/// Create an instance of each available plugin
+fn create_plugins() -> Vec<Box<dyn Plugin + Sync + Send>> {
+    let mut result: Vec<Box<dyn Plugin + Sync + Send>> = vec![
+        Box::new(KeyboardPlugin::new()),
+        Box::new(MacrosPlugin::new()),
+        Box::new(IntrospectionPlugin::new()),
+        Box::new(ProfilesPlugin::new()),
+        Box::new(SystemPlugin::new()),
+        Box::new(SensorsPlugin::new()),
+        Box::new(AudioPlugin::new()),
+        Box::new(KeySoundsPlugin::new()),
+        Box::new(VolumePlugin::new()),
+        Box::new(MicrophonePlugin::new()),
+        Box::new(PowerPlugin::new()),
+        Box::new(PomodoroPlugin::new()),
+        Box::new(AccentPlugin::new()),
+    ];
+
+    #[cfg(feature = "dbus")]
+    result.push(Box::new(SessionPlugin::new()));
+
+    #[cfg(feature = "weather")]
+    result.push(Box::new(WeatherPlugin::new()));
+
+    #[cfg(feature = "ci")]
+    result.push(Box::new(CiPlugin::new()));
+
+    result
+}
+
+/// Get the names of all available plugins, and the declarations of the
+/// Lua functions that they supply. The plugins are not initialized
+pub fn get_plugin_apis() -> Vec<(String, &'static [ApiFunction])> {
+    create_plugins()
+        .iter()
+        .map(|plugin| (plugin.get_name(), plugin.get_api()))
+        .collect()
+}
+
+/// Register all available plugins, the plugins listed in `plugins.disabled` of the
+/// configuration file are not initialized
 pub fn register_plugins() -> Result<()> {
     trace!("Registering all available plugins...");
 
+    let disabled_plugins = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get::<Vec<String>>("plugins.disabled").ok())
+        .unwrap_or_default();
+
+    let is_enabled = |name: &str| {
+        !disabled_plugins.iter().any(|n| n == name) || constants::REQUIRED_PLUGINS.contains(&name)
+    };
+
+    // the enabled plugins are initialized before they are registered, without
+    // holding the lock of the plugin manager
+    let mut plugins = Vec::new();
+
+    for mut plugin in create_plugins() {
+        let enabled = is_enabled(&plugin.get_name());
+
+        if enabled {
+            plugin.initialize()?;
+        }
+
+        plugins.push((plugin, enabled));
+    }
+
+    // out-of-tree plugins may not replace the built-in plugins
+    for mut plugin in loader::load_plugins() {
+        let name = plugin.get_name();
+
+        if plugins.iter().any(|(p, _)| p.get_name() == name) {
+            error!("A plugin with the name {} is already registered", name);
+            continue;
+        }
+
+        let enabled = is_enabled(&name);
+
+        if enabled {
+            if let Err(e) = plugin.initialize() {
+                error!("Could not register the plugin {}: {}", name, e);
+                continue;
+            }
+        }
+
+        plugins.push((plugin, enabled));
+    }
+
     let mut plugin_manager = plugin_manager::PLUGIN_MANAGER.write();
 
-    plugin_manager.register_plugin(Box::new(KeyboardPlugin::new()))?;
-    plugin_manager.register_plugin(Box::new(MacrosPlugin::new()))?;
-    plugin_manager.register_plugin(Box::new(IntrospectionPlugin::new()))?;
-    plugin_manager.register_plugin(Box::new(ProfilesPlugin::new()))?;
-    plugin_manager.register_plugin(Box::new(SystemPlugin::new()))?;
-    plugin_manager.register_plugin(Box::new(SensorsPlugin::new()))?;
-    plugin_manager.register_plugin(Box::new(AudioPlugin::new()))?;
+    for (plugin, enabled) in plugins {
+        plugin_manager.register_plugin(plugin, enabled);
+    }
 
     trace!("Done registering all available plugins");
 
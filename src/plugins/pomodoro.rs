@@ -0,0 +1,238 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::Context;
+use std::any::Any;
+// use thiserror::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::plugins;
+use crate::plugins::Plugin;
+use crate::scripting::script;
+
+// pub type Result<T> = std::result::Result<T, PomodoroPluginError>;
+
+// #[derive(Debug, Error)]
+// pub enum PomodoroPluginError {
+//     #[error("Unknown error: {description}")]
+//     UnknownError { description: String },
+// }
+
+lazy_static! {
+    /// State of the running timer, the state is kept by the daemon,
+    /// so that it survives the reloading of scripts
+    static ref TIMER: Arc<Mutex<Option<TimerState>>> = Arc::new(Mutex::new(None));
+}
+
+/// The phases of a pomodoro timer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Phase {
+    Work,
+    Break,
+}
+
+impl Phase {
+    /// Name of the phase, as seen by Lua scripts
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Work => "work",
+            Phase::Break => "break",
+        }
+    }
+}
+
+struct TimerState {
+    work: Duration,
+    pause: Duration,
+
+    phase: Phase,
+    phase_start: Instant,
+}
+
+impl TimerState {
+    fn phase_duration(&self) -> Duration {
+        match self.phase {
+            Phase::Work => self.work,
+            Phase::Break => self.pause,
+        }
+    }
+
+    fn remaining_secs(&self) -> u32 {
+        self.phase_duration()
+            .checked_sub(self.phase_start.elapsed())
+            .unwrap_or_default()
+            .as_secs() as u32
+    }
+}
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_timer_phase() -> (phase, remaining)",
+    Sys,
+    "since 0.1.2",
+    "Returns the current phase of the pomodoro timer (\"work\", \"break\" or \"stopped\") and the remaining seconds of that phase";
+};
+
+/// A plugin that implements a pomodoro timer, that alternates between
+/// work and break phases. Scripts are notified of the progress of the
+/// timer via the `on_timer_phase(phase, remaining)` event handler
+pub struct PomodoroPlugin {}
+
+impl PomodoroPlugin {
+    pub fn new() -> Self {
+        PomodoroPlugin {}
+    }
+
+    /// Start a new timer, with a work phase of `work_mins` minutes followed by
+    /// a break of `break_mins` minutes. A running timer will be restarted
+    pub fn start(work_mins: u32, break_mins: u32) {
+        info!(
+            "Starting pomodoro timer: {} minutes of work, {} minutes break",
+            work_mins, break_mins
+        );
+
+        *TIMER.lock() = Some(TimerState {
+            work: Duration::from_secs(u64::from(work_mins) * 60),
+            pause: Duration::from_secs(u64::from(break_mins) * 60),
+
+            phase: Phase::Work,
+            phase_start: Instant::now(),
+        });
+
+        Self::notify_scripts();
+    }
+
+    /// Stop the running timer
+    pub fn stop() {
+        info!("Stopping pomodoro timer");
+
+        *TIMER.lock() = None;
+
+        Self::notify_scripts();
+    }
+
+    /// Get the current phase and the remaining seconds of the current phase,
+    /// or None if no timer is running
+    pub fn get_status() -> Option<(Phase, u32)> {
+        TIMER
+            .lock()
+            .as_ref()
+            .map(|timer| (timer.phase, timer.remaining_secs()))
+    }
+
+    /// Get the current phase, the remaining seconds and the total duration of the
+    /// current phase in seconds, or None if no timer is running
+    pub fn get_progress() -> Option<(Phase, u32, u32)> {
+        TIMER.lock().as_ref().map(|timer| {
+            (
+                timer.phase,
+                timer.remaining_secs(),
+                timer.phase_duration().as_secs() as u32,
+            )
+        })
+    }
+
+    /// Notify all running Lua scripts of the state of the timer
+    fn notify_scripts() {
+        let (phase, remaining) = match Self::get_status() {
+            Some((phase, remaining)) => (phase.name(), remaining),
+            None => ("stopped", 0),
+        };
+
+        for lua_tx in crate::LUA_TXS.lock().iter() {
+            lua_tx
+                .send(script::Message::TimerPhase(phase, remaining))
+                .unwrap_or_else(|e| error!("Send error: {}", e));
+        }
+    }
+
+    /// Switch to the next phase, if the current one has elapsed
+    fn update() {
+        let mut timer = TIMER.lock();
+
+        if let Some(timer) = timer.as_mut() {
+            if timer.phase_start.elapsed() >= timer.phase_duration() {
+                timer.phase = match timer.phase {
+                    Phase::Work => Phase::Break,
+                    Phase::Break => Phase::Work,
+                };
+
+                timer.phase_start = Instant::now();
+
+                info!("Pomodoro timer: Entering phase '{}'", timer.phase.name());
+            }
+        }
+    }
+}
+
+impl Plugin for PomodoroPlugin {
+    fn get_name(&self) -> String {
+        "Pomodoro".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "A pomodoro timer, alternating between work and break phases".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_timer_phase = lua_ctx.create_function(|_, ()| {
+            Ok(match PomodoroPlugin::get_status() {
+                Some((phase, remaining)) => (phase.name(), remaining),
+                None => ("stopped", 0),
+            })
+        })?;
+        globals.set("get_timer_phase", get_timer_phase)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {
+        if TIMER.lock().is_some() {
+            Self::update();
+            Self::notify_scripts();
+        }
+    }
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        // update the timer once per second
+        Some(constants::TARGET_FPS)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,275 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use rlua::Context;
+use std::any::Any;
+// use thiserror::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, Ordering};
+
+use crate::constants;
+use crate::plugins;
+use crate::plugins::Plugin;
+
+// pub type Result<T> = std::result::Result<T, PowerPluginError>;
+
+// #[derive(Debug, Error)]
+// pub enum PowerPluginError {
+//     #[error("Unknown error: {description}")]
+//     UnknownError { description: String },
+// }
+
+lazy_static! {
+    /// True if the system is running on battery power
+    static ref ON_BATTERY: AtomicBool = AtomicBool::new(false);
+
+    /// Charge of the battery in percent, or -1 if no battery is present
+    static ref BATTERY_PERCENT: AtomicIsize = AtomicIsize::new(-1);
+
+    /// Brightness scale in percent, applied while running on battery power
+    static ref BATTERY_BRIGHTNESS: AtomicIsize = AtomicIsize::new(100);
+
+    /// Max. frames per second, while running on battery power
+    static ref BATTERY_FPS: AtomicU64 = AtomicU64::new(constants::TARGET_FPS);
+}
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "on_battery() -> b",
+    Sys,
+    "since 0.1.2",
+    "Returns true if the system is currently running on battery power";
+
+    "get_battery_percent() -> i",
+    Sys,
+    "since 0.1.2",
+    "Returns the charge of the battery in percent, or -1 if no battery is present";
+};
+
+/// A plugin that monitors the power supply of the system, and
+/// reduces the power consumption of the device while running
+/// on battery power
+pub struct PowerPlugin {}
+
+impl PowerPlugin {
+    pub fn new() -> Self {
+        PowerPlugin {}
+    }
+
+    /// Read the state of the power supplies of the system from sysfs
+    pub fn refresh() {
+        let mut battery_present = false;
+        let mut mains_online = false;
+        let mut battery_percent = -1;
+
+        match fs::read_dir(constants::POWER_SUPPLY_DIR) {
+            Ok(entries) => {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    let path = entry.path();
+
+                    match read_attribute(&path, "type").as_ref().map(String::as_str) {
+                        Some("Battery") => {
+                            battery_present = true;
+
+                            if let Some(capacity) = read_attribute(&path, "capacity")
+                                .and_then(|c| c.parse::<isize>().ok())
+                            {
+                                battery_percent = isize::max(battery_percent, capacity);
+                            }
+                        }
+
+                        Some("Mains") | Some("USB") => {
+                            if read_attribute(&path, "online").as_ref().map(String::as_str)
+                                == Some("1")
+                            {
+                                mains_online = true;
+                            }
+                        }
+
+                        _ => (),
+                    }
+                }
+            }
+
+            Err(e) => trace!("Could not read power supply information: {}", e),
+        }
+
+        let on_battery = battery_present && !mains_online;
+
+        if ON_BATTERY.swap(on_battery, Ordering::SeqCst) != on_battery {
+            if on_battery {
+                info!("Now running on battery power");
+            } else {
+                info!("Now running on external power");
+            }
+        }
+
+        BATTERY_PERCENT.store(battery_percent, Ordering::SeqCst);
+    }
+
+    /// Returns true if the system is running on battery power
+    pub fn on_battery() -> bool {
+        ON_BATTERY.load(Ordering::SeqCst)
+    }
+
+    /// Get the charge of the battery in percent, or -1 if no battery is present
+    pub fn get_battery_percent() -> isize {
+        BATTERY_PERCENT.load(Ordering::SeqCst)
+    }
+}
+
+/// Read the sysfs attribute `name` of the power supply at `path`
+fn read_attribute(path: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(path.join(name))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Clamp the brightness scale `percent` of the configuration file to 0..=100
+fn clamp_brightness(percent: i64) -> isize {
+    let result = percent.max(0).min(100);
+
+    if result != percent {
+        warn!(
+            "Power: The brightness on battery power has been clamped to {}%",
+            result
+        );
+    }
+
+    result as isize
+}
+
+/// Clamp the frame rate `fps` of the configuration file to 1..=TARGET_FPS
+fn clamp_fps(fps: i64) -> u64 {
+    let result = fps.max(1).min(constants::TARGET_FPS as i64);
+
+    if result != fps {
+        warn!(
+            "Power: The frame rate on battery power has been clamped to {} fps",
+            result
+        );
+    }
+
+    result as u64
+}
+
+/// Scale the global brightness value `brightness`, taking the current
+/// power supply state into account
+pub fn scale_brightness(brightness: isize) -> isize {
+    if PowerPlugin::on_battery() {
+        brightness * BATTERY_BRIGHTNESS.load(Ordering::SeqCst) / 100
+    } else {
+        brightness
+    }
+}
+
+/// Returns true if the main loop shall render a frame in iteration `ticks`.
+/// While running on battery power, frames are skipped to reach the
+/// configured max. frames per second
+pub fn is_render_frame(ticks: u64) -> bool {
+    if PowerPlugin::on_battery() {
+        let fps = u64::max(BATTERY_FPS.load(Ordering::SeqCst), 1);
+        let divisor = u64::max(constants::TARGET_FPS / fps, 1);
+
+        ticks % divisor == 0
+    } else {
+        true
+    }
+}
+
+impl Plugin for PowerPlugin {
+    fn get_name(&self) -> String {
+        "Power".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Power supply and battery awareness".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        if let Some(config) = crate::CONFIG.lock().as_ref() {
+            if let Ok(brightness) = config.get_int("power.battery_brightness") {
+                BATTERY_BRIGHTNESS.store(clamp_brightness(brightness), Ordering::SeqCst);
+            }
+
+            if let Ok(fps) = config.get_int("power.battery_fps") {
+                BATTERY_FPS.store(clamp_fps(fps), Ordering::SeqCst);
+            }
+        }
+
+        Self::refresh();
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let on_battery = lua_ctx.create_function(|_, ()| Ok(PowerPlugin::on_battery()))?;
+        globals.set("on_battery", on_battery)?;
+
+        let get_battery_percent =
+            lua_ctx.create_function(|_, ()| Ok(PowerPlugin::get_battery_percent()))?;
+        globals.set("get_battery_percent", get_battery_percent)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {
+        Self::refresh();
+    }
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        Some(constants::POWER_UPDATE_TICKS)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_brightness() {
+        assert_eq!(clamp_brightness(30), 30);
+        assert_eq!(clamp_brightness(-10), 0);
+        assert_eq!(clamp_brightness(250), 100);
+    }
+
+    #[test]
+    fn test_clamp_fps() {
+        assert_eq!(clamp_fps(30), 30);
+        assert_eq!(clamp_fps(0), 1);
+        assert_eq!(clamp_fps(-5), 1);
+        assert_eq!(clamp_fps(1000), constants::TARGET_FPS);
+    }
+}
@@ -15,7 +15,8 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-//use failure::Fail;
+//use thiserror::Error;
+use eruption_sdk::ApiFunction;
 use rlua::Context;
 use std::any::Any;
 
@@ -24,9 +25,9 @@ use crate::plugins::Plugin;
 
 //pub type Result<T> = std::result::Result<T, ProfilesPluginError>;
 
-//#[derive(Debug, Fail)]
+//#[derive(Debug, Error)]
 //pub enum ProfilesPluginError {
-////#[fail(display = "Unknown error: {}", description)]
+////#[error("Unknown error: {description}")]
 ////UnknownError { description: String },
 //}
 
@@ -58,8 +59,16 @@ impl Plugin for ProfilesPlugin {
         Ok(())
     }
 
+    fn get_api(&self) -> &'static [ApiFunction] {
+        &[]
+    }
+
     fn main_loop_hook(&self, _ticks: u64) {}
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
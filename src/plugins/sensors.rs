@@ -15,11 +15,12 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use eruption_sdk::{api_functions, ApiFunction};
 use lazy_static::lazy_static;
 use log::*;
 use rlua::Context;
 use std::any::Any;
-// use failure::Fail;
+// use thiserror::Error;
 use parking_lot::Mutex;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -31,9 +32,9 @@ use crate::plugins::Plugin;
 
 // pub type Result<T> = std::result::Result<T, SensorsPluginError>;
 
-// #[derive(Debug, Fail)]
+// #[derive(Debug, Error)]
 // pub enum SensorsPluginError {
-//     #[fail(display = "Unknown error: {}", description)]
+//     #[error("Unknown error: {description}")]
 //     UnknownError { description: String },
 // }
 
@@ -45,6 +46,44 @@ lazy_static! {
     static ref SYSTEM: Arc<Mutex<sysinfo::System>> = Arc::new(Mutex::new(sysinfo::System::new()));
 }
 
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_package_temp() -> f",
+    Hw,
+    "since before 0.0.9",
+    "Returns the temperature of the CPU package";
+
+    "get_package_max_temp() -> f",
+    Hw,
+    "since before 0.0.9",
+    "Returns the max. temperature of the CPU package. (Approx. 80-100°C)";
+
+    "get_gpu_temp() -> f",
+    Hw,
+    "since 0.1.2",
+    "Returns the temperature of the GPU, or 0.0 if no GPU sensor is available";
+
+    "get_mem_total_kb() -> i",
+    Hw,
+    "since before 0.0.9",
+    "Returns the total installed memory size";
+
+    "get_mem_used_kb() -> i",
+    Hw,
+    "since before 0.0.9",
+    "Returns the amount of used memory";
+
+    "get_swap_total_kb() -> i",
+    Hw,
+    "since before 0.0.9",
+    "Returns the total size of the swap space";
+
+    "get_swap_used_kb() -> i",
+    Hw,
+    "since before 0.0.9",
+    "Returns the amount of used swap space";
+};
+
 /// A plugin that gives Lua scripts access to the systems sensor data
 pub struct SensorsPlugin {}
 
@@ -96,6 +135,27 @@ impl SensorsPlugin {
         }
     }
 
+    /// Get the temperature of the GPU, this is the maximum of all sensors that
+    /// belong to a GPU, or 0.0 if there are none
+    pub fn get_gpu_temp() -> f32 {
+        DO_REFRESH.store(true, Ordering::SeqCst);
+
+        let system = SYSTEM.lock();
+
+        system
+            .get_components()
+            .iter()
+            .filter(|c| {
+                let label = c.get_label().to_lowercase();
+
+                ["gpu", "nouveau", "radeon"]
+                    .iter()
+                    .any(|name| label.contains(name))
+            })
+            .map(|c| c.get_temperature())
+            .fold(0.0, f32::max)
+    }
+
     /// Get the total installed memory size
     pub fn get_mem_total_kb() -> u64 {
         DO_REFRESH.store(true, Ordering::SeqCst);
@@ -153,6 +213,10 @@ impl Plugin for SensorsPlugin {
             lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_package_max_temp()))?;
         globals.set("get_package_max_temp", get_package_max_temp)?;
 
+        let get_gpu_temp =
+            lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_gpu_temp()))?;
+        globals.set("get_gpu_temp", get_gpu_temp)?;
+
         let get_mem_total_kb =
             lua_ctx.create_function(move |_, ()| Ok(SensorsPlugin::get_mem_total_kb()))?;
         globals.set("get_mem_total_kb", get_mem_total_kb)?;
@@ -172,14 +236,22 @@ impl Plugin for SensorsPlugin {
         Ok(())
     }
 
-    fn main_loop_hook(&self, ticks: u64) {
-        // refresh sensor state (default: every other second), but only
-        // if the sensors have been used at least once
-        if ticks % crate::constants::SENSOR_UPDATE_TICKS == 0 && DO_REFRESH.load(Ordering::SeqCst) {
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {
+        // refresh sensor state, but only if the sensors have been used at least once
+        if DO_REFRESH.load(Ordering::SeqCst) {
             Self::refresh();
         }
     }
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        // default: every other second
+        Some(crate::constants::SENSOR_UPDATE_TICKS)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
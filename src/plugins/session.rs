@@ -0,0 +1,343 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use dbus::{arg::Variant, ffidisp::BusType, ffidisp::Connection, Message as DbusMessage, Path};
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::Context;
+use std::any::Any;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::paths::{self, SeatUser};
+use crate::plugins::{self, macros, Plugin};
+use crate::scripting::script;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, SessionPluginError>;
+
+#[derive(Debug, Error)]
+pub enum SessionPluginError {
+    #[error("Could not query logind: {description}")]
+    LogindError { description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+lazy_static! {
+    /// True if the session of the user is locked
+    static ref SESSION_LOCKED: AtomicBool = AtomicBool::new(false);
+
+    /// Profile to switch to while the session is locked, from the `[session]` section
+    static ref LOCKED_PROFILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+    /// Profile switch that has been requested by this plugin, but not yet performed
+    /// by the main loop. On unlock, None restores the previous profile
+    static ref PENDING_PROFILE_SWITCH: Mutex<Option<ProfileSwitch>> = Mutex::new(None);
+
+    /// Disable the injection of keys, while the session is locked
+    static ref BLOCK_MACROS: AtomicBool = AtomicBool::new(true);
+
+    /// The seat that the devices are attached to, from the `[session]` section
+    static ref SEAT: Mutex<String> = Mutex::new(constants::DEFAULT_SEAT.to_string());
+
+    /// Set when the user of the active session on our seat changed, but the
+    /// main loop did not yet reload the active profile
+    static ref SEAT_USER_CHANGED: AtomicBool = AtomicBool::new(false);
+}
+
+/// State of the sessions, as reported by logind
+struct SessionState {
+    /// True if an active session is locked
+    locked: bool,
+
+    /// Uid and name of the user of the active session on our seat
+    seat_user: Option<(u32, String)>,
+}
+
+/// A profile switch that has to be performed by the main loop
+#[derive(Debug, Clone)]
+pub enum ProfileSwitch {
+    /// Switch to the "locked" profile
+    Lock(PathBuf),
+
+    /// Switch back to the profile that was active before locking
+    Unlock,
+}
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "is_session_locked() -> b",
+    Sys,
+    "since 0.1.2",
+    "Returns true if the session of the user is currently locked";
+};
+
+/// A plugin that watches the session lock state via logind. Scripts are notified
+/// via `on_session_lock()` and `on_session_unlock()`. While the session is locked,
+/// the injection of keys is disabled, and an optional "locked" profile is active.
+/// The plugin also tracks the user of the active session on our seat, whose
+/// profiles are part of the profile search path
+pub struct SessionPlugin {}
+
+impl SessionPlugin {
+    pub fn new() -> Self {
+        SessionPlugin {}
+    }
+
+    /// Returns true if the session of the user is locked
+    pub fn is_locked() -> bool {
+        SESSION_LOCKED.load(Ordering::SeqCst)
+    }
+
+    /// Get the profile switch that has been requested by the plugin, if any
+    pub fn take_profile_switch() -> Option<ProfileSwitch> {
+        PENDING_PROFILE_SWITCH.lock().take()
+    }
+
+    /// Returns true if the user of the active session on our seat changed since the
+    /// last call. The profile search path includes the profiles of that user
+    pub fn take_seat_user_changed() -> bool {
+        SEAT_USER_CHANGED.swap(false, Ordering::SeqCst)
+    }
+
+    /// Query logind whether an active session is locked, and which user is active on our seat
+    fn query_sessions(connection: &Connection) -> Result<SessionState> {
+        let map_err = |e: dbus::Error| SessionPluginError::LogindError {
+            description: format!("{}", e),
+        };
+
+        let msg = DbusMessage::new_method_call(
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            "ListSessions",
+        )
+        .map_err(|e| SessionPluginError::LogindError { description: e })?;
+
+        let reply = connection
+            .send_with_reply_and_block(msg, constants::DBUS_TIMEOUT_MILLIS as i32)
+            .map_err(map_err)?;
+
+        let sessions: Vec<(String, u32, String, String, Path)> =
+            reply.read1().map_err(|e| SessionPluginError::LogindError {
+                description: format!("{}", e),
+            })?;
+
+        let mut result = SessionState {
+            locked: false,
+            seat_user: None,
+        };
+
+        let our_seat = SEAT.lock().clone();
+
+        for (_id, uid, user, seat, path) in sessions {
+            let get_property = |name: &str| -> Result<bool> {
+                let msg = DbusMessage::new_method_call(
+                    "org.freedesktop.login1",
+                    path.clone(),
+                    "org.freedesktop.DBus.Properties",
+                    "Get",
+                )
+                .map_err(|e| SessionPluginError::LogindError { description: e })?
+                .append2("org.freedesktop.login1.Session", name);
+
+                let reply = connection
+                    .send_with_reply_and_block(msg, constants::DBUS_TIMEOUT_MILLIS as i32)
+                    .map_err(map_err)?;
+
+                let value: Variant<bool> =
+                    reply.read1().map_err(|e| SessionPluginError::LogindError {
+                        description: format!("{}", e),
+                    })?;
+
+                Ok(value.0)
+            };
+
+            if !get_property("Active")? {
+                continue;
+            }
+
+            if get_property("LockedHint")? {
+                result.locked = true;
+            }
+
+            if seat == our_seat {
+                result.seat_user = Some((uid, user));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Record the user of the active session on our seat
+    fn update_seat_user(seat_user: Option<(u32, String)>) {
+        let current = paths::get_seat_user().map(|user| user.uid);
+
+        if current == seat_user.as_ref().map(|(uid, _)| *uid) {
+            return;
+        }
+
+        let seat_user = seat_user.and_then(|(uid, name)| match util::get_home_dir(uid) {
+            Some(home) => Some(SeatUser { uid, name, home }),
+
+            None => {
+                warn!("Could not determine the home directory of user '{}'", name);
+                None
+            }
+        });
+
+        let uid = seat_user.as_ref().map(|user| user.uid);
+
+        if paths::set_seat_user(seat_user) {
+            // the user of the seat may use the REST API
+            #[cfg(feature = "rest")]
+            crate::rest::grant_token_access(uid);
+
+            // the user of the seat may write mentions
+            crate::mentions::grant_pipe_access(uid);
+
+            SEAT_USER_CHANGED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Record the new lock state, and notify the scripts if it changed
+    fn update(locked: bool) {
+        if SESSION_LOCKED.swap(locked, Ordering::SeqCst) == locked {
+            return;
+        }
+
+        if locked {
+            info!("The session has been locked");
+        } else {
+            info!("The session has been unlocked");
+        }
+
+        if BLOCK_MACROS.load(Ordering::SeqCst) {
+            macros::INJECTION_BLOCKED.store(locked, Ordering::SeqCst);
+        }
+
+        if let Some(profile) = LOCKED_PROFILE.lock().as_ref() {
+            *PENDING_PROFILE_SWITCH.lock() = Some(if locked {
+                ProfileSwitch::Lock(profile.clone())
+            } else {
+                ProfileSwitch::Unlock
+            });
+        }
+
+        for lua_tx in crate::LUA_TXS.lock().iter() {
+            lua_tx
+                .send(script::Message::SessionLocked(locked))
+                .unwrap_or_else(|e| error!("Send error: {}", e));
+        }
+    }
+
+    fn spawn_session_thread() -> Result<()> {
+        thread::Builder::new()
+            .name("session".into())
+            .spawn(move || {
+                let connection = match Connection::get_private(BusType::System) {
+                    Ok(connection) => connection,
+
+                    Err(e) => {
+                        error!("Could not connect to the system bus: {}", e);
+                        return;
+                    }
+                };
+
+                loop {
+                    match Self::query_sessions(&connection) {
+                        Ok(state) => {
+                            Self::update(state.locked);
+                            Self::update_seat_user(state.seat_user);
+                        }
+
+                        Err(e) => trace!("Could not query the session lock state: {}", e),
+                    }
+
+                    thread::sleep(Duration::from_millis(constants::SESSION_POLL_MILLIS));
+                }
+            })
+            .map_err(|_e| SessionPluginError::ThreadSpawnError {})?;
+
+        Ok(())
+    }
+}
+
+impl Plugin for SessionPlugin {
+    fn get_name(&self) -> String {
+        "Session".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Session lock detection, secure mode and seat user tracking".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        if let Some(config) = crate::CONFIG.lock().as_ref() {
+            if let Ok(profile) = config.get_str("session.locked_profile") {
+                *LOCKED_PROFILE.lock() = Some(PathBuf::from(profile));
+            }
+
+            if let Ok(block_macros) = config.get::<bool>("session.block_macros") {
+                BLOCK_MACROS.store(block_macros, Ordering::SeqCst);
+            }
+
+            if let Ok(seat) = config.get_str("session.seat") {
+                *SEAT.lock() = seat;
+            }
+        }
+
+        Self::spawn_session_thread()?;
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let is_session_locked = lua_ctx.create_function(|_, ()| Ok(SessionPlugin::is_locked()))?;
+        globals.set("is_session_locked", is_session_locked)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -15,22 +15,51 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use eruption_sdk::{api_functions, ApiFunction};
 use log::*;
 use rlua::Context;
 use std::any::Any;
-// use failure::Fail;
+// use thiserror::Error;
 
 use crate::plugins;
 use crate::plugins::Plugin;
 
 // pub type Result<T> = std::result::Result<T, SystemPluginError>;
 
-// #[derive(Debug, Fail)]
+// #[derive(Debug, Error)]
 // pub enum SystemPluginError {
-//     #[fail(display = "Unknown error: {}", description)]
+//     #[error("Unknown error: {description}")]
 //     UnknownError { description: String },
 // }
 
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_current_load_avg_1() -> f",
+    Sys,
+    "since before 0.0.9",
+    "Returns the system load average of the last 1 minute";
+
+    "get_current_load_avg_5() -> f",
+    Sys,
+    "since before 0.0.9",
+    "Returns the system load average of the last 5 minutes";
+
+    "get_current_load_avg_10() -> f",
+    Sys,
+    "since before 0.0.9",
+    "Returns the system load average of the last 10 minutes";
+
+    "get_runnable_tasks() -> i",
+    Sys,
+    "since before 0.0.9",
+    "Returns the number of runnable tasks on the system";
+
+    "get_total_tasks() -> i",
+    Sys,
+    "since before 0.0.9",
+    "Returns the total number of tasks on the system";
+};
+
 /// A plugin that gives Lua scripts access to the systems state like e.g.
 /// the number of runnable processes or the load average
 pub struct SystemPlugin {}
@@ -130,8 +159,16 @@ impl Plugin for SystemPlugin {
         Ok(())
     }
 
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
     fn main_loop_hook(&self, _ticks: u64) {}
 
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -0,0 +1,246 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use rlua::Context;
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::thread;
+use thiserror::Error;
+
+use crate::overlay;
+use crate::plugins;
+use crate::plugins::Plugin;
+use crate::scripting::script;
+
+pub type Result<T> = std::result::Result<T, VolumePluginError>;
+
+#[derive(Debug, Error)]
+pub enum VolumePluginError {
+    #[error("Pulse Audio error: {description}")]
+    PulseError { description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+lazy_static! {
+    /// Volume of the default sink in percent, or -1 if unknown
+    static ref VOLUME: AtomicIsize = AtomicIsize::new(-1);
+
+    /// Mute state of the default sink
+    static ref MUTED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Subscribes to the sink and server events of PulseAudio, and reports the state
+/// of the default sink. Runs the PulseAudio mainloop, does not return unless an
+/// error occurs
+fn run_volume_monitor() -> Result<()> {
+    use libpulse_binding as pulse;
+    use pulse::context::subscribe::{Facility, InterestMaskSet};
+    use pulse::context::{FlagSet, State};
+    use pulse::mainloop::standard::{IterateResult, Mainloop};
+    use pulse::volume::Volume;
+
+    let mut mainloop = Mainloop::new().ok_or(VolumePluginError::PulseError {
+        description: "Could not create the mainloop".into(),
+    })?;
+
+    let mut context = pulse::context::Context::new(&mainloop, "eruption").ok_or(
+        VolumePluginError::PulseError {
+            description: "Could not create the context".into(),
+        },
+    )?;
+
+    context
+        .connect(None, FlagSet::NOFLAGS, None)
+        .map_err(|e| VolumePluginError::PulseError {
+            description: format!("Could not connect to Pulse Audio: {}", e),
+        })?;
+
+    // wait until the context is ready
+    loop {
+        match mainloop.iterate(true) {
+            IterateResult::Success(_) => (),
+
+            _ => {
+                return Err(VolumePluginError::PulseError {
+                    description: "Mainloop error".into(),
+                })
+            }
+        }
+
+        match context.get_state() {
+            State::Ready => break,
+
+            State::Failed | State::Terminated => {
+                return Err(VolumePluginError::PulseError {
+                    description: "Could not connect to Pulse Audio".into(),
+                })
+            }
+
+            _ => (),
+        }
+    }
+
+    fn report_volume(result: pulse::callbacks::ListResult<&pulse::context::introspect::SinkInfo>) {
+        if let pulse::callbacks::ListResult::Item(sink) = result {
+            let percent = (f64::from(sink.volume.avg().0) * 100.0 / f64::from(Volume::NORMAL.0))
+                .round() as isize;
+
+            VolumePlugin::report_volume(percent, sink.mute);
+        }
+    }
+
+    // establish the initial state
+    context
+        .introspect()
+        .get_sink_info_by_name("@DEFAULT_SINK@", report_volume);
+
+    let introspector = context.introspect();
+
+    // changes of other sinks are ignored, server events cover a change of the
+    // default sink
+    context.set_subscribe_callback(Some(Box::new(move |facility, _operation, _index| {
+        if facility == Some(Facility::Sink) || facility == Some(Facility::Server) {
+            introspector.get_sink_info_by_name("@DEFAULT_SINK@", report_volume);
+        }
+    })));
+
+    context.subscribe(
+        InterestMaskSet::SINK | InterestMaskSet::SERVER,
+        |_success| {},
+    );
+
+    mainloop.run().map_err(|_e| VolumePluginError::PulseError {
+        description: "Mainloop error".into(),
+    })?;
+
+    Ok(())
+}
+
+/// Spawns the thread that monitors the volume
+fn spawn_volume_monitor_thread() -> Result<()> {
+    thread::Builder::new()
+        .name("volume-monitor".into())
+        .spawn(move || {
+            run_volume_monitor().unwrap_or_else(|e| warn!("Volume monitor terminated: {}", e))
+        })
+        .map_err(|_e| VolumePluginError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_volume() -> i",
+    Sys,
+    "since 0.1.2",
+    "Returns the volume of the default audio sink in percent, or -1 if unknown";
+
+    "is_muted() -> b",
+    Sys,
+    "since 0.1.2",
+    "Returns true if the default audio sink is muted";
+};
+
+/// A plugin that monitors the volume and the mute state of the default audio sink.
+/// Changes are picked up regardless of their origin, e.g. the media keys or
+/// the mixer of the desktop. PipeWire is supported via its PulseAudio server
+pub struct VolumePlugin {}
+
+impl VolumePlugin {
+    pub fn new() -> Self {
+        VolumePlugin {}
+    }
+
+    /// Get the volume in percent, or -1 if unknown
+    pub fn get_volume() -> isize {
+        VOLUME.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the default audio sink is muted
+    pub fn is_muted() -> bool {
+        MUTED.load(Ordering::SeqCst)
+    }
+
+    /// Record the state of the default sink, and notify the Lua VMs if it changed
+    fn report_volume(percent: isize, muted: bool) {
+        let old_percent = VOLUME.swap(percent, Ordering::SeqCst);
+        let old_muted = MUTED.swap(muted, Ordering::SeqCst);
+
+        overlay::update_volume(percent, muted);
+
+        if old_percent != percent || old_muted != muted {
+            debug!("Volume: {}%, muted: {}", percent, muted);
+
+            for lua_tx in crate::LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(script::Message::VolumeChanged(percent, muted))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+        }
+    }
+}
+
+impl Plugin for VolumePlugin {
+    fn get_name(&self) -> String {
+        "Volume".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Monitor the volume and the mute state of the default audio sink".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        spawn_volume_monitor_thread()?;
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_volume = lua_ctx.create_function(|_, ()| Ok(VolumePlugin::get_volume()))?;
+        globals.set("get_volume", get_volume)?;
+
+        let is_muted = lua_ctx.create_function(|_, ()| Ok(VolumePlugin::is_muted()))?;
+        globals.set("is_muted", is_muted)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
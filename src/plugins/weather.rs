@@ -0,0 +1,296 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiFunction};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::Context;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::plugins;
+use crate::plugins::Plugin;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, WeatherPluginError>;
+
+#[derive(Debug, Error)]
+pub enum WeatherPluginError {
+    #[error("Could not fetch weather data: {description}")]
+    FetchError { description: String },
+
+    #[error("Could not parse weather data")]
+    ParseError {},
+
+    #[error("Unknown weather data provider: {provider}")]
+    UnknownProvider { provider: String },
+}
+
+lazy_static! {
+    /// The most recently fetched weather data
+    static ref WEATHER: Arc<Mutex<Option<WeatherData>>> = Arc::new(Mutex::new(None));
+}
+
+/// Current weather conditions
+#[derive(Debug, Clone)]
+/// The functions of the Lua API that are provided by this plugin
+pub const API: &[ApiFunction] = api_functions! {
+    "get_weather() -> {weather}",
+    Sys,
+    "since 0.1.2",
+    "Returns a table with the fields `condition`, `temperature` (°C) and `storm`, or `nil` if no weather data is available yet";
+};
+
+pub struct WeatherData {
+    /// Short description of the weather condition, like e.g. "Light rain"
+    pub condition: String,
+
+    /// Temperature in degrees Celsius
+    pub temperature: f64,
+
+    /// Set if there is a thunderstorm
+    pub storm: bool,
+}
+
+/// Supported weather data providers
+#[derive(Debug, Clone)]
+enum Provider {
+    /// wttr.in, does not require an API key
+    Wttr,
+
+    /// OpenWeatherMap, requires an API key
+    OpenWeatherMap { api_key: String },
+}
+
+/// A plugin that periodically fetches weather data
+pub struct WeatherPlugin {}
+
+impl WeatherPlugin {
+    pub fn new() -> Self {
+        WeatherPlugin {}
+    }
+
+    /// Get the most recently fetched weather data
+    pub fn get_weather() -> Option<WeatherData> {
+        WEATHER.lock().clone()
+    }
+
+    /// Fetch the current weather conditions at `location` from `provider`
+    fn fetch(provider: &Provider, location: &str) -> Result<WeatherData> {
+        let url = match provider {
+            Provider::Wttr => format!("https://wttr.in/{}?format=j1", util::url_encode(location)),
+
+            Provider::OpenWeatherMap { api_key } => format!(
+                "https://api.openweathermap.org/data/2.5/weather?q={}&appid={}&units=metric",
+                util::url_encode(location),
+                util::url_encode(api_key)
+            ),
+        };
+
+        let response = ureq::get(&url)
+            .timeout_connect(constants::WEATHER_TIMEOUT_MILLIS)
+            .timeout_read(constants::WEATHER_TIMEOUT_MILLIS)
+            .call();
+
+        if !response.ok() {
+            return Err(WeatherPluginError::FetchError {
+                description: response.status_line().to_string(),
+            });
+        }
+
+        let json = response
+            .into_json()
+            .map_err(|e| WeatherPluginError::FetchError {
+                description: format!("{}", e),
+            })?;
+
+        match provider {
+            Provider::Wttr => {
+                let current = &json["current_condition"][0];
+
+                let condition = current["weatherDesc"][0]["value"]
+                    .as_str()
+                    .ok_or(WeatherPluginError::ParseError {})?
+                    .to_string();
+
+                let temperature = current["temp_C"]
+                    .as_str()
+                    .and_then(|t| t.parse::<f64>().ok())
+                    .ok_or(WeatherPluginError::ParseError {})?;
+
+                // see: https://www.worldweatheronline.com/developer/api/docs/weather-icons.aspx
+                let code = current["weatherCode"]
+                    .as_str()
+                    .and_then(|c| c.parse::<u32>().ok())
+                    .unwrap_or(0);
+                let storm = [200, 386, 389, 392, 395].contains(&code);
+
+                Ok(WeatherData {
+                    condition,
+                    temperature,
+                    storm,
+                })
+            }
+
+            Provider::OpenWeatherMap { .. } => {
+                let condition = json["weather"][0]["description"]
+                    .as_str()
+                    .ok_or(WeatherPluginError::ParseError {})?
+                    .to_string();
+
+                let temperature = json["main"]["temp"]
+                    .as_f64()
+                    .ok_or(WeatherPluginError::ParseError {})?;
+
+                // condition codes 2xx denote thunderstorms
+                let storm = json["weather"][0]["id"]
+                    .as_u64()
+                    .map(|id| (200..300).contains(&id))
+                    .unwrap_or(false);
+
+                Ok(WeatherData {
+                    condition,
+                    temperature,
+                    storm,
+                })
+            }
+        }
+    }
+
+    /// Spawn a thread that periodically fetches weather data
+    fn spawn_update_thread(
+        provider: Provider,
+        location: String,
+        interval: Duration,
+    ) -> plugins::Result<()> {
+        let builder = thread::Builder::new().name("weather".into());
+        builder.spawn(move || loop {
+            match Self::fetch(&provider, &location) {
+                Ok(weather) => {
+                    debug!("Weather data: {:?}", weather);
+                    *WEATHER.lock() = Some(weather);
+                }
+
+                Err(e) => warn!("{}", e),
+            }
+
+            thread::sleep(interval);
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Plugin for WeatherPlugin {
+    fn get_name(&self) -> String {
+        "Weather".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Periodically fetch weather data".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        if !config.get::<bool>("weather.enabled").unwrap_or(false) {
+            info!("Weather data DISABLED by configuration");
+            return Ok(());
+        }
+
+        let provider = config
+            .get_str("weather.provider")
+            .unwrap_or_else(|_| "wttr.in".into());
+
+        let provider = match provider.as_str() {
+            "wttr.in" => Provider::Wttr,
+
+            "openweathermap" => Provider::OpenWeatherMap {
+                api_key: config.get_str("weather.api_key").unwrap_or_default(),
+            },
+
+            // the other plugins must still be registered
+            _ => {
+                error!("{}", WeatherPluginError::UnknownProvider { provider });
+                return Ok(());
+            }
+        };
+
+        let location = config.get_str("weather.location").unwrap_or_default();
+
+        let interval = Duration::from_secs(
+            config
+                .get_int("weather.update_interval_secs")
+                .unwrap_or(constants::WEATHER_UPDATE_INTERVAL_SECS as i64)
+                .max(constants::WEATHER_MIN_UPDATE_INTERVAL_SECS as i64) as u64,
+        );
+
+        Self::spawn_update_thread(provider, location, interval)?;
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_weather =
+            lua_ctx.create_function(|lua_ctx, ()| match WeatherPlugin::get_weather() {
+                Some(weather) => {
+                    let mut result = HashMap::new();
+
+                    result.insert(
+                        "condition",
+                        rlua::Value::String(lua_ctx.create_string(&weather.condition)?),
+                    );
+                    result.insert("temperature", rlua::Value::Number(weather.temperature));
+                    result.insert("storm", rlua::Value::Boolean(weather.storm));
+
+                    Ok(Some(result))
+                }
+
+                None => Ok(None),
+            })?;
+        globals.set("get_weather", get_weather)?;
+
+        Ok(())
+    }
+
+    fn get_api(&self) -> &'static [ApiFunction] {
+        API
+    }
+
+    fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn get_hook_interval(&self) -> Option<u64> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,694 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Pointer keys, that emulate a mouse. While the modifier (Easy Shift by default)
+//! is held down, the bound keys move the pointer of a virtual mouse, press its
+//! buttons or turn its wheel. The pointer accelerates from the initial to the
+//! maximum speed, along the configured curve, while a key is held down.
+
+use evdev_rs::enums::{EventCode, EventType, EV_KEY, EV_REL, EV_SYN};
+use evdev_rs::{Device, InputEvent, TimeVal, UInputDevice};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::hotkeys;
+
+pub type Result<T> = std::result::Result<T, PointerKeysError>;
+
+#[derive(Debug, Error)]
+pub enum PointerKeysError {
+    #[error("Invalid pointer key action: {action}")]
+    InvalidAction { action: String },
+
+    #[error("Could not create the virtual mouse device")]
+    DeviceError {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// Actions that may be bound to a pointer key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    ButtonLeft,
+    ButtonRight,
+    ButtonMiddle,
+    ScrollUp,
+    ScrollDown,
+}
+
+impl FromStr for Action {
+    type Err = PointerKeysError;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action.trim() {
+            "up" => Ok(Action::Up),
+            "down" => Ok(Action::Down),
+            "left" => Ok(Action::Left),
+            "right" => Ok(Action::Right),
+            "button-left" => Ok(Action::ButtonLeft),
+            "button-right" => Ok(Action::ButtonRight),
+            "button-middle" => Ok(Action::ButtonMiddle),
+            "scroll-up" => Ok(Action::ScrollUp),
+            "scroll-down" => Ok(Action::ScrollDown),
+
+            _ => Err(PointerKeysError::InvalidAction {
+                action: action.to_string(),
+            }),
+        }
+    }
+}
+
+impl Action {
+    /// The button of the virtual mouse, if the action presses a button
+    fn button(self) -> Option<EV_KEY> {
+        match self {
+            Action::ButtonLeft => Some(EV_KEY::BTN_LEFT),
+            Action::ButtonRight => Some(EV_KEY::BTN_RIGHT),
+            Action::ButtonMiddle => Some(EV_KEY::BTN_MIDDLE),
+
+            _ => None,
+        }
+    }
+}
+
+/// The acceleration curve of the pointer, maps the fraction of the time to reach
+/// the maximum speed to the fraction of the maximum speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Constant,
+    Linear,
+    Quadratic,
+    Cubic,
+}
+
+impl FromStr for Curve {
+    type Err = ();
+
+    fn from_str(curve: &str) -> std::result::Result<Self, Self::Err> {
+        match curve.trim() {
+            "constant" => Ok(Curve::Constant),
+            "linear" => Ok(Curve::Linear),
+            "quadratic" => Ok(Curve::Quadratic),
+            "cubic" => Ok(Curve::Cubic),
+
+            _ => Err(()),
+        }
+    }
+}
+
+impl Curve {
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Curve::Constant => 0.0,
+            Curve::Linear => t,
+            Curve::Quadratic => t * t,
+            Curve::Cubic => t * t * t,
+        }
+    }
+}
+
+/// Configuration of the pointer keys
+struct Settings {
+    /// The key that has to be held down, to use the pointer keys
+    modifier: u32,
+
+    /// Maps key codes to actions
+    bindings: HashMap<u32, Action>,
+
+    /// Initial and maximum speed of the pointer, in pixels per second
+    speed: f64,
+    max_speed: f64,
+
+    /// Time until the maximum speed is reached
+    accel: Duration,
+    curve: Curve,
+}
+
+enum Message {
+    Button(EV_KEY, bool),
+    Scroll(i32),
+    HorizontalScroll(i32),
+}
+
+lazy_static! {
+    static ref SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+
+    /// Pointer keys that are held down, with the point in time they have been pressed
+    static ref HELD_KEYS: Mutex<HashMap<u32, (Action, Instant)>> = Mutex::new(HashMap::new());
+
+    static ref POINTER_TX: Mutex<Option<Sender<Message>>> = Mutex::new(None);
+}
+
+/// The default bindings, for the left hand while Easy Shift is held down
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("W", "up"),
+    ("S", "down"),
+    ("A", "left"),
+    ("D", "right"),
+    ("Q", "button-left"),
+    ("E", "button-right"),
+    ("X", "button-middle"),
+    ("R", "scroll-up"),
+    ("F", "scroll-down"),
+];
+
+fn load_settings() -> Result<Option<Settings>> {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    if !config.get_bool("pointer_keys.enabled").unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let modifier = config
+        .get_str("pointer_keys.modifier")
+        .unwrap_or_else(|_| "EASYSHIFT".into());
+
+    let modifier =
+        hotkeys::parse_key_name(&modifier).map_err(|_e| PointerKeysError::InvalidAction {
+            action: modifier.clone(),
+        })?;
+
+    let bindings = config
+        .get::<HashMap<String, String>>("pointer_keys.bindings")
+        .unwrap_or_else(|_| {
+            DEFAULT_BINDINGS
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        });
+
+    let mut result = HashMap::new();
+
+    for (name, action) in bindings.iter() {
+        match hotkeys::parse_key_name(name) {
+            Ok(code) => {
+                result.insert(code, action.parse::<Action>()?);
+            }
+
+            Err(e) => error!("Invalid pointer key: {}", e),
+        }
+    }
+
+    let speed = config
+        .get_float("pointer_keys.speed")
+        .unwrap_or(constants::POINTER_KEYS_SPEED)
+        .max(1.0);
+
+    let max_speed = config
+        .get_float("pointer_keys.max_speed")
+        .unwrap_or(constants::POINTER_KEYS_MAX_SPEED)
+        .max(speed);
+
+    let accel = Duration::from_millis(
+        config
+            .get::<u64>("pointer_keys.accel_millis")
+            .unwrap_or(constants::POINTER_KEYS_ACCEL_MILLIS)
+            .max(1),
+    );
+
+    let curve = config
+        .get_str("pointer_keys.curve")
+        .unwrap_or_else(|_| "quadratic".into());
+
+    let curve = curve.parse::<Curve>().unwrap_or_else(|_| {
+        warn!("Invalid acceleration curve: {}", curve);
+        Curve::Quadratic
+    });
+
+    Ok(Some(Settings {
+        modifier,
+        bindings: result,
+        speed,
+        max_speed,
+        accel,
+        curve,
+    }))
+}
+
+fn create_device() -> Result<UInputDevice> {
+    let dev = Device::new().ok_or(PointerKeysError::DeviceError {})?;
+
+    dev.set_name("Eruption Virtual Mouse");
+    dev.set_bustype(3);
+    dev.set_product_id(0x0124);
+    dev.set_vendor_id(0x0059);
+    dev.set_version(0x01);
+
+    let enable = |code: EventCode| {
+        dev.enable(&code)
+            .map_err(|_e| PointerKeysError::DeviceError {})
+    };
+
+    dev.enable(&EventType::EV_REL)
+        .map_err(|_e| PointerKeysError::DeviceError {})?;
+    dev.enable(&EventType::EV_KEY)
+        .map_err(|_e| PointerKeysError::DeviceError {})?;
+
+    enable(EventCode::EV_REL(EV_REL::REL_X))?;
+    enable(EventCode::EV_REL(EV_REL::REL_Y))?;
+    enable(EventCode::EV_REL(EV_REL::REL_WHEEL))?;
+    enable(EventCode::EV_REL(EV_REL::REL_HWHEEL))?;
+    enable(EventCode::EV_KEY(EV_KEY::BTN_LEFT))?;
+    enable(EventCode::EV_KEY(EV_KEY::BTN_RIGHT))?;
+    enable(EventCode::EV_KEY(EV_KEY::BTN_MIDDLE))?;
+    enable(EventCode::EV_SYN(EV_SYN::SYN_REPORT))?;
+
+    UInputDevice::create_from_device(&dev).map_err(|_e| PointerKeysError::DeviceError {})
+}
+
+/// The current time, as a timestamp of emitted events
+pub fn now() -> TimeVal {
+    let mut time: libc::timeval = libc::timeval {
+        tv_sec: 0,
+        tv_usec: 0,
+    };
+
+    unsafe {
+        libc::gettimeofday(&mut time, std::ptr::null_mut());
+    }
+
+    TimeVal::from_raw(&time)
+}
+
+/// Emit the events `events` on the virtual mouse, followed by a SYN_REPORT
+fn emit(device: &UInputDevice, events: &[(EventType, EventCode, i32)]) {
+    let time = now();
+
+    let result = events
+        .iter()
+        .cloned()
+        .chain(std::iter::once((
+            EventType::EV_SYN,
+            EventCode::EV_SYN(EV_SYN::SYN_REPORT),
+            0,
+        )))
+        .try_for_each(|(event_type, event_code, value)| {
+            device.write_event(&InputEvent {
+                time: time.clone(),
+                event_type,
+                event_code,
+                value,
+            })
+        });
+
+    result.unwrap_or_else(|e| error!("Could not write to the virtual mouse: {}", e));
+}
+
+/// Get the velocity of the pointer in pixels per second at `now`, from the held
+/// keys `held_keys`
+fn get_velocity(
+    settings: &Settings,
+    held_keys: &HashMap<u32, (Action, Instant)>,
+    now: Instant,
+) -> (f64, f64) {
+    let mut velocity = (0.0, 0.0);
+
+    for (action, since) in held_keys.values() {
+        let elapsed = now.saturating_duration_since(*since);
+        let t = (elapsed.as_secs_f64() / settings.accel.as_secs_f64()).min(1.0);
+        let speed =
+            settings.speed + (settings.max_speed - settings.speed) * settings.curve.apply(t);
+
+        match action {
+            Action::Up => velocity.1 -= speed,
+            Action::Down => velocity.1 += speed,
+            Action::Left => velocity.0 -= speed,
+            Action::Right => velocity.0 += speed,
+
+            _ => (),
+        }
+    }
+
+    velocity
+}
+
+/// Returns the direction of the scroll keys that are held down, and are due to
+/// repeat
+fn get_scroll_repeat() -> i32 {
+    let repeat = Duration::from_millis(constants::POINTER_KEYS_SCROLL_REPEAT_MILLIS);
+
+    HELD_KEYS
+        .lock()
+        .values()
+        .filter(|(_, since)| since.elapsed() >= repeat)
+        .map(|(action, _)| match action {
+            Action::ScrollUp => 1,
+            Action::ScrollDown => -1,
+
+            _ => 0,
+        })
+        .sum()
+}
+
+fn run_pointer_thread(device: UInputDevice, pointer_rx: Receiver<Message>) {
+    let interval = Duration::from_millis(constants::POINTER_KEYS_INTERVAL_MILLIS);
+    let scroll_repeat = Duration::from_millis(constants::POINTER_KEYS_SCROLL_REPEAT_MILLIS);
+
+    // fractions of pixels, that are carried over to the next step
+    let mut remainder = (0.0, 0.0);
+
+    let mut last_step = Instant::now();
+    let mut last_scroll = Instant::now();
+
+    loop {
+        match pointer_rx.recv_timeout(interval) {
+            Ok(Message::Button(button, down)) => emit(
+                &device,
+                &[(
+                    EventType::EV_KEY,
+                    EventCode::EV_KEY(button),
+                    if down { 1 } else { 0 },
+                )],
+            ),
+
+            Ok(Message::Scroll(direction)) => {
+                last_scroll = Instant::now();
+
+                emit(
+                    &device,
+                    &[(
+                        EventType::EV_REL,
+                        EventCode::EV_REL(EV_REL::REL_WHEEL),
+                        direction,
+                    )],
+                );
+            }
+
+            Ok(Message::HorizontalScroll(delta)) => emit(
+                &device,
+                &[(
+                    EventType::EV_REL,
+                    EventCode::EV_REL(EV_REL::REL_HWHEEL),
+                    delta,
+                )],
+            ),
+
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let dt = last_step.elapsed().as_secs_f64();
+        last_step = Instant::now();
+
+        let velocity = match SETTINGS.lock().as_ref() {
+            Some(settings) => get_velocity(settings, &HELD_KEYS.lock(), Instant::now()),
+            None => (0.0, 0.0),
+        };
+
+        if velocity == (0.0, 0.0) {
+            remainder = (0.0, 0.0);
+        } else {
+            let dx = velocity.0 * dt + remainder.0;
+            let dy = velocity.1 * dt + remainder.1;
+
+            remainder = (dx.fract(), dy.fract());
+
+            emit(
+                &device,
+                &[
+                    (
+                        EventType::EV_REL,
+                        EventCode::EV_REL(EV_REL::REL_X),
+                        dx.trunc() as i32,
+                    ),
+                    (
+                        EventType::EV_REL,
+                        EventCode::EV_REL(EV_REL::REL_Y),
+                        dy.trunc() as i32,
+                    ),
+                ],
+            );
+        }
+
+        if last_scroll.elapsed() >= scroll_repeat {
+            let direction = get_scroll_repeat();
+
+            if direction != 0 {
+                last_scroll = Instant::now();
+
+                emit(
+                    &device,
+                    &[(
+                        EventType::EV_REL,
+                        EventCode::EV_REL(EV_REL::REL_WHEEL),
+                        direction,
+                    )],
+                );
+            }
+        }
+    }
+}
+
+/// Load the configuration of the `[pointer_keys]` section, and create the virtual
+/// mouse if the pointer keys are enabled
+pub fn initialize() -> Result<()> {
+    let settings = match load_settings()? {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+
+    start_virtual_mouse()?;
+
+    info!(
+        "Pointer keys enabled, {} key(s) bound",
+        settings.bindings.len()
+    );
+
+    *SETTINGS.lock() = Some(settings);
+
+    Ok(())
+}
+
+/// Create the virtual mouse, if it does not exist yet. It is shared by the pointer
+/// keys and the dial
+pub fn start_virtual_mouse() -> Result<()> {
+    let mut pointer_tx = POINTER_TX.lock();
+
+    if pointer_tx.is_some() {
+        return Ok(());
+    }
+
+    let (tx, pointer_rx) = channel();
+    let (ready_tx, ready_rx) = channel();
+
+    // the device is owned by the thread, it is only published once it exists, so
+    // that keys are not consumed if it could not be created
+    thread::Builder::new()
+        .name("pointer-keys".into())
+        .spawn(move || match create_device() {
+            Ok(device) => {
+                ready_tx.send(Ok(())).ok();
+                run_pointer_thread(device, pointer_rx);
+            }
+
+            Err(e) => {
+                ready_tx.send(Err(e)).ok();
+            }
+        })
+        .map_err(|_e| PointerKeysError::ThreadSpawnError {})?;
+
+    ready_rx
+        .recv()
+        .unwrap_or(Err(PointerKeysError::DeviceError {}))?;
+
+    *pointer_tx = Some(tx);
+
+    Ok(())
+}
+
+fn send(message: Message) {
+    if let Some(pointer_tx) = POINTER_TX.lock().as_ref() {
+        pointer_tx
+            .send(message)
+            .unwrap_or_else(|e| error!("Could not send an event to the virtual mouse: {}", e));
+    }
+}
+
+/// Turn the horizontal wheel of the virtual mouse by `delta` steps, positive
+/// values scroll to the right
+pub fn scroll_horizontal(delta: i32) {
+    send(Message::HorizontalScroll(delta));
+}
+
+/// Process a key event of the hardware keyboard. Returns true if the event has
+/// been consumed: Presses of bound keys while the modifier is held down, and
+/// their releases and repeats, even if the modifier has been released meanwhile
+pub fn process_event(raw_event: &evdev_rs::InputEvent) -> bool {
+    let code = match raw_event.event_code {
+        EventCode::EV_KEY(ref key) => key.clone() as u32,
+        _ => return false,
+    };
+
+    let (action, modifier) = {
+        let settings = SETTINGS.lock();
+
+        let settings = match settings.as_ref() {
+            Some(settings) => settings,
+            None => return false,
+        };
+
+        match settings.bindings.get(&code) {
+            Some(action) => (*action, settings.modifier),
+            None => return false,
+        }
+    };
+
+    let mut held_keys = HELD_KEYS.lock();
+
+    match raw_event.value {
+        // key down
+        1 => {
+            if !hotkeys::is_key_pressed(modifier) {
+                return false;
+            }
+
+            held_keys.insert(code, (action, Instant::now()));
+
+            drop(held_keys);
+
+            if let Some(button) = action.button() {
+                send(Message::Button(button, true));
+            } else if action == Action::ScrollUp {
+                send(Message::Scroll(1));
+            } else if action == Action::ScrollDown {
+                send(Message::Scroll(-1));
+            }
+
+            true
+        }
+
+        // key up
+        0 => match held_keys.remove(&code) {
+            Some((action, _)) => {
+                drop(held_keys);
+
+                if let Some(button) = action.button() {
+                    send(Message::Button(button, false));
+                }
+
+                true
+            }
+
+            None => false,
+        },
+
+        // auto repeat
+        _ => held_keys.contains_key(&code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(curve: Curve) -> Settings {
+        Settings {
+            modifier: constants::EASY_SHIFT_KEY_CODE,
+            bindings: HashMap::new(),
+            speed: 100.0,
+            max_speed: 500.0,
+            accel: Duration::from_millis(1000),
+            curve,
+        }
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!("up".parse::<Action>().unwrap(), Action::Up);
+        assert_eq!(
+            " scroll-down ".parse::<Action>().unwrap(),
+            Action::ScrollDown
+        );
+        assert_eq!(
+            "button-middle".parse::<Action>().unwrap().button(),
+            Some(EV_KEY::BTN_MIDDLE)
+        );
+        assert_eq!(Action::Left.button(), None);
+        assert!("jump".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn test_parse_curve() {
+        assert_eq!("constant".parse::<Curve>(), Ok(Curve::Constant));
+        assert_eq!("linear".parse::<Curve>(), Ok(Curve::Linear));
+        assert_eq!("quadratic".parse::<Curve>(), Ok(Curve::Quadratic));
+        assert_eq!("cubic".parse::<Curve>(), Ok(Curve::Cubic));
+        assert_eq!("exponential".parse::<Curve>(), Err(()));
+
+        assert!((Curve::Quadratic.apply(0.5) - 0.25).abs() < 1e-6);
+        assert!(Curve::Constant.apply(1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_get_velocity() {
+        let start = Instant::now();
+        let mut held_keys = HashMap::new();
+
+        assert_eq!(
+            get_velocity(&settings(Curve::Linear), &held_keys, start),
+            (0.0, 0.0)
+        );
+
+        held_keys.insert(1, (Action::Right, start));
+        held_keys.insert(2, (Action::Up, start));
+
+        // the initial speed
+        assert_eq!(
+            get_velocity(&settings(Curve::Linear), &held_keys, start),
+            (100.0, -100.0)
+        );
+
+        // half way to the max. speed
+        let velocity = get_velocity(
+            &settings(Curve::Linear),
+            &held_keys,
+            start + Duration::from_millis(500),
+        );
+        assert!((velocity.0 - 300.0).abs() < 1e-6);
+
+        // the max. speed is not exceeded
+        assert_eq!(
+            get_velocity(
+                &settings(Curve::Quadratic),
+                &held_keys,
+                start + Duration::from_secs(5)
+            ),
+            (500.0, -500.0)
+        );
+
+        // opposite directions cancel out, buttons don't move the pointer
+        held_keys.insert(3, (Action::Left, start));
+        held_keys.insert(4, (Action::ButtonLeft, start));
+
+        let velocity = get_velocity(&settings(Curve::Constant), &held_keys, start);
+        assert!(velocity.0.abs() < 1e-6);
+    }
+}
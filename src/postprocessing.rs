@@ -0,0 +1,346 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Post-processing filters of profiles, that are applied to the composited frames
+//! of the scripts, before the overlays of the daemon are drawn. Filters are
+//! specified by the `post_processing` list of a profile, as the name of the filter
+//! and an optional parameter, e.g.:
+//!
+//! ```toml
+//! post_processing = ["gaussian-blur:1.5", "bloom:0.6", "scanlines"]
+//! ```
+//!
+//! The filters operate on the grid of keys, they are applied in order. Devices
+//! without a grid of keys, like headsets, are not processed.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use rand::Rng;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+use crate::profiles::Profile;
+use crate::rvdevice::{DeviceKind, RvDeviceState, RGBA};
+
+pub type Result<T> = std::result::Result<T, PostProcessingError>;
+
+#[derive(Debug, Error)]
+pub enum PostProcessingError {
+    #[error("Unknown filter: {name}")]
+    UnknownFilter { name: String },
+
+    #[error("Invalid parameter of filter {name}: {param}")]
+    InvalidParameter { name: String, param: String },
+}
+
+/// A post-processing filter
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    /// Average of the keys within `radius` columns and rows
+    BoxBlur { radius: usize },
+
+    /// Gaussian blur with the standard deviation `sigma`, in keys
+    GaussianBlur { sigma: f64 },
+
+    /// Bright keys glow onto their neighbours, `strength` in the range 0.0..1.0
+    Bloom { strength: f64 },
+
+    /// Darken every other row, the rows move slowly. `intensity` in 0.0..1.0
+    Scanlines { intensity: f64 },
+
+    /// Jitter the brightness of the whole frame, `amount` in 0.0..1.0
+    Flicker { amount: f64 },
+}
+
+impl FromStr for Filter {
+    type Err = PostProcessingError;
+
+    fn from_str(filter: &str) -> Result<Self> {
+        let mut parts = filter.trim().splitn(2, ':');
+
+        let name = parts.next().unwrap_or_default();
+        let param = parts.next();
+
+        let invalid = || PostProcessingError::InvalidParameter {
+            name: name.to_string(),
+            param: param.unwrap_or_default().to_string(),
+        };
+
+        let number = |default: f64, max: f64| -> Result<f64> {
+            match param {
+                Some(param) => param
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| *v >= 0.0 && *v <= max)
+                    .ok_or_else(invalid),
+
+                None => Ok(default),
+            }
+        };
+
+        match name {
+            "box-blur" => Ok(Filter::BoxBlur {
+                radius: number(1.0, 4.0)?.round() as usize,
+            }),
+
+            "gaussian-blur" => Ok(Filter::GaussianBlur {
+                sigma: number(1.0, 4.0)?,
+            }),
+
+            "bloom" => Ok(Filter::Bloom {
+                strength: number(0.5, 1.0)?,
+            }),
+
+            "scanlines" => Ok(Filter::Scanlines {
+                intensity: number(0.3, 1.0)?,
+            }),
+
+            "flicker" => Ok(Filter::Flicker {
+                amount: number(0.1, 1.0)?,
+            }),
+
+            _ => Err(PostProcessingError::UnknownFilter {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+/// Colors of the keys as floating point values, to avoid rounding between passes
+type Frame = Vec<[f64; 4]>;
+
+/// Bright parts of a frame contribute to the bloom above this luminance
+const BLOOM_THRESHOLD: f64 = 160.0;
+
+/// Number of frames, until the scanlines move by one row
+const SCANLINE_FRAMES_PER_ROW: u64 = 8;
+
+lazy_static! {
+    /// The filters of the active profile
+    static ref FILTERS: RwLock<Vec<Filter>> = RwLock::new(vec![]);
+}
+
+/// Number of processed frames, drives the animated filters
+static FRAME: AtomicU64 = AtomicU64::new(0);
+
+/// Select the filters of the profile `profile`
+pub fn apply_profile(profile: &Profile) {
+    let filters: Vec<Filter> = profile
+        .post_processing
+        .iter()
+        .flatten()
+        .filter_map(|filter| filter.parse::<Filter>().map_err(|e| error!("{}", e)).ok())
+        .collect();
+
+    if !filters.is_empty() {
+        info!("Using post-processing filters: {:?}", filters);
+    }
+
+    *FILTERS.write() = filters;
+}
+
+/// Convolve the frame with the separable kernel `kernel`, that is centered on
+/// its middle element. `grid` is the number of columns and keys per column of the
+/// frame. Keys beyond the edges are clamped to the nearest key
+fn convolve(frame: &[[f64; 4]], kernel: &[f64], grid: (usize, usize)) -> Frame {
+    let (columns, rows) = grid;
+    let radius = (kernel.len() / 2) as isize;
+
+    let pass = |frame: &[[f64; 4]], horizontal: bool| -> Frame {
+        (0..frame.len())
+            .map(|index| {
+                let column = (index / rows) as isize;
+                let row = (index % rows) as isize;
+
+                let mut sum = [0.0; 4];
+
+                for (i, weight) in kernel.iter().enumerate() {
+                    let offset = i as isize - radius;
+
+                    let (column, row) = if horizontal {
+                        ((column + offset).max(0).min(columns as isize - 1), row)
+                    } else {
+                        (column, (row + offset).max(0).min(rows as isize - 1))
+                    };
+
+                    let neighbour = frame
+                        .get(column as usize * rows + row as usize)
+                        .copied()
+                        .unwrap_or([0.0; 4]);
+
+                    for (sum, value) in sum.iter_mut().zip(neighbour.iter()) {
+                        *sum += weight * value;
+                    }
+                }
+
+                sum
+            })
+            .collect()
+    };
+
+    pass(&pass(frame, true), false)
+}
+
+fn box_kernel(radius: usize) -> Vec<f64> {
+    let len = radius * 2 + 1;
+
+    vec![1.0 / len as f64; len]
+}
+
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = (sigma * 3.0).ceil().max(1.0) as isize;
+
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let sum: f64 = kernel.iter().sum();
+
+    kernel.iter().map(|weight| weight / sum).collect()
+}
+
+fn luminance(color: &[f64; 4]) -> f64 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+fn apply_filter(frame: Frame, filter: Filter, grid: (usize, usize), frame_number: u64) -> Frame {
+    let (_columns, rows) = grid;
+
+    match filter {
+        Filter::BoxBlur { radius } if radius > 0 => convolve(&frame, &box_kernel(radius), grid),
+        Filter::BoxBlur { .. } => frame,
+
+        Filter::GaussianBlur { sigma } if sigma > 0.0 => {
+            convolve(&frame, &gaussian_kernel(sigma), grid)
+        }
+        Filter::GaussianBlur { .. } => frame,
+
+        Filter::Bloom { strength } => {
+            let bright: Frame = frame
+                .iter()
+                .map(|color| {
+                    if luminance(color) >= BLOOM_THRESHOLD {
+                        *color
+                    } else {
+                        [0.0; 4]
+                    }
+                })
+                .collect();
+
+            let glow = convolve(&bright, &gaussian_kernel(1.5), grid);
+
+            frame
+                .iter()
+                .zip(glow.iter())
+                .map(|(color, glow)| {
+                    let mut result = *color;
+
+                    for (value, glow) in result.iter_mut().zip(glow.iter()).take(3) {
+                        *value += glow * strength;
+                    }
+
+                    result
+                })
+                .collect()
+        }
+
+        Filter::Scanlines { intensity } => {
+            let phase = (frame_number / SCANLINE_FRAMES_PER_ROW) as usize % 2;
+
+            frame
+                .iter()
+                .enumerate()
+                .map(|(index, color)| {
+                    let mut result = *color;
+
+                    if (index % rows) % 2 == phase {
+                        for value in result.iter_mut().take(3) {
+                            *value *= 1.0 - intensity;
+                        }
+                    }
+
+                    result
+                })
+                .collect()
+        }
+
+        Filter::Flicker { amount } if amount > 0.0 => {
+            let factor = 1.0 - rand::thread_rng().gen_range(0.0, amount);
+
+            frame
+                .iter()
+                .map(|color| {
+                    let mut result = *color;
+
+                    for value in result.iter_mut().take(3) {
+                        *value *= factor;
+                    }
+
+                    result
+                })
+                .collect()
+        }
+        Filter::Flicker { .. } => frame,
+    }
+}
+
+/// Apply the filters of the active profile to the composited frames `led_maps`,
+/// using the grid of keys of the respective device in `devices`. Frames without a
+/// device are processed as the frames of a keyboard
+pub fn process_frame(led_maps: &mut [Vec<RGBA>], devices: &[RvDeviceState]) {
+    let filters = FILTERS.read();
+
+    if filters.is_empty() {
+        return;
+    }
+
+    let frame_number = FRAME.fetch_add(1, Ordering::SeqCst);
+
+    for (index, led_map) in led_maps.iter_mut().enumerate() {
+        let kind = devices
+            .get(index)
+            .map_or(DeviceKind::Keyboard, |device| device.kind);
+
+        let grid = match kind.grid() {
+            Some(grid) => grid,
+            None => continue,
+        };
+
+        let mut frame: Frame = led_map
+            .iter()
+            .map(|c| [c.r as f64, c.g as f64, c.b as f64, c.a as f64])
+            .collect();
+
+        for filter in filters.iter() {
+            frame = apply_filter(frame, *filter, grid, frame_number);
+        }
+
+        let clamp = |v: f64| v.round().max(0.0).min(255.0) as u8;
+
+        for (led, color) in led_map.iter_mut().zip(frame.iter()) {
+            *led = RGBA {
+                r: clamp(color[0]),
+                g: clamp(color[1]),
+                b: clamp(color[2]),
+                a: clamp(color[3]),
+            };
+        }
+    }
+}
@@ -16,34 +16,38 @@
 */
 
 use crate::constants;
-use failure::Fail;
+use crate::migration;
 use log::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::default::Default;
 use std::fs;
 use std::path::{Path, PathBuf};
+use thiserror::Error;
 use uuid::Uuid;
 
 pub type Result<T> = std::result::Result<T, ProfileError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum ProfileError {
-    #[fail(display = "Could not open profile file for reading")]
+    #[error("Could not open profile file for reading")]
     OpenError {},
 
-    #[fail(display = "Could not parse profile file")]
+    #[error("Could not parse profile file")]
     ParseError {},
 
-    #[fail(display = "Could not save profile file: {}", msg)]
+    #[error("Could not save profile file: {msg}")]
     WriteError { msg: String },
 
-    #[fail(display = "Could not find profile file from UUID")]
+    #[error("Could not find profile file from UUID")]
     FindError {},
 
-    #[fail(display = "Could not set a config value in a profile: {}", msg)]
+    #[error("Could not set a config value in a profile: {msg}")]
     SetValueError { msg: String },
-    // #[fail(display = "Unknown error: {}", description)]
+
+    #[error("Could not migrate profile: {msg}")]
+    MigrationError { msg: String },
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
@@ -106,6 +110,10 @@ fn default_script_file() -> Vec<PathBuf> {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Profile {
+    /// Version of the profile format, profiles of older versions are migrated on load
+    #[serde(default)]
+    pub version: u32,
+
     #[serde(default = "default_id")]
     pub id: Uuid,
 
@@ -120,6 +128,49 @@ pub struct Profile {
     pub active_scripts: Vec<PathBuf>,
 
     pub config: Option<HashMap<String, Vec<ConfigParam>>>,
+
+    /// Maps script file names to the device they render to, specified by
+    /// device index or serial number. Scripts that are not listed here
+    /// render to the default device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub devices: Option<HashMap<String, String>>,
+
+    /// Native animation that is played while the daemon starts up, before the
+    /// scripts are loaded, e.g. "sweep" or "fade-in"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_animation: Option<String>,
+
+    /// Native animation that is played when the daemon exits, e.g. "fade-out"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_animation: Option<String>,
+
+    /// Color of the startup and shutdown animations, specified as 0xRRGGBB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animation_color: Option<u32>,
+
+    /// Color filter of the output stage, e.g. "deuteranopia", overrides the
+    /// `[accessibility]` section of the config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_filter: Option<String>,
+
+    /// Remap the colors of status indicators, like the volume bar and alarms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub high_contrast: Option<bool>,
+
+    /// Show the remaining time of the pomodoro timer on the number row
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus_timer: Option<bool>,
+
+    /// Post-processing filters of the composited frames, applied in order, e.g.
+    /// "gaussian-blur:1.5" or "scanlines"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_processing: Option<Vec<String>>,
+
+    /// Hotkeys and macros per application, maps the window class of an application
+    /// to key combinations and their actions, like the `[hotkeys]` section. They take
+    /// precedence over the global hotkeys, while the application is focused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_macros: Option<HashMap<String, HashMap<String, String>>>,
 }
 
 pub trait FindConfig {
@@ -207,52 +258,93 @@ impl FindConfig for Vec<ConfigParam> {
 
 impl Profile {
     pub fn new(profile_file: &Path) -> Result<Self> {
-        // parse manifest
-        match fs::read_to_string(profile_file) {
-            Ok(toml) => {
-                // parse profile
-                match toml::de::from_str::<Self>(&toml) {
-                    Ok(mut result) => {
-                        // fill in required fields, after parsing
-                        result.id = Uuid::new_v4();
-                        result.profile_file = profile_file.to_path_buf();
-
-                        result.config = Some(HashMap::new());
-
-                        Ok(result)
-                    }
+        let mut result = Self::parse(profile_file)?;
 
-                    Err(_e) => Err(ProfileError::ParseError {}),
-                }
-            }
+        // fill in required fields, after parsing
+        result.id = Uuid::new_v4();
+        result.profile_file = profile_file.to_path_buf();
 
-            Err(_e) => Err(ProfileError::OpenError {}),
-        }
+        result.config = Some(HashMap::new());
+
+        Ok(result)
     }
 
     pub fn from(profile_file: &Path) -> Result<Self> {
-        // parse manifest
-        match fs::read_to_string(profile_file) {
-            Ok(toml) => {
-                // parse profile
-                match toml::de::from_str::<Self>(&toml) {
-                    Ok(mut result) => {
-                        // fill in required fields, after parsing
-                        result.profile_file = profile_file.to_path_buf();
-
-                        if result.config.is_none() {
-                            result.config = Some(HashMap::new());
-                        }
+        let mut result = Self::parse(profile_file)?;
 
-                        Ok(result)
-                    }
+        // fill in required fields, after parsing
+        result.profile_file = profile_file.to_path_buf();
 
-                    Err(_e) => Err(ProfileError::ParseError {}),
-                }
-            }
+        if result.config.is_none() {
+            result.config = Some(HashMap::new());
+        }
+
+        Ok(result)
+    }
 
-            Err(_e) => Err(ProfileError::OpenError {}),
+    /// Parse the profile file `profile_file`, profiles of older versions of the
+    /// profile format are migrated to the current version. If `global.migrate_profiles`
+    /// is enabled, migrated profiles are written back, after creating a backup
+    fn parse(profile_file: &Path) -> Result<Self> {
+        let toml = fs::read_to_string(profile_file).map_err(|_e| ProfileError::OpenError {})?;
+        let mut value =
+            toml::de::from_str::<toml::Value>(&toml).map_err(|_e| ProfileError::ParseError {})?;
+
+        let map_err = |e: migration::MigrationError| ProfileError::MigrationError {
+            msg: format!("{}", e),
+        };
+
+        let version = migration::get_version(&value).map_err(map_err)?;
+
+        if migration::migrate(&mut value).map_err(map_err)? {
+            info!(
+                "Migrated profile '{}' from version {} to version {}",
+                profile_file.display(),
+                version,
+                migration::PROFILE_VERSION
+            );
+
+            let write_back = crate::CONFIG
+                .lock()
+                .as_ref()
+                .and_then(|config| config.get::<bool>("global.migrate_profiles").ok())
+                .unwrap_or(false);
+
+            if write_back {
+                Self::write_back(profile_file, version, &value).unwrap_or_else(|e| {
+                    warn!(
+                        "Could not write back the migrated profile '{}': {}",
+                        profile_file.display(),
+                        e
+                    )
+                });
+            }
         }
+
+        value
+            .try_into::<Self>()
+            .map_err(|_e| ProfileError::ParseError {})
+    }
+
+    /// Replace the profile file `profile_file` of version `version` with the migrated
+    /// profile `value`. The original file is kept as e.g. `default.profile.v0`
+    fn write_back(profile_file: &Path, version: u32, value: &toml::Value) -> Result<()> {
+        let mut backup_file = profile_file.as_os_str().to_owned();
+        backup_file.push(format!(".v{}", version));
+
+        fs::copy(profile_file, &backup_file).map_err(|_| ProfileError::WriteError {
+            msg: "Could not create a backup".into(),
+        })?;
+
+        let toml = toml::ser::to_string_pretty(value).map_err(|_| ProfileError::WriteError {
+            msg: "Could not convert profile data".into(),
+        })?;
+
+        fs::write(profile_file, &toml).map_err(|_| ProfileError::WriteError {
+            msg: "Could not write file".into(),
+        })?;
+
+        Ok(())
     }
 
     pub fn find_by_uuid(uuid: Uuid, profile_path: &Path) -> Result<Self> {
@@ -293,6 +385,21 @@ impl Profile {
         Ok(())
     }
 
+    /// Set the config param `param` of the script `script_name`, replacing a
+    /// previous value. The profile is not saved
+    pub fn set_config_param(&mut self, script_name: &str, param: ConfigParam) {
+        let config = self
+            .config
+            .get_or_insert_with(HashMap::new)
+            .entry(script_name.to_string())
+            .or_insert_with(Vec::new);
+
+        match config.find_config_param_mut(param.get_name()) {
+            Some(existing) => *existing = param,
+            None => config.push(param),
+        }
+    }
+
     pub fn get_int_value(&self, script_name: &str, name: &str) -> Option<&i64> {
         if let Some(config) = &self.config {
             if let Some(cfg) = config.get(script_name) {
@@ -605,19 +712,34 @@ impl Default for Profile {
         let config = Some(HashMap::new());
 
         Self {
+            version: migration::PROFILE_VERSION,
             id: default_id(),
             profile_file,
             name: "Default".into(),
             description: "Auto-generated profile".into(),
             active_scripts: vec![PathBuf::from(constants::DEFAULT_EFFECT_SCRIPT)],
             config,
+            devices: None,
+            startup_animation: None,
+            shutdown_animation: None,
+            animation_color: None,
+            color_filter: None,
+            high_contrast: None,
+            focus_timer: None,
+            post_processing: None,
+            app_macros: None,
         }
     }
 }
 
 pub fn get_profiles(profile_path: &Path) -> Result<Vec<Profile>> {
-    let profile_files = get_profile_files(&profile_path).unwrap();
+    let profile_files = get_profile_files(&profile_path)?;
+
+    load_profiles(&profile_files)
+}
 
+/// Load the profiles `profile_files`, profiles that could not be processed are skipped
+pub fn load_profiles(profile_files: &[PathBuf]) -> Result<Vec<Profile>> {
     let mut errors_present = false;
     let mut result: Vec<Profile> = vec![];
 
@@ -646,7 +768,7 @@ pub fn get_profiles(profile_path: &Path) -> Result<Vec<Profile>> {
 }
 
 pub fn get_profile_files(profile_path: &Path) -> Result<Vec<PathBuf>> {
-    let paths = fs::read_dir(&profile_path).unwrap();
+    let paths = fs::read_dir(&profile_path).map_err(|_e| ProfileError::OpenError {})?;
 
     Ok(paths
         .map(|p| p.unwrap().path())
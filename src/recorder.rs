@@ -0,0 +1,299 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use gif::SetParameter;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::{KEYS_PER_COLUMN, NUM_COLUMNS, NUM_KEYS, RGBA};
+
+pub type Result<T> = std::result::Result<T, RecorderError>;
+
+#[derive(Debug, Error)]
+pub enum RecorderError {
+    #[error("Could not access the recording: {description}")]
+    IoError { description: String },
+
+    #[error("Invalid recording file")]
+    InvalidFormat {},
+
+    #[error("The recording does not contain any frames")]
+    EmptyRecording {},
+
+    #[error("Could not encode the image: {description}")]
+    EncoderError { description: String },
+}
+
+impl From<std::io::Error> for RecorderError {
+    fn from(e: std::io::Error) -> Self {
+        RecorderError::IoError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+/// Magic bytes at the start of each recording
+const MAGIC: &[u8; 4] = b"ERUR";
+
+/// Version of the file format
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of a frame: timestamp and the colors, as RGB triplets
+const FRAME_SIZE: usize = 8 + NUM_KEYS * 3;
+
+/// Size of a key in the exported images, in pixels, including the gap
+const KEY_SIZE: usize = 16;
+
+/// Gap between two keys in the exported images, in pixels
+const KEY_GAP: usize = 2;
+
+lazy_static! {
+    /// The recording file, while a recording is running, and its start time
+    static ref RECORDING: Mutex<Option<(BufWriter<File>, Instant)>> = Mutex::new(None);
+}
+
+/// A recorded frame
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Time since the start of the recording, in microseconds
+    pub timestamp: u64,
+
+    pub led_map: Vec<RGBA>,
+}
+
+/// Start recording the composited frames of the default device to `path`
+pub fn start<P: AsRef<Path>>(path: P) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path.as_ref())?);
+
+    file.write_all(MAGIC)?;
+    file.write_all(&[FORMAT_VERSION])?;
+
+    info!("Recording frames to: {}", path.as_ref().display());
+
+    *RECORDING.lock() = Some((file, Instant::now()));
+
+    Ok(())
+}
+
+/// Stop the running recording, if any
+pub fn stop() {
+    if let Some((mut file, _)) = RECORDING.lock().take() {
+        file.flush()
+            .unwrap_or_else(|e| error!("Could not write the recording: {}", e));
+    }
+}
+
+/// Record the frame `led_map`, if a recording is running
+pub fn record(led_map: &[RGBA]) {
+    let mut recording = RECORDING.lock();
+
+    if let Some((file, start)) = recording.as_mut() {
+        let timestamp = start.elapsed().as_micros() as u64;
+
+        let mut buf = Vec::with_capacity(FRAME_SIZE);
+        buf.extend_from_slice(&timestamp.to_le_bytes());
+
+        for color in led_map.iter().take(NUM_KEYS) {
+            buf.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        if let Err(e) = file.write_all(&buf) {
+            error!("Could not write the recording, stopping it: {}", e);
+            *recording = None;
+        }
+    }
+}
+
+/// Read all frames of the recording `path`
+pub fn read_recording<P: AsRef<Path>>(path: P) -> Result<Vec<Frame>> {
+    let buf = fs::read(path.as_ref())?;
+
+    if buf.len() < 5 || &buf[0..4] != MAGIC || buf[4] != FORMAT_VERSION {
+        return Err(RecorderError::InvalidFormat {});
+    }
+
+    if (buf.len() - 5) % FRAME_SIZE != 0 {
+        return Err(RecorderError::InvalidFormat {});
+    }
+
+    Ok(buf[5..]
+        .chunks(FRAME_SIZE)
+        .map(|chunk| {
+            let mut timestamp = [0u8; 8];
+            timestamp.copy_from_slice(&chunk[0..8]);
+
+            let led_map = chunk[8..]
+                .chunks(3)
+                .map(|c| RGBA {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                    a: 0xff,
+                })
+                .collect();
+
+            Frame {
+                timestamp: u64::from_le_bytes(timestamp),
+                led_map,
+            }
+        })
+        .collect())
+}
+
+/// Resample the frames `frames` to a constant rate of `fps` frames per second
+fn resample(frames: &[Frame], fps: u32) -> Vec<&Frame> {
+    let interval = 1_000_000 / u64::from(fps.max(1));
+    let duration = frames.last().map(|f| f.timestamp).unwrap_or(0);
+
+    let mut result = vec![];
+    let mut current = 0;
+    let mut t = 0;
+
+    while t <= duration {
+        while current + 1 < frames.len() && frames[current + 1].timestamp <= t {
+            current += 1;
+        }
+
+        result.push(&frames[current]);
+        t += interval;
+    }
+
+    result
+}
+
+/// Get the size of the exported images, in pixels
+fn get_image_size() -> (usize, usize) {
+    (
+        NUM_COLUMNS * KEY_SIZE + KEY_GAP,
+        KEYS_PER_COLUMN * KEY_SIZE + KEY_GAP,
+    )
+}
+
+/// Render the LED map `led_map` onto an image of the keyboard, as RGB triplets.
+/// Keys are laid out on a regular grid, like the canvas of the scripts
+fn render(led_map: &[RGBA]) -> Vec<u8> {
+    let (width, height) = get_image_size();
+    let mut pixels = vec![0x10; width * height * 3];
+
+    for (index, color) in led_map.iter().enumerate() {
+        let column = index / KEYS_PER_COLUMN;
+        let row = index % KEYS_PER_COLUMN;
+
+        let x0 = column * KEY_SIZE + KEY_GAP;
+        let y0 = row * KEY_SIZE + KEY_GAP;
+
+        for y in y0..y0 + KEY_SIZE - KEY_GAP {
+            for x in x0..x0 + KEY_SIZE - KEY_GAP {
+                let offset = (y * width + x) * 3;
+                pixels[offset..offset + 3].copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+    }
+
+    pixels
+}
+
+fn encoder_error<E: std::fmt::Display>(e: E) -> RecorderError {
+    RecorderError::EncoderError {
+        description: format!("{}", e),
+    }
+}
+
+/// Export the frames as an animated GIF to `path`
+fn export_gif(frames: &[&Frame], fps: u32, path: &Path) -> Result<()> {
+    let (width, height) = get_image_size();
+
+    let file = File::create(path)?;
+    let mut encoder =
+        gif::Encoder::new(file, width as u16, height as u16, &[]).map_err(encoder_error)?;
+    encoder.set(gif::Repeat::Infinite).map_err(encoder_error)?;
+
+    // the delay is specified in units of 10 milliseconds
+    let delay = (100 / fps.max(1)).max(2) as u16;
+
+    for frame in frames {
+        let pixels = render(&frame.led_map);
+
+        let mut gif_frame = gif::Frame::from_rgb_speed(
+            width as u16,
+            height as u16,
+            &pixels,
+            constants::GIF_QUANTIZATION_SPEED,
+        );
+        gif_frame.delay = delay;
+
+        encoder.write_frame(&gif_frame).map_err(encoder_error)?;
+    }
+
+    Ok(())
+}
+
+/// Export the frames as a sequence of PNG images to the directory `path`
+fn export_png_sequence(frames: &[&Frame], path: &Path) -> Result<()> {
+    let (width, height) = get_image_size();
+
+    fs::create_dir_all(path)?;
+
+    for (index, frame) in frames.iter().enumerate() {
+        let file = File::create(path.join(format!("frame-{:05}.png", index)))?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(encoder_error)?;
+        writer
+            .write_image_data(&render(&frame.led_map))
+            .map_err(encoder_error)?;
+    }
+
+    Ok(())
+}
+
+/// Export the recording `path` to `output`, resampled to `fps` frames per second.
+/// If `output` ends with ".gif", an animated GIF is written, otherwise a sequence
+/// of PNG images is written to the directory `output`
+pub fn export<P: AsRef<Path>>(path: P, output: P, fps: u32) -> Result<()> {
+    let frames = read_recording(path)?;
+
+    if frames.is_empty() {
+        return Err(RecorderError::EmptyRecording {});
+    }
+
+    let frames = resample(&frames, fps);
+    let output = output.as_ref();
+
+    if output
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("gif"))
+    {
+        export_gif(&frames, fps, output)?;
+    } else {
+        export_png_sequence(&frames, output)?;
+    }
+
+    println!("Exported {} frames to: {}", frames.len(), output.display());
+
+    Ok(())
+}
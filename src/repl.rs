@@ -0,0 +1,373 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A debug console, that evaluates Lua snippets in the context of a running
+//! script, e.g. to inspect or change its globals. The daemon listens on a Unix
+//! socket, that is only accessible by root, since the snippets are executed with
+//! the privileges of the daemon.
+//!
+//! The client sends the name of the script as the first line, then one line of
+//! Lua per request. Each reply is a single line: "= " followed by the results,
+//! "! " followed by an error, or "> " if the input is incomplete and continues
+//! on the next line. Newlines and backslashes in replies are escaped.
+
+use lazy_static::lazy_static;
+use log::*;
+use rlua::{Context, MultiValue, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::scripting::script;
+
+pub type Result<T> = std::result::Result<T, ReplError>;
+
+#[derive(Debug, Error)]
+pub enum ReplError {
+    #[error("Could not listen on {path}: {description}")]
+    SocketError { path: String, description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+lazy_static! {
+    /// Number of connected clients
+    static ref CLIENTS: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// The result of the evaluation of a snippet
+#[derive(Debug, Clone)]
+pub enum EvalResult {
+    /// The formatted results of the snippet
+    Value(String),
+
+    /// The snippet failed to compile or raised an error
+    Error(String),
+
+    /// The snippet is incomplete, e.g. a function definition spanning lines
+    Incomplete,
+}
+
+/// Format the Lua value `value`, tables are displayed one level deep
+pub fn format_value(value: &Value, nested: bool) -> String {
+    match value {
+        Value::Nil => "nil".into(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("{:?}", s.to_str().unwrap_or("<invalid utf-8>")),
+
+        Value::Table(table) if !nested => {
+            let mut entries = vec![];
+
+            for (i, pair) in table.clone().pairs::<Value, Value>().enumerate() {
+                if i >= constants::REPL_MAX_TABLE_ENTRIES {
+                    entries.push("...".to_string());
+                    break;
+                }
+
+                match pair {
+                    Ok((k, v)) => entries.push(format!(
+                        "[{}] = {}",
+                        format_value(&k, true),
+                        format_value(&v, true)
+                    )),
+
+                    Err(e) => {
+                        entries.push(format!("<{}>", e));
+                        break;
+                    }
+                }
+            }
+
+            format!("{{{}}}", entries.join(", "))
+        }
+
+        other => other.type_name().into(),
+    }
+}
+
+/// Evaluate the snippet `code` in the Lua context `lua_ctx`. Expressions are
+/// evaluated first, as in the standalone Lua interpreter, so that e.g. "x" displays
+/// the value of the global x
+pub fn eval(lua_ctx: Context, code: &str) -> EvalResult {
+    let result = match lua_ctx
+        .load(&format!("return {}", code))
+        .eval::<MultiValue>()
+    {
+        Err(rlua::Error::SyntaxError { .. }) => lua_ctx.load(code).eval::<MultiValue>(),
+        result => result,
+    };
+
+    match result {
+        Ok(values) => EvalResult::Value(
+            values
+                .iter()
+                .map(|value| format_value(value, false))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+
+        Err(rlua::Error::SyntaxError {
+            incomplete_input: true,
+            ..
+        }) => EvalResult::Incomplete,
+
+        Err(e) => EvalResult::Error(format!("{}", e)),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Evaluate the snippet `code` in the script `script_name`. If the script runs on
+/// multiple devices, the reply of the first instance is used
+fn eval_in_script(script_name: &str, code: &str) -> EvalResult {
+    let (reply_tx, reply_rx) = channel();
+
+    for lua_tx in crate::LUA_TXS.lock().iter() {
+        lua_tx
+            .send(script::Message::Eval(
+                script_name.to_string(),
+                code.to_string(),
+                reply_tx.clone(),
+            ))
+            .unwrap_or_else(|e| error!("Could not send an event to a Lua VM: {}", e));
+    }
+
+    drop(reply_tx);
+
+    match reply_rx.recv_timeout(Duration::from_millis(constants::REPL_EVAL_TIMEOUT_MILLIS)) {
+        Ok(result) => result,
+        Err(_) => EvalResult::Error("The script did not respond".into()),
+    }
+}
+
+fn handle_client(stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut lines = BufReader::new(stream).lines();
+
+    let script_name = match lines.next() {
+        Some(line) => line?.trim().to_string(),
+        None => return Ok(()),
+    };
+
+    let is_active = crate::ACTIVE_SCRIPTS
+        .lock()
+        .iter()
+        .any(|manifest| manifest.name == script_name);
+
+    if !is_active {
+        writeln!(
+            writer,
+            "! {}",
+            escape(&format!("No active script named '{}'", script_name))
+        )?;
+
+        return Ok(());
+    }
+
+    info!("Attached the debug console to the script '{}'", script_name);
+    writeln!(writer, "= ")?;
+
+    let mut buffer = String::new();
+
+    for line in lines {
+        buffer.push_str(&line?);
+        buffer.push('\n');
+
+        match eval_in_script(&script_name, &buffer) {
+            EvalResult::Value(values) => writeln!(writer, "= {}", escape(&values))?,
+            EvalResult::Error(e) => writeln!(writer, "! {}", escape(&e))?,
+
+            EvalResult::Incomplete => {
+                writeln!(writer, "> ")?;
+                continue;
+            }
+        }
+
+        buffer.clear();
+    }
+
+    info!(
+        "Detached the debug console from the script '{}'",
+        script_name
+    );
+
+    Ok(())
+}
+
+fn run_listener(listener: UnixListener) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if CLIENTS.load(Ordering::SeqCst) >= constants::REPL_MAX_CLIENTS {
+                    warn!("Too many clients of the debug console");
+                    continue;
+                }
+
+                CLIENTS.fetch_add(1, Ordering::SeqCst);
+
+                thread::Builder::new()
+                    .name("repl-client".into())
+                    .spawn(move || {
+                        handle_client(stream)
+                            .unwrap_or_else(|e| warn!("Debug console client error: {}", e));
+
+                        CLIENTS.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .map(|_| ())
+                    .unwrap_or_else(|e| {
+                        CLIENTS.fetch_sub(1, Ordering::SeqCst);
+                        error!("Could not spawn a thread: {}", e);
+                    });
+            }
+
+            Err(e) => warn!("Could not accept a client of the debug console: {}", e),
+        }
+    }
+}
+
+/// Listen for clients of the debug console, if enabled
+pub fn initialize() -> Result<()> {
+    let (enabled, path) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config.get_bool("repl.enabled").unwrap_or(false),
+            config
+                .get_str("repl.socket")
+                .unwrap_or_else(|_| constants::DEFAULT_REPL_SOCKET.into()),
+        )
+    };
+
+    if !enabled {
+        return Ok(());
+    }
+
+    let path = PathBuf::from(path);
+    let socket_error = |e: io::Error| ReplError::SocketError {
+        path: path.display().to_string(),
+        description: format!("{}", e),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(socket_error)?;
+    }
+
+    // remove a stale socket of a previous instance
+    if path.exists() {
+        fs::remove_file(&path).map_err(socket_error)?;
+    }
+
+    let listener = UnixListener::bind(&path).map_err(socket_error)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map_err(socket_error)?;
+
+    warn!(
+        "The debug console is enabled, listening on {}",
+        path.display()
+    );
+
+    thread::Builder::new()
+        .name("repl".into())
+        .spawn(move || run_listener(listener))
+        .map_err(|_e| ReplError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// Attach the debug console to the script `script_name`, reading snippets from
+/// stdin until EOF
+pub fn run_client(script_name: &str, socket: &Path) -> io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    let mut replies = BufReader::new(stream.try_clone()?).lines();
+
+    writeln!(stream, "{}", script_name)?;
+
+    let greeting = replies.next().transpose()?.unwrap_or_default();
+
+    if greeting.starts_with("! ") {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            unescape(&greeting[2..]),
+        ));
+    }
+
+    let prompt = format!("{}> ", script_name);
+    let continuation = format!("{:>width$}> ", "", width = script_name.len());
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock().lines();
+
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    while let Some(line) = input.next() {
+        writeln!(stream, "{}", line?)?;
+
+        let reply = match replies.next() {
+            Some(reply) => reply?,
+            None => return Ok(()),
+        };
+
+        let (kind, text) = reply.split_at(reply.len().min(2));
+        let text = unescape(text);
+
+        match kind {
+            "= " if text.is_empty() => print!("{}", prompt),
+            "= " => print!("{}\n{}", text, prompt),
+            "> " => print!("{}", continuation),
+            _ => print!("{}\n{}", text, prompt),
+        }
+
+        io::stdout().flush()?;
+    }
+
+    println!();
+
+    Ok(())
+}
@@ -0,0 +1,509 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! An embedded HTTP server, that exposes a REST API mirroring the D-Bus control
+//! API, and serves a small web page to control the daemon from a browser:
+//!
+//! * `GET /api/status`: Brightness, do-not-disturb mode and the active profile
+//! * `GET /api/metrics`: Percentiles of the latency of the input path, in microseconds,
+//!   unless latency tracing is disabled. Runtimes of the main loop hooks of the plugins
+//! * `GET /api/profiles`: All available profiles
+//! * `GET /api/profile`, `PUT /api/profile` (`{"file":"..."}`): The active profile
+//! * `GET /api/brightness`, `PUT /api/brightness` (`{"brightness":80}`)
+//! * `GET /api/scripts`: The active scripts, with the values of their parameters and
+//!   their capabilities
+//! * `PUT /api/scripts/<index>/params/<name>` (`{"value":"..."}`): Change a parameter
+//!   of the `index`-th active script. The change is saved to the active profile as well
+//!
+//! Requests to the API have to carry the access token as `Authorization: Bearer <token>`.
+//! A new token is generated on each start of the daemon, and written to a file that
+//! is only readable by root and the user of the active session on the seat. Requests
+//! of web pages from other origins are rejected.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::thread;
+use thiserror::Error;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::constants;
+use crate::dbus_interface::Message;
+use crate::latency;
+use crate::mentions;
+use crate::paths;
+use crate::plugin_manager;
+use crate::profiles::{self, GetAttr};
+use crate::scripting::manifest::{self, GetAttr as GetAttrManifest};
+use crate::scripting::overrides;
+use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
+
+lazy_static! {
+    /// The access token of the API, and the file that it has been written to
+    static ref TOKEN: RwLock<Option<(String, PathBuf)>> = RwLock::new(None);
+}
+
+pub type Result<T> = std::result::Result<T, RestApiError>;
+
+#[derive(Debug, Error)]
+pub enum RestApiError {
+    #[error("Could not bind to {address}: {description}")]
+    BindError {
+        address: String,
+        description: String,
+    },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+
+    #[error("Bad request: {description}")]
+    BadRequest { description: String },
+
+    #[error("Not found")]
+    NotFound {},
+
+    #[error("Missing or invalid access token")]
+    Unauthorized {},
+
+    #[error("Requests from other origins are not allowed")]
+    Forbidden {},
+
+    #[error("Could not write the access token: {description}")]
+    TokenError { description: String },
+
+    #[error("Could not send a message to the main thread")]
+    ChannelError {},
+}
+
+/// The web page, that is served on `/`
+const INDEX_HTML: &str = include_str!("../static/remote.html");
+
+fn bad_request<E: std::fmt::Display>(e: E) -> RestApiError {
+    RestApiError::BadRequest {
+        description: format!("{}", e),
+    }
+}
+
+/// Describe the profile `profile` as a JSON object
+fn profile_to_json(profile: &profiles::Profile) -> Value {
+    json!({
+        "name": profile.name,
+        "id": profile.id.to_string(),
+        "file": profile
+            .profile_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy(),
+    })
+}
+
+fn get_status() -> Value {
+    let active_profile = ACTIVE_PROFILE.lock();
+
+    json!({
+        "brightness": crate::BRIGHTNESS.load(Ordering::SeqCst),
+        "do_not_disturb": crate::DO_NOT_DISTURB.load(Ordering::SeqCst),
+        "active_profile": active_profile.as_ref().map(profile_to_json),
+    })
+}
+
+fn get_metrics() -> Value {
+    let mut result = json!({
+        "plugins": plugin_manager::PLUGIN_MANAGER.read().get_hook_metrics()
+    });
+
+    if latency::is_enabled() {
+        result["latency"] = json!(latency::metrics());
+    }
+
+    result
+}
+
+fn get_profiles() -> Result<Value> {
+    let profile_files = paths::get_profile_files();
+
+    let mut profiles = profiles::load_profiles(&profile_files).map_err(bad_request)?;
+    profiles.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+
+    Ok(Value::Array(profiles.iter().map(profile_to_json).collect()))
+}
+
+fn get_profile() -> Result<Value> {
+    ACTIVE_PROFILE
+        .lock()
+        .as_ref()
+        .map(profile_to_json)
+        .ok_or(RestApiError::NotFound {})
+}
+
+fn switch_profile(main_tx: &Sender<Message>, body: &Value) -> Result<Value> {
+    let file = body["file"]
+        .as_str()
+        .ok_or_else(|| bad_request("Expected a profile file"))?;
+
+    // only profiles of the profile directories may be activated
+    let path = paths::find_profile(file).map_err(bad_request)?;
+
+    if !path.is_file() {
+        return Err(RestApiError::NotFound {});
+    }
+
+    main_tx
+        .send(Message::SwitchProfile(path))
+        .map_err(|_e| RestApiError::ChannelError {})?;
+
+    Ok(json!({ "file": file }))
+}
+
+fn get_brightness() -> Value {
+    json!({ "brightness": crate::BRIGHTNESS.load(Ordering::SeqCst) })
+}
+
+fn set_brightness(body: &Value) -> Result<Value> {
+    let brightness = body["brightness"]
+        .as_i64()
+        .ok_or_else(|| bad_request("Expected a brightness value"))?;
+
+    crate::BRIGHTNESS.store(brightness.max(0).min(100) as isize, Ordering::SeqCst);
+
+    Ok(get_brightness())
+}
+
+/// Get the values of the parameters of the script `manifest`, as specified by the
+/// active profile, or the defaults of the manifest
+fn get_param_values(manifest: &manifest::Manifest) -> HashMap<String, String> {
+    let mut result: HashMap<String, String> = manifest
+        .config
+        .iter()
+        .flatten()
+        .map(|param| (param.get_name().clone(), param.get_default()))
+        .collect();
+
+    if let Some(profile) = ACTIVE_PROFILE.lock().as_ref() {
+        if let Some(config) = profile.config.as_ref().and_then(|c| c.get(&manifest.name)) {
+            for param in config.iter() {
+                result.insert(param.get_name().clone(), param.get_value());
+            }
+        }
+    }
+
+    for param in overrides::get(&manifest.name).iter() {
+        result.insert(param.get_name().clone(), param.get_value());
+    }
+
+    result
+}
+
+fn get_scripts() -> Value {
+    let scripts = ACTIVE_SCRIPTS.lock();
+
+    Value::Array(
+        scripts
+            .iter()
+            .enumerate()
+            .map(|(index, manifest)| {
+                json!({
+                    "index": index,
+                    "name": manifest.name,
+                    "description": manifest.description,
+                    "params": manifest.config,
+                    "values": get_param_values(manifest),
+                    "permissions": manifest.permissions,
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Change the parameter `name` of the `index`-th active script, the new value is
+/// pushed into the running scripts and saved to the active profile
+fn set_param(index: usize, name: &str, body: &Value) -> Result<Value> {
+    let value = match &body["value"] {
+        Value::String(value) => value.clone(),
+        Value::Null => return Err(bad_request("Expected a value")),
+        value => value.to_string(),
+    };
+
+    let script_name = ACTIVE_SCRIPTS
+        .lock()
+        .get(index)
+        .map(|manifest| manifest.name.clone())
+        .ok_or(RestApiError::NotFound {})?;
+
+    overrides::set(&script_name, name, &value, true).map_err(bad_request)?;
+
+    Ok(json!({ "name": name, "value": value }))
+}
+
+fn post_mention(body: &Value) -> Result<Value> {
+    let source = body["source"]
+        .as_str()
+        .ok_or_else(|| bad_request("Expected a source"))?;
+    let text = body["text"].as_str().unwrap_or_default();
+
+    mentions::notify(source, text);
+
+    Ok(json!({ "source": source }))
+}
+
+/// Dispatch the request with the method `method` to the endpoint `url`
+fn route(main_tx: &Sender<Message>, method: &Method, url: &str, body: &Value) -> Result<Value> {
+    let path: Vec<&str> = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    match (method, path.as_slice()) {
+        (Method::Get, ["api", "status"]) => Ok(get_status()),
+
+        (Method::Get, ["api", "metrics"]) => Ok(get_metrics()),
+
+        (Method::Get, ["api", "profiles"]) => get_profiles(),
+
+        (Method::Get, ["api", "profile"]) => get_profile(),
+        (Method::Put, ["api", "profile"]) | (Method::Post, ["api", "profile"]) => {
+            switch_profile(main_tx, body)
+        }
+
+        (Method::Get, ["api", "brightness"]) => Ok(get_brightness()),
+        (Method::Put, ["api", "brightness"]) | (Method::Post, ["api", "brightness"]) => {
+            set_brightness(body)
+        }
+
+        (Method::Get, ["api", "scripts"]) => Ok(get_scripts()),
+        (Method::Put, ["api", "scripts", index, "params", name])
+        | (Method::Post, ["api", "scripts", index, "params", name]) => {
+            let index = index.parse::<usize>().map_err(bad_request)?;
+
+            set_param(index, name, body)
+        }
+
+        (Method::Post, ["api", "mention"]) => post_mention(body),
+
+        _ => Err(RestApiError::NotFound {}),
+    }
+}
+
+/// Get the value of the header `name` of the request `request`
+fn get_header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Compare the strings `a` and `b` in constant time
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Check the access token and the origin of the request `request`. Browsers send
+/// the origin of the page that issues a request, only requests of the page that
+/// is served by ourselves are accepted
+fn authorize(request: &Request) -> Result<()> {
+    if let Some(origin) = get_header(request, "Origin") {
+        let host = get_header(request, "Host").unwrap_or_default();
+
+        if origin != format!("http://{}", host) {
+            return Err(RestApiError::Forbidden {});
+        }
+    }
+
+    let token = TOKEN.read();
+    let token = token.as_ref().ok_or(RestApiError::Unauthorized {})?;
+
+    let authorization = get_header(request, "Authorization").unwrap_or_default();
+
+    let prefix = "Bearer ";
+
+    if authorization.starts_with(prefix)
+        && constant_time_eq(
+            authorization[prefix.len()..].trim().as_bytes(),
+            token.0.as_bytes(),
+        )
+    {
+        Ok(())
+    } else {
+        Err(RestApiError::Unauthorized {})
+    }
+}
+
+fn handle_request(main_tx: &Sender<Message>, mut request: Request) {
+    let content_type = |value: &str| Header::from_bytes(&b"Content-Type"[..], value).unwrap();
+
+    let url = request.url().to_string();
+    let method = request.method().clone();
+
+    let result = if method == Method::Get && (url == "/" || url == "/index.html") {
+        request.respond(
+            Response::from_string(INDEX_HTML).with_header(content_type("text/html; charset=utf-8")),
+        )
+    } else {
+        let mut body = String::new();
+
+        let body =
+            authorize(&request).and_then(|_| match request.as_reader().read_to_string(&mut body) {
+                Ok(0) => Ok(Value::Null),
+                Ok(_) => serde_json::from_str::<Value>(&body).map_err(bad_request),
+                Err(e) => Err(bad_request(e)),
+            });
+
+        let (status, value) = match body.and_then(|body| route(main_tx, &method, &url, &body)) {
+            Ok(value) => (200, value),
+
+            Err(e) => {
+                debug!("REST API: {} {}: {}", method, url, e);
+
+                let status = match e {
+                    RestApiError::BadRequest { .. } => 400,
+                    RestApiError::Unauthorized {} => 401,
+                    RestApiError::Forbidden {} => 403,
+                    RestApiError::NotFound {} => 404,
+                    _ => 500,
+                };
+
+                (status, json!({ "error": format!("{}", e) }))
+            }
+        };
+
+        request.respond(
+            Response::from_string(value.to_string())
+                .with_status_code(status)
+                .with_header(content_type("application/json")),
+        )
+    };
+
+    result.unwrap_or_else(|e| warn!("REST API: Could not send a response: {}", e));
+}
+
+/// Generate a new access token, and write it to the file `token_file`. The file is
+/// only accessible by root, until the user of the seat is granted access
+fn write_token(token_file: &Path) -> Result<String> {
+    let token_error = |e: std::io::Error| RestApiError::TokenError {
+        description: format!("{}", e),
+    };
+
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+
+    let token = hex::encode(bytes);
+
+    if let Some(dir) = token_file.parent() {
+        fs::create_dir_all(dir).map_err(token_error)?;
+    }
+
+    // never follow a file that has been placed there by somebody else
+    match fs::remove_file(token_file) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(token_error(e)),
+        _ => (),
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(token_file)
+        .map_err(token_error)?;
+
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+        .map_err(token_error)?;
+    file.write_all(token.as_bytes()).map_err(token_error)?;
+
+    Ok(token)
+}
+
+/// Grant the user `uid` of the active session on the seat access to the token,
+/// the file is handed back to root if nobody is logged in
+pub fn grant_token_access(uid: Option<u32>) {
+    if let Some((_, token_file)) = TOKEN.read().as_ref() {
+        let uid = uid.unwrap_or(0);
+
+        nix::unistd::chown(
+            token_file,
+            Some(nix::unistd::Uid::from_raw(uid)),
+            Some(nix::unistd::Gid::from_raw(0)),
+        )
+        .unwrap_or_else(|e| warn!("REST API: Could not change the owner of the token: {}", e));
+    }
+}
+
+/// Start the HTTP server, if it is enabled in the `[rest]` section of the config.
+/// Changes of the active profile are requested via `main_tx`
+pub fn initialize(main_tx: Sender<Message>) -> Result<()> {
+    let (enabled, address, token_file) = match crate::CONFIG.lock().as_ref() {
+        Some(config) => (
+            config.get::<bool>("rest.enabled").unwrap_or(false),
+            config
+                .get_str("rest.listen_address")
+                .unwrap_or_else(|_| constants::DEFAULT_REST_LISTEN_ADDRESS.to_string()),
+            config
+                .get_str("rest.token_file")
+                .unwrap_or_else(|_| constants::DEFAULT_REST_TOKEN_FILE.to_string()),
+        ),
+
+        None => (
+            false,
+            constants::DEFAULT_REST_LISTEN_ADDRESS.to_string(),
+            constants::DEFAULT_REST_TOKEN_FILE.to_string(),
+        ),
+    };
+
+    if !enabled {
+        info!("REST API DISABLED by configuration");
+        return Ok(());
+    }
+
+    let token_file = PathBuf::from(token_file);
+    let token = write_token(&token_file)?;
+
+    *TOKEN.write() = Some((token, token_file.clone()));
+
+    grant_token_access(paths::get_seat_user().map(|user| user.uid));
+
+    info!("REST API access token: {}", token_file.display());
+
+    let server = Server::http(&address).map_err(|e| RestApiError::BindError {
+        address: address.clone(),
+        description: format!("{}", e),
+    })?;
+
+    info!("REST API listening on http://{}/", address);
+
+    thread::Builder::new()
+        .name("rest".into())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                handle_request(&main_tx, request);
+            }
+        })
+        .map_err(|_e| RestApiError::ThreadSpawnError {})?;
+
+    Ok(())
+}
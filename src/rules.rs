@@ -0,0 +1,634 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A declarative rule engine, so that common automation does not require writing
+//! Lua. The rules of the `[[rules]]` tables are evaluated periodically, the
+//! actions of a rule are performed by the main loop as soon as all of its
+//! conditions hold, and the `otherwise` actions as soon as they cease to hold:
+//!
+//! ```toml
+//! [[rules]]
+//! when = ["time in 22:00-07:00", "power == battery"]
+//! then = ["brightness:30", "disable-script:shockwave.lua"]
+//! otherwise = ["brightness:100", "enable-script:shockwave.lua"]
+//! ```
+
+use chrono::{Local, NaiveTime, Timelike};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::constants;
+use crate::plugins::{PowerPlugin, SensorsPlugin, SystemPlugin};
+use crate::windows;
+
+pub type Result<T> = std::result::Result<T, RuleError>;
+
+#[derive(Debug, Error)]
+pub enum RuleError {
+    #[error("Invalid rule condition: {condition}")]
+    InvalidCondition { condition: String },
+
+    #[error("Invalid rule action: {action}")]
+    InvalidAction { action: String },
+
+    #[error("Invalid rule configuration: {description}")]
+    InvalidConfig { description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+lazy_static! {
+    /// The configured rules
+    static ref RULES: Mutex<Vec<Rule>> = Mutex::new(vec![]);
+
+    /// Actions that have to be performed by the main loop
+    static ref PENDING_ACTIONS: Mutex<Vec<Action>> = Mutex::new(vec![]);
+
+    /// Scripts that have been disabled by a rule, they are skipped when a profile is loaded
+    static ref DISABLED_SCRIPTS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// A condition of a rule, like "cpu_load > 80"
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The local time is within the range, the range may wrap around midnight
+    Time(NaiveTime, NaiveTime),
+
+    /// The system is running on battery (true) or AC power (false)
+    OnBattery(bool),
+
+    /// The charge of the battery is above (true) or below (false) the threshold
+    Battery(bool, f64),
+
+    /// The focused application is (true) or is not (false) the window class
+    App(bool, String),
+
+    /// The load of the CPUs in percent is above (true) or below (false) the threshold
+    CpuLoad(bool, f64),
+
+    /// The temperature of the CPU is above (true) or below (false) the threshold
+    CpuTemp(bool, f64),
+}
+
+impl FromStr for Condition {
+    type Err = RuleError;
+
+    /// Parse a condition like "battery < 20" or "time in 22:00-07:00"
+    fn from_str(condition: &str) -> Result<Self> {
+        let err = || RuleError::InvalidCondition {
+            condition: condition.to_string(),
+        };
+
+        let parts: Vec<&str> = condition.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(err());
+        }
+
+        let above = || match parts[1] {
+            ">" => Ok(true),
+            "<" => Ok(false),
+
+            _ => Err(err()),
+        };
+
+        let equal = || match parts[1] {
+            "==" => Ok(true),
+            "!=" => Ok(false),
+
+            _ => Err(err()),
+        };
+
+        let threshold = || parts[2].parse::<f64>().map_err(|_e| err());
+
+        match parts[0] {
+            "time" if parts[1] == "in" => {
+                let mut range = parts[2].splitn(2, '-');
+
+                let mut time = || {
+                    range
+                        .next()
+                        .and_then(|t| NaiveTime::parse_from_str(t, "%H:%M").ok())
+                        .ok_or_else(err)
+                };
+
+                Ok(Condition::Time(time()?, time()?))
+            }
+
+            "power" => match parts[2] {
+                "battery" => Ok(Condition::OnBattery(equal()?)),
+                "ac" => Ok(Condition::OnBattery(!equal()?)),
+
+                _ => Err(err()),
+            },
+
+            "battery" => Ok(Condition::Battery(above()?, threshold()?)),
+            "app" => Ok(Condition::App(equal()?, windows::normalize_class(parts[2]))),
+            "cpu_load" => Ok(Condition::CpuLoad(above()?, threshold()?)),
+            "cpu_temp" => Ok(Condition::CpuTemp(above()?, threshold()?)),
+
+            _ => Err(err()),
+        }
+    }
+}
+
+impl Condition {
+    fn compare(above: bool, value: f64, threshold: f64) -> bool {
+        if above {
+            value > threshold
+        } else {
+            value < threshold
+        }
+    }
+
+    /// Returns true if `now` is within the range `from`..`until`, the range may
+    /// wrap around midnight
+    fn is_in_range(from: NaiveTime, until: NaiveTime, now: NaiveTime) -> bool {
+        if from <= until {
+            from <= now && now < until
+        } else {
+            from <= now || now < until
+        }
+    }
+
+    /// Returns true if the condition currently holds
+    fn evaluate(&self) -> bool {
+        match self {
+            Condition::Time(from, until) => {
+                let now = Local::now().time().with_nanosecond(0).unwrap();
+
+                Self::is_in_range(*from, *until, now)
+            }
+
+            Condition::OnBattery(on_battery) => PowerPlugin::on_battery() == *on_battery,
+
+            Condition::Battery(above, threshold) => {
+                let percent = PowerPlugin::get_battery_percent();
+
+                // no battery present
+                percent >= 0 && Self::compare(*above, percent as f64, *threshold)
+            }
+
+            Condition::App(equal, class) => {
+                (windows::get_active_app().as_ref() == Some(class)) == *equal
+            }
+
+            Condition::CpuLoad(above, threshold) => {
+                let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.max(1);
+                let load = f64::from(SystemPlugin::get_current_load_avg_1()) * 100.0 / cpus as f64;
+
+                Self::compare(*above, load, *threshold)
+            }
+
+            Condition::CpuTemp(above, threshold) => Self::compare(
+                *above,
+                f64::from(SensorsPlugin::get_package_temp()),
+                *threshold,
+            ),
+        }
+    }
+}
+
+/// Actions that may be performed by a rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Switch to the profile, e.g.: "profile:gaming.profile"
+    SwitchProfile(PathBuf),
+
+    /// Set the global brightness in percent, e.g.: "brightness:30"
+    SetBrightness(isize),
+
+    /// Load a script of the profiles again, e.g.: "enable-script:shockwave.lua"
+    EnableScript(String),
+
+    /// Skip a script of the profiles, e.g.: "disable-script:shockwave.lua"
+    DisableScript(String),
+
+    /// Enter or leave do-not-disturb mode, e.g.: "dnd:on"
+    SetDnd(bool),
+}
+
+impl FromStr for Action {
+    type Err = RuleError;
+
+    fn from_str(action: &str) -> Result<Self> {
+        let err = || RuleError::InvalidAction {
+            action: action.to_string(),
+        };
+
+        let mut parts = action.trim().splitn(2, ':');
+        let name = parts.next().ok_or_else(err)?;
+        let arg = parts.next().map(|a| a.trim()).filter(|a| !a.is_empty());
+        let arg = arg.ok_or_else(err)?;
+
+        match name {
+            "profile" => Ok(Action::SwitchProfile(PathBuf::from(arg))),
+
+            "brightness" => arg
+                .parse::<isize>()
+                .ok()
+                .filter(|b| (0..=100).contains(b))
+                .map(Action::SetBrightness)
+                .ok_or_else(err),
+
+            "enable-script" => Ok(Action::EnableScript(arg.to_string())),
+            "disable-script" => Ok(Action::DisableScript(arg.to_string())),
+
+            "dnd" => match arg {
+                "on" => Ok(Action::SetDnd(true)),
+                "off" => Ok(Action::SetDnd(false)),
+
+                _ => Err(err()),
+            },
+
+            _ => Err(err()),
+        }
+    }
+}
+
+/// A rule of the configuration file, as specified by the user
+#[derive(Debug, Clone, Deserialize)]
+struct RuleConfig {
+    when: Vec<String>,
+
+    #[serde(default)]
+    then: Vec<String>,
+
+    #[serde(default)]
+    otherwise: Vec<String>,
+}
+
+/// A rule, the actions are performed as soon as all of the conditions hold
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub conditions: Vec<Condition>,
+
+    pub actions: Vec<Action>,
+
+    /// Performed as soon as the conditions cease to hold
+    pub otherwise: Vec<Action>,
+
+    /// True while all of the conditions hold, None if not yet evaluated
+    active: Option<bool>,
+}
+
+impl Rule {
+    fn new(config: &RuleConfig) -> Result<Self> {
+        if config.when.is_empty() {
+            return Err(RuleError::InvalidConfig {
+                description: "A rule requires at least one condition".into(),
+            });
+        }
+
+        let parse_actions = |actions: &[String]| {
+            actions
+                .iter()
+                .map(|a| a.parse::<Action>())
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Rule {
+            conditions: config
+                .when
+                .iter()
+                .map(|c| c.parse::<Condition>())
+                .collect::<Result<Vec<_>>>()?,
+
+            actions: parse_actions(&config.then)?,
+            otherwise: parse_actions(&config.otherwise)?,
+            active: None,
+        })
+    }
+
+    /// Evaluate the rule, returns the actions that have to be performed, if any.
+    /// `holds` returns true if a condition currently holds
+    fn evaluate<F: Fn(&Condition) -> bool>(&mut self, holds: F) -> &[Action] {
+        let active = self.conditions.iter().all(holds);
+
+        if self.active == Some(active) {
+            return &[];
+        }
+
+        let initial = self.active.is_none();
+        self.active = Some(active);
+
+        if active {
+            debug!("Rule became active: {:?}", self.conditions);
+
+            &self.actions
+        } else if initial {
+            // the state has not been changed by the rule yet
+            &[]
+        } else {
+            debug!("Rule became inactive: {:?}", self.conditions);
+
+            &self.otherwise
+        }
+    }
+}
+
+/// Load the rules from the `[[rules]]` tables of the configuration file, and
+/// start evaluating them
+pub fn initialize() -> Result<()> {
+    {
+        let config = crate::CONFIG.lock();
+        let rules = match config.as_ref().unwrap().get::<Vec<RuleConfig>>("rules") {
+            Ok(rules) => rules,
+
+            // no rules configured
+            Err(config::ConfigError::NotFound(_)) => return Ok(()),
+
+            Err(e) => {
+                return Err(RuleError::InvalidConfig {
+                    description: format!("{}", e),
+                })
+            }
+        };
+
+        let mut result = RULES.lock();
+        result.clear();
+
+        for (index, rule) in rules.iter().enumerate() {
+            match Rule::new(rule) {
+                Ok(rule) => {
+                    info!("Rule: {:?} => {:?}", rule.conditions, rule.actions);
+                    result.push(rule);
+                }
+
+                Err(e) => error!("Invalid rule #{}: {}", index + 1, e),
+            }
+        }
+
+        if result.is_empty() {
+            return Ok(());
+        }
+    }
+
+    spawn_rules_thread()
+}
+
+/// Evaluate the rules `rules`, returns the actions that have to be performed in
+/// the order of the rules, so actions of later rules take precedence
+fn collect_actions<F: Fn(&Condition) -> bool>(rules: &mut [Rule], holds: F) -> Vec<Action> {
+    let mut actions = vec![];
+
+    for rule in rules.iter_mut() {
+        actions.extend_from_slice(rule.evaluate(&holds));
+    }
+
+    actions
+}
+
+/// Spawn a thread that evaluates the rules, querying the sensors may be
+/// slow, so this is not done on the main thread
+fn spawn_rules_thread() -> Result<()> {
+    thread::Builder::new()
+        .name("rules".into())
+        .spawn(move || loop {
+            let actions = collect_actions(&mut RULES.lock(), Condition::evaluate);
+
+            if !actions.is_empty() {
+                PENDING_ACTIONS.lock().extend(actions);
+            }
+
+            thread::sleep(Duration::from_millis(constants::RULES_CHECK_MILLIS));
+        })
+        .map_err(|_e| RuleError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// Get the actions that have to be performed by the main loop, in the order of
+/// the rules
+pub fn take_actions() -> Vec<Action> {
+    std::mem::replace(&mut *PENDING_ACTIONS.lock(), vec![])
+}
+
+/// Enable or disable the script `script_file`, returns true if the state changed.
+/// The change takes effect when a profile is loaded
+pub fn set_script_disabled(script_file: &str, disabled: bool) -> bool {
+    let mut scripts = DISABLED_SCRIPTS.lock();
+
+    if disabled {
+        scripts.insert(script_file.to_string())
+    } else {
+        scripts.remove(script_file)
+    }
+}
+
+/// Returns true if the script `script_file` has been disabled by a rule
+pub fn is_script_disabled(script_file: &Path) -> bool {
+    let scripts = DISABLED_SCRIPTS.lock();
+
+    script_file
+        .file_name()
+        .map_or(false, |f| scripts.contains(&*f.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(t: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(t, "%H:%M").unwrap()
+    }
+
+    fn rule(when: &[&str], then: &[&str], otherwise: &[&str]) -> Rule {
+        let strings = |v: &[&str]| -> Vec<String> { v.iter().map(|s| s.to_string()).collect() };
+
+        Rule::new(&RuleConfig {
+            when: strings(when),
+            then: strings(then),
+            otherwise: strings(otherwise),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            "time in 22:00-07:00".parse::<Condition>().unwrap(),
+            Condition::Time(time("22:00"), time("07:00"))
+        );
+        assert_eq!(
+            "power == battery".parse::<Condition>().unwrap(),
+            Condition::OnBattery(true)
+        );
+        assert_eq!(
+            "power == ac".parse::<Condition>().unwrap(),
+            Condition::OnBattery(false)
+        );
+        assert_eq!(
+            "battery < 20".parse::<Condition>().unwrap(),
+            Condition::Battery(false, 20.0)
+        );
+        assert_eq!(
+            "cpu_load > 80".parse::<Condition>().unwrap(),
+            Condition::CpuLoad(true, 80.0)
+        );
+
+        assert!("battery <= 20".parse::<Condition>().is_err());
+        assert!("battery < low".parse::<Condition>().is_err());
+        assert!("time in 22:00".parse::<Condition>().is_err());
+        assert!("cpu_load>80".parse::<Condition>().is_err());
+        assert!("gpu_load > 80".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(
+            "profile:gaming.profile".parse::<Action>().unwrap(),
+            Action::SwitchProfile(PathBuf::from("gaming.profile"))
+        );
+        assert_eq!(
+            "brightness: 30".parse::<Action>().unwrap(),
+            Action::SetBrightness(30)
+        );
+        assert_eq!("dnd:on".parse::<Action>().unwrap(), Action::SetDnd(true));
+
+        assert!("brightness:101".parse::<Action>().is_err());
+        assert!("dnd:maybe".parse::<Action>().is_err());
+        assert!("profile:".parse::<Action>().is_err());
+        assert!("reboot:now".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn test_is_in_range() {
+        assert!(Condition::is_in_range(
+            time("08:00"),
+            time("17:00"),
+            time("08:00")
+        ));
+        assert!(Condition::is_in_range(
+            time("08:00"),
+            time("17:00"),
+            time("12:00")
+        ));
+        assert!(!Condition::is_in_range(
+            time("08:00"),
+            time("17:00"),
+            time("17:00")
+        ));
+        assert!(!Condition::is_in_range(
+            time("08:00"),
+            time("17:00"),
+            time("07:59")
+        ));
+
+        // the range wraps around midnight
+        assert!(Condition::is_in_range(
+            time("22:00"),
+            time("07:00"),
+            time("23:30")
+        ));
+        assert!(Condition::is_in_range(
+            time("22:00"),
+            time("07:00"),
+            time("00:00")
+        ));
+        assert!(Condition::is_in_range(
+            time("22:00"),
+            time("07:00"),
+            time("06:59")
+        ));
+        assert!(!Condition::is_in_range(
+            time("22:00"),
+            time("07:00"),
+            time("12:00")
+        ));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut rule = rule(
+            &["power == battery", "battery < 20"],
+            &["brightness:30"],
+            &["brightness:100"],
+        );
+
+        // all of the conditions have to hold
+        let battery = |c: &Condition| *c == Condition::OnBattery(true);
+        let low_battery = |c: &Condition| {
+            *c == Condition::OnBattery(true) || *c == Condition::Battery(false, 20.0)
+        };
+
+        // the state has not been changed by the rule yet, so it is not reverted
+        assert!(rule.evaluate(battery).is_empty());
+
+        assert_eq!(rule.evaluate(low_battery), &[Action::SetBrightness(30)]);
+
+        // actions are only performed when the state of the rule changes
+        assert!(rule.evaluate(low_battery).is_empty());
+
+        assert_eq!(rule.evaluate(battery), &[Action::SetBrightness(100)]);
+        assert!(rule.evaluate(battery).is_empty());
+    }
+
+    #[test]
+    fn test_collect_actions() {
+        let mut rules = vec![
+            rule(
+                &["power == battery"],
+                &["brightness:50"],
+                &["brightness:100"],
+            ),
+            rule(
+                &["battery < 20"],
+                &["brightness:30", "dnd:on"],
+                &["dnd:off"],
+            ),
+        ];
+
+        let everything = |_c: &Condition| true;
+        let nothing = |_c: &Condition| false;
+
+        // actions of later rules take precedence, they are performed last
+        assert_eq!(
+            collect_actions(&mut rules, everything),
+            vec![
+                Action::SetBrightness(50),
+                Action::SetBrightness(30),
+                Action::SetDnd(true)
+            ]
+        );
+
+        assert_eq!(
+            collect_actions(&mut rules, nothing),
+            vec![Action::SetBrightness(100), Action::SetDnd(false)]
+        );
+
+        assert!(collect_actions(&mut rules, nothing).is_empty());
+    }
+
+    #[test]
+    fn test_rule_without_conditions() {
+        assert!(Rule::new(&RuleConfig {
+            when: vec![],
+            then: vec!["dnd:on".to_string()],
+            otherwise: vec![],
+        })
+        .is_err());
+    }
+}
@@ -15,49 +15,66 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use failure::Fail;
 use log::*;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{thread, time};
+use thiserror::Error;
+
+use crate::capture::{self, Interface, Kind};
+use crate::constants;
+use crate::util;
 
 pub type Result<T> = std::result::Result<T, RvDeviceError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum RvDeviceError {
-    #[fail(display = "Could not enumerate devices")]
+    #[error("Could not enumerate devices")]
     EnumerationError {},
 
-    #[fail(display = "Could not open the device file")]
+    #[error("Could not open the device file")]
     DeviceOpenError {},
 
-    // #[fail(display = "Invalid init sequence")]
+    // #[error("Invalid init sequence")]
     // InitSequenceError {},
 
-    // #[fail(display = "Invalid operation")]
+    // #[error("Invalid operation")]
     // InvalidOperation {},
-    #[fail(display = "Device not bound")]
+    #[error("Device not bound")]
     DeviceNotBound {},
 
-    #[fail(display = "Device not opened")]
+    #[error("Device not opened")]
     DeviceNotOpened {},
 
-    #[fail(display = "Device not initialized")]
+    #[error("Device not initialized")]
     DeviceNotInitialized {},
 
-    #[fail(display = "Invalid status code")]
+    #[error("Invalid status code")]
     InvalidStatusCode {},
 
-    #[fail(display = "Invalid result")]
+    #[error("Invalid result")]
     InvalidResult {},
 
-    #[fail(display = "Write error")]
+    #[error("Write error")]
     WriteError {},
-    //#[fail(display = "Could not close the device")]
+
+    #[error("The device did not respond in time")]
+    Timeout {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+
+    #[error("Not supported by the device")]
+    NotSupported {},
+
+    #[error("The device is in use")]
+    DeviceBusy {},
+    //#[error("Could not close the device")]
     //CloseError {},
 
-    // #[fail(display = "Unknown error: {}", description)]
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
@@ -74,7 +91,7 @@ pub enum RvDeviceError {
 // 10 => "Could not close the device",
 // _ => "Unknown error",
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct RGBA {
     pub r: u8,
     pub g: u8,
@@ -82,6 +99,116 @@ pub struct RGBA {
     pub a: u8,
 }
 
+/// Information about the managed device, as reported by the hardware
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub model_name: String,
+    pub firmware_version: String,
+    pub serial_number: String,
+    pub usb_path: String,
+}
+
+/// The state that the LEDs are left in, when the daemon exits
+#[derive(Debug, Copy, Clone)]
+pub enum ExitState {
+    /// Switch back to the built-in hardware effect (rainbow wave)
+    HardwareDefault,
+
+    /// Turn off all LEDs
+    AllOff,
+
+    /// Set all LEDs to a static color
+    Static(RGBA),
+
+    /// Store a static color in the hardware profile of the device, and switch to
+    /// it. The device keeps showing the color while the system is suspended, and
+    /// after it has been power cycled
+    Stored(RGBA),
+}
+
+impl ExitState {
+    /// Construct an exit state from its name in the configuration file,
+    /// one of "hardware", "off", "static" or "stored". `color` is only used for
+    /// the "static" and "stored" exit states and is specified as 0xRRGGBB
+    pub fn from_config(name: &str, color: u32) -> Option<Self> {
+        let color = RGBA {
+            r: ((color >> 16) & 0xff) as u8,
+            g: ((color >> 8) & 0xff) as u8,
+            b: (color & 0xff) as u8,
+            a: 0xff,
+        };
+
+        match name {
+            "hardware" => Some(ExitState::HardwareDefault),
+            "off" => Some(ExitState::AllOff),
+            "static" => Some(ExitState::Static(color)),
+            "stored" => Some(ExitState::Stored(color)),
+
+            _ => None,
+        }
+    }
+}
+
+/// Events of special keys, that are not reported via evdev but as
+/// HID reports on the control interface of the device
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpecialKeyEvent {
+    /// The Easy Shift key (CAPS LOCK) has been pressed or released
+    EasyShift(bool),
+
+    /// The FN key has been pressed or released
+    FnKey(bool),
+}
+
+impl SpecialKeyEvent {
+    /// Decode a HID report of the control interface. Returns None if the
+    /// report does not describe an event of a special key
+    pub fn from_report(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 5 || buf[0] != 0x03 || buf[1] != 0x00 {
+            return None;
+        }
+
+        let is_pressed = buf[4] != 0x00;
+
+        match (buf[2], buf[3]) {
+            (0x0a, EASY_SHIFT_CODE) => Some(SpecialKeyEvent::EasyShift(is_pressed)),
+            (0xfb, FN_KEY_CODE) => Some(SpecialKeyEvent::FnKey(is_pressed)),
+
+            _ => None,
+        }
+    }
+}
+
+/// Send the feature report `buf` to the control device, the report is recorded,
+/// if a capture is running
+fn send_feature_report(ctrl_dev: &hidapi::HidDevice, buf: &[u8]) -> hidapi::HidResult<()> {
+    capture::record(Interface::Control, Kind::SetFeature, buf);
+
+    ctrl_dev.send_feature_report(buf)
+}
+
+/// Get the feature report `buf[0]` from the control device, the report is recorded,
+/// if a capture is running
+fn get_feature_report(ctrl_dev: &hidapi::HidDevice, buf: &mut [u8]) -> hidapi::HidResult<usize> {
+    let len = ctrl_dev.get_feature_report(buf)?;
+
+    capture::record(
+        Interface::Control,
+        Kind::GetFeature,
+        &buf[..len.min(buf.len())],
+    );
+
+    Ok(len)
+}
+
+/// Write the output report `buf` to the LED device, the report is recorded,
+/// if a capture is running
+fn write_report(led_dev: &hidapi::HidDevice, buf: &[u8]) -> hidapi::HidResult<usize> {
+    capture::record(Interface::Led, Kind::Write, buf);
+
+    led_dev.write(buf)
+}
+
 pub const VENDOR_STR: &str = "ROCCAT";
 pub const VENDOR_ID: u16 = 0x1e7d;
 pub const PRODUCT_ID: [u16; 2] = [0x3098, 0x307a];
@@ -89,43 +216,364 @@ pub const CTRL_INTERFACE: i32 = 1;
 pub const LED_INTERFACE: i32 = 3;
 pub const NUM_KEYS: usize = 144;
 
+/// The RGB headsets (Elo 7.1 USB, Elo 7.1 Air), their LEDs are controlled via
+/// output reports of a single interface. Support is experimental, and has to be
+/// enabled via `global.enable_headsets`
+pub const HEADSET_PRODUCT_ID: [u16; 2] = [0x3a34, 0x3a37];
+pub const HEADSET_INTERFACE: i32 = 0;
+
+/// Number of LED zones of a headset (left and right ear cup), the zones are set
+/// from the first entries of the LED map of the device
+pub const NUM_HEADSET_ZONES: usize = 2;
+
+/// Id of the output report, that sets the LEDs of a headset. The report carries a
+/// command in byte 1: 0x01 enables or disables software control, 0x04 sets the
+/// colors of the zones.
+///
+/// Note: ROCCAT does not publish the protocol of the headsets, and this layout has
+/// not been taken from a published source or verified against the hardware yet.
+/// Please report, if your headset does not react to it
+const HEADSET_LED_REPORT_ID: u8 = 0xff;
+
+/// Number of keys per column of the LED map, the LED map is in column major order
+pub const KEYS_PER_COLUMN: usize = 6;
+
+/// Number of columns of the LED map
+pub const NUM_COLUMNS: usize = NUM_KEYS / KEYS_PER_COLUMN;
+
+/// Key codes of the special keys, as found in the HID reports of the control interface
+const EASY_SHIFT_CODE: u8 = 0xff;
+const FN_KEY_CODE: u8 = 0x77;
+
+/// Find the index of the device specified by `selector`, which may either
+/// be the index of the device, its serial number, or "keyboard" or "headset" for
+/// the first device of that kind
+pub fn find_device(devices: &[RvDeviceState], selector: &str) -> Option<usize> {
+    match selector.trim().parse::<usize>() {
+        Ok(index) if index < devices.len() => Some(index),
+
+        _ if selector.trim() == "keyboard" => devices
+            .iter()
+            .position(|device| device.kind == DeviceKind::Keyboard),
+
+        _ if selector.trim() == "headset" => devices
+            .iter()
+            .position(|device| device.kind == DeviceKind::Headset),
+
+        _ => devices.iter().position(|device| {
+            device
+                .ctrl_hiddev_info
+                .as_ref()
+                .and_then(|info| info.serial_number.as_ref())
+                .map(|serial| serial == selector.trim())
+                .unwrap_or(false)
+        }),
+    }
+}
+
+/// Firmware revision specific quirks of the device init sequence
+pub struct FirmwareQuirk {
+    /// Lowest firmware version that this entry applies to
+    pub min_version: u8,
+
+    /// Highest firmware version that this entry applies to
+    pub max_version: u8,
+
+    /// Short description of the quirk, used for logging
+    pub description: &'static str,
+
+    /// IDs of the control reports to send during initialization, in order
+    pub init_sequence: &'static [u8],
+}
+
+/// The init sequence used for all known firmware revisions
+const DEFAULT_INIT_SEQUENCE: &[u8] = &[0x15, 0x05, 0x07, 0x0a, 0x0b, 0x06, 0x09, 0x0d, 0x13];
+
+/// Init sequence to use if the firmware version could not be determined
+const FALLBACK_QUIRK: FirmwareQuirk = FirmwareQuirk {
+    min_version: 0x00,
+    max_version: 0xff,
+    description: "Unknown firmware revision",
+    init_sequence: DEFAULT_INIT_SEQUENCE,
+};
+
+/// Firmware quirk table. The first entry matching the firmware version
+/// reported by the device is used. Entries for specific revisions have to
+/// precede more generic ones
+static FIRMWARE_QUIRKS: &[FirmwareQuirk] = &[FirmwareQuirk {
+    min_version: 0x00,
+    max_version: 0xff,
+    description: "Default init sequence",
+    init_sequence: DEFAULT_INIT_SEQUENCE,
+}];
+
+/// Find the quirk table entry for the firmware version `firmware_version`
+pub fn find_firmware_quirk(firmware_version: Option<u8>) -> &'static FirmwareQuirk {
+    match firmware_version {
+        Some(version) => FIRMWARE_QUIRKS
+            .iter()
+            .find(|q| version >= q.min_version && version <= q.max_version)
+            .unwrap_or_else(|| {
+                warn!("No init sequence found for firmware version {}", version);
+                &FALLBACK_QUIRK
+            }),
+
+        None => {
+            warn!("Firmware version unknown, using the fallback init sequence");
+            &FALLBACK_QUIRK
+        }
+    }
+}
+
+/// Select the smallest reliable settle time of `candidates` (in milliseconds, in
+/// ascending order), that is below `default`. `measure` writes a few frames with
+/// the specified settle time and returns the duration of the slowest write. A
+/// candidate is reliable if no write fails and the slowest write takes at most
+/// 1.5 times as long as with `default`, plus 1ms. Returns `default` if no
+/// candidate is reliable
+fn select_settle_time<F>(default: Duration, candidates: &[u64], mut measure: F) -> Result<Duration>
+where
+    F: FnMut(Duration) -> Result<Duration>,
+{
+    let baseline = measure(default)?;
+    let limit = baseline + baseline / 2 + Duration::from_millis(1);
+
+    for candidate in candidates {
+        let candidate = Duration::from_millis(*candidate);
+
+        if candidate >= default {
+            break;
+        }
+
+        match measure(candidate) {
+            Ok(slowest) if slowest <= limit => return Ok(candidate),
+            _ => continue,
+        }
+    }
+
+    Ok(default)
+}
+
+/// Feature report 0x0d, enabling custom (software controlled) effects
+const CUSTOM_EFFECTS_REPORT: [u8; 443] = [
+    0x0d, 0xbb, 0x01, 0x00, 0x06, 0x0b, 0x05, 0x45, 0x83, 0xca, 0xca, 0xca, 0xca, 0xca, 0xca, 0xce,
+    0xce, 0xd2, 0xce, 0xce, 0xd2, 0x19, 0x19, 0x19, 0x19, 0x19, 0x19, 0x23, 0x23, 0x2d, 0x23, 0x23,
+    0x2d, 0xe0, 0xe0, 0xe0, 0xe0, 0xe0, 0xe0, 0xe3, 0xe3, 0xe6, 0xe3, 0xe3, 0xe6, 0xd2, 0xd2, 0xd5,
+    0xd2, 0xd2, 0xd5, 0xd5, 0xd5, 0xd9, 0xd5, 0x00, 0xd9, 0x2d, 0x2d, 0x36, 0x2d, 0x2d, 0x36, 0x36,
+    0x36, 0x40, 0x36, 0x00, 0x40, 0xe6, 0xe6, 0xe9, 0xe6, 0xe6, 0xe9, 0xe9, 0xe9, 0xec, 0xe9, 0x00,
+    0xec, 0xd9, 0xd9, 0xdd, 0xd9, 0xdd, 0xdd, 0xe0, 0xe0, 0xdd, 0xe0, 0xe4, 0xe4, 0x40, 0x40, 0x4a,
+    0x40, 0x4a, 0x4a, 0x53, 0x53, 0x4a, 0x53, 0x5d, 0x5d, 0xec, 0xec, 0xef, 0xec, 0xef, 0xef, 0xf2,
+    0xf2, 0xef, 0xf2, 0xf5, 0xf5, 0xe4, 0xe4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x5d, 0x5d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf5, 0xf5, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe4, 0xe4, 0xe8, 0xe8, 0xe8, 0xe8, 0xe8,
+    0xeb, 0xeb, 0xeb, 0x00, 0xeb, 0x5d, 0x5d, 0x67, 0x67, 0x67, 0x67, 0x67, 0x70, 0x70, 0x70, 0x00,
+    0x70, 0xf5, 0xf5, 0xf8, 0xf8, 0xf8, 0xf8, 0xf8, 0xfb, 0xfb, 0xfb, 0x00, 0xfb, 0xeb, 0xef, 0xef,
+    0xef, 0x00, 0xef, 0xf0, 0xf0, 0xed, 0xf0, 0xf0, 0x00, 0x70, 0x7a, 0x7a, 0x7a, 0x00, 0x7a, 0x7a,
+    0x7a, 0x6f, 0x7a, 0x7a, 0x00, 0xfb, 0xfd, 0xfd, 0xfd, 0x00, 0xfd, 0xf8, 0xf8, 0xea, 0xf8, 0xf8,
+    0x00, 0xed, 0xed, 0xea, 0xed, 0xed, 0x00, 0xed, 0xea, 0xea, 0xf6, 0xe7, 0xea, 0x6f, 0x6f, 0x65,
+    0x6f, 0x6f, 0x00, 0x6f, 0x65, 0x65, 0x66, 0x5a, 0x65, 0xea, 0xea, 0xdc, 0xea, 0xea, 0x00, 0xea,
+    0xdc, 0xdc, 0x00, 0xce, 0xdc, 0xea, 0xe7, 0xe5, 0xe7, 0xe5, 0xe5, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x65, 0x5a, 0x50, 0x5a, 0x50, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdc, 0xce, 0xc0,
+    0xce, 0xc0, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe7, 0x00, 0x00, 0xe2, 0xe2, 0xe2, 0xe2,
+    0xdf, 0xdf, 0xdf, 0xdf, 0xdf, 0x5a, 0x00, 0x00, 0x45, 0x45, 0x45, 0x45, 0x3b, 0x3b, 0x3b, 0x3b,
+    0x3b, 0xce, 0x00, 0x00, 0xb2, 0xb2, 0xb2, 0xb2, 0xa4, 0xa4, 0xa4, 0xa4, 0xa4, 0xdc, 0xdc, 0xdc,
+    0xdc, 0x00, 0xda, 0xda, 0xda, 0xda, 0xda, 0x00, 0xd7, 0x30, 0x30, 0x30, 0x30, 0x00, 0x26, 0x26,
+    0x26, 0x26, 0x26, 0x00, 0x1c, 0x96, 0x96, 0x96, 0x96, 0x00, 0x88, 0x88, 0x88, 0x88, 0x88, 0x00,
+    0x7a, 0xd7, 0xd7, 0xd7, 0x00, 0xd4, 0xd4, 0xd4, 0xd4, 0xd4, 0xd1, 0xd1, 0xd1, 0x1c, 0x1c, 0x1c,
+    0x00, 0x11, 0x11, 0x11, 0x11, 0x11, 0x06, 0x06, 0x06, 0x7a, 0x7a, 0x7a, 0x00, 0x6c, 0x6c, 0x6c,
+    0x6c, 0x6c, 0x5e, 0x5e, 0x5e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0xcf,
+];
+
+/// Pacing of the writes to the LED device, shared by all handles of a device
+#[derive(Debug)]
+struct WritePacing {
+    /// Min. interval between the end of a write and the start of the next one
+    settle_time: Duration,
+
+    /// Time of the end of the most recent write
+    last_write: Option<Instant>,
+}
+
+/// The health of a device, shared by all handles of a device
+#[derive(Debug, Default)]
+struct DeviceHealth {
+    /// Set while the device is failing, e.g. frames could not be written to it
+    is_failed: bool,
+
+    /// Number of consecutive frames that could not be written to the device
+    failed_frames: u32,
+
+    /// Time of the last attempt to re-open the device
+    last_reopen: Option<Instant>,
+
+    /// A change of the health that has been caused by the writer thread, it is
+    /// reported by the next call to `send_led_map_with_retry`
+    pending_change: Option<HealthChange>,
+}
+
+/// The state of the writer thread of a device, shared by all handles of a device
+#[derive(Debug, Default)]
+struct WriterState {
+    /// The frame that is written next, frames that are submitted while one is
+    /// pending replace it
+    pending_frame: Option<Vec<RGBA>>,
+
+    /// Set to stop the writer thread
+    shutdown: bool,
+}
+
+/// The state of the writer thread, and the condition variable it waits on
+type Writer = (Mutex<WriterState>, Condvar);
+
+/// The kind of a device, devices of all kinds share the same LED maps
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeviceKind {
+    Keyboard,
+
+    /// A headset, with a few LED zones
+    Headset,
+}
+
+impl DeviceKind {
+    /// Returns the grid of the LED map of a device of this kind, as the number of
+    /// columns and the number of keys per column. Devices without a grid of keys,
+    /// like headsets, return `None`
+    pub fn grid(&self) -> Option<(usize, usize)> {
+        match self {
+            DeviceKind::Keyboard => Some((NUM_COLUMNS, KEYS_PER_COLUMN)),
+            DeviceKind::Headset => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct RvDeviceState {
+    pub kind: DeviceKind,
+
     pub is_bound: bool,
     pub ctrl_hiddev_info: Option<hidapi::HidDeviceInfo>,
     pub led_hiddev_info: Option<hidapi::HidDeviceInfo>,
 
-    pub is_opened: bool,
+    /// The state of the device is shared by all clones, like the one of the output
+    /// sink of the devices, since any of them may close and re-open the device
+    is_opened: Arc<AtomicBool>,
     pub ctrl_hiddev: Arc<Mutex<Option<hidapi::HidDevice>>>,
     pub led_hiddev: Arc<Mutex<Option<hidapi::HidDevice>>>,
 
-    pub is_initialized: bool,
+    is_initialized: Arc<AtomicBool>,
+
+    /// Device information, queried during device initialization
+    pub device_info: Option<DeviceInfo>,
+
+    health: Arc<Mutex<DeviceHealth>>,
+
+    /// The LED map that has been written most recently, and the time of the write.
+    /// Unchanged frames are not written again, until the refresh interval elapsed.
+    /// Shared by all clones of the state, like the one of the writer thread
+    last_led_map: Arc<Mutex<Option<(Vec<RGBA>, Instant)>>>,
+
+    /// The hardware profile that has been written most recently. The profile is
+    /// kept in flash memory, so an unchanged profile is not written again
+    hardware_profile: Arc<Mutex<Option<Vec<u8>>>>,
+
+    pacing: Arc<Mutex<WritePacing>>,
+
+    /// Frames that are submitted by scripts, via `submit_led_map`
+    writer: Arc<Writer>,
+
+    /// The writer thread, once it has been spawned
+    writer_thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+}
+
+/// A change of the health of a device, as reported by `send_led_map_with_retry`
+#[derive(Debug, Clone)]
+pub enum HealthChange {
+    /// Frames could not be written to the device, even after retrying
+    Failed(String),
+
+    /// The device stopped responding, writes to it did not complete in time
+    Unresponsive,
+
+    /// The device works again, after it has failed
+    Recovered,
 }
 
 impl RvDeviceState {
     pub fn get_dev_id(&self) -> String {
         self.led_hiddev_info
-            .clone()
-            .unwrap()
-            .path
-            .to_str()
-            .unwrap()
-            .to_string()
+            .as_ref()
+            .map(|info| info.path.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the control and the LED interface of the device are opened
+    pub fn is_opened(&self) -> bool {
+        self.is_opened.load(Ordering::SeqCst)
     }
 
-    pub fn enumerate_devices(api: &hidapi::HidApi) -> Result<Self> {
+    /// Returns true if the initialization handshake has been completed
+    pub fn is_initialized(&self) -> bool {
+        self.is_initialized.load(Ordering::SeqCst)
+    }
+
+    /// Find all other processes that hold the control or LED interface of the device open
+    pub fn find_device_claims(&self) -> Vec<util::DeviceClaim> {
+        self.ctrl_hiddev_info
+            .iter()
+            .chain(self.led_hiddev_info.iter())
+            .flat_map(|info| util::find_device_claims(&*info.path.to_string_lossy()))
+            .fold(vec![], |mut result: Vec<util::DeviceClaim>, claim| {
+                if !result.iter().any(|c| c.pid == claim.pid) {
+                    result.push(claim);
+                }
+
+                result
+            })
+    }
+
+    /// Find all supported devices. The control and LED interfaces of a device
+    /// are paired by their serial number, or in the order of enumeration if
+    /// the device does not report a serial number
+    pub fn enumerate_devices(api: &hidapi::HidApi) -> Result<Vec<Self>> {
         trace!("Enumerating all available HID devices on the system...");
 
-        let mut found_led_dev = false;
-        let mut found_ctrl_dev = false;
+        let mut ctrl_devices = vec![];
+        let mut led_devices = vec![];
+        let mut headsets = vec![];
 
-        let mut ctrl_device = None;
-        let mut led_device = None;
+        // the protocol of the headsets has not been verified yet
+        let enable_headsets = crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|config| config.get_bool("global.enable_headsets").ok())
+            .unwrap_or(false);
 
         for device in api.devices() {
             trace!("{:#?}", device);
 
             if device.vendor_id == VENDOR_ID
+                && HEADSET_PRODUCT_ID.contains(&device.product_id)
+                && device.interface_number == HEADSET_INTERFACE
+            {
+                if !enable_headsets {
+                    info!(
+                        "Skipping headset, support is disabled by the configuration: {:?}",
+                        device.path
+                    );
+
+                    continue;
+                }
+
+                info!(
+                    "Found headset: {:?}: {}",
+                    device.path,
+                    device.product_string.as_deref().unwrap_or("<unknown>")
+                );
+
+                headsets.push(device);
+            } else if device.vendor_id == VENDOR_ID
                 && PRODUCT_ID.contains(&device.product_id)
                 && device.interface_number == CTRL_INTERFACE
             {
@@ -135,8 +583,7 @@ impl RvDeviceState {
                 });
                 let path = device.path.clone();
 
-                found_ctrl_dev = true;
-                ctrl_device = Some(device);
+                ctrl_devices.push(device);
 
                 info!("Found Control interface: {:?}: {}", path, product_string);
             } else if device.vendor_id == VENDOR_ID
@@ -149,33 +596,89 @@ impl RvDeviceState {
                 });
                 let path = device.path.clone();
 
-                found_led_dev = true;
-                led_device = Some(device);
+                led_devices.push(device);
 
                 info!("Found LED interface: {:?}: {}", path, product_string);
             }
         }
 
-        if !found_ctrl_dev || !found_led_dev {
+        let mut result = vec![];
+
+        for ctrl_device in ctrl_devices {
+            let index = led_devices
+                .iter()
+                .position(|led_device| {
+                    led_device.product_id == ctrl_device.product_id
+                        && led_device.serial_number == ctrl_device.serial_number
+                })
+                .or_else(|| {
+                    led_devices
+                        .iter()
+                        .position(|led_device| led_device.product_id == ctrl_device.product_id)
+                });
+
+            match index {
+                Some(index) => {
+                    let led_device = led_devices.remove(index);
+                    result.push(Self::bind(&ctrl_device, &led_device));
+                }
+
+                None => warn!(
+                    "No matching LED interface found for: {:?}",
+                    ctrl_device.path
+                ),
+            }
+        }
+
+        // headsets are numbered after the keyboards, a headset may also be the
+        // only supported device
+        result.extend(headsets.iter().map(|headset| Self::bind_headset(headset)));
+
+        if result.is_empty() {
             warn!("At least one required device could not be detected");
             Err(RvDeviceError::EnumerationError {})
         } else {
-            let device = Self::bind(&ctrl_device.unwrap(), &led_device.unwrap());
-            Ok(device)
+            info!("Found {} supported device(s)", result.len());
+            Ok(result)
         }
     }
 
     pub fn bind(ctrl_dev: &hidapi::HidDeviceInfo, led_dev: &hidapi::HidDeviceInfo) -> Self {
         RvDeviceState {
+            kind: DeviceKind::Keyboard,
+
             is_bound: true,
             ctrl_hiddev_info: Some(ctrl_dev.clone()),
             led_hiddev_info: Some(led_dev.clone()),
 
-            is_opened: false,
+            is_opened: Arc::new(AtomicBool::new(false)),
             ctrl_hiddev: Arc::new(Mutex::new(None)),
             led_hiddev: Arc::new(Mutex::new(None)),
 
-            is_initialized: false,
+            is_initialized: Arc::new(AtomicBool::new(false)),
+
+            device_info: None,
+
+            health: Arc::new(Mutex::new(DeviceHealth::default())),
+            last_led_map: Arc::new(Mutex::new(None)),
+            hardware_profile: Arc::new(Mutex::new(None)),
+
+            pacing: Arc::new(Mutex::new(WritePacing {
+                settle_time: Duration::from_millis(constants::DEVICE_SETTLE_MILLIS),
+                last_write: None,
+            })),
+
+            writer: Arc::new((Mutex::new(WriterState::default()), Condvar::new())),
+            writer_thread: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Bind the headset `dev`, the interface serves as the control and the LED device
+    pub fn bind_headset(dev: &hidapi::HidDeviceInfo) -> Self {
+        RvDeviceState {
+            kind: DeviceKind::Headset,
+
+            ..Self::bind(dev, dev)
         }
     }
 
@@ -187,19 +690,29 @@ impl RvDeviceState {
         } else {
             trace!("Opening control device...");
 
-            match self.ctrl_hiddev_info.clone().unwrap().open_device(&api) {
+            match self
+                .ctrl_hiddev_info
+                .as_ref()
+                .ok_or(RvDeviceError::DeviceNotBound {})?
+                .open_device(&api)
+            {
                 Ok(dev) => *self.ctrl_hiddev.lock() = Some(dev),
                 Err(_) => return Err(RvDeviceError::DeviceOpenError {}),
             }
 
             trace!("Opening LED device...");
 
-            match self.led_hiddev_info.clone().unwrap().open_device(&api) {
+            match self
+                .led_hiddev_info
+                .as_ref()
+                .ok_or(RvDeviceError::DeviceNotBound {})?
+                .open_device(&api)
+            {
                 Ok(dev) => *self.led_hiddev.lock() = Some(dev),
                 Err(_) => return Err(RvDeviceError::DeviceOpenError {}),
             }
 
-            self.is_opened = true;
+            self.is_opened.store(true, Ordering::SeqCst);
 
             Ok(())
         }
@@ -210,16 +723,18 @@ impl RvDeviceState {
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
         } else {
+            self.stop_writer();
+
             trace!("Closing control device...");
             *self.ctrl_hiddev.lock() = None;
 
             trace!("Closing LED device...");
             *self.led_hiddev.lock() = None;
 
-            self.is_opened = false;
+            self.is_opened.store(false, Ordering::SeqCst);
 
             Ok(())
         }
@@ -230,61 +745,104 @@ impl RvDeviceState {
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
         } else {
-            self.query_ctrl_report(0x0f)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.send_ctrl_report(0x15)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
-
-            self.send_ctrl_report(0x05)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
-
-            self.send_ctrl_report(0x07)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
-
-            self.send_ctrl_report(0x0a)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+            match self.query_device_info() {
+                Ok(device_info) => {
+                    info!(
+                        "Device: {}, firmware version: {}",
+                        device_info.model_name, device_info.firmware_version
+                    );
+
+                    self.device_info = Some(device_info);
+                }
 
-            self.send_ctrl_report(0x0b)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+                Err(e) => error!("Could not query device information: {}", e),
+            }
 
-            self.send_ctrl_report(0x06)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+            if self.kind == DeviceKind::Headset {
+                return self.send_headset_init_sequence();
+            }
 
-            self.send_ctrl_report(0x09)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+            let firmware_version = self
+                .device_info
+                .as_ref()
+                .and_then(|info| info.firmware_version.parse::<u8>().ok());
 
-            self.send_ctrl_report(0x0d)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+            let quirk = find_firmware_quirk(firmware_version);
+            debug!("Using init sequence: {}", quirk.description);
 
-            self.send_ctrl_report(0x13)
-                .unwrap_or_else(|e| error!("{}", e));
-            self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+            for id in quirk.init_sequence.iter() {
+                self.send_ctrl_report(*id)
+                    .unwrap_or_else(|e| error!("{}", e));
+                self.wait_for_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
+            }
 
             self.close_ctrl_dev().unwrap_or_else(|e| error!("{}", e));
 
-            self.is_initialized = true;
+            // the device forgets its LED state during initialization
+            *self.last_led_map.lock() = None;
+            self.is_initialized.store(true, Ordering::SeqCst);
 
             Ok(())
         }
     }
 
-    fn query_ctrl_report(&mut self, id: u8) -> Result<()> {
+    /// Query model name, firmware version, serial number and USB path of the device.
+    /// Requires the control device to be opened
+    pub fn query_device_info(&mut self) -> Result<DeviceInfo> {
+        trace!("Querying device information...");
+
+        // headsets don't report their firmware version
+        let firmware_version = match self.kind {
+            DeviceKind::Keyboard => {
+                let report = self.query_ctrl_report(0x0f)?;
+
+                report
+                    .get(2)
+                    .map(|v| format!("{}", v))
+                    .ok_or(RvDeviceError::InvalidResult {})?
+            }
+
+            DeviceKind::Headset => "<unknown>".into(),
+        };
+
+        let ctrl_info = self
+            .ctrl_hiddev_info
+            .as_ref()
+            .ok_or(RvDeviceError::DeviceNotBound {})?;
+
+        let model_name = ctrl_info
+            .product_string
+            .clone()
+            .unwrap_or_else(|| "<unknown>".into());
+
+        let serial_number = ctrl_info
+            .serial_number
+            .clone()
+            .unwrap_or_else(|| "<unknown>".into());
+
+        let usb_path = self
+            .led_hiddev_info
+            .as_ref()
+            .map(|info| info.path.to_string_lossy().to_string())
+            .ok_or(RvDeviceError::DeviceNotBound {})?;
+
+        Ok(DeviceInfo {
+            model_name,
+            firmware_version,
+            serial_number,
+            usb_path,
+        })
+    }
+
+    fn query_ctrl_report(&mut self, id: u8) -> Result<Vec<u8>> {
         trace!("Querying control device feature report");
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
         } else {
             match id {
@@ -293,13 +851,13 @@ impl RvDeviceState {
                     buf[0] = id;
 
                     let ctrl_dev = self.ctrl_hiddev.as_ref().lock();
-                    let ctrl_dev = ctrl_dev.as_ref().unwrap();
+                    let ctrl_dev = ctrl_dev.as_ref().ok_or(RvDeviceError::DeviceNotOpened {})?;
 
-                    match ctrl_dev.get_feature_report(&mut buf) {
-                        Ok(_result) => {
+                    match get_feature_report(ctrl_dev, &mut buf) {
+                        Ok(result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
-                            Ok(())
+                            Ok(buf[..result].to_vec())
                         }
 
                         Err(_) => Err(RvDeviceError::InvalidResult {}),
@@ -316,17 +874,17 @@ impl RvDeviceState {
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
         } else {
             let ctrl_dev = self.ctrl_hiddev.as_ref().lock();
-            let ctrl_dev = ctrl_dev.as_ref().unwrap();
+            let ctrl_dev = ctrl_dev.as_ref().ok_or(RvDeviceError::DeviceNotOpened {})?;
 
             match id {
                 0x15 => {
                     let buf: [u8; 3] = [0x15, 0x00, 0x01];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -340,7 +898,7 @@ impl RvDeviceState {
                 0x05 => {
                     let buf: [u8; 4] = [0x05, 0x04, 0x00, 0x04];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -363,7 +921,7 @@ impl RvDeviceState {
                         0x46, 0x00, 0x00, 0xfc, 0x00, 0x00, 0x48, 0x00, 0x00, 0xcd, 0x0e,
                     ];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -377,7 +935,7 @@ impl RvDeviceState {
                 0x0a => {
                     let buf: [u8; 8] = [0x0a, 0x08, 0x00, 0xff, 0xf1, 0x00, 0x02, 0x02];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -398,7 +956,7 @@ impl RvDeviceState {
                         0x05, 0x00, 0x00, 0xde, 0x01,
                     ];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -425,7 +983,7 @@ impl RvDeviceState {
                         0x24,
                     ];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -444,7 +1002,7 @@ impl RvDeviceState {
                         0x01, 0x00, 0x00, 0x00, 0x00, 0xcd, 0x04,
                     ];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -456,97 +1014,10 @@ impl RvDeviceState {
                 }
 
                 0x0d => {
-                    // hardware wave effect
-                    /* let mut buf: [u8; 443] = [
-                        0x0d, 0xbb, 0x01, 0x00, 0x0a, 0x04, 0x05, 0x45, 0x83, 0xca, 0xca, 0xca,
-                        0xca, 0xca, 0xca, 0xce, 0xce, 0xd2, 0xce, 0xce, 0xd2, 0x19, 0x19, 0x19,
-                        0x19, 0x19, 0x19, 0x23, 0x23, 0x2d, 0x23, 0x23, 0x2d, 0xe0, 0xe0, 0xe0,
-                        0xe0, 0xe0, 0xe0, 0xe3, 0xe3, 0xe6, 0xe3, 0xe3, 0xe6, 0xd2, 0xd2, 0xd5,
-                        0xd2, 0xd2, 0xd5, 0xd5, 0xd5, 0xd9, 0xd5, 0x00, 0xd9, 0x2d, 0x2d, 0x36,
-                        0x2d, 0x2d, 0x36, 0x36, 0x36, 0x40, 0x36, 0x00, 0x40, 0xe6, 0xe6, 0xe9,
-                        0xe6, 0xe6, 0xe9, 0xe9, 0xe9, 0xec, 0xe9, 0x00, 0xec, 0xd9, 0xd9, 0xdd,
-                        0xd9, 0xdd, 0xdd, 0xe0, 0xe0, 0xdd, 0xe0, 0xe4, 0xe4, 0x40, 0x40, 0x4a,
-                        0x40, 0x4a, 0x4a, 0x53, 0x53, 0x4a, 0x53, 0x5d, 0x5d, 0xec, 0xec, 0xef,
-                        0xec, 0xef, 0xef, 0xf2, 0xf2, 0xef, 0xf2, 0xf5, 0xf5, 0xe4, 0xe4, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0x5d, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf5, 0xf5, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe4, 0xe4, 0xe8,
-                        0xe8, 0xe8, 0xe8, 0xe8, 0xeb, 0xeb, 0xeb, 0x00, 0xeb, 0x5d, 0x5d, 0x67,
-                        0x67, 0x67, 0x67, 0x67, 0x70, 0x70, 0x70, 0x00, 0x70, 0xf5, 0xf5, 0xf8,
-                        0xf8, 0xf8, 0xf8, 0xf8, 0xfb, 0xfb, 0xfb, 0x00, 0xfb, 0xeb, 0xef, 0xef,
-                        0xef, 0x00, 0xef, 0xf0, 0xf0, 0xed, 0xf0, 0xf0, 0x00, 0x70, 0x7a, 0x7a,
-                        0x7a, 0x00, 0x7a, 0x7a, 0x7a, 0x6f, 0x7a, 0x7a, 0x00, 0xfb, 0xfd, 0xfd,
-                        0xfd, 0x00, 0xfd, 0xf8, 0xf8, 0xea, 0xf8, 0xf8, 0x00, 0xed, 0xed, 0xea,
-                        0xed, 0xed, 0x00, 0xed, 0xea, 0xea, 0xf6, 0xe7, 0xea, 0x6f, 0x6f, 0x65,
-                        0x6f, 0x6f, 0x00, 0x6f, 0x65, 0x65, 0x66, 0x5a, 0x65, 0xea, 0xea, 0xdc,
-                        0xea, 0xea, 0x00, 0xea, 0xdc, 0xdc, 0x00, 0xce, 0xdc, 0xea, 0xe7, 0xe5,
-                        0xe7, 0xe5, 0xe5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x65, 0x5a, 0x50,
-                        0x5a, 0x50, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdc, 0xce, 0xc0,
-                        0xce, 0xc0, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe7, 0x00, 0x00,
-                        0xe2, 0xe2, 0xe2, 0xe2, 0xdf, 0xdf, 0xdf, 0xdf, 0xdf, 0x5a, 0x00, 0x00,
-                        0x45, 0x45, 0x45, 0x45, 0x3b, 0x3b, 0x3b, 0x3b, 0x3b, 0xce, 0x00, 0x00,
-                        0xb2, 0xb2, 0xb2, 0xb2, 0xa4, 0xa4, 0xa4, 0xa4, 0xa4, 0xdc, 0xdc, 0xdc,
-                        0xdc, 0x00, 0xda, 0xda, 0xda, 0xda, 0xda, 0x00, 0xd7, 0x30, 0x30, 0x30,
-                        0x30, 0x00, 0x26, 0x26, 0x26, 0x26, 0x26, 0x00, 0x1c, 0x96, 0x96, 0x96,
-                        0x96, 0x00, 0x88, 0x88, 0x88, 0x88, 0x88, 0x00, 0x7a, 0xd7, 0xd7, 0xd7,
-                        0x00, 0xd4, 0xd4, 0xd4, 0xd4, 0xd4, 0xd1, 0xd1, 0xd1, 0x1c, 0x1c, 0x1c,
-                        0x00, 0x11, 0x11, 0x11, 0x11, 0x11, 0x06, 0x06, 0x06, 0x7a, 0x7a, 0x7a,
-                        0x00, 0x6c, 0x6c, 0x6c, 0x6c, 0x6c, 0x5e, 0x5e, 0x5e, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0xcf,
-                    ];
-
-                    // byte 5   == 01-slow 06-med 0b-fast
-                    // byte 441 == 1e-slow 23-med 28-fast
-
-                    buf[5] = 0x06;
-                    buf[441] = 0x23;
-
-                    */
-
                     // custom effects
-                    let buf: [u8; 443] = [
-                        0x0d, 0xbb, 0x01, 0x00, 0x06, 0x0b, 0x05, 0x45, 0x83, 0xca, 0xca, 0xca,
-                        0xca, 0xca, 0xca, 0xce, 0xce, 0xd2, 0xce, 0xce, 0xd2, 0x19, 0x19, 0x19,
-                        0x19, 0x19, 0x19, 0x23, 0x23, 0x2d, 0x23, 0x23, 0x2d, 0xe0, 0xe0, 0xe0,
-                        0xe0, 0xe0, 0xe0, 0xe3, 0xe3, 0xe6, 0xe3, 0xe3, 0xe6, 0xd2, 0xd2, 0xd5,
-                        0xd2, 0xd2, 0xd5, 0xd5, 0xd5, 0xd9, 0xd5, 0x00, 0xd9, 0x2d, 0x2d, 0x36,
-                        0x2d, 0x2d, 0x36, 0x36, 0x36, 0x40, 0x36, 0x00, 0x40, 0xe6, 0xe6, 0xe9,
-                        0xe6, 0xe6, 0xe9, 0xe9, 0xe9, 0xec, 0xe9, 0x00, 0xec, 0xd9, 0xd9, 0xdd,
-                        0xd9, 0xdd, 0xdd, 0xe0, 0xe0, 0xdd, 0xe0, 0xe4, 0xe4, 0x40, 0x40, 0x4a,
-                        0x40, 0x4a, 0x4a, 0x53, 0x53, 0x4a, 0x53, 0x5d, 0x5d, 0xec, 0xec, 0xef,
-                        0xec, 0xef, 0xef, 0xf2, 0xf2, 0xef, 0xf2, 0xf5, 0xf5, 0xe4, 0xe4, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0x5d, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf5, 0xf5, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe4, 0xe4, 0xe8,
-                        0xe8, 0xe8, 0xe8, 0xe8, 0xeb, 0xeb, 0xeb, 0x00, 0xeb, 0x5d, 0x5d, 0x67,
-                        0x67, 0x67, 0x67, 0x67, 0x70, 0x70, 0x70, 0x00, 0x70, 0xf5, 0xf5, 0xf8,
-                        0xf8, 0xf8, 0xf8, 0xf8, 0xfb, 0xfb, 0xfb, 0x00, 0xfb, 0xeb, 0xef, 0xef,
-                        0xef, 0x00, 0xef, 0xf0, 0xf0, 0xed, 0xf0, 0xf0, 0x00, 0x70, 0x7a, 0x7a,
-                        0x7a, 0x00, 0x7a, 0x7a, 0x7a, 0x6f, 0x7a, 0x7a, 0x00, 0xfb, 0xfd, 0xfd,
-                        0xfd, 0x00, 0xfd, 0xf8, 0xf8, 0xea, 0xf8, 0xf8, 0x00, 0xed, 0xed, 0xea,
-                        0xed, 0xed, 0x00, 0xed, 0xea, 0xea, 0xf6, 0xe7, 0xea, 0x6f, 0x6f, 0x65,
-                        0x6f, 0x6f, 0x00, 0x6f, 0x65, 0x65, 0x66, 0x5a, 0x65, 0xea, 0xea, 0xdc,
-                        0xea, 0xea, 0x00, 0xea, 0xdc, 0xdc, 0x00, 0xce, 0xdc, 0xea, 0xe7, 0xe5,
-                        0xe7, 0xe5, 0xe5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x65, 0x5a, 0x50,
-                        0x5a, 0x50, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdc, 0xce, 0xc0,
-                        0xce, 0xc0, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe7, 0x00, 0x00,
-                        0xe2, 0xe2, 0xe2, 0xe2, 0xdf, 0xdf, 0xdf, 0xdf, 0xdf, 0x5a, 0x00, 0x00,
-                        0x45, 0x45, 0x45, 0x45, 0x3b, 0x3b, 0x3b, 0x3b, 0x3b, 0xce, 0x00, 0x00,
-                        0xb2, 0xb2, 0xb2, 0xb2, 0xa4, 0xa4, 0xa4, 0xa4, 0xa4, 0xdc, 0xdc, 0xdc,
-                        0xdc, 0x00, 0xda, 0xda, 0xda, 0xda, 0xda, 0x00, 0xd7, 0x30, 0x30, 0x30,
-                        0x30, 0x00, 0x26, 0x26, 0x26, 0x26, 0x26, 0x00, 0x1c, 0x96, 0x96, 0x96,
-                        0x96, 0x00, 0x88, 0x88, 0x88, 0x88, 0x88, 0x00, 0x7a, 0xd7, 0xd7, 0xd7,
-                        0x00, 0xd4, 0xd4, 0xd4, 0xd4, 0xd4, 0xd1, 0xd1, 0xd1, 0x1c, 0x1c, 0x1c,
-                        0x00, 0x11, 0x11, 0x11, 0x11, 0x11, 0x06, 0x06, 0x06, 0x7a, 0x7a, 0x7a,
-                        0x00, 0x6c, 0x6c, 0x6c, 0x6c, 0x6c, 0x5e, 0x5e, 0x5e, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0xcf,
-                    ];
+                    let buf = CUSTOM_EFFECTS_REPORT;
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -564,7 +1035,7 @@ impl RvDeviceState {
                     // custom effects
                     let buf: [u8; 8] = [0x13, 0x08, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
 
-                    match ctrl_dev.send_feature_report(&buf) {
+                    match send_feature_report(ctrl_dev, &buf) {
                         Ok(_result) => {
                             hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -585,19 +1056,27 @@ impl RvDeviceState {
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
         } else {
+            let deadline =
+                Instant::now() + Duration::from_millis(constants::DEVICE_RESPONSE_TIMEOUT_MILLIS);
+
             loop {
+                if Instant::now() >= deadline {
+                    warn!("The control device did not respond in time");
+                    return Err(RvDeviceError::Timeout {});
+                }
+
                 thread::sleep(time::Duration::from_millis(150));
 
                 let mut buf: [u8; 4] = [0; 4];
                 buf[0] = 0x04;
 
                 let ctrl_dev = self.ctrl_hiddev.as_ref().lock();
-                let ctrl_dev = ctrl_dev.as_ref().unwrap();
+                let ctrl_dev = ctrl_dev.as_ref().ok_or(RvDeviceError::DeviceNotOpened {})?;
 
-                match ctrl_dev.get_feature_report(&mut buf) {
+                match get_feature_report(ctrl_dev, &mut buf) {
                     Ok(_result) => {
                         hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
@@ -617,7 +1096,7 @@ impl RvDeviceState {
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
         } else {
             *self.ctrl_hiddev.lock() = None;
@@ -629,79 +1108,469 @@ impl RvDeviceState {
     pub fn send_led_map(&mut self, led_map: &[RGBA]) -> Result<()> {
         trace!("Setting LEDs from supplied map...");
 
+        // the state of the LEDs is unknown, until the frame has been written completely
+        *self.last_led_map.lock() = None;
+
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
-        } else if !self.is_initialized {
+        } else if !self.is_initialized() {
             Err(RvDeviceError::DeviceNotInitialized {})
         } else {
+            // give the device time to process the previous frame, without holding
+            // the lock of the LED device
+            self.wait_for_settle_time();
+
             match &*self.led_hiddev.as_ref().lock() {
-                Some(led_dev) => {
-                    let mut hwmap: [u8; 444] = [0; 444];
+                Some(led_dev) => self.write_led_map(led_dev, led_map),
+                None => Err(RvDeviceError::DeviceNotOpened {}),
+            }
+        }
+    }
 
-                    // Colors are in blocks of 12 keys (2 columns). Color parts are sorted by color e.g. the red
-                    // values for all 12 keys are first then come the green values etc.
-                    for (i, color) in led_map.iter().enumerate() {
-                        let offset = ((i / 12) * 36) + (i % 12);
+    /// Write the LED map `led_map` like `send_led_map`, but fail with `DeviceBusy`
+    /// instead of blocking if the LED device is locked, e.g. by a thread that
+    /// panicked while writing to it. The lock is held until the write completed
+    pub fn try_send_led_map(&mut self, led_map: &[RGBA]) -> Result<()> {
+        *self.last_led_map.lock() = None;
 
-                        hwmap[offset] = color.r;
-                        hwmap[offset + 12] = color.g;
-                        hwmap[offset + 24] = color.b;
-                    }
+        if !self.is_bound {
+            Err(RvDeviceError::DeviceNotBound {})
+        } else if !self.is_opened() {
+            Err(RvDeviceError::DeviceNotOpened {})
+        } else if !self.is_initialized() {
+            Err(RvDeviceError::DeviceNotInitialized {})
+        } else {
+            self.wait_for_settle_time();
+
+            match self.led_hiddev.try_lock() {
+                Some(guard) => match &*guard {
+                    Some(led_dev) => self.write_led_map(led_dev, led_map),
+                    None => Err(RvDeviceError::DeviceNotOpened {}),
+                },
+
+                None => Err(RvDeviceError::DeviceBusy {}),
+            }
+        }
+    }
 
-                    let (slice, hwmap) = hwmap.split_at(60);
+    /// Write the LED map `led_map` to the LED device `led_dev`, that has been
+    /// locked by the caller
+    fn write_led_map(&self, led_dev: &hidapi::HidDevice, led_map: &[RGBA]) -> Result<()> {
+        if self.kind == DeviceKind::Headset {
+            return self.write_headset_led_map(led_dev, led_map);
+        }
 
-                    let mut buf: [u8; 65] = [0; 65];
-                    buf[1..5].copy_from_slice(&[0xa1, 0x01, 0x01, 0xb4]);
-                    buf[5..65].copy_from_slice(&slice);
+        let mut hwmap: [u8; 444] = [0; 444];
 
-                    hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
+        // Colors are in blocks of 12 keys (2 columns). Color parts are sorted by color e.g. the red
+        // values for all 12 keys are first then come the green values etc.
+        for (i, color) in led_map.iter().enumerate() {
+            let offset = ((i / 12) * 36) + (i % 12);
 
-                    match led_dev.write(&buf) {
-                        Ok(len) => {
-                            trace!("Wrote: {} bytes", len);
-                            if len < 65 {
-                                return Err(RvDeviceError::WriteError {});
-                            }
-                        }
+            hwmap[offset] = color.r;
+            hwmap[offset + 12] = color.g;
+            hwmap[offset + 24] = color.b;
+        }
 
-                        Err(_) => return Err(RvDeviceError::WriteError {}),
-                    }
+        let (slice, hwmap) = hwmap.split_at(60);
 
-                    for bytes in hwmap.chunks(64) {
-                        buf[1..65].copy_from_slice(bytes);
+        // hidapi does not support timeouts on writes, so measure the time it
+        // takes to write the frame, to detect a device that stopped responding
+        let start = Instant::now();
 
-                        hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
+        let mut buf: [u8; 65] = [0; 65];
+        buf[1..5].copy_from_slice(&[0xa1, 0x01, 0x01, 0xb4]);
+        buf[5..65].copy_from_slice(&slice);
 
-                        match led_dev.write(&buf) {
-                            Ok(len) => {
-                                trace!("Wrote: {} bytes", len);
-                                if len < 65 {
-                                    return Err(RvDeviceError::WriteError {});
-                                }
-                            }
+        hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
-                            Err(_) => return Err(RvDeviceError::WriteError {}),
-                        }
+        match write_report(led_dev, &buf) {
+            Ok(len) => {
+                trace!("Wrote: {} bytes", len);
+                if len < 65 {
+                    return Err(RvDeviceError::WriteError {});
+                }
+            }
+
+            Err(_) => return Err(RvDeviceError::WriteError {}),
+        }
+
+        for bytes in hwmap.chunks(64) {
+            buf[1..65].copy_from_slice(bytes);
+
+            hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
+
+            match write_report(led_dev, &buf) {
+                Ok(len) => {
+                    trace!("Wrote: {} bytes", len);
+                    if len < 65 {
+                        return Err(RvDeviceError::WriteError {});
                     }
+                }
+
+                Err(_) => return Err(RvDeviceError::WriteError {}),
+            }
+        }
+
+        self.pacing.lock().last_write = Some(Instant::now());
+
+        if start.elapsed() > Duration::from_millis(constants::DEVICE_WRITE_TIMEOUT_MILLIS) {
+            return Err(RvDeviceError::Timeout {});
+        }
+
+        *self.last_led_map.lock() = Some((led_map.to_vec(), Instant::now()));
+
+        Ok(())
+    }
+
+    /// Take the LEDs of the headset under software control, or return them to
+    /// the built-in effect of the headset
+    fn set_headset_software_control(&self, enabled: bool) -> Result<()> {
+        match &*self.led_hiddev.as_ref().lock() {
+            Some(led_dev) => {
+                // byte 1 selects the control command, byte 2 enables software control
+                let mut buf: [u8; 16] = [0; 16];
+                buf[0] = HEADSET_LED_REPORT_ID;
+                buf[1] = 0x01;
+                buf[2] = if enabled { 0x01 } else { 0x00 };
+
+                write_report(led_dev, &buf)
+                    .map(|_| ())
+                    .map_err(|_e| RvDeviceError::WriteError {})
+            }
+
+            None => Err(RvDeviceError::DeviceNotOpened {}),
+        }
+    }
+
+    fn send_headset_init_sequence(&mut self) -> Result<()> {
+        self.set_headset_software_control(true)?;
+
+        *self.last_led_map.lock() = None;
+        self.is_initialized.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Write the zones of the headset, that are taken from the first entries of the
+    /// LED map `led_map`
+    fn write_headset_led_map(&self, led_dev: &hidapi::HidDevice, led_map: &[RGBA]) -> Result<()> {
+        // byte 1 selects the LED command, the colors of the zones follow
+        // as RGB triplets from byte 3 on
+        let mut buf: [u8; 16] = [0; 16];
+        buf[0] = HEADSET_LED_REPORT_ID;
+        buf[1] = 0x04;
+        buf[2] = NUM_HEADSET_ZONES as u8;
+
+        for (i, color) in led_map.iter().take(NUM_HEADSET_ZONES).enumerate() {
+            buf[3 + i * 3..6 + i * 3].copy_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
 
-                    Ok(())
+        match write_report(led_dev, &buf) {
+            Ok(len) if len >= buf.len() => (),
+            _ => return Err(RvDeviceError::WriteError {}),
+        }
+
+        self.pacing.lock().last_write = Some(Instant::now());
+
+        *self.last_led_map.lock() = Some((led_map.to_vec(), Instant::now()));
+
+        Ok(())
+    }
+
+    /// Submit the LED map `led_map` to the writer thread of the device, that writes
+    /// it as soon as the settle time of the previous write elapsed. Used by scripts
+    /// that set the LEDs directly, so that they are never blocked by the device
+    pub fn submit_led_map(&self, led_map: &[RGBA]) -> Result<()> {
+        let (state, condvar) = &*self.writer;
+
+        let mut writer_thread = self.writer_thread.lock();
+
+        if writer_thread.is_none() {
+            // the writer may have been stopped before
+            state.lock().shutdown = false;
+
+            let mut writer = self.clone();
+
+            *writer_thread = Some(
+                thread::Builder::new()
+                    .name("led-writer".into())
+                    .spawn(move || writer.run_writer())
+                    .map_err(|_e| RvDeviceError::ThreadSpawnError {})?,
+            );
+        }
+
+        state.lock().pending_frame = Some(led_map.to_vec());
+        condvar.notify_one();
+
+        Ok(())
+    }
+
+    /// Stop the writer thread of the device, if it is running, and wait for it to
+    /// exit. A pending frame is discarded
+    pub fn stop_writer(&self) {
+        let (state, condvar) = &*self.writer;
+
+        if let Some(writer_thread) = self.writer_thread.lock().take() {
+            {
+                let mut state = state.lock();
+
+                state.shutdown = true;
+                state.pending_frame = None;
+            }
+
+            condvar.notify_all();
+
+            writer_thread
+                .join()
+                .unwrap_or_else(|_e| error!("The LED writer thread panicked"));
+        }
+    }
+
+    fn run_writer(&mut self) {
+        loop {
+            let led_map = {
+                let (state, condvar) = &*self.writer;
+                let mut state = state.lock();
+
+                while state.pending_frame.is_none() && !state.shutdown {
+                    condvar.wait(&mut state);
                 }
 
-                None => Err(RvDeviceError::DeviceNotOpened {}),
+                if state.shutdown {
+                    break;
+                }
+
+                state.pending_frame.take().unwrap()
+            };
+
+            let result = self.send_led_map(&led_map);
+
+            // the device is re-opened by the main loop, that holds the HID API, so
+            // a change of the health is passed on to it
+            let (change, _reopen) = self.update_health(&result, false);
+
+            if change.is_some() {
+                self.health.lock().pending_change = change;
             }
         }
     }
 
+    /// Sleep until the settle time of the most recent write elapsed. The deadline
+    /// is computed while holding the lock, but it is released before sleeping, so
+    /// that the clones of the device are not blocked
+    fn wait_for_settle_time(&self) {
+        let remaining = {
+            let pacing = self.pacing.lock();
+
+            pacing
+                .last_write
+                .and_then(|last_write| pacing.settle_time.checked_sub(last_write.elapsed()))
+        };
+
+        if let Some(remaining) = remaining {
+            thread::sleep(remaining);
+        }
+    }
+
+    /// Get the min. interval between two writes to the LED device
+    pub fn get_settle_time(&self) -> Duration {
+        self.pacing.lock().settle_time
+    }
+
+    /// Set the min. interval between two writes to the LED device
+    pub fn set_settle_time(&self, settle_time: Duration) {
+        self.pacing.lock().settle_time = settle_time;
+    }
+
+    /// Measure the min. reliable interval between two writes to the LED device, and
+    /// use it as the settle time. Black frames are written with each of the candidate
+    /// intervals, in ascending order. An interval is reliable, if no write fails and
+    /// no write takes considerably longer than with the default settle time, since
+    /// a device that is still busy delays the next report
+    pub fn calibrate_settle_time(&mut self) -> Result<Duration> {
+        let black = [RGBA {
+            r: 0x00,
+            g: 0x00,
+            b: 0x00,
+            a: 0x00,
+        }; NUM_KEYS];
+
+        let default = Duration::from_millis(constants::DEVICE_SETTLE_MILLIS);
+
+        let result = select_settle_time(
+            default,
+            constants::DEVICE_SETTLE_CANDIDATES_MILLIS,
+            |settle_time| {
+                self.set_settle_time(settle_time);
+
+                let mut slowest = Duration::from_millis(0);
+
+                for _ in 0..constants::DEVICE_CALIBRATION_FRAMES {
+                    // the pacing is part of the write, it must not be measured
+                    self.wait_for_settle_time();
+
+                    let start = Instant::now();
+                    self.send_led_map(&black)?;
+
+                    slowest = slowest.max(start.elapsed());
+                }
+
+                Ok(slowest)
+            },
+        );
+
+        let settle_time = *result.as_ref().unwrap_or(&default);
+        self.set_settle_time(settle_time);
+
+        result
+    }
+
+    /// Returns true if `led_map` equals the frame that has been written most recently,
+    /// and the refresh interval did not elapse yet. The protocol of the device has no
+    /// partial updates, a frame is either written completely or skipped
+    fn is_unchanged(&self, led_map: &[RGBA]) -> bool {
+        self.last_led_map
+            .lock()
+            .as_ref()
+            .map_or(false, |(last, since)| {
+                last.as_slice() == led_map
+                    && since.elapsed()
+                        < Duration::from_millis(constants::DEVICE_REFRESH_INTERVAL_MILLIS)
+            })
+    }
+
+    /// Send the LED map `led_map` to the device, and retry up to DEVICE_WRITE_ATTEMPTS
+    /// times on errors. If the device keeps failing, it is closed and re-opened.
+    /// Frames that did not change are skipped, but written periodically, so that a
+    /// failing device is still detected. Returns the change of the health of the
+    /// device, if any
+    pub fn send_led_map_with_retry(
+        &mut self,
+        api: &hidapi::HidApi,
+        led_map: &[RGBA],
+    ) -> Option<HealthChange> {
+        if self.is_unchanged(led_map) {
+            trace!("Skipping an unchanged frame");
+            return None;
+        }
+
+        let mut result = self.send_led_map(led_map);
+
+        for attempt in 1..constants::DEVICE_WRITE_ATTEMPTS {
+            if result.is_ok() {
+                break;
+            }
+
+            // don't retry on timeouts, that would only stall the main loop even longer
+            if let Err(RvDeviceError::Timeout {}) = result {
+                break;
+            }
+
+            trace!("Retrying to write the LED map, attempt: {}", attempt + 1);
+            result = self.send_led_map(led_map);
+        }
+
+        let (change, reopen) = self.update_health(&result, true);
+
+        // the lock is not held while re-opening, that may take a while
+        if reopen {
+            self.reopen(api)
+                .unwrap_or_else(|e| warn!("Could not re-open the device: {}", e));
+        }
+
+        let pending_change = self.health.lock().pending_change.take();
+
+        change.or(pending_change)
+    }
+
+    /// Record the result `result` of a write of a frame in the health of the device,
+    /// that is shared by all handles of the device. Returns the change of the health,
+    /// if any, and whether the device is due to be re-opened. Callers that can not
+    /// re-open the device pass `false` as `can_reopen`
+    fn update_health(&self, result: &Result<()>, can_reopen: bool) -> (Option<HealthChange>, bool) {
+        let mut health = self.health.lock();
+
+        match result {
+            Ok(()) => {
+                health.failed_frames = 0;
+
+                if health.is_failed {
+                    info!("Device {} recovered", self.get_dev_id());
+
+                    health.is_failed = false;
+                    (Some(HealthChange::Recovered), false)
+                } else {
+                    (None, false)
+                }
+            }
+
+            Err(e) => {
+                health.failed_frames = health.failed_frames.saturating_add(1);
+
+                let reopen_due = health.last_reopen.map_or(true, |t| {
+                    t.elapsed() >= Duration::from_millis(constants::DEVICE_REOPEN_INTERVAL_MILLIS)
+                });
+
+                let reopen = can_reopen
+                    && health.failed_frames >= constants::DEVICE_REOPEN_THRESHOLD
+                    && reopen_due;
+
+                if reopen {
+                    health.last_reopen = Some(Instant::now());
+                }
+
+                let change = if !health.is_failed {
+                    error!(
+                        "Could not send the LED map to device {}: {}",
+                        self.get_dev_id(),
+                        e
+                    );
+
+                    health.is_failed = true;
+
+                    if let RvDeviceError::Timeout {} = e {
+                        Some(HealthChange::Unresponsive)
+                    } else {
+                        Some(HealthChange::Failed(format!("{}", e)))
+                    }
+                } else {
+                    None
+                };
+
+                (change, reopen)
+            }
+        }
+    }
+
+    /// Close the device, open it again and repeat the initialization handshake
+    pub fn reopen(&mut self, api: &hidapi::HidApi) -> Result<()> {
+        info!("Re-opening device {}...", self.get_dev_id());
+
+        if self.is_opened() {
+            self.close_all()?;
+        }
+
+        self.is_initialized.store(false, Ordering::SeqCst);
+
+        self.open(api)?;
+        self.send_init_sequence()?;
+
+        Ok(())
+    }
+
     pub fn set_led_init_pattern(&mut self) -> Result<()> {
         trace!("Setting LED init pattern...");
 
         if !self.is_bound {
             Err(RvDeviceError::DeviceNotBound {})
-        } else if !self.is_opened {
+        } else if !self.is_opened() {
             Err(RvDeviceError::DeviceNotOpened {})
-        } else if !self.is_initialized {
+        } else if !self.is_initialized() {
             Err(RvDeviceError::DeviceNotInitialized {})
         } else {
             let led_map: [RGBA; NUM_KEYS] = [RGBA {
@@ -718,14 +1587,259 @@ impl RvDeviceState {
         }
     }
 
+    /// Open an additional handle to the control interface, that is used to
+    /// read the HID reports of the special keys, like FN and Easy Shift
+    pub fn open_special_keys_device(&self, api: &hidapi::HidApi) -> Result<hidapi::HidDevice> {
+        trace!("Opening control device for special keys...");
+
+        if !self.is_bound {
+            Err(RvDeviceError::DeviceNotBound {})
+        } else if self.kind == DeviceKind::Headset {
+            Err(RvDeviceError::NotSupported {})
+        } else {
+            self.ctrl_hiddev_info
+                .as_ref()
+                .ok_or(RvDeviceError::DeviceNotBound {})?
+                .open_device(&api)
+                .map_err(|_| RvDeviceError::DeviceOpenError {})
+        }
+    }
+
+    /// Switch the device back to its built-in hardware effect. The control
+    /// device has been closed after initialization, so it is re-opened here
+    pub fn set_hardware_effect_mode(&mut self, api: &hidapi::HidApi) -> Result<()> {
+        trace!("Switching to hardware effect mode...");
+
+        if self.kind == DeviceKind::Headset {
+            return self.set_headset_software_control(false);
+        }
+
+        // byte 4 selects the effect (0x0a: wave), byte 5 is the speed of the
+        // effect (01-slow 06-med 0b-fast)
+        let mut buf = CUSTOM_EFFECTS_REPORT;
+        buf[4] = 0x0a;
+        buf[5] = 0x06;
+
+        self.write_hardware_profile(api, buf)
+    }
+
+    /// Open the control device for a single request. The control device is closed
+    /// after the init sequence, so requests that are not part of it open it anew
+    fn open_ctrl_dev(&self, api: &hidapi::HidApi) -> Result<hidapi::HidDevice> {
+        if !self.is_bound {
+            return Err(RvDeviceError::DeviceNotBound {});
+        }
+
+        self.ctrl_hiddev_info
+            .as_ref()
+            .ok_or(RvDeviceError::DeviceNotBound {})?
+            .open_device(&api)
+            .map_err(|_e| RvDeviceError::DeviceOpenError {})
+    }
+
+    /// Send the raw feature report `buf` to the control device, the first byte is
+    /// the report id
+    pub fn send_raw_feature_report(&self, api: &hidapi::HidApi, buf: &[u8]) -> Result<()> {
+        let ctrl_dev = self.open_ctrl_dev(api)?;
+
+        hexdump::hexdump_iter(buf).for_each(|s| trace!("  {}", s));
+
+        send_feature_report(&ctrl_dev, buf).map_err(|_e| RvDeviceError::WriteError {})
+    }
+
+    /// Get the raw feature report `id` of the control device, `len` is the size of
+    /// the report including the report id
+    pub fn get_raw_feature_report(
+        &self,
+        api: &hidapi::HidApi,
+        id: u8,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let ctrl_dev = self.open_ctrl_dev(api)?;
+
+        let mut buf = vec![0; len];
+        buf[0] = id;
+
+        match get_feature_report(&ctrl_dev, &mut buf) {
+            Ok(result) => {
+                hexdump::hexdump_iter(&buf).for_each(|s| trace!("  {}", s));
+
+                buf.truncate(result);
+                Ok(buf)
+            }
+
+            Err(_) => Err(RvDeviceError::InvalidResult {}),
+        }
+    }
+
+    /// Store the static color `color` in the hardware profile of the device, and
+    /// switch to it. The device shows the color while the host does not send
+    /// frames, e.g. while the system is suspended
+    pub fn set_stored_color(&mut self, api: &hidapi::HidApi, color: RGBA) -> Result<()> {
+        trace!("Storing a static color in the hardware profile...");
+
+        // headsets have no hardware profile, they keep the color that has been set
+        if self.kind == DeviceKind::Headset {
+            return self.send_led_map(&[color; NUM_KEYS]);
+        }
+
+        // byte 4 selects the effect (0x01: static), the colors of the keys follow
+        // the header from byte 9 on, laid out like the LED map
+        let mut buf = CUSTOM_EFFECTS_REPORT;
+        buf[4] = 0x01;
+
+        for i in 0..NUM_KEYS {
+            let offset = 9 + ((i / 12) * 36) + (i % 12);
+
+            buf[offset] = color.r;
+            buf[offset + 12] = color.g;
+            buf[offset + 24] = color.b;
+        }
+
+        self.write_hardware_profile(api, buf)
+    }
+
+    /// Write the hardware profile `buf` (feature report 0x0d) to the device, and
+    /// switch from direct mode to the hardware profile. The profile is only written
+    /// if it differs from the one that has been written most recently, to spare
+    /// the flash memory of the device
+    fn write_hardware_profile(&mut self, api: &hidapi::HidApi, mut buf: [u8; 443]) -> Result<()> {
+        if !self.is_bound {
+            Err(RvDeviceError::DeviceNotBound {})
+        } else if !self.is_opened() {
+            Err(RvDeviceError::DeviceNotOpened {})
+        } else {
+            match self
+                .ctrl_hiddev_info
+                .as_ref()
+                .ok_or(RvDeviceError::DeviceNotBound {})?
+                .open_device(&api)
+            {
+                Ok(dev) => *self.ctrl_hiddev.lock() = Some(dev),
+                Err(_) => return Err(RvDeviceError::DeviceOpenError {}),
+            }
+
+            // the last two bytes hold the sum of all preceding bytes
+            let checksum: u16 = buf[..441].iter().map(|b| u16::from(*b)).sum();
+            buf[441..443].copy_from_slice(&checksum.to_le_bytes());
+
+            let is_written = self.hardware_profile.lock().as_deref() == Some(&buf[..]);
+
+            if is_written {
+                trace!("The hardware profile is unchanged, not writing it again");
+            } else {
+                {
+                    let ctrl_dev = self.ctrl_hiddev.as_ref().lock();
+                    let ctrl_dev = ctrl_dev.as_ref().ok_or(RvDeviceError::DeviceNotOpened {})?;
+
+                    // a partially written profile is unknown
+                    *self.hardware_profile.lock() = None;
+
+                    send_feature_report(ctrl_dev, &buf)
+                        .map_err(|_| RvDeviceError::InvalidResult {})?;
+                }
+
+                self.wait_for_ctrl_dev()?;
+
+                *self.hardware_profile.lock() = Some(buf.to_vec());
+            }
+
+            {
+                let ctrl_dev = self.ctrl_hiddev.as_ref().lock();
+                let ctrl_dev = ctrl_dev.as_ref().ok_or(RvDeviceError::DeviceNotOpened {})?;
+
+                let buf: [u8; 8] = [0x13, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+                send_feature_report(ctrl_dev, &buf).map_err(|_| RvDeviceError::InvalidResult {})?;
+            }
+
+            self.wait_for_ctrl_dev()?;
+            self.close_ctrl_dev()?;
+
+            Ok(())
+        }
+    }
+
+    /// Leave the LEDs in the state `exit_state`. Switching back to the
+    /// hardware effect requires `api`, if it is not available the LEDs
+    /// will be turned off instead
+    pub fn restore_exit_state(
+        &mut self,
+        api: Option<&hidapi::HidApi>,
+        exit_state: ExitState,
+    ) -> Result<()> {
+        trace!("Restoring exit state: {:?}", exit_state);
+
+        let color = match exit_state {
+            ExitState::HardwareDefault => match api {
+                Some(api) => return self.set_hardware_effect_mode(api),
+
+                None => {
+                    warn!("Can not switch to hardware effect mode, turning off the LEDs instead");
+
+                    RGBA {
+                        r: 0x00,
+                        g: 0x00,
+                        b: 0x00,
+                        a: 0x00,
+                    }
+                }
+            },
+
+            ExitState::AllOff => RGBA {
+                r: 0x00,
+                g: 0x00,
+                b: 0x00,
+                a: 0x00,
+            },
+
+            ExitState::Static(color) => color,
+
+            ExitState::Stored(color) => match api {
+                Some(api) => return self.set_stored_color(api, color),
+
+                None => {
+                    warn!("Can not store the color in the hardware profile, setting it directly instead");
+                    color
+                }
+            },
+        };
+
+        let led_map: [RGBA; NUM_KEYS] = [color; NUM_KEYS];
+        self.send_led_map(&led_map)?;
+
+        thread::sleep(Duration::from_millis(constants::DEVICE_SETTLE_MILLIS));
+
+        Ok(())
+    }
+
+    /// Restore the exit state `exit_state` from a panic handler, the hardware
+    /// profile is not touched. Fails with `DeviceBusy` instead of blocking, if the LED
+    /// device is locked
+    pub fn try_restore_exit_state(&mut self, exit_state: ExitState) -> Result<()> {
+        let color = match exit_state {
+            ExitState::Static(color) | ExitState::Stored(color) => color,
+
+            ExitState::HardwareDefault | ExitState::AllOff => RGBA {
+                r: 0x00,
+                g: 0x00,
+                b: 0x00,
+                a: 0x00,
+            },
+        };
+
+        let led_map: [RGBA; NUM_KEYS] = [color; NUM_KEYS];
+        self.try_send_led_map(&led_map)
+    }
+
     // pub fn set_led_off_pattern(&mut self) -> Result<()> {
     //     trace!("Setting LED off pattern...");
 
     //     if !self.is_bound {
     //         Err(RvDeviceError::DeviceNotBound {})
-    //     } else if !self.is_opened {
+    //     } else if !self.is_opened() {
     //         Err(RvDeviceError::DeviceNotOpened {})
-    //     } else if !self.is_initialized {
+    //     } else if !self.is_initialized() {
     //         Err(RvDeviceError::DeviceNotInitialized {})
     //     } else {
     //         let led_map: [RGBA; NUM_KEYS] = [RGBA {
@@ -752,3 +1866,126 @@ impl RvDeviceState {
     //         .clone()
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    fn device_info(product_id: u16, interface_number: i32, serial: &str) -> hidapi::HidDeviceInfo {
+        hidapi::HidDeviceInfo {
+            path: CString::new(format!("/dev/hidraw-{}", serial)).unwrap(),
+            vendor_id: VENDOR_ID,
+            product_id,
+            serial_number: Some(serial.to_string()),
+            release_number: 0,
+            manufacturer_string: Some(VENDOR_STR.to_string()),
+            product_string: None,
+            usage_page: 0,
+            usage: 0,
+            interface_number,
+        }
+    }
+
+    #[test]
+    fn test_find_device() {
+        let keyboard = RvDeviceState::bind(
+            &device_info(PRODUCT_ID[0], CTRL_INTERFACE, "kbd"),
+            &device_info(PRODUCT_ID[0], LED_INTERFACE, "kbd"),
+        );
+        let headset = RvDeviceState::bind_headset(&device_info(
+            HEADSET_PRODUCT_ID[0],
+            HEADSET_INTERFACE,
+            "hs",
+        ));
+
+        let devices = vec![keyboard, headset.clone()];
+
+        assert_eq!(find_device(&devices, "0"), Some(0));
+        assert_eq!(find_device(&devices, "1"), Some(1));
+        assert_eq!(find_device(&devices, "2"), None);
+
+        assert_eq!(find_device(&devices, "keyboard"), Some(0));
+        assert_eq!(find_device(&devices, " headset "), Some(1));
+
+        assert_eq!(find_device(&devices, "kbd"), Some(0));
+        assert_eq!(find_device(&devices, "hs"), Some(1));
+        assert_eq!(find_device(&devices, "unknown"), None);
+
+        // a headset on its own is the first device
+        let devices = vec![headset];
+
+        assert_eq!(find_device(&devices, "headset"), Some(0));
+        assert_eq!(find_device(&devices, "keyboard"), None);
+    }
+
+    #[test]
+    fn test_exit_state_from_config() {
+        let color = RGBA {
+            r: 0x12,
+            g: 0x34,
+            b: 0x56,
+            a: 0xff,
+        };
+
+        assert!(matches!(
+            ExitState::from_config("stored", 0x123456),
+            Some(ExitState::Stored(c)) if c == color
+        ));
+        assert!(matches!(
+            ExitState::from_config("static", 0x123456),
+            Some(ExitState::Static(c)) if c == color
+        ));
+
+        assert!(matches!(
+            ExitState::from_config("hardware", 0),
+            Some(ExitState::HardwareDefault)
+        ));
+        assert!(matches!(
+            ExitState::from_config("off", 0),
+            Some(ExitState::AllOff)
+        ));
+
+        assert!(ExitState::from_config("Stored", 0).is_none());
+        assert!(ExitState::from_config("", 0).is_none());
+    }
+
+    #[test]
+    fn test_select_settle_time() {
+        let ms = Duration::from_millis;
+
+        // the baseline is 4ms, so the limit is 7ms: 5ms fails, 10ms is too slow
+        let mut measured = vec![];
+        let result = select_settle_time(ms(20), &[5, 10, 15, 25], |settle_time| {
+            measured.push(settle_time);
+
+            match settle_time.as_millis() {
+                20 => Ok(ms(4)),
+                5 => Err(RvDeviceError::WriteError {}),
+                10 => Ok(ms(8)),
+                _ => Ok(ms(6)),
+            }
+        });
+
+        assert_eq!(result.unwrap(), ms(15));
+        assert_eq!(measured, vec![ms(20), ms(5), ms(10), ms(15)]);
+
+        // candidates at or above the default are never measured
+        let mut measured = vec![];
+        let result = select_settle_time(ms(10), &[5, 10, 15], |settle_time| {
+            measured.push(settle_time);
+
+            if settle_time < ms(10) {
+                Ok(ms(9))
+            } else {
+                Ok(ms(2))
+            }
+        });
+
+        assert_eq!(result.unwrap(), ms(10));
+        assert_eq!(measured, vec![ms(10), ms(5)]);
+
+        // the calibration fails if the default does not work
+        assert!(select_settle_time(ms(10), &[5], |_| Err(RvDeviceError::WriteError {})).is_err());
+    }
+}
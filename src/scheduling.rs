@@ -0,0 +1,391 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Scheduling priorities of the I/O threads. The threads that read from the
+//! keyboard (`events`), write to the virtual keyboard (`uinput`) and write to the
+//! devices (`main`) may be assigned a nice value, or a realtime priority
+//! (`SCHED_FIFO`), in the `[scheduling.<thread>]` sections of the config. This
+//! reduces the jitter of the input latency while the system is under load. The
+//! policy only applies to the named thread, threads that it spawns later on are
+//! reset to the default policy (`SCHED_RESET_ON_FORK`).
+//!
+//! To keep the daemon from competing with a game for the same cores, the threads
+//! of the daemon may be restricted to a set of CPUs, and the frame rate may be
+//! capped while a fullscreen application is focused.
+
+use lazy_static::lazy_static;
+use log::*;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+use crate::constants;
+use crate::windows;
+
+pub type Result<T> = std::result::Result<T, SchedulingError>;
+
+#[derive(Debug, Error)]
+pub enum SchedulingError {
+    #[error("Invalid scheduling policy: {policy}")]
+    InvalidPolicy { policy: String },
+
+    #[error("Invalid priority {priority}, expected a value in the range {min}..={max}")]
+    InvalidPriority { priority: i64, min: i64, max: i64 },
+
+    #[error("Invalid CPU: {cpu}")]
+    InvalidCpu { cpu: i64 },
+
+    #[error("Not permitted: {description}")]
+    PermissionDenied { description: String },
+
+    #[error("System call failed: {description}")]
+    SyscallError { description: String },
+}
+
+lazy_static! {
+    /// Max. frames per second while a fullscreen application is focused, 0 means no limit
+    static ref FULLSCREEN_FPS: AtomicU64 = AtomicU64::new(0);
+}
+
+/// The scheduling policy of a thread
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Policy {
+    /// The default time-sharing scheduler, with a nice value
+    Normal { nice: i32 },
+
+    /// Realtime scheduling, with a priority in the range 1..=99
+    Fifo { priority: i32 },
+}
+
+fn parse_policy(policy: &str, priority: Option<i64>) -> Result<Policy> {
+    let check_range = |priority: i64, min: i64, max: i64| {
+        if priority < min || priority > max {
+            Err(SchedulingError::InvalidPriority { priority, min, max })
+        } else {
+            Ok(priority as i32)
+        }
+    };
+
+    match policy.to_lowercase().as_str() {
+        "normal" | "other" => Ok(Policy::Normal {
+            nice: check_range(priority.unwrap_or(0), -20, 19)?,
+        }),
+
+        "fifo" | "realtime" => Ok(Policy::Fifo {
+            priority: check_range(priority.unwrap_or(10), 1, 99)?,
+        }),
+
+        _ => Err(SchedulingError::InvalidPolicy {
+            policy: policy.to_string(),
+        }),
+    }
+}
+
+/// Get the configured policy of the thread `thread`, if any
+fn get_policy(thread: &str) -> Result<Option<Policy>> {
+    let config = crate::CONFIG.lock();
+
+    let config = match config.as_ref() {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let policy = match config.get_str(&format!("scheduling.{}.policy", thread)) {
+        Ok(policy) => policy,
+        Err(_) => return Ok(None),
+    };
+
+    let priority = config
+        .get_int(&format!("scheduling.{}.priority", thread))
+        .ok();
+
+    parse_policy(&policy, priority).map(Some)
+}
+
+/// The bit of CAP_SYS_NICE in the capability sets
+const CAP_SYS_NICE: u32 = 23;
+
+/// Get the effective capabilities from the contents of `/proc/<pid>/status`
+fn parse_effective_caps(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with("CapEff:"))
+        .and_then(|line| u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok())
+}
+
+/// Returns true if the daemon has the capability CAP_SYS_NICE, root may lack it,
+/// e.g. if the capabilities are restricted by the service manager
+fn has_cap_sys_nice() -> bool {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| parse_effective_caps(&status))
+        .map_or(false, |caps| caps & (1 << CAP_SYS_NICE) != 0)
+}
+
+/// Returns true if the daemon may use realtime scheduling with the priority
+/// `priority`, either due to CAP_SYS_NICE or due to RLIMIT_RTPRIO
+fn may_use_realtime(priority: i32) -> bool {
+    if has_cap_sys_nice() {
+        return true;
+    }
+
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_RTPRIO, &mut limit) } != 0 {
+        return false;
+    }
+
+    limit.rlim_cur >= priority as libc::rlim_t
+}
+
+/// Set the scheduling policy `policy` of the calling thread, threads that it spawns
+/// later on are reset to the default policy
+fn set_scheduler(policy: libc::c_int, priority: i32) -> std::result::Result<(), libc::c_int> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+
+    // on Linux, the pid 0 denotes the calling thread, not the whole process
+    if unsafe { libc::sched_setscheduler(0, policy | libc::SCHED_RESET_ON_FORK, &param) } != 0 {
+        return Err(std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EINVAL));
+    }
+
+    Ok(())
+}
+
+fn set_nice(nice: i32) -> Result<()> {
+    // a negative nice value is not inherited by the threads that are spawned later on
+    set_scheduler(libc::SCHED_OTHER, 0).map_err(|e| SchedulingError::SyscallError {
+        description: format!("{}", std::io::Error::from_raw_os_error(e)),
+    })?;
+
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+
+    // on Linux, the nice value is a property of the thread
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+        let e = std::io::Error::last_os_error();
+
+        return match e.raw_os_error() {
+            Some(libc::EPERM) | Some(libc::EACCES) => Err(SchedulingError::PermissionDenied {
+                description: format!("Could not set the nice value to {}", nice),
+            }),
+
+            _ => Err(SchedulingError::SyscallError {
+                description: format!("{}", e),
+            }),
+        };
+    }
+
+    Ok(())
+}
+
+fn set_fifo(priority: i32) -> Result<()> {
+    if !may_use_realtime(priority) {
+        return Err(SchedulingError::PermissionDenied {
+            description: format!(
+                "Realtime priority {} requires CAP_SYS_NICE, or a sufficient RLIMIT_RTPRIO",
+                priority
+            ),
+        });
+    }
+
+    match set_scheduler(libc::SCHED_FIFO, priority) {
+        Ok(()) => Ok(()),
+
+        Err(libc::EPERM) => Err(SchedulingError::PermissionDenied {
+            description: format!("Could not set the realtime priority to {}", priority),
+        }),
+
+        Err(e) => Err(SchedulingError::SyscallError {
+            description: format!("{}", std::io::Error::from_raw_os_error(e)),
+        }),
+    }
+}
+
+/// Apply the policy `policy` to the calling thread. If realtime scheduling is not
+/// permitted, the highest nice value that is permitted is used instead
+pub fn set_policy(policy: Policy) -> Result<()> {
+    match policy {
+        Policy::Normal { nice } => set_nice(nice),
+
+        Policy::Fifo { priority } => set_fifo(priority).or_else(|e| match e {
+            SchedulingError::PermissionDenied { .. } => {
+                warn!("{}, falling back to a nice value", e);
+
+                set_nice(-10).or_else(|_e| set_nice(0))
+            }
+
+            e => Err(e),
+        }),
+    }
+}
+
+/// Get the CPUs of the config key `key`, if any
+fn get_cpus(key: &str) -> Result<Option<Vec<usize>>> {
+    let config = crate::CONFIG.lock();
+
+    let cpus = match config.as_ref().map(|c| c.get::<Vec<i64>>(key)) {
+        Some(Ok(cpus)) => cpus,
+        _ => return Ok(None),
+    };
+
+    let max_cpu = libc::CPU_SETSIZE as i64 - 1;
+
+    cpus.iter()
+        .map(|cpu| {
+            if *cpu < 0 || *cpu > max_cpu {
+                Err(SchedulingError::InvalidCpu { cpu: *cpu })
+            } else {
+                Ok(*cpu as usize)
+            }
+        })
+        .collect::<Result<Vec<usize>>>()
+        .map(Some)
+}
+
+/// Restrict the thread `tid` to the CPUs `cpus`, 0 denotes the calling thread
+fn set_affinity(tid: libc::pid_t, cpus: &[usize]) -> Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+
+    for cpu in cpus {
+        unsafe { libc::CPU_SET(*cpu, &mut set) };
+    }
+
+    if unsafe { libc::sched_setaffinity(tid, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(SchedulingError::SyscallError {
+            description: format!("{}", std::io::Error::last_os_error()),
+        });
+    }
+
+    Ok(())
+}
+
+/// Restrict all threads of the daemon to the CPUs of the `[scheduling]` section,
+/// threads that are spawned later on inherit the affinity. Also loads the frame
+/// rate ceiling for fullscreen applications
+pub fn initialize() -> Result<()> {
+    if let Some(config) = crate::CONFIG.lock().as_ref() {
+        let fps = config
+            .get_int("scheduling.fullscreen_fps")
+            .unwrap_or(0)
+            .max(0) as u64;
+
+        FULLSCREEN_FPS.store(fps.min(constants::TARGET_FPS), Ordering::SeqCst);
+    }
+
+    if let Some(cpus) = get_cpus("scheduling.cpus")? {
+        let tasks = fs::read_dir("/proc/self/task").map_err(|e| SchedulingError::SyscallError {
+            description: format!("{}", e),
+        })?;
+
+        for task in tasks.filter_map(|t| t.ok()) {
+            if let Some(tid) = task.file_name().to_str().and_then(|t| t.parse().ok()) {
+                set_affinity(tid, &cpus)?;
+            }
+        }
+
+        info!("Restricted the daemon to the CPUs {:?}", cpus);
+    }
+
+    Ok(())
+}
+
+/// Returns true if the main loop shall render a frame in iteration `ticks`.
+/// While a fullscreen application is focused, frames are skipped to reach
+/// the configured max. frames per second
+pub fn is_render_frame(ticks: u64) -> bool {
+    let fps = FULLSCREEN_FPS.load(Ordering::SeqCst);
+
+    if fps > 0 && windows::is_fullscreen() {
+        let divisor = u64::max(constants::TARGET_FPS / fps, 1);
+
+        ticks % divisor == 0
+    } else {
+        true
+    }
+}
+
+/// Apply the configured scheduling policy and CPU affinity to the calling thread,
+/// which is named `thread` in the config. This has to be called by the thread itself
+pub fn apply(thread: &str) {
+    match get_cpus(&format!("scheduling.{}.cpus", thread)) {
+        Ok(Some(cpus)) => match set_affinity(0, &cpus) {
+            Ok(()) => info!("CPU affinity of thread '{}': {:?}", thread, cpus),
+
+            Err(e) => warn!(
+                "Could not set the CPU affinity of thread '{}': {}",
+                thread, e
+            ),
+        },
+
+        Ok(None) => {}
+
+        Err(e) => error!("Invalid CPU affinity of thread '{}': {}", thread, e),
+    }
+
+    match get_policy(thread) {
+        Ok(Some(policy)) => match set_policy(policy) {
+            Ok(()) => info!("Scheduling policy of thread '{}': {:?}", thread, policy),
+
+            Err(e) => warn!(
+                "Could not set the scheduling policy of thread '{}': {}",
+                thread, e
+            ),
+        },
+
+        Ok(None) => {}
+
+        Err(e) => error!(
+            "Invalid scheduling configuration of thread '{}': {}",
+            thread, e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_effective_caps() {
+        let status = "Name:\teruption\nCapInh:\t0000000000000000\nCapPrm:\t0000003fffffffff\nCapEff:\t0000000000800000\n";
+
+        let caps = parse_effective_caps(status).unwrap();
+        assert_eq!(caps, 1 << CAP_SYS_NICE);
+
+        assert_eq!(parse_effective_caps("Name:\teruption\n"), None);
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            parse_policy("fifo", None).unwrap(),
+            Policy::Fifo { priority: 10 }
+        );
+        assert_eq!(
+            parse_policy("normal", Some(-5)).unwrap(),
+            Policy::Normal { nice: -5 }
+        );
+        assert!(parse_policy("fifo", Some(100)).is_err());
+        assert!(parse_policy("batch", None).is_err());
+    }
+}
@@ -0,0 +1,754 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use eruption_sdk::{api_functions, ApiDeprecation, ApiFunction, Registry, CORE_NAMESPACE};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::{Context, Function, MultiValue, Table};
+use std::collections::HashSet;
+
+use crate::constants;
+use crate::plugins;
+
+lazy_static! {
+    /// The registry of all functions of the Lua API, provided by the daemon and the plugins
+    static ref REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+    /// Pairs of script name and deprecated function, that have already been warned about
+    static ref DEPRECATION_WARNINGS: Mutex<HashSet<(String, &'static str)>> = Mutex::new(HashSet::new());
+}
+
+/// The deprecated functions of the Lua API
+pub const DEPRECATIONS: &[ApiDeprecation] = &[
+    ApiDeprecation {
+        name: "noise",
+        replacement: Some("open_simplex_noise"),
+        since: "0.0.11",
+    },
+    ApiDeprecation {
+        name: "set_color_map",
+        replacement: Some("submit_color_map"),
+        since: "0.1.2",
+    },
+];
+
+/// Parse an API level like "0.1.2" into its components
+fn parse_api_level(level: &str) -> Option<Vec<u32>> {
+    level
+        .trim()
+        .split('.')
+        .map(|c| c.parse::<u32>().ok())
+        .collect()
+}
+
+/// Returns true if the API level `a` is older than `b`
+fn is_older_api_level(a: &str, b: &str) -> bool {
+    match (parse_api_level(a), parse_api_level(b)) {
+        (Some(a), Some(b)) => a < b,
+        _ => false,
+    }
+}
+
+/// Returns true if a script, that requires the API level `required`, may be
+/// executed by this version of the daemon
+pub fn is_api_level_supported(required: &str) -> bool {
+    parse_api_level(required).is_some() && !is_older_api_level(constants::API_LEVEL, required)
+}
+
+/// The functions that are provided by the daemon itself, the functions of
+/// the plugins are declared by the respective plugin
+pub const CORE_API: &[ApiFunction] = api_functions! {
+    "trace(message)",
+    Std,
+    "since before 0.0.9",
+    "Log message with severity: `trace`";
+
+    "debug(message)",
+    Std,
+    "since before 0.0.9",
+    "Log message with severity: `debug`";
+
+    "info(message)",
+    Std,
+    "since before 0.0.9",
+    "Log message with severity: `info`";
+
+    "warn(message)",
+    Std,
+    "since before 0.0.9",
+    "Log message with severity: `warn`";
+
+    "error(message)",
+    Std,
+    "since before 0.0.9",
+    "Log message with severity: `error`";
+
+    "delay(millis)",
+    Std,
+    "since before 0.0.9",
+    "Delay script execution for `millis` milliseconds";
+
+    "set_dnd(enable)",
+    Std,
+    "since 0.1.2",
+    "Enter or leave do-not-disturb mode. While active, animations are frozen to a static, dimmed frame. May be bound to a hotkey, e.g. in `macros.lua`";
+
+    "get_dnd() -> b",
+    Std,
+    "since 0.1.2",
+    "Returns true if do-not-disturb mode is active. Notification-driven effects should check this before they are rendered";
+
+    "get_active_app() -> s",
+    Std,
+    "since 0.1.2",
+    "Returns the window class of the focused application in lower case, e.g. \"blender\", or `nil` if unknown. Requires a window watcher in the session of the user, see `eruption active-app`";
+
+    "store_set(key, value)",
+    Std,
+    "since 0.1.2",
+    "Set the value of `key` in the key-value store that is shared by all scripts. Values may be booleans, numbers or strings, `nil` removes the key. All scripts are notified via `on_store_changed(key)`";
+
+    "store_get(key) -> value",
+    Std,
+    "since 0.1.2",
+    "Returns the value of `key` from the shared key-value store, or `nil` if the key is not set";
+
+    "cache_put(key, table)",
+    Std,
+    "since 0.1.2",
+    "Store the table of numbers `table` on disk, e.g. a precomputed noise field. Entries are specific to the script, the values of its parameters and the API level";
+
+    "cache_get(key) -> table",
+    Std,
+    "since 0.1.2",
+    "Returns the table that has been stored via `cache_put(key, table)`, or `nil` if it is not cached (yet)";
+
+    "fs_read(path) -> s",
+    Std,
+    "since 0.1.2",
+    "Returns the contents of the file `path` in the data directory of the script, or `nil` if it can not be read. Paths may not leave the data directory. Requires the capability `file-read`";
+
+    "fs_write(path, data) -> b",
+    Std,
+    "since 0.1.2",
+    "Write the string `data` to the file `path` in the data directory of the script, returns true on success. Files are limited to 1 MiB. Requires the capability `file-write`";
+
+    "json_decode(s) -> value",
+    Std,
+    "since 0.1.2",
+    "Returns the value of the JSON document `s`, objects and arrays are converted to tables";
+
+    "json_encode(value) -> s",
+    Std,
+    "since 0.1.2",
+    "Returns `value` encoded as JSON. Tables with the keys 1..n are encoded as arrays, other tables as objects";
+
+    "toml_decode(s) -> value",
+    Std,
+    "since 0.1.2",
+    "Returns the value of the TOML document `s`, as a table";
+
+    "toml_encode(value) -> s",
+    Std,
+    "since 0.1.2",
+    "Returns the table `value` encoded as TOML";
+
+    "http_get(url) -> id",
+    Std,
+    "since 0.1.2",
+    "Queue a GET request of `url` and return the id of the request, or `nil` if the rate limit of 10 requests per minute has been exceeded. The response is delivered via `on_http_response(id, status, body)`. Requires the capability `network`";
+
+    "exec_async(cmd, [args]) -> id",
+    Std,
+    "since 0.1.2",
+    "Queue the command `cmd` with the arguments of the table `args` and return the id of the command, or `nil` if the rate limit of 30 commands per minute has been exceeded or no user is active on the seat. Commands are not executed by a shell, they run with the identity of the seat user. The exit status and the output are delivered via `on_exec_finished(id, status, stdout)`. Requires the capability `exec`";
+
+    "get_time_millis() -> i",
+    Std,
+    "since 0.1.2",
+    "Returns the number of milliseconds elapsed since the daemon has been started. Uses a monotonic clock, so it is unaffected by changes of the system time";
+
+    "request_ticks(rate)",
+    Std,
+    "since 0.1.2",
+    "Request `rate` calls of `on_tick(delta)` per second, up to the frame rate of the daemon. Also resumes the ticks after `suspend_ticks()`";
+
+    "suspend_ticks()",
+    Std,
+    "since 0.1.2",
+    "Stop calling `on_tick(delta)`, e.g. while the effect is static. The last frame of the script is kept, other event handlers are still called";
+
+    "spawn_effect(func)",
+    Std,
+    "since 0.1.2",
+    "Run the function `func` as a coroutine based effect. The effect is started on the next timer tick, and is resumed on later ticks after each call to `wait(millis)`. Please see Listing 02";
+
+    "wait(millis)",
+    Std,
+    "since 0.1.2",
+    "Suspend the current effect for `millis` milliseconds. May only be called from within an effect started by `spawn_effect(func)`";
+
+    "get_local_time() -> {time}",
+    Std,
+    "since 0.1.2",
+    "Returns a table with the fields `year`, `month`, `day`, `hour`, `minute`, `second`, `weekday` (1 = Monday) and `yearday` of the current local time";
+
+    "days_until(date) -> i",
+    Std,
+    "since 0.1.2",
+    "Returns the number of days from today until `date`, specified as \"YYYY-MM-DD\". Dates in the past yield negative values, invalid dates yield `nil`";
+
+    "abs(f) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns the absolute value of `f`";
+
+    "sin(a) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns the sine of angle `a`";
+
+    "pow(f, p) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns `f` to the power of `p`";
+
+    "sqrt(f) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns the square root of `f`";
+
+    "rand(l, h) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns a random number in the range `l..h`";
+
+    "trunc(f) -> i",
+    Math,
+    "since before 0.0.9",
+    "Truncate the fractional part of `f`";
+
+    "lerp(f0, f1, f) -> f",
+    Math,
+    "since 0.0.9",
+    "Linear interpolation of `f` to `f0`..`f1`, where `f` should lie in the range of -1.0..+1.0";
+
+    "min(f1, f2) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns the smaller one of the two values";
+
+    "max(f1, f2) -> f",
+    Math,
+    "since before 0.0.9",
+    "Returns the greater one of the two values";
+
+    "clamp(f, l, h) -> f",
+    Math,
+    "since before 0.0.9",
+    "Clamp `f` to range `l..h`";
+
+    "color_to_rgb(color) -> (r, g, b)",
+    Color,
+    "since before 0.0.9",
+    "Returns the red, green and blue components of `color`";
+
+    "color_to_hsl(color) -> (h, s, l)",
+    Color,
+    "since 0.0.10",
+    "Returns the hue, saturation and lightness components of `color`";
+
+    "rgb_to_color(r, g, b) -> color",
+    Color,
+    "since before 0.0.9",
+    "Returns a color, constructed fom the r, g and b components";
+
+    "hsl_to_color(h, s, l) -> color",
+    Color,
+    "since 0.0.9",
+    "Returns a color, constructed fom hue [0..360), saturation [0.0...1.0] and lightness [0.0..0.5] and (0.5..1.0] components";
+
+    "color_to_rgba(color) -> (r, g, b, a)",
+    Color,
+    "since 0.0.12",
+    "Returns the red, green blue and alpha components of `color`";
+
+    "rgba_to_color(r, g, b, a) -> color",
+    Color,
+    "since 0.0.12",
+    "Returns a color, constructed fom the r, g, b and alpha components";
+
+    "hsla_to_color(h, s, l, a) -> color",
+    Color,
+    "since 0.0.12",
+    "Returns a color, constructed fom hue [0..360), saturation [0.0...1.0], lightness [0.0..0.5], (0.5..1.0] an dalpha components";
+
+    "linear_gradient(start_color, end_color, p) -> color",
+    Color,
+    "since before 0.0.9",
+    "Returns the interpolated color at position `p` located between `start_color`..`end_color`. The value of `p` should lie in the range of 0..1";
+
+    "get_palette(name) -> table",
+    Color,
+    "since 0.1.2",
+    "Returns the colors of the palette `name` of the palette library, or `nil` if there is no such palette. The palette of the desktop wallpaper is published as \"wallpaper\", if enabled";
+
+    "get_palette_names() -> table",
+    Color,
+    "since 0.1.2",
+    "Returns the names of all palettes of the palette library";
+
+    "noise(f1, f2, f3) -> f",
+    Noise,
+    "removed in 0.0.11",
+    "Computes an Open Simplex Noise value";
+
+    "perlin_noise(f1, f2, f3) -> f",
+    Noise,
+    "since 0.0.11",
+    "Computes a Perlin noise value";
+
+    "billow_noise(f1, f2, f3) -> f",
+    Noise,
+    "since 0.0.11",
+    "Computes a Billow noise value";
+
+    "voronoi_noise(f1, f2, f3) -> f",
+    Noise,
+    "since 0.0.11",
+    "Computes a Voronoi noise value";
+
+    "fractal_brownian_noise(f1, f2, f3) -> f",
+    Noise,
+    "since 0.0.11",
+    "Computes a Fractal Brownian Motion noise value";
+
+    "ridged_multifractal_noise(f1, f2, f3) -> f",
+    Noise,
+    "since 0.0.11",
+    "Computes a Ridged Multifractal noise value";
+
+    "open_simplex_noise(f1, f2, f3) -> f",
+    Noise,
+    "since 0.0.11",
+    "Computes an Open Simplex Noise value";
+
+    "get_num_keys() -> i",
+    Hw,
+    "since before 0.0.9",
+    "Returns the number of keys of the connected device (Approx. 144)";
+
+    "get_num_devices() -> i",
+    Hw,
+    "since 0.1.2",
+    "Returns the number of connected devices. Devices are numbered starting from 0, device 0 is the default device";
+
+    "get_target_device() -> i",
+    Hw,
+    "since 0.1.2",
+    "Returns the index of the device that the script renders to, as specified in the `[devices]` table of the profile";
+
+    "find_device(selector) -> i",
+    Hw,
+    "since 0.1.2",
+    "Returns the index of the device specified by `selector`, either a device index, a serial number, or `"keyboard"` or `"headset"` for the first device of that kind. Returns `nil` if no such device is connected";
+
+    "get_device_info([device]) -> {info}",
+    Hw,
+    "since 0.1.2",
+    "Returns a table with the fields `model_name`, `firmware_version`, `serial_number` and `usb_path` of the device `device`, or of the target device if omitted";
+
+    "get_key_color([device,] key_index) -> color",
+    Hw,
+    "since before 0.0.9",
+    "Returns the current color of the key `key_index`. The optional argument `device` is available since 0.1.2";
+
+    "set_key_color([device,] key_index, color)",
+    Hw,
+    "since before 0.0.9",
+    "Sets the current color of the key `key_index` to `color`. The optional argument `device` is available since 0.1.2";
+
+    "get_color_map([device]) -> [color_map]",
+    Hw,
+    "since before 0.0.9",
+    "Returns the current colors of all LEDs. The optional argument `device` is available since 0.1.2";
+
+    "set_color_map([device,] [color_map])",
+    Hw,
+    "since before 0.0.9",
+    "Set all LEDs at once to the colors specified in the array `color_map`. This will directly access the keyboard. Please see also: submit_color_map(). The optional argument `device` is available since 0.1.2";
+
+    "submit_color_map([color_map])",
+    Hw,
+    "since 0.0.12",
+    "Set all LEDs at once to the colors specified in the array `color_map`. Color maps of all scripts will be alpha blended together. and then sent to the keyboard once for each render frame. Scripts render to their target device";
+
+    "rotate([color_map], theta) -> [color_map]",
+    Hw,
+    "since 0.1.1",
+    "Returns the color map `color_map`, rotated by `theta` radians. Experimental";
+
+    "set_frame_interpolation(enable)",
+    Hw,
+    "since 0.1.2",
+    "Enable or disable linear blending between the last two color maps submitted by this script on intermediate render frames. May also be enabled via `frame_interpolation = true` in the script's manifest";
+
+    "get_canvas_size() -> (w, h)",
+    Hw,
+    "since 0.1.2",
+    "Returns the width and the height of the canvas in pixels. The canvas is a regular grid that is larger than the set of keys, so scripts don't depend on the number of keys of the device";
+
+    "get_key_position(key_index) -> (x, y)",
+    Hw,
+    "since 0.1.2",
+    "Returns the position of the center of the key `key_index` on the canvas. Raises an error if the key index is out of range, or if the device has no grid of keys";
+
+    "submit_canvas([canvas])",
+    Hw,
+    "since 0.1.2",
+    "Submit the array `canvas` of `w * h` colors in row major order. Each key is set to the average of the pixels of the canvas that it covers, the result is handled like a color map submitted via `submit_color_map()`. Raises an error if the device has no grid of keys";
+
+    "hid_send_feature(bytes) -> b",
+    Hw,
+    "since 0.1.2",
+    "Send the raw feature report `bytes` to the target device, the first byte is the report id. Returns true on success. Requires the capability `raw-hid`, raw HID access has to be enabled in `eruption.conf` and the report id has to be on the safelist";
+
+    "hid_get_feature(report_id, len) -> [bytes]",
+    Hw,
+    "since 0.1.2",
+    "Get the raw feature report `report_id` of the target device, `len` is the size of the report including the report id. Returns `nil` on failure. Requires the capability `raw-hid`, as `hid_send_feature()`";
+
+    "blink_pattern(keys, pattern, unit_ms, color) -> b",
+    Hw,
+    "since 0.1.2",
+    "Play the blink pattern `pattern` once on the keys of the table `keys` in `color`, each step lasts `unit_ms` milliseconds (10 to 10000). The pattern is either morse code like `\"... --- ...\"` (a space separates letters, a slash separates words) or a bitstring like `\"1100110\"`. The pattern is played by the daemon, it completes even if the script is unloaded. Returns false if the pattern is invalid";
+
+    "inject_key(ev_key, down, [pacing_ms])",
+    Macro,
+    "since 0.1.1",
+    "Inject a key event on the virtual keyboard. The optional `pacing_ms` overrides the delay after the event, that is configured by `macros.step_delay_millis`. Requires the capability `input-injection`";
+
+    "inject_with_modifiers(ev_key, modifiers, neutralize, [pacing_ms])",
+    Macro,
+    "since 0.1.2",
+    "Press and release `ev_key` on the virtual keyboard, while exactly the modifiers of the table `modifiers` (ev_key codes, e.g. `{ 42 }` for LEFTSHIFT) are held down. Other modifiers that are held down are released first and restored afterwards, unless `neutralize` is false. The optional `pacing_ms` overrides the delay after the combination. Requires the capability `input-injection`";
+
+    "abort_macro()",
+    Macro,
+    "since 0.1.2",
+    "Abort all running macros: Keys that are queued on the virtual keyboard are discarded, and keys that have been pressed by `inject_key()` are released. Requires the capability `input-injection`";
+
+    "is_macro_running() -> b",
+    Macro,
+    "since 0.1.2",
+    "Returns true while injected keys are queued on the virtual keyboard";
+
+    "clipboard_get() -> s",
+    Macro,
+    "since 0.1.2",
+    "Get the text on the clipboard of the seat user, or `nil` if it is empty or not accessible, e.g. while the session is locked. Requires the capability `clipboard`";
+
+    "clipboard_set(text) -> b",
+    Macro,
+    "since 0.1.2",
+    "Put `text` on the clipboard of the seat user, returns true on success. The text is limited to 1 MiB. Requires the capability `clipboard`";
+};
+
+/// Build the registry of the Lua API, from the declarations of the daemon and of all
+/// plugins. Fails if a function has been declared more than once
+pub fn build_registry() -> eruption_sdk::Result<Registry> {
+    let mut registry = Registry::new();
+
+    registry.add(CORE_NAMESPACE, CORE_API)?;
+
+    for (namespace, functions) in plugins::get_plugin_apis() {
+        registry.add(&namespace, functions)?;
+    }
+
+    Ok(registry)
+}
+
+/// Build the registry of the Lua API, it is used to verify the functions
+/// that are registered with each Lua VM, and to build the namespace tables
+pub fn initialize() -> eruption_sdk::Result<()> {
+    *REGISTRY.lock() = Some(build_registry()?);
+
+    Ok(())
+}
+
+/// Call `f` with the registry of the Lua API, the registry is built on first use,
+/// if it has not been initialized
+fn with_registry<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&Registry) -> R,
+{
+    let mut registry = REGISTRY.lock();
+
+    if registry.is_none() {
+        *registry = build_registry()
+            .map_err(|e| error!("Invalid Lua API declarations: {}", e))
+            .ok();
+    }
+
+    registry.as_ref().map(f)
+}
+
+/// Verify that each declared function of the Lua API has been registered with the
+/// Lua VM `lua_ctx`, and that each registered function has been declared
+pub fn verify_globals(lua_ctx: Context) {
+    with_registry(|registry| {
+        let globals = lua_ctx.globals();
+
+        for (namespace, function) in registry.functions() {
+            if function.is_removed() {
+                continue;
+            }
+
+            if globals.get::<_, rlua::Function>(function.name()).is_err() {
+                warn!(
+                    "The function '{}' of '{}' has been declared, but is not available",
+                    function.name(),
+                    namespace
+                );
+            }
+        }
+
+        for pair in globals.pairs::<String, rlua::Value>() {
+            if let Ok((name, rlua::Value::Function(_))) = pair {
+                if registry.find_namespace(&name).is_none() && !is_lua_builtin(&name) {
+                    warn!(
+                        "The function '{}' is available, but has not been declared",
+                        name
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Add each function of the Lua API to the table of its library, e.g. `color.*`
+/// or `device.*`. Unless `compat_globals` is set, the functions are removed from
+/// the global namespace afterwards
+pub fn register_namespaces(lua_ctx: Context, compat_globals: bool) -> rlua::Result<()> {
+    with_registry(|registry| -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        for (_namespace, function) in registry.functions() {
+            let name = function.name();
+
+            let func = match globals.get::<_, rlua::Value>(name)? {
+                rlua::Value::Function(func) => func,
+                _ => continue,
+            };
+
+            let table_name = function.lib.table_name();
+            let table = match globals.get::<_, Option<Table>>(table_name)? {
+                Some(table) => table,
+
+                None => {
+                    let table = lua_ctx.create_table()?;
+                    globals.set(table_name, table.clone())?;
+
+                    table
+                }
+            };
+
+            if !table.contains_key(name)? {
+                table.set(name, func)?;
+            }
+
+            if !compat_globals {
+                globals.set(name, rlua::Nil)?;
+            }
+        }
+
+        Ok(())
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Log a warning about the use of the deprecated function `deprecation` by the
+/// script `script`, once per script
+fn warn_deprecated(script: &str, deprecation: &ApiDeprecation) {
+    let mut warnings = DEPRECATION_WARNINGS.lock();
+
+    if warnings.insert((script.to_string(), deprecation.name)) {
+        match deprecation.replacement {
+            Some(replacement) => warn!(
+                "The script '{}' uses the function '{}', that is deprecated since API level {}, please use '{}' instead",
+                script, deprecation.name, deprecation.since, replacement
+            ),
+
+            None => warn!(
+                "The script '{}' uses the function '{}', that is deprecated since API level {}",
+                script, deprecation.name, deprecation.since
+            ),
+        }
+    }
+}
+
+/// Wrap `func`, so that calls log a one-time warning about the deprecation
+fn wrap_deprecated<'lua>(
+    lua_ctx: Context<'lua>,
+    func: Function<'lua>,
+    script: &str,
+    deprecation: &'static ApiDeprecation,
+) -> rlua::Result<Function<'lua>> {
+    let key = lua_ctx.create_registry_value(func)?;
+    let script = script.to_string();
+
+    lua_ctx.create_function(move |lua_ctx, args: MultiValue| {
+        warn_deprecated(&script, deprecation);
+
+        let func: Function = lua_ctx.registry_value(&key)?;
+        func.call::<_, MultiValue>(args)
+    })
+}
+
+/// Make calls of deprecated functions log a warning, with the name of the script
+/// `script`. For scripts that target an API level older than `api_level`, calls of
+/// removed functions are mapped to their replacements. Must be called after
+/// `register_namespaces`
+pub fn register_deprecations(lua_ctx: Context, script: &str, api_level: &str) -> rlua::Result<()> {
+    with_registry(|registry| -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        for deprecation in DEPRECATIONS {
+            let declaration = registry
+                .functions()
+                .iter()
+                .map(|(_, function)| function)
+                .find(|function| function.name() == deprecation.name);
+
+            let declaration = match declaration {
+                Some(declaration) => declaration,
+                None => continue,
+            };
+
+            let table = match globals.get::<_, Option<Table>>(declaration.lib.table_name())? {
+                Some(table) => table,
+                None => continue,
+            };
+
+            let func = if declaration.is_removed() {
+                // map calls of the removed function to its replacement, for scripts
+                // that have been written before it was removed
+                if !is_older_api_level(api_level, deprecation.since) {
+                    continue;
+                }
+
+                let replacement = match deprecation.replacement {
+                    Some(replacement) => replacement,
+                    None => continue,
+                };
+
+                match table.get::<_, Option<Function>>(replacement)? {
+                    Some(func) => func,
+                    None => continue,
+                }
+            } else {
+                match table.get::<_, Option<Function>>(deprecation.name)? {
+                    Some(func) => func,
+                    None => continue,
+                }
+            };
+
+            let wrapper = wrap_deprecated(lua_ctx, func, script, deprecation)?;
+
+            table.set(deprecation.name, wrapper.clone())?;
+
+            match globals.get::<_, rlua::Value>(deprecation.name)? {
+                // replace the global, that exists for scripts with `compat_globals`
+                rlua::Value::Function(_) => globals.set(deprecation.name, wrapper)?,
+
+                // scripts that target an API level older than the removal call the
+                // function as a global
+                rlua::Value::Nil if declaration.is_removed() => {
+                    globals.set(deprecation.name, wrapper)?
+                }
+
+                // the name of the function collides with a namespace table, e.g.
+                // `noise`, make the table callable
+                rlua::Value::Table(namespace) if declaration.is_removed() => {
+                    let metatable = lua_ctx.create_table()?;
+                    let key = lua_ctx.create_registry_value(wrapper)?;
+
+                    metatable.set(
+                        "__call",
+                        lua_ctx.create_function(
+                            move |lua_ctx, (_namespace, args): (Table, MultiValue)| {
+                                let func: Function = lua_ctx.registry_value(&key)?;
+                                func.call::<_, MultiValue>(args)
+                            },
+                        )?,
+                    )?;
+
+                    namespace.set_metatable(Some(metatable));
+                }
+
+                _ => (),
+            }
+        }
+
+        Ok(())
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Returns true if `name` is a function of the Lua standard library
+fn is_lua_builtin(name: &str) -> bool {
+    [
+        "assert",
+        "collectgarbage",
+        "dofile",
+        "error",
+        "getmetatable",
+        "ipairs",
+        "load",
+        "loadfile",
+        "next",
+        "pairs",
+        "pcall",
+        "print",
+        "rawequal",
+        "rawget",
+        "rawlen",
+        "rawset",
+        "require",
+        "select",
+        "setmetatable",
+        "tonumber",
+        "tostring",
+        "type",
+        "xpcall",
+    ]
+    .contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_registry() {
+        let registry = build_registry();
+
+        assert!(registry.is_ok(), "{}", registry.err().unwrap());
+        assert!(registry.unwrap().find_namespace("color_to_hsl").is_some());
+    }
+}
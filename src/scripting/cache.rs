@@ -0,0 +1,195 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::constants;
+
+pub type Result<T> = std::result::Result<T, CacheError>;
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("Could not access the cache: {description}")]
+    IoError { description: String },
+
+    #[error("Invalid cache file")]
+    InvalidFormat {},
+
+    #[error("No script context has been set")]
+    NoContext {},
+}
+
+impl From<std::io::Error> for CacheError {
+    fn from(e: std::io::Error) -> Self {
+        CacheError::IoError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+/// Magic bytes at the start of each cache file
+const MAGIC: &[u8; 4] = b"ERUC";
+
+/// Version of the file format
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of the header of a cache file: magic, version and number of values
+const HEADER_SIZE: usize = 4 + 1 + 4;
+
+thread_local! {
+    /// Name of the script running on this thread, and the values of its parameters,
+    /// cache entries are only valid for the same combination
+    static CONTEXT: RefCell<Option<(String, String)>> = RefCell::new(None);
+}
+
+/// Set the cache context of the script running on the current thread. `parameters`
+/// holds the values of all parameters of the script, e.g. "speed=1.5;hue=220"
+pub fn set_script_context(script_name: &str, parameters: &str) {
+    CONTEXT.with(|ctx| *ctx.borrow_mut() = Some((script_name.to_string(), parameters.to_string())));
+}
+
+/// Returns the directory that holds the cache files: `global.cache_dir` from the
+/// configuration file, or `eruption` below the XDG cache directory
+pub fn get_cache_dir() -> PathBuf {
+    let configured = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get_str("global.cache_dir").ok());
+
+    if let Some(dir) = configured {
+        return PathBuf::from(dir);
+    }
+
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("eruption"))
+        .unwrap_or_else(|| PathBuf::from(constants::DEFAULT_CACHE_DIR))
+}
+
+/// 64 bit FNV-1a hash, the file names of the cache have to be stable across
+/// builds, so the hasher of the standard library can't be used
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Get the path of the cache file of the entry `key`, for the script running on
+/// the current thread. The name of the file is derived from the name of the script,
+/// the values of its parameters, the key and the API level
+fn get_cache_file(key: &str) -> Result<PathBuf> {
+    CONTEXT.with(|ctx| match &*ctx.borrow() {
+        Some((script_name, parameters)) => {
+            let id = format!(
+                "{}\0{}\0{}\0{}",
+                script_name,
+                parameters,
+                key,
+                constants::API_LEVEL
+            );
+
+            let script_name: String = script_name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+
+            Ok(get_cache_dir().join(format!(
+                "{}-{:016x}.cache",
+                script_name,
+                fnv1a(id.as_bytes())
+            )))
+        }
+
+        None => Err(CacheError::NoContext {}),
+    })
+}
+
+/// Encode the table `values` into the contents of a cache file
+fn encode(values: &[f64]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(HEADER_SIZE + values.len() * 8);
+
+    result.extend_from_slice(MAGIC);
+    result.push(FORMAT_VERSION);
+    result.extend_from_slice(&(values.len() as u32).to_le_bytes());
+
+    for value in values {
+        result.extend_from_slice(&value.to_le_bytes());
+    }
+
+    result
+}
+
+/// Decode the contents of a cache file
+fn decode(buf: &[u8]) -> Result<Vec<f64>> {
+    if buf.len() < HEADER_SIZE || &buf[0..4] != MAGIC || buf[4] != FORMAT_VERSION {
+        return Err(CacheError::InvalidFormat {});
+    }
+
+    let count = u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]) as usize;
+
+    if buf.len() != HEADER_SIZE + count * 8 {
+        return Err(CacheError::InvalidFormat {});
+    }
+
+    let result = buf[HEADER_SIZE..]
+        .chunks(8)
+        .map(|c| f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]))
+        .collect();
+
+    Ok(result)
+}
+
+/// Get the cached table `key` of the script running on the current thread,
+/// returns None if the entry does not exist, or is invalid
+pub fn get(key: &str) -> Option<Vec<f64>> {
+    let file = get_cache_file(key).ok()?;
+    let buf = fs::read(&file).ok()?;
+
+    match decode(&buf) {
+        Ok(values) => {
+            debug!("Cache hit: {}", file.display());
+            Some(values)
+        }
+
+        Err(e) => {
+            warn!("Ignoring cache file {}: {}", file.display(), e);
+            None
+        }
+    }
+}
+
+/// Store the table `values` as the entry `key` of the script running on the current thread
+pub fn put(key: &str, values: &[f64]) -> Result<()> {
+    let file = get_cache_file(key)?;
+
+    fs::create_dir_all(get_cache_dir())?;
+
+    // write to a temporary file first, so that readers never see a partial entry
+    let tmp_file = file.with_extension("tmp");
+    fs::write(&tmp_file, encode(values))?;
+    fs::rename(&tmp_file, &file)?;
+
+    debug!("Cached: {}", file.display());
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::constants;
+use crate::rvdevice::{KEYS_PER_COLUMN, NUM_COLUMNS};
+
+/// The grid that the keys of a device are laid out on, as the number of columns
+/// and the number of keys per column
+pub type Grid = (usize, usize);
+
+/// The grid of the keyboards
+pub const DEFAULT_GRID: Grid = (NUM_COLUMNS, KEYS_PER_COLUMN);
+
+/// Get the width and the height of the canvas, in pixels
+pub fn get_size() -> (usize, usize) {
+    (constants::CANVAS_WIDTH, constants::CANVAS_HEIGHT)
+}
+
+/// Get the area of the canvas, that is covered by the key `index`, as a range of
+/// columns and a range of rows, in pixels. Keys are laid out on the regular grid
+/// `grid`. Returns `None` if `index` lies outside of the grid
+fn get_key_area(
+    index: usize,
+    grid: Grid,
+    width: usize,
+    height: usize,
+) -> Option<((usize, usize), (usize, usize))> {
+    let (columns, rows) = grid;
+
+    if index >= columns * rows {
+        return None;
+    }
+
+    let column = index / rows;
+    let row = index % rows;
+
+    let x0 = column * width / columns;
+    let x1 = ((column + 1) * width / columns).max(x0 + 1).min(width);
+
+    let y0 = row * height / rows;
+    let y1 = ((row + 1) * height / rows).max(y0 + 1).min(height);
+
+    Some(((x0, x1), (y0, y1)))
+}
+
+/// Get the position of the center of the key `index` of the grid `grid` on the
+/// canvas, in pixels. Returns `None` if `index` lies outside of the grid
+pub fn get_key_position(index: usize, grid: Grid) -> Option<(f64, f64)> {
+    let (width, height) = get_size();
+    let ((x0, x1), (y0, y1)) = get_key_area(index, grid, width, height)?;
+
+    Some(((x0 + x1) as f64 / 2.0, (y0 + y1) as f64 / 2.0))
+}
+
+/// Sample the canvas `canvas` (`width` x `height` pixels, in row major order, as
+/// 0xAARRGGBB) onto the keys of the grid `grid`. Each key is set to the average
+/// of the pixels of the area that it covers. Missing pixels are treated as transparent
+pub fn sample(canvas: &[u32], width: usize, height: usize, grid: Grid) -> Vec<u32> {
+    let (columns, rows) = grid;
+
+    (0..columns * rows)
+        .filter_map(|index| get_key_area(index, grid, width, height))
+        .map(|((x0, x1), (y0, y1))| {
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let pixel = canvas.get(y * width + x).copied().unwrap_or(0);
+
+                    for (channel, sum) in sum.iter_mut().enumerate() {
+                        *sum += (pixel >> (24 - channel * 8)) & 0xff;
+                    }
+
+                    count += 1;
+                }
+            }
+
+            sum.iter()
+                .fold(0, |color, sum| (color << 8) | (sum / count.max(1)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_key_position() {
+        let (columns, rows) = DEFAULT_GRID;
+        let (width, height) = get_size();
+
+        let (x, y) = get_key_position(0, DEFAULT_GRID).unwrap();
+        assert!(x < width as f64 / columns as f64 && y < height as f64 / rows as f64);
+
+        let (x, y) = get_key_position(columns * rows - 1, DEFAULT_GRID).unwrap();
+        assert!(x > width as f64 / 2.0 && y > height as f64 / 2.0);
+
+        assert!(get_key_position(columns * rows, DEFAULT_GRID).is_none());
+        assert!(get_key_position(6, (2, 3)).is_none());
+    }
+
+    #[test]
+    fn test_sample() {
+        let (width, height) = get_size();
+        let canvas = vec![0xffff_0000; width * height];
+
+        let keys = sample(&canvas, width, height, (2, 3));
+
+        assert_eq!(keys.len(), 6);
+        assert!(keys.iter().all(|color| *color == 0xffff_0000));
+    }
+}
@@ -0,0 +1,453 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Capabilities of scripts. Functions that may be abused, like the injection of
+//! keys, are only available to scripts that declare the respective capability in
+//! the `permissions` list of their manifest, e.g.:
+//!
+//! ```toml
+//! permissions = ["input-injection"]
+//! ```
+//!
+//! The Lua VM of a script is built from an allowlist: Only the libraries and
+//! functions that are safe for all scripts are loaded, functions of the standard
+//! library that may be abused are added for scripts that have been granted the
+//! respective capability. Functions of Eruption that require a capability are
+//! removed from the environment of scripts that lack it, before the script is
+//! loaded.
+
+use log::*;
+use rlua::{Context, Function, Lua, MultiValue, StdLib, Table, Value};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::scripting::manifest::Manifest;
+
+/// A capability, that is declared in the manifest of a script
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Inject keys on the virtual keyboard
+    InputInjection,
+
+    /// Access the network
+    Network,
+
+    /// Read files
+    FileRead,
+
+    /// Write files to the data directory of the script
+    FileWrite,
+
+    /// Execute external programs
+    Exec,
+
+    /// Read and write the clipboard of the seat user
+    Clipboard,
+
+    /// Send and get raw feature reports of the devices
+    RawHid,
+}
+
+impl Capability {
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::InputInjection => "input-injection",
+            Capability::Network => "network",
+            Capability::FileRead => "file-read",
+            Capability::FileWrite => "file-write",
+            Capability::Exec => "exec",
+            Capability::Clipboard => "clipboard",
+            Capability::RawHid => "raw-hid",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Global functions that require a capability
+const RESTRICTED_FUNCTIONS: &[(&str, Capability)] = &[
+    ("inject_key", Capability::InputInjection),
+    ("inject_with_modifiers", Capability::InputInjection),
+    ("abort_macro", Capability::InputInjection),
+    ("http_get", Capability::Network),
+    ("fs_read", Capability::FileRead),
+    ("fs_write", Capability::FileWrite),
+    ("exec_async", Capability::Exec),
+    ("clipboard_get", Capability::Clipboard),
+    ("clipboard_set", Capability::Clipboard),
+    ("hid_send_feature", Capability::RawHid),
+    ("hid_get_feature", Capability::RawHid),
+];
+
+/// Functions of the `os` library that are available to all scripts
+const SAFE_OS_FUNCTIONS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// Functions of the `io` library that are available to all scripts that have been
+/// granted a capability that requires the `io` library. `io.close` is missing,
+/// without an argument it closes the standard output of the daemon
+const SAFE_IO_FUNCTIONS: &[&str] = &["type"];
+
+/// Functions of the `io` library that require a capability. `io.open` is added
+/// separately, it may only open files for reading
+const IO_FUNCTIONS: &[(&str, Capability)] = &[
+    ("lines", Capability::FileRead),
+    ("input", Capability::FileRead),
+    ("read", Capability::FileRead),
+    ("popen", Capability::Exec),
+];
+
+/// Global functions of the base library that require a capability
+const BASE_FUNCTIONS: &[(&str, Capability)] = &[
+    ("dofile", Capability::FileRead),
+    ("loadfile", Capability::FileRead),
+];
+
+/// The name of the registry table, that holds the modules loaded via `require`
+const MODULES_KEY: &str = "eruption.modules";
+
+/// Returns true if the script `manifest` has been granted the capability `capability`
+pub fn is_granted(manifest: &Manifest, capability: Capability) -> bool {
+    manifest.permissions.contains(&capability)
+}
+
+/// Create the Lua VM of the script `manifest`. Only the libraries that are safe
+/// for all scripts are loaded: `package` and `debug` are never available, `os` is
+/// limited to functions that query the time and `io` is limited to the functions
+/// of the capabilities that have been granted. `load` only accepts text chunks
+/// and `require` only loads modules from the `lib/` directory next to the script
+pub fn new_lua(manifest: &Manifest) -> rlua::Result<Lua> {
+    let libs = StdLib::BASE
+        | StdLib::COROUTINE
+        | StdLib::TABLE
+        | StdLib::STRING
+        | StdLib::UTF8
+        | StdLib::MATH
+        | StdLib::OS;
+
+    let needs_io =
+        is_granted(manifest, Capability::FileRead) || is_granted(manifest, Capability::Exec);
+
+    let libs = if needs_io { libs | StdLib::IO } else { libs };
+
+    let lua = Lua::new_with(libs);
+
+    lua.context(|lua_ctx| -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        // os
+        let os: Table = globals.get("os")?;
+        let safe_os = lua_ctx.create_table()?;
+
+        for name in SAFE_OS_FUNCTIONS.iter() {
+            safe_os.set(*name, os.get::<_, Value>(*name)?)?;
+        }
+
+        if is_granted(manifest, Capability::Exec) {
+            safe_os.set("execute", os.get::<_, Value>("execute")?)?;
+        }
+
+        globals.set("os", safe_os)?;
+
+        // io
+        if needs_io {
+            let io: Table = globals.get("io")?;
+            let safe_io = lua_ctx.create_table()?;
+
+            for name in SAFE_IO_FUNCTIONS.iter() {
+                safe_io.set(*name, io.get::<_, Value>(*name)?)?;
+            }
+
+            for (name, capability) in IO_FUNCTIONS.iter() {
+                if is_granted(manifest, *capability) {
+                    safe_io.set(*name, io.get::<_, Value>(*name)?)?;
+                }
+            }
+
+            if is_granted(manifest, Capability::FileRead) {
+                safe_io.set("open", read_only_open(lua_ctx, io.get("open")?)?)?;
+            }
+
+            globals.set("io", safe_io)?;
+        }
+
+        // base
+        for (name, capability) in BASE_FUNCTIONS.iter() {
+            if !is_granted(manifest, *capability) {
+                globals.set(*name, Value::Nil)?;
+            }
+        }
+
+        globals.set("load", text_only_load(lua_ctx, globals.get("load")?)?)?;
+
+        let lib_dir = manifest
+            .script_file
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("lib");
+
+        lua_ctx.set_named_registry_value(MODULES_KEY, lua_ctx.create_table()?)?;
+
+        globals.set(
+            "require",
+            lua_ctx
+                .create_function(move |lua_ctx, name: String| require(lua_ctx, &lib_dir, &name))?,
+        )?;
+
+        Ok(())
+    })?;
+
+    Ok(lua)
+}
+
+/// Wrap `io.open`, so that files may only be opened for reading
+fn read_only_open<'lua>(
+    lua_ctx: Context<'lua>,
+    open: Function<'lua>,
+) -> rlua::Result<Function<'lua>> {
+    let key = lua_ctx.create_registry_value(open)?;
+
+    lua_ctx.create_function(move |lua_ctx, (path, mode): (String, Option<String>)| {
+        let mode = mode.unwrap_or_else(|| "r".to_string());
+
+        if mode != "r" && mode != "rb" {
+            return Err(rlua::Error::RuntimeError(format!(
+                "io.open: Files may only be opened for reading, invalid mode: '{}'",
+                mode
+            )));
+        }
+
+        let open: Function = lua_ctx.registry_value(&key)?;
+        open.call::<_, MultiValue>((path, mode))
+    })
+}
+
+/// Wrap `load`, so that binary chunks are rejected
+fn text_only_load<'lua>(
+    lua_ctx: Context<'lua>,
+    load: Function<'lua>,
+) -> rlua::Result<Function<'lua>> {
+    let key = lua_ctx.create_registry_value(load)?;
+
+    lua_ctx.create_function(move |lua_ctx, args: MultiValue| {
+        let mut args = args.into_vec();
+
+        // an explicit environment has to be passed on as is, even if it is nil
+        if args.len() < 3 {
+            args.resize(2, Value::Nil);
+            args.push(Value::Nil);
+        }
+
+        args[2] = Value::String(lua_ctx.create_string("t")?);
+
+        let load: Function = lua_ctx.registry_value(&key)?;
+        load.call::<_, MultiValue>(MultiValue::from_vec(args))
+    })
+}
+
+/// Returns true if `name` is a valid name of a module for `require`
+fn is_valid_module_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Load the module `name` from the directory `lib_dir`, modules are only loaded
+/// once per Lua VM
+fn require<'lua>(lua_ctx: Context<'lua>, lib_dir: &Path, name: &str) -> rlua::Result<Value<'lua>> {
+    if !is_valid_module_name(name) {
+        return Err(rlua::Error::RuntimeError(format!(
+            "require: Invalid module name: '{}'",
+            name
+        )));
+    }
+
+    let modules: Table = lua_ctx.named_registry_value(MODULES_KEY)?;
+
+    if let Some(module) = modules.get::<_, Option<Value>>(name)? {
+        return Ok(module);
+    }
+
+    let file = lib_dir.join(format!("{}.lua", name));
+    let source = fs::read_to_string(&file).map_err(|e| {
+        rlua::Error::RuntimeError(format!(
+            "require: Could not load module '{}' from {}: {}",
+            name,
+            file.display(),
+            e
+        ))
+    })?;
+
+    let module = lua_ctx
+        .load(&source)
+        .set_name(name)?
+        .call::<_, Value>(name)?;
+
+    // modules without a return value are cached as `true`, like Lua does
+    let module = match module {
+        Value::Nil => Value::Boolean(true),
+        module => module,
+    };
+
+    modules.set(name, module.clone())?;
+
+    Ok(module)
+}
+
+/// Remove all functions of Eruption from the environment of the Lua VM `lua_ctx`,
+/// that require a capability that the script `manifest` has not been granted
+pub fn apply(lua_ctx: Context, manifest: &Manifest) -> rlua::Result<()> {
+    let globals = lua_ctx.globals();
+
+    for (name, capability) in RESTRICTED_FUNCTIONS.iter() {
+        if !is_granted(manifest, *capability) {
+            globals.set(*name, rlua::Nil)?;
+        }
+    }
+
+    if !manifest.permissions.is_empty() {
+        debug!(
+            "Capabilities of script '{}': {}",
+            manifest.name,
+            describe(manifest)
+        );
+    }
+
+    Ok(())
+}
+
+/// Describe the capabilities of the script `manifest`, e.g. "input-injection, network"
+pub fn describe(manifest: &Manifest) -> String {
+    if manifest.permissions.is_empty() {
+        "none".to_string()
+    } else {
+        manifest
+            .permissions
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(permissions: &str) -> Manifest {
+        toml::from_str(&format!(
+            r#"
+            name = "Test"
+            description = "Test"
+            version = "0.0.1"
+            author = "Test"
+            min_supported_version = "0.0.1"
+            permissions = [{}]
+            "#,
+            permissions
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_is_valid_module_name() {
+        assert!(is_valid_module_name("debug"));
+        assert!(is_valid_module_name("my_lib2"));
+
+        assert!(!is_valid_module_name(""));
+        assert!(!is_valid_module_name("../secret"));
+        assert!(!is_valid_module_name("a.b"));
+        assert!(!is_valid_module_name("/etc/passwd"));
+    }
+
+    #[test]
+    fn test_new_lua_no_capabilities() {
+        let lua = new_lua(&manifest("")).unwrap();
+
+        lua.context(|lua_ctx| {
+            let result: (bool, bool, bool, bool, bool, bool) = lua_ctx
+                .load(
+                    r#"return package == nil, debug == nil, io == nil, os.execute == nil,
+                        os.exit == nil, dofile == nil"#,
+                )
+                .eval()
+                .unwrap();
+
+            assert_eq!(result, (true, true, true, true, true, true));
+
+            let time: bool = lua_ctx.load("return os.time() > 0").eval().unwrap();
+            assert!(time);
+        });
+    }
+
+    #[test]
+    fn test_new_lua_file_read() {
+        let lua = new_lua(&manifest(r#""file-read""#)).unwrap();
+
+        lua.context(|lua_ctx| {
+            let result: (bool, bool, bool) = lua_ctx
+                .load(r#"return io.open ~= nil, io.popen == nil, io.output == nil"#)
+                .eval()
+                .unwrap();
+
+            assert_eq!(result, (true, true, true));
+
+            assert!(lua_ctx
+                .load(r#"io.open("/tmp/eruption-test", "w")"#)
+                .exec()
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_load_text_only() {
+        let lua = new_lua(&manifest("")).unwrap();
+
+        lua.context(|lua_ctx| {
+            let text: i32 = lua_ctx
+                .load(r#"return load("return 42")()"#)
+                .eval()
+                .unwrap();
+            assert_eq!(text, 42);
+
+            let env: bool = lua_ctx
+                .load(r#"return load("return x", "chunk", "b", { x = true })()"#)
+                .eval()
+                .unwrap();
+            assert!(env);
+
+            let binary: bool = lua_ctx
+                .load(r#"return load(string.dump(function() end)) == nil"#)
+                .eval()
+                .unwrap();
+            assert!(binary);
+        });
+    }
+
+    #[test]
+    fn test_require_invalid_name() {
+        let lua = new_lua(&manifest("")).unwrap();
+
+        lua.context(|lua_ctx| {
+            assert!(lua_ctx.load(r#"require("../../etc/x")"#).exec().is_err());
+        });
+    }
+}
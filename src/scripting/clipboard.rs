@@ -0,0 +1,368 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Access to the clipboard of the user of the active session on our seat, e.g.
+//! for macros that transform the current selection. The clipboard is owned by the
+//! session of the user, so the daemon runs the tools of wl-clipboard (`wl-paste`,
+//! `wl-copy`) or `xclip` with the identity and the environment of that user.
+//!
+//! Access is denied while the session is locked, and the tools are killed if they
+//! don't finish within a short timeout, since they block the calling script.
+
+use log::*;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::paths::{self, SeatUser};
+use crate::plugins::SessionPlugin;
+
+pub type Result<T> = std::result::Result<T, ClipboardError>;
+
+#[derive(Debug, Error)]
+pub enum ClipboardError {
+    #[error("No user is active on the seat")]
+    NoSeatUser {},
+
+    #[error("The session is locked")]
+    SessionLocked {},
+
+    #[error("No clipboard backend is available")]
+    NoBackend {},
+
+    #[error("The text exceeds the limit of {limit} bytes")]
+    TooLarge { limit: usize },
+
+    #[error("Could not execute {program}: {description}")]
+    ExecError {
+        program: String,
+        description: String,
+    },
+
+    #[error("{program} did not finish in time")]
+    Timeout { program: String },
+}
+
+/// The tools that are used to access the clipboard
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Wayland,
+    X11,
+}
+
+/// The identity and the environment of the seat user, that the tools are run with
+struct Session {
+    uid: u32,
+    gid: u32,
+    env: Vec<(&'static str, OsString)>,
+}
+
+/// Find the socket of the Wayland compositor in the runtime directory of the user
+fn find_wayland_display(runtime_dir: &Path) -> Option<OsString> {
+    let mut sockets: Vec<OsString> = fs::read_dir(runtime_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .filter(|name| {
+            let name = name.to_string_lossy();
+            name.starts_with("wayland-") && !name.ends_with(".lock")
+        })
+        .collect();
+
+    sockets.sort();
+    sockets.into_iter().next()
+}
+
+/// Find the authority file of the X server, as created by the display manager
+/// or by Xwayland
+fn find_xauthority(user: &SeatUser, runtime_dir: &Path) -> Option<PathBuf> {
+    let in_runtime_dir = fs::read_dir(runtime_dir).ok().and_then(|entries| {
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with("xauth_") || name.starts_with(".mutter-Xwaylandauth.")
+            })
+    });
+
+    in_runtime_dir.or_else(|| {
+        let path = user.home.join(".Xauthority");
+
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    })
+}
+
+fn get_session() -> Result<(Session, Backend)> {
+    let user = paths::get_seat_user().ok_or(ClipboardError::NoSeatUser {})?;
+
+    if SessionPlugin::is_locked() {
+        return Err(ClipboardError::SessionLocked {});
+    }
+
+    let (backend, display) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config
+                .get_str("clipboard.backend")
+                .unwrap_or_else(|_| "auto".into()),
+            config
+                .get_str("clipboard.display")
+                .unwrap_or_else(|_| constants::DEFAULT_CLIPBOARD_DISPLAY.into()),
+        )
+    };
+
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", user.uid));
+    let wayland_display = find_wayland_display(&runtime_dir);
+
+    let backend = match backend.trim() {
+        "wayland" => Backend::Wayland,
+        "x11" => Backend::X11,
+
+        "auto" if wayland_display.is_some() => Backend::Wayland,
+        "auto" => Backend::X11,
+
+        other => {
+            warn!("Invalid clipboard backend: {}", other);
+            return Err(ClipboardError::NoBackend {});
+        }
+    };
+
+    let mut env = vec![
+        ("HOME", user.home.clone().into_os_string()),
+        ("USER", user.name.clone().into()),
+        ("XDG_RUNTIME_DIR", runtime_dir.clone().into_os_string()),
+        ("PATH", "/usr/local/bin:/usr/bin:/bin".into()),
+    ];
+
+    match backend {
+        Backend::Wayland => {
+            let wayland_display = wayland_display.ok_or(ClipboardError::NoBackend {})?;
+            env.push(("WAYLAND_DISPLAY", wayland_display));
+        }
+
+        Backend::X11 => {
+            env.push(("DISPLAY", display.into()));
+
+            if let Some(xauthority) = find_xauthority(&user, &runtime_dir) {
+                env.push(("XAUTHORITY", xauthority.into_os_string()));
+            }
+        }
+    }
+
+    let session = Session {
+        uid: user.uid,
+        gid: user.gid().unwrap_or(user.uid),
+        env,
+    };
+
+    Ok((session, backend))
+}
+
+fn spawn(
+    session: &Session,
+    program: &str,
+    args: &[&str],
+    stdin: Stdio,
+    stdout: Stdio,
+) -> Result<Child> {
+    Command::new(program)
+        .args(args)
+        .env_clear()
+        .envs(session.env.iter().cloned())
+        .uid(session.uid)
+        .gid(session.gid)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| ClipboardError::ExecError {
+            program: program.into(),
+            description: format!("{}", e),
+        })
+}
+
+/// Wait for the tool `child` to finish, it is killed after the timeout
+fn wait(child: &mut Child, program: &str) -> Result<bool> {
+    let deadline = Instant::now() + Duration::from_millis(constants::CLIPBOARD_TIMEOUT_MILLIS);
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status.success()),
+
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(ClipboardError::Timeout {
+                    program: program.into(),
+                });
+            }
+
+            Ok(None) => thread::sleep(Duration::from_millis(5)),
+
+            Err(e) => {
+                return Err(ClipboardError::ExecError {
+                    program: program.into(),
+                    description: format!("{}", e),
+                })
+            }
+        }
+    }
+}
+
+/// Get the text on the clipboard, returns None if the clipboard is empty or holds
+/// no text
+pub fn get() -> Result<Option<String>> {
+    let (session, backend) = get_session()?;
+
+    let (program, args): (&str, &[&str]) = match backend {
+        Backend::Wayland => ("wl-paste", &["--no-newline", "--type", "text"]),
+        Backend::X11 => ("xclip", &["-selection", "clipboard", "-out"]),
+    };
+
+    let mut child = spawn(&session, program, args, Stdio::null(), Stdio::piped())?;
+
+    // read on a separate thread, so that a tool that fills the pipe is not blocked
+    let stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut buf = vec![];
+
+        stdout
+            .take(constants::MAX_CLIPBOARD_SIZE as u64 + 1)
+            .read_to_end(&mut buf)
+            .map(|_| buf)
+    });
+
+    let success = wait(&mut child, program)?;
+
+    let buf = reader
+        .join()
+        .map_err(|_| ClipboardError::ExecError {
+            program: program.into(),
+            description: "Could not read the output".into(),
+        })?
+        .map_err(|e| ClipboardError::ExecError {
+            program: program.into(),
+            description: format!("{}", e),
+        })?;
+
+    if buf.len() > constants::MAX_CLIPBOARD_SIZE {
+        return Err(ClipboardError::TooLarge {
+            limit: constants::MAX_CLIPBOARD_SIZE,
+        });
+    }
+
+    // the tools fail if the clipboard is empty
+    if !success {
+        return Ok(None);
+    }
+
+    Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+}
+
+/// Put the text `text` on the clipboard
+pub fn set(text: &str) -> Result<()> {
+    if text.len() > constants::MAX_CLIPBOARD_SIZE {
+        return Err(ClipboardError::TooLarge {
+            limit: constants::MAX_CLIPBOARD_SIZE,
+        });
+    }
+
+    let (session, backend) = get_session()?;
+
+    // both tools fork, the child serves the clipboard until it is replaced
+    let (program, args): (&str, &[&str]) = match backend {
+        Backend::Wayland => ("wl-copy", &["--type", "text/plain"]),
+        Backend::X11 => ("xclip", &["-selection", "clipboard", "-in"]),
+    };
+
+    let mut child = spawn(&session, program, args, Stdio::piped(), Stdio::null())?;
+
+    // write on a separate thread, so that the timeout applies to a tool that does
+    // not read its input as well. The pipe is closed, when the text is written
+    let mut stdin = child.stdin.take().unwrap();
+    let text = text.to_string();
+    let writer = thread::spawn(move || stdin.write_all(text.as_bytes()));
+
+    let result = wait(&mut child, program);
+
+    let written = writer.join().map_err(|_| ClipboardError::ExecError {
+        program: program.into(),
+        description: "Could not write the input".into(),
+    })?;
+
+    // a timeout takes precedence over the broken pipe of the killed tool
+    let success = result?;
+
+    written.map_err(|e| ClipboardError::ExecError {
+        program: program.into(),
+        description: format!("{}", e),
+    })?;
+
+    if success {
+        Ok(())
+    } else {
+        Err(ClipboardError::ExecError {
+            program: program.into(),
+            description: "Exited with an error".into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_wayland_display() {
+        let dir =
+            std::env::temp_dir().join(format!("eruption-test-clipboard-{}", std::process::id()));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        // no socket
+        fs::write(dir.join("bus"), b"").unwrap();
+        assert_eq!(find_wayland_display(&dir), None);
+
+        // lock files are skipped, the first socket is picked
+        fs::write(dir.join("wayland-1"), b"").unwrap();
+        fs::write(dir.join("wayland-0.lock"), b"").unwrap();
+        fs::write(dir.join("wayland-0"), b"").unwrap();
+        assert_eq!(
+            find_wayland_display(&dir),
+            Some(OsString::from("wayland-0"))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(find_wayland_display(&dir), None);
+    }
+}
@@ -0,0 +1,313 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Commands that are executed on behalf of Lua scripts. Commands are executed by a
+//! small pool of worker threads, so that they never block the interpreters or the
+//! render loop. The exit status and the standard output are delivered to the
+//! script that issued the command, via the event handler
+//! `on_exec_finished(id, status, stdout)`.
+//!
+//! Commands are not executed by a shell, the arguments are passed verbatim. They
+//! are run with the identity of the user of the active session on our seat, with
+//! a minimal environment and the home directory of the user as the working
+//! directory. Commands are refused while no user is active on the seat.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::paths;
+use crate::scripting::script::{self, ScriptId};
+
+pub type Result<T> = std::result::Result<T, CommandError>;
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("Rate limit exceeded")]
+    RateLimitExceeded {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+
+    #[error("No user is active on the seat")]
+    NoSeatUser {},
+}
+
+/// A command, that is queued for the worker threads
+struct Request {
+    id: u64,
+    script: ScriptId,
+    program: String,
+    args: Vec<String>,
+}
+
+lazy_static! {
+    /// Queue of the worker threads, they are spawned on the first command
+    static ref REQUEST_TX: Mutex<Option<Sender<Request>>> = Mutex::new(None);
+
+    /// Points in time of the recent commands, indexed by the layer of the script
+    static ref RECENT_COMMANDS: Mutex<HashMap<usize, VecDeque<Instant>>> = Mutex::new(HashMap::new());
+}
+
+/// The id of the next command
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns true if the script on layer `layer` may execute another command, and
+/// records the command
+fn check_rate_limit(layer: usize, now: Instant) -> bool {
+    let window = Duration::from_secs(60);
+
+    let mut recent_commands = RECENT_COMMANDS.lock();
+    let commands = recent_commands.entry(layer).or_insert_with(VecDeque::new);
+
+    while commands
+        .front()
+        .map_or(false, |t| now.duration_since(*t) > window)
+    {
+        commands.pop_front();
+    }
+
+    if commands.len() >= constants::EXEC_MAX_COMMANDS_PER_MINUTE {
+        return false;
+    }
+
+    commands.push_back(now);
+
+    true
+}
+
+/// Execute the command `request`, returns the exit status and the standard output.
+/// The status is -1 if the command could not be executed, was terminated by a
+/// signal, or has been killed after the timeout
+fn execute(request: &Request) -> (i32, String) {
+    // the seat user may have changed since the command has been queued
+    let user = match paths::get_seat_user() {
+        Some(user) => user,
+        None => return (-1, format!("{}", CommandError::NoSeatUser {})),
+    };
+
+    let mut command = Command::new(&request.program);
+
+    // the command runs in a process group of its own, so that the processes it
+    // starts are killed along with it after the timeout
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+
+    let mut child = match command
+        .args(&request.args)
+        .env_clear()
+        .env("HOME", &user.home)
+        .env("USER", &user.name)
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .current_dir(&user.home)
+        .uid(user.uid)
+        .gid(user.gid().unwrap_or(user.uid))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return (-1, format!("{}", e)),
+    };
+
+    // the output beyond the limit is discarded, so that the command is not blocked
+    let mut stdout = child.stdout.take().unwrap();
+    let (output_tx, output_rx) = channel();
+
+    thread::spawn(move || {
+        let mut buf = vec![];
+
+        (&mut stdout)
+            .take(constants::EXEC_MAX_OUTPUT_SIZE as u64)
+            .read_to_end(&mut buf)
+            .and_then(|_| io::copy(&mut stdout, &mut io::sink()))
+            .unwrap_or_else(|e| {
+                warn!("Could not read the output of a command: {}", e);
+                0
+            });
+
+        output_tx.send(buf).ok();
+    });
+
+    let timeout = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get::<u64>("exec.timeout_millis").ok())
+        .unwrap_or(constants::EXEC_TIMEOUT_MILLIS);
+
+    let deadline = Instant::now() + Duration::from_millis(timeout);
+
+    let (status, finished) = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break (status.code().unwrap_or(-1), true),
+
+            Ok(None) if Instant::now() >= deadline => {
+                warn!(
+                    "Command {}: {} did not finish within {}ms",
+                    request.id, request.program, timeout
+                );
+
+                // the process group has the pid of the command
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+
+                let _ = child.kill();
+                let _ = child.wait();
+
+                break (-1, false);
+            }
+
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+
+            Err(e) => {
+                error!("Could not wait for command {}: {}", request.id, e);
+                break (-1, false);
+            }
+        }
+    };
+
+    // processes that have been started by the command may still hold the pipe open,
+    // so the output is awaited until the deadline only
+    let stdout = if finished {
+        output_rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .map(|buf| String::from_utf8_lossy(&buf).into_owned())
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    (status, stdout)
+}
+
+fn run_worker(rx: Arc<Mutex<Receiver<Request>>>) {
+    loop {
+        let request = match rx.lock().recv() {
+            Ok(request) => request,
+            Err(_e) => break,
+        };
+
+        debug!(
+            "Command {}: {} {}",
+            request.id,
+            request.program,
+            request.args.join(" ")
+        );
+
+        let (status, stdout) = execute(&request);
+
+        debug!("Command {}: Status {}", request.id, status);
+
+        // the script may have been unloaded in the meantime
+        request
+            .script
+            .send(script::Message::ExecFinished(request.id, status, stdout));
+    }
+}
+
+fn spawn_workers() -> Result<Sender<Request>> {
+    let (tx, rx) = channel();
+    let rx = Arc::new(Mutex::new(rx));
+
+    for index in 0..constants::EXEC_WORKER_THREADS {
+        let rx = rx.clone();
+
+        thread::Builder::new()
+            .name(format!("exec/{}", index))
+            .spawn(move || run_worker(rx))
+            .map_err(|_e| CommandError::ThreadSpawnError {})?;
+    }
+
+    Ok(tx)
+}
+
+/// Queue the command `program` with the arguments `args`, on behalf of the script
+/// `script`. Returns the id of the command, that is passed to `on_exec_finished`
+pub fn exec_async(script: ScriptId, program: &str, args: Vec<String>) -> Result<u64> {
+    if paths::get_seat_user().is_none() {
+        return Err(CommandError::NoSeatUser {});
+    }
+
+    if !check_rate_limit(script.layer, Instant::now()) {
+        return Err(CommandError::RateLimitExceeded {});
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+
+    let mut request_tx = REQUEST_TX.lock();
+
+    if request_tx.is_none() {
+        *request_tx = Some(spawn_workers()?);
+    }
+
+    request_tx
+        .as_ref()
+        .unwrap()
+        .send(Request {
+            id,
+            script,
+            program: program.to_string(),
+            args,
+        })
+        .unwrap_or_else(|e| error!("Send error: {}", e));
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_rate_limit() {
+        // layers that are not used by scripts
+        let layer = usize::max_value();
+        let other_layer = usize::max_value() - 1;
+
+        let start = Instant::now();
+
+        for _ in 0..constants::EXEC_MAX_COMMANDS_PER_MINUTE {
+            assert!(check_rate_limit(layer, start));
+        }
+
+        assert!(!check_rate_limit(layer, start + Duration::from_secs(30)));
+
+        // the limit applies per layer
+        assert!(check_rate_limit(other_layer, start));
+
+        // the window has passed
+        assert!(check_rate_limit(layer, start + Duration::from_secs(61)));
+    }
+}
@@ -0,0 +1,550 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Breakpoints and single-stepping for Lua scripts. If the debugger is enabled,
+//! a line hook is installed into each Lua VM, that pauses the script when it
+//! reaches a breakpoint. While a script is paused, its global and local variables
+//! may be inspected via the `org.eruption.Debugger` interface, and it may be resumed
+//! or stepped line by line. A paused script is resumed after a timeout, since the
+//! main loop waits for the event handlers of the scripts.
+//!
+//! The `debug` library of Lua is loaded privately: `debug.getinfo` and
+//! `debug.getlocal` are kept in the registry, and the `debug` global is removed
+//! before the script is loaded, so scripts can not use it.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Condvar, Mutex, RwLock};
+use rlua::{Context, Function, HookTriggers, Lua, StdLib, Table, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::repl;
+
+/// Name of the registry value, that holds the names of the globals that have been
+/// defined before the script has been loaded
+const BUILTINS_KEY: &str = "eruption.debugger.builtins";
+
+/// Name of the registry value, that holds `debug.getinfo`
+const GETINFO_KEY: &str = "eruption.debugger.getinfo";
+
+/// Name of the registry value, that holds `debug.getlocal`
+const GETLOCAL_KEY: &str = "eruption.debugger.getlocal";
+
+/// Maximum depth of the call stack that is searched for the paused function
+const MAX_STACK_LEVEL: i64 = 32;
+
+/// A script that is paused at a breakpoint, or after a step
+#[derive(Debug, Clone)]
+pub struct PausedScript {
+    pub script: String,
+    pub line: u32,
+
+    /// Names and formatted values of the global variables of the script
+    pub variables: Vec<(String, String)>,
+
+    /// Names and formatted values of the local variables of the paused function
+    pub locals: Vec<(String, String)>,
+}
+
+/// How to resume a paused script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Continue,
+    Step,
+}
+
+lazy_static! {
+    /// Set if the debugger is enabled by the configuration
+    static ref ENABLED: AtomicBool = AtomicBool::new(false);
+
+    /// Set if a breakpoint is set or a script is stepped, so that the line hook
+    /// returns early otherwise
+    static ref ARMED: AtomicBool = AtomicBool::new(false);
+
+    /// Breakpoints, as the name of the script and the line
+    static ref BREAKPOINTS: RwLock<HashSet<(String, u32)>> = RwLock::new(HashSet::new());
+
+    /// The script that pauses on its next line, after a step
+    static ref STEPPING: Mutex<Option<String>> = Mutex::new(None);
+
+    /// The paused script, only one script is paused at a time
+    static ref PAUSED: Mutex<Option<PausedScript>> = Mutex::new(None);
+
+    /// The command that resumes the paused script
+    static ref COMMAND: (Mutex<Option<Command>>, Condvar) = (Mutex::new(None), Condvar::new());
+}
+
+fn update_armed() {
+    let armed = !BREAKPOINTS.read().is_empty() || STEPPING.lock().is_some();
+
+    ARMED.store(armed, Ordering::SeqCst);
+}
+
+/// Enable the debugger, if requested by the configuration
+pub fn initialize() {
+    let enabled = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get_bool("debugger.enabled").ok())
+        .unwrap_or(false);
+
+    if enabled {
+        warn!("The debugger is enabled, scripts are slowed down by the line hook");
+    }
+
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns true if the debugger is enabled
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Get the name of the main chunk of the script `file`, as shown in the source of
+/// its functions. Lines of other chunks, like required modules, never pause
+pub fn chunk_name(file: &Path) -> String {
+    format!("@{}", file.display())
+}
+
+/// Install the line hook into the Lua VM `lua` of the script `script_name`, if the
+/// debugger is enabled. `chunk` is the name of the main chunk of the script, see
+/// `chunk_name()`
+pub fn attach(lua: &Lua, script_name: &str, chunk: &str) -> rlua::Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+
+    // the debug library is required to read locals, keep the functions we need
+    // in the registry and hide the library from the script
+    unsafe {
+        lua.unsafe_load_from_std_lib(StdLib::DEBUG)?;
+    }
+
+    lua.context(|lua_ctx| {
+        let debug: Table = lua_ctx.globals().get("debug")?;
+
+        lua_ctx.set_named_registry_value(GETINFO_KEY, debug.get::<_, Function>("getinfo")?)?;
+        lua_ctx.set_named_registry_value(GETLOCAL_KEY, debug.get::<_, Function>("getlocal")?)?;
+
+        lua_ctx.globals().set("debug", Value::Nil)
+    })?;
+
+    let script_name = script_name.to_string();
+    let chunk = chunk.to_string();
+
+    lua.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |lua_ctx, debug| {
+            if ARMED.load(Ordering::SeqCst) {
+                let line = debug.curr_line();
+
+                if line > 0 && debug.source().source == Some(chunk.as_bytes()) {
+                    on_line(lua_ctx, &script_name, &chunk, line as u32);
+                }
+            }
+
+            Ok(())
+        },
+    );
+
+    Ok(())
+}
+
+/// Record the names of the globals of the Lua VM `lua_ctx`, like the functions of
+/// the API. Must be called before the script is loaded, the globals that are
+/// defined later on are the variables of the script
+pub fn record_builtins(lua_ctx: Context) -> rlua::Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+
+    let builtins = lua_ctx.create_table()?;
+
+    for pair in lua_ctx.globals().pairs::<Value, Value>() {
+        let (name, _) = pair?;
+        builtins.set(name, true)?;
+    }
+
+    lua_ctx.set_named_registry_value(BUILTINS_KEY, builtins)
+}
+
+/// Get the global variables of the script that is executed by the Lua VM, sorted
+/// by name. Functions are skipped, like the event handlers
+fn get_variables(lua_ctx: Context) -> Vec<(String, String)> {
+    let mut result = vec![];
+
+    let builtins: Table = match lua_ctx.named_registry_value(BUILTINS_KEY) {
+        Ok(builtins) => builtins,
+        Err(_) => return result,
+    };
+
+    for pair in lua_ctx.globals().pairs::<String, Value>() {
+        let (name, value) = match pair {
+            Ok(pair) => pair,
+            Err(_) => continue,
+        };
+
+        if let Value::Function(_) = value {
+            continue;
+        }
+
+        match builtins.get::<_, Value>(name.as_str()) {
+            Ok(Value::Nil) => (),
+            _ => continue,
+        }
+
+        result.push((name, repl::format_value(&value, false)));
+    }
+
+    result.sort();
+    result.truncate(constants::DEBUGGER_MAX_VARIABLES);
+
+    result
+}
+
+/// Get the local variables of the function of the chunk `chunk`, that executes
+/// the line `line`, in the order of their declaration. Temporaries of Lua, whose
+/// names start with a parenthesis, are skipped
+fn get_locals(lua_ctx: Context, chunk: &str, line: u32) -> Vec<(String, String)> {
+    let mut result = vec![];
+
+    let (getinfo, getlocal): (Function, Function) = match (
+        lua_ctx.named_registry_value(GETINFO_KEY),
+        lua_ctx.named_registry_value(GETLOCAL_KEY),
+    ) {
+        (Ok(getinfo), Ok(getlocal)) => (getinfo, getlocal),
+        _ => return result,
+    };
+
+    // the level of the paused function depends on the frames between the hook and
+    // the call of `getinfo`, so search for it
+    let level = (1..=MAX_STACK_LEVEL).find(|level| {
+        match getinfo.call::<_, Option<Table>>((*level, "Sl")) {
+            Ok(Some(info)) => {
+                info.get::<_, String>("source").ok().as_deref() == Some(chunk)
+                    && info.get::<_, i64>("currentline").ok() == Some(line as i64)
+            }
+
+            _ => false,
+        }
+    });
+
+    let level = match level {
+        Some(level) => level,
+        None => return result,
+    };
+
+    for index in 1.. {
+        let (name, value) = match getlocal.call::<_, (Option<String>, Value)>((level, index)) {
+            Ok((Some(name), value)) => (name, value),
+            _ => break,
+        };
+
+        if name.starts_with('(') {
+            continue;
+        }
+
+        result.push((name, repl::format_value(&value, false)));
+
+        if result.len() >= constants::DEBUGGER_MAX_VARIABLES {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Returns true if the line `line` of the script `script` has a breakpoint
+fn is_breakpoint(script: &str, line: u32) -> bool {
+    BREAKPOINTS.read().contains(&(script.to_string(), line))
+}
+
+/// Called by the line hook, pause the script `script` if it reached a breakpoint,
+/// or if it is stepped
+fn on_line(lua_ctx: Context, script: &str, chunk: &str, line: u32) {
+    let step = STEPPING.lock().as_deref() == Some(script);
+
+    if !step && !is_breakpoint(script, line) {
+        return;
+    }
+
+    let timeout = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get::<u64>("debugger.pause_timeout_secs").ok())
+        .unwrap_or(constants::DEBUGGER_PAUSE_TIMEOUT_SECS);
+
+    let locals = get_locals(lua_ctx, chunk, line);
+
+    pause(lua_ctx, script, line, locals, Duration::from_secs(timeout));
+}
+
+/// Pause the script `script` at the line `line`, until it is resumed or `timeout`
+/// elapsed. Returns the command that resumed the script, or None after a timeout
+/// or if another script is paused already
+fn pause(
+    lua_ctx: Context,
+    script: &str,
+    line: u32,
+    locals: Vec<(String, String)>,
+    timeout: Duration,
+) -> Option<Command> {
+    {
+        let mut paused = PAUSED.lock();
+
+        if paused.is_some() {
+            return None;
+        }
+
+        // discard a command, that has been issued while no script was paused
+        *COMMAND.0.lock() = None;
+
+        *paused = Some(PausedScript {
+            script: script.to_string(),
+            line,
+            variables: get_variables(lua_ctx),
+            locals,
+        });
+    }
+
+    *STEPPING.lock() = None;
+    update_armed();
+
+    info!("Paused script '{}' at line {}", script, line);
+
+    let deadline = Instant::now() + timeout;
+
+    let command = {
+        let (command, condvar) = &*COMMAND;
+        let mut command = command.lock();
+
+        while command.is_none() {
+            if condvar.wait_until(&mut command, deadline).timed_out() {
+                break;
+            }
+        }
+
+        command.take()
+    };
+
+    *PAUSED.lock() = None;
+
+    match command {
+        Some(Command::Step) => {
+            *STEPPING.lock() = Some(script.to_string());
+            update_armed();
+        }
+
+        Some(Command::Continue) => info!("Resumed script '{}'", script),
+
+        None => warn!(
+            "Resumed script '{}' after a timeout of {}s",
+            script,
+            timeout.as_secs()
+        ),
+    }
+
+    command
+}
+
+/// Set a breakpoint at the line `line` of the script `script`, as named by its
+/// manifest. Returns false if the breakpoint has already been set
+pub fn set_breakpoint(script: &str, line: u32) -> bool {
+    let inserted = BREAKPOINTS.write().insert((script.to_string(), line));
+    update_armed();
+
+    inserted
+}
+
+/// Clear the breakpoint at the line `line` of the script `script`, or all
+/// breakpoints of the script if `line` is 0. Returns false if no breakpoint has
+/// been found
+pub fn clear_breakpoint(script: &str, line: u32) -> bool {
+    let removed = {
+        let mut breakpoints = BREAKPOINTS.write();
+        let len = breakpoints.len();
+
+        breakpoints.retain(|(s, l)| !(s == script && (line == 0 || *l == line)));
+
+        breakpoints.len() != len
+    };
+
+    update_armed();
+
+    removed
+}
+
+/// Get all breakpoints, sorted by script and line
+pub fn get_breakpoints() -> Vec<(String, u32)> {
+    let mut result: Vec<(String, u32)> = BREAKPOINTS.read().iter().cloned().collect();
+    result.sort();
+
+    result
+}
+
+/// Get the paused script, if any
+pub fn get_paused() -> Option<PausedScript> {
+    PAUSED.lock().clone()
+}
+
+/// Resume the paused script with `command`. Returns false if no script is paused
+pub fn resume(command: Command) -> bool {
+    if PAUSED.lock().is_none() {
+        return false;
+    }
+
+    let (pending, condvar) = &*COMMAND;
+
+    *pending.lock() = Some(command);
+    condvar.notify_all();
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_breakpoints() {
+        assert!(set_breakpoint("test-breakpoints", 3));
+        assert!(!set_breakpoint("test-breakpoints", 3));
+        assert!(set_breakpoint("test-breakpoints", 5));
+
+        assert!(is_breakpoint("test-breakpoints", 3));
+        assert!(!is_breakpoint("test-breakpoints", 4));
+        assert!(get_breakpoints().contains(&("test-breakpoints".to_string(), 5)));
+
+        assert!(clear_breakpoint("test-breakpoints", 3));
+        assert!(!clear_breakpoint("test-breakpoints", 3));
+        assert!(is_breakpoint("test-breakpoints", 5));
+
+        assert!(set_breakpoint("test-breakpoints", 7));
+        assert!(clear_breakpoint("test-breakpoints", 0));
+        assert!(!get_breakpoints()
+            .iter()
+            .any(|(script, _)| script == "test-breakpoints"));
+    }
+
+    #[test]
+    fn test_pause() {
+        let lua = Lua::new();
+
+        lua.context(|lua_ctx| {
+            // nothing is paused yet
+            assert!(!resume(Command::Continue));
+
+            let resumer = thread::spawn(|| loop {
+                if resume(Command::Step) {
+                    break;
+                }
+
+                thread::sleep(Duration::from_millis(10));
+            });
+
+            let locals = vec![("x".to_string(), "1".to_string())];
+            let command = pause(lua_ctx, "test-pause", 3, locals, Duration::from_secs(10));
+
+            resumer.join().unwrap();
+
+            assert_eq!(command, Some(Command::Step));
+            assert!(get_paused().is_none());
+            assert_eq!(STEPPING.lock().as_deref(), Some("test-pause"));
+
+            *STEPPING.lock() = None;
+            update_armed();
+
+            // not resumed, the timeout elapses
+            let start = Instant::now();
+            let command = pause(lua_ctx, "test-pause", 4, vec![], Duration::from_millis(50));
+
+            assert_eq!(command, None);
+            assert!(start.elapsed() >= Duration::from_millis(50));
+            assert!(get_paused().is_none());
+            assert!(!resume(Command::Continue));
+        });
+    }
+
+    #[test]
+    fn test_get_locals() {
+        let lua = Lua::new();
+
+        unsafe {
+            lua.unsafe_load_from_std_lib(StdLib::DEBUG).unwrap();
+        }
+
+        lua.context(|lua_ctx| {
+            let debug: Table = lua_ctx.globals().get("debug").unwrap();
+
+            lua_ctx
+                .set_named_registry_value(GETINFO_KEY, debug.get::<_, Function>("getinfo").unwrap())
+                .unwrap();
+            lua_ctx
+                .set_named_registry_value(
+                    GETLOCAL_KEY,
+                    debug.get::<_, Function>("getlocal").unwrap(),
+                )
+                .unwrap();
+
+            let inspect = lua_ctx
+                .create_function(|lua_ctx, line: u32| {
+                    let locals = get_locals(lua_ctx, "@test.lua", line);
+
+                    Ok(locals.into_iter().map(|(name, _)| name).collect::<Vec<_>>())
+                })
+                .unwrap();
+
+            lua_ctx.globals().set("inspect", inspect).unwrap();
+
+            // not a tail call, so that the frame of the chunk is kept
+            let locals: Vec<String> = lua_ctx
+                .load("local a = 1\nlocal b = \"x\"\nlocal r = inspect(3)\nreturn r")
+                .set_name("@test.lua")
+                .unwrap()
+                .eval()
+                .unwrap();
+
+            assert_eq!(locals, vec!["a".to_string(), "b".to_string()]);
+
+            // lines of other chunks are not found
+            let locals: Vec<String> = lua_ctx
+                .load("local c = 1\nlocal r = inspect(2)\nreturn r")
+                .set_name("@other.lua")
+                .unwrap()
+                .eval()
+                .unwrap();
+
+            assert!(locals.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_chunk_name() {
+        assert_eq!(
+            chunk_name(Path::new("/usr/share/eruption/scripts/shockwave.lua")),
+            "@/usr/share/eruption/scripts/shockwave.lua"
+        );
+    }
+}
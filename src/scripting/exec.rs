@@ -0,0 +1,450 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A runner for effects that are implemented by an external program, e.g. a Python
+//! or JavaScript script. The manifest of the effect specifies the command line via
+//! `exec`, the script file is appended as the last argument. An empty command line
+//! executes the script file itself. The program runs with the privileges of the
+//! user of the active session on our seat, it is not started if no user is active.
+//!
+//! The daemon and the program exchange JSON objects, one per line. Events are
+//! written to the standard input of the program:
+//!
+//! * `{"event":"startup","num_keys":144,"params":{"speed":1.5}}`
+//! * `{"event":"tick","delta":1}`
+//! * `{"event":"key_down","key_index":42}` and `{"event":"key_up","key_index":42}`
+//! * `{"event":"param_changed","name":"speed","value":2.0}`
+//! * `{"event":"quit","code":0}`
+//!
+//! The program writes its requests to its standard output:
+//!
+//! * `{"type":"color_map","colors":[4278190335, ...]}`: Submit a color map (0xAARRGGBB)
+//! * `{"type":"log","level":2,"message":"..."}`: Log a message (0: error .. 4: trace)
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::paths;
+use crate::profiles;
+use crate::rvdevice::NUM_KEYS;
+use crate::scripting::manifest::{ConfigParam, Manifest};
+use crate::scripting::overrides;
+use crate::scripting::script::{self, callbacks, Message, RunScriptResult, ScriptingError};
+use crate::scripting::ticks;
+use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
+
+/// An event that is sent to the program
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Startup {
+        num_keys: usize,
+        params: HashMap<String, serde_json::Value>,
+    },
+    Tick {
+        delta: u32,
+    },
+    KeyDown {
+        key_index: u8,
+    },
+    KeyUp {
+        key_index: u8,
+    },
+    ParamChanged {
+        name: String,
+        value: serde_json::Value,
+    },
+    Quit {
+        code: u32,
+    },
+}
+
+/// A request of the program
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    ColorMap { colors: Vec<u32> },
+    Log { level: u8, message: String },
+}
+
+fn exec_error<E: std::fmt::Display>(e: E) -> ScriptingError {
+    ScriptingError::ExecError {
+        description: format!("{}", e),
+    }
+}
+
+fn param_to_json(param: &profiles::ConfigParam) -> (String, serde_json::Value) {
+    match param {
+        profiles::ConfigParam::Int { name, value } => {
+            (name.clone(), serde_json::Value::from(*value))
+        }
+        profiles::ConfigParam::Float { name, value } => {
+            (name.clone(), serde_json::Value::from(*value))
+        }
+        profiles::ConfigParam::Bool { name, value } => {
+            (name.clone(), serde_json::Value::from(*value))
+        }
+        profiles::ConfigParam::String { name, value } => {
+            (name.clone(), serde_json::Value::from(value.as_str()))
+        }
+        profiles::ConfigParam::Color { name, value } => {
+            (name.clone(), serde_json::Value::from(*value))
+        }
+    }
+}
+
+/// Get the values of the config params of the effect, as specified by the runtime
+/// overrides, the active profile, or the default values of the manifest
+fn get_params(manifest: &Manifest) -> HashMap<String, serde_json::Value> {
+    let profile = ACTIVE_PROFILE.lock();
+    let profile = profile.as_ref();
+    let script_name = &manifest.name;
+
+    let mut result = HashMap::new();
+
+    for param in manifest.config.iter().flatten() {
+        let (name, value) = match param {
+            ConfigParam::Int { name, default, .. } => (
+                name,
+                serde_json::Value::from(
+                    *profile
+                        .and_then(|p| p.get_int_value(script_name, name))
+                        .unwrap_or(default),
+                ),
+            ),
+
+            ConfigParam::Float { name, default, .. } => (
+                name,
+                serde_json::Value::from(
+                    *profile
+                        .and_then(|p| p.get_float_value(script_name, name))
+                        .unwrap_or(default),
+                ),
+            ),
+
+            ConfigParam::Bool { name, default, .. } => (
+                name,
+                serde_json::Value::from(
+                    *profile
+                        .and_then(|p| p.get_bool_value(script_name, name))
+                        .unwrap_or(default),
+                ),
+            ),
+
+            ConfigParam::String { name, default, .. } => (
+                name,
+                serde_json::Value::from(
+                    profile
+                        .and_then(|p| p.get_str_value(script_name, name))
+                        .unwrap_or(default),
+                ),
+            ),
+
+            ConfigParam::Color { name, default, .. } => (
+                name,
+                serde_json::Value::from(
+                    *profile
+                        .and_then(|p| p.get_color_value(script_name, name))
+                        .unwrap_or(default),
+                ),
+            ),
+        };
+
+        result.insert(name.clone(), value);
+    }
+
+    // runtime overrides take precedence over the values of the profile
+    result.extend(overrides::get(script_name).iter().map(param_to_json));
+
+    result
+}
+
+/// Spawn the program of the effect `file`, as specified by its manifest. It runs
+/// as the user of the active session on our seat
+fn spawn_process(file: &PathBuf, command_line: &[String]) -> std::io::Result<Child> {
+    let user = paths::get_seat_user().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "No user is active on the seat",
+        )
+    })?;
+
+    let mut command = match command_line.split_first() {
+        Some((program, args)) => {
+            let mut command = Command::new(program);
+            command.args(args).arg(file);
+
+            command
+        }
+
+        None => Command::new(file),
+    };
+
+    if let Some(dir) = file.parent() {
+        command.current_dir(dir);
+    }
+
+    command
+        .env_clear()
+        .env("HOME", &user.home)
+        .env("USER", &user.name)
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .uid(user.uid)
+        .gid(user.gid().unwrap_or(user.uid))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+}
+
+fn write_event(stdin: &mut ChildStdin, event: &Event) -> script::Result<()> {
+    let line = serde_json::to_string(event).map_err(exec_error)?;
+
+    writeln!(stdin, "{}", line).map_err(exec_error)?;
+    stdin.flush().map_err(exec_error)?;
+
+    Ok(())
+}
+
+/// Write the events that are queued to `events_rx` to the standard input of the
+/// program, on a thread of its own, so that a program that does not read its input
+/// never blocks the event loop
+fn spawn_writer(
+    mut stdin: ChildStdin,
+    events_rx: Receiver<Event>,
+    layer: usize,
+) -> script::Result<thread::JoinHandle<()>> {
+    thread::Builder::new()
+        .name(format!("exec-writer/{}", layer))
+        .spawn(move || {
+            for event in events_rx.iter() {
+                if let Err(e) = write_event(&mut stdin, &event) {
+                    debug!("Could not write to the external effect: {}", e);
+                    break;
+                }
+            }
+        })
+        .map_err(exec_error)
+}
+
+/// Queue the event `event` for the program. Events are dropped, while the queue is
+/// full, an error is returned if the program closed its standard input
+fn send_event(events_tx: &SyncSender<Event>, event: Event) -> script::Result<()> {
+    match events_tx.try_send(event) {
+        Ok(()) => Ok(()),
+
+        Err(TrySendError::Full(event)) => {
+            warn!(
+                "The external effect does not read its events, dropped: {:?}",
+                event
+            );
+
+            Ok(())
+        }
+
+        Err(TrySendError::Disconnected(_)) => {
+            Err(exec_error("The external effect closed its standard input"))
+        }
+    }
+}
+
+/// Terminate the program, after it had the chance to handle the `quit` event
+fn terminate(mut child: Child) {
+    let deadline = Instant::now() + Duration::from_millis(constants::EXEC_TERMINATE_TIMEOUT_MILLIS);
+
+    while let Ok(None) = child.try_wait() {
+        if Instant::now() >= deadline {
+            child
+                .kill()
+                .unwrap_or_else(|e| warn!("Could not kill process: {}", e));
+
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    child
+        .wait()
+        .map(|status| debug!("External effect exited: {}", status))
+        .unwrap_or_else(|e| error!("Could not wait for process: {}", e));
+}
+
+/// Runs the external effect `file`, it renders to the device with the index
+/// `target_device`, as the `layer`-th layer. See `script::run_script`
+pub fn run_script(
+    file: PathBuf,
+    manifest: Manifest,
+    target_device: usize,
+    layer: usize,
+    rx: &Receiver<Message>,
+) -> script::Result<RunScriptResult> {
+    let command_line = manifest.exec.clone().unwrap_or_else(Vec::new);
+
+    let mut child = spawn_process(&file, &command_line).map_err(|e| {
+        error!(
+            "Could not execute the external effect '{}': {}",
+            file.display(),
+            e
+        );
+
+        exec_error(e)
+    })?;
+
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| exec_error("Could not open stdin of the process"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| exec_error("Could not open stdout of the process"))?;
+
+    ACTIVE_SCRIPTS.lock().push(manifest.clone());
+    callbacks::set_frame_interpolation(manifest.frame_interpolation);
+
+    // color maps are realized by this thread, since the LED map is thread local
+    let (frames_tx, frames_rx) = mpsc::channel();
+
+    let name = manifest.name.clone();
+    thread::Builder::new()
+        .name(format!("exec/{}", layer))
+        .spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                match serde_json::from_str::<Request>(&line) {
+                    Ok(Request::ColorMap { colors }) => {
+                        if frames_tx.send(colors).is_err() {
+                            break;
+                        }
+                    }
+
+                    Ok(Request::Log { level, message }) => match level {
+                        0 => callbacks::log_error(&message),
+                        1 => callbacks::log_warn(&message),
+                        2 => callbacks::log_info(&message),
+                        3 => callbacks::log_debug(&message),
+                        _ => callbacks::log_trace(&message),
+                    },
+
+                    Err(e) => warn!("Invalid request of the external effect '{}': {}", name, e),
+                }
+            }
+        })
+        .map_err(exec_error)?;
+
+    let (events_tx, events_rx) = mpsc::sync_channel(constants::EXEC_EVENT_QUEUE_LEN);
+    let writer = spawn_writer(stdin, events_rx, layer)?;
+
+    info!("Started external effect: {}", file.display());
+
+    let result = (|| -> script::Result<RunScriptResult> {
+        send_event(
+            &events_tx,
+            Event::Startup {
+                num_keys: NUM_KEYS,
+                params: get_params(&manifest),
+            },
+        )?;
+
+        loop {
+            if let Ok(msg) = rx.recv() {
+                match msg {
+                    Message::Quit(code) => send_event(&events_tx, Event::Quit { code })?,
+
+                    Message::Tick(delta) => {
+                        // further ticks will be queued again from now on
+                        ticks::tick_completed(layer);
+
+                        send_event(&events_tx, Event::Tick { delta })?;
+                    }
+
+                    Message::RenderFrame(frame_id) => {
+                        // only the most recent color map is of interest
+                        if let Some(colors) = frames_rx.try_iter().last() {
+                            callbacks::submit_color_map(&colors);
+                        }
+
+                        script::publish_layer_frame(layer, target_device, frame_id);
+                    }
+
+                    Message::KeyDown(key_index) => {
+                        let result = send_event(&events_tx, Event::KeyDown { key_index });
+
+                        *crate::UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() -= 1;
+                        crate::UPCALL_COMPLETED_ON_KEY_DOWN.1.notify_all();
+
+                        result?;
+                    }
+
+                    Message::KeyUp(key_index) => {
+                        let result = send_event(&events_tx, Event::KeyUp { key_index });
+
+                        *crate::UPCALL_COMPLETED_ON_KEY_UP.0.lock() -= 1;
+                        crate::UPCALL_COMPLETED_ON_KEY_UP.1.notify_all();
+
+                        result?;
+                    }
+
+                    Message::Unload => {
+                        debug!("TerminatedGracefully");
+
+                        return Ok(RunScriptResult::TerminatedGracefully);
+                    }
+
+                    Message::ParamChanged(script_name, param) => {
+                        if script_name == manifest.name {
+                            let (name, value) = param_to_json(&param);
+
+                            send_event(&events_tx, Event::ParamChanged { name, value })?;
+                        }
+                    }
+
+                    // the other events are not yet part of the protocol
+                    _ => (),
+                }
+            }
+        }
+    })();
+
+    // the writer closes the standard input, after the queued events are written
+    drop(events_tx);
+    terminate(child);
+
+    writer
+        .join()
+        .unwrap_or_else(|_| error!("The writer of the external effect panicked"));
+
+    result
+}
@@ -0,0 +1,190 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! HTTP requests of Lua scripts. Requests are executed by a small pool of worker
+//! threads, so that they never block the interpreters or the render loop. The
+//! responses are delivered to the script that issued the request, via the event
+//! handler `on_http_response(id, status, body)`.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::constants;
+use crate::scripting::script::{self, ScriptId};
+
+pub type Result<T> = std::result::Result<T, HttpError>;
+
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("Invalid URL, only http and https are supported: {url}")]
+    InvalidUrl { url: String },
+
+    #[error("Rate limit exceeded")]
+    RateLimitExceeded {},
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// A request, that is queued for the worker threads
+struct Request {
+    id: u64,
+    script: ScriptId,
+    url: String,
+}
+
+lazy_static! {
+    /// Queue of the worker threads, they are spawned on the first request
+    static ref REQUEST_TX: Mutex<Option<Sender<Request>>> = Mutex::new(None);
+
+    /// Points in time of the recent requests, indexed by the layer of the script
+    static ref RECENT_REQUESTS: Mutex<HashMap<usize, VecDeque<Instant>>> = Mutex::new(HashMap::new());
+}
+
+/// The id of the next request
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns true if the script on layer `layer` may issue another request, and
+/// records the request
+fn check_rate_limit(layer: usize) -> bool {
+    let window = Duration::from_secs(60);
+    let now = Instant::now();
+
+    let mut recent_requests = RECENT_REQUESTS.lock();
+    let requests = recent_requests.entry(layer).or_insert_with(VecDeque::new);
+
+    while requests
+        .front()
+        .map_or(false, |t| now.duration_since(*t) > window)
+    {
+        requests.pop_front();
+    }
+
+    if requests.len() >= constants::HTTP_MAX_REQUESTS_PER_MINUTE {
+        return false;
+    }
+
+    requests.push_back(now);
+
+    true
+}
+
+/// Execute the request `request`, returns the status code and the body of the
+/// response. The status code is 0 if the request failed
+fn execute(request: &Request) -> (u16, String) {
+    let response = ureq::get(&request.url)
+        .timeout_connect(constants::HTTP_TIMEOUT_MILLIS)
+        .timeout_read(constants::HTTP_TIMEOUT_MILLIS)
+        .call();
+
+    if let Some(e) = response.synthetic_error() {
+        return (0, format!("{}", e));
+    }
+
+    let status = response.status();
+
+    let mut body = String::new();
+    response
+        .into_reader()
+        .take(constants::HTTP_MAX_RESPONSE_SIZE as u64)
+        .read_to_string(&mut body)
+        .unwrap_or_else(|e| {
+            warn!("Could not read the response of {}: {}", request.url, e);
+            0
+        });
+
+    (status, body)
+}
+
+fn run_worker(rx: Arc<Mutex<Receiver<Request>>>) {
+    loop {
+        let request = match rx.lock().recv() {
+            Ok(request) => request,
+            Err(_e) => break,
+        };
+
+        debug!("HTTP request {}: GET {}", request.id, request.url);
+
+        let (status, body) = execute(&request);
+
+        debug!("HTTP request {}: Status {}", request.id, status);
+
+        // the script may have been unloaded in the meantime
+        request
+            .script
+            .send(script::Message::HttpResponse(request.id, status, body));
+    }
+}
+
+fn spawn_workers() -> Result<Sender<Request>> {
+    let (tx, rx) = channel();
+    let rx = Arc::new(Mutex::new(rx));
+
+    for index in 0..constants::HTTP_WORKER_THREADS {
+        let rx = rx.clone();
+
+        thread::Builder::new()
+            .name(format!("http/{}", index))
+            .spawn(move || run_worker(rx))
+            .map_err(|_e| HttpError::ThreadSpawnError {})?;
+    }
+
+    Ok(tx)
+}
+
+/// Queue a GET request of `url`, on behalf of the script `script`. Returns the id
+/// of the request, that is passed to `on_http_response`
+pub fn get(script: ScriptId, url: &str) -> Result<u64> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(HttpError::InvalidUrl {
+            url: url.to_string(),
+        });
+    }
+
+    if !check_rate_limit(script.layer) {
+        return Err(HttpError::RateLimitExceeded {});
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+
+    let mut request_tx = REQUEST_TX.lock();
+
+    if request_tx.is_none() {
+        *request_tx = Some(spawn_workers()?);
+    }
+
+    request_tx
+        .as_ref()
+        .unwrap()
+        .send(Request {
+            id,
+            script,
+            url: url.to_string(),
+        })
+        .unwrap_or_else(|e| error!("Send error: {}", e));
+
+    Ok(id)
+}
@@ -0,0 +1,425 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Integrity checks of scripts. A manifest may carry the SHA-256 checksum of its
+//! script, and an Ed25519 signature of the script and the manifest itself, so that
+//! e.g. the capabilities of a signed script can not be changed. Signatures are
+//! verified against the public keys in the trust store, a directory of files that
+//! contain one hex encoded public key each.
+//!
+//! Since scripts may inject keys, the `[security]` section of the config specifies
+//! what unsigned scripts are allowed to do:
+//!
+//! * `allow`: Unsigned scripts are executed without restrictions
+//! * `restrict`: Unsigned scripts may not inject keys, and external effects are refused
+//! * `deny`: Unsigned scripts are refused
+//!
+//! Scripts that do not match their checksum or signature are always refused, as
+//! are scripts whose manifest can not be read.
+
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey, Signature};
+use log::*;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::constants;
+use crate::scripting::manifest::Manifest;
+
+pub type Result<T> = std::result::Result<T, IntegrityError>;
+
+#[derive(Debug, Error)]
+pub enum IntegrityError {
+    #[error("The script '{script}' does not match its checksum")]
+    ChecksumMismatch { script: String },
+
+    #[error("The signature of the script '{script}' is invalid, or its key is not trusted")]
+    InvalidSignature { script: String },
+
+    #[error("The script '{script}' is not signed, and unsigned scripts are denied")]
+    Unsigned { script: String },
+
+    #[error("Could not read the script: {description}")]
+    IoError { description: String },
+
+    #[error("Invalid key: {description}")]
+    InvalidKey { description: String },
+
+    #[error("Could not read the manifest: {description}")]
+    ManifestError { description: String },
+}
+
+/// The result of the verification of a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    /// Signed with the trusted key `key`
+    Signed { key: String },
+
+    /// No signature, the checksum matches if it is specified
+    Unsigned,
+
+    /// The script has been modified
+    ChecksumMismatch,
+
+    /// The signature is invalid, or made with a key that is not trusted
+    InvalidSignature,
+}
+
+/// What unsigned scripts are allowed to do
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Policy {
+    Allow,
+    Restrict,
+    Deny,
+}
+
+/// The permissions of a script, that passed the verification
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Permissions {
+    Full,
+
+    /// The script may not inject keys
+    Restricted,
+
+    /// The script could not be verified, it may not inject keys and external
+    /// effects are refused
+    None,
+}
+
+/// Get the policy for unsigned scripts, from the `[security]` section of the config
+pub fn get_policy() -> Policy {
+    let policy = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get_str("security.unsigned_scripts").ok())
+        .unwrap_or_else(|| "allow".to_string());
+
+    match policy.as_str() {
+        "allow" => Policy::Allow,
+        "restrict" => Policy::Restrict,
+        "deny" => Policy::Deny,
+
+        _ => {
+            warn!(
+                "Invalid policy for unsigned scripts: '{}', restricting them",
+                policy
+            );
+
+            Policy::Restrict
+        }
+    }
+}
+
+fn get_trust_store_dir() -> PathBuf {
+    PathBuf::from(
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|config| config.get_str("security.trust_store").ok())
+            .unwrap_or_else(|| constants::DEFAULT_TRUST_STORE_DIR.to_string()),
+    )
+}
+
+/// Parse the hex encoded key in `data`
+fn parse_key(data: &str) -> Result<Vec<u8>> {
+    hex::decode(data.trim()).map_err(|e| IntegrityError::InvalidKey {
+        description: format!("{}", e),
+    })
+}
+
+/// Get the public keys of the trust store, with the names of their files
+pub fn get_trusted_keys() -> Vec<(String, PublicKey)> {
+    let dir = get_trust_store_dir();
+
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_e) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().to_string();
+
+            let key = fs::read_to_string(&path)
+                .ok()
+                .and_then(|data| parse_key(&data).ok())
+                .and_then(|bytes| PublicKey::from_bytes(&bytes).ok());
+
+            if key.is_none() {
+                warn!("Invalid public key in trust store: {}", path.display());
+            }
+
+            key.map(|key| (name, key))
+        })
+        .collect()
+}
+
+/// Get the message that is signed, for the script with the digest `digest` and its
+/// manifest `manifest`. The message covers all fields of the manifest except for
+/// the signature and the fields that are filled in after parsing
+fn signed_message(digest: &[u8], manifest: &Manifest) -> Result<Vec<u8>> {
+    let mut manifest = manifest.clone();
+
+    manifest.id = 0;
+    manifest.script_file = PathBuf::new();
+    manifest.html_class = String::new();
+    manifest.signature = None;
+
+    let manifest = serde_json::to_vec(&manifest).map_err(|e| IntegrityError::ManifestError {
+        description: format!("{}", e),
+    })?;
+
+    let mut message = digest.to_vec();
+    message.extend_from_slice(&manifest);
+
+    Ok(Sha256::digest(&message).to_vec())
+}
+
+/// Verify the script `data` against the checksum and signature of `manifest`
+pub fn verify_data(
+    data: &[u8],
+    manifest: &Manifest,
+    trusted_keys: &[(String, PublicKey)],
+) -> Verification {
+    let digest = Sha256::digest(data);
+
+    if let Some(expected) = &manifest.checksum {
+        if !expected.trim().eq_ignore_ascii_case(&hex::encode(&digest)) {
+            return Verification::ChecksumMismatch;
+        }
+    }
+
+    let signature = match &manifest.signature {
+        Some(signature) => signature,
+        None => return Verification::Unsigned,
+    };
+
+    let signature = match hex::decode(signature.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_bytes(&bytes).ok())
+    {
+        Some(signature) => signature,
+        None => return Verification::InvalidSignature,
+    };
+
+    let message = match signed_message(&digest, manifest) {
+        Ok(message) => message,
+        Err(_e) => return Verification::InvalidSignature,
+    };
+
+    trusted_keys
+        .iter()
+        .find(|(_, key)| key.verify(&message, &signature).is_ok())
+        .map_or(Verification::InvalidSignature, |(name, _)| {
+            Verification::Signed { key: name.clone() }
+        })
+}
+
+/// Verify the script of `manifest`
+pub fn verify(manifest: &Manifest) -> Result<Verification> {
+    let data = fs::read(&manifest.script_file).map_err(|e| IntegrityError::IoError {
+        description: format!("{}", e),
+    })?;
+
+    Ok(verify_data(&data, manifest, &get_trusted_keys()))
+}
+
+/// Apply the policy `policy` to the result of a verification
+pub fn check_verification(
+    script: &str,
+    verification: &Verification,
+    policy: Policy,
+) -> Result<Permissions> {
+    match verification {
+        Verification::Signed { .. } => Ok(Permissions::Full),
+
+        Verification::Unsigned => match policy {
+            Policy::Allow => Ok(Permissions::Full),
+            Policy::Restrict => Ok(Permissions::Restricted),
+            Policy::Deny => Err(IntegrityError::Unsigned {
+                script: script.to_string(),
+            }),
+        },
+
+        Verification::ChecksumMismatch => Err(IntegrityError::ChecksumMismatch {
+            script: script.to_string(),
+        }),
+
+        Verification::InvalidSignature => Err(IntegrityError::InvalidSignature {
+            script: script.to_string(),
+        }),
+    }
+}
+
+/// Verify the script of `manifest`, and get its permissions according to the policy
+/// of the config
+pub fn check(manifest: &Manifest) -> Result<Permissions> {
+    let verification = verify(manifest)?;
+
+    if let Verification::Signed { key } = &verification {
+        debug!("Script '{}' is signed by: {}", manifest.name, key);
+    }
+
+    check_verification(&manifest.name, &verification, get_policy())
+}
+
+/// Sign the script `script` and its manifest with the hex encoded secret key in the
+/// file `key_file`, and print the lines that need to be added to the manifest. The
+/// manifest must be signed again after each change
+pub fn sign_script<P: AsRef<Path>>(script: P, key_file: Option<P>) -> Result<()> {
+    let data = fs::read(script.as_ref()).map_err(|e| IntegrityError::IoError {
+        description: format!("{}", e),
+    })?;
+
+    let mut manifest =
+        Manifest::from(script.as_ref()).map_err(|e| IntegrityError::ManifestError {
+            description: format!("{}", e),
+        })?;
+
+    let digest = Sha256::digest(&data);
+
+    // the signature covers the checksum line that is printed here
+    manifest.checksum = Some(hex::encode(&digest));
+
+    println!("checksum = \"{}\"", hex::encode(&digest));
+
+    if let Some(key_file) = key_file {
+        let key_data =
+            fs::read_to_string(key_file.as_ref()).map_err(|e| IntegrityError::IoError {
+                description: format!("{}", e),
+            })?;
+
+        let secret = SecretKey::from_bytes(&parse_key(&key_data)?).map_err(|e| {
+            IntegrityError::InvalidKey {
+                description: format!("{}", e),
+            }
+        })?;
+
+        let public: PublicKey = (&secret).into();
+        let message = signed_message(&digest, &manifest)?;
+        let signature = ExpandedSecretKey::from(&secret).sign(&message, &public);
+
+        println!("signature = \"{}\"", hex::encode(&signature.to_bytes()[..]));
+        println!(
+            "# public key, for the trust store: {}",
+            hex::encode(public.as_bytes())
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::capabilities::Capability;
+
+    fn manifest(permissions: &str) -> Manifest {
+        toml::from_str(&format!(
+            r#"
+            name = "Test"
+            description = "Test"
+            version = "0.0.1"
+            author = "Test"
+            min_supported_version = "0.0.1"
+            permissions = [{}]
+            "#,
+            permissions
+        ))
+        .unwrap()
+    }
+
+    fn sign(data: &[u8], manifest: &mut Manifest) -> (String, PublicKey) {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public: PublicKey = (&secret).into();
+
+        let digest = Sha256::digest(data);
+        manifest.checksum = Some(hex::encode(&digest));
+
+        let message = signed_message(&digest, manifest).unwrap();
+        let signature = ExpandedSecretKey::from(&secret).sign(&message, &public);
+
+        manifest.signature = Some(hex::encode(&signature.to_bytes()[..]));
+
+        ("test".to_string(), public)
+    }
+
+    #[test]
+    fn test_verify_data_signed() {
+        let data = b"print(42)";
+        let mut manifest = manifest("");
+        let key = sign(data, &mut manifest);
+
+        assert_eq!(
+            verify_data(data, &manifest, &[key.clone()]),
+            Verification::Signed {
+                key: "test".to_string()
+            }
+        );
+
+        // fields that are filled in after parsing are not signed
+        manifest.id = 42;
+        manifest.html_class = "badge-primary".to_string();
+
+        assert_eq!(
+            verify_data(data, &manifest, &[key]),
+            Verification::Signed {
+                key: "test".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_data_modified_manifest() {
+        let data = b"print(42)";
+        let mut manifest = manifest("");
+        let key = sign(data, &mut manifest);
+
+        manifest.permissions.push(Capability::InputInjection);
+
+        assert_eq!(
+            verify_data(data, &manifest, &[key]),
+            Verification::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn test_verify_data_modified_script() {
+        let mut manifest = manifest("");
+        let key = sign(b"print(42)", &mut manifest);
+
+        assert_eq!(
+            verify_data(b"print(43)", &manifest, &[key]),
+            Verification::ChecksumMismatch
+        );
+    }
+
+    #[test]
+    fn test_verify_data_untrusted_key() {
+        let data = b"print(42)";
+        let mut manifest = manifest("");
+        sign(data, &mut manifest);
+
+        assert_eq!(
+            verify_data(data, &manifest, &[]),
+            Verification::InvalidSignature
+        );
+    }
+}
@@ -15,32 +15,33 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use failure::Fail;
 use log::*;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use thiserror::Error;
 
 use crate::profiles;
+use crate::scripting::capabilities::Capability;
 use crate::util;
 
 pub type Result<T> = std::result::Result<T, ManifestError>;
 
-#[derive(Debug, Fail, Clone)]
+#[derive(Debug, Error, Clone)]
 pub enum ManifestError {
-    #[fail(display = "Could not open file for reading")]
+    #[error("Could not open file for reading")]
     OpenError {},
 
-    #[fail(display = "Could not parse manifest file")]
+    #[error("Could not parse manifest file")]
     ParseError {},
 
-    #[fail(display = "Could not enumerate script files")]
+    #[error("Could not enumerate script files")]
     ScriptEnumerationError {},
 
-    #[fail(display = "Could not parse param value")]
+    #[error("Could not parse param value")]
     ParseParamError {},
-    // #[fail(display = "Unknown error: {}", description)]
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
@@ -56,6 +57,10 @@ fn default_html_class() -> String {
     "badge-default".into()
 }
 
+fn default_compat_globals() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum ConfigParam {
@@ -143,7 +148,7 @@ impl ParseConfig for Vec<ConfigParam> {
 
                 ConfigParam::Color { name, .. } => {
                     if name == param {
-                        let value = u32::from_str_radix(&val[1..], 16)
+                        let value = u32::from_str_radix(val.trim_start_matches('#'), 16)
                             .map_err(|_e| ManifestError::ParseParamError {})?;
 
                         return Ok(profiles::ConfigParam::Color {
@@ -206,9 +211,44 @@ pub struct Manifest {
     pub version: String,
     pub author: String,
     pub min_supported_version: String,
+
+    /// The API level the script has been written against, defaults to
+    /// `min_supported_version`. Calls of renamed functions are mapped for scripts
+    /// that target an older API level
+    #[serde(default)]
+    pub api_level: Option<String>,
+
     pub tags: Option<Vec<ScriptTag>>,
     pub config: Option<Vec<ConfigParam>>,
 
+    /// Linearly blend between the last two submitted color maps
+    /// on intermediate render frames
+    #[serde(default)]
+    pub frame_interpolation: bool,
+
+    /// Make the functions of the Lua API available as globals, in addition to
+    /// the namespace tables like `color.*`. New scripts should disable this
+    #[serde(default = "default_compat_globals")]
+    pub compat_globals: bool,
+
+    /// Command line of the program that implements the effect, the script file is
+    /// appended as the last argument. An empty command line executes the script file
+    #[serde(default)]
+    pub exec: Option<Vec<String>>,
+
+    /// Capabilities the script requires, like "input-injection". Functions that
+    /// require other capabilities are not available to the script
+    #[serde(default)]
+    pub permissions: Vec<Capability>,
+
+    /// Hex encoded SHA-256 checksum of the script file
+    #[serde(default)]
+    pub checksum: Option<String>,
+
+    /// Hex encoded Ed25519 signature of the checksum, made with a key of the trust store
+    #[serde(default)]
+    pub signature: Option<String>,
+
     #[serde(default = "default_html_class")]
     pub html_class: String,
 }
@@ -258,6 +298,13 @@ impl Manifest {
     pub fn from(script: &Path) -> Result<Self> {
         Self::new(default_id(), script)
     }
+
+    /// Get the API level the script has been written against
+    pub fn get_api_level(&self) -> &str {
+        self.api_level
+            .as_deref()
+            .unwrap_or(&self.min_supported_version)
+    }
 }
 
 /// Get a `Vec` of `PathBufs` of available script files in the directory `script_path`.
@@ -426,3 +473,28 @@ impl ScriptTag {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shipped_manifests() {
+        let script_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/scripts");
+
+        let manifests: Vec<PathBuf> = fs::read_dir(&script_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".lua.manifest"))
+            .collect();
+
+        assert!(!manifests.is_empty());
+
+        // every manifest that is shipped has to be parseable, or the script never loads
+        for manifest in manifests {
+            let script = manifest.with_extension("");
+
+            Manifest::from(&script).unwrap_or_else(|e| panic!("{}: {}", manifest.display(), e));
+        }
+    }
+}
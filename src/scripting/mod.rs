@@ -15,5 +15,21 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+pub mod api;
+pub mod cache;
+pub mod capabilities;
+pub mod canvas;
+pub mod clipboard;
+pub mod commands;
+pub mod debugger;
+pub mod exec;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod integrity;
 pub mod manifest;
+pub mod overrides;
+pub mod raw_hid;
 pub mod script;
+pub mod ticks;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,234 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Runtime overrides of the parameters of scripts. The value of a parameter is
+//! taken from the override, if present, then from the active profile, and then
+//! from the default value of the manifest. Overrides are pushed into the running
+//! interpreters, and are kept until they are cleared or the daemon is restarted.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::profiles::{self, FindConfig, GetAttr};
+use crate::scripting::manifest::{GetAttr as GetAttrManifest, Manifest, ParseConfig};
+use crate::scripting::script;
+use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
+
+pub type Result<T> = std::result::Result<T, OverrideError>;
+
+#[derive(Debug, Error)]
+pub enum OverrideError {
+    #[error("Invalid parameter '{name}', expected: <script>.<parameter>")]
+    InvalidName { name: String },
+
+    #[error("No active script named '{script}'")]
+    UnknownScript { script: String },
+
+    #[error("Invalid value '{value}' for the parameter '{name}'")]
+    InvalidValue { name: String, value: String },
+
+    #[error("Could not save the profile: {description}")]
+    ProfileSaveError { description: String },
+}
+
+lazy_static! {
+    /// Overridden parameters, indexed by the name of the script
+    static ref OVERRIDES: RwLock<HashMap<String, Vec<profiles::ConfigParam>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Split a qualified name like "shockwave.color_step" into the name of the script
+/// and the name of the parameter
+pub fn parse_name(qualified_name: &str) -> Result<(&str, &str)> {
+    let mut parts = qualified_name.rsplitn(2, '.');
+
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(script)) if !name.is_empty() && !script.is_empty() => Ok((script, name)),
+
+        _ => Err(OverrideError::InvalidName {
+            name: qualified_name.to_string(),
+        }),
+    }
+}
+
+/// Find the active script `script`, by its name or by the name of its script file,
+/// ignoring case, e.g. "Shockwave" or "shockwave"
+fn find_script(script: &str) -> Result<Manifest> {
+    let script = script.to_lowercase();
+
+    ACTIVE_SCRIPTS
+        .lock()
+        .iter()
+        .find(|manifest| {
+            manifest.name.to_lowercase() == script
+                || manifest.script_file.file_stem().map_or(false, |stem| {
+                    stem.to_string_lossy().to_lowercase() == script
+                })
+        })
+        .cloned()
+        .ok_or(OverrideError::UnknownScript { script })
+}
+
+/// Notify the running interpreters, that the parameter `param` of the script
+/// `script_name` has a new value
+fn notify_scripts(script_name: &str, param: &profiles::ConfigParam) {
+    for lua_tx in crate::LUA_TXS.lock().iter() {
+        lua_tx
+            .send(script::Message::ParamChanged(
+                script_name.to_string(),
+                param.clone(),
+            ))
+            .unwrap_or_else(|e| error!("Send error: {}", e));
+    }
+}
+
+/// Override the parameter `name` of the active script `script` with `value`.
+/// If `persist` is set, the value is saved to the active profile as well
+pub fn set(script: &str, name: &str, value: &str, persist: bool) -> Result<()> {
+    let manifest = find_script(script)?;
+
+    let invalid_value = || OverrideError::InvalidValue {
+        name: name.to_string(),
+        value: value.to_string(),
+    };
+
+    let param = manifest
+        .config
+        .as_ref()
+        .ok_or_else(invalid_value)?
+        .parse_config_param(name, value)
+        .map_err(|_e| invalid_value())?;
+
+    {
+        let mut overrides = OVERRIDES.write();
+        let params = overrides
+            .entry(manifest.name.clone())
+            .or_insert_with(Vec::new);
+
+        match params.find_config_param_mut(name) {
+            Some(existing) => *existing = param.clone(),
+            None => params.push(param.clone()),
+        }
+    }
+
+    info!(
+        "Parameter '{}' of script '{}' set to: {}",
+        name,
+        manifest.name,
+        param.get_value()
+    );
+
+    if persist {
+        if let Some(profile) = ACTIVE_PROFILE.lock().as_mut() {
+            profile.set_config_param(&manifest.name, param.clone());
+            profile
+                .save()
+                .map_err(|e| OverrideError::ProfileSaveError {
+                    description: format!("{}", e),
+                })?;
+        }
+    }
+
+    notify_scripts(&manifest.name, &param);
+
+    Ok(())
+}
+
+/// Remove the overrides of the script `script`, or of all scripts if `script` is
+/// None. The scripts are notified of the values of the parameters, as specified by
+/// the active profile or their manifest
+pub fn clear(script: Option<&str>) -> Result<()> {
+    let removed: Vec<(Manifest, Vec<profiles::ConfigParam>)> = match script {
+        Some(script) => {
+            let manifest = find_script(script)?;
+            let params = OVERRIDES.write().remove(&manifest.name).unwrap_or_default();
+
+            vec![(manifest, params)]
+        }
+
+        None => {
+            let overrides: Vec<_> = OVERRIDES.write().drain().collect();
+            let scripts = ACTIVE_SCRIPTS.lock();
+
+            overrides
+                .into_iter()
+                .filter_map(|(script_name, params)| {
+                    scripts
+                        .iter()
+                        .find(|manifest| manifest.name == script_name)
+                        .map(|manifest| (manifest.clone(), params))
+                })
+                .collect()
+        }
+    };
+
+    for (manifest, params) in removed {
+        for param in params {
+            if let Some(value) = get_profile_value(&manifest, param.get_name()) {
+                notify_scripts(&manifest.name, &value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the value of the parameter `name` of the script `manifest`, as specified by
+/// the active profile, or the default value of the manifest
+fn get_profile_value(manifest: &Manifest, name: &str) -> Option<profiles::ConfigParam> {
+    let profile_value = ACTIVE_PROFILE.lock().as_ref().and_then(|profile| {
+        profile
+            .config
+            .as_ref()
+            .and_then(|config| config.get(&manifest.name))
+            .and_then(|params| params.find_config_param(name))
+            .cloned()
+    });
+
+    profile_value.or_else(|| {
+        let config = manifest.config.as_ref()?;
+        let default = config.iter().find(|p| p.get_name() == name)?.get_default();
+
+        config.parse_config_param(name, &default).ok()
+    })
+}
+
+/// Get the overridden parameters of the script `script_name`
+pub fn get(script_name: &str) -> Vec<profiles::ConfigParam> {
+    OVERRIDES
+        .read()
+        .get(script_name)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Get the override of the parameter `name` of the script `script_name`, if any
+pub fn find(script_name: &str, name: &str) -> Option<profiles::ConfigParam> {
+    OVERRIDES
+        .read()
+        .get(script_name)
+        .and_then(|params| params.find_config_param(name))
+        .cloned()
+}
+
+/// Get the overridden parameters of all scripts, indexed by the name of the script
+pub fn get_all() -> HashMap<String, Vec<profiles::ConfigParam>> {
+    OVERRIDES.read().clone()
+}
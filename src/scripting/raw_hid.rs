@@ -0,0 +1,255 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Raw access to the feature reports of the control device, for experiments with
+//! undocumented features of the firmware. Access has to be enabled in the
+//! `[raw_hid]` section of the configuration, and is limited to a safelist of
+//! report ids. By default only the read-only status report is on the safelist,
+//! reports that write persistent state of the device, like the hardware profile,
+//! the key map or the firmware, have to be added to the safelist explicitly.
+//!
+//! Reports are accessible to scripts with the capability "raw-hid", and to user
+//! root via the `org.eruption.RawHid` interface of the D-Bus API.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::RvDeviceState;
+
+pub type Result<T> = std::result::Result<T, RawHidError>;
+
+#[derive(Debug, Error)]
+pub enum RawHidError {
+    #[error("Raw HID access is disabled")]
+    Disabled {},
+
+    #[error("Report id {id:#04x} is not on the safelist")]
+    ReportNotAllowed { id: u8 },
+
+    #[error("Invalid report length: {len}")]
+    InvalidLength { len: usize },
+
+    #[error("Invalid device: {device}")]
+    InvalidDevice { device: usize },
+
+    #[error("Raw HID access is not available, the devices have not been initialized")]
+    NotInitialized {},
+
+    #[error("Device error: {description}")]
+    DeviceError { description: String },
+}
+
+lazy_static! {
+    /// The HID API, that is required to open the control devices, and the devices
+    /// that are accessible via the D-Bus API
+    static ref DEVICES: RwLock<Option<(Arc<hidapi::HidApi>, Vec<RvDeviceState>)>> =
+        RwLock::new(None);
+}
+
+/// Make raw HID access available, `rvdevices` are the devices of the daemon. The
+/// control devices are opened for each request, so the clones of the devices are
+/// only used to find them
+pub fn initialize(hidapi: Arc<hidapi::HidApi>, rvdevices: Vec<RvDeviceState>) {
+    *DEVICES.write() = Some((hidapi, rvdevices));
+}
+
+fn get_hidapi() -> Result<Arc<hidapi::HidApi>> {
+    DEVICES
+        .read()
+        .as_ref()
+        .map(|(hidapi, _)| hidapi.clone())
+        .ok_or(RawHidError::NotInitialized {})
+}
+
+/// Get a clone of the device with the index `device`
+fn get_device(device: usize) -> Result<RvDeviceState> {
+    match &*DEVICES.read() {
+        Some((_, rvdevices)) => rvdevices
+            .get(device)
+            .cloned()
+            .ok_or(RawHidError::InvalidDevice { device }),
+
+        None => Err(RawHidError::NotInitialized {}),
+    }
+}
+
+/// Check whether raw access to the report `id` is permitted, by the settings
+/// `enabled` and the safelist `allowed_reports`
+fn check_report_allowed(id: u8, enabled: bool, allowed_reports: &[u8]) -> Result<()> {
+    if !enabled {
+        Err(RawHidError::Disabled {})
+    } else if !allowed_reports.contains(&id) {
+        Err(RawHidError::ReportNotAllowed { id })
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `len` is a valid length of a report, including the report id
+fn check_length(len: usize) -> Result<()> {
+    if len == 0 || len > constants::RAW_HID_MAX_REPORT_SIZE {
+        Err(RawHidError::InvalidLength { len })
+    } else {
+        Ok(())
+    }
+}
+
+/// Check whether raw access to the report `id` is permitted by the configuration
+fn check_report(id: u8) -> Result<()> {
+    let (enabled, allowed_reports) = {
+        let config = crate::CONFIG.lock();
+
+        match config.as_ref() {
+            Some(config) => (
+                config.get_bool("raw_hid.enabled").unwrap_or(false),
+                config
+                    .get::<Vec<u8>>("raw_hid.allowed_reports")
+                    .unwrap_or_else(|_| constants::RAW_HID_ALLOWED_REPORTS.to_vec()),
+            ),
+
+            // not configured, e.g. in a subcommand
+            None => (false, vec![]),
+        }
+    };
+
+    check_report_allowed(id, enabled, &allowed_reports)
+}
+
+/// Send the feature report `buf` to the device `rvdevice`, the first byte is the
+/// report id
+pub fn send_feature(rvdevice: &RvDeviceState, buf: &[u8]) -> Result<()> {
+    check_length(buf.len())?;
+    check_report(buf[0])?;
+
+    info!(
+        "Sending raw feature report {:#04x} ({} bytes)",
+        buf[0],
+        buf.len()
+    );
+
+    rvdevice
+        .send_raw_feature_report(&get_hidapi()?, buf)
+        .map_err(|e| RawHidError::DeviceError {
+            description: format!("{}", e),
+        })
+}
+
+/// Get the feature report `id` of the device `rvdevice`, `len` is the size of the
+/// report including the report id
+pub fn get_feature(rvdevice: &RvDeviceState, id: u8, len: usize) -> Result<Vec<u8>> {
+    check_length(len)?;
+    check_report(id)?;
+
+    debug!("Getting raw feature report {:#04x}", id);
+
+    rvdevice
+        .get_raw_feature_report(&get_hidapi()?, id, len)
+        .map_err(|e| RawHidError::DeviceError {
+            description: format!("{}", e),
+        })
+}
+
+/// Send the feature report `buf` to the device with the index `device`, like
+/// `send_feature`
+pub fn send_feature_to(device: usize, buf: &[u8]) -> Result<()> {
+    check_length(buf.len())?;
+    check_report(buf[0])?;
+
+    send_feature(&get_device(device)?, buf)
+}
+
+/// Get the feature report `id` of the device with the index `device`, like
+/// `get_feature`
+pub fn get_feature_from(device: usize, id: u8, len: usize) -> Result<Vec<u8>> {
+    check_length(len)?;
+    check_report(id)?;
+
+    get_feature(&get_device(device)?, id, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_report_allowed() {
+        let allowed = constants::RAW_HID_ALLOWED_REPORTS;
+
+        assert!(check_report_allowed(0x0f, true, allowed).is_ok());
+
+        // reports that write the hardware profile are not allowed by default
+        for id in [0x0d, 0x13, 0x00, 0xff].iter() {
+            match check_report_allowed(*id, true, allowed) {
+                Err(RawHidError::ReportNotAllowed { id: result }) => assert_eq!(result, *id),
+                result => panic!("Expected a rejected report: {:?}", result),
+            }
+        }
+
+        // explicitly allowed reports
+        assert!(check_report_allowed(0x0d, true, &[0x0d, 0x0f]).is_ok());
+        assert!(check_report_allowed(0x0f, true, &[]).is_err());
+
+        // nothing is accessible while raw access is disabled
+        assert!(matches!(
+            check_report_allowed(0x0f, false, allowed),
+            Err(RawHidError::Disabled {})
+        ));
+    }
+
+    #[test]
+    fn test_check_length() {
+        assert!(check_length(1).is_ok());
+        assert!(check_length(constants::RAW_HID_MAX_REPORT_SIZE).is_ok());
+
+        assert!(matches!(
+            check_length(0),
+            Err(RawHidError::InvalidLength { len: 0 })
+        ));
+        assert!(check_length(constants::RAW_HID_MAX_REPORT_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn test_requests() {
+        // the length is validated first, an empty report has no id
+        assert!(matches!(
+            send_feature_to(0, &[]),
+            Err(RawHidError::InvalidLength { len: 0 })
+        ));
+        assert!(matches!(
+            get_feature_from(0, 0x0f, constants::RAW_HID_MAX_REPORT_SIZE + 1),
+            Err(RawHidError::InvalidLength { .. })
+        ));
+
+        // raw access is disabled without a configuration, before any device is accessed
+        assert!(matches!(
+            send_feature_to(0, &[0x0f, 0x00]),
+            Err(RawHidError::Disabled {})
+        ));
+        assert!(matches!(
+            get_feature_from(7, 0x0f, 3),
+            Err(RawHidError::Disabled {})
+        ));
+
+        // no device is accessible, before the devices have been initialized
+        assert!(matches!(get_hidapi(), Err(RawHidError::NotInitialized {})));
+        assert!(matches!(get_device(0), Err(RawHidError::NotInitialized {})));
+    }
+}
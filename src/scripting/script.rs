@@ -15,25 +15,49 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use failure::Fail;
 use lazy_static::lazy_static;
 use log::*;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex, RwLock};
 use rand::Rng;
-use rlua::{Context, Function, Lua};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use rlua::{Context, FromLua, Function, Lua, RegistryKey, Thread, ThreadStatus, ToLua};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
-use std::sync::atomic::Ordering;
-use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
+use thiserror::Error;
 
+use crate::constants;
+use crate::logging;
+use crate::overlay;
+use crate::palettes;
 use crate::plugin_manager;
-use crate::rvdevice::{RvDeviceState, NUM_KEYS, RGBA};
+use crate::plugins::power;
+use crate::profiles::{self, GetAttr};
+use crate::rvdevice::{self, DeviceInfo, RvDeviceState, NUM_KEYS, RGBA};
+use crate::scripting::api;
+use crate::scripting::cache;
+use crate::scripting::capabilities::{self, Capability};
+use crate::scripting::clipboard;
+use crate::scripting::commands;
+use crate::scripting::debugger;
+use crate::scripting::exec;
+#[cfg(feature = "http")]
+use crate::scripting::http;
+use crate::scripting::integrity;
 use crate::scripting::manifest::{ConfigParam, Manifest};
-
+use crate::scripting::overrides;
+use crate::scripting::raw_hid;
+use crate::scripting::ticks;
+#[cfg(feature = "wasm")]
+use crate::scripting::wasm;
+
+use crate::repl;
+use crate::windows;
 use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
 
 pub enum Message {
@@ -43,24 +67,179 @@ pub enum Message {
     KeyDown(u8),
     KeyUp(u8),
 
+    /// a key has been held down for an implausibly long time, and has been released
+    KeyStuck(u8),
+
+    /// phase and remaining seconds of the pomodoro timer
+    TimerPhase(&'static str, u32),
+
+    /// do-not-disturb mode has been entered or left
+    DndChanged(bool),
+
+    /// the Easy Shift key has been pressed or released
+    EasyShift(bool),
+
+    /// the FN key has been pressed or released
+    FnKey(bool),
+
+    /// volume in percent and mute state of the audio sink
+    VolumeChanged(isize, bool),
+
+    /// the dial has been turned, contains the number of steps (positive is clockwise)
+    DialRotate(isize),
+
+    /// the dial has been pressed
+    DialPress,
+
+    /// a value of the shared store has been changed, contains the key
+    StoreChanged(String),
+
+    /// a device has failed, contains the index of the device and a description of the error
+    DeviceError(usize, String),
+
+    /// a device works again after it has failed, contains the index of the device
+    DeviceRecovered(usize),
+
+    /// the session of the user has been locked or unlocked
+    SessionLocked(bool),
+
+    /// a parameter of a script has been changed at runtime, contains the name of the script
+    ParamChanged(String, profiles::ConfigParam),
+
+    /// the response to an HTTP request of the script: id of the request, status code and body
+    HttpResponse(u64, u16, String),
+
+    /// a command of the script has finished: id of the command, exit status and output
+    ExecFinished(u64, i32, String),
+
+    /// a palette of the palette library has been published or replaced, contains its name
+    PaletteChanged(String),
+
+    /// the status of a CI pipeline has changed: name of the pipeline and new status
+    BuildStatusChanged(String, &'static str),
+
+    /// a chat client reported a mention: source (e.g. the name of the network) and text
+    Mention(String, String),
+
+    /// someone started or stopped speaking into the microphone
+    VoiceActivity(bool),
+
+    /// another application has been focused, contains its window class (empty if none)
+    ActiveAppChanged(String),
+
+    /// the accent color of the desktop environment has changed
+    AccentColorChanged(u32),
+
     //LoadScript(PathBuf),
     Unload,
 
-    /// blend LOCAL_LED_MAP with LED_MAP ("realize" the color map)
-    RealizeColorMap,
+    /// evaluate a snippet of Lua code in the context of the script, for the debug
+    /// console: name of the script, the code, and the channel for the result
+    Eval(String, String, std::sync::mpsc::Sender<repl::EvalResult>),
+
+    /// publish the frame of the script layer, so that it can be composed
+    /// with the other layers, contains the id of the render frame
+    RenderFrame(u64),
 }
 
 lazy_static! {
-    /// Global LED state of the managed device
-    pub static ref LED_MAP: Arc<Mutex<Vec<RGBA>>> = Arc::new(Mutex::new(vec![RGBA {
+    /// Origin of the monotonic clock, that is available to Lua scripts
+    pub static ref START_TIME: Instant = Instant::now();
+
+    /// Key-value store that is shared by all scripts
+    pub static ref STORE: Arc<Mutex<HashMap<String, StoreValue>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    /// Most recent frame of each script layer, indexed by layer. The condition
+    /// variable is signaled each time a layer publishes a new frame
+    pub static ref LAYER_FRAMES: Arc<(Mutex<Vec<Option<LayerFrame>>>, Condvar)> =
+        Arc::new((Mutex::new(vec![]), Condvar::new()));
+
+    /// Opacity of the script layers, indexed by layer. Layers without an entry are
+    /// fully opaque
+    static ref LAYER_OPACITY: RwLock<HashMap<usize, LayerOpacity>> = RwLock::new(HashMap::new());
+
+    /// Script layers that are bypassed by the compositor, their scripts keep running
+    static ref BYPASSED_LAYERS: RwLock<HashSet<usize>> = RwLock::new(HashSet::new());
+
+    /// The instance of the Lua VM that currently runs on each layer, and the sender
+    /// of its messages, indexed by layer
+    static ref INSTANCES: Mutex<HashMap<usize, (u64, Sender<Message>)>> = Mutex::new(HashMap::new());
+
+    /// Global LED state of the managed devices, one LED map per device.
+    /// The first LED map belongs to the default device
+    pub static ref LED_MAPS: Arc<Mutex<Vec<Vec<RGBA>>>> = Arc::new(Mutex::new(vec![vec![RGBA {
         r: 0x00,
         g: 0x00,
         b: 0x00,
         a: 0x00,
-    }; NUM_KEYS]));
+    }; NUM_KEYS]]));
+}
+
+/// The instance id of the next Lua VM
+static NEXT_INSTANCE: AtomicU64 = AtomicU64::new(1);
+
+/// Identifies a running Lua VM. Layers are reused by the scripts of the next
+/// profile, so results of asynchronous operations are routed by the instance
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScriptId {
+    pub layer: usize,
+    pub instance: u64,
+}
+
+impl ScriptId {
+    /// Register a new Lua VM on layer `layer`, that receives its messages via `tx`.
+    /// It replaces the previous one
+    fn register(layer: usize, tx: Sender<Message>) -> Self {
+        let instance = NEXT_INSTANCE.fetch_add(1, Ordering::SeqCst);
+
+        INSTANCES.lock().insert(layer, (instance, tx));
+
+        Self { layer, instance }
+    }
+
+    /// Unregister the Lua VM, unless it has already been replaced
+    fn unregister(self) {
+        let mut instances = INSTANCES.lock();
+
+        if instances.get(&self.layer).map(|(instance, _)| *instance) == Some(self.instance) {
+            instances.remove(&self.layer);
+        }
+    }
+
+    /// Returns true if the Lua VM is still running
+    pub fn is_running(self) -> bool {
+        INSTANCES
+            .lock()
+            .get(&self.layer)
+            .map(|(instance, _)| *instance)
+            == Some(self.instance)
+    }
+
+    /// Send the message `message` to the Lua VM, it is dropped if the VM is not
+    /// running anymore. The message never reaches the next VM on the same layer
+    pub fn send(self, message: Message) {
+        let instances = INSTANCES.lock();
+
+        match instances.get(&self.layer) {
+            Some((instance, lua_tx)) if *instance == self.instance => lua_tx
+                .send(message)
+                .unwrap_or_else(|e| error!("Send error: {}", e)),
+
+            _ => debug!(
+                "The script on layer {} has been unloaded, dropping a message",
+                self.layer
+            ),
+        }
+    }
 }
 
 thread_local! {
+    /// Set for scripts that failed the integrity check, and may not inject keys
+    pub static INJECTION_RESTRICTED: Cell<bool> = Cell::new(false);
+
+    /// The data directory of the script running on this thread, see `fs_read(path)`
+    pub static DATA_DIR: RefCell<Option<PathBuf>> = RefCell::new(None);
+
     /// LED color map to be realized on the next render frame
     pub static LOCAL_LED_MAP: RefCell<Vec<RGBA>> = RefCell::new(vec![RGBA {
         r: 0x00,
@@ -68,42 +247,406 @@ thread_local! {
         b: 0x00,
         a: 0x00,
     }; NUM_KEYS]);
+
+    /// The previously submitted LED color map, used by the frame interpolation stage
+    pub static PREVIOUS_LED_MAP: RefCell<Vec<RGBA>> = RefCell::new(vec![RGBA {
+        r: 0x00,
+        g: 0x00,
+        b: 0x00,
+        a: 0x00,
+    }; NUM_KEYS]);
+
+    /// State of the frame interpolation stage of this script layer
+    pub static FRAME_INTERPOLATION: RefCell<FrameInterpolation> =
+        RefCell::new(FrameInterpolation::default());
+
+    /// Coroutine based effects of this script, that are waiting to be resumed
+    static EFFECTS: RefCell<Vec<ScheduledEffect>> = RefCell::new(vec![]);
+}
+
+/// A coroutine based effect, registered by a script via `spawn_effect(func)`
+struct ScheduledEffect {
+    /// The Lua thread (coroutine) that runs the effect
+    thread: RegistryKey,
+
+    /// Value of the monotonic clock, at which the effect is to be resumed
+    resume_at: u64,
+}
+
+/// A frame, published by a script layer
+#[derive(Debug, Clone)]
+pub struct LayerFrame {
+    /// Id of the render frame that this frame has been rendered for
+    pub frame_id: u64,
+
+    /// Index of the device that the layer renders to
+    pub device: usize,
+
+    pub led_map: Vec<RGBA>,
+}
+
+/// Opacity of a script layer, that fades from `from` to `to` percent
+#[derive(Debug, Clone, Copy)]
+struct LayerOpacity {
+    from: f64,
+    to: f64,
+    since: Instant,
+    fade: Duration,
+}
+
+impl LayerOpacity {
+    /// The current opacity, in percent
+    fn get(&self) -> f64 {
+        let elapsed = self.since.elapsed();
+
+        if elapsed >= self.fade {
+            self.to
+        } else {
+            let t = elapsed.as_secs_f64() / self.fade.as_secs_f64();
+
+            self.from + (self.to - self.from) * t
+        }
+    }
+}
+
+/// Set the opacity of the script layer `layer` to `percent` (0-100), fading from
+/// the current opacity over the duration `fade`
+pub fn set_layer_opacity(layer: usize, percent: u32, fade: Duration) {
+    let mut opacity = LAYER_OPACITY.write();
+
+    let from = opacity.get(&layer).map_or(100.0, |opacity| opacity.get());
+
+    opacity.insert(
+        layer,
+        LayerOpacity {
+            from,
+            to: percent.min(100) as f64,
+            since: Instant::now(),
+            fade,
+        },
+    );
+}
+
+/// Get the opacity of the script layer `layer` in percent, or the target opacity
+/// if the layer is fading
+pub fn get_layer_opacity(layer: usize) -> u32 {
+    LAYER_OPACITY
+        .read()
+        .get(&layer)
+        .map_or(100, |opacity| opacity.to as u32)
+}
+
+/// Enable or bypass the script layer `layer`, the script of a bypassed layer keeps
+/// running, but its frames are not composed
+pub fn set_layer_enabled(layer: usize, enabled: bool) {
+    if enabled {
+        BYPASSED_LAYERS.write().remove(&layer);
+    } else {
+        BYPASSED_LAYERS.write().insert(layer);
+    }
+}
+
+/// Returns false if the script layer `layer` is bypassed
+pub fn is_layer_enabled(layer: usize) -> bool {
+    !BYPASSED_LAYERS.read().contains(&layer)
+}
+
+/// Alpha blend the most recent frames of the first `num_layers` script layers
+/// onto the LED maps of their target devices, in the order of the layers.
+/// Layers that did not publish a frame for the current render frame in time
+/// contribute their previous one. Bypassed layers are skipped, and the alpha
+/// channel of each layer is scaled by its opacity
+pub fn compose_layers(led_maps: &mut [Vec<RGBA>], num_layers: usize) {
+    let brightness = power::scale_brightness(crate::BRIGHTNESS.load(Ordering::SeqCst));
+
+    let opacity = LAYER_OPACITY.read();
+    let bypassed = BYPASSED_LAYERS.read();
+
+    for (index, layer) in LAYER_FRAMES.0.lock().iter().take(num_layers).enumerate() {
+        let layer = match layer {
+            Some(layer) if !bypassed.contains(&index) => layer,
+            _ => continue,
+        };
+
+        let opacity = opacity.get(&index).map_or(100.0, |opacity| opacity.get()) / 100.0;
+
+        if let Some(led_map) = led_maps.get_mut(layer.device) {
+            for (background, fg) in led_map.iter_mut().zip(layer.led_map.iter()) {
+                let bg = &background;
+                let fg = &RGBA {
+                    a: (fg.a as f64 * opacity).round() as u8,
+                    ..*fg
+                };
+
+                #[rustfmt::skip]
+                let color = RGBA {
+                    r: ((((fg.a as f64) * fg.r as f64 + (255 - fg.a) as f64 * bg.r as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
+                    g: ((((fg.a as f64) * fg.g as f64 + (255 - fg.a) as f64 * bg.g as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
+                    b: ((((fg.a as f64) * fg.b as f64 + (255 - fg.a) as f64 * bg.b as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
+                    a: fg.a as u8,
+                };
+
+                *background = color;
+            }
+        }
+    }
+}
+
+/// Wait until the first `num_layers` script layers published their frames for
+/// the render frame `frame_id`, or until `deadline` has passed. Returns the
+/// number of layers that missed the deadline
+pub fn wait_for_layers(frame_id: u64, num_layers: usize, deadline: Instant) -> usize {
+    let mut layer_frames = LAYER_FRAMES.0.lock();
+
+    loop {
+        let pending = (0..num_layers)
+            .filter(|i| match layer_frames.get(*i) {
+                Some(Some(frame)) => frame.frame_id < frame_id,
+                _ => true,
+            })
+            .count();
+
+        if pending == 0 || Instant::now() >= deadline {
+            return pending;
+        }
+
+        LAYER_FRAMES.1.wait_until(&mut layer_frames, deadline);
+    }
+}
+
+/// Forget the frames of all script layers, e.g. after the scripts have been reloaded.
+/// The opacity of the layers is reset, since another script may now occupy the layer
+pub fn clear_layers() {
+    LAYER_FRAMES.0.lock().clear();
+
+    LAYER_OPACITY.write().clear();
+    BYPASSED_LAYERS.write().clear();
+}
+
+/// A value of the shared store
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreValue {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+}
+
+impl<'lua> FromLua<'lua> for StoreValue {
+    fn from_lua(value: rlua::Value<'lua>, _lua_ctx: Context<'lua>) -> rlua::Result<Self> {
+        match value {
+            rlua::Value::Boolean(b) => Ok(StoreValue::Boolean(b)),
+            rlua::Value::Integer(i) => Ok(StoreValue::Integer(i)),
+            rlua::Value::Number(n) => Ok(StoreValue::Number(n)),
+            rlua::Value::String(s) => Ok(StoreValue::String(s.to_str()?.to_string())),
+
+            _ => Err(rlua::Error::RuntimeError(
+                "Only booleans, numbers and strings may be stored".into(),
+            )),
+        }
+    }
+}
+
+impl<'lua> ToLua<'lua> for StoreValue {
+    fn to_lua(self, lua_ctx: Context<'lua>) -> rlua::Result<rlua::Value<'lua>> {
+        match self {
+            StoreValue::Boolean(b) => Ok(rlua::Value::Boolean(b)),
+            StoreValue::Integer(i) => Ok(rlua::Value::Integer(i)),
+            StoreValue::Number(n) => Ok(rlua::Value::Number(n)),
+            StoreValue::String(s) => Ok(rlua::Value::String(lua_ctx.create_string(&s)?)),
+        }
+    }
+}
+
+/// Frame interpolation state of a script layer.
+/// When enabled, intermediate render frames are linearly blended between
+/// the last two submitted color maps
+#[derive(Debug, Default)]
+pub struct FrameInterpolation {
+    pub enabled: bool,
+
+    /// Number of render frames since the last color map has been submitted
+    pub frames_since_submit: u32,
+
+    /// Number of render frames between the last two submitted color maps
+    pub frames_per_submit: u32,
+}
+
+impl FrameInterpolation {
+    /// Get the blend factor in the range [0.0..1.0] for the current render frame
+    pub fn get_blend_factor(&self) -> f64 {
+        if !self.enabled || self.frames_per_submit == 0 {
+            1.0
+        } else {
+            f64::min(
+                f64::from(self.frames_since_submit) / f64::from(self.frames_per_submit),
+                1.0,
+            )
+        }
+    }
+}
+
+/// Linearly interpolate between the colors `from` and `to`, where `p` lies in [0.0..1.0]
+fn interpolate_rgba(from: RGBA, to: RGBA, p: f64) -> RGBA {
+    let lerp = |a: u8, b: u8| (f64::from(a) + (f64::from(b) - f64::from(a)) * p).round() as u8;
+
+    RGBA {
+        r: lerp(from.r, to.r),
+        g: lerp(from.g, to.g),
+        b: lerp(from.b, to.b),
+        a: lerp(from.a, to.a),
+    }
+}
+
+/// Get the color map of this script layer for the current render frame.
+/// Applies the frame interpolation stage, if enabled
+fn get_current_frame() -> Vec<RGBA> {
+    FRAME_INTERPOLATION.with(|state| {
+        let mut state = state.borrow_mut();
+
+        let p = state.get_blend_factor();
+        state.frames_since_submit = state.frames_since_submit.saturating_add(1);
+
+        LOCAL_LED_MAP.with(|current| {
+            let current = current.borrow();
+
+            if p >= 1.0 {
+                current.clone()
+            } else {
+                PREVIOUS_LED_MAP.with(|previous| {
+                    previous
+                        .borrow()
+                        .iter()
+                        .zip(current.iter())
+                        .map(|(from, to)| interpolate_rgba(*from, *to, p))
+                        .collect()
+                })
+            }
+        })
+    })
+}
+
+/// Register the function `func` as a coroutine based effect. The effect
+/// is started on the next timer tick
+fn spawn_effect(lua_ctx: Context, func: Function) -> rlua::Result<()> {
+    let thread = lua_ctx.create_thread(func)?;
+    let thread = lua_ctx.create_registry_value(thread)?;
+
+    EFFECTS.with(|effects| {
+        effects.borrow_mut().push(ScheduledEffect {
+            thread,
+            resume_at: 0,
+        })
+    });
+
+    Ok(())
+}
+
+/// Resume all coroutine based effects that are due. An effect yields the
+/// number of milliseconds it wants to wait, before it is resumed again
+fn resume_effects(lua_ctx: Context) -> rlua::Result<()> {
+    let now = START_TIME.elapsed().as_millis() as u64;
+
+    // effects may spawn other effects while running
+    let effects = EFFECTS.with(|effects| effects.replace(vec![]));
+    let mut pending = vec![];
+
+    for mut effect in effects {
+        if effect.resume_at > now {
+            pending.push(effect);
+            continue;
+        }
+
+        let thread: Thread = lua_ctx.registry_value(&effect.thread)?;
+
+        match thread.resume::<_, Option<u64>>(()) {
+            Ok(wait_millis) => {
+                if thread.status() == ThreadStatus::Resumable {
+                    effect.resume_at = now + wait_millis.unwrap_or(0);
+                    pending.push(effect);
+                } else {
+                    // the effect has finished
+                    lua_ctx.remove_registry_value(effect.thread)?;
+                }
+            }
+
+            Err(e) => {
+                error!(target: logging::SCRIPT_ERRORS, "Lua error in effect: {}", e);
+                lua_ctx.remove_registry_value(effect.thread)?;
+            }
+        }
+    }
+
+    EFFECTS.with(|effects| {
+        let mut effects = effects.borrow_mut();
+
+        pending.append(&mut effects);
+        *effects = pending;
+    });
+
+    Ok(())
 }
 
 pub type Result<T> = std::result::Result<T, ScriptingError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum ScriptingError {
-    #[fail(display = "Could not read script file")]
+    #[error("Could not read script file")]
     OpenError {},
 
-    #[fail(display = "Lua errors present")]
+    #[error("Lua errors present")]
     LuaError { e: rlua::Error },
 
-    #[fail(display = "Invalid or inaccessible manifest file")]
+    #[error("Invalid or inaccessible manifest file")]
     InaccessibleManifest {},
-    // #[fail(display = "Unknown error: {}", description)]
+
+    #[error("External effect error: {description}")]
+    ExecError { description: String },
+
+    #[error("Integrity check failed: {description}")]
+    IntegrityError { description: String },
+
+    #[error("The script requires API level {required}, supported is {supported}")]
+    UnsupportedApiLevel { required: String, supported: String },
+
+    #[cfg(feature = "wasm")]
+    #[error("WASM error: {description}")]
+    WasmError { description: String },
+    // #[error("Unknown error: {description}")]
     // UnknownError { description: String },
 }
 
 /// These functions are intended to be used from within lua scripts
-mod callbacks {
+pub(crate) mod callbacks {
     use byteorder::{ByteOrder, LittleEndian};
     use log::*;
     use noise::{Billow, Fbm, NoiseFn, OpenSimplex, Perlin, RidgedMulti, Worley};
     use palette::ConvertFrom;
     use palette::{Hsl, Srgb};
     use parking_lot::Mutex;
+    use std::collections::HashMap;
     use std::convert::TryFrom;
+    use std::fs;
+    use std::iter;
+    use std::path::{Component, Path, PathBuf};
     use std::sync::atomic::Ordering;
     use std::sync::Arc;
     use std::thread;
     use std::time::Duration;
 
-    use super::{LED_MAP, LOCAL_LED_MAP};
+    use chrono::{Datelike, Local, NaiveDate, Timelike};
+    use rlua::Context;
+
+    use super::{
+        StoreValue, DATA_DIR, FRAME_INTERPOLATION, INJECTION_RESTRICTED, LED_MAPS, LOCAL_LED_MAP,
+        PREVIOUS_LED_MAP, START_TIME, STORE,
+    };
 
+    use crate::constants;
+    use crate::logging;
     use crate::plugins::macros;
-    use crate::rvdevice::{RvDeviceState, NUM_KEYS, RGBA};
+    use crate::rvdevice::{DeviceInfo, RvDeviceState, NUM_KEYS, RGBA};
+    use crate::scripting::{cache, canvas};
 
     /// Log a message with severity level `trace`.
     pub(crate) fn log_trace(x: &str) {
@@ -127,7 +670,7 @@ mod callbacks {
 
     /// Log a message with severity level `error`.
     pub(crate) fn log_error(x: &str) {
-        error!("{}", x);
+        error!(target: logging::SCRIPT_ERRORS, "{}", x);
     }
 
     /// Delay execution of the lua script by `millis` milliseconds.
@@ -135,121 +678,477 @@ mod callbacks {
         thread::sleep(Duration::from_millis(millis));
     }
 
-    /// Inject a key on the eruption virtual keyboard.
-    pub(crate) fn inject_key(ev_key: u32, down: bool) {
-        // calling inject_key(..) from Lua will drop the current input;
-        // the original key event from the hardware keyboard will not be
-        // mirrored on the virtual keyboard.
-        macros::DROP_CURRENT_KEY.store(true, Ordering::SeqCst);
+    /// Enter or leave do-not-disturb mode.
+    pub(crate) fn set_dnd(enable: bool) {
+        crate::DO_NOT_DISTURB.store(enable, Ordering::SeqCst);
+    }
 
-        macros::UINPUT_TX
-            .lock()
-            .as_ref()
-            .unwrap()
-            .send(macros::Message::InjectKey { key: ev_key, down })
-            .unwrap();
+    /// Returns true if do-not-disturb mode is active.
+    pub(crate) fn get_dnd() -> bool {
+        crate::DO_NOT_DISTURB.load(Ordering::SeqCst)
     }
 
-    /// Get RGB components of a 32 bits color value.
-    pub(crate) fn color_to_rgb(c: u32) -> (u8, u8, u8) {
-        let r = u8::try_from((c >> 16) & 0xff).unwrap();
-        let g = u8::try_from((c >> 8) & 0xff).unwrap();
-        let b = u8::try_from(c & 0xff).unwrap();
+    /// Set the value of `key` in the shared store, a value of None removes the key.
+    /// All scripts are notified via `on_store_changed(key)` if the value changed.
+    pub(crate) fn store_set(key: &str, value: Option<StoreValue>) {
+        let previous = match value {
+            Some(value) => STORE.lock().insert(key.to_string(), value.clone()),
+            None => STORE.lock().remove(key),
+        };
 
-        (r, g, b)
+        if previous != value {
+            for lua_tx in crate::LUA_TXS.lock().iter() {
+                lua_tx
+                    .send(super::Message::StoreChanged(key.to_string()))
+                    .unwrap_or_else(|e| error!("Send error: {}", e));
+            }
+        }
     }
 
-    /// Get RGB components of a 32 bits color value.
-    #[allow(clippy::many_single_char_names)]
-    pub(crate) fn color_to_rgba(c: u32) -> (u8, u8, u8, u8) {
-        let a = u8::try_from((c >> 24) & 0xff).unwrap();
-        let r = u8::try_from((c >> 16) & 0xff).unwrap();
-        let g = u8::try_from((c >> 8) & 0xff).unwrap();
-        let b = u8::try_from(c & 0xff).unwrap();
+    /// Get the value of `key` from the shared store.
+    pub(crate) fn store_get(key: &str) -> Option<StoreValue> {
+        STORE.lock().get(key).cloned()
+    }
 
-        (r, g, b, a)
+    /// Get the cached table `key` of the running script, or None if it is not cached.
+    pub(crate) fn cache_get(key: &str) -> Option<Vec<f64>> {
+        cache::get(key)
     }
 
-    /// Get HSL components of a 32 bits color value.
-    #[allow(clippy::many_single_char_names)]
-    pub(crate) fn color_to_hsl(c: u32) -> (f64, f64, f64) {
-        let (r, g, b) = color_to_rgb(c);
-        let rgb =
-            Srgb::from_components(((r as f64 / 255.0), (g as f64 / 255.0), (b as f64 / 255.0)));
+    /// Store the table `values` as the entry `key` in the cache of the running script.
+    pub(crate) fn cache_put(key: &str, values: &[f64]) {
+        cache::put(key, values).unwrap_or_else(|e| error!("Could not cache '{}': {}", key, e));
+    }
 
-        let (h, s, l) = Hsl::from(rgb).into_components();
+    /// Resolve the path `path` below the data directory `data_dir`. Returns None for
+    /// absolute paths, and paths that would leave the data directory.
+    pub(crate) fn resolve_data_path(data_dir: &Path, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
 
-        (h.into(), s, l)
-    }
+        let is_safe = path.components().count() > 0
+            && path.components().all(|c| match c {
+                Component::Normal(_) | Component::CurDir => true,
+                _ => false,
+            });
 
-    /// Convert RGB components to a 32 bits color value.
-    pub(crate) fn rgb_to_color(r: u8, g: u8, b: u8) -> u32 {
-        LittleEndian::read_u32(&[b, g, r, 255])
+        if is_safe {
+            Some(data_dir.join(path))
+        } else {
+            None
+        }
     }
 
-    /// Convert RGBA components to a 32 bits color value.
-    pub(crate) fn rgba_to_color(r: u8, g: u8, b: u8, a: u8) -> u32 {
-        LittleEndian::read_u32(&[b, g, r, a])
-    }
+    fn get_data_path(path: &str) -> Option<PathBuf> {
+        DATA_DIR.with(|data_dir| {
+            let data_dir = data_dir.borrow();
+            let result = data_dir
+                .as_ref()
+                .and_then(|data_dir| resolve_data_path(data_dir, path));
 
-    /// Convert HSL components to a 32 bits color value.
-    pub(crate) fn hsl_to_color(h: f64, s: f64, l: f64) -> u32 {
-        let rgb = Srgb::convert_from(Hsl::new(h, s, l));
-        let rgb = rgb.into_components();
-        rgba_to_color(
-            (rgb.0 * 255.0) as u8,
-            (rgb.1 * 255.0) as u8,
-            (rgb.2 * 255.0) as u8,
-            255,
-        )
+            if result.is_none() {
+                warn!("Access denied, invalid path: {}", path);
+            }
+
+            result
+        })
     }
 
-    /// Convert HSLA components to a 32 bits color value.
-    pub(crate) fn hsla_to_color(h: f64, s: f64, l: f64, a: u8) -> u32 {
-        let rgb = Srgb::convert_from(Hsl::new(h, s, l));
-        let rgb = rgb.into_components();
-        rgba_to_color(
-            (rgb.0 * 255.0) as u8,
-            (rgb.1 * 255.0) as u8,
-            (rgb.2 * 255.0) as u8,
-            a,
-        )
+    /// Read the file `path` from the data directory of the running script.
+    pub(crate) fn fs_read(path: &str) -> Option<String> {
+        let path = get_data_path(path)?;
+
+        fs::read_to_string(&path)
+            .map_err(|e| debug!("Could not read {}: {}", path.display(), e))
+            .ok()
     }
 
-    /// Generate a linear RGB color gradient from start to dest color,
-    /// where p must lie in the range from [0.0..1.0].
-    #[allow(clippy::many_single_char_names)]
-    pub(crate) fn linear_gradient(start: u32, dest: u32, p: f64) -> u32 {
-        let sca: f64 = f64::from((start >> 24) & 0xff);
-        let scr: f64 = f64::from((start >> 16) & 0xff);
-        let scg: f64 = f64::from((start >> 8) & 0xff);
-        let scb: f64 = f64::from((start) & 0xff);
+    /// Write `data` to the file `path` in the data directory of the running script.
+    pub(crate) fn fs_write(path: &str, data: &str) -> bool {
+        if data.len() > constants::MAX_DATA_FILE_SIZE {
+            warn!(
+                "Refusing to write {} bytes, the limit is {} bytes",
+                data.len(),
+                constants::MAX_DATA_FILE_SIZE
+            );
 
-        let dca: f64 = f64::from((dest >> 24) & 0xff);
-        let dcr: f64 = f64::from((dest >> 16) & 0xff);
-        let dcg: f64 = f64::from((dest >> 8) & 0xff);
-        let dcb: f64 = f64::from((dest) & 0xff);
+            return false;
+        }
 
-        let r: f64 = (scr as f64) + (((dcr - scr) as f64) * p);
-        let g: f64 = (scg as f64) + (((dcg - scg) as f64) * p);
-        let b: f64 = (scb as f64) + (((dcb - scb) as f64) * p);
-        let a: f64 = (sca as f64) + (((dca - sca) as f64) * p);
+        let path = match get_data_path(path) {
+            Some(path) => path,
+            None => return false,
+        };
 
-        rgba_to_color(
-            r.round() as u8,
-            g.round() as u8,
-            b.round() as u8,
-            a.round() as u8,
-        )
-    }
+        let result = path
+            .parent()
+            .map_or(Ok(()), |parent| fs::create_dir_all(parent))
+            .and_then(|_| fs::write(&path, data));
 
-    /// Compute Perlin noise
-    pub(crate) fn perlin_noise(f1: f64, f2: f64, f3: f64) -> f64 {
-        let noise = Perlin::new();
-        noise.get([f1, f2, f3])
+        match result {
+            Ok(()) => true,
+
+            Err(e) => {
+                error!("Could not write {}: {}", path.display(), e);
+                false
+            }
+        }
     }
 
-    /// Compute Billow noise
+    /// Convert the JSON value `value` to a Lua value. Objects and arrays are
+    /// converted to tables, `null` is converted to `nil`.
+    pub(crate) fn json_to_lua<'lua>(
+        lua_ctx: Context<'lua>,
+        value: &serde_json::Value,
+    ) -> rlua::Result<rlua::Value<'lua>> {
+        use serde_json::Value;
+
+        Ok(match value {
+            Value::Null => rlua::Nil,
+            Value::Bool(b) => rlua::Value::Boolean(*b),
+
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => rlua::Value::Integer(i),
+                None => rlua::Value::Number(n.as_f64().unwrap_or(0.0)),
+            },
+
+            Value::String(s) => rlua::Value::String(lua_ctx.create_string(s)?),
+
+            Value::Array(values) => {
+                let table = lua_ctx.create_table()?;
+
+                for (i, value) in values.iter().enumerate() {
+                    table.set(i + 1, json_to_lua(lua_ctx, value)?)?;
+                }
+
+                rlua::Value::Table(table)
+            }
+
+            Value::Object(map) => {
+                let table = lua_ctx.create_table()?;
+
+                for (key, value) in map.iter() {
+                    table.set(key.as_str(), json_to_lua(lua_ctx, value)?)?;
+                }
+
+                rlua::Value::Table(table)
+            }
+        })
+    }
+
+    /// Convert the Lua value `value` to a JSON value. Tables with the keys 1..n are
+    /// converted to arrays, all other tables are converted to objects. Tables that
+    /// contain themselves, are nested too deeply or are too large are rejected.
+    pub(crate) fn lua_to_json<'lua>(
+        lua_ctx: Context<'lua>,
+        value: rlua::Value<'lua>,
+    ) -> rlua::Result<serde_json::Value> {
+        // tables are compared by identity, when used as keys of a table
+        let ancestors = lua_ctx.create_table()?;
+        let mut count = 0;
+
+        lua_to_json_value(&ancestors, value, 0, &mut count)
+    }
+
+    fn lua_to_json_value<'lua>(
+        ancestors: &rlua::Table<'lua>,
+        value: rlua::Value<'lua>,
+        depth: usize,
+        count: &mut usize,
+    ) -> rlua::Result<serde_json::Value> {
+        use serde_json::Value;
+
+        *count += 1;
+
+        if *count > constants::ENCODE_MAX_VALUES {
+            return Err(rlua::Error::RuntimeError(format!(
+                "Too many values, at most {} values can be encoded",
+                constants::ENCODE_MAX_VALUES
+            )));
+        }
+
+        Ok(match value {
+            rlua::Value::Nil => Value::Null,
+            rlua::Value::Boolean(b) => Value::Bool(b),
+            rlua::Value::Integer(i) => Value::from(i),
+            rlua::Value::Number(f) => Value::from(f),
+            rlua::Value::String(s) => Value::String(s.to_str()?.to_string()),
+
+            rlua::Value::Table(table) => {
+                if depth >= constants::ENCODE_MAX_DEPTH {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "Tables are nested too deeply, the max. depth is {}",
+                        constants::ENCODE_MAX_DEPTH
+                    )));
+                }
+
+                if ancestors.get::<_, Option<bool>>(table.clone())?.is_some() {
+                    return Err(rlua::Error::RuntimeError(
+                        "Tables that contain themselves can not be encoded".into(),
+                    ));
+                }
+
+                ancestors.set(table.clone(), true)?;
+
+                let len = table.len()? as usize;
+                let pairs: Vec<(rlua::Value, rlua::Value)> =
+                    table.pairs().collect::<rlua::Result<_>>()?;
+
+                let result = if len > 0 && pairs.len() == len {
+                    let mut values = vec![];
+
+                    for i in 1..=len {
+                        values.push(lua_to_json_value(
+                            ancestors,
+                            table.get(i)?,
+                            depth + 1,
+                            count,
+                        )?);
+                    }
+
+                    Value::Array(values)
+                } else {
+                    let mut map = serde_json::Map::new();
+
+                    for (key, value) in pairs {
+                        let key = match key {
+                            rlua::Value::String(s) => s.to_str()?.to_string(),
+                            rlua::Value::Integer(i) => i.to_string(),
+                            rlua::Value::Number(f) => f.to_string(),
+
+                            _ => {
+                                return Err(rlua::Error::RuntimeError(
+                                    "Invalid key, expected a string or a number".into(),
+                                ))
+                            }
+                        };
+
+                        map.insert(key, lua_to_json_value(ancestors, value, depth + 1, count)?);
+                    }
+
+                    Value::Object(map)
+                };
+
+                // tables may be referenced more than once, by different parents
+                ancestors.set(table, rlua::Nil)?;
+
+                result
+            }
+
+            _ => {
+                return Err(rlua::Error::RuntimeError(
+                    "Only tables, strings, numbers and booleans can be encoded".into(),
+                ))
+            }
+        })
+    }
+
+    /// Get the number of milliseconds elapsed since the daemon has been started.
+    /// The value is taken from a monotonic clock, so it is unaffected by changes
+    /// of the system time.
+    pub(crate) fn get_time_millis() -> u64 {
+        START_TIME.elapsed().as_millis() as u64
+    }
+
+    /// Get the current local time as a table.
+    pub(crate) fn get_local_time() -> HashMap<&'static str, u32> {
+        let now = Local::now();
+
+        let mut result = HashMap::new();
+
+        result.insert("year", now.year() as u32);
+        result.insert("month", now.month());
+        result.insert("day", now.day());
+        result.insert("hour", now.hour());
+        result.insert("minute", now.minute());
+        result.insert("second", now.second());
+        result.insert("weekday", now.weekday().number_from_monday());
+        result.insert("yearday", now.ordinal());
+
+        result
+    }
+
+    /// Get the number of days from `from` until `date`. The date has to be
+    /// specified as "YYYY-MM-DD". Dates that lie in the past yield negative values.
+    pub(crate) fn days_between(from: NaiveDate, date: &str) -> Option<i64> {
+        match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => Some(date.signed_duration_since(from).num_days()),
+
+            Err(e) => {
+                error!("Invalid date '{}': {}", date, e);
+                None
+            }
+        }
+    }
+
+    /// Get the number of days from today until `date`.
+    pub(crate) fn days_until(date: &str) -> Option<i64> {
+        days_between(Local::today().naive_local(), date)
+    }
+
+    /// Inject a key on the eruption virtual keyboard. If `pacing_millis` is set, it
+    /// overrides the default delay after the injection
+    pub(crate) fn inject_key(ev_key: u32, down: bool, pacing_millis: Option<u64>) {
+        // the original input must not be dropped, if the injected key would be
+        if macros::INJECTION_BLOCKED.load(Ordering::SeqCst) {
+            return;
+        }
+
+        // unsigned scripts may be restricted by the security policy
+        if INJECTION_RESTRICTED.with(|restricted| restricted.get()) {
+            debug!("Key injection denied, the script is not signed");
+            return;
+        }
+
+        // calling inject_key(..) from Lua will drop the current input;
+        // the original key event from the hardware keyboard will not be
+        // mirrored on the virtual keyboard.
+        macros::DROP_CURRENT_KEY.store(true, Ordering::SeqCst);
+
+        macros::queue_injection(macros::Message::InjectKey {
+            key: ev_key,
+            down,
+            pacing_millis,
+        });
+    }
+
+    /// Press and release a key on the eruption virtual keyboard, while exactly the
+    /// modifiers `modifiers` are held down. Modifiers that are held down on the
+    /// keyboard are released first and restored afterwards, unless `neutralize` is false
+    pub(crate) fn inject_with_modifiers(
+        ev_key: u32,
+        modifiers: Vec<u32>,
+        neutralize: bool,
+        pacing_millis: Option<u64>,
+    ) {
+        if macros::INJECTION_BLOCKED.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if INJECTION_RESTRICTED.with(|restricted| restricted.get()) {
+            debug!("Key injection denied, the script is not signed");
+            return;
+        }
+
+        macros::DROP_CURRENT_KEY.store(true, Ordering::SeqCst);
+
+        macros::queue_injection(macros::Message::InjectCombination {
+            key: ev_key,
+            modifiers,
+            neutralize,
+            pacing_millis,
+        });
+    }
+
+    /// Abort all running macros, see `macros::abort_macros()`
+    pub(crate) fn abort_macro() {
+        macros::abort_macros();
+    }
+
+    /// Returns true while injected keys are queued on the virtual keyboard
+    pub(crate) fn is_macro_running() -> bool {
+        macros::is_macro_running()
+    }
+
+    /// Get RGB components of a 32 bits color value.
+    pub(crate) fn color_to_rgb(c: u32) -> (u8, u8, u8) {
+        let r = u8::try_from((c >> 16) & 0xff).unwrap();
+        let g = u8::try_from((c >> 8) & 0xff).unwrap();
+        let b = u8::try_from(c & 0xff).unwrap();
+
+        (r, g, b)
+    }
+
+    /// Get RGB components of a 32 bits color value.
+    #[allow(clippy::many_single_char_names)]
+    pub(crate) fn color_to_rgba(c: u32) -> (u8, u8, u8, u8) {
+        let a = u8::try_from((c >> 24) & 0xff).unwrap();
+        let r = u8::try_from((c >> 16) & 0xff).unwrap();
+        let g = u8::try_from((c >> 8) & 0xff).unwrap();
+        let b = u8::try_from(c & 0xff).unwrap();
+
+        (r, g, b, a)
+    }
+
+    /// Get HSL components of a 32 bits color value.
+    #[allow(clippy::many_single_char_names)]
+    pub(crate) fn color_to_hsl(c: u32) -> (f64, f64, f64) {
+        let (r, g, b) = color_to_rgb(c);
+        let rgb =
+            Srgb::from_components(((r as f64 / 255.0), (g as f64 / 255.0), (b as f64 / 255.0)));
+
+        let (h, s, l) = Hsl::from(rgb).into_components();
+
+        (h.into(), s, l)
+    }
+
+    /// Convert RGB components to a 32 bits color value.
+    pub(crate) fn rgb_to_color(r: u8, g: u8, b: u8) -> u32 {
+        LittleEndian::read_u32(&[b, g, r, 255])
+    }
+
+    /// Convert RGBA components to a 32 bits color value.
+    pub(crate) fn rgba_to_color(r: u8, g: u8, b: u8, a: u8) -> u32 {
+        LittleEndian::read_u32(&[b, g, r, a])
+    }
+
+    /// Convert HSL components to a 32 bits color value.
+    pub(crate) fn hsl_to_color(h: f64, s: f64, l: f64) -> u32 {
+        let rgb = Srgb::convert_from(Hsl::new(h, s, l));
+        let rgb = rgb.into_components();
+        rgba_to_color(
+            (rgb.0 * 255.0) as u8,
+            (rgb.1 * 255.0) as u8,
+            (rgb.2 * 255.0) as u8,
+            255,
+        )
+    }
+
+    /// Convert HSLA components to a 32 bits color value.
+    pub(crate) fn hsla_to_color(h: f64, s: f64, l: f64, a: u8) -> u32 {
+        let rgb = Srgb::convert_from(Hsl::new(h, s, l));
+        let rgb = rgb.into_components();
+        rgba_to_color(
+            (rgb.0 * 255.0) as u8,
+            (rgb.1 * 255.0) as u8,
+            (rgb.2 * 255.0) as u8,
+            a,
+        )
+    }
+
+    /// Generate a linear RGB color gradient from start to dest color,
+    /// where p must lie in the range from [0.0..1.0].
+    #[allow(clippy::many_single_char_names)]
+    pub(crate) fn linear_gradient(start: u32, dest: u32, p: f64) -> u32 {
+        let sca: f64 = f64::from((start >> 24) & 0xff);
+        let scr: f64 = f64::from((start >> 16) & 0xff);
+        let scg: f64 = f64::from((start >> 8) & 0xff);
+        let scb: f64 = f64::from((start) & 0xff);
+
+        let dca: f64 = f64::from((dest >> 24) & 0xff);
+        let dcr: f64 = f64::from((dest >> 16) & 0xff);
+        let dcg: f64 = f64::from((dest >> 8) & 0xff);
+        let dcb: f64 = f64::from((dest) & 0xff);
+
+        let r: f64 = (scr as f64) + (((dcr - scr) as f64) * p);
+        let g: f64 = (scg as f64) + (((dcg - scg) as f64) * p);
+        let b: f64 = (scb as f64) + (((dcb - scb) as f64) * p);
+        let a: f64 = (sca as f64) + (((dca - sca) as f64) * p);
+
+        rgba_to_color(
+            r.round() as u8,
+            g.round() as u8,
+            b.round() as u8,
+            a.round() as u8,
+        )
+    }
+
+    /// Compute Perlin noise
+    pub(crate) fn perlin_noise(f1: f64, f2: f64, f3: f64) -> f64 {
+        let noise = Perlin::new();
+        noise.get([f1, f2, f3])
+    }
+
+    /// Compute Billow noise
     pub(crate) fn billow_noise(f1: f64, f2: f64, f3: f64) -> f64 {
         let noise = Billow::new();
         noise.get([f1, f2, f3])
@@ -339,36 +1238,96 @@ mod callbacks {
         NUM_KEYS
     }
 
-    /// Get the current color of the key `idx`.
-    pub(crate) fn get_key_color(rvdevid: &str, idx: usize) -> u32 {
-        error!("{}: {}", rvdevid, idx);
-        0
+    /// Get information about the managed device, as a table of strings.
+    pub(crate) fn get_device_info(device_info: &DeviceInfo) -> HashMap<&'static str, String> {
+        let mut result = HashMap::new();
+
+        result.insert("model_name", device_info.model_name.clone());
+        result.insert("firmware_version", device_info.firmware_version.clone());
+        result.insert("serial_number", device_info.serial_number.clone());
+        result.insert("usb_path", device_info.usb_path.clone());
+
+        result
+    }
+
+    /// Get the current color of the key `idx` of the device `device`.
+    pub(crate) fn get_key_color(device: usize, idx: usize) -> u32 {
+        match LED_MAPS
+            .lock()
+            .get(device)
+            .and_then(|led_map| led_map.get(idx))
+        {
+            Some(v) => {
+                ((v.r as u32).overflowing_shl(16).0
+                    + (v.g as u32).overflowing_shl(8).0
+                    + v.b as u32) as u32
+            }
+
+            None => {
+                error!("Invalid device or key index: {}: {}", device, idx);
+                0
+            }
+        }
     }
 
-    /// Set the color of the key `idx` to `c`.
-    pub(crate) fn set_key_color(rvdev: &Arc<Mutex<RvDeviceState>>, idx: usize, c: u32) {
-        let mut led_map = LED_MAP.lock();
-        led_map[idx] = RGBA {
-            a: u8::try_from((c >> 24) & 0xff).unwrap(),
-            r: u8::try_from((c >> 16) & 0xff).unwrap(),
-            g: u8::try_from((c >> 8) & 0xff).unwrap(),
-            b: u8::try_from(c & 0xff).unwrap(),
+    /// Set the color of the key `idx` of the device `device` to `c`.
+    pub(crate) fn set_key_color(
+        rvdevs: &[Arc<Mutex<RvDeviceState>>],
+        device: usize,
+        idx: usize,
+        c: u32,
+    ) -> rlua::Result<()> {
+        let mut led_maps = LED_MAPS.lock();
+
+        let (rvdev, led_map) = match (rvdevs.get(device), led_maps.get_mut(device)) {
+            (Some(rvdev), Some(led_map)) => (rvdev, led_map),
+
+            _ => {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "Invalid device index: {}",
+                    device
+                )))
+            }
         };
 
-        let mut rvdev = rvdev.lock();
+        match led_map.get_mut(idx) {
+            Some(color) => {
+                *color = RGBA {
+                    a: u8::try_from((c >> 24) & 0xff).unwrap(),
+                    r: u8::try_from((c >> 16) & 0xff).unwrap(),
+                    g: u8::try_from((c >> 8) & 0xff).unwrap(),
+                    b: u8::try_from(c & 0xff).unwrap(),
+                };
+            }
+
+            None => {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "Invalid key index: {}",
+                    idx
+                )))
+            }
+        }
 
         rvdev
-            .send_led_map(&*led_map)
+            .lock()
+            .submit_led_map(&*led_map)
             .unwrap_or_else(|e| error!("Could not send the LED map to the keyboard: {}", e));
 
-        thread::sleep(Duration::from_millis(
-            crate::constants::DEVICE_SETTLE_MILLIS,
-        ));
+        Ok(())
     }
 
-    /// Get state of all LEDs
-    pub(crate) fn get_color_map() -> Vec<u32> {
-        let global_led_map = LED_MAP.lock();
+    /// Get state of all LEDs of the device `device`
+    pub(crate) fn get_color_map(device: usize) -> Vec<u32> {
+        let led_maps = LED_MAPS.lock();
+
+        let global_led_map = match led_maps.get(device) {
+            Some(led_map) => led_map,
+
+            None => {
+                error!("Invalid device index: {}", device);
+                return vec![0; NUM_KEYS];
+            }
+        };
 
         let result = global_led_map
             .iter()
@@ -384,208 +1343,841 @@ mod callbacks {
         result
     }
 
-    /// Set all LEDs at once.
-    pub(crate) fn set_color_map(rvdev: &Arc<Mutex<RvDeviceState>>, map: &[u32]) {
-        assert!(map.len() == NUM_KEYS);
-
-        let mut led_map = [RGBA {
-            r: 0,
-            g: 0,
-            b: 0,
-            a: 0,
-        }; NUM_KEYS];
-
-        let mut i = 0;
-        loop {
-            led_map[i] = RGBA {
-                a: u8::try_from((map[i] >> 24) & 0xff).unwrap(),
-                r: u8::try_from((map[i] >> 16) & 0xff).unwrap(),
-                g: u8::try_from((map[i] >> 8) & 0xff).unwrap(),
-                b: u8::try_from(map[i] & 0xff).unwrap(),
-            };
+    /// Convert a color map supplied by a Lua script to `RGBA` values.
+    /// Maps that do not contain exactly `NUM_KEYS` entries are padded with
+    /// transparent black or truncated, respectively.
+    pub(crate) fn color_map_to_rgba(map: &[u32]) -> Vec<RGBA> {
+        if map.len() != NUM_KEYS {
+            warn!(
+                "Color map has an invalid size: {} (expected {} entries), it will be padded or truncated",
+                map.len(),
+                NUM_KEYS
+            );
+        }
 
-            i += 1;
-            if i >= NUM_KEYS - 1 {
-                break;
+        map.iter()
+            .map(|c| RGBA {
+                a: u8::try_from((c >> 24) & 0xff).unwrap(),
+                r: u8::try_from((c >> 16) & 0xff).unwrap(),
+                g: u8::try_from((c >> 8) & 0xff).unwrap(),
+                b: u8::try_from(c & 0xff).unwrap(),
+            })
+            .chain(iter::repeat(RGBA {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            }))
+            .take(NUM_KEYS)
+            .collect()
+    }
+
+    /// Set all LEDs of the device `device` at once.
+    pub(crate) fn set_color_map(rvdevs: &[Arc<Mutex<RvDeviceState>>], device: usize, map: &[u32]) {
+        let led_map = color_map_to_rgba(map);
+
+        let mut led_maps = LED_MAPS.lock();
+
+        let (rvdev, global_led_map) = match (rvdevs.get(device), led_maps.get_mut(device)) {
+            (Some(rvdev), Some(global_led_map)) => (rvdev, global_led_map),
+
+            _ => {
+                error!("Invalid device index: {}", device);
+                return;
             }
-        }
+        };
 
-        let mut global_led_map = LED_MAP.lock();
-        *global_led_map = led_map.to_vec();
+        global_led_map.copy_from_slice(&led_map);
 
-        let mut rvdev = rvdev.lock();
         rvdev
-            .send_led_map(&led_map)
+            .lock()
+            .submit_led_map(&led_map)
             .unwrap_or_else(|e| error!("Could not send the LED map to the keyboard: {}", e));
-
-        thread::sleep(Duration::from_millis(
-            crate::constants::DEVICE_SETTLE_MILLIS,
-        ));
     }
 
     /// Submit LED color map for later realization, as soon as the
     /// next frame is rendered
     pub(crate) fn submit_color_map(map: &[u32]) {
-        //debug!("submit_color_map: {}/{}", map.len(), NUM_KEYS);
-        assert!(map.len() == NUM_KEYS);
-
-        let mut led_map = [RGBA {
-            r: 0,
-            g: 0,
-            b: 0,
-            a: 0,
-        }; NUM_KEYS];
-
-        let mut i = 0;
-        loop {
-            led_map[i] = RGBA {
-                a: u8::try_from((map[i] >> 24) & 0xff).unwrap(),
-                r: u8::try_from((map[i] >> 16) & 0xff).unwrap(),
-                g: u8::try_from((map[i] >> 8) & 0xff).unwrap(),
-                b: u8::try_from(map[i] & 0xff).unwrap(),
-            };
-
-            i += 1;
-            if i >= NUM_KEYS - 1 {
-                break;
+        let led_map = color_map_to_rgba(map);
+
+        FRAME_INTERPOLATION.with(|state| {
+            let mut state = state.borrow_mut();
+
+            if state.enabled {
+                // remember the previous color map, so that we are able to
+                // blend between the last two submitted maps
+                LOCAL_LED_MAP.with(|local_map| {
+                    PREVIOUS_LED_MAP.with(|previous_map| {
+                        previous_map
+                            .borrow_mut()
+                            .copy_from_slice(&local_map.borrow())
+                    })
+                });
+
+                state.frames_per_submit = u32::max(state.frames_since_submit, 1);
+                state.frames_since_submit = 0;
             }
-        }
+        });
 
         LOCAL_LED_MAP.with(|local_map| local_map.borrow_mut().copy_from_slice(&led_map));
     }
+
+    /// Get the width and the height of the canvas, in pixels.
+    pub(crate) fn get_canvas_size() -> (usize, usize) {
+        canvas::get_size()
+    }
+
+    /// Get the position of the center of the key `idx` of the grid `grid` on the canvas.
+    pub(crate) fn get_key_position(
+        idx: usize,
+        grid: Option<canvas::Grid>,
+    ) -> rlua::Result<(f64, f64)> {
+        grid.and_then(|grid| canvas::get_key_position(idx, grid))
+            .ok_or_else(|| rlua::Error::RuntimeError(format!("Invalid key index: {}", idx)))
+    }
+
+    /// Sample the canvas `map` onto the keys of the grid `grid`, and submit the result
+    /// like a color map.
+    pub(crate) fn submit_canvas(map: &[u32], grid: Option<canvas::Grid>) -> rlua::Result<()> {
+        let grid = grid.ok_or_else(|| {
+            rlua::Error::RuntimeError("The device has no grid of keys".to_string())
+        })?;
+
+        let (width, height) = canvas::get_size();
+
+        submit_color_map(&canvas::sample(map, width, height, grid));
+
+        Ok(())
+    }
+
+    /// Enable or disable the frame interpolation stage of the current script layer.
+    pub(crate) fn set_frame_interpolation(enable: bool) {
+        FRAME_INTERPOLATION.with(|state| {
+            let mut state = state.borrow_mut();
+
+            state.enabled = enable;
+            state.frames_since_submit = 0;
+            state.frames_per_submit = 0;
+        });
+    }
+
+    #[test]
+    fn test_days_between() {
+        let from = NaiveDate::from_ymd(2020, 2, 27);
+
+        assert_eq!(days_between(from, "2020-02-27"), Some(0));
+        assert_eq!(days_between(from, "2020-03-01"), Some(3));
+        assert_eq!(days_between(from, "2019-12-31"), Some(-58));
+        assert_eq!(days_between(from, "2020-13-01"), None);
+        assert_eq!(days_between(from, "tomorrow"), None);
+    }
+
+    #[test]
+    fn test_store() {
+        store_set("test_store", Some(StoreValue::Integer(42)));
+        assert_eq!(store_get("test_store"), Some(StoreValue::Integer(42)));
+
+        store_set("test_store", Some(StoreValue::String("game".into())));
+        assert_eq!(
+            store_get("test_store"),
+            Some(StoreValue::String("game".into()))
+        );
+
+        store_set("test_store", None);
+        assert_eq!(store_get("test_store"), None);
+    }
+
+    #[test]
+    fn test_color_map_to_rgba() {
+        let data: Vec<u32> = (0..NUM_KEYS as u32).map(|i| 0xff00_0000 | i).collect();
+
+        let result = color_map_to_rgba(&data);
+
+        assert_eq!(result.len(), NUM_KEYS);
+
+        for (i, color) in result.iter().enumerate() {
+            assert_eq!(color.a, 0xff);
+            assert_eq!(color.b as usize, i);
+        }
+    }
+
+    #[test]
+    fn test_color_map_to_rgba_short_map() {
+        let data = vec![0xffff_ffffu32; 10];
+
+        let result = color_map_to_rgba(&data);
+
+        assert_eq!(result.len(), NUM_KEYS);
+        assert_eq!(result[9].r, 0xff);
+        assert_eq!(result[10].a, 0x00);
+        assert_eq!(result[NUM_KEYS - 1].r, 0x00);
+    }
+
+    #[test]
+    fn test_color_map_to_rgba_long_map() {
+        let data = vec![0x80ff_0000u32; NUM_KEYS + 20];
+
+        let result = color_map_to_rgba(&data);
+
+        assert_eq!(result.len(), NUM_KEYS);
+        assert_eq!(result[NUM_KEYS - 1].r, 0xff);
+        assert_eq!(result[NUM_KEYS - 1].a, 0x80);
+    }
+
+    #[test]
+    fn test_submit_color_map_interpolated() {
+        set_frame_interpolation(true);
+
+        submit_color_map(&vec![0x0000_0000u32; NUM_KEYS]);
+
+        // simulate two render frames between submissions
+        FRAME_INTERPOLATION.with(|state| state.borrow_mut().frames_since_submit = 2);
+        submit_color_map(&vec![0xffff_ffffu32; NUM_KEYS]);
+
+        FRAME_INTERPOLATION.with(|state| {
+            let mut state = state.borrow_mut();
+            assert_eq!(state.frames_per_submit, 2);
+
+            state.frames_since_submit = 1;
+            assert!((state.get_blend_factor() - 0.5).abs() < std::f64::EPSILON);
+        });
+
+        let frame = super::get_current_frame();
+        assert_eq!(frame[NUM_KEYS - 1].r, 0x80);
+
+        set_frame_interpolation(false);
+    }
+
+    #[test]
+    fn test_submit_canvas() {
+        let (width, height) = get_canvas_size();
+
+        // the left half of the canvas is red, the right half is blue
+        let data: Vec<u32> = (0..width * height)
+            .map(|i| {
+                if i % width < width / 2 {
+                    0xffff_0000
+                } else {
+                    0xff00_00ff
+                }
+            })
+            .collect();
+
+        submit_canvas(&data, Some(canvas::DEFAULT_GRID)).unwrap();
+
+        LOCAL_LED_MAP.with(|local_map| {
+            let local_map = local_map.borrow();
+
+            let first = local_map[0];
+            assert_eq!(
+                (first.r, first.g, first.b, first.a),
+                (0xff, 0x00, 0x00, 0xff)
+            );
+
+            let last = local_map[NUM_KEYS - 1];
+            assert_eq!((last.r, last.g, last.b, last.a), (0x00, 0x00, 0xff, 0xff));
+        });
+
+        let (x, y) = get_key_position(0, Some(canvas::DEFAULT_GRID)).unwrap();
+        assert!(x < width as f64 / 2.0 && y < height as f64);
+
+        assert!(get_key_position(NUM_KEYS, Some(canvas::DEFAULT_GRID)).is_err());
+        assert!(get_key_position(0, None).is_err());
+        assert!(submit_canvas(&data, None).is_err());
+    }
+
+    #[test]
+    fn test_resolve_data_path() {
+        let data_dir = Path::new("/var/lib/eruption/data/test");
+
+        assert_eq!(
+            resolve_data_path(data_dir, "state.json"),
+            Some(data_dir.join("state.json"))
+        );
+        assert_eq!(
+            resolve_data_path(data_dir, "./levels/1.toml"),
+            Some(data_dir.join("./levels/1.toml"))
+        );
+
+        assert_eq!(resolve_data_path(data_dir, ""), None);
+        assert_eq!(resolve_data_path(data_dir, "/etc/passwd"), None);
+        assert_eq!(resolve_data_path(data_dir, "../other/state.json"), None);
+        assert_eq!(resolve_data_path(data_dir, "levels/../../other"), None);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = r#"{"name":"test","values":[1,2.5,true],"nested":{"empty":null}}"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+
+        rlua::Lua::new().context(|lua_ctx| {
+            let lua_value = json_to_lua(lua_ctx, &value).unwrap();
+            let result = lua_to_json(lua_ctx, lua_value).unwrap();
+
+            // null values vanish in Lua tables
+            assert_eq!(result["name"], "test");
+            assert_eq!(result["values"], serde_json::json!([1, 2.5, true]));
+            assert_eq!(result["nested"], serde_json::json!({}));
+        });
+    }
+
+    #[test]
+    fn test_json_cycles_and_depth() {
+        rlua::Lua::new().context(|lua_ctx| {
+            let cyclic: rlua::Value = lua_ctx
+                .load("local t = {} t.self = t return t")
+                .eval()
+                .unwrap();
+            assert!(lua_to_json(lua_ctx, cyclic).is_err());
+
+            let deep: rlua::Value = lua_ctx
+                .load("local t = {} for i = 1, 100 do t = { t } end return t")
+                .eval()
+                .unwrap();
+            assert!(lua_to_json(lua_ctx, deep).is_err());
+
+            // a table may be referenced more than once, if it does not contain itself
+            let shared: rlua::Value = lua_ctx
+                .load("local c = { 1, 2 } return { a = c, b = c }")
+                .eval()
+                .unwrap();
+            let result = lua_to_json(lua_ctx, shared).unwrap();
+
+            assert_eq!(result["a"], serde_json::json!([1, 2]));
+            assert_eq!(result["b"], serde_json::json!([1, 2]));
+        });
+    }
+
+    #[test]
+    fn test_submit_color_map() {
+        let data = vec![0xff12_3456u32; NUM_KEYS];
+
+        submit_color_map(&data);
+
+        LOCAL_LED_MAP.with(|local_map| {
+            let local_map = local_map.borrow();
+
+            // the last key must be updated as well
+            let last = local_map[NUM_KEYS - 1];
+            assert_eq!((last.r, last.g, last.b, last.a), (0x12, 0x34, 0x56, 0xff));
+        });
+    }
+}
+
+/// Returns the directory that holds the data files of all scripts: `global.data_dir`
+/// from the configuration file, or the default data directory
+pub fn get_data_dir() -> PathBuf {
+    PathBuf::from(
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|config| config.get_str("global.data_dir").ok())
+            .unwrap_or_else(|| constants::DEFAULT_DATA_DIR.to_string()),
+    )
+}
+
+/// Publish the current frame of the script running on this thread as the
+/// `layer`-th layer, the main thread composes the frames of all layers
+pub(crate) fn publish_layer_frame(layer: usize, target_device: usize, frame_id: u64) {
+    let led_map = get_current_frame();
+
+    let mut layer_frames = LAYER_FRAMES.0.lock();
+
+    if layer_frames.len() <= layer {
+        layer_frames.resize(layer + 1, None);
+    }
+
+    layer_frames[layer] = Some(LayerFrame {
+        frame_id,
+        device: target_device,
+        led_map,
+    });
+
+    LAYER_FRAMES.1.notify_all();
+}
+
+/// Action requests for `run_script`
+pub enum RunScriptResult {
+    /// Script terminated gracefully
+    TerminatedGracefully,
+    // Currently running interpreter will be shut down, to execute another Lua script
+    //ReExecuteOtherScript(PathBuf),
 }
 
-/// Action requests for `run_script`
-pub enum RunScriptResult {
-    /// Script terminated gracefully
-    TerminatedGracefully,
-    // Currently running interpreter will be shut down, to execute another Lua script
-    //ReExecuteOtherScript(PathBuf),
-}
+/// Loads and runs a lua script.
+/// Initializes a lua environment, loads the script and executes it.
+/// The script renders to the device with the index `target_device`, its frames
+/// are composed as the `layer`-th layer
+pub fn run_script(
+    file: PathBuf,
+    rvdevices: Vec<RvDeviceState>,
+    target_device: usize,
+    layer: usize,
+    tx: &Sender<Message>,
+    rx: &Receiver<Message>,
+) -> Result<RunScriptResult> {
+    #[cfg(feature = "wasm")]
+    {
+        if file.extension().map_or(false, |ext| ext == "wasm") {
+            return wasm::run_script(file, target_device, layer, rx);
+        }
+    }
+
+    // verify the checksum and signature of the script
+    let permissions = match Manifest::from(&file) {
+        Ok(manifest) => integrity::check(&manifest).map_err(|e| {
+            error!("{}", e);
+
+            ScriptingError::IntegrityError {
+                description: format!("{}", e),
+            }
+        })?,
+
+        // fail closed, if the manifest can not be verified
+        Err(_e) => integrity::Permissions::None,
+    };
+
+    // effects that are implemented by an external program
+    if let Ok(manifest) = Manifest::from(&file) {
+        if manifest.exec.is_some() {
+            if !capabilities::is_granted(&manifest, Capability::Exec) {
+                error!(
+                    "External effects require the capability 'exec', refusing to execute: {}",
+                    file.display()
+                );
+
+                return Err(ScriptingError::ExecError {
+                    description: "Missing capability: exec".into(),
+                });
+            }
+
+            if permissions != integrity::Permissions::Full {
+                error!(
+                    "External effects must be signed, refusing to execute: {}",
+                    file.display()
+                );
+
+                return Err(ScriptingError::IntegrityError {
+                    description: "Unsigned external effect".into(),
+                });
+            }
+
+            return exec::run_script(file, manifest, target_device, layer, rx);
+        }
+    }
+
+    if permissions != integrity::Permissions::Full {
+        warn!(
+            "The script {} is not signed, it may not inject keys",
+            file.display()
+        );
+    }
+
+    DATA_DIR.with(|data_dir| {
+        *data_dir.borrow_mut() = file.file_stem().map(|stem| get_data_dir().join(stem));
+    });
+
+    INJECTION_RESTRICTED
+        .with(|restricted| restricted.set(permissions != integrity::Permissions::Full));
+
+    match fs::read_to_string(file.clone()) {
+        Ok(script) => {
+            // effects of a previous Lua VM can not be resumed
+            EFFECTS.with(|effects| effects.borrow_mut().clear());
+
+            let manifest = Manifest::from(&file);
+            if let Err(error) = manifest {
+                error!(
+                    target: logging::SCRIPT_ERRORS,
+                    "Could not parse manifest file for script '{}': {}",
+                    file.display(),
+                    error
+                );
+
+                return Err(ScriptingError::InaccessibleManifest {});
+            } else if !api::is_api_level_supported(
+                &manifest.as_ref().unwrap().min_supported_version,
+            ) {
+                let required = manifest.unwrap().min_supported_version;
+
+                error!(
+                    target: logging::SCRIPT_ERRORS,
+                    "The script '{}' requires API level {}, supported is {}",
+                    file.display(),
+                    required,
+                    constants::API_LEVEL
+                );
+
+                return Err(ScriptingError::UnsupportedApiLevel {
+                    required,
+                    supported: constants::API_LEVEL.to_string(),
+                });
+            } else {
+                ACTIVE_SCRIPTS
+                    .lock()
+                    .push(manifest.as_ref().unwrap().clone());
+
+                callbacks::set_frame_interpolation(manifest.as_ref().unwrap().frame_interpolation);
+            }
+
+            let lua = match capabilities::new_lua(manifest.as_ref().unwrap()) {
+                Ok(lua) => lua,
+                Err(e) => return Err(ScriptingError::LuaError { e }),
+            };
+
+            let chunk = debugger::chunk_name(&file);
+
+            debugger::attach(&lua, &manifest.as_ref().unwrap().name, &chunk)
+                .unwrap_or_else(|e| error!("Could not attach the debugger: {}", e));
+
+            let script_id = ScriptId::register(layer, tx.clone());
+
+            let result: rlua::Result<RunScriptResult> = lua.context::<_, _>(|lua_ctx| {
+                register_support_globals(lua_ctx, &rvdevices[target_device])?;
+                register_support_funcs(lua_ctx, &rvdevices, target_device, script_id)?;
+                let manifest = manifest.unwrap();
+                register_script_config(lua_ctx, &manifest)?;
+
+                if cfg!(debug_assertions) {
+                    api::verify_globals(lua_ctx);
+                }
+
+                // remove the functions that require capabilities the script lacks
+                capabilities::apply(lua_ctx, &manifest)?;
+
+                api::register_namespaces(lua_ctx, manifest.compat_globals)?;
+                api::register_deprecations(lua_ctx, &manifest.name, manifest.get_api_level())?;
+
+                debugger::record_builtins(lua_ctx)?;
+
+                // start execution of the Lua script
+                lua_ctx
+                    .load(&script)
+                    .set_name(&chunk)?
+                    .eval::<()>()
+                    .or_else(|e| {
+                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                        Err(e)
+                    })?;
+
+                // call startup event handler, iff present
+                if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_startup") {
+                    handler.call::<_, ()>(()).or_else(|e| {
+                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                        Err(e)
+                    })?;
+                }
+
+                loop {
+                    if let Ok(msg) = rx.recv() {
+                        match msg {
+                            Message::Quit(param) => {
+                                if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_quit")
+                                {
+                                    handler.call::<_, ()>(param).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
+
+                            Message::Tick(param) => {
+                                // further ticks will be queued again from now on
+                                ticks::tick_completed(layer);
+
+                                if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_tick")
+                                {
+                                    handler.call::<_, ()>(param).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+
+                                resume_effects(lua_ctx)?;
+                            }
+
+                            Message::RenderFrame(frame_id) => {
+                                publish_layer_frame(layer, target_device, frame_id);
+                            }
+
+                            Message::KeyDown(param) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_key_down")
+                                {
+                                    handler.call::<_, ()>(param).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+
+                                *crate::UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() -= 1;
+                                crate::UPCALL_COMPLETED_ON_KEY_DOWN.1.notify_all();
+                            }
+
+                            Message::KeyUp(param) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_key_up")
+                                {
+                                    handler.call::<_, ()>(param).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+
+                                *crate::UPCALL_COMPLETED_ON_KEY_UP.0.lock() -= 1;
+                                crate::UPCALL_COMPLETED_ON_KEY_UP.1.notify_all();
+                            }
+
+                            Message::KeyStuck(param) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_key_stuck")
+                                {
+                                    handler.call::<_, ()>(param).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
+
+                            Message::TimerPhase(phase, remaining) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_timer_phase")
+                                {
+                                    handler.call::<_, ()>((phase, remaining)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
+
+                            Message::StoreChanged(key) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_store_changed")
+                                {
+                                    handler.call::<_, ()>(key).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
+
+                            Message::HttpResponse(id, status, body) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_http_response")
+                                {
+                                    handler.call::<_, ()>((id, status, body)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-/// Loads and runs a lua script.
-/// Initializes a lua environment, loads the script and executes it
-pub fn run_script(
-    file: PathBuf,
-    rvdevice: RvDeviceState,
-    rx: &Receiver<Message>,
-) -> Result<RunScriptResult> {
-    match fs::read_to_string(file.clone()) {
-        Ok(script) => {
-            let lua = Lua::new();
+                            Message::ExecFinished(id, status, stdout) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_exec_finished")
+                                {
+                                    handler.call::<_, ()>((id, status, stdout)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-            let manifest = Manifest::from(&file);
-            if let Err(error) = manifest {
-                error!(
-                    "Could not parse manifest file for script '{}': {}",
-                    file.display(),
-                    error
-                );
+                            Message::PaletteChanged(name) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_palette_changed")
+                                {
+                                    handler.call::<_, ()>(name).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                return Err(ScriptingError::InaccessibleManifest {});
-            } else {
-                ACTIVE_SCRIPTS
-                    .lock()
-                    .push(manifest.as_ref().unwrap().clone());
-            }
+                            Message::BuildStatusChanged(name, status) => {
+                                if let Ok(handler) = lua_ctx
+                                    .globals()
+                                    .get::<_, Function>("on_build_status_changed")
+                                {
+                                    handler.call::<_, ()>((name, status)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-            let result: rlua::Result<RunScriptResult> = lua.context::<_, _>(|lua_ctx| {
-                register_support_globals(lua_ctx, &rvdevice)?;
-                register_support_funcs(lua_ctx, &rvdevice)?;
-                register_script_config(lua_ctx, &manifest.unwrap())?;
+                            Message::Mention(source, text) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_mention")
+                                {
+                                    handler.call::<_, ()>((source, text)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                // start execution of the Lua script
-                lua_ctx.load(&script).eval::<()>()?;
+                            Message::VoiceActivity(active) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_voice_activity")
+                                {
+                                    handler.call::<_, ()>(active).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                // call startup event handler, iff present
-                if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_startup") {
-                    handler.call::<_, ()>(()).or_else(|e| {
-                        error!("Lua error: {}", e);
-                        Err(e)
-                    })?;
-                }
+                            Message::ActiveAppChanged(class) => {
+                                if let Ok(handler) = lua_ctx
+                                    .globals()
+                                    .get::<_, Function>("on_active_app_changed")
+                                {
+                                    handler.call::<_, ()>(class).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                loop {
-                    if let Ok(msg) = rx.recv() {
-                        match msg {
-                            Message::Quit(param) => {
-                                if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_quit")
+                            Message::AccentColorChanged(color) => {
+                                if let Ok(handler) = lua_ctx
+                                    .globals()
+                                    .get::<_, Function>("on_accent_color_changed")
                                 {
-                                    handler.call::<_, ()>(param).or_else(|e| {
-                                        error!("Lua error: {}", e);
+                                    handler.call::<_, ()>(color).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
                                         Err(e)
                                     })?;
                                 }
                             }
 
-                            Message::Tick(param) => {
-                                if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_tick")
+                            Message::DndChanged(enabled) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_dnd_changed")
                                 {
-                                    handler.call::<_, ()>(param).or_else(|e| {
-                                        error!("Lua error: {}", e);
+                                    handler.call::<_, ()>(enabled).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
                                         Err(e)
                                     })?;
                                 }
                             }
 
-                            Message::RealizeColorMap => {
-                                LOCAL_LED_MAP.with(|foreground| {
-                                    for (idx, background) in LED_MAP.lock().iter_mut().enumerate() {
-                                        let bg = &background;
-                                        let fg = foreground.borrow()[idx];
+                            Message::EasyShift(down) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_easy_shift")
+                                {
+                                    handler.call::<_, ()>(down).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                                        let brightness = crate::BRIGHTNESS.load(Ordering::SeqCst);
+                            Message::FnKey(down) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_fn_key")
+                                {
+                                    handler.call::<_, ()>(down).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                                        #[rustfmt::skip]
-                                        let color = RGBA {
-                                            r: ((((fg.a as f64) * fg.r as f64 + (255 - fg.a) as f64 * bg.r as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                            g: ((((fg.a as f64) * fg.g as f64 + (255 - fg.a) as f64 * bg.g as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                            b: ((((fg.a as f64) * fg.b as f64 + (255 - fg.a) as f64 * bg.b as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                            a: fg.a as u8,
-                                        };
+                            Message::VolumeChanged(volume, muted) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_volume_changed")
+                                {
+                                    handler.call::<_, ()>((volume, muted)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
 
-                                        *background = color;
-                                    }
-                                });
-
-                                // signal readiness / notify the main thread that we are done
-                                crate::COLOR_MAPS_READY_CONDITION
-                                    .0
-                                    .lock()
-                                    .checked_sub(1)
-                                    .unwrap_or_else(|| {
-                                        warn!("Incorrect state in locking code detected");
-                                        0
-                                    });
-                                crate::COLOR_MAPS_READY_CONDITION.1.notify_one();
+                            Message::DialRotate(delta) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_dial_rotate")
+                                {
+                                    handler.call::<_, ()>(delta).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
                             }
 
-                            Message::KeyDown(param) => {
+                            Message::DialPress => {
                                 if let Ok(handler) =
-                                    lua_ctx.globals().get::<_, Function>("on_key_down")
+                                    lua_ctx.globals().get::<_, Function>("on_dial_press")
                                 {
-                                    handler.call::<_, ()>(param).or_else(|e| {
-                                        error!("Lua error: {}", e);
+                                    handler.call::<_, ()>(()).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
                                         Err(e)
                                     })?;
                                 }
+                            }
 
-                                *crate::UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() -= 1;
-                                crate::UPCALL_COMPLETED_ON_KEY_DOWN.1.notify_all();
+                            Message::DeviceError(device, description) => {
+                                if let Ok(handler) =
+                                    lua_ctx.globals().get::<_, Function>("on_device_error")
+                                {
+                                    handler.call::<_, ()>((device, description)).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
                             }
 
-                            Message::KeyUp(param) => {
+                            Message::DeviceRecovered(device) => {
                                 if let Ok(handler) =
-                                    lua_ctx.globals().get::<_, Function>("on_key_up")
+                                    lua_ctx.globals().get::<_, Function>("on_device_recovered")
                                 {
-                                    handler.call::<_, ()>(param).or_else(|e| {
-                                        error!("Lua error: {}", e);
+                                    handler.call::<_, ()>(device).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
                                         Err(e)
                                     })?;
                                 }
+                            }
 
-                                *crate::UPCALL_COMPLETED_ON_KEY_UP.0.lock() -= 1;
-                                crate::UPCALL_COMPLETED_ON_KEY_UP.1.notify_all();
+                            Message::SessionLocked(locked) => {
+                                let handler = if locked {
+                                    "on_session_lock"
+                                } else {
+                                    "on_session_unlock"
+                                };
+
+                                if let Ok(handler) = lua_ctx.globals().get::<_, Function>(handler) {
+                                    handler.call::<_, ()>(()).or_else(|e| {
+                                        error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                        Err(e)
+                                    })?;
+                                }
+                            }
+
+                            Message::ParamChanged(script_name, param) => {
+                                if script_name == manifest.name {
+                                    set_param_global(&lua_ctx.globals(), &param)?;
+                                    update_cache_context(&lua_ctx.globals(), &manifest)?;
+
+                                    if let Ok(handler) =
+                                        lua_ctx.globals().get::<_, Function>("on_param_changed")
+                                    {
+                                        handler.call::<_, ()>(param.get_name().clone()).or_else(
+                                            |e| {
+                                                error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
+                                                Err(e)
+                                            },
+                                        )?;
+                                    }
+                                }
+                            }
+
+                            Message::Eval(script_name, code, reply_tx) => {
+                                if script_name == manifest.name {
+                                    // the receiver may have timed out already
+                                    let _ = reply_tx.send(repl::eval(lua_ctx, &code));
+                                }
                             }
 
                             //Message::LoadScript(script_path) => {
@@ -598,7 +2190,7 @@ pub fn run_script(
                                 //lua_ctx.globals().get::<_, Function>("on_quit")
                                 //{
                                 //handler.call::<_, ()>(()).or_else(|e| {
-                                //error!("Lua error: {}", e);
+                                //error!(target: logging::SCRIPT_ERRORS, "Lua error: {}", e);
                                 //Err(e)
                                 //})?;
                                 //}
@@ -610,6 +2202,8 @@ pub fn run_script(
                 }
             });
 
+            script_id.unregister();
+
             match result {
                 Ok(action) => Ok(action),
 
@@ -624,24 +2218,30 @@ pub fn run_script(
 fn register_support_globals(lua_ctx: Context, _rvdevice: &RvDeviceState) -> rlua::Result<()> {
     let globals = lua_ctx.globals();
 
-    lua_ctx
-        .load("package.path = package.path .. ';src/scripts/lib/?.lua'")
-        .exec()
-        .unwrap();
-
     let mut config: HashMap<&str, &str> = HashMap::new();
     config.insert("daemon_name", "eruption");
     config.insert("daemon_version", "0.1.1");
-    config.insert("api_level", "0.1.1");
+    config.insert("api_level", constants::API_LEVEL);
 
     globals.set("config", config)?;
 
     Ok(())
 }
 
-fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::Result<()> {
-    let rvdevid = rvdevice.get_dev_id();
-    let rvdev = Arc::new(Mutex::new(rvdevice.clone()));
+fn register_support_funcs(
+    lua_ctx: Context,
+    rvdevices: &[RvDeviceState],
+    target_device: usize,
+    script_id: ScriptId,
+) -> rlua::Result<()> {
+    let layer = script_id.layer;
+
+    let rvdevs: Arc<Vec<Arc<Mutex<RvDeviceState>>>> = Arc::new(
+        rvdevices
+            .iter()
+            .map(|rvdevice| Arc::new(Mutex::new(rvdevice.clone())))
+            .collect(),
+    );
 
     let globals = lua_ctx.globals();
 
@@ -682,6 +2282,144 @@ fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::R
     })?;
     globals.set("delay", delay)?;
 
+    let set_dnd = lua_ctx.create_function(|_, enable: bool| {
+        callbacks::set_dnd(enable);
+        Ok(())
+    })?;
+    globals.set("set_dnd", set_dnd)?;
+
+    let get_dnd = lua_ctx.create_function(|_, ()| Ok(callbacks::get_dnd()))?;
+    globals.set("get_dnd", get_dnd)?;
+
+    let get_active_app = lua_ctx.create_function(|_, ()| Ok(windows::get_active_app()))?;
+    globals.set("get_active_app", get_active_app)?;
+
+    // shared store
+    let store_set = lua_ctx.create_function(|_, (key, value): (String, Option<StoreValue>)| {
+        callbacks::store_set(&key, value);
+        Ok(())
+    })?;
+    globals.set("store_set", store_set)?;
+
+    let store_get = lua_ctx.create_function(|_, key: String| Ok(callbacks::store_get(&key)))?;
+    globals.set("store_get", store_get)?;
+
+    // effect cache
+    let cache_get = lua_ctx.create_function(|_, key: String| Ok(callbacks::cache_get(&key)))?;
+    globals.set("cache_get", cache_get)?;
+
+    let cache_put = lua_ctx.create_function(|_, (key, values): (String, Vec<f64>)| {
+        callbacks::cache_put(&key, &values);
+        Ok(())
+    })?;
+    globals.set("cache_put", cache_put)?;
+
+    // data files and serialization
+    let fs_read = lua_ctx.create_function(|_, path: String| Ok(callbacks::fs_read(&path)))?;
+    globals.set("fs_read", fs_read)?;
+
+    let fs_write = lua_ctx.create_function(|_, (path, data): (String, String)| {
+        Ok(callbacks::fs_write(&path, &data))
+    })?;
+    globals.set("fs_write", fs_write)?;
+
+    let json_decode = lua_ctx.create_function(|lua_ctx, data: String| {
+        let value = serde_json::from_str::<serde_json::Value>(&data)
+            .map_err(|e| rlua::Error::RuntimeError(format!("Invalid JSON: {}", e)))?;
+
+        callbacks::json_to_lua(lua_ctx, &value)
+    })?;
+    globals.set("json_decode", json_decode)?;
+
+    let json_encode = lua_ctx.create_function(|lua_ctx, value: rlua::Value| {
+        Ok(callbacks::lua_to_json(lua_ctx, value)?.to_string())
+    })?;
+    globals.set("json_encode", json_encode)?;
+
+    let toml_decode = lua_ctx.create_function(|lua_ctx, data: String| {
+        let value = toml::de::from_str::<serde_json::Value>(&data)
+            .map_err(|e| rlua::Error::RuntimeError(format!("Invalid TOML: {}", e)))?;
+
+        callbacks::json_to_lua(lua_ctx, &value)
+    })?;
+    globals.set("toml_decode", toml_decode)?;
+
+    let toml_encode = lua_ctx.create_function(|lua_ctx, value: rlua::Value| {
+        toml::ser::to_string(&callbacks::lua_to_json(lua_ctx, value)?)
+            .map_err(|e| rlua::Error::RuntimeError(format!("Could not encode TOML: {}", e)))
+    })?;
+    globals.set("toml_encode", toml_encode)?;
+
+    // clipboard
+    let clipboard_get = lua_ctx.create_function(|_, ()| {
+        Ok(clipboard::get()
+            .map_err(|e| warn!("Could not read the clipboard: {}", e))
+            .ok()
+            .flatten())
+    })?;
+    globals.set("clipboard_get", clipboard_get)?;
+
+    let clipboard_set = lua_ctx.create_function(|_, text: String| {
+        Ok(clipboard::set(&text)
+            .map_err(|e| warn!("Could not write the clipboard: {}", e))
+            .is_ok())
+    })?;
+    globals.set("clipboard_set", clipboard_set)?;
+
+    // network access
+    #[cfg(feature = "http")]
+    {
+        let http_get = lua_ctx.create_function(move |_, url: String| {
+            Ok(http::get(script_id, &url)
+                .map_err(|e| warn!("HTTP request failed: {}", e))
+                .ok())
+        })?;
+        globals.set("http_get", http_get)?;
+    }
+
+    // external commands
+    let exec_async =
+        lua_ctx.create_function(move |_, (program, args): (String, Option<Vec<String>>)| {
+            Ok(
+                commands::exec_async(script_id, &program, args.unwrap_or_default())
+                    .map_err(|e| warn!("Could not execute a command: {}", e))
+                    .ok(),
+            )
+        })?;
+    globals.set("exec_async", exec_async)?;
+
+    // time and date
+    let get_time_millis = lua_ctx.create_function(|_, ()| Ok(callbacks::get_time_millis()))?;
+    globals.set("get_time_millis", get_time_millis)?;
+
+    // tick rate
+    let request_ticks = lua_ctx.create_function(move |_, rate: u64| {
+        ticks::request_tick_rate(layer, Some(rate));
+        Ok(())
+    })?;
+    globals.set("request_ticks", request_ticks)?;
+
+    let suspend_ticks = lua_ctx.create_function(move |_, ()| {
+        ticks::request_tick_rate(layer, None);
+        Ok(())
+    })?;
+    globals.set("suspend_ticks", suspend_ticks)?;
+
+    // coroutine based effects
+    let spawn_effect =
+        lua_ctx.create_function(|lua_ctx, func: Function| spawn_effect(lua_ctx, func))?;
+    globals.set("spawn_effect", spawn_effect)?;
+
+    lua_ctx
+        .load("function wait(millis) coroutine.yield(millis) end")
+        .exec()?;
+
+    let get_local_time = lua_ctx.create_function(|_, ()| Ok(callbacks::get_local_time()))?;
+    globals.set("get_local_time", get_local_time)?;
+
+    let days_until = lua_ctx.create_function(|_, date: String| Ok(callbacks::days_until(&date)))?;
+    globals.set("days_until", days_until)?;
+
     // math library
     let max = lua_ctx.create_function(|_, (f1, f2): (f64, f64)| Ok(f1.max(f2)))?;
     globals.set("max", max)?;
@@ -726,11 +2464,35 @@ fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::R
     globals.set("lerp", lerp)?;
 
     // keyboard state and macros
-    let inject_key = lua_ctx.create_function(|_, (ev_key, down): (u32, bool)| {
-        callbacks::inject_key(ev_key, down);
+    let inject_key =
+        lua_ctx.create_function(|_, (ev_key, down, pacing_ms): (u32, bool, Option<u64>)| {
+            callbacks::inject_key(ev_key, down, pacing_ms);
+            Ok(())
+        })?;
+    globals.set("inject_key", inject_key)?;
+
+    let inject_with_modifiers =
+        lua_ctx.create_function(|_, args: (u32, Vec<u32>, Option<bool>, Option<u64>)| {
+            let (ev_key, modifiers, neutralize, pacing_ms) = args;
+
+            callbacks::inject_with_modifiers(
+                ev_key,
+                modifiers,
+                neutralize.unwrap_or(true),
+                pacing_ms,
+            );
+            Ok(())
+        })?;
+    globals.set("inject_with_modifiers", inject_with_modifiers)?;
+
+    let abort_macro = lua_ctx.create_function(|_, ()| {
+        callbacks::abort_macro();
         Ok(())
     })?;
-    globals.set("inject_key", inject_key)?;
+    globals.set("abort_macro", abort_macro)?;
+
+    let is_macro_running = lua_ctx.create_function(|_, ()| Ok(callbacks::is_macro_running()))?;
+    globals.set("is_macro_running", is_macro_running)?;
 
     // color handling
     let color_to_rgb = lua_ctx.create_function(|_, c: u32| Ok(callbacks::color_to_rgb(c)))?;
@@ -765,6 +2527,14 @@ fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::R
     })?;
     globals.set("linear_gradient", linear_gradient)?;
 
+    // palette library
+    let get_palette =
+        lua_ctx.create_function(|_, name: String| Ok(palettes::get_palette(&name)))?;
+    globals.set("get_palette", get_palette)?;
+
+    let get_palette_names = lua_ctx.create_function(|_, ()| Ok(palettes::get_palette_names()))?;
+    globals.set("get_palette_names", get_palette_names)?;
+
     // noise utilities
     let perlin_noise = lua_ctx.create_function(|_, (f1, f2, f3): (f64, f64, f64)| {
         Ok(callbacks::perlin_noise(f1, f2, f3))
@@ -807,26 +2577,95 @@ fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::R
     let get_num_keys = lua_ctx.create_function(move |_, ()| Ok(callbacks::get_num_keys()))?;
     globals.set("get_num_keys", get_num_keys)?;
 
-    let rvdevid_tmp = rvdevid;
-    let get_key_color = lua_ctx
-        .create_function(move |_, idx: usize| Ok(callbacks::get_key_color(&rvdevid_tmp, idx)))?;
-    globals.set("get_key_color", get_key_color)?;
+    let num_devices = rvdevices.len();
+    let get_num_devices = lua_ctx.create_function(move |_, ()| Ok(num_devices))?;
+    globals.set("get_num_devices", get_num_devices)?;
 
-    let rvdev_tmp = rvdev.clone();
-    let set_key_color = lua_ctx.create_function(move |_, (idx, c): (usize, u32)| {
-        callbacks::set_key_color(&rvdev_tmp, idx, c);
-        Ok(())
+    let get_target_device = lua_ctx.create_function(move |_, ()| Ok(target_device))?;
+    globals.set("get_target_device", get_target_device)?;
+
+    let rvdevices_tmp = rvdevices.to_vec();
+    let find_device = lua_ctx.create_function(move |_, selector: String| {
+        Ok(rvdevice::find_device(&rvdevices_tmp, &selector))
+    })?;
+    globals.set("find_device", find_device)?;
+
+    let device_infos: Vec<DeviceInfo> = rvdevices
+        .iter()
+        .map(|rvdevice| rvdevice.device_info.clone().unwrap_or_default())
+        .collect();
+    let get_device_info = lua_ctx.create_function(move |_, device: Option<usize>| {
+        let device = device.unwrap_or(target_device);
+
+        match device_infos.get(device) {
+            Some(device_info) => Ok(Some(callbacks::get_device_info(device_info))),
+
+            None => {
+                error!("Invalid device index: {}", device);
+                Ok(None)
+            }
+        }
+    })?;
+    globals.set("get_device_info", get_device_info)?;
+
+    // the device index may be omitted, it defaults to the target device of the script
+    let get_key_color = lua_ctx.create_function(move |_, (a, b): (usize, Option<usize>)| {
+        Ok(match b {
+            Some(idx) => callbacks::get_key_color(a, idx),
+            None => callbacks::get_key_color(target_device, a),
+        })
     })?;
+    globals.set("get_key_color", get_key_color)?;
+
+    let rvdevs_tmp = rvdevs.clone();
+    let set_key_color =
+        lua_ctx.create_function(move |_, (a, b, c): (usize, u32, Option<u32>)| match c {
+            Some(c) => callbacks::set_key_color(&rvdevs_tmp, a, b as usize, c),
+            None => callbacks::set_key_color(&rvdevs_tmp, target_device, a, b),
+        })?;
     globals.set("set_key_color", set_key_color)?;
 
-    let get_color_map = lua_ctx.create_function(move |_, ()| Ok(callbacks::get_color_map()))?;
-    globals.set("get_color_map", get_color_map)?;
+    // raw feature reports of the target device
+    let rvdevs_tmp = rvdevs.clone();
+    let hid_send_feature = lua_ctx.create_function(move |_, buf: Vec<u8>| {
+        let rvdev = rvdevs_tmp.get(target_device).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!("Invalid device index: {}", target_device))
+        })?;
 
-    let rvdev_tmp = rvdev;
-    let set_color_map = lua_ctx.create_function(move |_, map: Vec<u32>| {
-        callbacks::set_color_map(&rvdev_tmp, &map);
-        Ok(())
+        Ok(raw_hid::send_feature(&rvdev.lock(), &buf)
+            .map_err(|e| warn!("Could not send the feature report: {}", e))
+            .is_ok())
+    })?;
+    globals.set("hid_send_feature", hid_send_feature)?;
+
+    let rvdevs_tmp = rvdevs.clone();
+    let hid_get_feature = lua_ctx.create_function(move |_, (id, len): (u8, usize)| {
+        let rvdev = rvdevs_tmp.get(target_device).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!("Invalid device index: {}", target_device))
+        })?;
+
+        Ok(raw_hid::get_feature(&rvdev.lock(), id, len)
+            .map_err(|e| warn!("Could not get the feature report: {}", e))
+            .ok())
+    })?;
+    globals.set("hid_get_feature", hid_get_feature)?;
+
+    let get_color_map = lua_ctx.create_function(move |_, device: Option<usize>| {
+        Ok(callbacks::get_color_map(device.unwrap_or(target_device)))
     })?;
+    globals.set("get_color_map", get_color_map)?;
+
+    let rvdevs_tmp = rvdevs;
+    let set_color_map =
+        lua_ctx.create_function(move |lua_ctx, (a, b): (rlua::Value, Option<Vec<u32>>)| {
+            let (device, map) = match b {
+                Some(map) => (usize::from_lua(a, lua_ctx)?, map),
+                None => (target_device, Vec::<u32>::from_lua(a, lua_ctx)?),
+            };
+
+            callbacks::set_color_map(&rvdevs_tmp, device, &map);
+            Ok(())
+        })?;
     globals.set("set_color_map", set_color_map)?;
 
     let submit_color_map = lua_ctx.create_function(move |_, map: Vec<u32>| {
@@ -835,6 +2674,47 @@ fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::R
     })?;
     globals.set("submit_color_map", submit_color_map)?;
 
+    let get_canvas_size = lua_ctx.create_function(|_, ()| Ok(callbacks::get_canvas_size()))?;
+    globals.set("get_canvas_size", get_canvas_size)?;
+
+    // the grid of the keys of the target device, `None` if it has no keys
+    let grid = rvdevices
+        .get(target_device)
+        .and_then(|rvdevice| rvdevice.kind.grid());
+
+    let get_key_position =
+        lua_ctx.create_function(move |_, idx: usize| callbacks::get_key_position(idx, grid))?;
+    globals.set("get_key_position", get_key_position)?;
+
+    let submit_canvas =
+        lua_ctx.create_function(move |_, map: Vec<u32>| callbacks::submit_canvas(&map, grid))?;
+    globals.set("submit_canvas", submit_canvas)?;
+
+    let set_frame_interpolation = lua_ctx.create_function(move |_, enable: bool| {
+        callbacks::set_frame_interpolation(enable);
+        Ok(())
+    })?;
+    globals.set("set_frame_interpolation", set_frame_interpolation)?;
+
+    let blink_pattern = lua_ctx.create_function(
+        |_, (keys, pattern, unit_ms, color): (Vec<usize>, String, u64, u32)| {
+            let color = RGBA {
+                r: ((color >> 16) & 0xff) as u8,
+                g: ((color >> 8) & 0xff) as u8,
+                b: (color & 0xff) as u8,
+                a: 0xff,
+            };
+
+            Ok(overlay::show_blink_pattern(
+                &keys,
+                &pattern,
+                Duration::from_millis(unit_ms),
+                color,
+            ))
+        },
+    )?;
+    globals.set("blink_pattern", blink_pattern)?;
+
     // finally, register Lua functions supplied by eruption plugins
     let plugin_manager = plugin_manager::PLUGIN_MANAGER.read();
     let plugins = plugin_manager.get_plugins();
@@ -846,6 +2726,17 @@ fn register_support_funcs(lua_ctx: Context, rvdevice: &RvDeviceState) -> rlua::R
     Ok(())
 }
 
+/// Set the global variable of the script parameter `param`
+fn set_param_global(globals: &rlua::Table, param: &profiles::ConfigParam) -> rlua::Result<()> {
+    match param {
+        profiles::ConfigParam::Int { name, value } => globals.raw_set::<&str, i64>(name, *value),
+        profiles::ConfigParam::Float { name, value } => globals.raw_set::<&str, f64>(name, *value),
+        profiles::ConfigParam::Bool { name, value } => globals.raw_set::<&str, bool>(name, *value),
+        profiles::ConfigParam::String { name, value } => globals.raw_set::<&str, &str>(name, value),
+        profiles::ConfigParam::Color { name, value } => globals.raw_set::<&str, u32>(name, *value),
+    }
+}
+
 fn register_script_config(lua_ctx: Context, manifest: &Manifest) -> rlua::Result<()> {
     let profile = &*ACTIVE_PROFILE.lock();
     let script_name = &manifest.name;
@@ -919,5 +2810,41 @@ fn register_script_config(lua_ctx: Context, manifest: &Manifest) -> rlua::Result
         }
     }
 
+    // runtime overrides take precedence over the values of the profile
+    for param in overrides::get(script_name).iter() {
+        set_param_global(&globals, param)?;
+    }
+
+    update_cache_context(&globals, manifest)
+}
+
+/// Cache entries of the script are only valid for the current values of its parameters
+fn update_cache_context(globals: &rlua::Table, manifest: &Manifest) -> rlua::Result<()> {
+    let script_name = &manifest.name;
+    let mut parameters = vec![];
+
+    if let Some(config) = &manifest.config {
+        for param in config.iter() {
+            let name = match param {
+                ConfigParam::Int { name, .. }
+                | ConfigParam::Float { name, .. }
+                | ConfigParam::Bool { name, .. }
+                | ConfigParam::String { name, .. }
+                | ConfigParam::Color { name, .. } => name,
+            };
+
+            let value = match globals.raw_get::<&str, rlua::Value>(name)? {
+                rlua::Value::Boolean(b) => b.to_string(),
+                rlua::Value::Integer(i) => i.to_string(),
+                rlua::Value::Number(n) => n.to_string(),
+                rlua::Value::String(s) => s.to_str()?.to_string(),
+                _ => String::new(),
+            };
+
+            parameters.push(format!("{}={}", name, value));
+        }
+    }
+
+    cache::set_script_context(script_name, &parameters.join(";"));
     Ok(())
 }
@@ -0,0 +1,246 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+
+lazy_static! {
+    /// Tick state of each script layer, indexed by layer
+    static ref TICK_STATES: Mutex<Vec<TickState>> = Mutex::new(vec![]);
+}
+
+/// Tick state of a script layer
+#[derive(Debug, Default, Clone)]
+struct TickState {
+    /// Set while a Tick message is in flight, e.g. has not yet been handled
+    pending: bool,
+
+    /// Accumulated delta of the ticks that have been coalesced
+    delta: u32,
+
+    /// Number of ticks that have been coalesced
+    coalesced: u32,
+
+    /// The script requested ticks on every n-th iteration of the main loop only,
+    /// via `request_ticks(rate)`
+    divisor: Option<u64>,
+
+    /// The script requested to receive no ticks, via `suspend_ticks()`
+    suspended: bool,
+}
+
+/// Queue a tick with the delta `delta` for the script layer `layer`. If the
+/// previous tick has not been handled yet, the tick is coalesced with the
+/// next one, and None is returned. Otherwise the accumulated delta is
+/// returned, and a Tick message has to be sent to the layer. The delta of a
+/// coalesced tick includes the number of skipped ticks
+pub fn queue_tick(layer: usize, delta: u32) -> Option<u32> {
+    let mut tick_states = TICK_STATES.lock();
+
+    if tick_states.len() <= layer {
+        tick_states.resize(layer + 1, TickState::default());
+    }
+
+    let state = &mut tick_states[layer];
+
+    if state.pending {
+        state.delta = state.delta.saturating_add(delta);
+        state.coalesced = state.coalesced.saturating_add(1);
+
+        None
+    } else {
+        let result = state
+            .delta
+            .saturating_add(delta)
+            .saturating_add(state.coalesced);
+
+        state.pending = true;
+        state.delta = 0;
+        state.coalesced = 0;
+
+        Some(result)
+    }
+}
+
+/// Set the tick rate that has been requested by the script of layer `layer`, in
+/// ticks per second. None suspends the ticks of the layer, its frame is frozen
+pub fn request_tick_rate(layer: usize, rate: Option<u64>) {
+    let mut tick_states = TICK_STATES.lock();
+
+    if tick_states.len() <= layer {
+        tick_states.resize(layer + 1, TickState::default());
+    }
+
+    let state = &mut tick_states[layer];
+
+    match rate {
+        Some(rate) => {
+            let rate = rate.max(1).min(constants::TARGET_FPS);
+
+            state.divisor = Some(constants::TARGET_FPS / rate);
+
+            // the elapsed time of the suspension is not reported
+            if state.suspended {
+                state.suspended = false;
+                state.delta = 0;
+                state.coalesced = 0;
+            }
+        }
+
+        None => state.suspended = true,
+    }
+}
+
+/// Returns true if the script layer `layer` wants a tick in main loop iteration
+/// `ticks`. The delta of iterations that are skipped due to a reduced tick rate
+/// is accumulated, so that the next tick reports the elapsed time
+pub fn is_tick_requested(layer: usize, ticks: u64, delta: u32) -> bool {
+    let mut tick_states = TICK_STATES.lock();
+
+    let state = match tick_states.get_mut(layer) {
+        Some(state) => state,
+        None => return true,
+    };
+
+    if state.suspended {
+        return false;
+    }
+
+    match state.divisor {
+        Some(divisor) if ticks % divisor != 0 => {
+            // the skipped iteration counts as a tick
+            state.delta = state.delta.saturating_add(delta).saturating_add(1);
+
+            false
+        }
+
+        _ => true,
+    }
+}
+
+/// Signal that the script layer `layer` has handled its Tick message
+pub fn tick_completed(layer: usize) {
+    if let Some(state) = TICK_STATES.lock().get_mut(layer) {
+        state.pending = false;
+    }
+}
+
+/// Forget the tick states of all script layers, e.g. after the scripts have been reloaded
+pub fn clear_tick_states() {
+    TICK_STATES.lock().clear();
+}
+
+/// Adapts the tick rate to the system load: If the scripts are not able to keep up
+/// with the tick rate for a sustained period of time, ticks are only sent on every
+/// n-th iteration of the main loop. The tick rate is restored, when headroom returns
+#[derive(Debug)]
+pub struct AdaptiveTickRate {
+    /// Ticks are sent on every `divisor`-th iteration of the main loop
+    divisor: u64,
+
+    /// Start of the current measurement window
+    window_start: Instant,
+
+    /// Number of ticks queued during the current window
+    queued: u32,
+
+    /// Number of ticks that have been coalesced during the current window
+    coalesced: u32,
+
+    /// Accumulated delta of the iterations that did not send a tick
+    pending_delta: u32,
+
+    /// Lower bound of the divisor, e.g. while a fullscreen application is focused
+    min_divisor: u64,
+}
+
+impl AdaptiveTickRate {
+    pub fn new() -> Self {
+        AdaptiveTickRate {
+            divisor: 1,
+            window_start: Instant::now(),
+            queued: 0,
+            coalesced: 0,
+            pending_delta: 0,
+            min_divisor: 1,
+        }
+    }
+
+    /// Send ticks on no more than every `min_divisor`-th iteration of the main
+    /// loop, regardless of the load
+    pub fn set_min_divisor(&mut self, min_divisor: u64) {
+        self.min_divisor = u64::max(min_divisor, 1);
+    }
+
+    /// Returns the delta to send to the scripts in main loop iteration `ticks`,
+    /// or None if no ticks shall be sent in this iteration
+    pub fn next_tick(&mut self, ticks: u64, delta: u32) -> Option<u32> {
+        if ticks % u64::max(self.divisor, self.min_divisor) == 0 {
+            let result = self.pending_delta.saturating_add(delta);
+            self.pending_delta = 0;
+
+            Some(result)
+        } else {
+            // the skipped iteration counts as a tick
+            self.pending_delta = self.pending_delta.saturating_add(delta).saturating_add(1);
+
+            None
+        }
+    }
+
+    /// Record whether a queued tick has been coalesced, and re-evaluate
+    /// the tick rate at the end of each measurement window
+    pub fn record(&mut self, coalesced: bool) {
+        self.queued += 1;
+
+        if coalesced {
+            self.coalesced += 1;
+        }
+
+        if self.window_start.elapsed() >= Duration::from_millis(constants::TICK_RATE_WINDOW_MILLIS)
+        {
+            let percent = self.coalesced * 100 / u32::max(self.queued, 1);
+
+            if percent >= constants::TICK_RATE_OVERLOAD_PERCENT
+                && self.divisor < constants::MAX_TICK_RATE_DIVISOR
+            {
+                self.divisor += 1;
+
+                warn!(
+                    "Scripts can't keep up, {}% of the ticks have been coalesced. Reducing the tick rate to {} per second",
+                    percent,
+                    constants::TARGET_FPS / self.divisor
+                );
+            } else if percent == 0 && self.divisor > 1 {
+                self.divisor -= 1;
+
+                info!(
+                    "Restoring the tick rate to {} per second",
+                    constants::TARGET_FPS / self.divisor
+                );
+            }
+
+            self.window_start = Instant::now();
+            self.queued = 0;
+            self.coalesced = 0;
+        }
+    }
+}
@@ -0,0 +1,401 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A runtime for effects that have been compiled to WebAssembly, e.g. from Rust or
+//! AssemblyScript. WASM effects are listed in the profile like Lua scripts and need a
+//! manifest as well. The host API is imported from the module `env`:
+//!
+//! * `get_num_keys() -> i32`
+//! * `get_time_millis() -> i64`
+//! * `submit_color_map(ptr: i32, len: i32)`: Submit `len` colors (0xAARRGGBB, little endian)
+//! * `get_param(name_ptr: i32, name_len: i32) -> f64`: Value of a config param, or NaN
+//! * `log(level: i32, ptr: i32, len: i32)`: Log an UTF-8 message (0: error .. 4: trace)
+//!
+//! The module has to export its `memory`, and may export the event handlers
+//! `on_startup()`, `on_tick(delta: i32)`, `on_key_down(key_index: i32)`,
+//! `on_key_up(key_index: i32)` and `on_quit(code: i32)`.
+//!
+//! Calls into the module are interrupted after WASM_CALL_TIMEOUT_MILLIS. The
+//! wasmtime version in use has no fuel metering, so a watchdog thread interrupts
+//! calls that exceed their deadline instead.
+
+use log::*;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmtime::{Caller, Config, Engine, Extern, InterruptHandle, Linker, Module, Store, Trap};
+
+use crate::constants;
+use crate::profiles;
+use crate::rvdevice::NUM_KEYS;
+use crate::scripting::manifest::{ConfigParam, Manifest};
+use crate::scripting::overrides;
+use crate::scripting::script::{self, callbacks, Message, RunScriptResult, ScriptingError};
+use crate::scripting::ticks;
+use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
+
+fn wasm_error<E: std::fmt::Display>(e: E) -> ScriptingError {
+    ScriptingError::WasmError {
+        description: format!("{}", e),
+    }
+}
+
+/// Get the range of `len` bytes at `ptr`, if it lies within a memory of `size` bytes
+fn get_memory_range(ptr: i32, len: i32, size: usize) -> Option<std::ops::Range<usize>> {
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+
+    if end <= size {
+        Some(start..end)
+    } else {
+        None
+    }
+}
+
+/// Copy `len` bytes at `ptr` out of the exported memory of the calling module
+fn read_memory(caller: &Caller<'_>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("The module does not export its memory"))?;
+
+    let range = get_memory_range(ptr, len, memory.data_size())
+        .ok_or_else(|| Trap::new("Out of bounds memory access"))?;
+
+    // the memory is not accessed concurrently, since the module is single threaded
+    let data = unsafe { memory.data_unchecked() };
+
+    Ok(data[range].to_vec())
+}
+
+/// Spawn a thread, that interrupts calls into the module that exceed their
+/// deadline. The deadline of each call is sent before it starts, and None once it
+/// returned. The thread ends when the sender is dropped
+fn spawn_watchdog(handle: InterruptHandle) -> std::io::Result<Sender<Option<Instant>>> {
+    let (tx, rx) = mpsc::channel::<Option<Instant>>();
+
+    thread::Builder::new()
+        .name("wasm-watchdog".into())
+        .spawn(move || {
+            let mut deadline = None;
+
+            loop {
+                let result = match deadline {
+                    Some(deadline) => {
+                        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+
+                match result {
+                    Ok(next) => deadline = next,
+
+                    Err(RecvTimeoutError::Timeout) => {
+                        warn!("Interrupting a call into a WASM effect, that took too long");
+
+                        handle.interrupt();
+                        deadline = None;
+                    }
+
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        })?;
+
+    Ok(tx)
+}
+
+/// Get the value of the config param `name` of the effect, as specified by a runtime
+/// override, the active profile, or the default value of the manifest. Strings are
+/// not supported
+fn get_param(manifest: &Manifest, name: &str) -> Option<f64> {
+    if let Some(param) = overrides::find(&manifest.name, name) {
+        return match param {
+            profiles::ConfigParam::Int { value, .. } => Some(value as f64),
+            profiles::ConfigParam::Float { value, .. } => Some(value),
+            profiles::ConfigParam::Bool { value, .. } => Some(if value { 1.0 } else { 0.0 }),
+            profiles::ConfigParam::Color { value, .. } => Some(f64::from(value)),
+            profiles::ConfigParam::String { .. } => None,
+        };
+    }
+
+    let profile = ACTIVE_PROFILE.lock();
+    let profile = profile.as_ref();
+    let script_name = &manifest.name;
+
+    manifest
+        .config
+        .as_ref()?
+        .iter()
+        .find_map(|param| match param {
+            ConfigParam::Int {
+                name: n, default, ..
+            } if n == name => Some(
+                *profile
+                    .and_then(|p| p.get_int_value(script_name, name))
+                    .unwrap_or(default) as f64,
+            ),
+
+            ConfigParam::Float {
+                name: n, default, ..
+            } if n == name => Some(
+                *profile
+                    .and_then(|p| p.get_float_value(script_name, name))
+                    .unwrap_or(default),
+            ),
+
+            ConfigParam::Bool {
+                name: n, default, ..
+            } if n == name => {
+                let value = *profile
+                    .and_then(|p| p.get_bool_value(script_name, name))
+                    .unwrap_or(default);
+
+                Some(if value { 1.0 } else { 0.0 })
+            }
+
+            ConfigParam::Color {
+                name: n, default, ..
+            } if n == name => Some(f64::from(
+                *profile
+                    .and_then(|p| p.get_color_value(script_name, name))
+                    .unwrap_or(default),
+            )),
+
+            _ => None,
+        })
+}
+
+/// Register the host API with the linker `linker`
+fn register_host_funcs(linker: &mut Linker, manifest: &Manifest) -> anyhow::Result<()> {
+    linker.func("env", "get_num_keys", || NUM_KEYS as i32)?;
+
+    linker.func("env", "get_time_millis", || {
+        callbacks::get_time_millis() as i64
+    })?;
+
+    linker.func(
+        "env",
+        "submit_color_map",
+        |caller: Caller<'_>, ptr: i32, len: i32| -> Result<(), Trap> {
+            let len = len
+                .checked_mul(4)
+                .ok_or_else(|| Trap::new("Out of bounds memory access"))?;
+            let data = read_memory(&caller, ptr, len)?;
+
+            let map: Vec<u32> = data
+                .chunks(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+
+            callbacks::submit_color_map(&map);
+
+            Ok(())
+        },
+    )?;
+
+    let manifest = manifest.clone();
+    linker.func(
+        "env",
+        "get_param",
+        move |caller: Caller<'_>, ptr: i32, len: i32| -> Result<f64, Trap> {
+            let name = read_memory(&caller, ptr, len)?;
+            let name = String::from_utf8_lossy(&name);
+
+            Ok(get_param(&manifest, &name).unwrap_or(std::f64::NAN))
+        },
+    )?;
+
+    linker.func(
+        "env",
+        "log",
+        |caller: Caller<'_>, level: i32, ptr: i32, len: i32| -> Result<(), Trap> {
+            let msg = read_memory(&caller, ptr, len)?;
+            let msg = String::from_utf8_lossy(&msg);
+
+            match level {
+                0 => callbacks::log_error(&msg),
+                1 => callbacks::log_warn(&msg),
+                2 => callbacks::log_info(&msg),
+                3 => callbacks::log_debug(&msg),
+                _ => callbacks::log_trace(&msg),
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Loads and runs the WASM effect `file`, it renders to the device with the index
+/// `target_device`, as the `layer`-th layer. See `script::run_script`
+pub fn run_script(
+    file: PathBuf,
+    target_device: usize,
+    layer: usize,
+    rx: &Receiver<Message>,
+) -> script::Result<RunScriptResult> {
+    let manifest = Manifest::from(&file).map_err(|e| {
+        error!(
+            "Could not parse manifest file for script '{}': {}",
+            file.display(),
+            e
+        );
+
+        ScriptingError::InaccessibleManifest {}
+    })?;
+
+    ACTIVE_SCRIPTS.lock().push(manifest.clone());
+    callbacks::set_frame_interpolation(manifest.frame_interpolation);
+
+    let mut config = Config::new();
+    config.interruptable(true);
+
+    let engine = Engine::new(&config);
+    let store = Store::new(&engine);
+
+    let watchdog =
+        spawn_watchdog(store.interrupt_handle().map_err(wasm_error)?).map_err(wasm_error)?;
+
+    // bound the runtime of a call `f` into the module
+    let guarded = |f: &mut dyn FnMut() -> script::Result<()>| -> script::Result<()> {
+        let timeout = Duration::from_millis(constants::WASM_CALL_TIMEOUT_MILLIS);
+        watchdog.send(Some(Instant::now() + timeout)).ok();
+
+        let result = f();
+
+        watchdog.send(None).ok();
+
+        result
+    };
+
+    let module = Module::from_file(&engine, &file).map_err(wasm_error)?;
+
+    let mut linker = Linker::new(&store);
+    register_host_funcs(&mut linker, &manifest).map_err(wasm_error)?;
+
+    // the start function of the module is run on instantiation
+    let mut instance = None;
+    guarded(&mut || {
+        instance = Some(linker.instantiate(&module).map_err(wasm_error)?);
+        Ok(())
+    })?;
+    let instance = instance.unwrap();
+
+    let on_startup = instance
+        .get_func("on_startup")
+        .map(|f| f.get0::<()>())
+        .transpose()
+        .map_err(wasm_error)?;
+
+    let get_handler = |name: &str| {
+        instance
+            .get_func(name)
+            .map(|f| f.get1::<i32, ()>())
+            .transpose()
+            .map_err(wasm_error)
+    };
+
+    let on_tick = get_handler("on_tick")?;
+    let on_key_down = get_handler("on_key_down")?;
+    let on_key_up = get_handler("on_key_up")?;
+    let on_quit = get_handler("on_quit")?;
+
+    info!("Loaded WASM effect: {}", file.display());
+
+    if let Some(handler) = &on_startup {
+        guarded(&mut || handler().map_err(wasm_error))?;
+    }
+
+    let call = |handler: &Option<_>, param: i32| -> script::Result<()> {
+        if let Some(handler) = handler {
+            guarded(&mut || handler(param).map_err(wasm_error))?;
+        }
+
+        Ok(())
+    };
+
+    loop {
+        if let Ok(msg) = rx.recv() {
+            match msg {
+                Message::Quit(param) => call(&on_quit, param as i32)?,
+
+                Message::Tick(param) => {
+                    // further ticks will be queued again from now on
+                    ticks::tick_completed(layer);
+
+                    call(&on_tick, param as i32)?;
+                }
+
+                Message::RenderFrame(frame_id) => {
+                    script::publish_layer_frame(layer, target_device, frame_id);
+                }
+
+                Message::KeyDown(param) => {
+                    let result = call(&on_key_down, param as i32);
+
+                    *crate::UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_KEY_DOWN.1.notify_all();
+
+                    result?;
+                }
+
+                Message::KeyUp(param) => {
+                    let result = call(&on_key_up, param as i32);
+
+                    *crate::UPCALL_COMPLETED_ON_KEY_UP.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_KEY_UP.1.notify_all();
+
+                    result?;
+                }
+
+                Message::Unload => {
+                    debug!("TerminatedGracefully");
+
+                    return Ok(RunScriptResult::TerminatedGracefully);
+                }
+
+                // the other events are not yet supported by the WASM host API
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_memory_range() {
+        assert_eq!(get_memory_range(0, 4, 16), Some(0..4));
+        assert_eq!(get_memory_range(12, 4, 16), Some(12..16));
+        assert_eq!(get_memory_range(16, 0, 16), Some(16..16));
+
+        assert_eq!(get_memory_range(13, 4, 16), None);
+        assert_eq!(get_memory_range(-1, 4, 16), None);
+        assert_eq!(get_memory_range(0, -4, 16), None);
+        assert_eq!(
+            get_memory_range(i32::max_value(), i32::max_value(), 16),
+            None
+        );
+    }
+}
@@ -0,0 +1,62 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- global state variables --
+color_map = {}
+current_color = nil
+target_color = nil
+progress = 1.0
+
+-- event handler functions --
+function on_startup(config)
+    target_color = get_accent_color() or color_fallback
+    current_color = target_color
+end
+
+function on_accent_color_changed(color)
+    current_color = blend(current_color, target_color, progress)
+    target_color = color
+    progress = 0.0
+
+    request_ticks(100)
+end
+
+function on_tick(delta)
+    progress = math.min(progress + transition_speed, 1.0)
+
+    update_color_map(blend(current_color, target_color, progress))
+
+    -- the color is static, until the accent color changes
+    if progress >= 1.0 then
+        suspend_ticks()
+    end
+end
+
+-- utility functions --
+function blend(from, to, p)
+    local r1, g1, b1, a1 = color_to_rgba(from)
+    local r2, g2, b2, a2 = color_to_rgba(to)
+
+    return rgba_to_color(math.floor(lerp(r1, r2, p)), math.floor(lerp(g1, g2, p)),
+                         math.floor(lerp(b1, b2, p)), math.floor(lerp(0, 255, opacity)))
+end
+
+function update_color_map(color)
+    for i = 0, get_num_keys() do
+        color_map[i] = color
+    end
+
+    submit_color_map(color_map)
+end
@@ -0,0 +1,69 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- global state variables --
+color_map = {}
+names = {}
+status = {}
+ticks = 0
+
+-- key indices of F1..F12
+function_keys = { 12, 18, 24, 29, 49, 54, 60, 66, 79, 85, 86, 87 }
+
+-- event handler functions --
+function on_startup(config)
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    -- the pipelines are shown on the function keys, in the specified order
+    for name in string.gmatch(pipelines, "[^,%s]+") do
+        table.insert(names, name)
+        status[name] = get_build_status(name) or "unknown"
+    end
+end
+
+function on_build_status_changed(name, new_status)
+    status[name] = new_status
+end
+
+function on_tick(delta)
+    ticks = ticks + delta + 1
+
+    for i, name in ipairs(names) do
+        local key_index = function_keys[i]
+
+        if key_index ~= nil then
+            local s = status[name]
+            local color = color_unknown
+
+            if s == "success" then
+                color = color_success
+            elseif s == "failure" then
+                color = color_failure
+            elseif s == "running" or s == "pending" then
+                -- pulse while the pipeline is running
+                if ticks % 40 < 20 then
+                    color = color_running
+                end
+            end
+
+            color_map[key_index] = color
+        end
+    end
+
+    submit_color_map(color_map)
+end
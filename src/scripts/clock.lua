@@ -0,0 +1,67 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- global state variables --
+color_map = {}
+last_minute = nil
+
+-- key indices of F1..F12
+function_keys = { 12, 18, 24, 29, 49, 54, 60, 66, 79, 85, 86, 87 }
+
+-- event handler functions --
+function on_startup(config)
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    update_color_map()
+
+    -- the clock only changes once a minute
+    request_ticks(1)
+end
+
+function on_tick(delta)
+    local time = get_local_time()
+    if time.minute ~= last_minute then
+        update_color_map()
+    end
+end
+
+-- utility functions --
+function update_color_map()
+    local time = get_local_time()
+    last_minute = time.minute
+
+    for i = 0, get_num_keys() do
+        color_map[i] = 0x00000000
+    end
+
+    -- the hour is shown on F1..F12, the minutes in steps of 5 minutes
+    local hour = time.hour % 12
+    if hour == 0 then
+        hour = 12
+    end
+
+    local minutes = math.floor(time.minute / 5)
+    if minutes == 0 then
+        minutes = 12
+    end
+
+    color_map[function_keys[minutes]] = color_minutes
+    color_map[function_keys[hour]] = color_hour
+
+    submit_color_map(color_map)
+end
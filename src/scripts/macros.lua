@@ -19,6 +19,8 @@ LEFT_CTRL = 2
 RIGHT_CTRL = 3
 LEFT_ALT = 4
 RIGHT_ALTGR = 5
+EASY_SHIFT = 6
+FN = 7
 
 modifier_map = {}
 
@@ -580,6 +582,16 @@ function on_startup(config)
 	modifier_map[RIGHT_CTRL] = false
 	modifier_map[LEFT_ALT] = false
 	modifier_map[RIGHT_ALTGR] = false
+	modifier_map[EASY_SHIFT] = false
+	modifier_map[FN] = false
+end
+
+function on_easy_shift(down)
+	modifier_map[EASY_SHIFT] = down
+end
+
+function on_fn_key(down)
+	modifier_map[FN] = down
 end
 
 function on_key_down(key_index)
@@ -593,16 +605,8 @@ function on_key_down(key_index)
 	if key_index == 17 then modifier_map[LEFT_ALT] = true end
 	if key_index == 71 then modifier_map[RIGHT_ALTGR] = true end
 
-	-- media keys (F9 - F12)
-	if modifier_map[RIGHT_CTRL] and key_index == 79 then
-		inject_key(165, true) -- EV_KEY::PREVSONG
-	elseif modifier_map[RIGHT_CTRL] and key_index == 85 then
-		inject_key(166, true) -- EV_KEY::STOPCD
-	elseif modifier_map[RIGHT_CTRL] and key_index == 86 then
-		inject_key(164, true) -- EV_KEY::PLAYPAUSE
-	elseif modifier_map[RIGHT_CTRL] and key_index == 87 then
-		inject_key(163, true) -- EV_KEY::NEXTSONG
-	end
+	-- media keys (F9 - F12) are handled by the daemon, see the
+	-- [media_keys] section of eruption.conf
 end
 
 function on_key_up(key_index)
@@ -616,14 +620,4 @@ function on_key_up(key_index)
 	if key_index == 17 then modifier_map[LEFT_ALT] = false end
 	if key_index == 71 then modifier_map[RIGHT_ALTGR] = false end
 
-	-- media keys (F9 - F12)
-	if modifier_map[RIGHT_CTRL] and key_index == 79 then
-		inject_key(165, false) -- EV_KEY::PREVSONG
-	elseif modifier_map[RIGHT_CTRL] and key_index == 85 then
-		inject_key(166, false) -- EV_KEY::STOPCD
-	elseif modifier_map[RIGHT_CTRL] and key_index == 86 then
-		inject_key(164, false) -- EV_KEY::PLAYPAUSE
-	elseif modifier_map[RIGHT_CTRL] and key_index == 87 then
-		inject_key(163, false) -- EV_KEY::NEXTSONG
-	end
 end
@@ -0,0 +1,61 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- global state variables --
+color_map = {}
+key_group = {}
+accepted_sources = {}
+flash_ticks = 0
+
+-- event handler functions --
+function on_startup(config)
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    for key_index in string.gmatch(keys, "%d+") do
+        table.insert(key_group, tonumber(key_index))
+    end
+
+    for source in string.gmatch(sources, "[^,%s]+") do
+        accepted_sources[source] = true
+    end
+end
+
+function on_mention(source, text)
+    if next(accepted_sources) == nil or accepted_sources[source] then
+        info("Mention from " .. source .. ": " .. text)
+        flash_ticks = flash_count * 40
+    end
+end
+
+function on_tick(delta)
+    local color = 0x00000000
+
+    if flash_ticks > 0 then
+        flash_ticks = flash_ticks - 1
+
+        if flash_ticks % 40 >= 20 then
+            color = color_mention
+        end
+    end
+
+    for i, key_index in ipairs(key_group) do
+        color_map[key_index] = color
+    end
+
+    submit_color_map(color_map)
+end
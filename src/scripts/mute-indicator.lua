@@ -0,0 +1,42 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- global state variables --
+color_map = {}
+muted = false
+
+-- event handler functions --
+function on_startup(config)
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    muted = is_muted()
+end
+
+function on_volume_changed(volume, now_muted)
+    muted = now_muted
+end
+
+function on_tick(delta)
+    if muted then
+        color_map[key_index] = color_muted
+    else
+        color_map[key_index] = color_unmuted
+    end
+
+    submit_color_map(color_map)
+end
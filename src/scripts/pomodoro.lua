@@ -0,0 +1,86 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- key indices of F1..F12, the progress bar is shown on the function keys
+function_keys = { 12, 18, 24, 29, 49, 54, 60, 66, 79, 85, 86, 87 }
+
+-- global state variables --
+color_map = {}
+phase = "stopped"
+remaining = 0
+total = 0
+flash_ticks = 0
+ticks = 0
+
+-- event handler functions --
+function on_startup(config)
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    phase, remaining = get_timer_phase()
+    total = remaining
+end
+
+function on_timer_phase(new_phase, new_remaining)
+    -- flash the keyboard when a new phase starts
+    if new_phase ~= phase then
+        total = new_remaining
+
+        if new_phase == "break" then
+            flash_ticks = flash_duration
+        end
+    end
+
+    phase = new_phase
+    remaining = new_remaining
+
+    if remaining > total then
+        total = remaining
+    end
+end
+
+function on_tick(delta)
+    ticks = ticks + delta + 1
+
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    if flash_ticks > 0 then
+        flash_ticks = flash_ticks - 1
+
+        if ticks % 20 < 10 then
+            for i = 0, num_keys do
+                color_map[i] = color_flash
+            end
+        end
+    elseif phase ~= "stopped" and total > 0 then
+        -- render the progress bar on F1..F12, from left to right
+        local color = color_work
+        if phase == "break" then
+            color = color_break
+        end
+
+        local progress = trunc((total - remaining) / total * #function_keys)
+        for i = 1, progress do
+            color_map[function_keys[i]] = color
+        end
+    end
+
+    submit_color_map(color_map)
+end
@@ -0,0 +1,54 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+-- global state variables --
+color_map = {}
+voice_active = false
+brightness = 0.0
+
+-- event handler functions --
+function on_startup(config)
+    local num_keys = get_num_keys()
+    for i = 0, num_keys do
+        color_map[i] = 0x00000000
+    end
+
+    voice_active = is_voice_active()
+end
+
+function on_voice_activity(active)
+    voice_active = active
+end
+
+function on_tick(delta)
+    -- fade in quickly, and fade out slowly
+    if voice_active then
+        local level = get_mic_level() / 100.0
+        local target = 0.5 + level * 0.5
+
+        brightness = brightness + (target - brightness) * 0.3
+    else
+        brightness = brightness * fade_speed
+    end
+
+    local r, g, b, a = color_to_rgba(color_voice)
+    local color = rgba_to_color(r, g, b, math.floor(a * brightness))
+
+    for i = 0, get_num_keys() do
+        color_map[i] = color
+    end
+
+    submit_color_map(color_map)
+end
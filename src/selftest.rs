@@ -0,0 +1,212 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use std::fs::OpenOptions;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::{DeviceKind, RvDeviceError, RvDeviceState, NUM_KEYS, RGBA};
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, SelfTestError>;
+
+#[derive(Debug, Error)]
+pub enum SelfTestError {
+    #[error("Unknown capability: {name}")]
+    UnknownCapability { name: String },
+}
+
+/// The features of a device that are probed on startup
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Capability {
+    /// The LED interface accepts frames
+    Lighting,
+
+    /// Key events can be read from the input device of the keyboard
+    KeyEvents,
+
+    /// The secondary control interface is available, it reports the FN and Easy Shift keys
+    SpecialKeys,
+
+    /// Media keys can be injected, this requires key events and the uinput device
+    MediaKeys,
+}
+
+impl FromStr for Capability {
+    type Err = SelfTestError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.trim() {
+            "lighting" => Ok(Capability::Lighting),
+            "key-events" => Ok(Capability::KeyEvents),
+            "special-keys" => Ok(Capability::SpecialKeys),
+            "media-keys" => Ok(Capability::MediaKeys),
+
+            _ => Err(SelfTestError::UnknownCapability {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+/// The result of the self-test of a device
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    /// Firmware version, if it could be read from the device
+    pub firmware_version: Option<String>,
+
+    pub lighting: bool,
+    pub key_events: bool,
+    pub special_keys: bool,
+    pub media_keys: bool,
+}
+
+impl Capabilities {
+    pub fn has(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Lighting => self.lighting,
+            Capability::KeyEvents => self.key_events,
+            Capability::SpecialKeys => self.special_keys,
+            Capability::MediaKeys => self.media_keys,
+        }
+    }
+}
+
+lazy_static! {
+    /// The capabilities of each device, as found by the self-test
+    static ref CAPABILITIES: RwLock<Vec<Capabilities>> = RwLock::new(vec![]);
+}
+
+/// Probe the device `rvdevice`: Read the firmware version, write a test frame
+/// and check for the secondary control interface and the input devices.
+/// The device must be opened and initialized
+pub fn probe(rvdevice: &mut RvDeviceState, hidapi: &hidapi::HidApi) -> Capabilities {
+    let firmware_version = rvdevice
+        .device_info
+        .as_ref()
+        .map(|info| info.firmware_version.clone());
+
+    let test_frame = vec![
+        RGBA {
+            r: 0x00,
+            g: 0x00,
+            b: 0x00,
+            a: 0xff,
+        };
+        NUM_KEYS
+    ];
+
+    let lighting = match rvdevice.send_led_map(&test_frame) {
+        Ok(()) => true,
+
+        Err(e) => {
+            error!("Self-test: The device did not accept a test frame: {}", e);
+            false
+        }
+    };
+
+    let special_keys = match rvdevice.open_special_keys_device(hidapi) {
+        Ok(_ctrl_dev) => true,
+
+        // headsets have no special keys
+        Err(RvDeviceError::NotSupported {}) => false,
+
+        Err(e) => {
+            error!(
+                "Self-test: The secondary control interface is not available: {}",
+                e
+            );
+            false
+        }
+    };
+
+    // the input device belongs to the keyboard
+    let key_events = rvdevice.kind == DeviceKind::Keyboard
+        && match util::get_evdev_from_udev() {
+            Ok(_filename) => true,
+
+            Err(e) => {
+                error!(
+                    "Self-test: The input device of the keyboard is not available: {}",
+                    e
+                );
+                false
+            }
+        };
+
+    let uinput = match OpenOptions::new()
+        .write(true)
+        .open(constants::UINPUT_DEVICE)
+    {
+        Ok(_file) => true,
+
+        Err(e) => {
+            error!(
+                "Self-test: Could not open {}, keys can not be injected: {}",
+                constants::UINPUT_DEVICE,
+                e
+            );
+            false
+        }
+    };
+
+    Capabilities {
+        firmware_version,
+
+        lighting,
+        key_events,
+        special_keys,
+        media_keys: key_events && uinput,
+    }
+}
+
+/// Record the capabilities of all devices, and log which of them are available
+pub fn set_capabilities(capabilities: Vec<Capabilities>) {
+    let available = |b| if b { "available" } else { "NOT AVAILABLE" };
+
+    for (index, caps) in capabilities.iter().enumerate() {
+        info!(
+            "Device {}: firmware: {}, lighting: {}, key events: {}, special keys: {}, media keys: {}",
+            index,
+            caps.firmware_version
+                .as_ref()
+                .map(|v| v.as_str())
+                .unwrap_or("unknown"),
+            available(caps.lighting),
+            available(caps.key_events),
+            available(caps.special_keys),
+            available(caps.media_keys)
+        );
+    }
+
+    *CAPABILITIES.write() = capabilities;
+}
+
+/// Get the capabilities of the device with the index `index`, as found by the self-test
+pub fn get_capabilities(index: usize) -> Capabilities {
+    CAPABILITIES.read().get(index).cloned().unwrap_or_default()
+}
+
+/// Returns true if the default device has the capability `capability`. Key
+/// events and media keys are only handled for the default device
+pub fn has_capability(capability: Capability) -> bool {
+    get_capabilities(0).has(capability)
+}
@@ -0,0 +1,241 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Export of the composited frames via POSIX shared memory, so that external
+//! visualizers (e.g. OBS overlays or desktop widgets) can show the current state
+//! of the keyboard without polling the control API. The segment starts with a
+//! header of 32 bytes, followed by an RGBA quadruple for each LED:
+//!
+//! | Offset | Size  | Contents                                                   |
+//! |--------|-------|------------------------------------------------------------|
+//! | 0      | 8     | Magic bytes `ERUFRAME`                                     |
+//! | 8      | 4     | Version of the layout (u32, native endianness)             |
+//! | 12     | 4     | Number of LEDs (u32)                                       |
+//! | 16     | 8     | Sequence counter (u64), odd while a frame is being written |
+//! | 24     | 8     | Milliseconds since the start of the daemon (u64)           |
+//! | 32     | 4 * n | The RGBA values of the LEDs                                |
+//!
+//! Readers copy the frame, and retry if the sequence counter was odd, or changed
+//! while copying.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::ffi::CString;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use thiserror::Error;
+
+use crate::constants;
+use crate::rvdevice::{NUM_KEYS, RGBA};
+use crate::scripting::script;
+
+pub type Result<T> = std::result::Result<T, ShmError>;
+
+#[derive(Debug, Error)]
+pub enum ShmError {
+    #[error("Invalid name of the shared memory segment: {name}")]
+    InvalidName { name: String },
+
+    #[error("Could not create the shared memory segment: {description}")]
+    ShmOpenError { description: String },
+
+    #[error("Could not map the shared memory segment: {description}")]
+    MmapError { description: String },
+}
+
+/// Magic bytes at the start of the segment
+const MAGIC: &[u8; 8] = b"ERUFRAME";
+
+/// Version of the layout of the segment
+const LAYOUT_VERSION: u32 = 1;
+
+/// Size of the header, the frame starts at this offset
+const HEADER_SIZE: usize = 32;
+
+/// Offset of the sequence counter
+const SEQUENCE_OFFSET: usize = 16;
+
+/// Offset of the timestamp
+const TIMESTAMP_OFFSET: usize = 24;
+
+/// Size of the segment
+const SEGMENT_SIZE: usize = HEADER_SIZE + NUM_KEYS * 4;
+
+/// The mapped shared memory segment
+struct Segment {
+    ptr: *mut u8,
+}
+
+// the segment is only accessed while the lock of SEGMENT is held
+unsafe impl Send for Segment {}
+
+impl Segment {
+    fn sequence(&self) -> &AtomicU64 {
+        unsafe { &*(self.ptr.add(SEQUENCE_OFFSET) as *const AtomicU64) }
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= SEGMENT_SIZE);
+
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(offset), data.len()) };
+    }
+}
+
+lazy_static! {
+    static ref SEGMENT: Mutex<Option<Segment>> = Mutex::new(None);
+}
+
+fn open_segment(name: &str) -> Result<Segment> {
+    let c_name = CString::new(name).map_err(|_e| ShmError::InvalidName {
+        name: name.to_string(),
+    })?;
+
+    // /dev/shm is writable by all users, so a segment that already exists may have
+    // been created by another user. It is replaced by a fresh segment
+    unsafe { libc::shm_unlink(c_name.as_ptr()) };
+
+    let fd = unsafe {
+        libc::shm_open(
+            c_name.as_ptr(),
+            libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+            constants::SHM_FRAME_MODE,
+        )
+    };
+
+    if fd < 0 {
+        return Err(ShmError::ShmOpenError {
+            description: format!("{}", std::io::Error::last_os_error()),
+        });
+    }
+
+    let fail = |fd: libc::c_int| {
+        let e = std::io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+
+        ShmError::ShmOpenError {
+            description: format!("{}", e),
+        }
+    };
+
+    // shm_open() is subject to the umask of the daemon
+    if unsafe { libc::fchown(fd, libc::geteuid(), libc::getegid()) } != 0
+        || unsafe { libc::fchmod(fd, constants::SHM_FRAME_MODE) } != 0
+    {
+        return Err(fail(fd));
+    }
+
+    if unsafe { libc::ftruncate(fd, SEGMENT_SIZE as libc::off_t) } != 0 {
+        return Err(fail(fd));
+    }
+
+    // the segment must not be smaller than the mapping, accesses beyond its end
+    // would raise SIGBUS
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        return Err(fail(fd));
+    }
+
+    if (stat.st_size as u64) < SEGMENT_SIZE as u64 {
+        unsafe { libc::close(fd) };
+
+        return Err(ShmError::ShmOpenError {
+            description: format!("Invalid size of the segment: {} bytes", stat.st_size),
+        });
+    }
+
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            SEGMENT_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+
+    // the mapping stays valid after the descriptor has been closed
+    unsafe { libc::close(fd) };
+
+    if ptr == libc::MAP_FAILED {
+        return Err(ShmError::MmapError {
+            description: format!("{}", std::io::Error::last_os_error()),
+        });
+    }
+
+    let mut segment = Segment {
+        ptr: ptr as *mut u8,
+    };
+
+    segment.write(0, MAGIC);
+    segment.write(8, &LAYOUT_VERSION.to_ne_bytes());
+    segment.write(12, &(NUM_KEYS as u32).to_ne_bytes());
+    segment.sequence().store(0, Ordering::SeqCst);
+
+    Ok(segment)
+}
+
+/// Create the shared memory segment, if enabled in the `[shm]` section
+pub fn initialize() -> Result<()> {
+    let (enabled, name) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        (
+            config.get_bool("shm.enabled").unwrap_or(false),
+            config
+                .get_str("shm.name")
+                .unwrap_or_else(|_| constants::DEFAULT_SHM_FRAME_NAME.into()),
+        )
+    };
+
+    if !enabled {
+        return Ok(());
+    }
+
+    *SEGMENT.lock() = Some(open_segment(&name)?);
+
+    info!("Exporting the frames to the shared memory segment {}", name);
+
+    Ok(())
+}
+
+/// Publish the composited frame `led_map`, if the export is enabled
+pub fn publish(led_map: &[RGBA]) {
+    let mut segment = SEGMENT.lock();
+
+    if let Some(segment) = segment.as_mut() {
+        let mut buf = Vec::with_capacity(NUM_KEYS * 4);
+
+        for color in led_map.iter().take(NUM_KEYS) {
+            buf.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+
+        // seqlock: the counter is odd while the frame is being written
+        segment.sequence().fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::Release);
+
+        let timestamp_millis = script::START_TIME.elapsed().as_millis() as u64;
+
+        segment.write(TIMESTAMP_OFFSET, &timestamp_millis.to_ne_bytes());
+        segment.write(HEADER_SIZE, &buf);
+
+        fence(Ordering::Release);
+        segment.sequence().fetch_add(1, Ordering::SeqCst);
+    }
+}
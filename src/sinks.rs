@@ -0,0 +1,228 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Output sinks, that the final frames of the render loop are written to. Frames
+//! are written to the devices themselves, the frame recorder, the shared memory
+//! export and LED strips on the network. All registered sinks are active at once,
+//! new targets implement `OutputSink` and register themselves. The mock sink
+//! replaces the devices, if enabled.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+
+use crate::recorder;
+use crate::rvdevice::RGBA;
+use crate::shm;
+
+/// A target of the final frames of the render loop
+pub trait OutputSink {
+    /// The name of the sink, for logging
+    fn name(&self) -> &str;
+
+    /// Returns true if the sink accepts the frames of the device `device`, by
+    /// default only the frames of the default device are accepted
+    fn accepts(&self, device: usize) -> bool {
+        device == 0
+    }
+
+    /// Write the final frame `led_map` of the device `device`
+    fn write_frame(&mut self, device: usize, led_map: &[RGBA]);
+
+    /// Called once when the daemon exits, after the last frame has been written
+    fn close(&mut self) {}
+}
+
+lazy_static! {
+    /// The registered sinks, in the order of their registration
+    static ref SINKS: Mutex<Vec<Box<dyn OutputSink + Send>>> = Mutex::new(vec![]);
+}
+
+/// Register the sink `sink`, it receives all subsequent frames that it accepts
+pub fn register(sink: Box<dyn OutputSink + Send>) {
+    info!("Registered output sink: {}", sink.name());
+
+    SINKS.lock().push(sink);
+}
+
+/// Write the final frame `led_map` of the device `device` to all registered sinks
+/// that accept it
+pub fn write_frame(device: usize, led_map: &[RGBA]) {
+    for sink in SINKS.lock().iter_mut() {
+        if sink.accepts(device) {
+            sink.write_frame(device, led_map);
+        }
+    }
+}
+
+/// Close and remove all registered sinks, in the order of their registration
+pub fn close() {
+    let sinks = SINKS.lock().drain(..).collect::<Vec<_>>();
+
+    for mut sink in sinks {
+        debug!("Closing output sink: {}", sink.name());
+
+        sink.close();
+    }
+}
+
+/// Records the frames of the default device, while a recording is running
+struct RecorderSink;
+
+impl OutputSink for RecorderSink {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    fn write_frame(&mut self, _device: usize, led_map: &[RGBA]) {
+        recorder::record(led_map);
+    }
+}
+
+/// Exports the frames of the default device to shared memory, if enabled
+struct ShmSink;
+
+impl OutputSink for ShmSink {
+    fn name(&self) -> &str {
+        "shm"
+    }
+
+    fn write_frame(&mut self, _device: usize, led_map: &[RGBA]) {
+        shm::publish(led_map);
+    }
+}
+
+/// Accepts the frames of all devices and discards them, it only logs the number
+/// of frames and the average brightness. Used to run the render loop without
+/// touching the devices
+#[derive(Default)]
+struct MockSink {
+    frames: u64,
+}
+
+impl OutputSink for MockSink {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn accepts(&self, _device: usize) -> bool {
+        true
+    }
+
+    fn write_frame(&mut self, device: usize, led_map: &[RGBA]) {
+        self.frames += 1;
+
+        let brightness = led_map
+            .iter()
+            .map(|c| (c.r as u64 + c.g as u64 + c.b as u64) / 3)
+            .sum::<u64>()
+            / led_map.len().max(1) as u64;
+
+        trace!(
+            "Mock sink: Frame {} of device {}, average brightness: {}",
+            self.frames,
+            device,
+            brightness
+        );
+    }
+}
+
+/// Returns true if the mock sink is enabled in the `[outputs]` section, it replaces
+/// the sink of the devices
+pub fn is_mock_enabled() -> bool {
+    crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get_bool("outputs.mock").ok())
+        .unwrap_or(false)
+}
+
+/// Register the built-in sinks: The frame recorder, the shared memory export and,
+/// if enabled, the mock sink. The sink of the devices is registered by the daemon,
+/// once the devices are initialized
+pub fn initialize() {
+    register(Box::new(RecorderSink));
+    register(Box::new(ShmSink));
+
+    if is_mock_enabled() {
+        register(Box::new(MockSink::default()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Records the device and the length of each frame that it receives
+    struct TestSink {
+        frames: Arc<Mutex<Vec<(usize, usize)>>>,
+    }
+
+    impl OutputSink for TestSink {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn write_frame(&mut self, device: usize, led_map: &[RGBA]) {
+            self.frames.lock().push((device, led_map.len()));
+        }
+    }
+
+    #[test]
+    fn test_accepts() {
+        let sink = TestSink {
+            frames: Arc::new(Mutex::new(vec![])),
+        };
+
+        assert!(sink.accepts(0));
+        assert!(!sink.accepts(1));
+
+        let mock = MockSink::default();
+
+        assert!(mock.accepts(0));
+        assert!(mock.accepts(1));
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let frames = Arc::new(Mutex::new(vec![]));
+
+        register(Box::new(TestSink {
+            frames: frames.clone(),
+        }));
+
+        let led_map = vec![
+            RGBA {
+                r: 0xff,
+                g: 0x00,
+                b: 0x00,
+                a: 0xff,
+            };
+            4
+        ];
+
+        write_frame(0, &led_map);
+        write_frame(1, &led_map);
+        write_frame(0, &led_map[..2]);
+
+        // frames of other devices are not accepted by default
+        assert_eq!(*frames.lock(), vec![(0, 4), (0, 2)]);
+
+        SINKS.lock().retain(|sink| sink.name() != "test");
+    }
+}
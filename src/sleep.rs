@@ -0,0 +1,190 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Handling of suspend and resume. A delay inhibitor of logind gives the main
+//! loop the chance to pause rendering before the system suspends. On resume, the
+//! devices are reinitialized, since they may have lost their state, and the active
+//! profile is replayed so that the effects start from a clean state.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, SleepError>;
+
+#[derive(Debug, Error)]
+pub enum SleepError {
+    #[error("Could not talk to logind: {description}")]
+    LogindError { description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// A transition that has to be handled by the main loop
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SleepEvent {
+    /// The system is about to suspend, rendering has to be paused
+    Suspend,
+
+    /// The system resumed, the devices have to be reinitialized
+    Resume,
+}
+
+lazy_static! {
+    /// Set while the system is suspended, or about to suspend
+    static ref SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+    /// Transition that has not yet been handled by the main loop
+    static ref PENDING_EVENT: Mutex<Option<SleepEvent>> = Mutex::new(None);
+
+    /// Set by the main loop, after rendering has been paused
+    static ref SUSPEND_ACKNOWLEDGED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+}
+
+/// Returns true while the system is suspended, or about to suspend
+pub fn is_suspended() -> bool {
+    SUSPENDED.load(Ordering::SeqCst)
+}
+
+/// Get the transition that has to be handled by the main loop, if any
+pub fn take_event() -> Option<SleepEvent> {
+    PENDING_EVENT.lock().take()
+}
+
+/// Signal that rendering has been paused, the system may suspend now
+pub fn acknowledge_suspend() {
+    *SUSPEND_ACKNOWLEDGED.0.lock() = true;
+    SUSPEND_ACKNOWLEDGED.1.notify_all();
+}
+
+#[cfg(feature = "dbus")]
+mod logind {
+    use dbus::{arg::OwnedFd, ffidisp::BusType, ffidisp::Connection, Message as DbusMessage};
+    use log::*;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use super::{SleepError, SleepEvent, PENDING_EVENT, SUSPENDED, SUSPEND_ACKNOWLEDGED};
+    use crate::constants;
+
+    /// Take a delay inhibitor, that is held until rendering has been paused
+    fn inhibit(connection: &Connection) -> super::Result<OwnedFd> {
+        let msg = DbusMessage::new_method_call(
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            "Inhibit",
+        )
+        .map_err(|e| SleepError::LogindError { description: e })?
+        .append3("sleep", "Eruption", "Pausing the effects before suspend")
+        .append1("delay");
+
+        let reply = connection
+            .send_with_reply_and_block(msg, constants::DBUS_TIMEOUT_MILLIS as i32)
+            .map_err(|e| SleepError::LogindError {
+                description: format!("{}", e),
+            })?;
+
+        reply.read1().map_err(|e| SleepError::LogindError {
+            description: format!("{}", e),
+        })
+    }
+
+    fn prepare_for_sleep(start: bool) {
+        SUSPENDED.store(start, Ordering::SeqCst);
+
+        if start {
+            info!("The system is about to suspend");
+
+            *SUSPEND_ACKNOWLEDGED.0.lock() = false;
+            *PENDING_EVENT.lock() = Some(SleepEvent::Suspend);
+
+            // give the main loop some time to pause rendering
+            let mut acknowledged = SUSPEND_ACKNOWLEDGED.0.lock();
+
+            if !*acknowledged {
+                SUSPEND_ACKNOWLEDGED.1.wait_for(
+                    &mut acknowledged,
+                    Duration::from_millis(constants::SLEEP_PAUSE_TIMEOUT_MILLIS),
+                );
+            }
+        } else {
+            info!("The system resumed from suspend");
+
+            *PENDING_EVENT.lock() = Some(SleepEvent::Resume);
+        }
+    }
+
+    pub fn run() -> super::Result<()> {
+        let connection =
+            Connection::get_private(BusType::System).map_err(|e| SleepError::LogindError {
+                description: format!("{}", e),
+            })?;
+
+        connection
+            .add_match(
+                "type='signal',interface='org.freedesktop.login1.Manager',member='PrepareForSleep'",
+            )
+            .map_err(|e| SleepError::LogindError {
+                description: format!("{}", e),
+            })?;
+
+        let mut inhibitor = Some(inhibit(&connection)?);
+
+        loop {
+            for msg in connection.incoming(constants::DBUS_TIMEOUT_MILLIS) {
+                if msg.member().as_deref() != Some("PrepareForSleep") {
+                    continue;
+                }
+
+                let start: bool = match msg.read1() {
+                    Ok(start) => start,
+                    Err(_) => continue,
+                };
+
+                prepare_for_sleep(start);
+
+                if start {
+                    // releasing the inhibitor lets the system suspend
+                    inhibitor = None;
+                } else if inhibitor.is_none() {
+                    inhibitor = inhibit(&connection)
+                        .map_err(|e| error!("Could not take a sleep inhibitor: {}", e))
+                        .ok();
+                }
+            }
+        }
+    }
+}
+
+/// Watch for suspend and resume via logind
+pub fn initialize() -> Result<()> {
+    #[cfg(feature = "dbus")]
+    {
+        std::thread::Builder::new()
+            .name("sleep".into())
+            .spawn(|| {
+                logind::run().unwrap_or_else(|e| error!("Suspend handling disabled: {}", e));
+            })
+            .map_err(|_e| SleepError::ThreadSpawnError {})?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,659 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Snapshots of the effective configuration, e.g. to migrate to another machine,
+//! or to attach to a bug report. A snapshot is a tar archive with the extension
+//! `.eruption-snapshot`, that contains:
+//!
+//! * `snapshot.toml`: The version of Eruption, the time of creation, the active
+//!   profile and the parameters of scripts that are overridden at runtime
+//! * `eruption.conf`: The configuration file, including hotkeys and key groups.
+//!   Secrets like API keys and access tokens are redacted
+//! * `profiles/*.profile`: The active profile, and all profiles in /etc and in the
+//!   configuration directory of the user
+//! * `state/eruption.state`, `state/alerts.toml`: The runtime state of the daemon
+//!
+//! The snapshot is only readable by its owner. When a snapshot is imported,
+//! existing files are kept as a backup with the extension `.bak`.
+
+use chrono::Local;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+use crate::constants;
+use crate::paths;
+use crate::profiles::{self, Profile};
+
+pub type Result<T> = std::result::Result<T, SnapshotError>;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Could not access the snapshot: {description}")]
+    IoError { description: String },
+
+    #[error("Invalid snapshot: {description}")]
+    InvalidSnapshot { description: String },
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::IoError {
+            description: format!("{}", e),
+        }
+    }
+}
+
+fn invalid_snapshot<S: Into<String>>(description: S) -> SnapshotError {
+    SnapshotError::InvalidSnapshot {
+        description: description.into(),
+    }
+}
+
+/// Name of the description of the snapshot, in the archive
+const SNAPSHOT_INFO_FILE: &str = "snapshot.toml";
+
+/// Name of the configuration file, in the archive
+const SNAPSHOT_CONFIG_FILE: &str = "eruption.conf";
+
+/// The files of the runtime state, that are part of a snapshot
+const STATE_FILES: &[&str] = &["eruption.state", "alerts.toml"];
+
+/// The keys of the configuration file that hold secrets, qualified by their table.
+/// Their values are replaced by an empty string in snapshots
+const SECRET_KEYS: &[&str] = &["weather.api_key", "ci.pipelines.token", "sync.key"];
+
+/// File mode of exported snapshots
+const SNAPSHOT_MODE: u32 = 0o600;
+
+/// Metadata of a snapshot, as specified by `snapshot.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    /// Version of Eruption, that created the snapshot
+    pub version: String,
+
+    /// Time of creation, in RFC 3339 format
+    pub created: String,
+
+    /// File name of the active profile
+    pub profile: Option<String>,
+
+    /// Parameters of scripts that were overridden at runtime, maps qualified names
+    /// like "shockwave.color_step" to their values
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+}
+
+/// A snapshot that has been read into memory
+#[derive(Debug)]
+pub struct Snapshot {
+    pub info: SnapshotInfo,
+
+    /// The files of the snapshot, with paths relative to the root of the archive
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// Returns true if `path` is relative and does not leave the directory it is
+/// extracted to
+fn is_safe_path(path: &Path) -> bool {
+    path.components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+/// Returns true if `name` is a plain file name, that consists of alphanumeric
+/// characters, '-', '_' and '.', and is not hidden
+fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Strip leading "./" components from `path`
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// Get the file name of the active profile, as recorded in the runtime state
+fn get_active_profile() -> Option<String> {
+    let state = fs::read_to_string(PathBuf::from(constants::STATE_DIR).join("eruption.state"))
+        .ok()?
+        .parse::<toml::Value>()
+        .ok()?;
+
+    state
+        .get("profile")
+        .and_then(|profile| profile.as_str())
+        .map(|profile| profile.to_string())
+}
+
+/// Get the profiles that are part of a snapshot: the active profile, and the
+/// profiles in /etc and in the configuration directory of the user. Profiles of a
+/// directory with a higher precedence hide the profiles with the same file name
+fn get_profile_files(active_profile: Option<&str>) -> Vec<PathBuf> {
+    let mut result = BTreeMap::new();
+
+    if let Some(active_profile) = active_profile {
+        if let Ok(file) = paths::find_profile(active_profile) {
+            if file.is_file() {
+                result.insert(PathBuf::from(active_profile), file);
+            }
+        }
+    }
+
+    let mut dirs = vec![PathBuf::from(constants::SYSTEM_PROFILE_DIR)];
+    dirs.extend(paths::get_local_user_dir("profiles"));
+
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        match profiles::get_profile_files(dir) {
+            Ok(files) => {
+                for file in files {
+                    if let Some(name) = file.file_name() {
+                        result.insert(PathBuf::from(name), file.clone());
+                    }
+                }
+            }
+
+            Err(e) => warn!("Could not enumerate profiles in {}: {}", dir.display(), e),
+        }
+    }
+
+    result.into_iter().map(|(_, file)| file).collect()
+}
+
+/// Replace the values of the secret keys of the configuration file `config` by an
+/// empty string. The file is processed line by line, so that comments and the
+/// formatting of all other lines are kept
+fn redact_secrets(config: &str) -> String {
+    let mut table = String::new();
+    let mut result = String::with_capacity(config.len());
+
+    for line in config.lines() {
+        let trimmed = line.trim_start();
+        let content = trimmed.split('#').next().unwrap_or_default().trim();
+
+        let name = content.trim_matches(|c| c == '[' || c == ']').trim();
+
+        // a header of a table, or of an array of tables, as opposed to the nested
+        // arrays of a value that spans multiple lines
+        if content.starts_with('[')
+            && content.ends_with(']')
+            && trimmed.len() == line.len()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '"')
+        {
+            table = name.to_string();
+        } else if let Some(index) = content.find('=') {
+            let key = content[..index].trim().trim_matches('"');
+
+            let qualified_key = if table.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", table, key)
+            };
+
+            if SECRET_KEYS.contains(&qualified_key.as_str()) {
+                let indent = &line[..line.len() - trimmed.len()];
+
+                result.push_str(&format!("{}{} = \"\"\n", indent, key));
+                continue;
+            }
+        }
+
+        result.push_str(line);
+        result.push('\n');
+    }
+
+    result
+}
+
+fn append_file(builder: &mut tar::Builder<File>, path: &Path, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Local::now().timestamp() as u64);
+    header.set_cksum();
+
+    builder.append_data(&mut header, path, data)?;
+
+    Ok(())
+}
+
+/// Write a snapshot with the description `info` to `path`, containing the
+/// configuration file `config_file`, the profiles `profile_files` and the files of
+/// the runtime state in `state_dir`
+fn write_snapshot(
+    path: &Path,
+    info: &SnapshotInfo,
+    config_file: &Path,
+    profile_files: &[PathBuf],
+    state_dir: &Path,
+) -> Result<()> {
+    let info = toml::ser::to_string_pretty(info).map_err(|e| SnapshotError::IoError {
+        description: format!("{}", e),
+    })?;
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(SNAPSHOT_MODE)
+        .open(path)?;
+
+    // the mode is only applied to newly created files
+    file.set_permissions(fs::Permissions::from_mode(SNAPSHOT_MODE))?;
+
+    let mut builder = tar::Builder::new(file);
+
+    append_file(&mut builder, Path::new(SNAPSHOT_INFO_FILE), info.as_bytes())?;
+    append_file(
+        &mut builder,
+        Path::new(SNAPSHOT_CONFIG_FILE),
+        redact_secrets(&fs::read_to_string(config_file)?).as_bytes(),
+    )?;
+
+    for file in profile_files {
+        let name = Path::new("profiles").join(file.file_name().unwrap());
+
+        debug!("Adding profile: {}", file.display());
+        append_file(&mut builder, &name, &fs::read(&file)?)?;
+    }
+
+    for name in STATE_FILES {
+        let file = state_dir.join(name);
+
+        if file.is_file() {
+            append_file(
+                &mut builder,
+                &Path::new("state").join(name),
+                &fs::read(&file)?,
+            )?;
+        }
+    }
+
+    builder.finish()?;
+
+    Ok(())
+}
+
+/// Export the effective configuration into the snapshot `path`. The configuration
+/// file is read from `config_file`, `overrides` are the parameters that are
+/// overridden in the running daemon
+pub fn export<P: AsRef<Path>>(
+    path: P,
+    config_file: &Path,
+    overrides: BTreeMap<String, String>,
+) -> Result<()> {
+    let profile = get_active_profile();
+
+    let info = SnapshotInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created: Local::now().to_rfc3339(),
+        profile: profile.clone(),
+        overrides,
+    };
+
+    write_snapshot(
+        path.as_ref(),
+        &info,
+        config_file,
+        &get_profile_files(profile.as_deref()),
+        Path::new(constants::STATE_DIR),
+    )?;
+
+    debug!("Exported the configuration to: {}", path.as_ref().display());
+
+    Ok(())
+}
+
+/// Read the snapshot `path` and validate it: Only the configuration file, profiles
+/// and the files of the runtime state are allowed, each of them at most once, and
+/// all of them must be parseable
+pub fn read_snapshot<P: AsRef<Path>>(path: P) -> Result<Snapshot> {
+    let mut archive = tar::Archive::new(File::open(path.as_ref())?);
+
+    let mut info = None;
+    let mut files: Vec<(PathBuf, Vec<u8>)> = vec![];
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = normalize_path(&entry.path()?);
+
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+
+        if !entry.header().entry_type().is_file() || !is_safe_path(&entry_path) {
+            return Err(invalid_snapshot(format!(
+                "Invalid file: {}",
+                entry_path.display()
+            )));
+        }
+
+        if entry.header().size()? > constants::MAX_SNAPSHOT_FILE_SIZE {
+            return Err(invalid_snapshot(format!(
+                "File too large: {}",
+                entry_path.display()
+            )));
+        }
+
+        // the size in the header is not trusted, read at most one byte more than allowed
+        let mut buf = vec![];
+        entry
+            .by_ref()
+            .take(constants::MAX_SNAPSHOT_FILE_SIZE + 1)
+            .read_to_end(&mut buf)?;
+
+        if buf.len() as u64 > constants::MAX_SNAPSHOT_FILE_SIZE {
+            return Err(invalid_snapshot(format!(
+                "File too large: {}",
+                entry_path.display()
+            )));
+        }
+
+        let toml = String::from_utf8_lossy(&buf);
+        let parent = entry_path.parent().unwrap_or_else(|| Path::new(""));
+        let extension = entry_path.extension().map(|e| e.to_string_lossy());
+
+        if entry_path == Path::new(SNAPSHOT_INFO_FILE) {
+            if info.is_some() {
+                return Err(invalid_snapshot(format!(
+                    "Duplicate file: {}",
+                    SNAPSHOT_INFO_FILE
+                )));
+            }
+
+            info = Some(
+                toml::de::from_str::<SnapshotInfo>(&toml)
+                    .map_err(|e| invalid_snapshot(format!("{}: {}", SNAPSHOT_INFO_FILE, e)))?,
+            );
+
+            continue;
+        }
+
+        if files.iter().any(|(file, _)| *file == entry_path) {
+            return Err(invalid_snapshot(format!(
+                "Duplicate file: {}",
+                entry_path.display()
+            )));
+        }
+
+        if entry_path == Path::new(SNAPSHOT_CONFIG_FILE) {
+            toml.parse::<toml::Value>()
+                .map_err(|e| invalid_snapshot(format!("{}: {}", SNAPSHOT_CONFIG_FILE, e)))?;
+        } else if parent == Path::new("profiles") && extension.as_deref() == Some("profile") {
+            // profiles are installed into /etc, so only plain file names are allowed
+            let name = entry_path.file_name().unwrap().to_string_lossy();
+
+            if !is_valid_file_name(&name) {
+                return Err(invalid_snapshot(format!(
+                    "Invalid file name: {}",
+                    entry_path.display()
+                )));
+            }
+
+            toml::de::from_str::<Profile>(&toml)
+                .map_err(|e| invalid_snapshot(format!("{}: {}", entry_path.display(), e)))?;
+        } else if parent == Path::new("state")
+            && STATE_FILES
+                .iter()
+                .any(|name| entry_path.file_name() == Some(name.as_ref()))
+        {
+            toml.parse::<toml::Value>()
+                .map_err(|e| invalid_snapshot(format!("{}: {}", entry_path.display(), e)))?;
+        } else {
+            return Err(invalid_snapshot(format!(
+                "Unexpected file: {}",
+                entry_path.display()
+            )));
+        }
+
+        files.push((entry_path, buf));
+    }
+
+    let info = info.ok_or_else(|| invalid_snapshot(format!("Missing {}", SNAPSHOT_INFO_FILE)))?;
+
+    Ok(Snapshot { info, files })
+}
+
+/// The result of an import
+#[derive(Debug)]
+pub struct ImportedSnapshot {
+    pub info: SnapshotInfo,
+
+    /// The files that have been written
+    pub files: Vec<PathBuf>,
+}
+
+/// Write the files of the snapshot `path`: The configuration file is written to
+/// `config_file`, profiles to `profile_dir` and the files of the runtime state to
+/// `state_dir`
+fn import_into(
+    path: &Path,
+    config_file: &Path,
+    profile_dir: &Path,
+    state_dir: &Path,
+) -> Result<ImportedSnapshot> {
+    let snapshot = read_snapshot(path)?;
+
+    debug!(
+        "Importing a snapshot of Eruption {}, created {}",
+        snapshot.info.version, snapshot.info.created
+    );
+
+    let mut files = vec![];
+
+    for (file, data) in snapshot.files.iter() {
+        // the paths have been validated by `read_snapshot`
+        let target = if file == Path::new(SNAPSHOT_CONFIG_FILE) {
+            config_file.to_path_buf()
+        } else if file.starts_with("profiles") {
+            profile_dir.join(file.file_name().unwrap())
+        } else {
+            state_dir.join(file.file_name().unwrap())
+        };
+
+        if target.exists() {
+            let mut backup = target.clone().into_os_string();
+            backup.push(".bak");
+
+            fs::rename(&target, &backup)?;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&target, data)?;
+
+        debug!("Imported: {}", target.display());
+        files.push(target);
+    }
+
+    Ok(ImportedSnapshot {
+        info: snapshot.info,
+        files,
+    })
+}
+
+/// Import the snapshot `path`: The configuration file is written to `config_file`,
+/// profiles are installed into /etc, which takes precedence over the default
+/// profiles. The result contains the parameters that were overridden at the time
+/// of the export, and the files that have been written
+pub fn import<P: AsRef<Path>>(path: P, config_file: &Path) -> Result<ImportedSnapshot> {
+    import_into(
+        path.as_ref(),
+        config_file,
+        Path::new(constants::SYSTEM_PROFILE_DIR),
+        Path::new(constants::STATE_DIR),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"
+name = "Test"
+description = "A profile of the test"
+active_scripts = ["shockwave.lua"]
+"#;
+
+    /// Create a configuration file, a profile and the runtime state below the temp
+    /// directory, and the directories to import them into
+    fn setup(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!(
+            "eruption-test-snapshot-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        for dir in &["export/profiles", "export/state", "import/profiles"] {
+            fs::create_dir_all(base.join(dir)).unwrap();
+        }
+
+        fs::write(base.join("export/eruption.conf"), "[global]\n").unwrap();
+        fs::write(base.join("export/profiles/test.profile"), PROFILE).unwrap();
+        fs::write(
+            base.join("export/state/eruption.state"),
+            "profile = \"test.profile\"\n",
+        )
+        .unwrap();
+
+        fs::write(base.join("import/eruption.conf"), "# previous\n").unwrap();
+
+        base
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let base = setup("round-trip");
+        let file = base.join("test.eruption-snapshot");
+
+        let mut overrides = BTreeMap::new();
+        overrides.insert("shockwave.color_step".to_string(), "2".to_string());
+
+        let info = SnapshotInfo {
+            version: "0.1.0".to_string(),
+            created: Local::now().to_rfc3339(),
+            profile: Some("test.profile".to_string()),
+            overrides: overrides.clone(),
+        };
+
+        write_snapshot(
+            &file,
+            &info,
+            &base.join("export/eruption.conf"),
+            &[base.join("export/profiles/test.profile")],
+            &base.join("export/state"),
+        )
+        .unwrap();
+
+        let imported = import_into(
+            &file,
+            &base.join("import/eruption.conf"),
+            &base.join("import/profiles"),
+            &base.join("import/state"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::metadata(&file).unwrap().permissions().mode() & 0o777,
+            SNAPSHOT_MODE
+        );
+
+        assert_eq!(imported.info.profile.as_deref(), Some("test.profile"));
+        assert_eq!(imported.info.overrides, overrides);
+        assert_eq!(imported.files.len(), 3);
+
+        assert_eq!(
+            fs::read_to_string(base.join("import/eruption.conf")).unwrap(),
+            "[global]\n"
+        );
+        assert_eq!(
+            fs::read_to_string(base.join("import/profiles/test.profile")).unwrap(),
+            PROFILE
+        );
+        assert_eq!(
+            fs::read_to_string(base.join("import/state/eruption.state")).unwrap(),
+            "profile = \"test.profile\"\n"
+        );
+
+        // existing files are kept as a backup
+        assert_eq!(
+            fs::read_to_string(base.join("import/eruption.conf.bak")).unwrap(),
+            "# previous\n"
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let config = r#"# the weather
+[weather]
+provider = "openweathermap"
+api_key = "0123456789"  # personal
+
+[[ci.pipelines]]
+name = "eruption"
+  token = "secret"
+
+[rest]
+token_file = "/run/eruption/rest-token"
+"#;
+
+        let redacted = redact_secrets(config);
+
+        assert!(!redacted.contains("0123456789"));
+        assert!(!redacted.contains("secret"));
+
+        assert!(redacted.contains("# the weather\n"));
+        assert!(redacted.contains("\napi_key = \"\"\n"));
+        assert!(redacted.contains("\n  token = \"\"\n"));
+        assert!(redacted.contains("\ntoken_file = \"/run/eruption/rest-token\"\n"));
+
+        // dotted keys of the root table
+        assert_eq!(
+            redact_secrets("weather.api_key = \"0123456789\"\n"),
+            "weather.api_key = \"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_is_valid_file_name() {
+        assert!(is_valid_file_name("default.profile"));
+        assert!(is_valid_file_name("my-profile_2.profile"));
+
+        assert!(!is_valid_file_name(".profile"));
+        assert!(!is_valid_file_name(""));
+        assert!(!is_valid_file_name("new\nline.profile"));
+        assert!(!is_valid_file_name("space .profile"));
+    }
+}
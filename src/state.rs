@@ -15,7 +15,7 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use failure::{Error, Fail};
+use thiserror::Error;
 
 use lazy_static::lazy_static;
 //use log::*;
@@ -31,13 +31,13 @@ use crate::plugins::audio;
 
 pub type Result<T> = std::result::Result<T, StateError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum StateError {
-    #[fail(display = "Could not load global runtime state: {}", error)]
-    StateLoadError { error: Error },
+    #[error("Could not load global runtime state: {error}")]
+    StateLoadError { error: anyhow::Error },
 
-    #[fail(display = "Could not save global runtime state: {}", error)]
-    StateWriteError { error: Error },
+    #[error("Could not save global runtime state: {error}")]
+    StateWriteError { error: anyhow::Error },
 }
 
 lazy_static! {
@@ -114,12 +114,19 @@ pub fn init_global_runtime_state() -> Result<()> {
 pub fn save_runtime_state() -> Result<()> {
     let state_path = PathBuf::from(constants::STATE_DIR).join("eruption.state");
 
-    let config = State {
-        profile: crate::ACTIVE_PROFILE
+    // a previewed profile is never persisted, save the original profile instead
+    let profile_file = match crate::PROFILE_PREVIEW.lock().as_ref() {
+        Some((previous_profile, _)) => previous_profile.clone(),
+        None => crate::ACTIVE_PROFILE
             .lock()
             .as_ref()
             .unwrap()
             .profile_file
+            .clone(),
+    };
+
+    let config = State {
+        profile: profile_file
             .file_name()
             .unwrap()
             .to_str()
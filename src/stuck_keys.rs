@@ -0,0 +1,173 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A watchdog for stuck keys. If the release of a key gets lost, e.g. due to a
+//! race between grabbing the keyboard and mirroring its events, the key stays
+//! down on the virtual keyboard. Keys that are held down for an implausibly long
+//! time are released by a synthetic key-up event.
+
+use evdev_rs::enums::{int_to_ev_key, EventCode};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::hotkeys;
+use crate::plugins::macros;
+use crate::scripting::script;
+use crate::util;
+
+/// Configuration and state of the watchdog
+struct Watchdog {
+    /// Keys that are held down for longer than this are considered stuck,
+    /// None if the watchdog is disabled
+    timeout: Option<Duration>,
+
+    /// Keys that may be held down indefinitely, e.g. the keys to move in games
+    ignored_keys: HashSet<u32>,
+
+    /// Points in time the keys that are currently held down have been pressed
+    pressed_keys: HashMap<u32, Instant>,
+}
+
+lazy_static! {
+    static ref WATCHDOG: Mutex<Watchdog> = Mutex::new(Watchdog {
+        timeout: None,
+        ignored_keys: HashSet::new(),
+        pressed_keys: HashMap::new(),
+    });
+}
+
+/// Load the configuration of the `[watchdog]` section
+pub fn initialize() {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    let mut watchdog = WATCHDOG.lock();
+
+    if !config.get::<bool>("watchdog.enabled").unwrap_or(false) {
+        watchdog.timeout = None;
+        return;
+    }
+
+    let timeout = Duration::from_secs(
+        config
+            .get_int("watchdog.stuck_key_secs")
+            .unwrap_or(constants::DEFAULT_STUCK_KEY_SECS as i64)
+            .max(1) as u64,
+    );
+
+    watchdog.ignored_keys = config
+        .get::<Vec<String>>("watchdog.ignored_keys")
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|name| {
+            hotkeys::parse_key_name(name)
+                .map_err(|e| error!("Invalid key of the watchdog: {}", e))
+                .ok()
+        })
+        .collect();
+
+    info!(
+        "Releasing keys that are held down for more than {} seconds",
+        timeout.as_secs()
+    );
+
+    watchdog.timeout = Some(timeout);
+}
+
+/// Record the state of a key of the hardware keyboard
+pub fn record_event(event: &evdev_rs::InputEvent) {
+    let code = match event.event_code {
+        EventCode::EV_KEY(ref key) => key.clone() as u32,
+        _ => return,
+    };
+
+    let mut watchdog = WATCHDOG.lock();
+
+    if watchdog.timeout.is_none() {
+        return;
+    }
+
+    match event.value {
+        0 => {
+            watchdog.pressed_keys.remove(&code);
+        }
+
+        1 => {
+            watchdog.pressed_keys.insert(code, Instant::now());
+        }
+
+        // auto repeat does not restart the timeout
+        _ => {}
+    }
+}
+
+/// Release all keys that have been held down for longer than the timeout, and
+/// notify the Lua VMs. This is called periodically by the main loop
+pub fn check() {
+    let stuck_keys: Vec<u32> = {
+        let mut watchdog = WATCHDOG.lock();
+
+        let timeout = match watchdog.timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        let stuck_keys: Vec<u32> = watchdog
+            .pressed_keys
+            .iter()
+            .filter(|(code, since)| {
+                since.elapsed() > timeout && !watchdog.ignored_keys.contains(code)
+            })
+            .map(|(code, _)| *code)
+            .collect();
+
+        for code in stuck_keys.iter() {
+            watchdog.pressed_keys.remove(code);
+        }
+
+        stuck_keys
+    };
+
+    for code in stuck_keys {
+        let key = match int_to_ev_key(code) {
+            Some(key) => key,
+            None => continue,
+        };
+
+        warn!("Releasing stuck key: {:?}", key);
+
+        hotkeys::set_key_state(code, false);
+
+        if let Some(uinput_tx) = macros::UINPUT_TX.lock().as_ref() {
+            uinput_tx
+                .send(macros::Message::ReleaseKey(code))
+                .unwrap_or_else(|e| error!("Could not send a pending keyboard event: {}", e));
+        }
+
+        let index = util::ev_key_to_key_index(key);
+
+        for lua_tx in crate::LUA_TXS.lock().iter() {
+            lua_tx
+                .send(script::Message::KeyStuck(index))
+                .unwrap_or_else(|e| error!("Send error: {}", e));
+        }
+    }
+}
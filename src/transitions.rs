@@ -0,0 +1,177 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Transitions between profiles. The most recent composited frame of each device
+//! is kept, when the profile is switched it is captured, and blended with the
+//! first frames of the new profile over the configured duration.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Mutex, RwLock};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::rvdevice::{KEYS_PER_COLUMN, NUM_KEYS, RGBA};
+
+/// The effect of a transition
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Switch to the new profile immediately
+    None,
+
+    /// Blend the old profile into the new one
+    Crossfade,
+
+    /// Reveal the new profile column by column, from left to right
+    Wipe,
+}
+
+impl FromStr for Effect {
+    type Err = ();
+
+    fn from_str(effect: &str) -> std::result::Result<Self, Self::Err> {
+        match effect.trim() {
+            "none" => Ok(Effect::None),
+            "crossfade" => Ok(Effect::Crossfade),
+            "wipe" => Ok(Effect::Wipe),
+
+            _ => Err(()),
+        }
+    }
+}
+
+/// A transition that is in progress
+struct Transition {
+    /// The frames of the old profile, one per device
+    from: Vec<Vec<RGBA>>,
+    since: Instant,
+}
+
+lazy_static! {
+    /// The configured effect and duration of transitions
+    static ref SETTINGS: RwLock<(Effect, Duration)> = RwLock::new((
+        Effect::Crossfade,
+        Duration::from_millis(constants::TRANSITION_DURATION_MILLIS)
+    ));
+
+    /// The most recent composited frames, one per device
+    static ref LAST_FRAMES: Mutex<Vec<Vec<RGBA>>> = Mutex::new(vec![]);
+
+    /// The transition in progress, if any
+    static ref TRANSITION: Mutex<Option<Transition>> = Mutex::new(None);
+}
+
+/// Load the configuration of the `[transitions]` section
+pub fn initialize() {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    let effect = config
+        .get_str("transitions.effect")
+        .unwrap_or_else(|_| "crossfade".into());
+
+    let effect = effect.parse::<Effect>().unwrap_or_else(|_| {
+        warn!("Invalid transition effect: {}", effect);
+        Effect::Crossfade
+    });
+
+    let duration = config
+        .get::<u64>("transitions.duration_millis")
+        .unwrap_or(constants::TRANSITION_DURATION_MILLIS)
+        .min(constants::TRANSITION_MAX_DURATION_MILLIS);
+
+    *SETTINGS.write() = (effect, Duration::from_millis(duration));
+}
+
+/// Start a transition from the most recent frames, called when the profile is
+/// switched
+pub fn start() {
+    let (effect, duration) = *SETTINGS.read();
+
+    if effect == Effect::None || duration.as_millis() == 0 {
+        return;
+    }
+
+    let from = LAST_FRAMES.lock().clone();
+
+    if from.is_empty() {
+        return;
+    }
+
+    *TRANSITION.lock() = Some(Transition {
+        from,
+        since: Instant::now(),
+    });
+}
+
+fn blend(from: &RGBA, to: &RGBA, t: f64) -> RGBA {
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+
+    RGBA {
+        r: mix(from.r, to.r),
+        g: mix(from.g, to.g),
+        b: mix(from.b, to.b),
+        a: mix(from.a, to.a),
+    }
+}
+
+/// Blend the captured frames into the composited frames `led_maps` of the new
+/// profile, while a transition is in progress. The result is kept, so that a
+/// transition that starts during another one continues from the visible state
+pub fn process_frame(led_maps: &mut [Vec<RGBA>]) {
+    let (effect, duration) = *SETTINGS.read();
+
+    {
+        let mut transition = TRANSITION.lock();
+
+        if let Some(current) = transition.as_ref() {
+            let t = current.since.elapsed().as_secs_f64() / duration.as_secs_f64();
+
+            if t >= 1.0 {
+                *transition = None;
+            } else {
+                for (led_map, from) in led_maps.iter_mut().zip(current.from.iter()) {
+                    match effect {
+                        Effect::Wipe => {
+                            let num_columns = NUM_KEYS / KEYS_PER_COLUMN;
+                            let edge = (t * num_columns as f64) as usize;
+
+                            for (i, (led, from)) in led_map.iter_mut().zip(from.iter()).enumerate()
+                            {
+                                if i / KEYS_PER_COLUMN >= edge {
+                                    *led = *from;
+                                }
+                            }
+                        }
+
+                        _ => {
+                            for (led, from) in led_map.iter_mut().zip(from.iter()) {
+                                *led = blend(from, led, t);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut last_frames = LAST_FRAMES.lock();
+
+    last_frames.clear();
+    last_frames.extend(led_maps.iter().cloned());
+}
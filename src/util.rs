@@ -18,24 +18,26 @@
 // use std::fs::File;
 // use std::io::prelude::*;
 use evdev_rs::enums::EV_KEY;
-use failure::Fail;
+use std::ffi::{CStr, OsStr};
 use std::fs;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use thiserror::Error;
 use udev::Enumerator;
 
 use crate::rvdevice;
 
 pub type Result<T> = std::result::Result<T, UtilError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum UtilError {
-    #[fail(display = "No compatible devices found")]
+    #[error("No compatible devices found")]
     NoDevicesFound {},
 
-    #[fail(display = "Error occurred during device enumeration")]
+    #[error("Error occurred during device enumeration")]
     EnumerationError {},
 
-    #[fail(display = "Could not enumerate udev devices")]
+    #[error("Could not enumerate udev devices")]
     UdevError {},
 }
 
@@ -104,6 +106,58 @@ pub fn get_evdev_from_udev() -> Result<String> {
 //     Ok(())
 // }
 
+/// A process that holds a device file open
+#[derive(Debug, Clone)]
+pub struct DeviceClaim {
+    pub pid: u32,
+    pub name: String,
+}
+
+impl std::fmt::Display for DeviceClaim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (pid {})", self.name, self.pid)
+    }
+}
+
+/// Find all other processes that hold the device file `path` open, by scanning
+/// the file descriptors in `/proc`. Processes whose file descriptors can not be
+/// inspected, e.g. due to missing permissions, are skipped
+pub fn find_device_claims<P: AsRef<Path>>(path: P) -> Vec<DeviceClaim> {
+    let path = match fs::canonicalize(path.as_ref()) {
+        Ok(path) => path,
+        Err(_) => return vec![],
+    };
+
+    let own_pid = std::process::id();
+    let mut result = vec![];
+
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+                Ok(pid) if pid != own_pid => pid,
+                _ => continue,
+            };
+
+            let holds_device = fs::read_dir(entry.path().join("fd"))
+                .map(|fds| {
+                    fds.filter_map(|fd| fd.ok())
+                        .any(|fd| fs::read_link(fd.path()).ok().as_ref() == Some(&path))
+                })
+                .unwrap_or(false);
+
+            if holds_device {
+                let name = fs::read_to_string(entry.path().join("comm"))
+                    .map(|name| name.trim().to_string())
+                    .unwrap_or_else(|_| "<unknown>".into());
+
+                result.push(DeviceClaim { pid, name });
+            }
+        }
+    }
+
+    result
+}
+
 /// Returns the associated manifest path in `PathBuf` for the script `script_path`.
 pub fn get_manifest_for(script_file: &Path) -> PathBuf {
     let mut manifest_path = script_file.to_path_buf();
@@ -328,6 +382,65 @@ static _EV_TO_INDEX_ANSI: [u8; 0x2ff + 1] = [
     0xff, // 0x2f0
 ];
 
+/// Get the index of the LED of the key `key`, plus one. Returns 0 for keys that
+/// have no LED
 pub fn ev_key_to_key_index(key: EV_KEY) -> u8 {
-    EV_TO_INDEX_ISO[((key as u8) as usize)] + 1
+    get_key_index(key as u32).map_or(0, |index| index + 1)
+}
+
+/// Get the index of the LED of the key with the evdev key code `code`, if the
+/// key has an LED
+pub fn get_key_index(code: u32) -> Option<u8> {
+    EV_TO_INDEX_ISO
+        .get(code as usize)
+        .copied()
+        .filter(|index| *index != 0xff)
+}
+
+/// Get the evdev key codes that are mapped to the LED with the index `index`,
+/// this is the inverse of the key index table
+pub fn key_index_to_ev_keys(index: usize) -> Vec<EV_KEY> {
+    EV_TO_INDEX_ISO
+        .iter()
+        .enumerate()
+        .filter(|(_code, i)| **i as usize == index)
+        .filter_map(|(code, _i)| evdev_rs::enums::int_to_ev_key(code as u32))
+        .collect()
+}
+
+/// Percent-encode `s`, for use in a path segment or a query parameter of a URL.
+/// All characters except the unreserved characters of RFC 3986 are encoded
+#[cfg(any(feature = "weather", feature = "ci"))]
+pub fn url_encode(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                result.push(b as char)
+            }
+
+            _ => result.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    result
+}
+
+/// Get the home directory of the user with the uid `uid` from the user database
+pub fn get_home_dir(uid: u32) -> Option<PathBuf> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+    let rc =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+
+    if rc != 0 || result.is_null() || passwd.pw_dir.is_null() {
+        return None;
+    }
+
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+
+    Some(PathBuf::from(OsStr::from_bytes(home.to_bytes())))
 }
@@ -0,0 +1,479 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Lighting themes that are generated from the desktop wallpaper. The dominant
+//! colors of the wallpaper are extracted and published to the palette library,
+//! e.g. as the palette "wallpaper". The wallpaper is either specified by the
+//! configuration, or queried from the desktop of the seat user via `gsettings`.
+//! It is checked periodically, and the palette is refreshed when it changes.
+//!
+//! PNG and JPEG images are supported. A wallpaper of the desktop is only read if
+//! it is a regular file, that is owned by the seat user or readable by everyone.
+
+use log::*;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+use crate::constants;
+use crate::palettes;
+use crate::paths;
+use crate::scripting::script::callbacks;
+
+pub type Result<T> = std::result::Result<T, WallpaperError>;
+
+#[derive(Debug, Error)]
+pub enum WallpaperError {
+    #[error("Could not read the wallpaper {path}: {description}")]
+    ImageError { path: String, description: String },
+
+    #[error("Could not spawn a thread")]
+    ThreadSpawnError {},
+}
+
+/// The configuration of the `[wallpaper]` section
+#[derive(Debug, Clone)]
+struct Settings {
+    /// The wallpaper, if None it is queried from the desktop
+    path: Option<PathBuf>,
+
+    /// The name of the published palette
+    palette: String,
+
+    num_colors: usize,
+    poll_interval: Duration,
+}
+
+/// Decode the percent-encoded bytes of the URI `uri`
+fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                result.push(byte);
+                i += 3;
+
+                continue;
+            }
+        }
+
+        result.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Query the wallpaper of the GNOME desktop of the seat user, returns the path and
+/// the uid of the user
+fn query_desktop_wallpaper() -> Option<(PathBuf, u32)> {
+    let user = paths::get_seat_user()?;
+
+    let output = Command::new("gsettings")
+        .args(&["get", "org.gnome.desktop.background", "picture-uri"])
+        .env_clear()
+        .env("HOME", &user.home)
+        .env("XDG_RUNTIME_DIR", format!("/run/user/{}", user.uid))
+        .env(
+            "DBUS_SESSION_BUS_ADDRESS",
+            format!("unix:path=/run/user/{}/bus", user.uid),
+        )
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .uid(user.uid)
+        .gid(user.gid().unwrap_or(user.uid))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| debug!("Could not query the wallpaper of the desktop: {}", e))
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    // the output is a quoted URI, like 'file:///usr/share/backgrounds/default.png'
+    let uri = String::from_utf8_lossy(&output.stdout);
+    let uri = uri.trim().trim_matches('\'');
+
+    if uri.starts_with("file://") {
+        Some((PathBuf::from(decode_uri(&uri["file://".len()..])), user.uid))
+    } else {
+        None
+    }
+}
+
+/// Returns an error if an image of `width` x `height` pixels is too large to be decoded
+fn check_dimensions(width: usize, height: usize) -> std::result::Result<(), String> {
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= constants::WALLPAPER_MAX_PIXELS => Ok(()),
+        _ => Err(format!(
+            "The image is too large: {}x{} pixels",
+            width, height
+        )),
+    }
+}
+
+/// Decode the PNG image `file`, returns the pixels and the number of channels
+fn decode_png<R: Read>(file: R) -> std::result::Result<(Vec<u8>, usize), String> {
+    // limit the allocations of the decoder, the dimensions are checked below
+    let limits = png::Limits {
+        bytes: constants::WALLPAPER_MAX_PIXELS * 4,
+    };
+
+    let mut decoder = png::Decoder::new_with_limits(file, limits);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let (info, mut reader) = decoder.read_info().map_err(|e| format!("{}", e))?;
+
+    check_dimensions(info.width as usize, info.height as usize)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+
+        other => return Err(format!("Unsupported color type: {:?}", other)),
+    };
+
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(|e| format!("{}", e))?;
+
+    Ok((buf, channels))
+}
+
+/// Decode the JPEG image `file`, returns the pixels and the number of channels
+fn decode_jpeg<R: Read>(file: R) -> std::result::Result<(Vec<u8>, usize), String> {
+    let mut decoder = jpeg_decoder::Decoder::new(file);
+
+    decoder.read_info().map_err(|e| format!("{}", e))?;
+
+    let info = decoder
+        .info()
+        .ok_or_else(|| "Missing image info".to_string())?;
+
+    check_dimensions(info.width as usize, info.height as usize)?;
+
+    let channels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => 1,
+        jpeg_decoder::PixelFormat::RGB24 => 3,
+
+        other => return Err(format!("Unsupported pixel format: {:?}", other)),
+    };
+
+    let buf = decoder.decode().map_err(|e| format!("{}", e))?;
+
+    Ok((buf, channels))
+}
+
+/// Returns an error unless `metadata` is of a regular file, that may be read by the
+/// user `uid`: It has to be owned by the user, or be readable by everyone. Any file
+/// may be read, if `uid` is None
+fn check_file(metadata: &fs::Metadata, uid: Option<u32>) -> std::result::Result<(), String> {
+    if !metadata.is_file() {
+        return Err("Not a regular file".into());
+    }
+
+    match uid {
+        Some(uid) if metadata.uid() != uid && metadata.mode() & 0o004 == 0 => {
+            Err(format!("The file is not accessible by the user {}", uid))
+        }
+
+        _ => Ok(()),
+    }
+}
+
+/// Decode the PNG or JPEG image `path`, returns the pixels as RGB triplets. If the
+/// image has been specified by the user `uid`, it has to be accessible by the user
+fn decode_image(path: &Path, uid: Option<u32>) -> std::result::Result<Vec<[u8; 3]>, String> {
+    // don't block on a named pipe, and don't follow a symlink to a file of root
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+        .open(path)
+        .map_err(|e| format!("{}", e))?;
+
+    check_file(&file.metadata().map_err(|e| format!("{}", e))?, uid)?;
+
+    let mut file = BufReader::new(file);
+
+    // the format is detected by the signature, not by the file name
+    let mut signature = [0; 3];
+
+    file.read_exact(&mut signature)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .map_err(|e| format!("{}", e))?;
+
+    let (buf, channels) = if signature == [0xff, 0xd8, 0xff] {
+        decode_jpeg(file)?
+    } else {
+        decode_png(file)?
+    };
+
+    // sample a subset of the pixels of large images
+    let num_pixels = buf.len() / channels;
+    let stride = (num_pixels / constants::WALLPAPER_MAX_SAMPLES).max(1);
+
+    let pixels = buf
+        .chunks_exact(channels)
+        .step_by(stride)
+        .map(|pixel| {
+            if channels < 3 {
+                [pixel[0], pixel[0], pixel[0]]
+            } else {
+                [pixel[0], pixel[1], pixel[2]]
+            }
+        })
+        .collect();
+
+    Ok(pixels)
+}
+
+/// Extract up to `num_colors` dominant colors of `pixels`. The pixels are binned
+/// into a coarse grid of the RGB color space, the most populated bins are picked,
+/// skipping very dark bins and bins that are similar to one that has been picked
+fn extract_palette(pixels: &[[u8; 3]], num_colors: usize) -> Vec<u32> {
+    let mut bins: HashMap<u16, (usize, [usize; 3])> = HashMap::new();
+
+    for pixel in pixels {
+        let key =
+            ((pixel[0] as u16 >> 3) << 10) | ((pixel[1] as u16 >> 3) << 5) | (pixel[2] as u16 >> 3);
+        let bin = bins.entry(key).or_insert((0, [0; 3]));
+
+        bin.0 += 1;
+
+        for (sum, value) in bin.1.iter_mut().zip(pixel.iter()) {
+            *sum += *value as usize;
+        }
+    }
+
+    let mut bins: Vec<(usize, [u8; 3])> = bins
+        .values()
+        .map(|(count, sums)| {
+            (
+                *count,
+                [
+                    (sums[0] / count) as u8,
+                    (sums[1] / count) as u8,
+                    (sums[2] / count) as u8,
+                ],
+            )
+        })
+        .collect();
+
+    bins.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let distance = |a: &[u8; 3], b: &[u8; 3]| {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+            .sum::<i32>()
+    };
+
+    let mut result: Vec<[u8; 3]> = vec![];
+
+    for (_, color) in bins {
+        if result.len() >= num_colors {
+            break;
+        }
+
+        if color
+            .iter()
+            .all(|c| *c < constants::WALLPAPER_MIN_BRIGHTNESS)
+        {
+            continue;
+        }
+
+        if result
+            .iter()
+            .all(|picked| distance(picked, &color) >= constants::WALLPAPER_MIN_DISTANCE.pow(2))
+        {
+            result.push(color);
+        }
+    }
+
+    result
+        .iter()
+        .map(|c| callbacks::rgb_to_color(c[0], c[1], c[2]))
+        .collect()
+}
+
+/// Extract the palette of the wallpaper `path` of the user `uid` and publish it
+fn update_palette(path: &Path, uid: Option<u32>, settings: &Settings) -> Result<()> {
+    let pixels = decode_image(path, uid).map_err(|description| WallpaperError::ImageError {
+        path: path.display().to_string(),
+        description,
+    })?;
+
+    let colors = extract_palette(&pixels, settings.num_colors);
+
+    info!(
+        "Extracted {} colors from the wallpaper {}",
+        colors.len(),
+        path.display()
+    );
+
+    palettes::set_palette(&settings.palette, colors);
+
+    Ok(())
+}
+
+fn run_watcher(settings: Settings) {
+    let mut last: Option<(PathBuf, Option<SystemTime>)> = None;
+
+    loop {
+        // the wallpaper of the desktop is specified by the seat user
+        let wallpaper = match &settings.path {
+            Some(path) => Some((path.clone(), None)),
+            None => query_desktop_wallpaper().map(|(path, uid)| (path, Some(uid))),
+        };
+
+        if let Some((path, uid)) = wallpaper {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            let current = Some((path.clone(), modified));
+
+            if current != last {
+                update_palette(&path, uid, &settings)
+                    .unwrap_or_else(|e| warn!("Could not extract the palette: {}", e));
+
+                last = current;
+            }
+        }
+
+        thread::sleep(settings.poll_interval);
+    }
+}
+
+/// Watch the wallpaper and publish its palette, if enabled
+pub fn initialize() -> Result<()> {
+    let (enabled, settings) = {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        let settings = Settings {
+            path: config
+                .get_str("wallpaper.path")
+                .ok()
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from),
+
+            palette: config
+                .get_str("wallpaper.palette")
+                .unwrap_or_else(|_| "wallpaper".into()),
+
+            num_colors: config
+                .get::<usize>("wallpaper.num_colors")
+                .unwrap_or(constants::WALLPAPER_NUM_COLORS)
+                .max(1),
+
+            poll_interval: Duration::from_secs(
+                config
+                    .get::<u64>("wallpaper.poll_interval_secs")
+                    .unwrap_or(constants::WALLPAPER_POLL_INTERVAL_SECS)
+                    .max(1),
+            ),
+        };
+
+        (
+            config.get_bool("wallpaper.enabled").unwrap_or(false),
+            settings,
+        )
+    };
+
+    if !enabled {
+        return Ok(());
+    }
+
+    thread::Builder::new()
+        .name("wallpaper".into())
+        .spawn(move || run_watcher(settings))
+        .map_err(|_e| WallpaperError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_uri() {
+        assert_eq!(
+            decode_uri("/home/user/Pictures/My%20Wallpaper.png"),
+            "/home/user/Pictures/My Wallpaper.png"
+        );
+        assert_eq!(decode_uri("/tmp/%C3%A4.jpg"), "/tmp/\u{e4}.jpg");
+
+        // invalid or truncated escapes are kept
+        assert_eq!(decode_uri("/tmp/100%zz.png"), "/tmp/100%zz.png");
+        assert_eq!(decode_uri("/tmp/a%2"), "/tmp/a%2");
+    }
+
+    #[test]
+    fn test_extract_palette() {
+        let mut pixels = vec![[255, 0, 0]; 100];
+        pixels.extend(vec![[0, 0, 250]; 50]);
+        pixels.extend(vec![[235, 20, 20]; 40]);
+        pixels.extend(vec![[0, 0, 0]; 200]);
+
+        let colors = extract_palette(&pixels, 4);
+
+        // black is skipped, the similar reds are merged
+        assert_eq!(
+            colors,
+            vec![
+                callbacks::rgb_to_color(255, 0, 0),
+                callbacks::rgb_to_color(0, 0, 250),
+            ]
+        );
+
+        assert_eq!(extract_palette(&pixels, 1).len(), 1);
+        assert!(extract_palette(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir =
+            std::env::temp_dir().join(format!("eruption-test-wallpaper-{}", std::process::id()));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let metadata = fs::metadata(&dir).unwrap();
+        assert!(check_file(&metadata, None).is_err());
+
+        let file = dir.join("wallpaper.png");
+        fs::write(&file, b"").unwrap();
+
+        let metadata = fs::metadata(&file).unwrap();
+        assert!(check_file(&metadata, None).is_ok());
+        assert!(check_file(&metadata, Some(metadata.uid())).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A warmth filter of the output stage, that matches the color temperature of
+//! the screens. The color temperature is reported by a helper that runs in the
+//! session of the user (see `support/bin/eruption-warmth-watcher`), since the
+//! tools that set it (GNOME Night Light, KDE Night Color, redshift and gammastep)
+//! are only reachable from the session.
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::constants;
+use crate::rvdevice::RGBA;
+
+lazy_static! {
+    /// Global "warmth filter enabled" flag
+    static ref ENABLE_WARMTH: AtomicBool = AtomicBool::new(false);
+
+    /// Strength of the filter in percent, 100 matches the screens
+    static ref STRENGTH: AtomicU32 = AtomicU32::new(100);
+
+    /// The color temperature of the screens, in Kelvin
+    static ref TEMPERATURE: AtomicU32 = AtomicU32::new(constants::NEUTRAL_COLOR_TEMPERATURE);
+
+    /// The multipliers of the red, green and blue channels, None if neutral
+    static ref MULTIPLIERS: RwLock<Option<(f64, f64, f64)>> = RwLock::new(None);
+}
+
+/// Load the configuration of the `[warmth]` section
+pub fn initialize() {
+    let config = crate::CONFIG.lock();
+    let config = config.as_ref().unwrap();
+
+    ENABLE_WARMTH.store(
+        config.get::<bool>("warmth.enabled").unwrap_or(false),
+        Ordering::SeqCst,
+    );
+
+    STRENGTH.store(
+        config
+            .get_int("warmth.strength")
+            .unwrap_or(100)
+            .max(0)
+            .min(100) as u32,
+        Ordering::SeqCst,
+    );
+}
+
+/// Approximation of the color of a black body with the temperature `kelvin`,
+/// the components are in the range 0.0..=1.0 (after Tanner Helland)
+fn black_body(kelvin: u32) -> (f64, f64, f64) {
+    let t = f64::from(kelvin) / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2)
+    };
+
+    let g = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+
+    let clamp = |v: f64| (v / 255.0).max(0.0).min(1.0);
+
+    (clamp(r), clamp(g), clamp(b))
+}
+
+/// Get the color temperature of the screens, in Kelvin
+pub fn get_color_temperature() -> u32 {
+    TEMPERATURE.load(Ordering::SeqCst)
+}
+
+/// Record the color temperature of the screens, in Kelvin
+pub fn set_color_temperature(kelvin: u32) {
+    let kelvin = kelvin
+        .max(constants::MIN_COLOR_TEMPERATURE)
+        .min(constants::NEUTRAL_COLOR_TEMPERATURE);
+
+    if TEMPERATURE.swap(kelvin, Ordering::SeqCst) == kelvin {
+        return;
+    }
+
+    debug!("Color temperature of the screens: {} K", kelvin);
+
+    *MULTIPLIERS.write() = if kelvin >= constants::NEUTRAL_COLOR_TEMPERATURE {
+        None
+    } else {
+        // relative to the white point of the screens
+        let (r, g, b) = black_body(kelvin);
+        let (r0, g0, b0) = black_body(constants::NEUTRAL_COLOR_TEMPERATURE);
+
+        Some((r / r0, g / g0, b / b0))
+    };
+}
+
+/// Apply the warmth filter to `led_map`, if enabled
+pub fn filter_led_map(led_map: &mut [RGBA]) {
+    if !ENABLE_WARMTH.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let (r, g, b) = match *MULTIPLIERS.read() {
+        Some(multipliers) => multipliers,
+        None => return,
+    };
+
+    let strength = f64::from(STRENGTH.load(Ordering::SeqCst)) / 100.0;
+    let scale = |m: f64| (1.0 - strength) + strength * m.min(1.0);
+
+    let (r, g, b) = (scale(r), scale(g), scale(b));
+
+    for color in led_map.iter_mut() {
+        color.r = (f64::from(color.r) * r).round() as u8;
+        color.g = (f64::from(color.g) * g).round() as u8;
+        color.b = (f64::from(color.b) * b).round() as u8;
+    }
+}
@@ -0,0 +1,82 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Tracking of the focused application. The daemon has no access to the display
+//! server of the session, so the class of the focused window is reported by a
+//! helper that runs in the session of the user, via the D-Bus interface
+//! `org.eruption.Windows` (see `support/bin/eruption-window-watcher`).
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::scripting::script;
+
+lazy_static! {
+    /// Window class of the focused application, in lower case
+    static ref ACTIVE_APP: Mutex<Option<String>> = Mutex::new(None);
+
+    /// Set while the focused application is in fullscreen mode, e.g. a game
+    static ref FULLSCREEN: AtomicBool = AtomicBool::new(false);
+}
+
+/// Normalize the window class `class`, classes are matched case-insensitively
+pub fn normalize_class(class: &str) -> String {
+    class.trim().to_lowercase()
+}
+
+/// Get the window class of the focused application, if known
+pub fn get_active_app() -> Option<String> {
+    ACTIVE_APP.lock().clone()
+}
+
+/// Record the window class of the focused application, an empty class means that
+/// no window is focused. The Lua VMs are notified if the application changed
+pub fn set_active_app(class: &str) {
+    let class = normalize_class(class);
+    let class = if class.is_empty() { None } else { Some(class) };
+
+    let old_class = std::mem::replace(&mut *ACTIVE_APP.lock(), class.clone());
+
+    if old_class != class {
+        debug!(
+            "Active application: {}",
+            class.as_deref().unwrap_or("<none>")
+        );
+
+        for lua_tx in crate::LUA_TXS.lock().iter() {
+            lua_tx
+                .send(script::Message::ActiveAppChanged(
+                    class.clone().unwrap_or_default(),
+                ))
+                .unwrap_or_else(|e| error!("Send error: {}", e));
+        }
+    }
+}
+
+/// Returns true while the focused application is in fullscreen mode
+pub fn is_fullscreen() -> bool {
+    FULLSCREEN.load(Ordering::SeqCst)
+}
+
+/// Record whether the focused application is in fullscreen mode
+pub fn set_fullscreen(fullscreen: bool) {
+    if FULLSCREEN.swap(fullscreen, Ordering::SeqCst) != fullscreen {
+        debug!("Fullscreen application: {}", fullscreen);
+    }
+}
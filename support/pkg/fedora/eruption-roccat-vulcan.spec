@@ -85,6 +85,8 @@ cp -a %{_builddir}/%{name}-%{version}/support/profiles/profile4.profile %{buildr
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/preset-red-yellow.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/preset-blue-red.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/profiles/spectrum-analyzer.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
+cp -a %{_builddir}/%{name}-%{version}/support/profiles/locked.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
+cp -a %{_builddir}/%{name}-%{version}/support/profiles/clock.profile %{buildroot}%{_sharedstatedir}/%{ShortName}/profiles/
 cp -a %{_builddir}/%{name}-%{version}/support/sfx/typewriter1.wav %{buildroot}%{_datarootdir}/%{ShortName}/sfx/typewriter1.wav
 cp -a %{_builddir}/%{name}-%{version}/support/sfx/phaser1.wav %{buildroot}%{_datarootdir}/%{ShortName}/sfx/phaser1.wav
 cp -a %{_builddir}/%{name}-%{version}/support/sfx/phaser2.wav %{buildroot}%{_datarootdir}/%{ShortName}/sfx/phaser2.wav
@@ -95,6 +97,10 @@ cp -ra %{_builddir}/%{name}-%{version}/src/scripts %{buildroot}%{_datarootdir}/%
 #cp -ra %{_builddir}/%{name}-%{version}/static %{buildroot}%{_datarootdir}/%{ShortName}/
 
 install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{buildroot}%{_bindir}/eruption
+install -Dp -m 0755 %{_builddir}/%{name}-%{version}/support/bin/eruption-accent-watcher %{buildroot}%{_bindir}/eruption-accent-watcher
+install -Dp -m 0755 %{_builddir}/%{name}-%{version}/support/bin/eruption-display-watcher %{buildroot}%{_bindir}/eruption-display-watcher
+install -Dp -m 0755 %{_builddir}/%{name}-%{version}/support/bin/eruption-warmth-watcher %{buildroot}%{_bindir}/eruption-warmth-watcher
+install -Dp -m 0755 %{_builddir}/%{name}-%{version}/support/bin/eruption-window-watcher %{buildroot}%{_bindir}/eruption-window-watcher
 
 %post
 %systemd_post %{ShortName}.service
@@ -113,6 +119,10 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{bu
 %{_sysconfdir}/dbus-1/system.d/org.eruption.control.conf
 /usr/lib/udev/rules.d/99-eruption-roccat-vulcan.rules
 %{_bindir}/eruption
+%{_bindir}/eruption-accent-watcher
+%{_bindir}/eruption-display-watcher
+%{_bindir}/eruption-warmth-watcher
+%{_bindir}/eruption-window-watcher
 %{_unitdir}/eruption.service
 %{_presetdir}/50-eruption.preset
 %{_sharedstatedir}/%{ShortName}/profiles/default.profile
@@ -125,6 +135,8 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{bu
 %{_sharedstatedir}/%{ShortName}/profiles/preset-red-yellow.profile
 %{_sharedstatedir}/%{ShortName}/profiles/preset-blue-red.profile
 %{_sharedstatedir}/%{ShortName}/profiles/spectrum-analyzer.profile
+%{_sharedstatedir}/%{ShortName}/profiles/locked.profile
+%{_sharedstatedir}/%{ShortName}/profiles/clock.profile
 %{_datarootdir}/%{ShortName}/scripts/examples/simple.lua
 %{_datarootdir}/%{ShortName}/scripts/lib/debug.lua
 %{_datarootdir}/%{ShortName}/scripts/macros.lua
@@ -185,6 +197,20 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{bu
 %{_datarootdir}/%{ShortName}/scripts/temperature.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/water.lua
 %{_datarootdir}/%{ShortName}/scripts/water.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/accent-color.lua
+%{_datarootdir}/%{ShortName}/scripts/accent-color.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/ci-status.lua
+%{_datarootdir}/%{ShortName}/scripts/ci-status.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/clock.lua
+%{_datarootdir}/%{ShortName}/scripts/clock.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/mention.lua
+%{_datarootdir}/%{ShortName}/scripts/mention.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/mute-indicator.lua
+%{_datarootdir}/%{ShortName}/scripts/mute-indicator.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/pomodoro.lua
+%{_datarootdir}/%{ShortName}/scripts/pomodoro.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/voice-glow.lua
+%{_datarootdir}/%{ShortName}/scripts/voice-glow.lua.manifest
 %{_datarootdir}/%{ShortName}/sfx/typewriter1.wav
 %{_datarootdir}/%{ShortName}/sfx/phaser1.wav
 %{_datarootdir}/%{ShortName}/sfx/phaser2.wav
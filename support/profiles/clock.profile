@@ -0,0 +1,6 @@
+id = '8c1d7e9a-2f4b-4b6e-9a35-3f0d6c1e5b72'
+name = "Clock"
+description = "A dim clock, e.g. while the displays are blanked"
+active_scripts = [
+	'clock.lua',
+]
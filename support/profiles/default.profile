@@ -1,3 +1,4 @@
+version = 1
 id = '5dc62fa6-e965-45cb-a0da-e87d29713095'
 name = 'Default'
 description = 'The default profile'
@@ -1,3 +1,4 @@
+version = 1
 id = '5dc62fa6-e965-45cb-a0da-e87d29713106'
 name = 'FX2'
 description = 'Effects Profile #2'
@@ -0,0 +1,12 @@
+version = 1
+id = '5dc62fa6-e965-45cb-a0da-e87d29713107'
+name = "Locked"
+description = "Active while the session is locked, without macros"
+active_scripts = [
+	'solid.lua',
+]
+
+[[config.'Solid Color']]
+type = 'color'
+name = 'color_background'
+value = 0xff0f0f3f
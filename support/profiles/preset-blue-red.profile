@@ -1,3 +1,4 @@
+version = 1
 id = '5dc62fa6-e965-45cb-a0da-e87d29713100'
 name = 'Preset: Blue and Red'
 description = '''Presets for a 'blue and red' color scheme'''